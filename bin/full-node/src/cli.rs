@@ -53,7 +53,8 @@ pub struct CliOptionsRun {
     /// Coloring: auto, always, never
     #[structopt(long, default_value = "auto")]
     pub color: ColorChoice,
-    /// Ed25519 private key of network identity (32 bytes hexadecimal).
+    /// Ed25519 private key of network identity (32 bytes hexadecimal). If not provided, the key
+    /// stored in the database is used, or a new one is generated and stored.
     #[structopt(long)]
     pub node_key: Option<NodeKey>,
     /// Bind point of the JSON-RPC server ("none" or <ip>:<port>).
@@ -62,6 +63,15 @@ pub struct CliOptionsRun {
     /// Do not load or store anything on disk.
     #[structopt(long)]
     pub tmp: bool,
+    /// Discover nodes of the local network through mDNS.
+    #[structopt(long)]
+    pub mdns: bool,
+    /// Name of the node reported to the telemetry servers.
+    #[structopt(long, default_value = "smoldot")]
+    pub name: String,
+    /// Do not report anything to the telemetry servers found in the chain specification.
+    #[structopt(long)]
+    pub no_telemetry: bool,
 }
 
 #[derive(Debug)]
@@ -23,7 +23,8 @@ use smoldot::{
     database::full_sqlite,
     header,
     informant::HashDisplay,
-    libp2p::{connection, multiaddr, peer_id::PeerId},
+    libp2p::{connection, multiaddr, peer_id, peer_id::PeerId},
+    telemetry,
 };
 use std::{
     borrow::Cow, convert::TryFrom as _, fs, io, iter, path::PathBuf, sync::Arc, thread,
@@ -31,9 +32,12 @@ use std::{
 };
 use tracing::Instrument as _;
 
+mod batch_verifier;
+mod dns;
 mod json_rpc_service;
 mod network_service;
 mod sync_service;
+mod telemetry_service;
 
 /// Runs the node using the given configuration. Catches SIGINT signals and stops if one is
 /// detected.
@@ -86,9 +90,12 @@ pub async fn run(cli_options: cli::CliOptionsRun) {
             .expect("Failed to decode chain specs")
     };
 
+    // Note that the full node needs the storage of the genesis block in order to execute the
+    // blocks. Chain specs that only contain the root hash of the genesis storage are rejected.
     // TODO: don't unwrap?
     let genesis_chain_information =
-        chain::chain_information::ChainInformation::from_chain_spec(&chain_spec).unwrap();
+        chain::chain_information::ChainInformation::from_chain_spec(&chain_spec)
+            .expect("Failed to build the genesis chain information from the chain specs");
 
     // If `chain_spec` define a parachain, also load the specs of the relay chain.
     let (relay_chain_spec, _parachain_id) =
@@ -160,9 +167,27 @@ pub async fn run(cli_options: cli::CliOptionsRun) {
         smoldot::metadata::decode(&metadata).unwrap()
     );*/
 
+    // Private key used by the networking. Represents the identity of the node on the
+    // peer-to-peer network. Unless it is overridden on the command line, the key is stored in the
+    // database so that the identity of the node doesn't change between restarts.
+    let network_identity_key = if let Some(node_key) = &cli_options.node_key {
+        *node_key.as_ref()
+    } else if let Some(key) = database.network_identity_key().unwrap() {
+        key
+    } else {
+        let key = rand::random();
+        database.set_network_identity_key(&key).unwrap();
+        key
+    };
+
     let (network_service, network_events_receivers) =
         network_service::NetworkService::new(network_service::Config {
             listen_addresses: Vec::new(),
+            mdns_discovery: cli_options.mdns,
+            dns_resolver: Arc::new(dns::CachingResolver::new(
+                dns::SystemResolver,
+                Duration::from_secs(300),
+            )),
             num_events_receivers: 2 + if relay_chain_database.is_some() { 1 } else { 0 },
             chains: iter::once(network_service::ChainConfig {
                 protocol_id: chain_spec.protocol_id().to_owned(),
@@ -190,6 +215,7 @@ pub async fn run(cli_options: cli::CliOptionsRun) {
                     }
                     list
                 },
+                database: database.clone(),
             })
             .chain(
                 relay_chain_spec
@@ -227,17 +253,13 @@ pub async fn run(cli_options: cli::CliOptionsRun) {
                                 }
                                 list
                             },
+                            database: relay_chain_database.as_ref().unwrap().clone(),
                         }
                     })
                     .into_iter(),
             )
             .collect(),
-            noise_key: if let Some(node_key) = cli_options.node_key {
-                connection::NoiseKey::new(node_key.as_ref())
-            } else {
-                // TODO: load from disk or something instead
-                connection::NoiseKey::new(&rand::random())
-            },
+            noise_key: connection::NoiseKey::new(&network_identity_key),
             tasks_executor: {
                 let threads_pool = threads_pool.clone();
                 Box::new(move |task| threads_pool.spawn_ok(task))
@@ -257,6 +279,11 @@ pub async fn run(cli_options: cli::CliOptionsRun) {
         network_events_receiver: network_events_receivers.next().unwrap(),
         network_service: (network_service.clone(), 0),
         database,
+        banned_blocks: chain_spec.bad_blocks_hashes().copied().collect(),
+        fork_blocks: chain_spec
+            .fork_blocks()
+            .map(|(height, hash)| (height, *hash))
+            .collect(),
     })
     .instrument(tracing::debug_span!("sync-service-init"))
     .await;
@@ -271,6 +298,18 @@ pub async fn run(cli_options: cli::CliOptionsRun) {
                 network_events_receiver: network_events_receivers.next().unwrap(),
                 network_service: (network_service.clone(), 1),
                 database: relay_chain_database,
+                banned_blocks: relay_chain_spec
+                    .as_ref()
+                    .unwrap()
+                    .bad_blocks_hashes()
+                    .copied()
+                    .collect(),
+                fork_blocks: relay_chain_spec
+                    .as_ref()
+                    .unwrap()
+                    .fork_blocks()
+                    .map(|(height, hash)| (height, *hash))
+                    .collect(),
             })
             .instrument(tracing::debug_span!("relay-chain-sync-service-init"))
             .await,
@@ -302,21 +341,48 @@ pub async fn run(cli_options: cli::CliOptionsRun) {
         None
     };
 
-    /*let mut telemetry = {
-        let endpoints = chain_spec
-            .telemetry_endpoints()
-            .map(|addr| (addr.as_ref().to_owned(), 0))
-            .collect::<Vec<_>>();
+    // Connect to the telemetry servers found in the chain specification, unless disabled on
+    // the command line. Invalid addresses are ignored.
+    let mut telemetry = {
+        let endpoints = if cli_options.no_telemetry {
+            Vec::new()
+        } else {
+            chain_spec
+                .telemetry_endpoints()
+                .filter_map(
+                    |(address, verbosity)| match telemetry::parse_endpoint(address) {
+                        Ok(endpoint) => Some((endpoint, verbosity)),
+                        Err(err) => {
+                            tracing::warn!(%address, %err, "invalid-telemetry-endpoint");
+                            None
+                        }
+                    },
+                )
+                .collect()
+        };
+
+        let local_peer_id = PeerId::from_public_key(&peer_id::PublicKey::Ed25519(
+            *connection::NoiseKey::new(&network_identity_key).libp2p_public_ed25519_key(),
+        ));
 
-        smoldot::telemetry::init_telemetry(smoldot::telemetry::TelemetryConfig {
-            endpoints: smoldot::telemetry::TelemetryEndpoints::new(endpoints).unwrap(),
-            wasm_external_transport: None,
+        telemetry_service::TelemetryService::new(telemetry_service::Config {
             tasks_executor: {
                 let threads_pool = threads_pool.clone();
-                Box::new(move |task| threads_pool.spawn_obj_ok(From::from(task))) as Box<_>
+                Box::new(move |task| threads_pool.spawn_ok(task))
             },
+            endpoints,
+            connected_message: telemetry::SystemConnected {
+                chain_name: chain_spec.name(),
+                node_name: &cli_options.name,
+                implementation: "smoldot",
+                version: env!("CARGO_PKG_VERSION"),
+                genesis_hash: &genesis_chain_information.finalized_block_header.hash(),
+                network_id: &local_peer_id.to_base58(),
+            }
+            .to_json(),
+            send_buffer_len: 16,
         })
-    };*/
+    };
 
     // Starting from here, a SIGINT (or equivalent) handler is setup. If the user does Ctrl+C,
     // a message will be sent on `ctrlc_rx`.
@@ -401,42 +467,21 @@ pub async fn run(cli_options: cli::CliOptionsRun) {
                 }
             }
 
-            /*telemetry_event = telemetry.next_event().fuse() => {
-                telemetry.send(smoldot::telemetry::message::TelemetryMessage::SystemConnected(smoldot::telemetry::message::SystemConnected {
-                    chain: chain_spec.name().to_owned().into_boxed_str(),
-                    name: String::from("✨ Smoldot ✨").into_boxed_str(),  // TODO: node name
-                    implementation: String::from("Secret projet 🤫").into_boxed_str(),  // TODO:
-                    version: String::from(env!("CARGO_PKG_VERSION")).into_boxed_str(),
-                    validator: None,
-                    network_id: None, // TODO: Some(service.local_peer_id().to_base58().into_boxed_str()),
-                }));
-            },*/
-
             _ = telemetry_timer.next() => {
-                /*let sync_state = sync_state.lock().await.clone();
-
-                // Some of the fields below are set to `None` because there is no plan to
-                // implement reporting accurate metrics about the node.
-                telemetry.send(smoldot::telemetry::message::TelemetryMessage::SystemInterval(smoldot::telemetry::message::SystemInterval {
-                    stats: smoldot::telemetry::message::NodeStats {
-                        peers: network_state.num_network_connections.load(Ordering::Relaxed),
-                        txcount: 0,  // TODO:
-                    },
-                    memory: None,
-                    cpu: None,
-                    bandwidth_upload: Some(0.0), // TODO:
-                    bandwidth_download: Some(0.0), // TODO:
-                    finalized_height: Some(sync_state.finalized_block_number),
-                    finalized_hash: Some(sync_state.finalized_block_hash.into()),
-                    block: smoldot::telemetry::message::Block {
-                        hash: sync_state.best_block_hash.into(),
-                        height: sync_state.best_block_number,
-                    },
-                    used_state_cache_size: None,
-                    used_db_cache_size: None,
-                    disk_read_per_sec: None,
-                    disk_write_per_sec: None,
-                }));*/
+                if telemetry.is_empty() {
+                    continue;
+                }
+
+                let sync_state = sync_service.sync_state().await;
+                telemetry.send(telemetry::VERBOSITY_INFO, &telemetry::SystemInterval {
+                    best_block_number: sync_state.best_block_number,
+                    best_block_hash: &sync_state.best_block_hash,
+                    finalized_block_number: sync_state.finalized_block_number,
+                    finalized_block_hash: &sync_state.finalized_block_hash,
+                    num_peers: u64::try_from(network_service.num_peers(0).await)
+                        .unwrap_or(u64::max_value()),
+                    num_transactions: 0, // TODO: no transactions pool in the full node yet
+                }.to_json());
             },
 
             _ = ctrlc_rx => {
@@ -510,12 +555,14 @@ async fn open_database(
             full_sqlite::DatabaseOpen::Empty(empty) => {
                 // The finalized block is the genesis block. As such, it has an empty body and
                 // no justification.
+                let genesis_storage = chain_spec.genesis_storage().into_genesis_items().unwrap();
                 empty
                     .initialize(
                         genesis_chain_information,
                         iter::empty(),
                         None,
-                        chain_spec.genesis_storage(),
+                        genesis_storage.iter(),
+                        smoldot::genesis_trie_entry_version(&genesis_storage),
                     )
                     .unwrap()
             }
@@ -0,0 +1,83 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Verification of batches of signatures on multiple threads.
+//!
+//! See [`smoldot::sync::all::BlockVerification::SignatureBatchVerify`].
+
+use futures::channel::oneshot;
+use smoldot::executor::host::SignatureVerification;
+use std::thread;
+
+/// Batches smaller than this are verified on the calling thread, as the overhead of dispatching
+/// the verifications to other threads would outweigh the gain.
+const MIN_PARALLEL_BATCH_LEN: usize = 16;
+
+/// Number of threads spawned in order to verify a batch, in addition to the calling thread.
+// TODO: make configurable
+const NUM_THREADS: usize = 3;
+
+/// Returns `true` if all the signatures of the batch are valid.
+///
+/// The signatures are split between the calling thread and [`NUM_THREADS`] threads spawned for
+/// this purpose. If spawning a thread fails, its share of the signatures is verified on the
+/// calling thread instead.
+pub async fn verify_batch(signatures: &[SignatureVerification]) -> bool {
+    if signatures.len() < MIN_PARALLEL_BATCH_LEN {
+        return signatures.iter().all(|s| s.verify());
+    }
+
+    // The calling thread verifies one of the chunks itself.
+    let chunk_size = (signatures.len() + NUM_THREADS) / (NUM_THREADS + 1);
+    let mut chunks = signatures.chunks(chunk_size);
+    let local_chunk = chunks.next().unwrap();
+
+    let mut local_valid = true;
+    let mut remote_results = Vec::with_capacity(NUM_THREADS);
+    let mut fallback_chunks = Vec::new();
+
+    for chunk in chunks {
+        let (tx, rx) = oneshot::channel();
+        let thread_spawn_result = thread::Builder::new()
+            .name("signatures-verif".into())
+            .spawn({
+                let chunk = chunk.to_vec();
+                move || {
+                    let _ = tx.send(chunk.iter().all(|s| s.verify()));
+                }
+            });
+
+        match thread_spawn_result {
+            Ok(_) => remote_results.push(rx),
+            Err(_) => fallback_chunks.push(chunk),
+        }
+    }
+
+    local_valid &= local_chunk.iter().all(|s| s.verify());
+    for chunk in fallback_chunks {
+        local_valid &= chunk.iter().all(|s| s.verify());
+    }
+
+    // All the results are waited for even if one of them is `false`, in order to not leave
+    // verifications running in the background.
+    let mut remote_valid = true;
+    for rx in remote_results {
+        remote_valid &= rx.await.unwrap_or(false);
+    }
+
+    local_valid && remote_valid
+}
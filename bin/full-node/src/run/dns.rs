@@ -0,0 +1,99 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Resolution of the domain names found in `/dns`, `/dns4` and `/dns6` multiaddresses.
+//!
+//! The [`Resolver`] trait abstracts over the way domain names are resolved. The
+//! [`SystemResolver`] uses the resolver of the operating system, while the [`CachingResolver`]
+//! wraps around another resolver and keeps its results for a certain duration.
+
+use core::{pin::Pin, time::Duration};
+use futures::prelude::*;
+use std::{collections::HashMap, io, net::IpAddr, sync::Arc, time::Instant};
+
+/// Platform-specific way to resolve domain names into IP addresses.
+pub trait Resolver: Send + Sync {
+    /// Resolves the given domain name into a list of IP addresses, both IPv4 and IPv6.
+    fn resolve(
+        &self,
+        name: &str,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<IpAddr>, io::Error>> + Send>>;
+}
+
+/// [`Resolver`] that uses the resolver of the operating system.
+#[derive(Debug, Default)]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn resolve(
+        &self,
+        name: &str,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<IpAddr>, io::Error>> + Send>> {
+        let name = name.to_owned();
+        Box::pin(async move {
+            // The port is irrelevant, but is required by the API.
+            let addrs = async_std::net::ToSocketAddrs::to_socket_addrs(&(&*name, 0)).await?;
+            Ok(addrs.map(|addr| addr.ip()).collect())
+        })
+    }
+}
+
+/// [`Resolver`] that wraps around another resolver and caches the successful resolutions.
+///
+/// Cached entries are considered stale after a certain duration, after which the name is
+/// resolved again. Failed resolutions aren't cached.
+pub struct CachingResolver<T> {
+    inner: T,
+    /// Duration after which a cache entry is stale.
+    refresh_interval: Duration,
+    /// For each domain name, when it has been resolved and the result of the resolution.
+    cache: Arc<parking_lot::Mutex<HashMap<String, (Instant, Vec<IpAddr>)>>>,
+}
+
+impl<T> CachingResolver<T> {
+    /// Initializes a new [`CachingResolver`] that resolves names using `inner`, and whose
+    /// entries are refreshed after `refresh_interval`.
+    pub fn new(inner: T, refresh_interval: Duration) -> Self {
+        CachingResolver {
+            inner,
+            refresh_interval,
+            cache: Arc::new(parking_lot::Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+impl<T: Resolver> Resolver for CachingResolver<T> {
+    fn resolve(
+        &self,
+        name: &str,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<IpAddr>, io::Error>> + Send>> {
+        if let Some((when, addrs)) = self.cache.lock().get(name) {
+            if when.elapsed() < self.refresh_interval {
+                return Box::pin(future::ready(Ok(addrs.clone())));
+            }
+        }
+
+        let resolution = self.inner.resolve(name);
+        let cache = self.cache.clone();
+        let name = name.to_owned();
+        Box::pin(async move {
+            let addrs = resolution.await?;
+            cache.lock().insert(name, (Instant::now(), addrs.clone()));
+            Ok(addrs)
+        })
+    }
+}
@@ -27,22 +27,40 @@
 // TODO: doc
 // TODO: re-review this once finished
 
+use super::dns;
+
 use core::{cmp, pin::Pin, time::Duration};
 use futures::{channel::mpsc, prelude::*};
 use futures_timer::Delay;
 use smoldot::{
+    database::full_sqlite,
+    executor, header,
     informant::HashDisplay,
     libp2p::{
         async_rw_with_buffers, connection,
         multiaddr::{Multiaddr, Protocol},
         peer_id::PeerId,
-        read_write::ReadWrite,
     },
-    network::{protocol, service},
+    network::{mdns, peerset, protocol, reputation, service},
+};
+use std::{
+    convert::TryFrom as _,
+    io, iter,
+    net::{IpAddr, SocketAddr},
+    num::NonZeroUsize,
+    sync::{Arc, Weak},
+    time::Instant,
 };
-use std::{io, net::SocketAddr, num::NonZeroUsize, sync::Arc, time::Instant};
 use tracing::Instrument as _;
 
+/// Maximum number of addresses loaded from the database of each chain that are connected to at
+/// startup, in addition to the bootnodes.
+const MAX_STORED_ADDRESSES_DIALED: usize = 32;
+
+/// Maximum number of storage proof and call proof requests of light clients waiting to be
+/// answered. Requests received while this limit is reached are refused.
+const MAX_PENDING_PROOF_REQUESTS: usize = 16;
+
 /// Configuration for a [`NetworkService`].
 pub struct Config {
     /// Closure that spawns background tasks.
@@ -54,6 +72,14 @@ pub struct Config {
     /// Addresses to listen for incoming connections.
     pub listen_addresses: Vec<Multiaddr>,
 
+    /// If `true`, nodes of the local network are discovered by sending mDNS queries. See
+    /// [`smoldot::network::mdns`].
+    pub mdns_discovery: bool,
+
+    /// Resolver used to turn the domain names of `/dns`, `/dns4` and `/dns6` multiaddresses
+    /// into IP addresses.
+    pub dns_resolver: Arc<dyn dns::Resolver>,
+
     /// List of block chains to be connected to.
     pub chains: Vec<ChainConfig>,
 
@@ -84,6 +110,10 @@ pub struct ChainConfig {
 
     /// If true, the chain uses the GrandPa networking protocol.
     pub has_grandpa_protocol: bool,
+
+    /// Database of the chain. Used in order to answer the storage proof and call proof requests
+    /// of light clients. Only requests concerning the finalized block are answered.
+    pub database: Arc<full_sqlite::SqliteFullDatabase>,
 }
 
 /// Event generated by the events reporters returned by [`NetworkService::new`].
@@ -114,6 +144,9 @@ pub struct NetworkService {
 
     /// Data structure holding the entire state of the networking.
     network: service::ChainNetwork<Instant>,
+
+    /// For each chain, see [`ChainConfig::database`].
+    databases: Vec<Arc<full_sqlite::SqliteFullDatabase>>,
 }
 
 /// Fields of [`NetworkService`] behind a mutex.
@@ -122,6 +155,40 @@ struct Guarded {
     tasks_executor: Box<dyn FnMut(Pin<Box<dyn Future<Output = ()> + Send>>) + Send>,
 }
 
+/// Light client request waiting to be answered by the [`proofs_worker`].
+enum ProofRequest {
+    Storage {
+        chain_index: usize,
+        request: service::DetachedStorageProofRequestIn,
+    },
+    Call {
+        chain_index: usize,
+        request: service::DetachedCallProofRequestIn,
+    },
+}
+
+impl ProofRequest {
+    /// Notifies the remote that the request can't be answered.
+    async fn refuse(self, network: &service::ChainNetwork<Instant>) {
+        match self {
+            ProofRequest::Storage { request, .. } => request.attach(network).refuse().await,
+            ProofRequest::Call { request, .. } => request.attach(network).refuse().await,
+        }
+    }
+}
+
+/// Runtime of a chain kept in order to answer the call proof requests of light clients.
+struct RuntimeCache {
+    /// Value of `:code` the runtime has been compiled from.
+    code: Vec<u8>,
+
+    /// Value of `:heappages` the runtime has been compiled with.
+    heap_pages: Option<Vec<u8>>,
+
+    /// Compiled runtime.
+    runtime: executor::host::HostVmPrototype,
+}
+
 impl NetworkService {
     /// Initializes the network service with the given configuration.
     pub async fn new(
@@ -131,8 +198,12 @@ impl NetworkService {
             .map(|_| mpsc::channel(16))
             .unzip();
 
-        // For each listening address in the configuration, create a background task dedicated to
-        // listening on that address.
+        let (proofs_worker_tx, proofs_worker_rx) = mpsc::channel(MAX_PENDING_PROOF_REQUESTS);
+
+        // For each listening address in the configuration, create the corresponding listening
+        // socket. A background task dedicated to each socket is spawned below, once the network
+        // service has been initialized.
+        let mut tcp_listeners = Vec::with_capacity(config.listen_addresses.len());
         for listen_address in config.listen_addresses {
             // Try to parse the requested address and create the corresponding listening socket.
             let tcp_listener: async_std::net::TcpListener = {
@@ -164,37 +235,14 @@ impl NetworkService {
                 }
             };
 
-            // Spawn a background task dedicated to this listener.
-            (config.tasks_executor)(Box::pin(
-                async move {
-                    loop {
-                        // TODO: add a way to immediately interrupt the listener if the network service is destroyed (or fails to create altogether), in order to immediately liberate the port
-
-                        let (_socket, _addr) = match tcp_listener.accept().await {
-                            Ok(v) => v,
-                            Err(_) => {
-                                // Errors here can happen if the accept failed, for example if no file
-                                // descriptor is available.
-                                // A wait is added in order to avoid having a busy-loop failing to
-                                // accept connections.
-                                futures_timer::Delay::new(Duration::from_secs(2)).await;
-                                continue;
-                            }
-                        };
-
-                        todo!() // TODO: report new connection
-                    }
-                }
-                .instrument(
-                    tracing::debug_span!(parent: None, "listener", address = %listen_address),
-                ),
-            ))
+            tcp_listeners.push((listen_address, tcp_listener));
         }
 
         // TODO: code is messy
         let mut known_nodes =
             Vec::with_capacity(config.chains.iter().map(|c| c.bootstrap_nodes.len()).sum());
         let mut chains = Vec::with_capacity(config.chains.len());
+        let mut databases = Vec::with_capacity(config.chains.len());
         for chain in config.chains {
             let mut bootstrap_nodes = Vec::with_capacity(chain.bootstrap_nodes.len());
             for (peer_id, addr) in chain.bootstrap_nodes {
@@ -202,15 +250,55 @@ impl NetworkService {
                 known_nodes.push((peer_id, addr));
             }
 
+            // In addition to the bootnodes, connect to the nodes stored in the database whose
+            // most recent dialing attempt has succeeded.
+            match chain.database.network_addresses() {
+                Ok(addresses) => {
+                    for address in addresses
+                        .into_iter()
+                        .filter(|a| a.consecutive_failures == 0)
+                        .take(MAX_STORED_ADDRESSES_DIALED)
+                    {
+                        let peer_id = match PeerId::from_bytes(address.peer_id) {
+                            Ok(p) => p,
+                            Err(_) => continue,
+                        };
+                        let addr = match Multiaddr::try_from(address.address) {
+                            Ok(a) => a,
+                            Err(_) => continue,
+                        };
+                        if known_nodes.iter().any(|(p, a)| *p == peer_id && *a == addr) {
+                            continue;
+                        }
+                        bootstrap_nodes.push(known_nodes.len());
+                        known_nodes.push((peer_id, addr));
+                    }
+                }
+                Err(error) => {
+                    tracing::warn!(%error, "network-addresses-load-error");
+                }
+            }
+
             chains.push(service::ChainConfig {
                 bootstrap_nodes,
-                in_slots: 25,
-                out_slots: 25,
+                slots: peerset::SlotsConfig {
+                    in_full: 25,
+                    in_light: 100,
+                    out_full: 25,
+                    out_light: 0,
+                },
                 protocol_id: chain.protocol_id,
                 best_hash: chain.best_block.1,
                 best_number: chain.best_block.0,
                 genesis_hash: chain.genesis_block_hash,
                 role: protocol::Role::Full,
+                serve_light_requests: true,
+                // TODO: justifications aren't stored in the database yet, making it impossible to generate warp sync proofs
+                serve_grandpa_warp_sync: false,
+                serve_kademlia_providers: true,
+                max_response_sizes: Default::default(),
+                collation_protocol: false,
+                reserved_only: false,
                 grandpa_protocol_config: if chain.has_grandpa_protocol {
                     // TODO: dummy values
                     Some(service::GrandpaState {
@@ -222,6 +310,8 @@ impl NetworkService {
                     None
                 },
             });
+
+            databases.push(chain.database);
         }
 
         // Initialize the network service.
@@ -229,6 +319,7 @@ impl NetworkService {
             guarded: parking_lot::Mutex::new(Guarded {
                 tasks_executor: config.tasks_executor,
             }),
+            databases,
             network: service::ChainNetwork::new(service::Config {
                 chains,
                 known_nodes,
@@ -236,17 +327,101 @@ impl NetworkService {
                 peers_capacity: 100,       // TODO: ?
                 noise_key: config.noise_key,
                 handshake_timeout: Duration::from_secs(8),
+                ping_interval: Duration::from_secs(20),
+                ping_timeout: Duration::from_secs(10),
+                idle_connection_timeout: None,
+                max_connections: 512,
+                max_incoming_connections_per_ip: 8,
+                max_simultaneous_dials: NonZeroUsize::new(32).unwrap(),
+                dial_backoff_initial: Duration::from_secs(2),
+                dial_backoff_max: Duration::from_secs(300),
                 // TODO: we use an abnormally large channel in order to by pass https://github.com/paritytech/smoldot/issues/615
                 // once the issue is solved, this should be restored to a smaller value, such as 64
                 pending_api_events_buffer_size: NonZeroUsize::new(2048).unwrap(),
                 randomness_seed: rand::random(),
+                reputation: reputation::Config {
+                    randomness_seed: rand::random(),
+                    disconnect_threshold: -200,
+                    ban_threshold: -500,
+                    ban_duration: Duration::from_secs(5 * 60),
+                    recovery_per_second: 2,
+                    max_peers: 8192,
+                },
+                request_receive_window: 1024 * 1024,
+                notifications_receive_window: 256 * 1024,
+                max_pending_response_bytes: 256 * 1024 * 1024,
             }),
         });
 
+        // For each listening socket, spawn a background task dedicated to accepting incoming
+        // connections on it.
+        for (listen_address, tcp_listener) in tcp_listeners {
+            (network_service.guarded.try_lock().unwrap().tasks_executor)(Box::pin({
+                let network_service = Arc::downgrade(&network_service);
+                async move {
+                    loop {
+                        // TODO: add a way to immediately interrupt the listener if the network service is destroyed, in order to immediately liberate the port
+
+                        let (socket, addr) = match tcp_listener.accept().await {
+                            Ok(v) => v,
+                            Err(_) => {
+                                // Errors here can happen if the accept failed, for example if no file
+                                // descriptor is available.
+                                // A wait is added in order to avoid having a busy-loop failing to
+                                // accept connections.
+                                futures_timer::Delay::new(Duration::from_secs(2)).await;
+                                continue;
+                            }
+                        };
+
+                        let network_service = match network_service.upgrade() {
+                            Some(ns) => ns,
+                            None => return,
+                        };
+
+                        let remote_addr = Multiaddr::from(addr.ip()).with(Protocol::Tcp(addr.port()));
+                        let id = match network_service
+                            .network
+                            .add_incoming_connection(Instant::now(), remote_addr.clone())
+                            .await
+                        {
+                            Ok(id) => id,
+                            Err(error) => {
+                                // The socket is simply closed.
+                                tracing::debug!(%remote_addr, %error, "incoming-connection-refused");
+                                continue;
+                            }
+                        };
+
+                        let network_service2 = network_service.clone();
+                        (network_service.guarded.lock().tasks_executor)(Box::pin({
+                            established_connection_task(socket, network_service2, id).instrument(
+                                tracing::trace_span!(parent: None, "connection", address = %remote_addr),
+                            )
+                        }));
+                    }
+                }
+                .instrument(
+                    tracing::debug_span!(parent: None, "listener", address = %listen_address),
+                )
+            }));
+        }
+
+        // Spawn the task that answers the light client requests.
+        (network_service.guarded.try_lock().unwrap().tasks_executor)(Box::pin(
+            proofs_worker(
+                Arc::downgrade(&network_service),
+                network_service.databases.len(),
+                proofs_worker_rx,
+            )
+            .instrument(tracing::debug_span!(parent: None, "proofs-worker")),
+        ));
+
         // Spawn a task pulling events from the network and transmitting them to the event senders.
         (network_service.guarded.try_lock().unwrap().tasks_executor)(Box::pin({
             // TODO: keeping a Weak here doesn't really work to shut down tasks
             let network_service = Arc::downgrade(&network_service);
+            let mut proofs_worker = proofs_worker_tx;
             async move {
                 loop {
                     let event = loop {
@@ -321,16 +496,97 @@ impl NetworkService {
                                 tracing::debug!(%peer_id, "identify-request");
                                 request.respond("smoldot").await;
                             }
+                            service::Event::StorageProofRequestIn {
+                                peer_id,
+                                chain_index,
+                                request,
+                            } => {
+                                tracing::debug!(
+                                    %peer_id, %chain_index,
+                                    block = %HashDisplay(request.block_hash()),
+                                    num_keys = request.keys().len(),
+                                    "storage-proof-request"
+                                );
+                                let request = ProofRequest::Storage {
+                                    chain_index,
+                                    request: request.detach(),
+                                };
+                                network_service
+                                    .queue_proof_request(&mut proofs_worker, request)
+                                    .await;
+                            }
+                            service::Event::GrandpaWarpSyncRequestIn { request, .. } => {
+                                // Can't happen, as `serve_grandpa_warp_sync` is always `false`.
+                                request.refuse().await;
+                            }
+                            service::Event::CallProofRequestIn {
+                                peer_id,
+                                chain_index,
+                                request,
+                            } => {
+                                tracing::debug!(
+                                    %peer_id, %chain_index,
+                                    block = %HashDisplay(request.block_hash()),
+                                    method = %request.method(),
+                                    "call-proof-request"
+                                );
+                                let request = ProofRequest::Call {
+                                    chain_index,
+                                    request: request.detach(),
+                                };
+                                network_service
+                                    .queue_proof_request(&mut proofs_worker, request)
+                                    .await;
+                            }
                             service::Event::GrandpaCommitMessage {
                                 chain_index,
+                                peer_id,
                                 message,
                             } => {
                                 tracing::debug!(
-                                    %chain_index,
+                                    %chain_index, %peer_id,
                                     target_hash = %HashDisplay(message.decode().message.target_hash),
                                     "grandpa-commit-message"
                                 );
                             }
+                            service::Event::GrandpaNeighborPacket {
+                                chain_index,
+                                peer_id,
+                                state,
+                            } => {
+                                tracing::debug!(
+                                    %chain_index, %peer_id,
+                                    round_number = %state.round_number,
+                                    set_id = %state.set_id,
+                                    commit_finalized_height = %state.commit_finalized_height,
+                                    "grandpa-neighbor-packet"
+                                );
+                            }
+                            service::Event::GrandpaCatchUp {
+                                chain_index,
+                                peer_id,
+                                catch_up,
+                            } => {
+                                tracing::debug!(
+                                    %chain_index, %peer_id,
+                                    base_hash = %HashDisplay(catch_up.decode().base_hash),
+                                    "grandpa-catch-up"
+                                );
+                            }
+                            service::Event::CollationMessage { .. } => {
+                                // Can't happen, as `collation_protocol` is always `false`.
+                            }
+                            service::Event::Transactions {
+                                chain_index,
+                                peer_id,
+                                transactions,
+                            } => {
+                                tracing::debug!(
+                                    %chain_index, %peer_id,
+                                    num_transactions = transactions.decode().len(),
+                                    "transactions"
+                                );
+                            }
                             service::Event::ProtocolError { peer_id, error } => {
                                 // TODO: handle properly?
                                 tracing::warn!(
@@ -395,9 +651,132 @@ impl NetworkService {
             }));
         }
 
+        // Spawn a task that periodically stores the address book of each chain in its database.
+        (network_service.guarded.try_lock().unwrap().tasks_executor)(Box::pin({
+            let network_service = Arc::downgrade(&network_service);
+            async move {
+                loop {
+                    futures_timer::Delay::new(Duration::from_secs(60)).await;
+
+                    let network_service = match network_service.upgrade() {
+                        Some(ns) => ns,
+                        None => {
+                            tracing::debug!("address-book-store-finish");
+                            return;
+                        }
+                    };
+
+                    for chain_index in 0..network_service.network.num_chains() {
+                        let address_book = network_service.network.address_book(chain_index).await;
+                        let result = network_service.databases[chain_index].set_network_addresses(
+                            address_book
+                                .into_iter()
+                                .map(|entry| full_sqlite::NetworkAddress {
+                                    peer_id: entry.peer_id.into_bytes(),
+                                    address: entry.address.to_vec(),
+                                    consecutive_failures: entry.consecutive_failures,
+                                }),
+                        );
+                        if let Err(error) = result {
+                            tracing::warn!(%chain_index, %error, "address-book-store-error");
+                        }
+                    }
+                }
+            }
+            .instrument(tracing::debug_span!(parent: None, "address-book-store"))
+        }));
+
+        // Spawn a task dedicated to the mDNS discovery.
+        if config.mdns_discovery {
+            (network_service.guarded.try_lock().unwrap().tasks_executor)(Box::pin({
+                let network_service = Arc::downgrade(&network_service);
+                async move {
+                    // Queries sent from a port other than the mDNS port are answered directly
+                    // to the sender. There is thus no need to bind to the mDNS port, which is
+                    // often already in use by the operating system.
+                    let socket =
+                        match async_std::net::UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0)))
+                            .await
+                        {
+                            Ok(s) => s,
+                            Err(error) => {
+                                tracing::warn!(%error, "mdns-bind-error");
+                                return;
+                            }
+                        };
+
+                    let query = mdns::build_query(0);
+                    let mut receive_buffer = vec![0; 9000];
+
+                    loop {
+                        if let Err(error) = socket
+                            .send_to(
+                                &query,
+                                SocketAddr::from((mdns::MULTICAST_ADDRESS_V4, mdns::PORT)),
+                            )
+                            .await
+                        {
+                            tracing::debug!(%error, "mdns-query-error");
+                        }
+
+                        // Process the responses until it is time to send the next query.
+                        let mut next_query = Delay::new(Duration::from_secs(30)).fuse();
+                        loop {
+                            let packet_len = futures::select! {
+                                _ = next_query => break,
+                                result = socket.recv_from(&mut receive_buffer).fuse() => {
+                                    match result {
+                                        Ok((len, _)) => len,
+                                        Err(_) => continue,
+                                    }
+                                }
+                            };
+
+                            let nodes = match mdns::decode_response(&receive_buffer[..packet_len]) {
+                                Ok(n) if !n.is_empty() => n,
+                                Ok(_) => continue,
+                                Err(error) => {
+                                    tracing::debug!(%error, "mdns-bad-packet");
+                                    continue;
+                                }
+                            };
+
+                            let network_service = match network_service.upgrade() {
+                                Some(ns) => ns,
+                                None => {
+                                    tracing::debug!("mdns-discovery-finish");
+                                    return;
+                                }
+                            };
+
+                            let nodes = nodes
+                                .into_iter()
+                                .map(|(peer_id, address)| {
+                                    tracing::debug!(%peer_id, %address, "mdns-discovered");
+                                    (peer_id, vec![address])
+                                })
+                                .collect::<Vec<_>>();
+
+                            // mDNS doesn't indicate which chains the nodes belong to. The nodes
+                            // are inserted in all the chains, and are disconnected from if they
+                            // turn out to not belong to them.
+                            for chain_index in 0..network_service.network.num_chains() {
+                                network_service
+                                    .network
+                                    .insert_discovered_nodes(chain_index, nodes.iter().cloned())
+                                    .await;
+                            }
+                        }
+                    }
+                }
+                .instrument(tracing::debug_span!(parent: None, "mdns-discovery"))
+            }));
+        }
+
         // Spawn task dedicated to opening connections.
         (network_service.guarded.try_lock().unwrap().tasks_executor)(Box::pin({
             let network_service = Arc::downgrade(&network_service);
+            let dns_resolver = config.dns_resolver;
             async move {
                 loop {
                     // TODO: stupid way to shut down task
@@ -409,14 +788,33 @@ impl NetworkService {
                         }
                     };
 
-                    let start_connect = network_service.network.next_start_connect(Instant::now()).await;
+                    // `next_start_connect` isn't woken up when the back-off delay of an address
+                    // expires. Start it again when that happens.
+                    let now = Instant::now();
+                    let backoff_expiration = network_service
+                        .network
+                        .next_dial_backoff_expiration(&now)
+                        .await;
+                    let start_connect = {
+                        let start_connect = network_service.network.next_start_connect(now).fuse();
+                        let backoff_expiration = match backoff_expiration {
+                            Some(when) => Delay::new(when - now).left_future(),
+                            None => future::pending().right_future(),
+                        }
+                        .fuse();
+                        futures::pin_mut!(start_connect, backoff_expiration);
+                        futures::select! {
+                            start_connect = start_connect => start_connect,
+                            () = backoff_expiration => continue,
+                        }
+                    };
 
                     let span = tracing::debug_span!("start-connect", ?start_connect.id, %start_connect.multiaddr);
                     let _enter = span.enter();
 
                     // Convert the `multiaddr` (typically of the form `/ip4/a.b.c.d/tcp/d`) into
                     // a `Future<dyn Output = Result<TcpStream, ...>>`.
-                    let socket = match multiaddr_to_socket(&start_connect.multiaddr) {
+                    let socket = match multiaddr_to_socket(&start_connect.multiaddr, &dns_resolver) {
                         Ok(socket) => socket,
                         Err(_) => {
                             tracing::debug!(%start_connect.multiaddr, "not-tcp");
@@ -476,6 +874,227 @@ impl NetworkService {
             .blocks_request(Instant::now(), &target, chain_index, config)
             .await
     }
+
+    /// Sends the given request to the [`proofs_worker`], or refuses it if too many requests are
+    /// already waiting to be answered.
+    async fn queue_proof_request(
+        &self,
+        proofs_worker: &mut mpsc::Sender<ProofRequest>,
+        request: ProofRequest,
+    ) {
+        if let Err(err) = proofs_worker.try_send(request) {
+            tracing::debug!("proof-request-refused-queue-full");
+            err.into_inner().refuse(&self.network).await;
+        }
+    }
+}
+
+/// Background task that answers the storage proof and call proof requests of light clients.
+///
+/// Generating a proof can be expensive, and is done in this task rather than in the task that
+/// processes the networking events. Requests are answered one by one, and the number of
+/// requests waiting to be answered is bounded by [`MAX_PENDING_PROOF_REQUESTS`].
+async fn proofs_worker(
+    network_service: Weak<NetworkService>,
+    num_chains: usize,
+    mut messages_rx: mpsc::Receiver<ProofRequest>,
+) {
+    // For each chain, runtime of the latest block a call proof has been requested against.
+    // `None` if no call proof has been answered yet.
+    let mut runtime_caches = (0..num_chains).map(|_| None).collect::<Vec<_>>();
+
+    while let Some(request) = messages_rx.next().await {
+        let network_service = match network_service.upgrade() {
+            Some(ns) => ns,
+            None => return,
+        };
+
+        match request {
+            ProofRequest::Storage {
+                chain_index,
+                request,
+            } => {
+                let proof = storage_proof(
+                    &network_service.databases[chain_index],
+                    request.block_hash(),
+                    request.keys(),
+                );
+                let request = request.attach(&network_service.network);
+                match proof {
+                    Some(proof) => request.respond(proof.iter()).await,
+                    None => request.refuse().await,
+                }
+            }
+            ProofRequest::Call {
+                chain_index,
+                request,
+            } => {
+                let proof = call_proof(
+                    &network_service.databases[chain_index],
+                    &mut runtime_caches[chain_index],
+                    request.block_hash(),
+                    request.method(),
+                    request.parameter(),
+                );
+                let request = request.attach(&network_service.network);
+                match proof {
+                    Some(proof) => request.respond(proof.iter()).await,
+                    None => request.refuse().await,
+                }
+            }
+        }
+    }
+}
+
+/// Builds a proof of the storage values of the given keys in the storage of the given block.
+///
+/// Returns `None` if the storage of this block isn't available, which is the case if the
+/// block is neither the finalized block nor one of its descendants.
+fn storage_proof(
+    database: &full_sqlite::SqliteFullDatabase,
+    block_hash: &[u8; 32],
+    keys: impl Iterator<Item = impl AsRef<[u8]>>,
+) -> Option<Vec<Vec<u8>>> {
+    database.block_storage_top_trie_proof(block_hash, keys).ok()
+}
+
+/// Calls the given runtime function against the storage of the given block, and builds a
+/// proof of all the storage entries accessed during the call.
+///
+/// Returns `None` if the storage of this block isn't available, which is the case if the
+/// block is neither the finalized block nor one of its descendants, or if the call has failed.
+///
+/// The database doesn't store child tries, and calls that access a child trie are refused.
+fn call_proof(
+    database: &full_sqlite::SqliteFullDatabase,
+    runtime_cache: &mut Option<RuntimeCache>,
+    block_hash: &[u8; 32],
+    method: &str,
+    parameter: &[u8],
+) -> Option<Vec<Vec<u8>>> {
+    let code = database
+        .block_storage_top_trie_get(block_hash, b":code")
+        .ok()??;
+    let heap_pages = database
+        .block_storage_top_trie_get(block_hash, b":heappages")
+        .ok()?;
+
+    // Compiling a runtime is expensive. The runtime of the previous call is re-used if the
+    // block has the same code.
+    let runtime = match runtime_cache.take() {
+        Some(cache) if cache.code == code && cache.heap_pages == heap_pages => cache.runtime,
+        _ => compile_runtime(&code, heap_pages.as_deref())?,
+    };
+
+    let (proof, runtime) = run_call_proof(database, block_hash, runtime, method, parameter);
+    if let Some(runtime) = runtime {
+        *runtime_cache = Some(RuntimeCache {
+            code,
+            heap_pages,
+            runtime,
+        });
+    }
+    proof
+}
+
+/// Performs the call of [`call_proof`] with the given runtime. Returns the proof, if successful,
+/// and the runtime, if it could be recovered.
+fn run_call_proof(
+    database: &full_sqlite::SqliteFullDatabase,
+    block_hash: &[u8; 32],
+    runtime: executor::host::HostVmPrototype,
+    method: &str,
+    parameter: &[u8],
+) -> (
+    Option<Vec<Vec<u8>>>,
+    Option<executor::host::HostVmPrototype>,
+) {
+    // Keys whose value has been read by the runtime. `:code` and `:heappages` are always
+    // included, as the remote needs them in order to perform the call.
+    let mut accessed_keys = vec![b":code".to_vec(), b":heappages".to_vec()];
+
+    let mut call =
+        match executor::read_only_runtime_host::run(executor::read_only_runtime_host::Config {
+            virtual_machine: runtime,
+            function_to_call: method,
+            parameter: iter::once(parameter),
+        }) {
+            Ok(call) => call,
+            Err((_, runtime)) => return (None, Some(runtime)),
+        };
+
+    let runtime = loop {
+        match call {
+            executor::read_only_runtime_host::RuntimeHostVm::Finished(Ok(success)) => {
+                break success.virtual_machine.into_prototype();
+            }
+            executor::read_only_runtime_host::RuntimeHostVm::Finished(Err(error)) => {
+                return (None, Some(error.prototype));
+            }
+            executor::read_only_runtime_host::RuntimeHostVm::StorageGet(get) => {
+                if get.child_trie().is_some() {
+                    return (None, None);
+                }
+                let key = get.key_as_vec();
+                let value = match database.block_storage_top_trie_get(block_hash, &key) {
+                    Ok(v) => v,
+                    // The block might have been pruned from the database in the meanwhile.
+                    Err(_) => return (None, None),
+                };
+                accessed_keys.push(key);
+                call = get.inject_value(value.as_ref().map(iter::once));
+            }
+            executor::read_only_runtime_host::RuntimeHostVm::NextKey(next_key) => {
+                if next_key.child_trie().is_some() {
+                    return (None, None);
+                }
+                let key = next_key.key().as_ref().to_vec();
+                let next = match database.block_storage_top_trie_next_key(block_hash, &key) {
+                    Ok(v) => v,
+                    Err(_) => return (None, None),
+                };
+                accessed_keys.push(key);
+                accessed_keys.extend(next.iter().cloned());
+                call = next_key.inject_key(next);
+            }
+            executor::read_only_runtime_host::RuntimeHostVm::StorageRoot(storage_root) => {
+                let state_root = match database
+                    .block_scale_encoded_header(block_hash)
+                    .ok()
+                    .flatten()
+                    .and_then(|h| header::decode(&h).ok().map(|h| *h.state_root))
+                {
+                    Some(r) => r,
+                    None => return (None, None),
+                };
+                call = storage_root.resume(&state_root);
+            }
+        }
+    };
+
+    let proof = database
+        .block_storage_top_trie_proof(block_hash, accessed_keys.iter())
+        .ok();
+    (proof, Some(runtime))
+}
+
+/// Compiles the given runtime code.
+///
+/// Returns `None` if the runtime is invalid.
+fn compile_runtime(
+    code: &[u8],
+    heap_pages: Option<&[u8]>,
+) -> Option<executor::host::HostVmPrototype> {
+    let heap_pages = executor::storage_heap_pages_to_value(heap_pages).ok()?;
+    executor::host::HostVmPrototype::new(executor::host::Config {
+        module: code,
+        heap_pages,
+        exec_hint: executor::vm::ExecHint::CompileAheadOfTime,
+        allow_unresolved_imports: false,
+        max_memory_size: None,
+        metered: false,
+    })
+    .ok()
 }
 
 /// Error when initializing the network service.
@@ -526,7 +1145,17 @@ async fn connection_task(
     };
 
     let id = network_service.network.pending_outcome_ok(id).await;
+    established_connection_task(tcp_socket, network_service, id).await
+}
 
+/// Asynchronous task managing a specific TCP connection whose [`service::ConnectionId`] has
+/// already been allocated, either after a successful dial or after accepting an incoming
+/// connection.
+async fn established_connection_task(
+    tcp_socket: async_std::net::TcpStream,
+    network_service: Arc<NetworkService>,
+    id: service::ConnectionId,
+) {
     // The Nagle algorithm, implemented in the kernel, consists in buffering the data to be sent
     // out and waiting a bit before actually sending it out, in order to potentially merge
     // multiple writes in a row into one packet. In the implementation below, it is guaranteed
@@ -538,118 +1167,51 @@ async fn connection_task(
     // The socket is wrapped around a `WithBuffers` object containing a read buffer and a write
     // buffer. These are the buffers whose pointer is passed to `read(2)` and `write(2)` when
     // reading/writing the socket.
-    let tcp_socket = async_rw_with_buffers::WithBuffers::new(tcp_socket);
-    futures::pin_mut!(tcp_socket);
-
-    loop {
-        let (read_buffer, write_buffer) = match tcp_socket.buffers() {
-            Ok(b) => b,
-            Err(error) => {
-                tracing::info!(%error, "task-finished");
-                // TODO: report disconnect to service
-                return;
-            }
-        };
-
-        let now = Instant::now();
-
-        let mut read_write = ReadWrite {
-            now,
-            incoming_buffer: read_buffer.map(|b| b.0),
-            outgoing_buffer: write_buffer,
-            read_bytes: 0,
-            written_bytes: 0,
-            wake_up_after: None,
-            wake_up_future: None,
-        };
-
-        match network_service
-            .network
-            .read_write(id, &mut read_write)
-            .await
-        {
-            Ok(rw) => rw,
-            Err(error) => {
-                tracing::info!(%error, "task-finished");
-                return;
-            }
-        };
-
-        if read_write.read_bytes != 0
-            || read_write.written_bytes != 0
-            || read_write.outgoing_buffer.is_none()
-        {
-            tracing::event!(
-                tracing::Level::TRACE,
-                read = read_write.read_bytes,
-                written = read_write.written_bytes,
-                "wake-up" = ?read_write.wake_up_after,  // TODO: ugly display
-                "write-close" = read_write.outgoing_buffer.is_none(),
-            );
-        }
-
-        if read_write.outgoing_buffer.is_none() && read_buffer.is_none() {
-            // Make sure to finish closing the TCP socket.
-            tcp_socket
-                .flush_close()
-                .instrument(tracing::debug_span!("flush-close"))
-                .await;
-            tracing::info!("task-finished");
-            return;
-        }
-
-        let read_bytes = read_write.read_bytes;
-        let written_bytes = read_write.written_bytes;
-        let write_closed = read_write.outgoing_buffer.is_none();
-        let wake_up_after = read_write.wake_up_after;
-        let wake_up_future = if let Some(wake_up_future) = read_write.wake_up_future {
-            future::Either::Left(wake_up_future)
-        } else {
-            future::Either::Right(future::pending())
-        };
-
-        if write_closed && !tcp_socket.is_closed() {
-            tcp_socket.close();
-            tracing::info!("write-closed");
-        }
-
-        tcp_socket.advance(read_bytes, written_bytes);
-
-        let mut poll_after = if let Some(wake_up) = wake_up_after {
-            if wake_up > now {
-                let dur = wake_up - now;
-                future::Either::Left(futures_timer::Delay::new(dur))
-            } else {
-                continue;
-            }
-        } else {
-            future::Either::Right(future::pending())
-        }
-        .fuse();
+    let result = async_rw_with_buffers::drive(
+        tcp_socket,
+        Instant::now,
+        futures_timer::Delay::new,
+        move |read_write| {
+            let network_service = network_service.clone();
+            Box::pin(async move {
+                let result = network_service.network.read_write(id, read_write).await;
+
+                if read_write.read_bytes != 0
+                    || read_write.written_bytes != 0
+                    || read_write.outgoing_buffer.is_none()
+                {
+                    tracing::event!(
+                        tracing::Level::TRACE,
+                        read = read_write.read_bytes,
+                        written = read_write.written_bytes,
+                        "wake-up" = ?read_write.wake_up_after,  // TODO: ugly display
+                        "write-close" = read_write.outgoing_buffer.is_none(),
+                    );
+                }
 
-        futures::select! {
-            _ = tcp_socket.as_mut().process().fuse() => {
-                tracing::event!(
-                    tracing::Level::TRACE,
-                    "socket-ready"
-                );
-            },
-            _ = wake_up_future.fuse() => {},
-            () = poll_after => {
-                // Nothing to do, but guarantees that we loop again.
-                tracing::event!(
-                    tracing::Level::TRACE,
-                    "timer-ready"
-                );
-            }
+                result
+            })
+        },
+    )
+    .await;
+
+    match result {
+        Ok(()) => tracing::info!("task-finished"),
+        Err(error) => {
+            // TODO: report disconnect to service
+            tracing::info!(%error, "task-finished");
         }
     }
 }
 
 /// Builds a future that connects to the given multiaddress. Returns an error if the multiaddress
 /// protocols aren't supported.
+///
+/// Domain names are resolved using the given [`dns::Resolver`]. If they resolve to multiple IP
+/// addresses, these addresses are tried one after the other.
 fn multiaddr_to_socket(
     addr: &Multiaddr,
+    dns_resolver: &Arc<dyn dns::Resolver>,
 ) -> Result<impl Future<Output = Result<async_std::net::TcpStream, io::Error>>, ()> {
     let mut iter = addr.iter();
     let proto1 = iter.next().ok_or(())?;
@@ -671,6 +1233,14 @@ fn multiaddr_to_socket(
 
     let proto1 = proto1.acquire();
     let proto2 = proto2.acquire();
+    let dns_resolver = dns_resolver.clone();
+
+    // `/dns4` and `/dns6` only accept IPv4 and IPv6 addresses respectively.
+    let ip_filter: fn(&IpAddr) -> bool = match proto1 {
+        Protocol::Dns4(_) => |ip| ip.is_ipv4(),
+        Protocol::Dns6(_) => |ip| ip.is_ipv6(),
+        _ => |_| true,
+    };
 
     Ok(async move {
         match (proto1, proto2) {
@@ -680,12 +1250,26 @@ fn multiaddr_to_socket(
             (Protocol::Ip6(ip), Protocol::Tcp(port)) => {
                 async_std::net::TcpStream::connect(SocketAddr::new(ip.into(), port)).await
             }
-            // TODO: for DNS, do things a bit more explicitly? with for example a library that does the resolution?
-            // TODO: differences between DNS, DNS4, DNS6 not respected
-            (Protocol::Dns(addr), Protocol::Tcp(port))
-            | (Protocol::Dns4(addr), Protocol::Tcp(port))
-            | (Protocol::Dns6(addr), Protocol::Tcp(port)) => {
-                async_std::net::TcpStream::connect((&*addr, port)).await
+            (Protocol::Dns(name), Protocol::Tcp(port))
+            | (Protocol::Dns4(name), Protocol::Tcp(port))
+            | (Protocol::Dns6(name), Protocol::Tcp(port)) => {
+                let ips = dns_resolver.resolve(&name).await?;
+                let ips = ips.into_iter().filter(ip_filter);
+
+                let mut last_error = None;
+                for ip in ips {
+                    match async_std::net::TcpStream::connect(SocketAddr::new(ip, port)).await {
+                        Ok(socket) => return Ok(socket),
+                        Err(err) => last_error = Some(err),
+                    }
+                }
+
+                Err(last_error.unwrap_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        "no IP address found for domain name",
+                    )
+                }))
             }
             _ => unreachable!(),
         }
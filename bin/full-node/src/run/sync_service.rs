@@ -24,7 +24,7 @@
 // TODO: doc
 // TODO: re-review this once finished
 
-use crate::run::network_service;
+use crate::run::{batch_verifier, network_service};
 
 use core::{convert::TryFrom as _, num::NonZeroU32, pin::Pin};
 use futures::{channel::mpsc, lock::Mutex, prelude::*};
@@ -35,6 +35,7 @@ use smoldot::{
     libp2p,
     network::{self, protocol::BlockData, service::BlocksRequestError},
     sync::{all, optimistic},
+    trie,
 };
 use std::{collections::BTreeMap, num::NonZeroU64, sync::Arc, time::SystemTime};
 use tracing::Instrument as _;
@@ -54,6 +55,13 @@ pub struct Config {
     /// Receiver for events coming from the network, as returned by
     /// [`network_service::NetworkService::new`].
     pub network_events_receiver: mpsc::Receiver<network_service::Event>,
+
+    /// List of hashes of blocks that are known to be bad, as found in the chain specification.
+    pub banned_blocks: Vec<[u8; 32]>,
+
+    /// List of block heights and hashes that the canonical chain must go through, as found in
+    /// the chain specification.
+    pub fork_blocks: Vec<(u64, [u8; 32])>,
 }
 
 /// Identifier for a blocks request to be performed.
@@ -125,6 +133,9 @@ impl SyncService {
                 },
                 max_disjoint_headers: 1024,
                 max_requests_per_block: NonZeroU32::new(3).unwrap(),
+                min_sources_per_block: NonZeroU32::new(1).unwrap(),
+                banned_blocks: config.banned_blocks,
+                fork_blocks: config.fork_blocks,
                 download_ahead_blocks: {
                     // Assuming a verification speed of 1k blocks/sec and a 95% latency of one second,
                     // the number of blocks to download ahead of time in order to not block is 1000.
@@ -142,19 +153,28 @@ impl SyncService {
                                 .map(|v| &v[..]),
                         )
                         .unwrap();
-                        executor::host::HostVmPrototype::new(
+                        executor::host::HostVmPrototype::new(executor::host::Config {
                             module,
                             heap_pages,
-                            executor::vm::ExecHint::CompileAheadOfTime, // TODO: probably should be decided by the optimisticsync
-                        )
+                            exec_hint: executor::vm::ExecHint::CompileAheadOfTime, // TODO: probably should be decided by the optimisticsync
+                            allow_unresolved_imports: false,
+                            max_memory_size: None,
+                            metered: false,
+                        })
                         .unwrap()
                     },
                 }),
             });
 
+            let finalized_trie_entries_version =
+                runtime_trie_entries_version(finalized_block_storage.get(&b":code"[..]).unwrap());
+
             SyncBackground {
                 sync,
                 finalized_block_storage,
+                finalized_trie_entries_version,
+                best_trie_entries_version: finalized_trie_entries_version,
+                non_finalized_trie_entries_versions: Default::default(),
                 sync_state: sync_state.clone(),
                 network_service: config.network_service.0,
                 network_chain_index: config.network_service.1,
@@ -187,7 +207,17 @@ impl SyncService {
 }
 
 enum ToDatabase {
-    FinalizedBlocks(Vec<optimistic::Block<()>>),
+    /// A block has been verified and can be inserted in the database without being finalized.
+    NonFinalizedBlock {
+        scale_encoded_header: Vec<u8>,
+        body: Vec<Vec<u8>>,
+        storage_top_trie_changes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+        trie_entries_version: trie::TrieEntryVersion,
+        is_new_best: bool,
+    },
+
+    /// The given blocks have been finalized, alongside with the version of their trie entries.
+    FinalizedBlocks(Vec<(optimistic::Block<()>, trie::TrieEntryVersion)>),
 }
 
 struct SyncBackground {
@@ -201,6 +231,19 @@ struct SyncBackground {
     /// parallel of this verification.
     finalized_block_storage: BTreeMap<Vec<u8>, Vec<u8>>,
 
+    /// Version of the trie entries of the storage of the latest finalized block, as indicated by
+    /// its runtime.
+    finalized_trie_entries_version: trie::TrieEntryVersion,
+
+    /// Version of the trie entries indicated by the runtime of the best block. This is the
+    /// version of the trie entries of the storage of the children of the best block.
+    best_trie_entries_version: trie::TrieEntryVersion,
+
+    /// Version of the trie entries of the storage of each non-finalized block that has been
+    /// sent to the database.
+    non_finalized_trie_entries_versions:
+        hashbrown::HashMap<[u8; 32], trie::TrieEntryVersion, fnv::FnvBuildHasher>,
+
     sync_state: Arc<Mutex<SyncState>>,
     network_service: Arc<network_service::NetworkService>,
     network_chain_index: usize,
@@ -295,6 +338,54 @@ impl SyncBackground {
         }
     }
 
+    /// Sends the given block, that has just been verified, to the database, so that the light
+    /// clients can request proofs of its storage.
+    ///
+    /// Does nothing if the body of the block isn't known, which is the case when not syncing in
+    /// full mode.
+    async fn send_non_finalized_block(&mut self, hash: &[u8; 32], is_new_best: bool) {
+        // Blocks are verified on top of the best block, whose runtime determines the version of
+        // the trie entries.
+        let trie_entries_version = self.best_trie_entries_version;
+
+        let (scale_encoded_header, body, storage_top_trie_changes) =
+            match self.sync.non_finalized_block(hash) {
+                Some(block) => (
+                    block.header.scale_encoding_vec(),
+                    block.body.clone(),
+                    block.storage_top_trie_changes.clone(),
+                ),
+                None => return,
+            };
+
+        self.update_best_trie_entries_version(&storage_top_trie_changes);
+        self.non_finalized_trie_entries_versions
+            .insert(*hash, trie_entries_version);
+
+        self.to_database
+            .send(ToDatabase::NonFinalizedBlock {
+                scale_encoded_header,
+                body,
+                storage_top_trie_changes,
+                trie_entries_version,
+                is_new_best,
+            })
+            .await
+            .unwrap();
+    }
+
+    /// Updates [`SyncBackground::best_trie_entries_version`] after a block with the given storage
+    /// changes has become the new best block.
+    fn update_best_trie_entries_version(
+        &mut self,
+        storage_top_trie_changes: &BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    ) {
+        if let Some(code) = storage_top_trie_changes.get(&b":code"[..]) {
+            // The verification would have failed if the code had been removed.
+            self.best_trie_entries_version = runtime_trie_entries_version(code.as_ref().unwrap());
+        }
+    }
+
     async fn process_blocks(mut self) -> Self {
         // The sync state machine can be in a few various states. At the time of writing:
         // idle, verifying header, verifying block, verifying grandpa warp sync proof,
@@ -342,6 +433,12 @@ impl SyncBackground {
                                 );
                                 span.record("outcome", &"failure");
                                 span.record("error", &tracing::field::display(error));
+
+                                // A failed verification resets the chain to the finalized block.
+                                self.best_trie_entries_version =
+                                    self.finalized_trie_entries_version;
+                                self.non_finalized_trie_entries_versions.clear();
+
                                 self.sync = sync_out;
                                 break;
                             }
@@ -388,41 +485,73 @@ impl SyncBackground {
                                     }
                                 }
 
+                                self.sync = sync_out;
+
+                                // The blocks that have previously been reported as verified
+                                // are found in `non_finalized_trie_entries_versions`. The others
+                                // are children of the best block.
+                                let finalized_blocks = finalized_blocks
+                                    .into_iter()
+                                    .map(|block| {
+                                        let version = match self
+                                            .non_finalized_trie_entries_versions
+                                            .remove(&block.header.hash())
+                                        {
+                                            Some(version) => version,
+                                            None => {
+                                                let version = self.best_trie_entries_version;
+                                                self.update_best_trie_entries_version(
+                                                    &block.storage_top_trie_changes,
+                                                );
+                                                version
+                                            }
+                                        };
+                                        (block, version)
+                                    })
+                                    .collect();
+
+                                // After the finalization, the best block is the finalized block.
+                                self.finalized_trie_entries_version =
+                                    self.best_trie_entries_version;
+                                self.non_finalized_trie_entries_versions.clear();
+
                                 self.to_database
                                     .send(ToDatabase::FinalizedBlocks(finalized_blocks))
                                     .await
                                     .unwrap();
-
-                                self.sync = sync_out;
                                 break;
                             }
                             all::BlockVerification::Success {
-                                is_new_best: true,
+                                is_new_best,
                                 sync: sync_out,
                                 ..
                             } => {
                                 span.record("outcome", &"success");
-                                span.record("is_new_best", &true);
+                                span.record("is_new_best", &is_new_best);
 
-                                // Processing has made a step forward.
-                                // There is nothing to do, but this is used to update to best block
-                                // shown on the informant.
-                                let mut lock = self.sync_state.lock().await;
-                                lock.best_block_hash = sync_out.best_block_hash();
-                                lock.best_block_number = sync_out.best_block_number();
-                                drop(lock);
+                                if is_new_best {
+                                    // Processing has made a step forward.
+                                    // There is nothing to do, but this is used to update to best
+                                    // block shown on the informant.
+                                    let mut lock = self.sync_state.lock().await;
+                                    lock.best_block_hash = sync_out.best_block_hash();
+                                    lock.best_block_number = sync_out.best_block_number();
+                                    drop(lock);
+                                }
 
                                 self.sync = sync_out;
-                                break;
-                            }
-                            all::BlockVerification::Success { sync: sync_out, .. } => {
-                                span.record("outcome", &"success");
-                                span.record("is_new_best", &false);
-                                self.sync = sync_out;
+                                self.send_non_finalized_block(&hash_to_verify, is_new_best)
+                                    .await;
                                 break;
                             }
 
                             all::BlockVerification::FinalizedStorageGet(req) => {
+                                // The in-memory copy of the finalized storage only contains the
+                                // main trie. The block can't be verified.
+                                if req.child_trie().is_some() {
+                                    verify = req.inject_unavailable();
+                                    continue;
+                                }
                                 let value = self
                                     .finalized_block_storage
                                     .get(&req.key_as_vec())
@@ -430,6 +559,12 @@ impl SyncBackground {
                                 verify = req.inject_value(value);
                             }
                             all::BlockVerification::FinalizedStorageNextKey(req) => {
+                                // The in-memory copy of the finalized storage only contains the
+                                // main trie. The block can't be verified.
+                                if req.child_trie().is_some() {
+                                    verify = req.inject_unavailable();
+                                    continue;
+                                }
                                 // TODO: to_vec() :-/
                                 let req_key = req.key().as_ref().to_vec();
                                 // TODO: to_vec() :-/
@@ -441,6 +576,12 @@ impl SyncBackground {
                                 verify = req.inject_key(next_key);
                             }
                             all::BlockVerification::FinalizedStoragePrefixKeys(req) => {
+                                // The in-memory copy of the finalized storage only contains the
+                                // main trie. The block can't be verified.
+                                if req.child_trie().is_some() {
+                                    verify = req.inject_unavailable();
+                                    continue;
+                                }
                                 // TODO: to_vec() :-/
                                 let prefix = req.prefix().as_ref().to_vec();
                                 // TODO: to_vec() :-/
@@ -451,6 +592,11 @@ impl SyncBackground {
                                     .map(|(k, _)| k);
                                 verify = req.inject_keys_ordered(keys);
                             }
+                            all::BlockVerification::SignatureBatchVerify(req) => {
+                                let all_valid =
+                                    batch_verifier::verify_batch(req.signatures()).await;
+                                verify = req.resume(all_valid);
+                            }
                         }
                     }
                 }
@@ -588,13 +734,41 @@ async fn start_database_write(
     loop {
         match messages_rx.next().await {
             None => break,
+            Some(ToDatabase::NonFinalizedBlock {
+                scale_encoded_header,
+                body,
+                storage_top_trie_changes,
+                trie_entries_version,
+                is_new_best,
+            }) => {
+                let span = tracing::trace_span!("non-finalized-block-db-write");
+                let _enter = span.enter();
+
+                let result = database.insert(
+                    &scale_encoded_header,
+                    is_new_best,
+                    body.iter(),
+                    storage_top_trie_changes
+                        .iter()
+                        .map(|(k, v)| (k, v.as_ref())),
+                    trie_entries_version,
+                );
+
+                match result {
+                    Ok(()) => {}
+                    Err(full_sqlite::InsertError::Duplicate) => {} // TODO: this should be an error ; right now we silence them because non-finalized blocks aren't loaded from the database at startup, resulting in them being downloaded again
+                    Err(err) => panic!("{}", err),
+                }
+            }
             Some(ToDatabase::FinalizedBlocks(finalized_blocks)) => {
                 let span = tracing::trace_span!("blocks-db-write", len = finalized_blocks.len());
                 let _enter = span.enter();
 
-                let new_finalized_hash = finalized_blocks.last().map(|lf| lf.header.hash());
+                let new_finalized_hash = finalized_blocks.last().map(|(lf, _)| lf.header.hash());
 
-                for block in finalized_blocks {
+                for (block, trie_entries_version) in finalized_blocks {
+                    // The blocks that have been sent with `ToDatabase::NonFinalizedBlock` are
+                    // already in the database.
                     // TODO: overhead for building the SCALE encoding of the header
                     let result = database.insert(
                         &block.header.scale_encoding().fold(Vec::new(), |mut a, b| {
@@ -607,11 +781,12 @@ async fn start_database_write(
                             .storage_top_trie_changes
                             .iter()
                             .map(|(k, v)| (k, v.as_ref())),
+                        trie_entries_version,
                     );
 
                     match result {
                         Ok(()) => {}
-                        Err(full_sqlite::InsertError::Duplicate) => {} // TODO: this should be an error ; right now we silence them because non-finalized blocks aren't loaded from the database at startup, resulting in them being downloaded again
+                        Err(full_sqlite::InsertError::Duplicate) => {}
                         Err(err) => panic!("{}", err),
                     }
                 }
@@ -623,3 +798,30 @@ async fn start_database_write(
         }
     }
 }
+
+/// Returns the version of the trie entries indicated by the given runtime code.
+///
+/// Version 0 is assumed if the runtime can't be executed or doesn't indicate any version.
+fn runtime_trie_entries_version(code: &[u8]) -> trie::TrieEntryVersion {
+    // The number of heap pages has no influence on the version indicated by the runtime.
+    let vm = match executor::host::HostVmPrototype::new(executor::host::Config {
+        module: code,
+        heap_pages: executor::DEFAULT_HEAP_PAGES,
+        exec_hint: executor::vm::ExecHint::Oneshot,
+        allow_unresolved_imports: true,
+        max_memory_size: None,
+        metered: false,
+    }) {
+        Ok(vm) => vm,
+        Err(_) => return trie::TrieEntryVersion::V0,
+    };
+
+    match executor::core_version(vm).0 {
+        Ok(version) => version
+            .decode()
+            .state_version
+            .and_then(|v| trie::TrieEntryVersion::from_state_version(u32::from(v)))
+            .unwrap_or(trie::TrieEntryVersion::V0),
+        Err(_) => trie::TrieEntryVersion::V0,
+    }
+}
@@ -0,0 +1,174 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Background service that reports information about the node to telemetry servers.
+//!
+//! One background task per server is spawned, which connects to the server and automatically
+//! reconnects if the connection is lost. The messages themselves are built using the
+//! [`smoldot::telemetry`] module.
+//!
+//! Messages are silently discarded if a server is unreachable or too slow to receive them, as
+//! telemetry is considered as best-effort.
+
+use futures::{
+    channel::mpsc,
+    io::{BufReader, BufWriter},
+    prelude::*,
+};
+use smoldot::telemetry::Endpoint;
+use std::{pin::Pin, time::Duration};
+
+/// Configuration for a [`TelemetryService`].
+pub struct Config {
+    /// Closure that spawns background tasks.
+    pub tasks_executor: Box<dyn FnMut(Pin<Box<dyn Future<Output = ()> + Send>>) + Send>,
+
+    /// List of telemetry servers to connect to, and their verbosity level.
+    pub endpoints: Vec<(Endpoint, u8)>,
+
+    /// JSON-formatted message sent first on each connection. Typically built using
+    /// [`smoldot::telemetry::SystemConnected::to_json`].
+    pub connected_message: String,
+
+    /// Number of messages to buffer up for each server before newer messages are discarded.
+    pub send_buffer_len: usize,
+}
+
+/// Running telemetry service. Dropping it shuts down all the background tasks.
+pub struct TelemetryService {
+    /// One sender per server, with the verbosity level of the server.
+    endpoints: Vec<(mpsc::Sender<String>, u8)>,
+}
+
+impl TelemetryService {
+    /// Initializes a new [`TelemetryService`] and spawns the tasks that connect to the servers.
+    pub fn new(mut config: Config) -> Self {
+        let connected_message = config.connected_message;
+        let send_buffer_len = config.send_buffer_len;
+        let tasks_executor = &mut config.tasks_executor;
+
+        let endpoints = config
+            .endpoints
+            .into_iter()
+            .map(|(endpoint, verbosity)| {
+                let (tx, rx) = mpsc::channel(send_buffer_len);
+                (tasks_executor)(endpoint_task(endpoint, connected_message.clone(), rx).boxed());
+                (tx, verbosity)
+            })
+            .collect();
+
+        TelemetryService { endpoints }
+    }
+
+    /// Returns `true` if the service doesn't send messages to any telemetry server.
+    pub fn is_empty(&self) -> bool {
+        self.endpoints.is_empty()
+    }
+
+    /// Sends a JSON-formatted message to all the servers whose verbosity level is superior or
+    /// equal to `verbosity`.
+    pub fn send(&mut self, verbosity: u8, message: &str) {
+        for (sender, endpoint_verbosity) in &mut self.endpoints {
+            if *endpoint_verbosity < verbosity {
+                continue;
+            }
+
+            // An error happens if the buffer is full, in which case the message is discarded.
+            let _ = sender.try_send(message.to_owned());
+        }
+    }
+}
+
+/// Background task dedicated to a single telemetry server. Ends when the corresponding sender
+/// is dropped.
+async fn endpoint_task(
+    endpoint: Endpoint,
+    connected_message: String,
+    mut messages: mpsc::Receiver<String>,
+) {
+    loop {
+        // Messages that have been queued while disconnected are obsolete.
+        while let Ok(Some(_)) = messages.try_next() {}
+
+        match connect_and_send(&endpoint, &connected_message, &mut messages).await {
+            Ok(()) => return,
+            Err(()) => {
+                tracing::debug!(
+                    host = %endpoint.host,
+                    port = endpoint.port,
+                    "telemetry-connection-failed"
+                );
+
+                // Wait a bit before reconnecting, in order to avoid hammering the server.
+                futures_timer::Delay::new(Duration::from_secs(10)).await;
+            }
+        }
+    }
+}
+
+/// Connects to the server, encrypting the connection with TLS if the endpoint requires it, and
+/// sends the messages received on `messages`.
+///
+/// Returns `Ok` if `messages` is closed, and `Err` if the connection has failed.
+async fn connect_and_send(
+    endpoint: &Endpoint,
+    connected_message: &str,
+    messages: &mut mpsc::Receiver<String>,
+) -> Result<(), ()> {
+    let socket = async_std::net::TcpStream::connect((&endpoint.host[..], endpoint.port))
+        .await
+        .map_err(|_| ())?;
+
+    if endpoint.secure {
+        let socket = async_tls::TlsConnector::default()
+            .connect(&endpoint.host, socket)
+            .await
+            .map_err(|_| ())?;
+        send_over_websocket(socket, endpoint, connected_message, messages).await
+    } else {
+        send_over_websocket(socket, endpoint, connected_message, messages).await
+    }
+}
+
+/// Performs the WebSocket handshake on the given socket, then sends the messages received on
+/// `messages`. See [`connect_and_send`].
+async fn send_over_websocket(
+    socket: impl AsyncRead + AsyncWrite + Unpin,
+    endpoint: &Endpoint,
+    connected_message: &str,
+    messages: &mut mpsc::Receiver<String>,
+) -> Result<(), ()> {
+    let io = BufReader::new(BufWriter::new(socket));
+
+    let mut client = soketto::handshake::Client::new(io, &endpoint.host, &endpoint.path);
+    match client.handshake().await {
+        Ok(soketto::handshake::ServerResponse::Accepted { .. }) => {}
+        _ => return Err(()),
+    }
+
+    let (mut sender, _receiver) = client.into_builder().finish();
+
+    sender.send_text(connected_message).await.map_err(|_| ())?;
+    sender.flush().await.map_err(|_| ())?;
+
+    while let Some(message) = messages.next().await {
+        sender.send_text(message).await.map_err(|_| ())?;
+        sender.flush().await.map_err(|_| ())?;
+    }
+
+    Ok(())
+}
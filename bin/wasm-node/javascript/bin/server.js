@@ -0,0 +1,289 @@
+#!/usr/bin/env node
+
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+// Standalone light client that loads a chain specification and serves the JSON-RPC API over
+// WebSocket and HTTP, similar to the JSON-RPC endpoint of a full node.
+//
+// This server runs the WebAssembly build of the light client on top of NodeJS. The light client
+// isn't available as a native binary, as its platform layer (timers, networking, tasks spawning)
+// is only implemented on top of the JavaScript bindings.
+//
+// Usage: smoldot-light-server --chain <path> [--parachain <path>] [--port <port>]
+//                             [--log-level <0-5>] [--max-connections <n>]
+//                             [--allowed-origin <origin>]...
+//
+// Each WebSocket connection gets its own chain from the point of view of smoldot, so that
+// subscriptions and request ids of different connections don't interfere with each other.
+// smoldot de-duplicates the services of these chains, and they all share the same networking
+// and syncing. The number of simultaneous WebSocket connections is capped by
+// `--max-connections`.
+//
+// Requests coming from web pages are only accepted if their `Origin` is one of the origins
+// passed with `--allowed-origin`, or `all` in order to accept all origins. By default, only
+// requests without an `Origin` and requests from pages served by `localhost` or `127.0.0.1` are
+// accepted.
+// HTTP POST requests are all forwarded to a single chain. Their ids are rewritten before being
+// sent to smoldot and restored in the response. Subscriptions aren't supported over HTTP.
+
+import * as smoldot from '../src/index.js';
+import { default as websocket } from 'websocket';
+import * as http from 'http';
+import * as process from 'process';
+import * as fs from 'fs';
+
+function parseArgs(argv) {
+    const args = {
+        chain: null,
+        parachain: null,
+        port: 9944,
+        logLevel: 3,
+        maxConnections: 16,
+        allowedOrigins: [],
+    };
+    for (let i = 0; i < argv.length; ++i) {
+        const value = argv[i + 1];
+        switch (argv[i]) {
+            case '--chain': args.chain = value; ++i; break;
+            case '--parachain': args.parachain = value; ++i; break;
+            case '--port': args.port = parseInt(value, 10); ++i; break;
+            case '--log-level': args.logLevel = parseInt(value, 10); ++i; break;
+            case '--max-connections': args.maxConnections = parseInt(value, 10); ++i; break;
+            case '--allowed-origin': args.allowedOrigins.push(value); ++i; break;
+            default:
+                console.error('Unknown argument: ' + argv[i]);
+                process.exit(1);
+        }
+    }
+    if (!args.chain || Number.isNaN(args.port) || Number.isNaN(args.logLevel) ||
+        Number.isNaN(args.maxConnections) || args.allowedOrigins.includes(undefined))
+    {
+        console.error('Usage: smoldot-light-server --chain <path> [--parachain <path>] [--port <port>] [--log-level <0-5>] [--max-connections <n>] [--allowed-origin <origin>]...');
+        process.exit(1);
+    }
+    return args;
+}
+
+const args = parseArgs(process.argv.slice(2));
+const relayChainSpec = fs.readFileSync(args.chain, 'utf8');
+const parachainSpec = args.parachain ? fs.readFileSync(args.parachain, 'utf8') : null;
+
+const client = smoldot.start({
+    maxLogLevel: args.logLevel,
+    forbidTcp: false,
+    forbidWs: false,
+    forbidWss: false,
+});
+
+// Adds the chain (or chains) to smoldot. `jsonRpcCallback` is passed to the chain whose JSON-RPC
+// API is served. Yields `{ relay, para }`, where `para` is `null` if no parachain was configured.
+async function addChains(client, jsonRpcCallback) {
+    if (!parachainSpec) {
+        const relay = await client.addChain({ chainSpec: relayChainSpec, jsonRpcCallback });
+        return { relay, para: null, served: relay };
+    }
+
+    const relay = await client.addChain({ chainSpec: relayChainSpec });
+    const para = await client.addChain({
+        chainSpec: parachainSpec,
+        jsonRpcCallback,
+        potentialRelayChains: [relay],
+    });
+    return { relay, para, served: para };
+}
+
+function removeChains(chains) {
+    if (chains.para)
+        chains.para.remove();
+    chains.relay.remove();
+}
+
+// Returns true if a request with the given value of the `Origin` header, or `undefined` if the
+// header is missing, is allowed. See the documentation at the top of this file.
+function isOriginAllowed(origin) {
+    if (args.allowedOrigins.length === 0) {
+        if (origin === undefined)
+            return true;
+        try {
+            const hostname = new URL(origin).hostname;
+            return hostname === 'localhost' || hostname === '127.0.0.1';
+        } catch (error) {
+            return false;
+        }
+    }
+
+    return args.allowedOrigins.includes('all') || args.allowedOrigins.includes(origin);
+}
+
+// Maximum time to wait for smoldot to answer an HTTP request before answering with an error.
+const HTTP_RESPONSE_TIMEOUT_MS = 30000;
+const httpPending = new Map();
+let httpNextId = 0;
+
+// Chain used for the HTTP requests. Also lets smoldot start syncing before any connection has
+// been established.
+const httpChain = client
+    .then(client => addChains(client, (response) => {
+        let parsed;
+        try {
+            parsed = JSON.parse(response);
+        } catch (error) {
+            return;
+        }
+        // Notifications have no `id` and can't be delivered over HTTP.
+        const pending = httpPending.get(parsed.id);
+        if (!pending)
+            return;
+        httpPending.delete(parsed.id);
+        parsed.id = pending.originalId;
+        pending.respond(JSON.stringify(parsed));
+    }))
+    .catch((error) => {
+        console.error("Error while adding chain: " + error);
+        process.exit(1);
+    });
+
+function handleHttpRequest(request, response) {
+    if (!isOriginAllowed(request.headers.origin)) {
+        response.writeHead(403);
+        response.end();
+        return;
+    }
+
+    if (request.method !== 'POST') {
+        response.writeHead(405);
+        response.end();
+        return;
+    }
+
+    let body = '';
+    request.on('data', (chunk) => body += chunk);
+    request.on('end', () => {
+        let parsed;
+        try {
+            parsed = JSON.parse(body);
+        } catch (error) {
+            response.writeHead(400);
+            response.end();
+            return;
+        }
+
+        const localId = httpNextId++;
+        const originalId = parsed.id;
+        parsed.id = localId;
+
+        // The entry is removed either when the response is sent, when the client goes away,
+        // or after a timeout if smoldot never answers (e.g. for an invalid request without an
+        // `id`).
+        const timeout = setTimeout(() => {
+            if (!httpPending.delete(localId))
+                return;
+            response.writeHead(504);
+            response.end();
+        }, HTTP_RESPONSE_TIMEOUT_MS);
+
+        httpPending.set(localId, {
+            originalId,
+            respond: (json) => {
+                clearTimeout(timeout);
+                response.writeHead(200, { 'Content-Type': 'application/json' });
+                response.end(json);
+            },
+        });
+
+        // Note that `request` emits `close` as soon as its body has been read on recent versions
+        // of NodeJS, while `response` emits `close` only once the response has been sent or the
+        // underlying connection has been closed.
+        response.on('close', () => {
+            clearTimeout(timeout);
+            httpPending.delete(localId);
+        });
+
+        httpChain
+            .then(chains => chains.served.sendJsonRpc(JSON.stringify(parsed)))
+            .catch((error) => {
+                clearTimeout(timeout);
+                httpPending.delete(localId);
+                response.writeHead(500);
+                response.end(error.toString());
+            });
+    });
+}
+
+const server = http.createServer(handleHttpRequest);
+server.listen(args.port, function () {
+    console.log('Server is listening on port ' + args.port);
+});
+
+const wsServer = new websocket.server({
+    httpServer: server,
+    autoAcceptConnections: false,
+});
+
+// Number of WebSocket connections currently open. Each of them holds its own chain or chains.
+let numConnections = 0;
+
+wsServer.on('request', function (request) {
+    // The `websocket` library sets `origin` to `null` if the header is missing.
+    if (!isOriginAllowed(request.origin === null ? undefined : request.origin)) {
+        request.reject(403);
+        return;
+    }
+
+    if (numConnections >= args.maxConnections) {
+        request.reject(503, 'Too many connections');
+        return;
+    }
+
+    const connection = request.accept(request.requestedProtocols[0], request.origin);
+    numConnections += 1;
+
+    const chains = client.then(client => addChains(client, (response) => {
+        connection.sendUTF(response);
+    }));
+
+    chains.catch((error) => {
+        console.error("Error while adding chain: " + error);
+        connection.close(400);
+    });
+
+    connection.on('message', function (message) {
+        if (message.type !== 'utf8') {
+            connection.close(400);
+            return;
+        }
+
+        chains
+            .then(chains => chains.served.sendJsonRpc(message.utf8Data))
+            .catch((error) => {
+                console.error("Error during JSON-RPC request: " + error);
+                connection.close(400);
+            });
+    });
+
+    connection.on('close', function () {
+        numConnections -= 1;
+        chains.then(removeChains).catch(() => { });
+    });
+});
+
+process.on('SIGINT', () => {
+    client
+        .then(client => client.shutdown())
+        .finally(() => process.exit(0));
+});
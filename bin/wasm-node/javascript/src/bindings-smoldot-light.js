@@ -52,6 +52,64 @@ export default (config) => {
             }
         },
 
+        // Used by the Rust side to emit the content of the database of a chain, which should be
+        // passed back when the same chain is later added again.
+        database_content_ready: (ptr, len, chainId) => {
+            ptr >>>= 0;
+            len >>>= 0;
+
+            let content = Buffer.from(config.instance.exports.memory.buffer).toString('utf8', ptr, ptr + len);
+            if (config.databaseContentCallback) {
+                config.databaseContentCallback(content, chainId);
+            }
+        },
+
+        // Used by the Rust side to emit a modification of the offchain storage of a chain, which
+        // should be passed back when the same chain is later added again.
+        offchain_storage_changed: (chainId, kind, keyPtr, keyLen, valuePtr, valueLen, valuePresent) => {
+            keyPtr >>>= 0;
+            keyLen >>>= 0;
+            valuePtr >>>= 0;
+            valueLen >>>= 0;
+
+            if (config.offchainStorageCallback) {
+                // The buffers are copied, as the memory of the Wasm VM can later be modified.
+                const memory = new Uint8Array(config.instance.exports.memory.buffer);
+                const key = memory.slice(keyPtr, keyPtr + keyLen);
+                const value = valuePresent != 0 ? memory.slice(valuePtr, valuePtr + valueLen) : null;
+                config.offchainStorageCallback({ kind: kind == 1 ? 'persistent' : 'local', key, value }, chainId);
+            }
+        },
+
+        // Used by the Rust side to emit a snapshot of its metrics, in response to a call to
+        // `metrics_request`.
+        metrics_ready: (ptr, len) => {
+            ptr >>>= 0;
+            len >>>= 0;
+
+            let metrics = Buffer.from(config.instance.exports.memory.buffer).toString('utf8', ptr, ptr + len);
+            if (config.metricsCallback) {
+                config.metricsCallback(metrics);
+            }
+        },
+
+        // Used by the Rust side to emit an estimate of the memory used by a chain, in response
+        // to a call to `chain_memory_usage_request`.
+        chain_memory_usage_ready: (chainId, runtimes, blocks, caches, network) => {
+            if (config.chainMemoryUsageCallback) {
+                const total = runtimes + blocks + caches + network;
+                config.chainMemoryUsageCallback({ runtimes, blocks, caches, network, total }, chainId);
+            }
+        },
+
+        // Used by the Rust side to notify that the shutdown requested with `shutdown` has
+        // finished.
+        shutdown_finished: () => {
+            if (config.shutdownFinishedCallback) {
+                config.shutdownFinishedCallback();
+            }
+        },
+
         // Used by the Rust side to emit a log entry.
         // See also the `max_log_level` parameter in the configuration.
         log: (level, target_ptr, target_len, message_ptr, message_len) => {
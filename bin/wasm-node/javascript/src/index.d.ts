@@ -42,6 +42,42 @@ export interface SmoldotClient {
    */
   addChain(options: SmoldotAddChainOptions): Promise<SmoldotChain>;
 
+  /**
+   * Returns a snapshot of the internal metrics of the client, such as the number of peers, the
+   * distance to the head of each chain, the number of runtime downloads, or the memory usage.
+   *
+   * The snapshot is in the text format of Prometheus.
+   * See <https://prometheus.io/docs/instrumenting/exposition_formats/>.
+   */
+  metrics(): Promise<string>;
+
+  /**
+   * Changes the maximum level of the log entries passed to the `logCallback`, overriding the
+   * `maxLogLevel` passed when starting the client.
+   *
+   * The filter is a comma-separated list of directives, each of the form `target=level` or
+   * `level`, for example `info,runtime-polkadot=debug,network=warn`. Log targets are matched by
+   * prefix. A directive without a target applies to all the other targets, and defaults to
+   * `info`. The levels are `off`, `error`, `warn`, `info`, `debug`, and `trace`.
+   *
+   * Invalid filters are ignored, and a warning is logged.
+   *
+   * @param filter Log filter to apply.
+   */
+  setLogFilter(filter: string): void;
+
+  /**
+   * Shuts down the client gracefully.
+   *
+   * The JSON-RPC callbacks are no longer called, then the `databaseContentCallback` of each
+   * chain is called one last time, so that the latest state can be persisted. Afterwards, all
+   * the connections are closed and the client is terminated.
+   *
+   * The returned promise is resolved once the shutdown has finished. Trying to use the client or
+   * any of its chains after this function has been called will lead to an exception being thrown.
+   */
+  shutdown(): Promise<void>;
+
   /**
    * Terminates the client.
    *
@@ -85,8 +121,51 @@ export interface SmoldotChain {
    * automatically keeps alive all relay chains that have an active parachains. There is no need
    * to track parachains and relaychains, or to destroy them in the correct order, as this is
    * handled automatically.
+   *
+   * Once a chain is no longer used, all the resources associated to it (connections, compiled
+   * runtimes, subscriptions, etc.) are freed. Adding and removing chains frequently, for example
+   * when switching between networks, doesn't leak memory.
    */
   remove(): void;
+
+  /**
+   * Returns an estimate of the memory used by this chain, in bytes.
+   *
+   * The resources of a chain are shared with all the other chains that have the same
+   * specification, and the resources of the relay chain of a parachain aren't included. In
+   * other words, this is an estimate of the memory that removing this chain and the other chains
+   * with the same specification would free. Embedders can use it to decide which chain to remove
+   * when running low on memory.
+   *
+   * All the values are zero if the chain is still initializing.
+   */
+  memoryUsage(): Promise<SmoldotChainMemoryUsage>;
+}
+
+/**
+ * Approximate number of bytes of memory used by a chain. See `SmoldotChain.memoryUsage`.
+ */
+export interface SmoldotChainMemoryUsage {
+  /**
+   * Runtimes kept in memory, including their code, their metadata, and their heap.
+   */
+  runtimes: number;
+  /**
+   * Headers of the blocks being tracked.
+   */
+  blocks: number;
+  /**
+   * Caches of decoded storage and call proofs.
+   */
+  caches: number;
+  /**
+   * Buffers of the connections open with peers.
+   */
+  network: number;
+  /**
+   * Sum of all the other fields.
+   */
+  total: number;
 }
 
 /**
@@ -94,6 +173,34 @@ export interface SmoldotChain {
  */
 export type SmoldotJsonRpcCallback = (response: string) => void;
 
+/**
+ * @param content Content of the database of the chain, to pass back as `databaseContent` when
+ * the chain is later added again.
+ */
+export type SmoldotDatabaseContentCallback = (content: string) => void;
+
+/**
+ * Kind of offchain storage. The `persistent` storage is expected to survive restarts, while the
+ * `local` storage doesn't necessarily need to be persisted.
+ */
+export type SmoldotOffchainStorageKind = 'persistent' | 'local';
+
+/**
+ * Entry of the offchain storage of a chain, as previously passed to `offchainStorageCallback`.
+ */
+export interface SmoldotOffchainStorageEntry {
+  kind: SmoldotOffchainStorageKind;
+  key: Uint8Array;
+  value: Uint8Array;
+}
+
+/**
+ * @param kind Kind of offchain storage that has been modified.
+ * @param key Key of the entry that has been modified.
+ * @param value New value of the entry, or `null` if the entry has been removed.
+ */
+export type SmoldotOffchainStorageCallback = (kind: SmoldotOffchainStorageKind, key: Uint8Array, value: Uint8Array | null) => void;
+
 /**
  * @param level How important this message is. 1 = Error, 2 = Warn, 3 = Info, 4 = Debug, 5 = Trace
  * @param target Name of the sub-system that the message concerns.
@@ -120,6 +227,27 @@ export interface SmoldotOptions {
    */
   maxLogLevel?: number;
 
+  /**
+   * Maximum fraction of the CPU time that the client is allowed to consume, between 0.0 and 1.0.
+   * Defaults to 1.0.
+   *
+   * When this limit is reached, the client sleeps in order to leave CPU time to the rest of the
+   * environment. This can be used in order for web pages to remain responsive on low-end
+   * devices, at the cost of the client syncing more slowly.
+   */
+  cpuRateLimit?: number;
+
+  /**
+   * Number of milliseconds that the moment when the timers of the client finish is rounded up
+   * to. Defaults to 0, which disables this rounding.
+   *
+   * Timers that finish around the same time are then all processed during the same wake up,
+   * at the cost of each timer finishing up to this number of milliseconds late. Values such as
+   * 50 can be used in order to reduce the number of wake ups, which helps environments such as
+   * mobile browsers save power.
+   */
+  timersGranularity?: number;
+
   /**
    * If `true`, then the client will never open any TCP connection.
    * Defaults to `false`.
@@ -170,6 +298,16 @@ export interface SmoldotAddChainOptions {
    */
   chainSpec: string;
 
+  /**
+   * Content of the database of this chain, as previously passed to `databaseContentCallback`.
+   *
+   * Smoldot uses this content in order to resume syncing from the state of the chain at the
+   * time the database content was emitted, rather than from the state found in the chain
+   * specification. The content is ignored if it is corrupted, if it belongs to a chain with a
+   * different genesis block, or if it is older than the state found in the chain specification.
+   */
+  databaseContent?: string;
+
   /**
    * If `chainSpec` concerns a parachain, contains the list of chains whose `id` smoldot will try
    * to match with the parachain's `relay_chain`.
@@ -197,10 +335,76 @@ export interface SmoldotAddChainOptions {
    */
   potentialRelayChains?: SmoldotChain[];
 
+  /**
+   * If `true`, smoldot only ever connects to the bootnodes found in the chain specification, and
+   * refuses all other nodes that try to connect to it. No other node is discovered.
+   * Defaults to `false`.
+   *
+   * This is useful for private chains where smoldot must only talk to a known set of nodes.
+   */
+  reservedOnly?: boolean;
+
+  /**
+   * 32 bytes ed25519 private key used as the identity of smoldot on the peer-to-peer network
+   * of this chain. If not provided, a new random identity is generated every time the chain is
+   * added.
+   *
+   * Passing the same key after a restart makes it possible for the nodes of the network to
+   * recognize smoldot, for example if they have been configured to only accept a specific set of
+   * peers.
+   */
+  networkIdentityKey?: Uint8Array;
+
   /**
    * Callback invoked by smoldot in response to calling `sendJsonRpc`.
    */
   jsonRpcCallback?: SmoldotJsonRpcCallback;
+
+  /**
+   * Maximum number of expensive JSON-RPC requests, such as runtime calls or storage queries,
+   * that are processed simultaneously for this chain. The other expensive requests are queued
+   * and processed later. If not provided, only the global limit on the number of simultaneous
+   * requests applies.
+   *
+   * This can be used in order to prevent a single misbehaving user of the client, for example
+   * a web page, from monopolizing the client when it is shared with other users.
+   */
+  jsonRpcMaxParallelExpensiveRequests?: number;
+
+  /**
+   * Callback invoked by smoldot whenever the content of the database of this chain has changed.
+   *
+   * The content should be stored somewhere, for example in the local storage of the browser, and
+   * passed back as `databaseContent` the next time the same chain is added.
+   */
+  databaseContentCallback?: SmoldotDatabaseContentCallback;
+
+  /**
+   * Content of the offchain storage of this chain, as previously passed to
+   * `offchainStorageCallback`. Defaults to `[]`.
+   *
+   * The offchain storage is read and modified through the `offchain_localStorageGet` and
+   * `offchain_localStorageSet` JSON-RPC functions. It isn't shared with the other chains, even
+   * if they have the same specification.
+   */
+  offchainStorage?: SmoldotOffchainStorageEntry[];
+
+  /**
+   * Callback invoked by smoldot whenever an entry of the offchain storage of this chain has been
+   * modified.
+   *
+   * The entries should be stored somewhere and passed back as `offchainStorage` the next time the
+   * same chain is added.
+   */
+  offchainStorageCallback?: SmoldotOffchainStorageCallback;
+
+  /**
+   * Maximum total size, in bytes, of the keys and values of the offchain storage of this chain.
+   * Modifications that would exceed this limit are refused, and entries of `offchainStorage`
+   * that don't fit are ignored.
+   * Defaults to 1 MiB.
+   */
+  offchainStorageMaxBytes?: number;
 }
 
 export interface HealthChecker {
@@ -61,6 +61,30 @@ export async function start(config) {
   // JSON-RPC response even though we've already sent a `removeChain` message to it.
   let chainsJsonRpcCallbacks = new Map();
 
+  // For each chain that is currently running, contains the callback to use to send back the
+  // content of the database of this chain. Entries are removed at the same time as the entries
+  // of `chainsJsonRpcCallbacks`, for the same reason.
+  let chainsDatabaseContentCallbacks = new Map();
+
+  // For each chain that is currently running, contains the callback to use to report the
+  // modifications of the offchain storage of this chain. Entries are removed at the same time as
+  // the entries of `chainsJsonRpcCallbacks`, for the same reason.
+  let chainsOffchainStorageCallbacks = new Map();
+
+  // List of functions to call in order to resolve the promises returned by `metrics`. Because
+  // the worker doesn't necessarily answer these requests in order, all the pending promises are
+  // resolved with the first snapshot received, which is at least as recent as the requests.
+  let pendingMetrics = [];
+
+  // For each chain that is currently running, contains the `resolve` and `reject` functions of
+  // the promises returned by `memoryUsage`. Similar to `pendingMetrics`. Entries are removed at
+  // the same time as the entries of `chainsJsonRpcCallbacks`.
+  let pendingChainsMemoryUsage = new Map();
+
+  // Function to call in order to resolve the promise returned by `shutdown`, or `null` if
+  // `shutdown` hasn't been called.
+  let shutdownResolve = null;
+
   // The worker periodically sends a message of kind 'livenessPing' in order to notify that it is
   // still alive.
   // If this liveness ping isn't received for a long time, an error is reported in the logs.
@@ -89,6 +113,30 @@ export async function start(config) {
       const cb = chainsJsonRpcCallbacks.get(message.chainId);
       if (cb) cb(message.data);
 
+    } else if (message.kind == 'databaseContent') {
+      const cb = chainsDatabaseContentCallbacks.get(message.chainId);
+      if (cb) cb(message.data);
+
+    } else if (message.kind == 'offchainStorage') {
+      const cb = chainsOffchainStorageCallbacks.get(message.chainId);
+      if (cb) cb(message.data.kind, message.data.key, message.data.value);
+
+    } else if (message.kind == 'metrics') {
+      // `pendingMetrics` is reset before resolving, in case a callback calls `metrics` again.
+      const pending = pendingMetrics;
+      pendingMetrics = [];
+      for (const resolve of pending)
+        resolve(message.data);
+
+    } else if (message.kind == 'chainMemoryUsage') {
+      const pending = pendingChainsMemoryUsage.get(message.chainId);
+      if (pending) {
+        // The list is reset before resolving, in case a callback calls `memoryUsage` again.
+        pendingChainsMemoryUsage.set(message.chainId, []);
+        for (const { resolve } of pending)
+          resolve(message.data);
+      }
+
     } else if (message.kind == 'chainAddedOk') {
       const expected = pendingConfirmations.shift();
       let chainId = message.chainId; // Later set to null when the chain is removed.
@@ -96,6 +144,11 @@ export async function start(config) {
       if (chainsJsonRpcCallbacks.has(chainId)) // Sanity check.
         throw 'Unexpected reuse of a chain ID';
       chainsJsonRpcCallbacks.set(chainId, expected.jsonRpcCallback);
+      if (expected.databaseContentCallback)
+        chainsDatabaseContentCallbacks.set(chainId, expected.databaseContentCallback);
+      if (expected.offchainStorageCallback)
+        chainsOffchainStorageCallbacks.set(chainId, expected.offchainStorageCallback);
+      pendingChainsMemoryUsage.set(chainId, []);
 
       // `expected` was pushed by the `addChain` method.
       // Resolve the promise that `addChain` returned to the user.
@@ -120,8 +173,25 @@ export async function start(config) {
           // response concerning that `chainId` to arrive after the `remove` function has
           // returned. We solve that by removing the callback immediately.
           chainsJsonRpcCallbacks.delete(chainId);
+          chainsDatabaseContentCallbacks.delete(chainId);
+          chainsOffchainStorageCallbacks.delete(chainId);
+          // Promises returned by `memoryUsage` that haven't been resolved yet are rejected.
+          for (const { reject } of pendingChainsMemoryUsage.get(chainId))
+            reject(new SmoldotError('Chain has been removed'));
+          pendingChainsMemoryUsage.delete(chainId);
           chainId = null;
         },
+        memoryUsage: () => {
+          if (workerError)
+            throw workerError;
+          if (chainId === null)
+            throw new SmoldotError('Chain has already been removed');
+          const promise = new Promise((resolve, reject) => {
+            pendingChainsMemoryUsage.get(chainId).push({ resolve, reject });
+          });
+          worker.postMessage({ ty: 'chainMemoryUsage', chainId });
+          return promise;
+        },
         // Hacky internal method that later lets us access the `chainId` of this chain for
         // implementation reasons.
         __internal_smoldot_id: () => chainId,
@@ -139,6 +209,12 @@ export async function start(config) {
     } else if (message.kind == 'log') {
       logCallback(message.level, message.target, message.message);
 
+    } else if (message.kind == 'shutdownFinished') {
+      if (livenessTimeout !== null)
+        clearTimeout(livenessTimeout);
+      worker.terminate();
+      shutdownResolve();
+
     } else if (message.kind == 'livenessPing') {
       resetLivenessTimeout();
 
@@ -170,6 +246,12 @@ export async function start(config) {
     // Maximum level of log entries sent by the client.
     // 0 = Logging disabled, 1 = Error, 2 = Warn, 3 = Info, 4 = Debug, 5 = Trace
     maxLogLevel: config.maxLogLevel || 3,
+    // Maximum fraction of CPU time the client is allowed to consume, as a 32 bits unsigned
+    // integer where `4294967295` means 100%.
+    cpuRateLimit: Math.round(4294967295 * Math.min(Math.max(config.cpuRateLimit || 1.0, 0.0), 1.0)),
+    // Number of milliseconds that the moment when timers finish is rounded up to, as a 32 bits
+    // unsigned integer.
+    timersGranularity: Math.round(Math.min(Math.max(config.timersGranularity || 0, 0), 4294967295)),
     forbidTcp: config.forbidTcp,
     forbidWs: config.forbidWs,
     forbidWss: config.forbidWss,
@@ -193,6 +275,9 @@ export async function start(config) {
         }
       }
 
+      if (!!options.networkIdentityKey && options.networkIdentityKey.length != 32)
+        throw new Error("networkIdentityKey must be 32 bytes long");
+
       // Build a promise that will be resolved or rejected after the chain has been added.
       let chainAddedPromiseResolve;
       let chainAddedPromiseReject;
@@ -206,17 +291,53 @@ export async function start(config) {
         reject: chainAddedPromiseReject,
         resolve: chainAddedPromiseResolve,
         jsonRpcCallback: options.jsonRpcCallback,
+        databaseContentCallback: options.databaseContentCallback,
+        offchainStorageCallback: options.offchainStorageCallback,
       });
 
       worker.postMessage({
         ty: 'addChain',
         chainSpec: options.chainSpec,
+        databaseContent: options.databaseContent,
         potentialRelayChains: potentialRelayChainsIds,
         jsonRpcRunning: !!options.jsonRpcCallback,
+        // Number of expensive JSON-RPC requests processed simultaneously, as a 32 bits unsigned
+        // integer. 0 means no specific limit.
+        jsonRpcMaxParallelExpensiveRequests:
+          Math.round(Math.min(Math.max(options.jsonRpcMaxParallelExpensiveRequests || 0, 0), 4294967295)),
+        offchainStorage: options.offchainStorage || [],
+        // Maximum size of the offchain storage, as a 32 bits unsigned integer. Defaults to 1 MiB.
+        offchainStorageMaxBytes: Math.round(Math.min(Math.max(
+          options.offchainStorageMaxBytes === undefined ? 1024 * 1024 : options.offchainStorageMaxBytes,
+          0), 4294967295)),
+        reservedOnly: !!options.reservedOnly,
+        networkIdentityKey: options.networkIdentityKey,
       });
 
       return chainAddedPromise;
     },
+    metrics: () => {
+      if (workerError)
+        throw workerError;
+      const promise = new Promise((resolve) => pendingMetrics.push(resolve));
+      worker.postMessage({ ty: 'metrics' });
+      return promise;
+    },
+    setLogFilter: (filter) => {
+      if (workerError)
+        throw workerError;
+      worker.postMessage({ ty: 'setLogFilter', filter });
+    },
+    shutdown: () => {
+      if (workerError)
+        throw workerError;
+      // Any further use of the client throws an exception, while the messages that the worker
+      // sends during the shutdown, such as the database content, are still processed.
+      workerError = new Error("shutdown() has been called");
+      const promise = new Promise((resolve) => shutdownResolve = resolve);
+      worker.postMessage({ ty: 'shutdown' });
+      return promise;
+    },
     terminate: () => {
       worker.terminate();
       if (!workerError)
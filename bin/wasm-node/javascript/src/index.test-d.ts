@@ -9,6 +9,8 @@ import smoldot, { Smoldot, SmoldotClient } from 'smoldot';
 // $ExpectType Promise<SmoldotClient>
 let sp = smoldot.start({
   maxLogLevel: 3,
+  cpuRateLimit: 0.5,
+  timersGranularity: 50,
   logCallback: (level, target, message) => { },
   forbidTcp: false,
   forbidWs: false,
@@ -22,15 +24,25 @@ sp = smoldot.start();
 
 sp.then(async (sm) => {
   // $ExpectType Promise<SmoldotChain>
-  const chain1 = sm.addChain({ chainSpec: '' });
+  const chain1 = sm.addChain({ chainSpec: '', databaseContent: '', databaseContentCallback: (content) => { } });
   // $ExpectType Promise<SmoldotChain>
-  const chain2Promise = sm.addChain({ chainSpec: '', potentialRelayChains: [await chain1], jsonRpcCallback: (resp) => { } });
+  const chain2Promise = sm.addChain({ chainSpec: '', potentialRelayChains: [await chain1], jsonRpcCallback: (resp) => { }, jsonRpcMaxParallelExpensiveRequests: 4 });
+  // $ExpectType Promise<SmoldotChain>
+  sm.addChain({ chainSpec: '', offchainStorage: [{ kind: 'persistent', key: new Uint8Array(), value: new Uint8Array() }], offchainStorageCallback: (kind, key, value) => { }, offchainStorageMaxBytes: 1024 });
   // $ExpectType SmoldotChain
   const chain2 = await chain2Promise;
   // $ExpectType void
   chain2.sendJsonRpc('{"id":8,"jsonrpc":"2.0","method":"system_health","params":[]}');
+  // $ExpectType Promise<SmoldotChainMemoryUsage>
+  chain2.memoryUsage();
   // $ExpectType void
   chain2.remove();
+  // $ExpectType Promise<string>
+  sm.metrics();
+  // $ExpectType void
+  sm.setLogFilter('info,network=debug');
+  // $ExpectType Promise<void>
+  sm.shutdown();
   // $ExpectType void
   sm.terminate();
 });
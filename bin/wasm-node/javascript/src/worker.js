@@ -47,6 +47,13 @@ const injectMessage = (instance, message) => {
     Buffer.from(instance.exports.memory.buffer)
       .write(message.chainSpec, chainSpecPtr);
 
+    // Write the database content into memory. Empty if no database content is available.
+    const databaseContent = message.databaseContent || '';
+    const databaseContentLen = Buffer.byteLength(databaseContent, 'utf8');
+    const databaseContentPtr = instance.exports.alloc(databaseContentLen) >>> 0;
+    Buffer.from(instance.exports.memory.buffer)
+      .write(databaseContent, databaseContentPtr);
+
     // Write the potential relay chains into memory.
     const potentialRelayChainsLen = message.potentialRelayChains.length;
     const potentialRelayChainsPtr = instance.exports.alloc(potentialRelayChainsLen * 4) >>> 0;
@@ -55,17 +62,44 @@ const injectMessage = (instance, message) => {
         .writeUInt32LE(message.potentialRelayChains[idx], potentialRelayChainsPtr + idx * 4);
     }
 
+    // Write the network identity key into memory, if any.
+    const networkIdentityKeyLen = message.networkIdentityKey ? message.networkIdentityKey.length : 0;
+    const networkIdentityKeyPtr = instance.exports.alloc(networkIdentityKeyLen) >>> 0;
+    if (message.networkIdentityKey) {
+      Buffer.from(instance.exports.memory.buffer)
+        .set(message.networkIdentityKey, networkIdentityKeyPtr);
+    }
+
     // `add_chain` unconditionally allocates a chain id. If an error occurs, however, this chain
     // id will refer to an *erroneous* chain. `chain_is_ok` is used below to determine whether it
     // has succeeeded or not.
     // Note that `add_chain` properly de-allocates buffers even if it failed.
     const chainId = instance.exports.add_chain(
       chainSpecPtr, chainSpecLen,
+      databaseContentPtr, databaseContentLen,
       message.jsonRpcRunning,
+      message.jsonRpcMaxParallelExpensiveRequests,
+      message.offchainStorageMaxBytes,
+      message.reservedOnly ? 1 : 0,
+      networkIdentityKeyPtr, networkIdentityKeyLen,
       potentialRelayChainsPtr, potentialRelayChainsLen
     );
 
     if (instance.exports.chain_is_ok(chainId) != 0) {
+      // Restore the offchain storage before any JSON-RPC request can reach the chain.
+      for (const entry of message.offchainStorage) {
+        const keyPtr = instance.exports.alloc(entry.key.length) >>> 0;
+        Buffer.from(instance.exports.memory.buffer).set(entry.key, keyPtr);
+        const valuePtr = instance.exports.alloc(entry.value.length) >>> 0;
+        Buffer.from(instance.exports.memory.buffer).set(entry.value, valuePtr);
+        instance.exports.chain_offchain_storage_load(
+          chainId,
+          entry.kind == 'persistent' ? 1 : 2,
+          keyPtr, entry.key.length,
+          valuePtr, entry.value.length
+        );
+      }
+
       compat.postMessage({ kind: 'chainAddedOk', chainId });
     } else {
       const errorMsgLen = instance.exports.chain_error_len(chainId) >>> 0;
@@ -76,6 +110,21 @@ const injectMessage = (instance, message) => {
       compat.postMessage({ kind: 'chainAddedErr', error: new Error(errorMsg) });
     }
 
+  } else if (message.ty == 'metrics') {
+    instance.exports.metrics_request();
+
+  } else if (message.ty == 'chainMemoryUsage') {
+    instance.exports.chain_memory_usage_request(message.chainId);
+
+  } else if (message.ty == 'shutdown') {
+    instance.exports.client_shutdown();
+
+  } else if (message.ty == 'setLogFilter') {
+    const len = Buffer.byteLength(message.filter, 'utf8');
+    const ptr = instance.exports.alloc(len) >>> 0;
+    Buffer.from(instance.exports.memory.buffer).write(message.filter, ptr);
+    instance.exports.set_log_filter(ptr, len);
+
   } else if (message.ty == 'removeChain') {
     instance.exports.remove_chain(message.chainId);
     // `compat.postMessage` is the same as `postMessage`, but works across environments.
@@ -101,6 +150,26 @@ const startInstance = async (config) => {
       // `compat.postMessage` is the same as `postMessage`, but works across environments.
       compat.postMessage({ kind: 'jsonrpc', data, chainId });
     },
+    metricsCallback: (data) => {
+      // `compat.postMessage` is the same as `postMessage`, but works across environments.
+      compat.postMessage({ kind: 'metrics', data });
+    },
+    chainMemoryUsageCallback: (data, chainId) => {
+      // `compat.postMessage` is the same as `postMessage`, but works across environments.
+      compat.postMessage({ kind: 'chainMemoryUsage', data, chainId });
+    },
+    shutdownFinishedCallback: () => {
+      // `compat.postMessage` is the same as `postMessage`, but works across environments.
+      compat.postMessage({ kind: 'shutdownFinished' });
+    },
+    databaseContentCallback: (data, chainId) => {
+      // `compat.postMessage` is the same as `postMessage`, but works across environments.
+      compat.postMessage({ kind: 'databaseContent', data, chainId });
+    },
+    offchainStorageCallback: (data, chainId) => {
+      // `compat.postMessage` is the same as `postMessage`, but works across environments.
+      compat.postMessage({ kind: 'offchainStorage', data, chainId });
+    },
     forbidTcp: config.forbidTcp,
     forbidWs: config.forbidWs,
     forbidWss: config.forbidWss,
@@ -125,7 +194,7 @@ const startInstance = async (config) => {
   wasiConfig.instance = result.instance;
 
   // Start initialization of smoldot.
-  result.instance.exports.init(config.maxLogLevel);
+  result.instance.exports.init(config.maxLogLevel, config.cpuRateLimit, config.timersGranularity);
 
   // Smoldot has finished initializing.
   // Since this function is an asynchronous function, it is possible that messages have been
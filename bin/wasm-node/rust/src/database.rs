@@ -0,0 +1,132 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Content of the database of a chain.
+//!
+//! The content of the database is periodically emitted through the FFI layer, and passed back
+//! by the user when the chain is added again. It consists of the serialized finalized chain
+//! information, alongside with the hash of the genesis block of the chain it belongs to. The
+//! genesis hash makes it possible to detect database contents that are passed to the wrong
+//! chain.
+
+use smoldot::{
+    chain::chain_information, database::finalized_serialize, json_rpc::methods::HashHexString,
+};
+
+/// Serializes the content of the database of a chain.
+///
+/// `chain_information` must be the output of [`finalized_serialize::encode_chain`].
+pub fn encode_database(genesis_block_hash: &[u8; 32], chain_information: &str) -> String {
+    serde_json::json!({
+        "genesisHash": HashHexString(*genesis_block_hash),
+        // The chain information is always valid JSON, as it has been produced by
+        // `finalized_serialize`.
+        "chain": serde_json::from_str::<serde_json::Value>(chain_information).unwrap(),
+    })
+    .to_string()
+}
+
+/// Deserializes the content of the database of a chain.
+///
+/// Returns an error if the content is corrupted or if it belongs to a chain whose genesis block
+/// hash isn't `expected_genesis_block_hash`.
+pub fn decode_database(
+    encoded: &str,
+    expected_genesis_block_hash: &[u8; 32],
+) -> Result<chain_information::ValidChainInformation, DecodeError> {
+    let mut decoded =
+        serde_json::from_str::<serde_json::Value>(encoded).map_err(|_| DecodeError::Corrupted)?;
+
+    let genesis_block_hash = decoded
+        .get_mut("genesisHash")
+        .map(serde_json::Value::take)
+        .ok_or(DecodeError::Corrupted)?;
+    let genesis_block_hash = serde_json::from_value::<HashHexString>(genesis_block_hash)
+        .map_err(|_| DecodeError::Corrupted)?
+        .0;
+    if genesis_block_hash != *expected_genesis_block_hash {
+        return Err(DecodeError::GenesisHashMismatch);
+    }
+
+    let chain = decoded.get("chain").ok_or(DecodeError::Corrupted)?;
+    let (chain_information, _) = finalized_serialize::decode_chain(&chain.to_string())
+        .map_err(|_| DecodeError::Corrupted)?;
+    Ok(chain_information)
+}
+
+/// Error potentially returned by [`decode_database`].
+#[derive(Debug, derive_more::Display, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The database content is invalid.
+    #[display(fmt = "Corrupted database content")]
+    Corrupted,
+    /// The database content belongs to a chain with a different genesis block.
+    #[display(fmt = "Database content belongs to a different chain")]
+    GenesisHashMismatch,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_database, encode_database, DecodeError};
+    use smoldot::{chain::chain_information, chain_spec::ChainSpec, database::finalized_serialize};
+
+    fn chain_information() -> chain_information::ValidChainInformation {
+        let chain_spec =
+            ChainSpec::from_json_bytes(&include_bytes!("../../../polkadot.json")[..]).unwrap();
+        chain_information::ValidChainInformation::from_chain_spec(&chain_spec).unwrap()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let chain_information = chain_information();
+        let encoded = encode_database(
+            &[1; 32],
+            &finalized_serialize::encode_chain((&chain_information).into()),
+        );
+
+        let decoded = decode_database(&encoded, &[1; 32]).unwrap();
+        assert_eq!(
+            decoded.as_ref().finalized_block_header.hash(),
+            chain_information.as_ref().finalized_block_header.hash()
+        );
+    }
+
+    #[test]
+    fn genesis_hash_mismatch() {
+        let encoded = encode_database(
+            &[1; 32],
+            &finalized_serialize::encode_chain((&chain_information()).into()),
+        );
+
+        assert_eq!(
+            decode_database(&encoded, &[2; 32]).unwrap_err(),
+            DecodeError::GenesisHashMismatch
+        );
+    }
+
+    #[test]
+    fn corrupted() {
+        assert_eq!(
+            decode_database("foo", &[1; 32]).unwrap_err(),
+            DecodeError::Corrupted
+        );
+        assert_eq!(
+            decode_database(r#"{"chain":{}}"#, &[1; 32]).unwrap_err(),
+            DecodeError::Corrupted
+        );
+    }
+}
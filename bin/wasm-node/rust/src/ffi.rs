@@ -23,6 +23,7 @@ use core::{
     fmt,
     future::Future,
     marker,
+    num::NonZeroU32,
     ops::{Add, Sub},
     pin::Pin,
     slice, str,
@@ -30,8 +31,8 @@ use core::{
     time::Duration,
 };
 use futures::prelude::*;
+use smoldot::{executor::host::OffchainStorageKind, libp2p::read_write::IncomingBuffers};
 use std::{
-    collections::VecDeque,
     sync::{atomic, Arc, Mutex},
     task,
 };
@@ -196,14 +197,21 @@ impl Sub<Instant> for Instant {
 }
 
 /// Implementation of [`log::Log`] that sends out logs to the FFI.
+///
+/// Log entries are filtered according to [`LOG_FILTER`], in addition to the global maximum level
+/// of the `log` crate.
 pub(crate) struct Logger;
 
 impl log::Log for Logger {
-    fn enabled(&self, _: &log::Metadata) -> bool {
-        true
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= LOG_FILTER.lock().unwrap().level(metadata.target())
     }
 
     fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
         let target = record.target();
         let message = format!("{}", record.args());
 
@@ -230,11 +238,8 @@ pub struct Connection {
     open: bool,
     /// `Some` if [`bindings::connection_closed`] has been called.
     closed_message: Option<String>,
-    /// List of messages received through [`bindings::connection_message`]. Must never contain
-    /// empty messages.
-    messages_queue: VecDeque<Box<[u8]>>,
-    /// Position of the read cursor within the first element of [`Connection::messages_queue`].
-    messages_queue_first_offset: usize,
+    /// List of messages received through [`bindings::connection_message`].
+    messages_queue: IncomingBuffers,
     /// Waker to wake up whenever one of the fields above is modified.
     waker: Option<Waker>,
     /// Prevents the [`Connection`] from being unpinned.
@@ -248,8 +253,9 @@ impl Connection {
             id: None,
             open: false,
             closed_message: None,
-            messages_queue: VecDeque::with_capacity(32),
-            messages_queue_first_offset: 0,
+            // The messages are allocated by the JavaScript code through `alloc`, and the
+            // processed buffers can't be reused.
+            messages_queue: IncomingBuffers::new(0),
             waker: None,
             _pinned: marker::PhantomPinned,
         });
@@ -335,10 +341,8 @@ impl Connection {
         })
         .await;
 
-        if let Some(buffer) = self.messages_queue.front() {
-            debug_assert!(!buffer.is_empty());
-            debug_assert!(self.messages_queue_first_offset < buffer.len());
-            Some(&buffer[self.messages_queue_first_offset..])
+        if !self.messages_queue.is_empty() {
+            Some(self.messages_queue.front())
         } else if self.closed_message.is_some() {
             None
         } else {
@@ -357,17 +361,7 @@ impl Connection {
     pub fn advance_read_cursor(self: &mut Pin<Box<Self>>, bytes: usize) {
         let this = unsafe { Pin::get_unchecked_mut(self.as_mut()) };
 
-        this.messages_queue_first_offset += bytes;
-
-        if let Some(buffer) = this.messages_queue.front() {
-            assert!(this.messages_queue_first_offset <= buffer.len());
-            if this.messages_queue_first_offset == buffer.len() {
-                this.messages_queue.pop_front();
-                this.messages_queue_first_offset = 0;
-            }
-        } else {
-            assert_eq!(bytes, 0);
-        };
+        this.messages_queue.advance(bytes);
     }
 
     /// Queues the given buffer. For WebSocket connections, queues it as a binary frame.
@@ -417,15 +411,20 @@ fn alloc(len: u32) -> u32 {
     u32::try_from(ptr as *mut u8 as usize).unwrap()
 }
 
-fn init(max_log_level: u32) {
-    let client = super::Client::new(match max_log_level {
-        0 => log::LevelFilter::Off,
-        1 => log::LevelFilter::Error,
-        2 => log::LevelFilter::Warn,
-        3 => log::LevelFilter::Info,
-        4 => log::LevelFilter::Debug,
-        _ => log::LevelFilter::Trace,
-    });
+fn init(max_log_level: u32, cpu_rate_limit: u32, timers_granularity_ms: u32) {
+    timers::set_granularity(Duration::from_millis(u64::from(timers_granularity_ms)));
+
+    let client = super::Client::new(
+        match max_log_level {
+            0 => log::LevelFilter::Off,
+            1 => log::LevelFilter::Error,
+            2 => log::LevelFilter::Warn,
+            3 => log::LevelFilter::Info,
+            4 => log::LevelFilter::Debug,
+            _ => log::LevelFilter::Trace,
+        },
+        cpu_rate_limit,
+    );
 
     let mut client_lock = CLIENT.lock().unwrap();
     assert!(client_lock.is_none());
@@ -435,7 +434,14 @@ fn init(max_log_level: u32) {
 fn add_chain(
     chain_spec_pointer: u32,
     chain_spec_len: u32,
+    database_content_ptr: u32,
+    database_content_len: u32,
     json_rpc_running: u32,
+    json_rpc_max_parallel_expensive_requests: u32,
+    offchain_storage_max_bytes: u32,
+    reserved_only: u32,
+    network_identity_key_ptr: u32,
+    network_identity_key_len: u32,
     potential_relay_chains_ptr: u32,
     potential_relay_chains_len: u32,
 ) -> u32 {
@@ -450,6 +456,28 @@ fn add_chain(
         }
     };
 
+    let database_content: Box<[u8]> = {
+        let database_content_ptr = usize::try_from(database_content_ptr).unwrap();
+        let database_content_len = usize::try_from(database_content_len).unwrap();
+        unsafe {
+            Box::from_raw(slice::from_raw_parts_mut(
+                database_content_ptr as *mut u8,
+                database_content_len,
+            ))
+        }
+    };
+
+    let network_identity_key: Box<[u8]> = {
+        let network_identity_key_ptr = usize::try_from(network_identity_key_ptr).unwrap();
+        let network_identity_key_len = usize::try_from(network_identity_key_len).unwrap();
+        unsafe {
+            Box::from_raw(slice::from_raw_parts_mut(
+                network_identity_key_ptr as *mut u8,
+                network_identity_key_len,
+            ))
+        }
+    };
+
     let potential_relay_chains: Vec<_> = {
         let allowed_relay_chains_ptr = usize::try_from(potential_relay_chains_ptr).unwrap();
         let allowed_relay_chains_len = usize::try_from(potential_relay_chains_len).unwrap();
@@ -475,7 +503,18 @@ fn add_chain(
         .unwrap()
         .add_chain(super::AddChainConfig {
             specification: str::from_utf8(&chain_spec).unwrap(),
+            database_content: str::from_utf8(&database_content).unwrap(),
             json_rpc_running: json_rpc_running != 0,
+            json_rpc_max_parallel_expensive_requests: NonZeroU32::new(
+                json_rpc_max_parallel_expensive_requests,
+            ),
+            offchain_storage_max_bytes: usize::try_from(offchain_storage_max_bytes).unwrap(),
+            reserved_only: reserved_only != 0,
+            network_identity_key: if network_identity_key.is_empty() {
+                None
+            } else {
+                Some(<[u8; 32]>::try_from(&network_identity_key[..]).unwrap())
+            },
             potential_relay_chains: potential_relay_chains.into_iter(),
         })
         .into()
@@ -489,6 +528,18 @@ fn remove_chain(chain_id: u32) {
         .remove_chain(super::ChainId::from(chain_id))
 }
 
+fn shutdown() {
+    let client = CLIENT.lock().unwrap().take().unwrap();
+    let shutdown = client.shutdown();
+
+    spawn_background_task(async move {
+        shutdown.await;
+        unsafe {
+            bindings::shutdown_finished();
+        }
+    });
+}
+
 fn chain_is_ok(chain_id: u32) -> u32 {
     let mut client_lock = CLIENT.lock().unwrap();
     if client_lock
@@ -527,6 +578,81 @@ fn chain_error_ptr(chain_id: u32) -> u32 {
 
 lazy_static::lazy_static! {
     static ref CLIENT: Mutex<Option<super::Client>> = Mutex::new(None);
+
+    /// Filter applied by the [`Logger`]. By default, only the maximum level passed to [`init`]
+    /// applies.
+    static ref LOG_FILTER: Mutex<LogFilter> = Mutex::new(LogFilter {
+        default: log::LevelFilter::Trace,
+        targets: Vec::new(),
+    });
+}
+
+/// Maximum log level of each log target. See [`set_log_filter`].
+struct LogFilter {
+    /// Level applied to the targets that don't match any entry of [`LogFilter::targets`].
+    default: log::LevelFilter,
+    /// List of target prefixes and their level. The longest matching prefix applies.
+    targets: Vec<(String, log::LevelFilter)>,
+}
+
+impl LogFilter {
+    /// Parses a comma-separated list of directives, each of the form `target=level` or `level`.
+    /// A directive without a target sets the default level.
+    fn parse(filter: &str) -> Result<Self, log::ParseLevelError> {
+        let mut out = LogFilter {
+            default: log::LevelFilter::Info,
+            targets: Vec::new(),
+        };
+
+        for directive in filter.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((target, level)) => out
+                    .targets
+                    .push((target.trim().to_owned(), level.trim().parse()?)),
+                None => out.default = directive.parse()?,
+            }
+        }
+
+        Ok(out)
+    }
+
+    /// Returns the maximum level of log entries of the given target.
+    fn level(&self, target: &str) -> log::LevelFilter {
+        self.targets
+            .iter()
+            .filter(|(prefix, _)| target.starts_with(&prefix[..]))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    /// Returns the highest level of all the targets.
+    fn max_level(&self) -> log::LevelFilter {
+        self.targets
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, |a, b| a.max(b))
+    }
+}
+
+fn set_log_filter(ptr: u32, len: u32) {
+    let filter: Box<[u8]> = {
+        let ptr = usize::try_from(ptr).unwrap();
+        let len = usize::try_from(len).unwrap();
+        unsafe { Box::from_raw(slice::from_raw_parts_mut(ptr as *mut u8, len)) }
+    };
+
+    // As mentioned in the documentation, the bytes *must* be valid UTF-8.
+    let filter = str::from_utf8(&filter).unwrap();
+
+    match LogFilter::parse(filter) {
+        Ok(parsed) => {
+            log::set_max_level(parsed.max_level());
+            *LOG_FILTER.lock().unwrap() = parsed;
+        }
+        Err(err) => {
+            log::warn!("Ignoring invalid log filter {:?}: {}", filter, err);
+        }
+    }
 }
 
 fn json_rpc_send(ptr: u32, len: u32, chain_id: u32) {
@@ -560,6 +686,125 @@ pub(crate) fn emit_json_rpc_response(rpc: &str, chain_id: super::ChainId) {
     }
 }
 
+fn metrics_request() {
+    let metrics = {
+        let client_lock = CLIENT.lock().unwrap();
+        client_lock.as_ref().unwrap().metrics()
+    };
+
+    spawn_background_task(async move {
+        let metrics = metrics.await.to_prometheus();
+        unsafe {
+            bindings::metrics_ready(
+                u32::try_from(metrics.as_bytes().as_ptr() as usize).unwrap(),
+                u32::try_from(metrics.as_bytes().len()).unwrap(),
+            );
+        }
+    });
+}
+
+fn chain_memory_usage_request(chain_id: u32) {
+    let memory_usage = {
+        let client_lock = CLIENT.lock().unwrap();
+        client_lock
+            .as_ref()
+            .unwrap()
+            .chain_memory_usage(super::ChainId::from(chain_id))
+    };
+
+    spawn_background_task(async move {
+        let memory_usage = memory_usage.await;
+        unsafe {
+            bindings::chain_memory_usage_ready(
+                chain_id,
+                memory_usage.runtimes as f64,
+                memory_usage.blocks as f64,
+                memory_usage.caches as f64,
+                memory_usage.network as f64,
+            );
+        }
+    });
+}
+
+fn chain_offchain_storage_load(
+    chain_id: u32,
+    kind: u32,
+    key_ptr: u32,
+    key_len: u32,
+    value_ptr: u32,
+    value_len: u32,
+) {
+    let key: Box<[u8]> = {
+        let key_ptr = usize::try_from(key_ptr).unwrap();
+        let key_len = usize::try_from(key_len).unwrap();
+        unsafe { Box::from_raw(slice::from_raw_parts_mut(key_ptr as *mut u8, key_len)) }
+    };
+
+    let value: Box<[u8]> = {
+        let value_ptr = usize::try_from(value_ptr).unwrap();
+        let value_len = usize::try_from(value_len).unwrap();
+        unsafe { Box::from_raw(slice::from_raw_parts_mut(value_ptr as *mut u8, value_len)) }
+    };
+
+    let kind = match kind {
+        1 => OffchainStorageKind::Persistent,
+        2 => OffchainStorageKind::Local,
+        _ => panic!("invalid offchain storage kind"),
+    };
+
+    let mut client_lock = CLIENT.lock().unwrap();
+    client_lock.as_mut().unwrap().offchain_storage_load(
+        super::ChainId::from(chain_id),
+        kind,
+        key.into(),
+        value.into(),
+    );
+}
+
+/// Emit a modification of the offchain storage of the given chain in destination to the
+/// JavaScript side. A `value` of `None` indicates that the entry has been removed.
+pub(crate) fn emit_offchain_storage_change(
+    chain_id: super::ChainId,
+    kind: OffchainStorageKind,
+    key: &[u8],
+    value: Option<&[u8]>,
+) {
+    let kind = match kind {
+        OffchainStorageKind::Persistent => 1,
+        OffchainStorageKind::Local => 2,
+    };
+
+    let (value_ptr, value_len) = value.map_or((0, 0), |value| {
+        (
+            u32::try_from(value.as_ptr() as usize).unwrap(),
+            u32::try_from(value.len()).unwrap(),
+        )
+    });
+
+    unsafe {
+        bindings::offchain_storage_changed(
+            u32::from(chain_id),
+            kind,
+            u32::try_from(key.as_ptr() as usize).unwrap(),
+            u32::try_from(key.len()).unwrap(),
+            value_ptr,
+            value_len,
+            if value.is_some() { 1 } else { 0 },
+        );
+    }
+}
+
+/// Emit the content of the database of the given chain in destination to the JavaScript side.
+pub(crate) fn emit_database_content(content: &str, chain_id: super::ChainId) {
+    unsafe {
+        bindings::database_content_ready(
+            u32::try_from(content.as_bytes().as_ptr() as usize).unwrap(),
+            u32::try_from(content.as_bytes().len()).unwrap(),
+            u32::from(chain_id),
+        );
+    }
+}
+
 fn timer_finished(timer_id: u32) {
     let callback = {
         let ptr = timer_id as *mut Box<dyn FnOnce()>;
@@ -586,18 +831,15 @@ fn connection_message(id: u32, ptr: u32, len: u32) {
     let message: Box<[u8]> =
         unsafe { Box::from_raw(slice::from_raw_parts_mut(ptr as *mut u8, len)) };
 
-    // Ignore empty message to avoid all sorts of problems.
+    // Ignore empty message to avoid waking up the connection task for nothing.
     if message.is_empty() {
         return;
     }
 
-    if connection.messages_queue.is_empty() {
-        connection.messages_queue_first_offset = 0;
-    }
-
     // TODO: add some limit to `messages_queue`, to avoid DoS attacks?
 
-    connection.messages_queue.push_back(message);
+    // Converting the message into a `Vec` doesn't copy its content.
+    connection.messages_queue.push(message.into_vec());
 
     if let Some(waker) = connection.waker.take() {
         waker.wake();
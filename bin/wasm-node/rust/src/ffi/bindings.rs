@@ -91,6 +91,63 @@ extern "C" {
     /// that the request was made to.
     pub fn json_rpc_respond(ptr: u32, len: u32, chain_id: u32);
 
+    /// Client is emitting the content of the database of the given chain.
+    ///
+    /// The database content is a UTF-8 string found in the memory of the WebAssembly virtual
+    /// machine at offset `ptr` and with length `len`. It contains the state of the finalized
+    /// chain, and should be stored somewhere by the host, then passed back to [`add_chain`] the
+    /// next time the same chain is added, in order to resume syncing from this state.
+    ///
+    /// This function is called periodically, every time the state of the finalized chain has
+    /// changed. Each call overrides the database content previously emitted for this chain.
+    pub fn database_content_ready(ptr: u32, len: u32, chain_id: u32);
+
+    /// Client is emitting a modification of the offchain storage of the given chain.
+    ///
+    /// `kind` is 1 for the persistent storage, or 2 for the local storage. The key is found in
+    /// the memory of the WebAssembly virtual machine at offset `key_ptr` and with length
+    /// `key_len`. If `value_present` is non-zero, the new value is found at offset `value_ptr`
+    /// and with length `value_len`. If `value_present` is zero, the entry has been removed.
+    ///
+    /// The host should store these entries somewhere, then pass them back to
+    /// [`chain_offchain_storage_load`] the next time the same chain is added.
+    pub fn offchain_storage_changed(
+        chain_id: u32,
+        kind: u32,
+        key_ptr: u32,
+        key_len: u32,
+        value_ptr: u32,
+        value_len: u32,
+        value_present: u32,
+    );
+
+    /// Client is emitting a snapshot of its internal metrics, in response to a call to
+    /// [`metrics_request`].
+    ///
+    /// The metrics are a UTF-8 string in the text format of Prometheus, found in the memory of
+    /// the WebAssembly virtual machine at offset `ptr` and with length `len`.
+    pub fn metrics_ready(ptr: u32, len: u32);
+
+    /// Client has finished shutting down, in response to a call to [`client_shutdown`]. All the
+    /// connections have been closed, and the client no longer performs any background work.
+    ///
+    /// It is now safe to destroy the WebAssembly virtual machine.
+    pub fn shutdown_finished();
+
+    /// Client is emitting an estimate of the memory used by a chain, in response to a call to
+    /// [`chain_memory_usage_request`].
+    ///
+    /// The values are numbers of bytes used by respectively the runtimes, the headers of the
+    /// blocks, the caches, and the buffers of the network connections of the services of the
+    /// chain. See [`chain_memory_usage_request`] for more information.
+    pub fn chain_memory_usage_ready(
+        chain_id: u32,
+        runtimes: f64,
+        blocks: f64,
+        caches: f64,
+        network: f64,
+    );
+
     /// Client is emitting a log entry.
     ///
     /// Each log entry is made of a log level (1 = Error, 2 = Warn, 3 = Info, 4 = Debug,
@@ -206,9 +263,38 @@ extern "C" {
 ///
 /// The client will emit log messages by calling the [`log()`] function, provided the log level is
 /// inferior or equal to the value of `max_log_level` passed here.
+///
+/// `cpu_rate_limit` is the maximum fraction of the CPU time that the client is allowed to
+/// consume, where `u32::max_value()` means 100% and `u32::max_value() / 2` means 50%. When this
+/// limit is reached, the client sleeps in order to leave CPU time to the host. Note that the
+/// time spent in the host functions called by the client is counted as well.
+///
+/// `timers_granularity_ms` is a number of milliseconds that the moment when the timers of the
+/// client finish is rounded up to. Timers that finish around the same time are then all processed
+/// together, which reduces the number of calls to [`start_timer`], at the cost of timers finishing
+/// up to this number of milliseconds late. A value of 0 disables this rounding.
+#[no_mangle]
+pub extern "C" fn init(max_log_level: u32, cpu_rate_limit: u32, timers_granularity_ms: u32) {
+    super::init(max_log_level, cpu_rate_limit, timers_granularity_ms)
+}
+
+/// Changes the maximum level of the log entries that the client emits, overriding the
+/// `max_log_level` passed to [`init`].
+///
+/// A buffer containing a UTF-8 filter must be passed as parameter. The filter is a
+/// comma-separated list of directives, each of the form `target=level` or `level`, for example
+/// `info,runtime-polkadot=debug,network=warn`. Log targets are matched by prefix, and the
+/// longest matching prefix applies. A directive without a target sets the level of the targets
+/// that don't match any other directive, which defaults to `info`. The levels are `off`,
+/// `error`, `warn`, `info`, `debug`, and `trace`.
+///
+/// If the filter is invalid, a warning is logged and the current filter is kept.
+///
+/// The buffer **must** have been allocated with [`alloc`]. It is freed when this function is
+/// called.
 #[no_mangle]
-pub extern "C" fn init(max_log_level: u32) {
-    super::init(max_log_level)
+pub extern "C" fn set_log_filter(ptr: u32, len: u32) {
+    super::set_log_filter(ptr, len)
 }
 
 /// Allocates a buffer of the given length, with an alignment of 1.
@@ -234,12 +320,31 @@ pub extern "C" fn alloc(len: u32) -> u32 {
 /// function. If the chain specification refer to a parachain, these chain ids are the ones that
 /// will be looked up to find the corresponding relay chain.
 ///
-/// These two buffers **must** have been allocated with [`alloc`]. They are freed when this
+/// Also use [`alloc`] to allocate a buffer containing the ed25519 private key used as the
+/// identity of the client on the peer-to-peer network. This buffer must be either 32 bytes long,
+/// or empty in which case a random identity is generated.
+///
+/// Also use [`alloc`] to allocate a buffer containing the database content of the chain, as
+/// previously emitted by [`database_content_ready`]. This buffer can be empty if no database
+/// content is available. Database content that is corrupted or that is older than the state
+/// found in the chain specification is ignored.
+///
+/// These four buffers **must** have been allocated with [`alloc`]. They are freed when this
 /// function is called, even if an error code is returned.
 ///
 /// If `json_rpc_running` is 0, then no JSON-RPC service will be started and all JSON-RPC requests
 /// targeting this chain will return an error. This can be used to save up resources.
 ///
+/// If `json_rpc_max_parallel_expensive_requests` is non-zero, then at most this number of
+/// expensive JSON-RPC requests, such as runtime calls or storage queries, are processed
+/// simultaneously for this chain. The other expensive requests are queued.
+///
+/// `offchain_storage_max_bytes` is the maximum total size, in bytes, of the keys and values of
+/// the offchain storage of this chain. Modifications that would exceed this limit are refused.
+///
+/// If `reserved_only` is non-zero, then the client only ever connects to the bootnodes found in
+/// the chain specification and refuses all other nodes.
+///
 /// If an error happens during the creation of the chain, a chain id will be allocated
 /// nonetheless, and must later be de-allocated by calling [`remove_chain`]. This allocated chain,
 /// however, will be in an erroneous state. Use [`chain_is_ok`] to determine whether this function
@@ -249,14 +354,28 @@ pub extern "C" fn alloc(len: u32) -> u32 {
 pub extern "C" fn add_chain(
     chain_spec_pointer: u32,
     chain_spec_len: u32,
+    database_content_ptr: u32,
+    database_content_len: u32,
     json_rpc_running: u32,
+    json_rpc_max_parallel_expensive_requests: u32,
+    offchain_storage_max_bytes: u32,
+    reserved_only: u32,
+    network_identity_key_ptr: u32,
+    network_identity_key_len: u32,
     potential_relay_chains_ptr: u32,
     potential_relay_chains_len: u32,
 ) -> u32 {
     super::add_chain(
         chain_spec_pointer,
         chain_spec_len,
+        database_content_ptr,
+        database_content_len,
         json_rpc_running,
+        json_rpc_max_parallel_expensive_requests,
+        offchain_storage_max_bytes,
+        reserved_only,
+        network_identity_key_ptr,
+        network_identity_key_len,
         potential_relay_chains_ptr,
         potential_relay_chains_len,
     )
@@ -317,6 +436,70 @@ pub extern "C" fn json_rpc_send(text_ptr: u32, text_len: u32, chain_id: u32) {
     super::json_rpc_send(text_ptr, text_len, chain_id)
 }
 
+/// Shuts down the client gracefully.
+///
+/// All the JSON-RPC services are instantly stopped, then [`database_content_ready`] is called
+/// one last time for each chain. Afterwards, the services of the chains are stopped and all the
+/// connections are closed. [`shutdown_finished`] is called once all the background work of the
+/// client has terminated.
+///
+/// It is forbidden to call any other function of the client after this function has been called.
+///
+/// > **Note**: This function isn't named `shutdown`, as it would otherwise collide with the
+/// >           `shutdown` function of the C library when compiling for a native target.
+#[no_mangle]
+pub extern "C" fn client_shutdown() {
+    super::shutdown()
+}
+
+/// Requests a snapshot of the internal metrics of the client, such as the number of peers or the
+/// memory usage.
+///
+/// The snapshot is later emitted using [`metrics_ready`]. If this function is called multiple
+/// times, [`metrics_ready`] is called once per call, but not necessarily in the same order.
+#[no_mangle]
+pub extern "C" fn metrics_request() {
+    super::metrics_request()
+}
+
+/// Requests an estimate of the memory used by the services of the given chain.
+///
+/// The services of a chain are shared with all the other chains that have the same
+/// specification, and the services of the relay chain of a parachain aren't included. In other
+/// words, the estimate corresponds to the memory that would be freed by removing the chain and
+/// all the other chains with the same specification.
+///
+/// The estimate is later emitted using [`chain_memory_usage_ready`]. All the values are zero if
+/// the chain is still initializing.
+///
+/// It is forbidden to call this function on an erroneous or removed chain.
+#[no_mangle]
+pub extern "C" fn chain_memory_usage_request(chain_id: u32) {
+    super::chain_memory_usage_request(chain_id)
+}
+
+/// Inserts an entry in the offchain storage of the given chain, as previously reported through
+/// [`offchain_storage_changed`]. This should be called right after [`add_chain`], before any
+/// JSON-RPC request is sent to this chain.
+///
+/// `kind` is 1 for the persistent storage, or 2 for the local storage. Use [`alloc`] to allocate
+/// a buffer for the key and a buffer for the value. These two buffers **must** have been
+/// allocated with [`alloc`]. They are freed when this function is called.
+///
+/// Entries that would make the offchain storage exceed the limit passed to [`add_chain`] are
+/// ignored. Does nothing if the chain is erroneous.
+#[no_mangle]
+pub extern "C" fn chain_offchain_storage_load(
+    chain_id: u32,
+    kind: u32,
+    key_ptr: u32,
+    key_len: u32,
+    value_ptr: u32,
+    value_len: u32,
+) {
+    super::chain_offchain_storage_load(chain_id, kind, key_ptr, key_len, value_ptr, value_len)
+}
+
 /// Must be called in response to [`start_timer`] after the given duration has passed.
 #[no_mangle]
 pub extern "C" fn timer_finished(timer_id: u32) {
@@ -21,9 +21,15 @@
 //! In order to optimize performances, we avoid invoking the ffi once per timer. Instead, the ffi
 //! is only used in order to wake up when the earliest timer finishes, then restarted for the next
 //! timer.
+//!
+//! Additionally, the moment when timers finish can be rounded up to a multiple of a certain
+//! granularity (see [`set_granularity`]), so that timers that finish around the same time are
+//! all processed during the same wake up. This reduces the number of wake ups, which matters in
+//! environments such as mobile browsers, at the cost of timers finishing slightly late.
 
 use core::{
     cmp::{Eq, Ord, Ordering, PartialEq, PartialOrd},
+    convert::TryFrom as _,
     future::Future,
     pin::Pin,
     task::{Context, Poll, Waker},
@@ -66,16 +72,21 @@ impl Delay {
             waker: None,
         });
 
-        let when_from_time_zero = when - lock.time_zero;
+        let when_from_time_zero = round_up(when - lock.time_zero, lock.granularity);
         lock.timers_queue.push(QueuedTimer {
             when_from_time_zero,
             timer_id,
         });
 
-        // If this was the first timer being inserted, then actually start the callback that
-        // will process timers.
-        if lock.timers_queue.len() == 1 {
-            super::start_timer_wrap(when - now, process_timers);
+        // Start the callback that will process timers, unless it is already going to be called
+        // early enough.
+        if lock
+            .next_wakeup
+            .map_or(true, |next_wakeup| when_from_time_zero < next_wakeup)
+        {
+            lock.next_wakeup = Some(when_from_time_zero);
+            let duration = when_from_time_zero.saturating_sub(now - lock.time_zero);
+            super::start_timer_wrap(duration, move || process_timers(when_from_time_zero));
         }
 
         Delay {
@@ -140,9 +151,20 @@ lazy_static::lazy_static! {
         timers_queue: BinaryHeap::new(),
         timers: slab::Slab::new(),
         time_zero: Instant::now(),
+        granularity: Duration::new(0, 0),
+        next_wakeup: None,
     });
 }
 
+/// Sets the granularity of the timers. The moment when each timer finishes is rounded up to a
+/// multiple of this value. A value of zero disables any rounding.
+///
+/// Only applies to the timers created after this function has been called.
+pub fn set_granularity(granularity: Duration) {
+    // Because we're in a single-threaded environment, `try_lock()` should always succeed.
+    TIMERS.try_lock().unwrap().granularity = granularity;
+}
+
 struct Timers {
     /// Same entries as `timer`, but ordered based on when they're finished. Items are only ever
     /// removed from [`process_timers`], even if the corresponding [`Delay`] is destroyed.
@@ -154,6 +176,13 @@ struct Timers {
     /// Arbitrary point in time set at initialization and that never changes. All moments in time
     /// are represented by `Duration`s relative to this value.
     time_zero: Instant,
+
+    /// See [`set_granularity`].
+    granularity: Duration,
+
+    /// Moment, relative to `time_zero`, when [`process_timers`] is going to be called through the
+    /// ffi, or `None` if it isn't going to be called.
+    next_wakeup: Option<Duration>,
 }
 
 struct Timer {
@@ -198,11 +227,19 @@ impl Ord for QueuedTimer {
 }
 
 /// Marks as ready all the timers in `TIMERS` that are finished.
-fn process_timers() {
+///
+/// `scheduled_for` is the moment, relative to `time_zero`, for which this call has been
+/// scheduled. Because an earlier call can be scheduled when a timer is inserted, it is possible
+/// for this function to be called at moments that no longer match [`Timers::next_wakeup`].
+fn process_timers(scheduled_for: Duration) {
     // Because we're in a single-threaded environment, `try_lock()` should always succeed.
     let mut lock = TIMERS.try_lock().unwrap();
     let now = Instant::now();
 
+    if lock.next_wakeup == Some(scheduled_for) {
+        lock.next_wakeup = None;
+    }
+
     // TODO: this assertion fails; figure out why; this shouldn't have any major consequence but still intriguing
     //debug_assert!(lock.time_zero + lock.timers_queue.peek().unwrap().when_from_time_zero <= now);
 
@@ -241,10 +278,32 @@ fn process_timers() {
     };
 
     if let Some(next_wakeup) = next_wakeup {
-        super::start_timer_wrap(lock.time_zero + next_wakeup - now, process_timers);
+        // Another call might already be scheduled early enough.
+        if lock
+            .next_wakeup
+            .map_or(true, |scheduled| next_wakeup < scheduled)
+        {
+            lock.next_wakeup = Some(next_wakeup);
+            let duration = next_wakeup.saturating_sub(now - lock.time_zero);
+            super::start_timer_wrap(duration, move || process_timers(next_wakeup));
+        }
     } else {
         // Clean up memory a bit. Hopefully this doesn't impact performances too much.
         lock.timers_queue.shrink_to_fit();
         lock.timers.shrink_to_fit();
     }
 }
+
+/// Rounds up `value` to a multiple of `granularity`. Returns `value` if `granularity` is zero.
+fn round_up(value: Duration, granularity: Duration) -> Duration {
+    let granularity = granularity.as_nanos();
+    if granularity == 0 {
+        return value;
+    }
+
+    let rounded = (value.as_nanos() + granularity - 1) / granularity * granularity;
+    Duration::new(
+        u64::try_from(rounded / 1_000_000_000).unwrap(),
+        u32::try_from(rounded % 1_000_000_000).unwrap(),
+    )
+}
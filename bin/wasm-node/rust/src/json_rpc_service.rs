@@ -37,7 +37,9 @@
 // TODO: doc
 // TODO: re-review this once finished
 
-use crate::{ffi, runtime_service, sync_service, transactions_service};
+use crate::{
+    ffi, network_service, offchain_storage, runtime_service, sync_service, transactions_service,
+};
 
 use futures::{
     channel::{mpsc, oneshot},
@@ -48,13 +50,13 @@ use futures::{
 use smoldot::{
     chain_spec,
     executor::{host, read_only_runtime_host},
-    header,
+    finality, header,
     json_rpc::{self, methods},
     libp2p::PeerId,
     network::protocol,
 };
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     convert::TryFrom as _,
     iter,
     num::NonZeroU32,
@@ -72,18 +74,28 @@ pub struct Config<'a> {
     /// >           have been filtered out from this name.
     pub log_name: String,
 
-    /// Closure that spawns background tasks.
-    pub tasks_executor: Box<dyn FnMut(String, Pin<Box<dyn Future<Output = ()> + Send>>) + Send>,
+    /// Closure that spawns background tasks. The [`crate::TaskPriority`] is a hint indicating
+    /// how the task should be scheduled.
+    pub tasks_executor: Box<
+        dyn FnMut(String, crate::TaskPriority, Pin<Box<dyn Future<Output = ()> + Send>>) + Send,
+    >,
 
     /// Service responsible for synchronizing the chain.
     pub sync_service: Arc<sync_service::SyncService>,
 
+    /// Networking service. Used to report information about the connected peers.
+    pub network_service: Arc<network_service::NetworkService>,
+
     /// Service responsible for emitting transactions and tracking their state.
     pub transactions_service: Arc<transactions_service::TransactionsService>,
 
     /// Service that provides a ready-to-be-called runtime for the current best block.
     pub runtime_service: Arc<runtime_service::RuntimeService>,
 
+    /// Offchain storage read and modified by the `offchain_localStorageGet` and
+    /// `offchain_localStorageSet` JSON-RPC functions.
+    pub offchain_storage: Arc<std::sync::Mutex<offchain_storage::OffchainStorage>>,
+
     /// Specification of the chain.
     pub chain_spec: &'a chain_spec::ChainSpec,
 
@@ -113,6 +125,14 @@ pub struct Config<'a> {
     /// the client.
     pub max_parallel_requests: NonZeroU32,
 
+    /// Maximum number of expensive JSON-RPC requests, such as runtime calls or storage queries,
+    /// that can be processed simultaneously. Additional expensive requests wait until one of
+    /// the requests being processed has finished.
+    ///
+    /// Since waiting requests count towards [`Config::max_parallel_requests`], this value should
+    /// be inferior to it in order to have an effect.
+    pub max_parallel_expensive_requests: NonZeroU32,
+
     /// Maximum number of JSON-RPC requests that can be added to a queue if it is not ready to be
     /// processed immediately. Any additional request will be immediately rejected.
     ///
@@ -158,11 +178,19 @@ impl JsonRpcService {
         // Channel used in the background in order to spawn new tasks scoped to the background.
         let (new_child_tasks_tx, mut new_child_tasks_rx) = mpsc::unbounded();
 
+        // Channel containing one item per expensive request that is allowed to start.
+        let (expensive_requests_permits_tx, expensive_requests_permits_rx) = mpsc::unbounded();
+        for _ in 0..config.max_parallel_expensive_requests.get() {
+            expensive_requests_permits_tx.unbounded_send(()).unwrap();
+        }
+
         let background = Arc::new(Background {
             log_target: format!("json-rpc-{}", config.log_name),
             new_requests_rx: Mutex::new(new_requests_rx),
             responses_sender: Mutex::new(responses_sender),
             new_child_tasks_tx: Mutex::new(new_child_tasks_tx),
+            expensive_requests_permits_tx,
+            expensive_requests_permits_rx: Mutex::new(expensive_requests_permits_rx),
             max_subscriptions: usize::try_from(config.max_subscriptions)
                 .unwrap_or(usize::max_value()),
             chain_name: config.chain_spec.name().to_owned(),
@@ -171,8 +199,10 @@ impl JsonRpcService {
             chain_properties_json: config.chain_spec.properties().to_owned(),
             peer_id_base58: config.peer_id.to_base58(),
             sync_service: config.sync_service,
+            network_service: config.network_service,
             runtime_service: config.runtime_service,
             transactions_service: config.transactions_service,
+            offchain_storage: config.offchain_storage,
             blocks: Mutex::new(Blocks {
                 known_blocks: lru::LruCache::new(256),
                 best_block: [0; 32],      // Filled below.
@@ -190,6 +220,7 @@ impl JsonRpcService {
         let max_parallel_requests = config.max_parallel_requests;
         (config.tasks_executor)(
             "json-rpc-service".into(),
+            crate::TaskPriority::Latency,
             async move {
                 // TODO: use subscribe_all?
                 let (finalized_block_header, mut finalized_blocks_subscription) =
@@ -397,6 +428,13 @@ struct Background {
     /// Whenever a task is sent on this channel, an executor runs it to completion.
     new_child_tasks_tx: Mutex<mpsc::UnboundedSender<future::BoxFuture<'static, ()>>>,
 
+    /// Contains one item per expensive request that is allowed to start. Expensive requests
+    /// take an item before being processed, and send it back after they have been processed.
+    /// See [`Config::max_parallel_expensive_requests`].
+    expensive_requests_permits_tx: mpsc::UnboundedSender<()>,
+    /// See [`Background::expensive_requests_permits_tx`].
+    expensive_requests_permits_rx: Mutex<mpsc::UnboundedReceiver<()>>,
+
     /// See [`Config::max_subscriptions`].
     max_subscriptions: usize,
 
@@ -414,10 +452,14 @@ struct Background {
 
     /// See [`Config::sync_service`].
     sync_service: Arc<sync_service::SyncService>,
+    /// See [`Config::network_service`].
+    network_service: Arc<network_service::NetworkService>,
     /// See [`Config::runtime_service`].
     runtime_service: Arc<runtime_service::RuntimeService>,
     /// See [`Config::transactions_service`].
     transactions_service: Arc<transactions_service::TransactionsService>,
+    /// See [`Config::offchain_storage`].
+    offchain_storage: Arc<std::sync::Mutex<offchain_storage::OffchainStorage>>,
 
     /// Blocks that are temporarily saved in order to serve JSON-RPC requests.
     // TODO: move somewhere else?
@@ -436,6 +478,41 @@ struct Background {
         Mutex<HashMap<(String, SubscriptionTy), oneshot::Sender<String>, fnv::FnvBuildHasher>>,
 }
 
+/// Returns `true` if processing the given call performs runtime calls or network queries, and
+/// must count towards [`Config::max_parallel_expensive_requests`].
+fn is_expensive_request(call: &methods::MethodCall) -> bool {
+    matches!(
+        call,
+        methods::MethodCall::chain_getBlock { .. }
+            | methods::MethodCall::payment_queryInfo { .. }
+            | methods::MethodCall::state_getKeysPaged { .. }
+            | methods::MethodCall::state_getMetadata { .. }
+            | methods::MethodCall::state_getRuntimeVersion { .. }
+            | methods::MethodCall::state_getStorage { .. }
+            | methods::MethodCall::state_queryStorageAt { .. }
+            | methods::MethodCall::system_accountNextIndex { .. }
+    )
+}
+
+/// Converts a kind of offchain storage as found in JSON-RPC requests into its equivalent in the
+/// offchain host functions.
+fn offchain_storage_kind(kind: methods::StorageKind) -> host::OffchainStorageKind {
+    match kind {
+        methods::StorageKind::Persistent => host::OffchainStorageKind::Persistent,
+        methods::StorageKind::Local => host::OffchainStorageKind::Local,
+    }
+}
+
+/// Gives back the permit to process an expensive request when destroyed.
+struct ExpensiveRequestPermit<'a>(&'a mpsc::UnboundedSender<()>);
+
+impl<'a> Drop for ExpensiveRequestPermit<'a> {
+    fn drop(&mut self) {
+        // The receiver is owned by the same `Background` as the sender, and can't be closed.
+        self.0.unbounded_send(()).unwrap();
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 enum SubscriptionTy {
     AllHeads,
@@ -486,6 +563,19 @@ impl Background {
             }
         };
 
+        // Requests that perform runtime calls or storage queries are expensive. They wait until
+        // the number of expensive requests being processed is below the limit. The permit is
+        // given back when `_expensive_request_permit` is dropped at the end of this function.
+        let _expensive_request_permit = if is_expensive_request(&call) {
+            // It is important for `expensive_requests_permits_rx` to be unlocked before
+            // processing the request.
+            let permit = self.expensive_requests_permits_rx.lock().await.next().await;
+            debug_assert!(permit.is_some());
+            Some(ExpensiveRequestPermit(&self.expensive_requests_permits_tx))
+        } else {
+            None
+        };
+
         // Most calls are handled directly in this method's body. The most voluminous (in terms
         // of lines of code) have their dedicated methods.
         match call {
@@ -732,6 +822,28 @@ impl Background {
                         .await;
                 }
             }
+            methods::MethodCall::grandpa_proveFinality { block_number } => {
+                // Only the proof of finality of the latest finalized block is kept in memory.
+                // Commit messages can't be turned into a finality proof, as they lack the
+                // ancestry of the votes.
+                let proof = match self.sync_service.finality_proof().await {
+                    Some((
+                        number,
+                        hash,
+                        sync_service::FinalityProof::GrandpaJustification(justification),
+                    )) if number == block_number => Some(methods::HexString(
+                        finality::justification::encode_grandpa_finality_proof(
+                            &hash,
+                            &justification,
+                        ),
+                    )),
+                    _ => None,
+                };
+
+                let response =
+                    methods::Response::grandpa_proveFinality(proof).to_json_response(request_id);
+                let _ = self.responses_sender.lock().await.send(response).await;
+            }
             methods::MethodCall::payment_queryInfo { extrinsic, hash } => {
                 assert!(hash.is_none()); // TODO: handle when hash != None
 
@@ -748,6 +860,41 @@ impl Background {
 
                 let _ = self.responses_sender.lock().await.send(response).await;
             }
+            methods::MethodCall::offchain_localStorageGet { kind, key } => {
+                let value = self
+                    .offchain_storage
+                    .lock()
+                    .unwrap()
+                    .get(offchain_storage_kind(kind), &key.0)
+                    .map(|value| methods::HexString(value.to_vec()));
+                let _ = self
+                    .responses_sender
+                    .lock()
+                    .await
+                    .send(
+                        methods::Response::offchain_localStorageGet(value)
+                            .to_json_response(request_id),
+                    )
+                    .await;
+            }
+            methods::MethodCall::offchain_localStorageSet { kind, key, value } => {
+                let result = self.offchain_storage.lock().unwrap().set(
+                    offchain_storage_kind(kind),
+                    &key.0,
+                    Some(&value.0),
+                );
+                let response = match result {
+                    Ok(()) => {
+                        methods::Response::offchain_localStorageSet(()).to_json_response(request_id)
+                    }
+                    Err(error) => json_rpc::parse::build_error_response(
+                        request_id,
+                        json_rpc::parse::ErrorResponse::ServerError(-32000, &error.to_string()),
+                        None,
+                    ),
+                };
+                let _ = self.responses_sender.lock().await.send(response).await;
+            }
             methods::MethodCall::rpc_methods {} => {
                 let _ = self
                     .responses_sender
@@ -1188,7 +1335,10 @@ impl Background {
                     // Additionally, using the `runtime_service` instead of the `sync_service`
                     // means that, when it comes to parachains, `isSyncing` will be `true` for as
                     // long as we haven't found any peer.
-                    is_syncing: !self.runtime_service.is_near_head_of_chain_heuristic().await,
+                    is_syncing: !matches!(
+                        self.runtime_service.status().await,
+                        sync_service::SyncStatus::NearHead
+                    ),
                     peers: u64::try_from(self.sync_service.syncing_peers().await.len())
                         .unwrap_or(u64::max_value()),
                     should_have_peers: self.chain_is_live,
@@ -1231,6 +1381,41 @@ impl Background {
                     )
                     .await;
             }
+            methods::MethodCall::system_networkState {} => {
+                let mut connected_peers = self
+                    .network_service
+                    .peers_list()
+                    .await
+                    .map(|peer_id| {
+                        let peer = methods::NetworkStatePeer {
+                            version_string: None,
+                            known_addresses: Vec::new(),
+                            protocols: Vec::new(),
+                        };
+                        (peer_id.to_base58(), peer)
+                    })
+                    .collect::<BTreeMap<_, _>>();
+
+                for (peer_id, identify) in self.network_service.identified_peers().await {
+                    if let Some(peer) = connected_peers.get_mut(&peer_id.to_base58()) {
+                        peer.version_string = Some(identify.agent_version);
+                        peer.known_addresses = identify
+                            .listen_addrs
+                            .iter()
+                            .map(|addr| addr.to_string())
+                            .collect();
+                        peer.protocols = identify.protocols;
+                    }
+                }
+
+                let response = methods::Response::system_networkState(methods::NetworkState {
+                    peer_id: self.peer_id_base58.clone(),
+                    connected_peers,
+                })
+                .to_json_response(request_id);
+
+                let _ = self.responses_sender.lock().await.send(response).await;
+            }
             methods::MethodCall::system_peers {} => {
                 let response = methods::Response::system_peers(
                     self.sync_service
@@ -1361,6 +1546,12 @@ impl Background {
                                 transactions_service::TransactionStatus::Dropped => {
                                     methods::TransactionStatus::Dropped
                                 }
+                                transactions_service::TransactionStatus::Invalid(_) => {
+                                    methods::TransactionStatus::Invalid
+                                }
+                                transactions_service::TransactionStatus::Usurped(hash) => {
+                                    methods::TransactionStatus::Usurped(hash)
+                                }
                                 transactions_service::TransactionStatus::Finalized(block) => {
                                     methods::TransactionStatus::Finalized(block)
                                 }
@@ -1474,10 +1665,16 @@ impl Background {
             let subscribe_all = self.runtime_service.subscribe_all(16).await;
             // TODO: is it correct to return all non-finalized blocks first? have to compare with PolkadotJS
             stream::iter(subscribe_all.non_finalized_blocks_ancestry_order)
-                .chain(subscribe_all.new_blocks.filter_map(|notif| {
-                    future::ready(match notif {
-                        sync_service::Notification::Block(b) => Some(b),
-                        _ => None,
+                .chain(subscribe_all.new_blocks.flat_map(|notif| {
+                    stream::iter(match notif {
+                        sync_service::Notification::Block(b) => vec![b],
+                        // In case of a gap, the blocks of the new state of the chain are reported
+                        // as new heads.
+                        sync_service::Notification::GapDetected {
+                            non_finalized_blocks_ancestry_order,
+                            ..
+                        } => non_finalized_blocks_ancestry_order,
+                        sync_service::Notification::Finalized { .. } => Vec::new(),
                     })
                 }))
                 .map(|notif| notif.scale_encoded_header)
@@ -1946,7 +2143,10 @@ async fn account_nonce(
                 break Err(AnnounceNonceError::ReadOnlyRuntime(error.detail));
             }
             read_only_runtime_host::RuntimeHostVm::StorageGet(get) => {
-                let storage_value = match runtime_call_lock.storage_entry(&get.key_as_vec()) {
+                let child_trie = get.child_trie().map(|c| c.as_ref().to_vec());
+                let storage_value = match runtime_call_lock
+                    .storage_entry(child_trie.as_deref(), &get.key_as_vec())
+                {
                     Ok(v) => v,
                     Err(err) => {
                         runtime_call_lock.unlock(
@@ -1957,8 +2157,20 @@ async fn account_nonce(
                 };
                 runtime_call = get.inject_value(storage_value.map(iter::once));
             }
-            read_only_runtime_host::RuntimeHostVm::NextKey(_) => {
-                todo!() // TODO:
+            read_only_runtime_host::RuntimeHostVm::NextKey(next_key) => {
+                let child_trie = next_key.child_trie().map(|c| c.as_ref().to_vec());
+                let key = next_key.key().as_ref().to_vec();
+                let key = match runtime_call_lock.storage_next_key(child_trie.as_deref(), &key) {
+                    Ok(v) => v,
+                    Err(err) => {
+                        runtime_call_lock.unlock(
+                            read_only_runtime_host::RuntimeHostVm::NextKey(next_key)
+                                .into_prototype(),
+                        );
+                        return Err(AnnounceNonceError::Call(err));
+                    }
+                };
+                runtime_call = next_key.inject_key(key);
             }
             read_only_runtime_host::RuntimeHostVm::StorageRoot(storage_root) => {
                 runtime_call = storage_root.resume(runtime_call_lock.block_storage_root());
@@ -2024,7 +2236,10 @@ async fn payment_query_info(
                 break Err(PaymentQueryInfoError::ReadOnlyRuntime(error.detail));
             }
             read_only_runtime_host::RuntimeHostVm::StorageGet(get) => {
-                let storage_value = match runtime_call_lock.storage_entry(&get.key_as_vec()) {
+                let child_trie = get.child_trie().map(|c| c.as_ref().to_vec());
+                let storage_value = match runtime_call_lock
+                    .storage_entry(child_trie.as_deref(), &get.key_as_vec())
+                {
                     Ok(v) => v,
                     Err(err) => {
                         runtime_call_lock.unlock(
@@ -2035,8 +2250,20 @@ async fn payment_query_info(
                 };
                 runtime_call = get.inject_value(storage_value.map(iter::once));
             }
-            read_only_runtime_host::RuntimeHostVm::NextKey(_) => {
-                todo!() // TODO:
+            read_only_runtime_host::RuntimeHostVm::NextKey(next_key) => {
+                let child_trie = next_key.child_trie().map(|c| c.as_ref().to_vec());
+                let key = next_key.key().as_ref().to_vec();
+                let key = match runtime_call_lock.storage_next_key(child_trie.as_deref(), &key) {
+                    Ok(v) => v,
+                    Err(err) => {
+                        runtime_call_lock.unlock(
+                            read_only_runtime_host::RuntimeHostVm::NextKey(next_key)
+                                .into_prototype(),
+                        );
+                        return Err(PaymentQueryInfoError::Call(err));
+                    }
+                };
+                runtime_call = next_key.inject_key(key);
             }
             read_only_runtime_host::RuntimeHostVm::StorageRoot(storage_root) => {
                 runtime_call = storage_root.resume(runtime_call_lock.block_storage_root());
@@ -22,10 +22,16 @@
 #![deny(rustdoc::broken_intra_doc_links)]
 #![deny(unused_crate_dependencies)]
 
-use futures::{channel::mpsc, prelude::*};
+use futures::{
+    channel::{mpsc, oneshot},
+    prelude::*,
+};
 use itertools::Itertools as _;
 use smoldot::{
     chain, chain_spec,
+    executor::host,
+    finality::grandpa::warp_sync_server,
+    header,
     informant::HashDisplay,
     json_rpc::{self, methods},
     libp2p::{connection, multiaddr, peer_id},
@@ -33,18 +39,23 @@ use smoldot::{
 use std::{
     collections::{hash_map::Entry, HashMap},
     convert::TryFrom as _,
+    mem,
     num::NonZeroU32,
     pin::Pin,
     str,
-    sync::Arc,
+    sync::{atomic, Arc},
     task,
+    time::Duration,
 };
 
 pub mod ffi;
 
+mod database;
 mod json_rpc_service;
 mod lossy_channel;
+mod metrics;
 mod network_service;
+mod offchain_storage;
 mod runtime_service;
 mod sync_service;
 mod transactions_service;
@@ -58,12 +69,33 @@ mod transactions_service;
 #[global_allocator]
 static ALLOC: std::alloc::System = std::alloc::System;
 
+/// Maximum distance, in number of blocks, between the best block and the point where a fork
+/// branches off from the best chain, before this fork is discarded.
+///
+/// Forks are normally resolved within a few blocks. This value is large enough to never discard
+/// a fork of a healthy chain.
+const MAX_NON_FINALIZED_FORK_DEPTH: u64 = 4096;
+
+/// Minimum number of distinct peers that must know about a block before it is verified and
+/// reported.
+///
+/// Cross-checking blocks with multiple peers is disabled by default, as it slows down the
+/// propagation of new blocks.
+const MIN_SOURCES_PER_BLOCK: u32 = 1;
+
 /// See [`Client::add_chain`].
 #[derive(Debug, Clone)]
 pub struct AddChainConfig<'a, TRelays> {
     /// JSON text containing the specification of the chain (the so-called "chain spec").
     pub specification: &'a str,
 
+    /// Content of the database of this chain, as previously emitted through the FFI layer.
+    /// Empty if no database content is available.
+    ///
+    /// If the database content is valid and more recent than the state found in the chain
+    /// specification, syncing starts from the finalized block found in the database.
+    pub database_content: &'a str,
+
     /// If [`AddChainConfig`] defines a parachain, contains the list of relay chains to choose
     /// from. Ignored if not a parachain.
     ///
@@ -80,6 +112,32 @@ pub struct AddChainConfig<'a, TRelays> {
     /// If `false`, then no JSON-RPC service is started for this chain. This saves up a lot of
     /// resources, but will cause all JSON-RPC requests targetting this chain to fail.
     pub json_rpc_running: bool,
+
+    /// Maximum number of expensive JSON-RPC requests, such as runtime calls or storage queries,
+    /// that the JSON-RPC service of this chain processes simultaneously. The other expensive
+    /// requests are queued. If `None`, only the limit on the total number of requests applies.
+    ///
+    /// Limiting this number prevents a single user of the client from monopolizing the
+    /// resources, such as the connections, shared with the other chains.
+    pub json_rpc_max_parallel_expensive_requests: Option<NonZeroU32>,
+
+    /// Maximum total size, in bytes, of the keys and values of the offchain storage of this
+    /// chain. Modifications that would exceed this limit are refused.
+    ///
+    /// Every modification of the offchain storage is reported through the FFI layer, so that it
+    /// can be passed back with [`Client::offchain_storage_load`] the next time the chain is added.
+    pub offchain_storage_max_bytes: usize,
+
+    /// If `true`, the client only ever connects to the bootnodes found in the chain
+    /// specification, and refuses all other nodes. No discovery of other nodes is performed.
+    pub reserved_only: bool,
+
+    /// Ed25519 private key used as the identity of the client on the peer-to-peer network of
+    /// this chain. If `None`, a random identity is generated.
+    ///
+    /// Passing the same key every time makes it possible for the nodes of the network to keep
+    /// recognizing the client after a restart.
+    pub network_identity_key: Option<[u8; 32]>,
 }
 
 /// Chain registered in a [`Client`].
@@ -103,9 +161,8 @@ impl From<ChainId> for u32 {
 }
 
 pub struct Client {
-    /// Tasks can be spawned by sending it on this channel. The first tuple element is the name
-    /// of the task used for debugging purposes.
-    new_task_tx: mpsc::UnboundedSender<(String, future::BoxFuture<'static, ()>)>,
+    /// Tasks can be spawned by sending it on this channel.
+    new_task_tx: TasksSender,
 
     /// List of chains currently running according to the public API. Indices in this container
     /// are reported through the public API. The values are keys found in
@@ -119,19 +176,33 @@ pub struct Client {
     ///
     /// The [`RunningChain`] is within a `MaybeDone`. The variant will be `MaybeDone::Future` if
     /// initialization is still in progress.
+    ///
+    /// The number of chains includes the parachains whose relay chain is this chain, as a relay
+    /// chain must keep running as long as its parachains are running.
+    ///
+    /// The `AbortHandle` aborts all the tasks of the services of the chain.
     chains_by_key: HashMap<
         ChainKey,
         (
             future::MaybeDone<future::Shared<future::RemoteHandle<RunningChain>>>,
             String,
             NonZeroU32,
+            future::AbortHandle,
         ),
     >,
+
+    /// Receives a message when the main task of the client has terminated, which happens after
+    /// all the other tasks have terminated and [`Client::new_task_tx`] has been dropped.
+    main_task_finished: oneshot::Receiver<()>,
 }
 
 impl Client {
     /// Initializes the smoldot Wasm client.
-    pub fn new(max_log_level: log::LevelFilter) -> Self {
+    ///
+    /// `cpu_rate_limit` is the maximum fraction of the CPU time that the tasks of the client are
+    /// allowed to consume, where `u32::max_value()` means 100%. When this limit is reached, the
+    /// client sleeps in order to leave CPU time to the embedding environment.
+    pub fn new(max_log_level: log::LevelFilter, cpu_rate_limit: u32) -> Self {
         // Try initialize the logging and the panic hook.
         // Note that `start_client` can theoretically be called multiple times, meaning that these
         // calls shouldn't panic if reached multiple times.
@@ -153,48 +224,26 @@ impl Client {
         // The `new_task_tx` and `new_task_rx` variables are used when spawning a new task is
         // required. Send a task on `new_task_tx` to start running it.
         // TODO: update comment ^
-        let (new_task_tx, mut new_task_rx) = mpsc::unbounded();
+        let (new_task_tx, new_task_rx) = mpsc::unbounded();
+        let (main_task_finished_tx, main_task_finished) = oneshot::channel();
 
         // This is the main future that executes the entire client.
-        ffi::spawn_background_task(async move {
-            let mut all_tasks = stream::FuturesUnordered::new();
-
-            // The code below processes tasks that have names.
-            #[pin_project::pin_project]
-            struct FutureAdapter<F> {
-                name: String,
-                #[pin]
-                future: F,
-            }
-
-            impl<F: Future> Future for FutureAdapter<F> {
-                type Output = F::Output;
-                fn poll(self: Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<Self::Output> {
-                    let this = self.project();
-                    log::trace!("enter: {}", &this.name);
-                    let out = this.future.poll(cx);
-                    log::trace!("leave");
-                    out
-                }
-            }
+        let main_task = async move {
+            run_tasks(new_task_rx).await;
+            let _ = main_task_finished_tx.send(());
+        };
 
-            loop {
-                futures::select! {
-                    (new_task_name, new_task) = new_task_rx.select_next_some() => {
-                        all_tasks.push(FutureAdapter {
-                            name: new_task_name,
-                            future: new_task,
-                        });
-                    },
-                    () = all_tasks.select_next_some() => {},
-                }
-            }
+        ffi::spawn_background_task(CpuRateLimited {
+            future: main_task,
+            rate_limit: cpu_rate_limit,
+            sleep: None,
         });
 
         Client {
             new_task_tx,
             public_api_chains: slab::Slab::with_capacity(2),
             chains_by_key: HashMap::with_capacity(2),
+            main_task_finished,
         }
     }
 
@@ -217,25 +266,67 @@ impl Client {
         // Load the information about the chain from the chain spec. If a light sync state is
         // present in the chain specs, it is possible to start sync at the finalized block it
         // describes.
-        let genesis_chain_information =
-            match chain::chain_information::ValidChainInformation::from_chain_spec(&chain_spec) {
-                Ok(ci) => ci,
-                Err(err) => {
+        // Chain specs that only contain the root hash of the genesis storage can only be used if
+        // they contain a light sync state, in which case the genesis block header is derived
+        // from this root hash.
+        let (chain_information, genesis_block_header) =
+            match (
+                chain::chain_information::ValidChainInformation::from_chain_spec(&chain_spec),
+                chain_spec.light_sync_state(),
+            ) {
+                (Ok(genesis_ci), None) => {
+                    let genesis_block_header = genesis_ci.as_ref().finalized_block_header.into();
+                    (genesis_ci, genesis_block_header)
+                }
+                (Ok(genesis_ci), Some(light_sync_state)) => (
+                    light_sync_state.as_chain_information(),
+                    genesis_ci.as_ref().finalized_block_header.into(),
+                ),
+                (
+                    Err(chain::chain_information::FromGenesisStorageError::UnknownStorageItems),
+                    Some(light_sync_state),
+                ) => (
+                    light_sync_state.as_chain_information(),
+                    smoldot::calculate_genesis_block_header(&chain_spec),
+                ),
+                (Err(err), _) => {
                     return ChainId(self.public_api_chains.insert(PublicApiChain::Erroneous(
                         format!("Failed to build genesis chain information: {}", err),
                     )));
                 }
             };
-        let chain_information = if let Some(light_sync_state) = chain_spec.light_sync_state() {
-            light_sync_state.as_chain_information()
+
+        // If the database content passed by the user is more recent than the state found in the
+        // chain specification, start syncing from the database instead. The database content is
+        // ignored if it can't be decoded, if it belongs to a chain with a different genesis
+        // block, or if it doesn't use the same consensus and finality algorithms as the chain
+        // specification.
+        let chain_information = if config.database_content.is_empty() {
+            chain_information
         } else {
-            genesis_chain_information.clone()
+            match database::decode_database(config.database_content, &genesis_block_header.hash()) {
+                Ok(database_ci)
+                    if database_ci.as_ref().finalized_block_header.number
+                        > chain_information.as_ref().finalized_block_header.number
+                        && mem::discriminant(&database_ci.as_ref().consensus)
+                            == mem::discriminant(&chain_information.as_ref().consensus)
+                        && mem::discriminant(&database_ci.as_ref().finality)
+                            == mem::discriminant(&chain_information.as_ref().finality) =>
+                {
+                    database_ci
+                }
+                Ok(_) => chain_information,
+                Err(err) => {
+                    log::warn!("Ignoring database content: {}", err);
+                    chain_information
+                }
+            }
         };
 
         // If the chain specification specifies a parachain, find the corresponding relay chain
         // in the list of potential relay chains passed by the user.
         // If no relay chain can be found, the chain creation fails.
-        let relay_chain_id = if let Some((relay_chain_id, _para_id)) = chain_spec.relay_chain() {
+        let relay_chain_id = if let Some(relay_chain_id) = chain_spec.relay_chain_id() {
             let chain = config
                 .potential_relay_chains
                 .filter(|c| {
@@ -279,33 +370,27 @@ impl Client {
         // identical chains to be de-duplicated, but security issues would arise if two chains
         // were considered identical while they're in reality not identical.
         let new_chain_key = ChainKey {
-            genesis_block_hash: genesis_chain_information
-                .as_ref()
-                .finalized_block_header
-                .hash(),
+            genesis_block_hash: genesis_block_header.hash(),
             relay_chain: relay_chain_id.map(|ck| {
                 (
                     Box::new(match self.public_api_chains.get(ck.0).unwrap() {
                         PublicApiChain::Ok { key, .. } => key.clone(),
                         _ => unreachable!(),
                     }),
-                    chain_spec.relay_chain().unwrap().1,
+                    chain_spec.para_id().unwrap(),
                 )
             }),
             protocol_id: chain_spec.protocol_id().to_owned(),
+            fork_id: chain_spec.fork_id().map(|id| id.to_owned()),
+            reserved_only: config.reserved_only,
+            network_identity_key: config.network_identity_key,
         };
 
         // Grab a couple of fields from the chain specification for later, as the chain
         // specification is consumed below.
         let chain_spec_chain_id = chain_spec.id().to_owned();
-        let genesis_block_hash = genesis_chain_information
-            .as_ref()
-            .finalized_block_header
-            .hash();
-        let genesis_block_state_root = *genesis_chain_information
-            .as_ref()
-            .finalized_block_header
-            .state_root;
+        let genesis_block_hash = genesis_block_header.hash();
+        let genesis_block_state_root = genesis_block_header.state_root;
 
         // Grab the services of the relay chain.
         //
@@ -363,7 +448,7 @@ impl Client {
                 if !self
                     .chains_by_key
                     .values()
-                    .any(|(_, name, _)| *name == attempt)
+                    .any(|(_, name, _, _)| *name == attempt)
                 {
                     break attempt;
                 }
@@ -375,6 +460,15 @@ impl Client {
             }
         };
 
+        // A relay chain must keep running as long as its parachains are running. Each new chain
+        // thus holds a reference to its relay chain, released in `remove_chain`.
+        if !self.chains_by_key.contains_key(&new_chain_key) {
+            if let Some((relay_chain_key, _)) = &new_chain_key.relay_chain {
+                let relay_chain = self.chains_by_key.get_mut(&**relay_chain_key).unwrap();
+                relay_chain.2 = NonZeroU32::new(relay_chain.2.get() + 1).unwrap();
+            }
+        }
+
         // Start the services of the chain to add, or grab the services if they already exist.
         let (running_chain_init, log_name) = match self.chains_by_key.entry(new_chain_key.clone()) {
             Entry::Occupied(mut entry) => {
@@ -386,14 +480,25 @@ impl Client {
             Entry::Vacant(entry) => {
                 // Key used by the networking. Represents the identity of the node on the
                 // peer-to-peer network.
-                let network_noise_key = connection::NoiseKey::new(&rand::random());
+                let network_noise_key = connection::NoiseKey::new(
+                    &config.network_identity_key.unwrap_or_else(rand::random),
+                );
+
+                // All the tasks of the services of the chain are spawned within the same group, so
+                // that they can all be destroyed at once when the chain is removed.
+                let (chain_tasks_tx, abort_chain_tasks) = spawn_tasks_group(
+                    &self.new_task_tx,
+                    format!("chain-{}", log_name),
+                    TaskPriority::Latency,
+                );
 
                 // Spawn a background task that initializes the services of the new chain and
                 // yields a `RunningChain`.
                 let running_chain_init_future: future::RemoteHandle<RunningChain> = {
-                    let new_tasks_tx = self.new_task_tx.clone();
+                    let new_tasks_tx = chain_tasks_tx.clone();
                     let chain_spec = chain_spec.clone(); // TODO: quite expensive
                     let log_name = log_name.clone();
+                    let reserved_only = config.reserved_only;
 
                     let future = async move {
                         // Wait until the relay chain has finished initializing, if necessary.
@@ -412,7 +517,7 @@ impl Client {
 
                         // TODO: avoid cloning here
                         let chain_name = chain_spec.name().to_owned();
-                        let relay_chain_para_id = chain_spec.relay_chain().map(|(_, id)| id);
+                        let relay_chain_para_id = chain_spec.para_id();
                         let starting_block_number =
                             chain_information.as_ref().finalized_block_header.number;
                         let starting_block_hash =
@@ -422,10 +527,11 @@ impl Client {
                             log_name.clone(),
                             new_tasks_tx,
                             chain_information,
-                            genesis_chain_information,
+                            genesis_block_header,
                             chain_spec,
                             relay_chain.as_ref().map(|(r, _)| r),
                             network_noise_key,
+                            reserved_only,
                         )
                         .await;
 
@@ -459,9 +565,10 @@ impl Client {
                     };
 
                     let (background_future, output_future) = future.remote_handle();
-                    self.new_task_tx
+                    chain_tasks_tx
                         .unbounded_send((
                             "services-initialization".to_owned(),
+                            TaskPriority::Background,
                             background_future.boxed(),
                         ))
                         .unwrap();
@@ -472,6 +579,7 @@ impl Client {
                     future::maybe_done(running_chain_init_future.shared()),
                     log_name,
                     NonZeroU32::new(1).unwrap(),
+                    abort_chain_tasks,
                 ));
                 (&mut entry.0, &entry.1)
             }
@@ -481,6 +589,26 @@ impl Client {
         let public_api_chains_entry = self.public_api_chains.vacant_entry();
         let new_chain_id = ChainId(public_api_chains_entry.key());
 
+        // The tasks specific to this entry in `public_api_chains` are spawned within the same
+        // group, so that they can all be destroyed at once when the chain is removed, even if the
+        // services of the chain keep running because they are shared with other chains.
+        let (public_tasks_tx, abort_public_tasks) = spawn_tasks_group(
+            &self.new_task_tx,
+            format!("public-api-chain-{}", log_name),
+            TaskPriority::Latency,
+        );
+
+        // The offchain storage belongs to this entry in `public_api_chains`, and every
+        // modification is reported through the FFI layer so that it can be persisted.
+        let offchain_storage = Arc::new(std::sync::Mutex::new(
+            offchain_storage::OffchainStorage::new(
+                config.offchain_storage_max_bytes,
+                Box::new(move |kind, key, value| {
+                    ffi::emit_offchain_storage_change(new_chain_id, kind, key, value)
+                }),
+            ),
+        ));
+
         // JSON-RPC service initialization. This is done every time `add_chain` is called, even
         // if a similar chain already existed.
         let json_rpc_service = if config.json_rpc_running {
@@ -493,8 +621,13 @@ impl Client {
 
             // Spawn a background task that initializes the JSON-RPC service.
             let json_rpc_service_init: future::RemoteHandle<Arc<json_rpc_service::JsonRpcService>> = {
-                let new_task_tx = self.new_task_tx.clone();
+                let new_task_tx = public_tasks_tx.clone();
                 let log_name = log_name.clone();
+                let max_parallel_requests = NonZeroU32::new(24).unwrap();
+                let max_parallel_expensive_requests = config
+                    .json_rpc_max_parallel_expensive_requests
+                    .map_or(max_parallel_requests, |max| max.min(max_parallel_requests));
+                let offchain_storage = offchain_storage.clone();
                 let init_future = async move {
                     // Wait for the chain to finish initializing before starting the JSON-RPC service.
                     (&mut running_chain_init).await;
@@ -504,16 +637,21 @@ impl Client {
                         json_rpc_service::Config {
                             log_name, // TODO: add a way to differentiate multiple different json-rpc services under the same chain
                             tasks_executor: Box::new({
-                                move |name, fut| new_task_tx.unbounded_send((name, fut)).unwrap()
+                                move |name, priority, fut| {
+                                    new_task_tx.unbounded_send((name, priority, fut)).unwrap()
+                                }
                             }),
                             sync_service: running_chain.sync_service,
+                            network_service: running_chain.network_service,
                             transactions_service: running_chain.transactions_service,
                             runtime_service: running_chain.runtime_service,
+                            offchain_storage,
                             chain_spec: &chain_spec,
                             peer_id: &running_chain.network_identity.clone(),
                             genesis_block_hash,
                             genesis_block_state_root,
-                            max_parallel_requests: NonZeroU32::new(24).unwrap(),
+                            max_parallel_requests,
+                            max_parallel_expensive_requests,
                             max_pending_requests: NonZeroU32::new(32).unwrap(),
                             max_subscriptions: 1024, // Note: the PolkadotJS UI is very heavy in terms of subscriptions.
                         },
@@ -521,8 +659,12 @@ impl Client {
                 };
 
                 let (background_run, output_future) = init_future.remote_handle();
-                self.new_task_tx
-                    .unbounded_send(("json-rpc-service-init".to_owned(), background_run.boxed()))
+                public_tasks_tx
+                    .unbounded_send((
+                        "json-rpc-service-init".to_owned(),
+                        TaskPriority::Background,
+                        background_run.boxed(),
+                    ))
                     .unwrap();
                 output_future
             };
@@ -532,10 +674,7 @@ impl Client {
 
             // Spawn another task that, after the JSON-RPC service has finished initializing,
             // polls its responses and sends them through the FFI layer.
-            //
-            // The expression is an `AbortHandle` that can be used in order to instantly kill this
-            // background task once the user decides to get rid of this chain.
-            let abort_run_task: future::AbortHandle = {
+            {
                 let shared_init = json_rpc_service_init.clone();
                 let run_task = async move {
                     let json_rpc_service = shared_init.await;
@@ -544,30 +683,228 @@ impl Client {
                         send_back(&response, new_chain_id)
                     }
                 };
-                let (run_task, abort_run_task) = future::abortable(run_task);
-                self.new_task_tx
+                public_tasks_tx
                     .unbounded_send((
                         "json-rpc-service-messages-out".to_owned(),
-                        run_task.map(|_| ()).boxed(),
+                        TaskPriority::Latency,
+                        run_task.boxed(),
                     ))
                     .unwrap();
-                abort_run_task
-            };
+            }
 
-            Some((future::maybe_done(json_rpc_service_init), abort_run_task))
+            Some(future::maybe_done(json_rpc_service_init))
         } else {
             None
         };
 
+        // Spawn a task that periodically emits the content of the database of the chain through
+        // the FFI layer, so that it can be passed back the next time the chain is added.
+        {
+            // Clone `running_chain_init`.
+            let mut running_chain_init = match running_chain_init {
+                future::MaybeDone::Done(d) => future::MaybeDone::Done(d.clone()),
+                future::MaybeDone::Future(d) => future::MaybeDone::Future(d.clone()),
+                future::MaybeDone::Gone => unreachable!(),
+            };
+
+            let database_task = async move {
+                (&mut running_chain_init).await;
+                let running_chain = Pin::new(&mut running_chain_init).take_output().unwrap();
+
+                let mut previous_content = None;
+                loop {
+                    ffi::Delay::new(Duration::from_secs(15)).await;
+
+                    // `None` is returned for chains whose state isn't worth persisting.
+                    let content = match running_chain
+                        .sync_service
+                        .serialize_chain_information()
+                        .await
+                    {
+                        Some(content) => content,
+                        None => break,
+                    };
+
+                    if previous_content.as_ref() != Some(&content) {
+                        ffi::emit_database_content(
+                            &database::encode_database(&genesis_block_hash, &content),
+                            new_chain_id,
+                        );
+                        previous_content = Some(content);
+                    }
+                }
+            };
+
+            public_tasks_tx
+                .unbounded_send((
+                    "database-content-out".to_owned(),
+                    TaskPriority::Background,
+                    database_task.boxed(),
+                ))
+                .unwrap();
+        }
+
+        // Spawn a task that applies to the offchain storage the changes made by the runtime
+        // through the offchain indexing host functions.
+        {
+            // Clone `running_chain_init`.
+            let mut running_chain_init = match running_chain_init {
+                future::MaybeDone::Done(d) => future::MaybeDone::Done(d.clone()),
+                future::MaybeDone::Future(d) => future::MaybeDone::Future(d.clone()),
+                future::MaybeDone::Gone => unreachable!(),
+            };
+
+            let offchain_storage = offchain_storage.clone();
+            let log_name = log_name.clone();
+            let offchain_indexing_task = async move {
+                (&mut running_chain_init).await;
+                let running_chain = Pin::new(&mut running_chain_init).take_output().unwrap();
+
+                let mut changes = running_chain
+                    .transactions_service
+                    .subscribe_offchain_storage_changes(16)
+                    .await;
+
+                while let Some(changes) = changes.next().await {
+                    let mut offchain_storage = offchain_storage.lock().unwrap();
+                    for (key, value) in changes {
+                        if let Err(err) = offchain_storage.set(
+                            host::OffchainStorageKind::Persistent,
+                            &key,
+                            value.as_deref(),
+                        ) {
+                            log::warn!(
+                                target: &log_name,
+                                "Failed to apply offchain indexing change: {}",
+                                err
+                            );
+                        }
+                    }
+                }
+            };
+
+            public_tasks_tx
+                .unbounded_send((
+                    "offchain-indexing".to_owned(),
+                    TaskPriority::Background,
+                    offchain_indexing_task.boxed(),
+                ))
+                .unwrap();
+        }
+
         // Success!
         public_api_chains_entry.insert(PublicApiChain::Ok {
             key: new_chain_key,
             chain_spec_chain_id,
             json_rpc_service,
+            offchain_storage,
+            tasks_tx: public_tasks_tx,
+            abort_tasks: abort_public_tasks,
         });
         new_chain_id
     }
 
+    /// Returns a future that yields a snapshot of the internal metrics of the client.
+    ///
+    /// Only the chains whose services have finished initializing are reported.
+    pub fn metrics(&self) -> impl Future<Output = metrics::Metrics> + Send + 'static {
+        let running_chains = self
+            .chains_by_key
+            .values()
+            .filter_map(|(running_chain, log_name, _, _)| match running_chain {
+                future::MaybeDone::Done(running_chain) => {
+                    Some((running_chain.clone(), log_name.clone()))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        async move {
+            let mut chains = Vec::with_capacity(running_chains.len());
+
+            for (running_chain, log_name) in running_chains {
+                chains.push(chain_metrics(&running_chain, log_name).await);
+            }
+
+            metrics::Metrics {
+                #[cfg(target_arch = "wasm32")]
+                memory_bytes: u64::try_from(core::arch::wasm32::memory_size(0)).unwrap() * 65536,
+                #[cfg(not(target_arch = "wasm32"))]
+                memory_bytes: 0,
+                num_tasks: u64::try_from(NUM_TASKS.load(atomic::Ordering::Relaxed)).unwrap(),
+                chains,
+            }
+        }
+    }
+
+    /// Returns an estimate of the memory used by the services of the given chain.
+    ///
+    /// The services of a chain are shared with all the other chains that have the same
+    /// specification. The services of the relay chain of a parachain aren't included.
+    /// All the values are zero if the chain is erroneous or is still initializing.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`ChainId`] is invalid.
+    ///
+    pub fn chain_memory_usage(
+        &self,
+        id: ChainId,
+    ) -> impl Future<Output = metrics::MemoryUsage> + Send + 'static {
+        let running_chain = match &self.public_api_chains[id.0] {
+            PublicApiChain::Ok { key, .. } => match self.chains_by_key.get(key) {
+                Some((future::MaybeDone::Done(running_chain), log_name, _, _)) => {
+                    Some((running_chain.clone(), log_name.clone()))
+                }
+                _ => None,
+            },
+            PublicApiChain::Erroneous(_) => None,
+        };
+
+        async move {
+            match running_chain {
+                Some((running_chain, log_name)) => {
+                    chain_metrics(&running_chain, log_name).await.memory
+                }
+                None => metrics::MemoryUsage::default(),
+            }
+        }
+    }
+
+    /// Inserts an entry in the offchain storage of the given chain, as previously reported
+    /// through the FFI layer. Does nothing if the chain is erroneous.
+    ///
+    /// Entries that don't fit in [`AddChainConfig::offchain_storage_max_bytes`] are ignored.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`ChainId`] is invalid.
+    ///
+    pub fn offchain_storage_load(
+        &mut self,
+        id: ChainId,
+        kind: host::OffchainStorageKind,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    ) {
+        if let PublicApiChain::Ok {
+            offchain_storage, ..
+        } = &self.public_api_chains[id.0]
+        {
+            if offchain_storage
+                .lock()
+                .unwrap()
+                .load(kind, key, value)
+                .is_err()
+            {
+                log::warn!(
+                    "Ignoring offchain storage entry exceeding the quota of {:?}",
+                    id
+                );
+            }
+        }
+    }
+
     /// If [`Client::add_chain`] encountered an error when creating this chain, returns the error
     /// message corresponding to it.
     pub fn chain_is_erroneous(&self, id: ChainId) -> Option<&str> {
@@ -590,36 +927,140 @@ impl Client {
     ///
     /// While from the API perspective it will look like the chain no longer exists, calling this
     /// function will not actually immediately disconnect from the given chain if it is still used
-    /// as the relay chain of a parachain.
+    /// by another chain with the same specification, or as the relay chain of a parachain.
+    /// Once this is no longer the case, all the tasks of the services of the chain are destroyed,
+    /// which closes its connections and frees its memory, including its compiled runtimes.
     pub fn remove_chain(&mut self, id: ChainId) {
         let removed_chain = self.public_api_chains.remove(id.0);
 
         match removed_chain {
             PublicApiChain::Ok {
-                json_rpc_service,
-                key,
-                ..
+                abort_tasks, key, ..
             } => {
-                if let Some((_, abort)) = json_rpc_service {
-                    // Instantly aborts the task that sends back responses.
-                    // This works only because Wasm is single-threaded, otherwise it would be
-                    // possible for another thread to still be polling that task.
-                    abort.abort();
-                }
-
-                let running_chain = self.chains_by_key.get_mut(&key).unwrap();
-                if running_chain.2.get() == 1 {
-                    self.chains_by_key.remove(&key);
-                } else {
-                    running_chain.2 = NonZeroU32::new(running_chain.2.get() - 1).unwrap();
+                // Instantly destroys the JSON-RPC service and the tasks that send back
+                // responses and database content.
+                // This works only because Wasm is single-threaded, otherwise it would be
+                // possible for another thread to still be polling these tasks.
+                abort_tasks.abort();
+
+                // Release the reference to the services of the chain, and, if the services are
+                // destroyed, the reference to the services of the relay chain.
+                let mut key_to_release = Some(key);
+                while let Some(key) = key_to_release.take() {
+                    let running_chain = self.chains_by_key.get_mut(&key).unwrap();
+                    if running_chain.2.get() == 1 {
+                        let (_, log_name, _, abort_chain_tasks) =
+                            self.chains_by_key.remove(&key).unwrap();
+                        // Same remark as above.
+                        abort_chain_tasks.abort();
+                        log::debug!("Shut down the services of {}", log_name);
+                        key_to_release = key.relay_chain.map(|(relay_chain, _)| *relay_chain);
+                    } else {
+                        running_chain.2 = NonZeroU32::new(running_chain.2.get() - 1).unwrap();
+                    }
                 }
             }
             _ => {}
         }
 
+        self.chains_by_key.shrink_to_fit();
+
         self.public_api_chains.shrink_to_fit();
     }
 
+    /// Shuts down the client.
+    ///
+    /// The JSON-RPC services of all the chains are instantly stopped, then the content of the
+    /// database of each chain is emitted one last time through the FFI layer. Afterwards, the
+    /// services of each chain are stopped one by one in the order transactions, runtime, sync,
+    /// and network, starting with the parachains. Stopping the network service closes all its
+    /// connections.
+    ///
+    /// The returned future yields once all the background tasks of the client have terminated.
+    pub fn shutdown(self) -> impl Future<Output = ()> + Send + 'static {
+        // Chains whose database content must be emitted one last time. Chains that are still
+        // initializing don't have anything worth persisting.
+        let mut to_flush = Vec::with_capacity(self.public_api_chains.len());
+
+        for (chain_id, chain) in self.public_api_chains.iter() {
+            if let PublicApiChain::Ok {
+                key, abort_tasks, ..
+            } = chain
+            {
+                // Same remark as in `remove_chain`.
+                abort_tasks.abort();
+
+                if let future::MaybeDone::Done(running_chain) = &self.chains_by_key[key].0 {
+                    to_flush.push((
+                        ChainId(chain_id),
+                        key.genesis_block_hash,
+                        running_chain.clone(),
+                    ));
+                }
+            }
+        }
+
+        // Parachains depend on the runtime service of their relay chain, and are thus stopped
+        // first.
+        let mut chains = self
+            .chains_by_key
+            .into_iter()
+            .map(|(key, (running_chain, log_name, _, abort_chain_tasks))| {
+                let running_chain = match running_chain {
+                    future::MaybeDone::Done(running_chain) => Some(running_chain),
+                    _ => None,
+                };
+                (
+                    key.relay_chain.is_none(),
+                    running_chain,
+                    log_name,
+                    abort_chain_tasks,
+                )
+            })
+            .collect::<Vec<_>>();
+        chains.sort_by_key(|(is_relay_chain, ..)| *is_relay_chain);
+
+        let new_task_tx = self.new_task_tx;
+        let main_task_finished = self.main_task_finished;
+
+        async move {
+            for (chain_id, genesis_block_hash, running_chain) in to_flush {
+                if let Some(content) = running_chain
+                    .sync_service
+                    .serialize_chain_information()
+                    .await
+                {
+                    ffi::emit_database_content(
+                        &database::encode_database(&genesis_block_hash, &content),
+                        chain_id,
+                    );
+                }
+            }
+
+            for (_, running_chain, log_name, abort_chain_tasks) in chains {
+                if let Some(running_chain) = running_chain {
+                    for (service_name, abort_service) in &running_chain.services_abort_handles {
+                        abort_service.abort();
+                        // Give the opportunity to the tasks of the service to be destroyed
+                        // before the next service is stopped.
+                        yield_once().await;
+                        log::debug!("Stopped the {} service of {}", service_name, log_name);
+                    }
+                }
+
+                // Also destroys the tasks that aren't part of any service, such as the
+                // initialization of the chain if it is still in progress.
+                abort_chain_tasks.abort();
+                log::debug!("Shut down the services of {}", log_name);
+            }
+
+            // The main task ends once all the tasks have terminated and no new task can be
+            // spawned.
+            drop(new_task_tx);
+            let _ = main_task_finished.await;
+        }
+    }
+
     /// Enqueues a JSON-RPC request towards the given chain.
     ///
     /// Since most JSON-RPC requests can only be answered asynchronously, the request is only
@@ -660,10 +1101,12 @@ impl Client {
         };
 
         if let Some(PublicApiChain::Ok {
-            json_rpc_service, ..
+            json_rpc_service,
+            tasks_tx,
+            ..
         }) = self.public_api_chains.get(chain_id.0)
         {
-            if let Some((ref json_rpc_service, _)) = json_rpc_service {
+            if let Some(json_rpc_service) = json_rpc_service {
                 let mut json_rpc_service = match json_rpc_service {
                     future::MaybeDone::Done(d) => future::MaybeDone::Done(d.clone()),
                     future::MaybeDone::Future(d) => future::MaybeDone::Future(d.clone()),
@@ -681,8 +1124,12 @@ impl Client {
                 };
 
                 // TODO: properly spread resources usage instead of spawning new tasks all the time
-                self.new_task_tx
-                    .unbounded_send(("json-rpc-request".to_owned(), future.boxed()))
+                tasks_tx
+                    .unbounded_send((
+                        "json-rpc-request".to_owned(),
+                        TaskPriority::Latency,
+                        future.boxed(),
+                    ))
                     .unwrap();
             } else {
                 send_back(
@@ -720,16 +1167,178 @@ enum PublicApiChain {
     Ok {
         key: ChainKey,
         chain_spec_chain_id: String,
-        json_rpc_service: Option<(
+        json_rpc_service: Option<
             future::MaybeDone<
                 future::Shared<future::RemoteHandle<Arc<json_rpc_service::JsonRpcService>>>,
             >,
-            future::AbortHandle,
-        )>,
+        >,
+        /// Offchain storage of this chain. Not shared with the other chains with the same
+        /// specification.
+        offchain_storage: Arc<std::sync::Mutex<offchain_storage::OffchainStorage>>,
+        /// Spawns a task within the group of tasks specific to this chain.
+        tasks_tx: TasksSender,
+        /// Aborts all the tasks of the group of tasks specific to this chain, including the
+        /// JSON-RPC service.
+        abort_tasks: future::AbortHandle,
     },
     Erroneous(String),
 }
 
+/// Channel on which tasks can be sent in order to be spawned. The first tuple element is the name
+/// of the task used for debugging purposes.
+type TasksSender = mpsc::UnboundedSender<(String, TaskPriority, future::BoxFuture<'static, ()>)>;
+
+/// Hint passed alongside each spawned task, indicating how the task should be scheduled.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TaskPriority {
+    /// The task must react quickly to events, for example because it drives a network
+    /// connection or answers a JSON-RPC request.
+    Latency,
+    /// The task performs work whose latency isn't important, such as compiling runtimes or
+    /// verifying blocks.
+    Background,
+}
+
+/// Number of [`FutureAdapter`]s currently alive. Reported in the metrics.
+static NUM_TASKS: atomic::AtomicUsize = atomic::AtomicUsize::new(0);
+
+/// Wraps around a task and prints logs whenever it is polled.
+#[pin_project::pin_project]
+struct FutureAdapter<F> {
+    name: String,
+    #[pin]
+    future: F,
+    /// Keeps [`NUM_TASKS`] up to date.
+    _counter: TaskCounter,
+}
+
+impl<F> FutureAdapter<F> {
+    fn new(name: String, future: F) -> Self {
+        NUM_TASKS.fetch_add(1, atomic::Ordering::Relaxed);
+        FutureAdapter {
+            name,
+            future,
+            _counter: TaskCounter,
+        }
+    }
+}
+
+/// Decrements [`NUM_TASKS`] when destroyed, including when the task is aborted.
+struct TaskCounter;
+
+impl Drop for TaskCounter {
+    fn drop(&mut self) {
+        NUM_TASKS.fetch_sub(1, atomic::Ordering::Relaxed);
+    }
+}
+
+impl<F: Future> Future for FutureAdapter<F> {
+    type Output = F::Output;
+    fn poll(self: Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<Self::Output> {
+        let this = self.project();
+        log::trace!("enter: {}", &this.name);
+        let out = this.future.poll(cx);
+        log::trace!("leave");
+        out
+    }
+}
+
+/// Wraps around a future and, after each time it is polled, prevents it from being polled again
+/// for a duration proportional to the time it took to poll it. This guarantees that the fraction
+/// of CPU time spent polling the future doesn't exceed the rate limit.
+#[pin_project::pin_project]
+struct CpuRateLimited<F> {
+    #[pin]
+    future: F,
+    /// See [`Client::new`]. `u32::max_value()` means no limit.
+    rate_limit: u32,
+    /// If `Some`, the future must not be polled before the timer has elapsed.
+    sleep: Option<ffi::Delay>,
+}
+
+impl<F: Future> Future for CpuRateLimited<F> {
+    type Output = F::Output;
+    fn poll(self: Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<Self::Output> {
+        let this = self.project();
+
+        if let Some(sleep) = this.sleep.as_mut() {
+            if sleep.poll_unpin(cx).is_pending() {
+                return task::Poll::Pending;
+            }
+            *this.sleep = None;
+        }
+
+        let before_poll = ffi::Instant::now();
+        let out = this.future.poll(cx);
+
+        if *this.rate_limit != u32::max_value() {
+            // If polling took a duration `d` and the rate limit is `r`, the next poll must not
+            // happen before `d * (1 / r - 1)`, so that the busy time is at most a fraction `r` of
+            // the total time.
+            let rate = f64::from((*this.rate_limit).max(1)) / f64::from(u32::max_value());
+            let mut sleep = ffi::Delay::new(before_poll.elapsed().mul_f64(1.0 / rate - 1.0));
+            // The timer is polled immediately in order to register the waker.
+            if sleep.poll_unpin(cx).is_pending() {
+                *this.sleep = Some(sleep);
+            }
+        }
+
+        out
+    }
+}
+
+/// Runs all the tasks sent on `new_tasks_rx`, until the channel is closed and all the tasks have
+/// finished.
+///
+/// Tasks whose priority is [`TaskPriority::Latency`] are always polled before the ones whose
+/// priority is [`TaskPriority::Background`], so that CPU-heavy tasks don't delay the tasks that
+/// must react quickly.
+async fn run_tasks(
+    mut new_tasks_rx: mpsc::UnboundedReceiver<(
+        String,
+        TaskPriority,
+        future::BoxFuture<'static, ()>,
+    )>,
+) {
+    let mut latency_tasks = stream::FuturesUnordered::new();
+    let mut background_tasks = stream::FuturesUnordered::new();
+
+    loop {
+        futures::select_biased! {
+            (new_task_name, priority, new_task) = new_tasks_rx.select_next_some() => {
+                let new_task = FutureAdapter::new(new_task_name, new_task);
+                match priority {
+                    TaskPriority::Latency => latency_tasks.push(new_task),
+                    TaskPriority::Background => background_tasks.push(new_task),
+                }
+            },
+            () = latency_tasks.select_next_some() => {},
+            () = background_tasks.select_next_some() => {},
+            complete => break,
+        }
+    }
+}
+
+/// Spawns through `new_task_tx` a task named `name` that runs all the tasks later sent on the
+/// returned channel. `priority` applies to the group as a whole, within the tasks of the
+/// group spawned through `new_task_tx`.
+///
+/// Aborting the returned `AbortHandle` instantly destroys all the tasks of the group at once,
+/// and thus frees all the resources that they hold.
+fn spawn_tasks_group(
+    new_task_tx: &TasksSender,
+    name: String,
+    priority: TaskPriority,
+) -> (TasksSender, future::AbortHandle) {
+    let (group_tasks_tx, group_tasks_rx) = mpsc::unbounded();
+
+    let (group_task, abort_handle) = future::abortable(run_tasks(group_tasks_rx));
+    new_task_tx
+        .unbounded_send((name, priority, group_task.map(|_| ()).boxed()))
+        .unwrap();
+    (group_tasks_tx, abort_handle)
+}
+
 /// Sends back a response or a notification to the JSON-RPC client.
 ///
 /// > **Note**: This method wraps around [`ffi::emit_json_rpc_response`] and exists primarily
@@ -762,6 +1371,15 @@ struct ChainKey {
     relay_chain: Option<(Box<ChainKey>, u32)>,
     /// Network protocol id, found in the chain specification.
     protocol_id: String,
+    /// Fork id, found in the chain specification. Chains that share the same genesis block but
+    /// have a different fork id are different chains.
+    fork_id: Option<String>,
+    /// See [`AddChainConfig::reserved_only`]. Chains in reserved-only mode must never share
+    /// their networking with chains that aren't.
+    reserved_only: bool,
+    /// See [`AddChainConfig::network_identity_key`]. Chains must never share their networking
+    /// with chains that have asked for a different identity.
+    network_identity_key: Option<[u8; 32]>,
 }
 
 #[derive(Clone)]
@@ -771,6 +1389,65 @@ struct RunningChain {
     sync_service: Arc<sync_service::SyncService>,
     runtime_service: Arc<runtime_service::RuntimeService>,
     transactions_service: Arc<transactions_service::TransactionsService>,
+    /// Abort the tasks of the transactions, runtime, sync, and network services, in this order,
+    /// alongside with the name of the service. See [`Client::shutdown`].
+    services_abort_handles: Vec<(&'static str, future::AbortHandle)>,
+}
+
+/// Builds the metrics of the given chain. See [`Client::metrics`].
+async fn chain_metrics(running_chain: &RunningChain, log_name: String) -> metrics::ChainMetrics {
+    let peers_best_blocks = running_chain
+        .sync_service
+        .syncing_peers()
+        .await
+        .map(|(_, _, height, _)| height)
+        .collect::<Vec<_>>();
+
+    // Grab the finalized and best blocks by briefly subscribing to the sync service.
+    let (finalized_block_number, best_block_number, sync_blocks_memory_bytes) = {
+        let subscription = running_chain.sync_service.subscribe_all(16).await;
+        let decode_number = |scale_encoded_header: &[u8]| {
+            header::decode(scale_encoded_header).map_or(0, |h| h.number)
+        };
+        let finalized = decode_number(&subscription.finalized_block_scale_encoded_header);
+        let best = subscription
+            .non_finalized_blocks_ancestry_order
+            .iter()
+            .rev()
+            .find(|block| block.is_new_best)
+            .map_or(finalized, |block| {
+                decode_number(&block.scale_encoded_header)
+            });
+        let headers_bytes = subscription.finalized_block_scale_encoded_header.len()
+            + subscription
+                .non_finalized_blocks_ancestry_order
+                .iter()
+                .map(|block| block.scale_encoded_header.len())
+                .sum::<usize>();
+        (finalized, best, u64::try_from(headers_bytes).unwrap())
+    };
+
+    let runtime = running_chain.runtime_service.metrics().await;
+
+    let memory = metrics::MemoryUsage {
+        runtimes: runtime.runtimes_memory_bytes,
+        blocks: runtime.blocks_memory_bytes + sync_blocks_memory_bytes,
+        caches: runtime.caches_memory_bytes,
+        network: running_chain
+            .network_service
+            .connections_memory_usage()
+            .await,
+    };
+
+    metrics::ChainMetrics {
+        log_name,
+        num_peers: u64::try_from(peers_best_blocks.len()).unwrap(),
+        best_block_number,
+        finalized_block_number,
+        highest_peer_block_number: peers_best_blocks.into_iter().max(),
+        runtime,
+        memory,
+    }
 }
 
 /// Starts all the services of the client.
@@ -779,29 +1456,51 @@ struct RunningChain {
 /// other services will later shut down as well.
 async fn start_services(
     log_name: String,
-    new_task_tx: mpsc::UnboundedSender<(
-        String,
-        Pin<Box<dyn Future<Output = ()> + Send + 'static>>,
-    )>,
+    new_task_tx: TasksSender,
     chain_information: chain::chain_information::ValidChainInformation,
-    genesis_chain_information: chain::chain_information::ValidChainInformation,
+    genesis_block_header: header::Header,
     chain_spec: chain_spec::ChainSpec,
     relay_chain: Option<&RunningChain>,
     network_noise_key: connection::NoiseKey,
+    reserved_only: bool,
 ) -> RunningChain {
     // Since `network_noise_key` is moved out below, use it to build the network identity ahead
     // of the network service starting.
     let network_identity =
         peer_id::PublicKey::Ed25519(*network_noise_key.libp2p_public_ed25519_key()).into_peer_id();
 
+    // Each service spawns its tasks within its own group, so that the services can be stopped
+    // one by one when the client shuts down.
+    let (network_tasks_tx, abort_network) = spawn_tasks_group(
+        &new_task_tx,
+        "network-service".to_owned(),
+        TaskPriority::Latency,
+    );
+    let (sync_tasks_tx, abort_sync) = spawn_tasks_group(
+        &new_task_tx,
+        "sync-service".to_owned(),
+        TaskPriority::Background,
+    );
+    let (runtime_tasks_tx, abort_runtime) = spawn_tasks_group(
+        &new_task_tx,
+        "runtime-service".to_owned(),
+        TaskPriority::Background,
+    );
+    let (transactions_tasks_tx, abort_transactions) = spawn_tasks_group(
+        &new_task_tx,
+        "transactions-service".to_owned(),
+        TaskPriority::Background,
+    );
+
     // The network service is responsible for connecting to the peer-to-peer network.
     let (network_service, mut network_event_receivers) =
         network_service::NetworkService::new(network_service::Config {
-            tasks_executor: Box::new({
-                let new_task_tx = new_task_tx.clone();
-                move |name, fut| new_task_tx.unbounded_send((name, fut)).unwrap()
+            tasks_executor: Box::new(move |name, priority, fut| {
+                network_tasks_tx
+                    .unbounded_send((name, priority, fut))
+                    .unwrap()
             }),
-            num_events_receivers: 1, // Configures the length of `network_event_receivers`
+            num_events_receivers: 2, // Configures the length of `network_event_receivers`
             noise_key: network_noise_key,
             chains: vec![network_service::ConfigChain {
                 log_name: log_name.clone(),
@@ -818,19 +1517,36 @@ async fn start_services(
                     }
                     list
                 },
+                // The finality engine of a chain never changes, and the one of the starting block
+                // can thus be used.
                 has_grandpa_protocol: matches!(
-                    genesis_chain_information.as_ref().finality,
+                    chain_information.as_ref().finality,
                     chain::chain_information::ChainInformationFinalityRef::Grandpa { .. }
                 ),
-                genesis_block_hash: genesis_chain_information
-                    .as_ref()
-                    .finalized_block_header
-                    .hash(),
+                genesis_block_hash: genesis_block_header.hash(),
                 best_block: (
                     chain_information.as_ref().finalized_block_header.number,
                     chain_information.as_ref().finalized_block_header.hash(),
                 ),
                 protocol_id: chain_spec.protocol_id().to_string(),
+                grandpa_warp_sync_server: match chain_information.as_ref().finality {
+                    chain::chain_information::ChainInformationFinalityRef::Grandpa {
+                        after_finalized_block_authorities_set_id,
+                        ..
+                    } => Some(warp_sync_server::Config {
+                        start_block_hash: chain_information.as_ref().finalized_block_header.hash(),
+                        start_block_number: chain_information
+                            .as_ref()
+                            .finalized_block_header
+                            .number,
+                        start_authorities_set_id: after_finalized_block_authorities_set_id,
+                        max_fragments_per_response: 32,
+                    }),
+                    _ => None,
+                },
+                // TODO: verify the signatures of the block announces of parachains
+                block_announce_validator: None,
+                reserved_only,
             }],
         })
         .await;
@@ -845,14 +1561,20 @@ async fn start_services(
             sync_service::SyncService::new(sync_service::Config {
                 log_name: log_name.clone(),
                 chain_information: chain_information.clone(),
-                tasks_executor: Box::new({
-                    let new_task_tx = new_task_tx.clone();
-                    move |name, fut| new_task_tx.unbounded_send((name, fut)).unwrap()
+                tasks_executor: Box::new(move |name, priority, fut| {
+                    sync_tasks_tx.unbounded_send((name, priority, fut)).unwrap()
                 }),
                 network_service: (network_service.clone(), 0),
                 network_events_receiver: network_event_receivers.pop().unwrap(),
+                max_non_finalized_fork_depth: MAX_NON_FINALIZED_FORK_DEPTH,
+                min_sources_per_block: NonZeroU32::new(MIN_SOURCES_PER_BLOCK).unwrap(),
+                banned_blocks: chain_spec.bad_blocks_hashes().copied().collect(),
+                fork_blocks: chain_spec
+                    .fork_blocks()
+                    .map(|(height, hash)| (height, *hash))
+                    .collect(),
                 parachain: Some(sync_service::ConfigParachain {
-                    parachain_id: chain_spec.relay_chain().unwrap().1,
+                    parachain_id: chain_spec.para_id().unwrap(),
                     relay_chain_sync: relay_chain.runtime_service.clone(),
                 }),
             })
@@ -863,16 +1585,15 @@ async fn start_services(
         // and allows performing runtime calls.
         let runtime_service = runtime_service::RuntimeService::new(runtime_service::Config {
             log_name: log_name.clone(),
-            tasks_executor: Box::new({
-                let new_task_tx = new_task_tx.clone();
-                move |name, fut| new_task_tx.unbounded_send((name, fut)).unwrap()
+            tasks_executor: Box::new(move |name, priority, fut| {
+                runtime_tasks_tx
+                    .unbounded_send((name, priority, fut))
+                    .unwrap()
             }),
             sync_service: sync_service.clone(),
             chain_spec: &chain_spec,
-            genesis_block_scale_encoded_header: genesis_chain_information
-                .as_ref()
-                .finalized_block_header
-                .scale_encoding_vec(),
+            genesis_block_scale_encoded_header: genesis_block_header.scale_encoding_vec(),
+            max_non_finalized_fork_depth: MAX_NON_FINALIZED_FORK_DEPTH,
         })
         .await;
 
@@ -887,12 +1608,18 @@ async fn start_services(
             sync_service::SyncService::new(sync_service::Config {
                 log_name: log_name.clone(),
                 chain_information: chain_information.clone(),
-                tasks_executor: Box::new({
-                    let new_task_tx = new_task_tx.clone();
-                    move |name, fut| new_task_tx.unbounded_send((name, fut)).unwrap()
+                tasks_executor: Box::new(move |name, priority, fut| {
+                    sync_tasks_tx.unbounded_send((name, priority, fut)).unwrap()
                 }),
                 network_service: (network_service.clone(), 0),
                 network_events_receiver: network_event_receivers.pop().unwrap(),
+                max_non_finalized_fork_depth: MAX_NON_FINALIZED_FORK_DEPTH,
+                min_sources_per_block: NonZeroU32::new(MIN_SOURCES_PER_BLOCK).unwrap(),
+                banned_blocks: chain_spec.bad_blocks_hashes().copied().collect(),
+                fork_blocks: chain_spec
+                    .fork_blocks()
+                    .map(|(height, hash)| (height, *hash))
+                    .collect(),
                 parachain: None,
             })
             .await,
@@ -902,16 +1629,15 @@ async fn start_services(
         // and allows performing runtime calls.
         let runtime_service = runtime_service::RuntimeService::new(runtime_service::Config {
             log_name: log_name.clone(),
-            tasks_executor: Box::new({
-                let new_task_tx = new_task_tx.clone();
-                move |name, fut| new_task_tx.unbounded_send((name, fut)).unwrap()
+            tasks_executor: Box::new(move |name, priority, fut| {
+                runtime_tasks_tx
+                    .unbounded_send((name, priority, fut))
+                    .unwrap()
             }),
             sync_service: sync_service.clone(),
             chain_spec: &chain_spec,
-            genesis_block_scale_encoded_header: genesis_chain_information
-                .as_ref()
-                .finalized_block_header
-                .scale_encoding_vec(),
+            genesis_block_scale_encoded_header: genesis_block_header.scale_encoding_vec(),
+            max_non_finalized_fork_depth: MAX_NON_FINALIZED_FORK_DEPTH,
         })
         .await;
 
@@ -925,14 +1651,17 @@ async fn start_services(
     let transactions_service = Arc::new(
         transactions_service::TransactionsService::new(transactions_service::Config {
             log_name,
-            tasks_executor: Box::new({
-                let new_task_tx = new_task_tx.clone();
-                move |name, fut| new_task_tx.unbounded_send((name, fut)).unwrap()
+            tasks_executor: Box::new(move |name, priority, fut| {
+                transactions_tasks_tx
+                    .unbounded_send((name, priority, fut))
+                    .unwrap()
             }),
             sync_service: sync_service.clone(),
             runtime_service: runtime_service.clone(),
             network_service: (network_service.clone(), 0),
+            network_events_receiver: network_event_receivers.pop().unwrap(),
             max_pending_transactions: NonZeroU32::new(64).unwrap(),
+            max_pending_transactions_bytes: NonZeroU32::new(4 * 1024 * 1024).unwrap(),
             max_concurrent_downloads: NonZeroU32::new(3).unwrap(),
             max_concurrent_validations: NonZeroU32::new(2).unwrap(),
         })
@@ -945,6 +1674,12 @@ async fn start_services(
         runtime_service,
         sync_service,
         transactions_service,
+        services_abort_handles: vec![
+            ("transactions", abort_transactions),
+            ("runtime", abort_runtime),
+            ("sync", abort_sync),
+            ("network", abort_network),
+        ],
     }
 }
 
@@ -0,0 +1,178 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Snapshot of the internal metrics of the client.
+//!
+//! See [`crate::Client::metrics`]. The snapshot can be turned into the text format of Prometheus
+//! with [`Metrics::to_prometheus`].
+
+use std::fmt::Write as _;
+
+/// Snapshot of the internal metrics of the client.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    /// Size in bytes of the memory of the WebAssembly virtual machine. Always 0 when the client
+    /// isn't compiled for WebAssembly.
+    pub memory_bytes: u64,
+    /// Number of background tasks currently alive.
+    pub num_tasks: u64,
+    /// Metrics of each chain whose services have finished initializing. Chains that share the
+    /// same services are only reported once.
+    pub chains: Vec<ChainMetrics>,
+}
+
+/// Metrics of a single chain. See [`Metrics::chains`].
+#[derive(Debug, Clone)]
+pub struct ChainMetrics {
+    /// Name of the chain used in the logs.
+    pub log_name: String,
+    /// Number of peers used to synchronize the chain.
+    pub num_peers: u64,
+    /// Height of the current best block.
+    pub best_block_number: u64,
+    /// Height of the current finalized block.
+    pub finalized_block_number: u64,
+    /// Highest best block height reported by the peers, if any.
+    pub highest_peer_block_number: Option<u64>,
+    /// See [`crate::runtime_service::Metrics`].
+    pub runtime: crate::runtime_service::Metrics,
+    /// Approximate memory used by the services of the chain.
+    pub memory: MemoryUsage,
+}
+
+/// Approximate number of bytes of memory used by the services of a chain.
+///
+/// These numbers are estimates and don't include the memory used by the allocator itself.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryUsage {
+    /// Runtimes kept in memory, including their code, their metadata, and the memory of their
+    /// virtual machine.
+    pub runtimes: u64,
+    /// Headers of the blocks tracked by the syncing and by the runtime service.
+    pub blocks: u64,
+    /// Caches of decoded storage and call proofs.
+    pub caches: u64,
+    /// Buffers of the connections open with peers.
+    pub network: u64,
+}
+
+impl MemoryUsage {
+    /// Returns the sum of all the fields.
+    pub fn total(&self) -> u64 {
+        self.runtimes + self.blocks + self.caches + self.network
+    }
+}
+
+impl Metrics {
+    /// Encodes the metrics in the text format of Prometheus.
+    ///
+    /// See <https://prometheus.io/docs/instrumenting/exposition_formats/>.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::with_capacity(2048);
+
+        write_header(
+            &mut out,
+            "smoldot_memory_bytes",
+            "gauge",
+            "Size of the memory",
+        );
+        let _ = writeln!(out, "smoldot_memory_bytes {}", self.memory_bytes);
+
+        write_header(
+            &mut out,
+            "smoldot_tasks",
+            "gauge",
+            "Number of background tasks",
+        );
+        let _ = writeln!(out, "smoldot_tasks {}", self.num_tasks);
+
+        let per_chain: [(&str, &str, &str, fn(&ChainMetrics) -> u64); 8] = [
+            ("smoldot_peers", "gauge", "Number of syncing peers", |c| {
+                c.num_peers
+            }),
+            (
+                "smoldot_best_block_number",
+                "gauge",
+                "Height of the best block",
+                |c| c.best_block_number,
+            ),
+            (
+                "smoldot_finalized_block_number",
+                "gauge",
+                "Height of the finalized block",
+                |c| c.finalized_block_number,
+            ),
+            (
+                "smoldot_sync_distance",
+                "gauge",
+                "Number of blocks between the best block and the best block of the peers",
+                |c| {
+                    c.highest_peer_block_number
+                        .map_or(0, |n| n.saturating_sub(c.best_block_number))
+                },
+            ),
+            (
+                "smoldot_runtime_downloads_started_total",
+                "counter",
+                "Number of runtime downloads started",
+                |c| c.runtime.runtime_downloads_started,
+            ),
+            (
+                "smoldot_runtime_downloads_failed_total",
+                "counter",
+                "Number of runtime downloads that have failed",
+                |c| c.runtime.runtime_downloads_failed,
+            ),
+            (
+                "smoldot_compiled_runtimes",
+                "gauge",
+                "Number of compiled runtimes kept in memory",
+                |c| c.runtime.compiled_runtimes,
+            ),
+            (
+                "smoldot_chain_memory_bytes",
+                "gauge",
+                "Approximate memory used by the services of the chain",
+                |c| c.memory.total(),
+            ),
+        ];
+
+        for (name, ty, help, value) in per_chain {
+            write_header(&mut out, name, ty, help);
+            for chain in &self.chains {
+                let _ = writeln!(
+                    out,
+                    "{}{{chain=\"{}\"}} {}",
+                    name,
+                    escape_label_value(&chain.log_name),
+                    value(chain)
+                );
+            }
+        }
+
+        out
+    }
+}
+
+fn write_header(out: &mut String, name: &str, ty: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, ty);
+}
+
+fn escape_label_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -38,25 +38,33 @@
 
 use crate::ffi;
 
-use core::{cmp, fmt, num::NonZeroUsize, pin::Pin, time::Duration};
+use core::{cmp, convert::TryFrom as _, fmt, num::NonZeroUsize, pin::Pin, time::Duration};
 use futures::{channel::mpsc, lock::Mutex, prelude::*};
 use smoldot::{
+    finality::grandpa::warp_sync_server,
     informant::HashDisplay,
     libp2p::{
         collection::{ConnectionError, HandshakeError},
         connection::{self, handshake},
         multiaddr::Multiaddr,
         peer_id::PeerId,
-        read_write::ReadWrite,
+        peers,
+        read_write::{InstrumentedReadWrite, ReadWrite},
     },
-    network::{protocol, service},
+    network::{peerset, protocol, reputation, service},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
 };
-use std::{collections::HashSet, sync::Arc};
 
 /// Configuration for a [`NetworkService`].
 pub struct Config {
-    /// Closure that spawns background tasks.
-    pub tasks_executor: Box<dyn FnMut(String, Pin<Box<dyn Future<Output = ()> + Send>>) + Send>,
+    /// Closure that spawns background tasks. The [`crate::TaskPriority`] is a hint indicating
+    /// how the task should be scheduled.
+    pub tasks_executor: Box<
+        dyn FnMut(String, crate::TaskPriority, Pin<Box<dyn Future<Output = ()> + Send>>) + Send,
+    >,
 
     /// Key to use for the encryption layer of all the connections. Gives the node its identity.
     pub noise_key: connection::NoiseKey,
@@ -92,6 +100,36 @@ pub struct ConfigChain {
 
     /// If true, the chain uses the GrandPa networking protocol.
     pub has_grandpa_protocol: bool,
+
+    /// If `Some`, the GrandPa warp sync requests sent by other nodes are answered, using the
+    /// blocks reported through [`NetworkService::report_grandpa_finalized_block`].
+    pub grandpa_warp_sync_server: Option<warp_sync_server::Config>,
+
+    /// If `Some`, called for each block announce received from a peer before it is reported as
+    /// an [`Event::BlockAnnounce`]. Can be used to perform verifications that the networking
+    /// can't perform by itself, such as verifying the signature of parachain block announces.
+    pub block_announce_validator: Option<BlockAnnounceValidator>,
+
+    /// If `true`, only the nodes of [`ConfigChain::bootstrap_nodes`] are connected to. Other
+    /// nodes trying to connect are refused, and no Kademlia discovery is performed.
+    pub reserved_only: bool,
+}
+
+/// See [`ConfigChain::block_announce_validator`].
+pub type BlockAnnounceValidator =
+    Box<dyn Fn(&PeerId, &service::EncodedBlockAnnounce) -> BlockAnnounceValidation + Send + Sync>;
+
+/// Outcome of a [`BlockAnnounceValidator`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BlockAnnounceValidation {
+    /// The block announce is reported as an [`Event::BlockAnnounce`].
+    Accept,
+    /// The block announce is discarded, but the peer that has sent it isn't at fault. For
+    /// example, the announced block might be irrelevant to the local node.
+    Discard,
+    /// The block announce is discarded, and the reputation of the peer that has sent it is
+    /// lowered.
+    Invalid,
 }
 
 pub struct NetworkService {
@@ -108,12 +146,24 @@ pub struct NetworkService {
     /// Names of the various chains the network service connects to. Used only for logging
     /// purposes.
     log_chain_names: Vec<String>,
+
+    /// For each chain, see [`ConfigChain::block_announce_validator`].
+    block_announce_validators: Vec<Option<BlockAnnounceValidator>>,
 }
 
 /// Fields of [`NetworkService`] behind a mutex.
 struct Guarded {
     /// See [`Config::tasks_executor`].
-    tasks_executor: Box<dyn FnMut(String, Pin<Box<dyn Future<Output = ()> + Send>>) + Send>,
+    tasks_executor: Box<
+        dyn FnMut(String, crate::TaskPriority, Pin<Box<dyn Future<Output = ()> + Send>>) + Send,
+    >,
+
+    /// For each chain, see [`ConfigChain::grandpa_warp_sync_server`].
+    grandpa_warp_sync_servers: Vec<Option<warp_sync_server::WarpSyncServer>>,
+
+    /// Response to the identify request sent to each connected peer, if any was received.
+    /// Entries are removed when the peer disconnects.
+    identified_peers: HashMap<PeerId, protocol::DecodedIdentifyResponse, fnv::FnvBuildHasher>,
 }
 
 impl NetworkService {
@@ -140,14 +190,23 @@ impl NetworkService {
         let mut known_nodes = Vec::new();
 
         let mut log_chain_names = Vec::with_capacity(num_chains);
+        let mut grandpa_warp_sync_servers = Vec::with_capacity(num_chains);
+        let mut block_announce_validators = Vec::with_capacity(num_chains);
+        let mut reserved_only_chains = Vec::with_capacity(num_chains);
 
         for chain in config.chains {
             chains.push(service::ChainConfig {
                 bootstrap_nodes: (known_nodes.len()
                     ..(known_nodes.len() + chain.bootstrap_nodes.len()))
                     .collect(),
-                in_slots: 3,
-                out_slots: 4,
+                // Light clients can't be of any help to the local node. No slot is allocated
+                // to them.
+                slots: peerset::SlotsConfig {
+                    in_full: 3,
+                    in_light: 0,
+                    out_full: 4,
+                    out_light: 0,
+                },
                 grandpa_protocol_config: if chain.has_grandpa_protocol {
                     // TODO: dummy values
                     Some(service::GrandpaState {
@@ -163,15 +222,32 @@ impl NetworkService {
                 best_number: chain.best_block.0,
                 genesis_hash: chain.genesis_block_hash,
                 role: protocol::Role::Light,
+                // The storage of blocks isn't available locally.
+                serve_light_requests: false,
+                serve_grandpa_warp_sync: chain.grandpa_warp_sync_server.is_some(),
+                serve_kademlia_providers: false,
+                max_response_sizes: Default::default(),
+                collation_protocol: false,
+                reserved_only: chain.reserved_only,
             });
 
+            grandpa_warp_sync_servers.push(
+                chain
+                    .grandpa_warp_sync_server
+                    .map(warp_sync_server::WarpSyncServer::new),
+            );
+
             known_nodes.extend(chain.bootstrap_nodes);
             log_chain_names.push(chain.log_name);
+            block_announce_validators.push(chain.block_announce_validator);
+            reserved_only_chains.push(chain.reserved_only);
         }
 
         let network_service = Arc::new(NetworkService {
             guarded: Mutex::new(Guarded {
                 tasks_executor: config.tasks_executor,
+                grandpa_warp_sync_servers,
+                identified_peers: HashMap::default(),
             }),
             network: service::ChainNetwork::new(service::Config {
                 chains,
@@ -180,18 +256,41 @@ impl NetworkService {
                 peers_capacity: 100,       // TODO: ?
                 noise_key: config.noise_key,
                 handshake_timeout: Duration::from_secs(8),
+                ping_interval: Duration::from_secs(20),
+                ping_timeout: Duration::from_secs(10),
+                idle_connection_timeout: Some(Duration::from_secs(30)),
+                max_connections: 64,
+                max_incoming_connections_per_ip: 4,
+                max_simultaneous_dials: NonZeroUsize::new(8).unwrap(),
+                dial_backoff_initial: Duration::from_secs(2),
+                dial_backoff_max: Duration::from_secs(120),
                 // TODO: we use an abnormally large channel in order to by pass https://github.com/paritytech/smoldot/issues/615
                 // once the issue is solved, this should be restored to a smaller value, such as 16
                 pending_api_events_buffer_size: NonZeroUsize::new(2048).unwrap(),
                 randomness_seed: rand::random(),
+                reputation: reputation::Config {
+                    randomness_seed: rand::random(),
+                    disconnect_threshold: -200,
+                    ban_threshold: -500,
+                    ban_duration: Duration::from_secs(5 * 60),
+                    recovery_per_second: 2,
+                    max_peers: 1024,
+                },
+                // The light client can run on constrained devices. These values are kept small
+                // in order to bound the memory used by large responses such as storage proofs.
+                request_receive_window: 256 * 1024,
+                notifications_receive_window: 256 * 1024,
+                max_pending_response_bytes: 32 * 1024 * 1024,
             }),
             important_nodes,
             log_chain_names,
+            block_announce_validators,
         });
 
         // Spawn a task pulling events from the network and transmitting them to the event senders.
         (network_service.guarded.try_lock().unwrap().tasks_executor)(
             "network-events".into(),
+            crate::TaskPriority::Latency,
             Box::pin({
                 // TODO: keeping a Weak here doesn't really work to shut down tasks
                 let network_service = Arc::downgrade(&network_service);
@@ -212,12 +311,64 @@ impl NetworkService {
                             {
                                 service::Event::Connected(peer_id) => {
                                     log::info!(target: "network", "Connected to {}", peer_id);
+
+                                    // Ask the remote for its identity, in order to learn its
+                                    // listen addresses and its agent version.
+                                    let network_service2 = network_service.clone();
+                                    (network_service.guarded.lock().await.tasks_executor)(
+                                        format!("identify-{}", peer_id),
+                                        crate::TaskPriority::Latency,
+                                        Box::pin(async move {
+                                            match network_service2
+                                                .network
+                                                .identify_request(ffi::Instant::now(), &peer_id)
+                                                .await
+                                            {
+                                                Ok(response) => {
+                                                    log::debug!(
+                                                        target: "network",
+                                                        "Connection({}) => Identified(agent_version={:?}, listen_addrs={})",
+                                                        peer_id,
+                                                        response.agent_version,
+                                                        response.listen_addrs.len()
+                                                    );
+
+                                                    // The peer might have disconnected while the
+                                                    // request was in progress.
+                                                    let mut guarded =
+                                                        network_service2.guarded.lock().await;
+                                                    if network_service2
+                                                        .network
+                                                        .peers_list()
+                                                        .await
+                                                        .any(|p| p == peer_id)
+                                                    {
+                                                        guarded
+                                                            .identified_peers
+                                                            .insert(peer_id, response);
+                                                    }
+                                                }
+                                                Err(error) => log::debug!(
+                                                    target: "network",
+                                                    "Connection({}) => IdentifyError({})",
+                                                    peer_id,
+                                                    error
+                                                ),
+                                            }
+                                        }),
+                                    );
                                 }
                                 service::Event::Disconnected {
                                     peer_id,
                                     chain_indices,
                                 } => {
                                     log::info!(target: "network", "Disconnected from {} (chains: {:?})", peer_id, chain_indices);
+                                    network_service
+                                        .guarded
+                                        .lock()
+                                        .await
+                                        .identified_peers
+                                        .remove(&peer_id);
                                     if !chain_indices.is_empty() {
                                         // TODO: properly implement when multiple chains
                                         if chain_indices.len() == 1 {
@@ -251,6 +402,35 @@ impl NetworkService {
                                         HashDisplay(&announce.decode().header.hash()),
                                         announce.decode().is_best
                                     );
+
+                                    let validation = network_service.block_announce_validators
+                                        [chain_index]
+                                        .as_ref()
+                                        .map_or(BlockAnnounceValidation::Accept, |validator| {
+                                            validator(&peer_id, &announce)
+                                        });
+                                    match validation {
+                                        BlockAnnounceValidation::Accept => {}
+                                        BlockAnnounceValidation::Discard => {
+                                            log::debug!(
+                                                target: "network",
+                                                "Connection({}, {}) => BlockAnnounce discarded",
+                                                peer_id,
+                                                &network_service.log_chain_names[chain_index],
+                                            );
+                                            continue;
+                                        }
+                                        BlockAnnounceValidation::Invalid => {
+                                            network_service
+                                                .report_peer(
+                                                    &peer_id,
+                                                    reputation::Penalty::InvalidBlockAnnounce,
+                                                )
+                                                .await;
+                                            continue;
+                                        }
+                                    }
+
                                     break Event::BlockAnnounce {
                                         chain_index,
                                         peer_id,
@@ -315,21 +495,123 @@ impl NetworkService {
                                     );
                                     request.respond("smoldot").await;
                                 }
+                                service::Event::GrandpaWarpSyncRequestIn {
+                                    peer_id,
+                                    chain_index,
+                                    request,
+                                } => {
+                                    log::debug!(
+                                        target: "network",
+                                        "Connection({}, {}) => GrandpaWarpSyncRequest(begin: {})",
+                                        peer_id,
+                                        &network_service.log_chain_names[chain_index],
+                                        HashDisplay(request.begin_hash()),
+                                    );
+
+                                    let guarded = network_service.guarded.lock().await;
+                                    let response = guarded.grandpa_warp_sync_servers[chain_index]
+                                        .as_ref()
+                                        .and_then(|server| {
+                                            server.build_response(request.begin_hash())
+                                        });
+                                    match response {
+                                        Some(response) => {
+                                            request
+                                                .respond(
+                                                    response.fragments.iter().copied(),
+                                                    response.is_finished,
+                                                )
+                                                .await
+                                        }
+                                        None => request.refuse().await,
+                                    }
+                                }
+                                // Can't happen, as `serve_light_requests` is always `false`.
+                                service::Event::StorageProofRequestIn { request, .. } => {
+                                    request.refuse().await;
+                                }
+                                service::Event::CallProofRequestIn { request, .. } => {
+                                    request.refuse().await;
+                                }
                                 service::Event::GrandpaCommitMessage {
                                     chain_index,
+                                    peer_id,
                                     message,
                                 } => {
                                     log::debug!(
                                         target: "network",
-                                        "Connection(?, {}) => GrandpaCommitMessage({})",
+                                        "Connection({}, {}) => GrandpaCommitMessage({})",
+                                        peer_id,
                                         &network_service.log_chain_names[chain_index],
                                         HashDisplay(message.decode().message.target_hash),
                                     );
                                     break Event::GrandpaCommitMessage {
                                         chain_index,
+                                        peer_id,
                                         message,
                                     };
                                 }
+                                service::Event::GrandpaNeighborPacket {
+                                    chain_index,
+                                    peer_id,
+                                    state,
+                                } => {
+                                    log::debug!(
+                                        target: "network",
+                                        "Connection({}, {}) => GrandpaNeighborPacket(round_number={}, set_id={}, commit_finalized_height={})",
+                                        peer_id,
+                                        &network_service.log_chain_names[chain_index],
+                                        state.round_number,
+                                        state.set_id,
+                                        state.commit_finalized_height,
+                                    );
+                                    break Event::GrandpaNeighborPacket {
+                                        chain_index,
+                                        peer_id,
+                                        state,
+                                    };
+                                }
+                                service::Event::GrandpaCatchUp {
+                                    chain_index,
+                                    peer_id,
+                                    catch_up,
+                                } => {
+                                    log::debug!(
+                                        target: "network",
+                                        "Connection({}, {}) => GrandpaCatchUp(set_id={}, round_number={}, base={})",
+                                        peer_id,
+                                        &network_service.log_chain_names[chain_index],
+                                        catch_up.decode().set_id,
+                                        catch_up.decode().round_number,
+                                        HashDisplay(catch_up.decode().base_hash),
+                                    );
+                                    break Event::GrandpaCatchUp {
+                                        chain_index,
+                                        peer_id,
+                                        catch_up,
+                                    };
+                                }
+                                service::Event::CollationMessage { .. } => {
+                                    // Can't happen, as `collation_protocol` is always `false`.
+                                }
+                                service::Event::Transactions {
+                                    chain_index,
+                                    peer_id,
+                                    transactions,
+                                } => {
+                                    log::debug!(
+                                        target: "network",
+                                        "Connection({}, {}) => Transactions(num={})",
+                                        peer_id,
+                                        &network_service.log_chain_names[chain_index],
+                                        transactions.decode().len(),
+                                    );
+                                    break Event::Transactions {
+                                        chain_index,
+                                        peer_id,
+                                        transactions,
+                                    };
+                                }
                                 service::Event::ProtocolError { peer_id, error } => {
                                     // TODO: handle properly?
                                     log::warn!(
@@ -360,6 +642,7 @@ impl NetworkService {
         // TODO: spawn multiple of these and tweak the `connection_task`, so that we limit ourselves to N simultaneous connection openings, to please some ISPs
         (network_service.guarded.try_lock().unwrap().tasks_executor)(
             "connections-open".into(),
+            crate::TaskPriority::Latency,
             Box::pin({
                 // TODO: keeping a Weak here doesn't really work to shut down tasks
                 let network_service = Arc::downgrade(&network_service);
@@ -372,10 +655,27 @@ impl NetworkService {
                             }
                         };
 
-                        let start_connect = network_service
+                        // `next_start_connect` isn't woken up when the back-off delay of an
+                        // address expires. Start it again when that happens.
+                        let now = ffi::Instant::now();
+                        let backoff_expiration = network_service
                             .network
-                            .next_start_connect(ffi::Instant::now())
+                            .next_dial_backoff_expiration(&now)
                             .await;
+                        let start_connect = {
+                            let start_connect =
+                                network_service.network.next_start_connect(now).fuse();
+                            let backoff_expiration = match backoff_expiration {
+                                Some(when) => ffi::Delay::new_at(when).left_future(),
+                                None => future::pending().right_future(),
+                            }
+                            .fuse();
+                            futures::pin_mut!(start_connect, backoff_expiration);
+                            futures::select! {
+                                start_connect = start_connect => start_connect,
+                                () = backoff_expiration => continue,
+                            }
+                        };
 
                         let is_important_peer = network_service
                             .important_nodes
@@ -393,6 +693,7 @@ impl NetworkService {
                         let network_service2 = network_service.clone();
                         (network_service.guarded.lock().await.tasks_executor)(
                             format!("connection-{}", start_connect.expected_peer_id),
+                            crate::TaskPriority::Latency,
                             Box::pin({
                                 connection_task(
                                     socket,
@@ -410,10 +711,64 @@ impl NetworkService {
             }),
         );
 
+        // Spawn a task that periodically checks the health of the bootnodes, and prints a
+        // warning for each chain whose bootnodes are all unusable.
+        (network_service.guarded.try_lock().unwrap().tasks_executor)(
+            "bootnodes-health".into(),
+            crate::TaskPriority::Background,
+            Box::pin({
+                let network_service = Arc::downgrade(&network_service);
+                async move {
+                    loop {
+                        ffi::Delay::new(Duration::from_secs(60)).await;
+
+                        let network_service = match network_service.upgrade() {
+                            Some(ns) => ns,
+                            None => return,
+                        };
+
+                        for chain_index in 0..network_service.network.num_chains() {
+                            let health =
+                                network_service.network.bootnodes_health(chain_index).await;
+
+                            let all_unusable = !health.is_empty()
+                                && health.iter().all(|(_, h)| {
+                                    matches!(
+                                        h,
+                                        service::BootnodeHealth::Unreachable { .. }
+                                            | service::BootnodeHealth::WrongGenesis
+                                    )
+                                });
+
+                            if all_unusable {
+                                log::warn!(
+                                    target: "connections",
+                                    "None of the bootnodes of {} are usable: {}",
+                                    &network_service.log_chain_names[chain_index],
+                                    health
+                                        .iter()
+                                        .map(|(peer_id, h)| format!("{} ({:?})", peer_id, h))
+                                        .collect::<Vec<_>>()
+                                        .join(", ")
+                                );
+                            }
+                        }
+                    }
+                }
+            }),
+        );
+
         // Spawn tasks dedicated to the Kademlia discovery.
+        // Chains in reserved-only mode only ever connect to their bootstrap nodes, and thus don't
+        // need any discovery.
         for chain_index in 0..num_chains {
+            if reserved_only_chains[chain_index] {
+                continue;
+            }
+
             (network_service.guarded.try_lock().unwrap().tasks_executor)(
                 "discovery".into(),
+                crate::TaskPriority::Background,
                 Box::pin({
                     // TODO: keeping a Weak here doesn't really work to shut down tasks
                     let network_service = Arc::downgrade(&network_service);
@@ -556,6 +911,74 @@ impl NetworkService {
             .await
     }
 
+    /// Sends a GrandPa catch-up request to the given peer. The response, if any, is later
+    /// reported as an [`Event::GrandpaCatchUp`].
+    pub async fn send_grandpa_catch_up_request(
+        &self,
+        chain_index: usize,
+        target: &PeerId,
+        request: protocol::CatchUpRequest,
+    ) {
+        log::debug!(
+            target: "network",
+            "Connection({}, {}) <= GrandpaCatchUpRequest(round_number={}, set_id={})",
+            target,
+            &self.log_chain_names[chain_index],
+            request.round_number,
+            request.set_id,
+        );
+
+        if let Err(err) = self
+            .network
+            .send_grandpa_catch_up_request(target, chain_index, request)
+            .await
+        {
+            log::debug!(
+                target: "network",
+                "Connection({}, {}) => GrandpaCatchUpRequestError({})",
+                target,
+                &self.log_chain_names[chain_index],
+                err,
+            );
+        }
+    }
+
+    /// Reports a block that has been finalized, alongside with the GrandPa justification that
+    /// proves its finality, if any. Used in order to answer the GrandPa warp sync requests of
+    /// other nodes.
+    ///
+    /// `authorities_set_id` must be the identifier of the GrandPa authorities set that must
+    /// finalize the children of this block, if known. All the finalized blocks that change the
+    /// list of authorities must be reported, even if this identifier isn't known.
+    ///
+    /// Has no effect if [`ConfigChain::grandpa_warp_sync_server`] was `None` for this chain.
+    pub async fn report_grandpa_finalized_block(
+        &self,
+        chain_index: usize,
+        scale_encoded_header: Vec<u8>,
+        scale_encoded_justification: Option<Vec<u8>>,
+        authorities_set_id: Option<u64>,
+    ) {
+        let mut guarded = self.guarded.lock().await;
+        let server = match &mut guarded.grandpa_warp_sync_servers[chain_index] {
+            Some(s) => s,
+            None => return,
+        };
+
+        if let Err(err) = server.inject_finalized(
+            scale_encoded_header,
+            scale_encoded_justification,
+            authorities_set_id,
+        ) {
+            log::warn!(
+                target: "network",
+                "Failed to report finalized block to the warp sync server of {}: {}",
+                &self.log_chain_names[chain_index],
+                err
+            );
+        }
+    }
+
     /// Sends a storage proof request to the given peer.
     // TODO: more docs
     pub async fn storage_proof_request(
@@ -577,6 +1000,18 @@ impl NetworkService {
             .storage_proof_request(ffi::Instant::now(), &target, chain_index, config)
             .await;
 
+        if let Err(err) = &result {
+            let penalty = match err {
+                service::StorageProofRequestError::Request(err) => request_error_penalty(err),
+                service::StorageProofRequestError::Decode(_) => {
+                    Some(reputation::Penalty::ProtocolViolation)
+                }
+            };
+            if let Some(penalty) = penalty {
+                self.report_peer(&target, penalty).await;
+            }
+        }
+
         log::debug!(
             target: "network",
             "Connection({}) => StorageProofRequest({:?})",
@@ -610,6 +1045,18 @@ impl NetworkService {
             .call_proof_request(ffi::Instant::now(), &target, chain_index, config)
             .await;
 
+        if let Err(err) = &result {
+            let penalty = match err {
+                service::CallProofRequestError::Request(err) => request_error_penalty(err),
+                service::CallProofRequestError::Decode(_) => {
+                    Some(reputation::Penalty::ProtocolViolation)
+                }
+            };
+            if let Some(penalty) = penalty {
+                self.report_peer(&target, penalty).await;
+            }
+        }
+
         log::debug!(
             target: "network",
             "Connection({}) => CallProofRequest({:?})",
@@ -657,8 +1104,77 @@ impl NetworkService {
     pub async fn peers_list(&self) -> impl Iterator<Item = PeerId> {
         self.network.peers_list().await
     }
+
+    /// Returns the list of connected peers whose identify request has succeeded, and the response
+    /// to this request.
+    pub async fn identified_peers(&self) -> Vec<(PeerId, protocol::DecodedIdentifyResponse)> {
+        self.guarded
+            .lock()
+            .await
+            .identified_peers
+            .iter()
+            .map(|(peer_id, response)| (peer_id.clone(), response.clone()))
+            .collect()
+    }
+
+    /// Returns a rough estimate of the number of bytes used by the buffers of the connections
+    /// currently open.
+    pub async fn connections_memory_usage(&self) -> u64 {
+        u64::try_from(self.peers_list().await.count()).unwrap() * CONNECTION_MEMORY_ESTIMATE
+    }
+
+    /// Reports a misbehaviour of the given peer, lowering its reputation. The peer is
+    /// automatically disconnected or banned if its reputation becomes too low.
+    pub async fn report_peer(&self, peer_id: &PeerId, penalty: reputation::Penalty) {
+        let outcome = self
+            .network
+            .report_peer(ffi::Instant::now(), peer_id, penalty)
+            .await;
+
+        match outcome {
+            reputation::ReportOutcome::Nothing => {
+                log::debug!(target: "network", "Reputation({}) <= {:?}", peer_id, penalty);
+            }
+            reputation::ReportOutcome::Disconnect => {
+                log::debug!(
+                    target: "network",
+                    "Reputation({}) <= {:?}; disconnecting",
+                    peer_id,
+                    penalty
+                );
+            }
+            reputation::ReportOutcome::Ban => {
+                log::warn!(
+                    target: "network",
+                    "Banning {} because of misbehaviour ({:?})",
+                    peer_id,
+                    penalty
+                );
+            }
+        }
+    }
+
+    /// Sorts the given list of peers from the lowest average ping time to the highest. Peers
+    /// whose ping time isn't known yet are put at the end of the list.
+    pub async fn sort_by_ping_time(&self, peers: impl Iterator<Item = PeerId>) -> Vec<PeerId> {
+        let mut with_ping_times = Vec::with_capacity(peers.size_hint().0);
+        for peer_id in peers {
+            let ping_time = self.network.peer_average_ping_time(&peer_id).await;
+            with_ping_times.push((peer_id, ping_time));
+        }
+
+        with_ping_times.sort_by_key(|(_, ping_time)| (ping_time.is_none(), *ping_time));
+        with_ping_times
+            .into_iter()
+            .map(|(peer_id, _)| peer_id)
+            .collect()
+    }
 }
 
+/// Rough estimate of the number of bytes used by a single connection. Dominated by the
+/// buffers of the encryption layer (up to 64kiB) and the buffer used to send out data.
+const CONNECTION_MEMORY_ESTIMATE: u64 = 80 * 1024;
+
 /// Event that can happen on the network service.
 #[derive(Debug, Clone)]
 pub enum Event {
@@ -680,9 +1196,46 @@ pub enum Event {
     },
     /// Received a GrandPa commit message from the network.
     GrandpaCommitMessage {
+        peer_id: PeerId,
         chain_index: usize,
         message: service::EncodedGrandpaCommitMessage,
     },
+    /// Received a GrandPa neighbor packet from the network.
+    GrandpaNeighborPacket {
+        peer_id: PeerId,
+        chain_index: usize,
+        state: service::GrandpaState,
+    },
+    /// Received a GrandPa catch-up message from the network.
+    GrandpaCatchUp {
+        peer_id: PeerId,
+        chain_index: usize,
+        catch_up: service::EncodedGrandpaCatchUp,
+    },
+    /// Received a list of transactions gossiped by a peer. The transactions haven't been
+    /// validated.
+    ///
+    /// If the validation of one of these transactions fails, the peer should be reported using
+    /// [`NetworkService::report_peer`] and [`reputation::Penalty::InvalidTransaction`].
+    Transactions {
+        peer_id: PeerId,
+        chain_index: usize,
+        transactions: service::EncodedTransactions,
+    },
+}
+
+/// Returns the penalty to apply to a peer that has failed to answer a request with the given
+/// error, if any.
+fn request_error_penalty(err: &peers::RequestError) -> Option<reputation::Penalty> {
+    match err {
+        peers::RequestError::Connection(connection::established::RequestError::Timeout) => {
+            Some(reputation::Penalty::RequestTimeout)
+        }
+        peers::RequestError::Connection(
+            connection::established::RequestError::ResponseLebError(_),
+        ) => Some(reputation::Penalty::ProtocolViolation),
+        _ => None,
+    }
 }
 
 /// Asynchronous task managing a specific connection.
@@ -765,6 +1318,9 @@ async fn connection_task(
 
     let mut write_buffer = vec![0; 4096];
 
+    // Statistics about the connection, printed when it closes.
+    let mut statistics = InstrumentedReadWrite::new();
+
     loop {
         let now = ffi::Instant::now();
 
@@ -780,11 +1336,14 @@ async fn connection_task(
             wake_up_future: None,
         };
 
-        match network_service
+        statistics.processing_start(&read_write);
+        let result = network_service
             .network
             .read_write(id, &mut read_write)
-            .await
-        {
+            .await;
+        statistics.processing_end(&read_write);
+
+        match result {
             Ok(rw) => rw,
             Err(err) if is_important_peer => {
                 log::warn!(
@@ -816,13 +1375,19 @@ async fn connection_task(
                 return;
             }
             Err(err) => {
-                log::debug!(target: "connections", "Connection({:?}, {}) => Closed: {}", id, expected_peer_id, err);
+                log::debug!(
+                    target: "connections", "Connection({:?}, {}) => Closed: {} ({:?})",
+                    id, expected_peer_id, err, statistics.statistics()
+                );
                 return;
             }
         };
 
         if read_write.is_dead() {
-            log::debug!(target: "connections", "Connection({:?}, {}) => Closed gracefully", id, expected_peer_id);
+            log::debug!(
+                target: "connections", "Connection({:?}, {}) => Closed gracefully ({:?})",
+                id, expected_peer_id, statistics.statistics()
+            );
             return;
         }
 
@@ -0,0 +1,232 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Offchain storage of a chain.
+//!
+//! The offchain storage is a key-value store whose content is controlled by the offchain workers,
+//! by the runtime through the offchain indexing host functions, and by the
+//! `offchain_localStorageGet` and `offchain_localStorageSet` JSON-RPC functions. It is not part
+//! of the state of the chain. The kinds of storage are the ones of the offchain host
+//! functions, see [`OffchainStorageKind`].
+//!
+//! The content of the storage is kept in memory. Every modification is reported to a callback
+//! passed at initialization, so that the storage can be persisted by the upper layer and later
+//! restored with [`OffchainStorage::load`].
+//!
+//! The total size of the keys and values stored is capped, in order to prevent the users of a
+//! chain from using up too much memory within the client.
+
+use smoldot::executor::host::OffchainStorageKind;
+use std::collections::HashMap;
+
+/// See the module-level documentation.
+pub struct OffchainStorage {
+    /// Content of the storage. Each kind of storage is a separate namespace.
+    entries: HashMap<(OffchainStorageKind, Vec<u8>), Vec<u8>, fnv::FnvBuildHasher>,
+
+    /// Sum of the lengths of all the keys and values in [`OffchainStorage::entries`].
+    size_bytes: usize,
+
+    /// Maximum value of [`OffchainStorage::size_bytes`].
+    max_size_bytes: usize,
+
+    /// Called whenever the content of the storage is modified. The value is `None` if the entry
+    /// has been removed.
+    on_change: Box<dyn FnMut(OffchainStorageKind, &[u8], Option<&[u8]>) + Send>,
+}
+
+impl OffchainStorage {
+    /// Creates a new empty storage.
+    ///
+    /// `on_change` is called whenever the content of the storage is modified, except by
+    /// [`OffchainStorage::load`].
+    pub fn new(
+        max_size_bytes: usize,
+        on_change: Box<dyn FnMut(OffchainStorageKind, &[u8], Option<&[u8]>) + Send>,
+    ) -> Self {
+        OffchainStorage {
+            entries: HashMap::with_capacity_and_hasher(0, Default::default()),
+            size_bytes: 0,
+            max_size_bytes,
+            on_change,
+        }
+    }
+
+    /// Inserts an entry that has previously been reported through the callback passed to
+    /// [`OffchainStorage::new`], without reporting it again.
+    pub fn load(
+        &mut self,
+        kind: OffchainStorageKind,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    ) -> Result<(), QuotaExceeded> {
+        self.insert(kind, key, value)
+    }
+
+    /// Returns the value associated to the given key, if any.
+    pub fn get(&self, kind: OffchainStorageKind, key: &[u8]) -> Option<&[u8]> {
+        // TODO: the key is cloned because `HashMap` can't be queried with a borrowed tuple
+        self.entries
+            .get(&(kind, key.to_vec()))
+            .map(|value| &value[..])
+    }
+
+    /// Sets the value associated to the given key, or removes the entry if `value` is `None`.
+    ///
+    /// On error, the storage is left untouched.
+    pub fn set(
+        &mut self,
+        kind: OffchainStorageKind,
+        key: &[u8],
+        value: Option<&[u8]>,
+    ) -> Result<(), QuotaExceeded> {
+        match value {
+            Some(value) => self.insert(kind, key.to_vec(), value.to_vec())?,
+            None => {
+                if let Some(previous) = self.entries.remove(&(kind, key.to_vec())) {
+                    self.size_bytes -= key.len() + previous.len();
+                }
+            }
+        }
+
+        (self.on_change)(kind, key, value);
+        Ok(())
+    }
+
+    fn insert(
+        &mut self,
+        kind: OffchainStorageKind,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    ) -> Result<(), QuotaExceeded> {
+        let previous_size = self
+            .entries
+            .get(&(kind, key.clone()))
+            .map_or(0, |previous| key.len() + previous.len());
+        let new_size_bytes = self.size_bytes - previous_size + key.len() + value.len();
+        if new_size_bytes > self.max_size_bytes {
+            return Err(QuotaExceeded);
+        }
+
+        self.size_bytes = new_size_bytes;
+        self.entries.insert((kind, key), value);
+        Ok(())
+    }
+}
+
+/// Error potentially returned when modifying an [`OffchainStorage`].
+#[derive(Debug, derive_more::Display)]
+#[display(fmt = "Offchain storage quota exceeded")]
+pub struct QuotaExceeded;
+
+#[cfg(test)]
+mod tests {
+    use super::{OffchainStorage, OffchainStorageKind};
+    use std::sync::{Arc, Mutex};
+
+    fn storage(max_size_bytes: usize) -> (OffchainStorage, Arc<Mutex<usize>>) {
+        let num_changes = Arc::new(Mutex::new(0));
+        let storage = OffchainStorage::new(max_size_bytes, {
+            let num_changes = num_changes.clone();
+            Box::new(move |_, _, _| *num_changes.lock().unwrap() += 1)
+        });
+        (storage, num_changes)
+    }
+
+    #[test]
+    fn set_within_quota() {
+        let (mut storage, num_changes) = storage(8);
+        storage
+            .set(OffchainStorageKind::Persistent, b"foo", Some(b"bar"))
+            .unwrap();
+        assert_eq!(
+            storage.get(OffchainStorageKind::Persistent, b"foo"),
+            Some(&b"bar"[..])
+        );
+        assert_eq!(storage.get(OffchainStorageKind::Local, b"foo"), None);
+        assert_eq!(storage.size_bytes, 6);
+        assert_eq!(*num_changes.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn set_exceeding_quota() {
+        let (mut storage, num_changes) = storage(8);
+        storage
+            .set(OffchainStorageKind::Persistent, b"foo", Some(b"bar"))
+            .unwrap();
+        assert!(storage
+            .set(OffchainStorageKind::Local, b"foo", Some(b"bar"))
+            .is_err());
+        assert_eq!(storage.get(OffchainStorageKind::Local, b"foo"), None);
+        assert_eq!(storage.size_bytes, 6);
+        assert_eq!(*num_changes.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn overwrite_accounts_previous_value() {
+        let (mut storage, _) = storage(8);
+        storage
+            .set(OffchainStorageKind::Persistent, b"foo", Some(b"bar"))
+            .unwrap();
+        storage
+            .set(OffchainStorageKind::Persistent, b"foo", Some(b"bazqu"))
+            .unwrap();
+        assert_eq!(storage.size_bytes, 8);
+        assert!(storage
+            .set(OffchainStorageKind::Persistent, b"foo", Some(b"bazqux"))
+            .is_err());
+        assert_eq!(
+            storage.get(OffchainStorageKind::Persistent, b"foo"),
+            Some(&b"bazqu"[..])
+        );
+        assert_eq!(storage.size_bytes, 8);
+    }
+
+    #[test]
+    fn removal_frees_space() {
+        let (mut storage, num_changes) = storage(8);
+        storage
+            .set(OffchainStorageKind::Persistent, b"foo", Some(b"bar"))
+            .unwrap();
+        storage
+            .set(OffchainStorageKind::Persistent, b"foo", None)
+            .unwrap();
+        assert_eq!(storage.size_bytes, 0);
+        assert_eq!(storage.get(OffchainStorageKind::Persistent, b"foo"), None);
+        assert_eq!(*num_changes.lock().unwrap(), 2);
+        storage
+            .set(OffchainStorageKind::Local, b"foo", Some(b"bar"))
+            .unwrap();
+    }
+
+    #[test]
+    fn load_respects_quota_and_doesnt_report() {
+        let (mut storage, num_changes) = storage(8);
+        storage
+            .load(
+                OffchainStorageKind::Persistent,
+                b"foo".to_vec(),
+                b"bar".to_vec(),
+            )
+            .unwrap();
+        assert!(storage
+            .load(OffchainStorageKind::Local, b"foo".to_vec(), b"bar".to_vec())
+            .is_err());
+        assert_eq!(storage.size_bytes, 6);
+        assert_eq!(*num_changes.lock().unwrap(), 0);
+    }
+}
@@ -44,11 +44,11 @@
 
 use crate::{
     ffi, lossy_channel,
-    sync_service::{self, StorageQueryError},
+    sync_service::{self, StorageQueryError, SyncStatus},
 };
 
 use futures::{
-    channel::mpsc,
+    channel::{mpsc, oneshot},
     lock::{Mutex, MutexGuard},
     prelude::*,
 };
@@ -58,13 +58,22 @@ use smoldot::{
     metadata,
     network::protocol,
     sync::download_tree,
-    trie::{self, proof_verify},
+    trie::{proof_decode, proof_verify},
+};
+use std::{
+    convert::TryFrom as _,
+    iter, mem,
+    pin::Pin,
+    sync::{atomic, Arc},
 };
-use std::{iter, mem, pin::Pin, sync::Arc};
 
 pub use crate::lossy_channel::Receiver as NotificationsReceiver;
 pub use smoldot::sync::download_tree::RuntimeError;
 
+/// Maximum size, in bytes, of the memory of the runtimes. Protects the browser tab from runtimes
+/// that would allocate an unreasonable amount of memory.
+const MAX_RUNTIME_MEMORY_SIZE: u32 = 256 * 1024 * 1024;
+
 /// Configuration for a runtime service.
 pub struct Config<'a> {
     /// Name of the chain, for logging purposes.
@@ -73,8 +82,11 @@ pub struct Config<'a> {
     /// >           have been filtered out from this name.
     pub log_name: String,
 
-    /// Closure that spawns background tasks.
-    pub tasks_executor: Box<dyn FnMut(String, Pin<Box<dyn Future<Output = ()> + Send>>) + Send>,
+    /// Closure that spawns background tasks. The [`crate::TaskPriority`] is a hint indicating
+    /// how the task should be scheduled.
+    pub tasks_executor: Box<
+        dyn FnMut(String, crate::TaskPriority, Pin<Box<dyn Future<Output = ()> + Send>>) + Send,
+    >,
 
     /// Service responsible for synchronizing the chain.
     pub sync_service: Arc<sync_service::SyncService>,
@@ -90,6 +102,14 @@ pub struct Config<'a> {
     /// >           expensive. We prefer to require this value from the upper layer instead, as
     /// >           it is most likely needed anyway.
     pub genesis_block_scale_encoded_header: Vec<u8>,
+
+    /// Maximum distance, in number of blocks, between the best block and the point where a fork
+    /// branches off from the best chain. The background task is reset if a fork is further
+    /// behind.
+    ///
+    /// The sync service normally discards these forks itself. This value is a safety measure
+    /// that bounds the number of non-finalized blocks kept in memory.
+    pub max_non_finalized_fork_depth: u64,
 }
 
 /// See [the module-level documentation](..).
@@ -100,8 +120,24 @@ pub struct RuntimeService {
     /// See [`Config::sync_service`].
     sync_service: Arc<sync_service::SyncService>,
 
+    /// Runtime code substitutions found in the chain specification. See
+    /// [`chain_spec::ChainSpec::code_substitutes`].
+    code_substitutes: Arc<Vec<CodeSubstitute>>,
+
+    /// See [`Config::max_non_finalized_fork_depth`].
+    max_non_finalized_fork_depth: u64,
+
+    /// Counters reported by [`RuntimeService::metrics`]. Shared with the temporary runtime
+    /// services created by the background task.
+    counters: Arc<Counters>,
+
     /// Fields behind a `Mutex`. Should only be locked for short-lived operations.
     guarded: Mutex<Guarded>,
+
+    /// Node values of the call proofs that have been decoded in the past, indexed by the state
+    /// root of the block the call proof has been made against. Makes it possible for successive
+    /// runtime calls against the same block to not decode the same trie nodes multiple times.
+    proof_nodes_cache: Mutex<lru::LruCache<[u8; 32], proof_decode::NodesCache>>,
 }
 
 impl RuntimeService {
@@ -109,24 +145,44 @@ impl RuntimeService {
     ///
     /// The future returned by this function is expected to finish relatively quickly and is
     /// necessary only for locking purposes.
+    ///
+    /// If the chain specification contains only the root hash of the genesis storage, the
+    /// runtime of the finalized block is instead downloaded from the network, and the future
+    /// returned by this function only finishes once this download has succeeded.
     pub async fn new(mut config: Config<'_>) -> Arc<Self> {
         // Target to use for all the logs of this service.
         let log_target = format!("runtime-{}", config.log_name);
 
-        let best_near_head_of_chain = config.sync_service.is_near_head_of_chain_heuristic().await;
-
-        // Build the runtime of the genesis block.
-        let genesis_runtime = {
-            let code = config
-                .chain_spec
-                .genesis_storage()
-                .find(|(k, _)| k == b":code")
-                .map(|(_, v)| v.to_vec());
-            let heap_pages = config
-                .chain_spec
-                .genesis_storage()
-                .find(|(k, _)| k == b":heappages")
-                .map(|(_, v)| v.to_vec());
+        let sync_status = config.sync_service.status().await;
+
+        // The `spec_version` of each substitute is obtained ahead of time, in order to later be
+        // compared with the one of the on-chain runtimes.
+        let mut code_substitutes = Vec::new();
+        for (block_hash, code) in config.chain_spec.code_substitutes() {
+            match SuccessfulRuntime::from_params(&Some(code.to_vec()), &None).await {
+                Ok(runtime) => code_substitutes.push(CodeSubstitute {
+                    block_hash: *block_hash,
+                    code: code.to_vec(),
+                    spec_version: runtime.runtime_spec.decode().spec_version,
+                }),
+                Err(error) => {
+                    log::warn!(
+                        target: &log_target,
+                        "Ignoring invalid code substitute for block {}: {}",
+                        HashDisplay(block_hash),
+                        error
+                    );
+                }
+            }
+        }
+
+        // Build the runtime of the genesis block, if the genesis storage is known. Otherwise, the
+        // runtime of the finalized block is later downloaded from the network.
+        let tree = if let Some(genesis_storage) =
+            config.chain_spec.genesis_storage().into_genesis_items()
+        {
+            let code = genesis_storage.value(b":code").map(|v| v.to_vec());
+            let heap_pages = genesis_storage.value(b":heappages").map(|v| v.to_vec());
 
             // Note that in the absolute we don't need to panic in case of a problem, and could
             // simply store an `Err` and continue running.
@@ -144,12 +200,7 @@ impl RuntimeService {
                             break;
                         }
                         metadata::Query::StorageGet(get) => {
-                            let key = get.key_as_vec();
-                            let value = config
-                                .chain_spec
-                                .genesis_storage()
-                                .find(|(k, _)| &**k == key)
-                                .map(|(_, v)| v);
+                            let value = genesis_storage.value(&get.key_as_vec());
                             query = get.inject_value(value.map(iter::once));
                         }
                         metadata::Query::Finished(Err(err), _) => {
@@ -159,28 +210,51 @@ impl RuntimeService {
                 }
             }
 
-            Runtime {
-                runtime,
-                runtime_code: code,
-                heap_pages,
-            }
+            download_tree::DownloadTree::from_finalized_block_and_runtime(
+                config.genesis_block_scale_encoded_header,
+                Runtime {
+                    runtime,
+                    runtime_code: code,
+                    heap_pages,
+                    substitute_exempt: false,
+                },
+            )
+        } else {
+            download_tree::DownloadTree::from_finalized_block(
+                config.genesis_block_scale_encoded_header,
+            )
+        };
+
+        // If the runtime isn't known yet, this function waits for the background task to have
+        // downloaded it, as the rest of the runtime service assumes that a runtime is available.
+        let (output_ready_tx, output_ready_rx) = if tree.has_output() {
+            (None, None)
+        } else {
+            let (tx, rx) = oneshot::channel();
+            (Some(tx), Some(rx))
         };
 
         let runtime_service = Arc::new(RuntimeService {
             log_target,
             sync_service: config.sync_service,
+            code_substitutes: Arc::new(code_substitutes),
+            max_non_finalized_fork_depth: config.max_non_finalized_fork_depth,
             guarded: Mutex::new(Guarded {
                 all_blocks_subscriptions: Vec::new(),
                 finalized_blocks_subscriptions: Vec::new(),
                 best_blocks_subscriptions: Vec::new(),
                 runtime_version_subscriptions: Vec::new(),
-                best_near_head_of_chain,
-                tree: Some(
-                    download_tree::DownloadTree::from_finalized_block_and_runtime(
-                        config.genesis_block_scale_encoded_header,
-                        genesis_runtime,
-                    ),
-                ),
+                status_subscriptions: Vec::new(),
+                reported_status: sync_status.clone(),
+                best_sync_status: sync_status.clone(),
+                sync_status,
+                tree: Some(tree),
+                output_ready: output_ready_tx,
+            }),
+            proof_nodes_cache: Mutex::new(lru::LruCache::new(4)),
+            counters: Arc::new(Counters {
+                runtime_downloads_started: atomic::AtomicU64::new(0),
+                runtime_downloads_failed: atomic::AtomicU64::new(0),
             }),
         });
 
@@ -190,13 +264,21 @@ impl RuntimeService {
         // This is strictly speaking not necessary as long as there is no active subscription.
         // However, in practice, there is most likely always going to be one. It is way easier to
         // always have a task active rather than create and destroy it.
-        (config.tasks_executor)("runtime-download".into(), {
-            let runtime_service = runtime_service.clone();
-            async move {
-                run_background(runtime_service).await;
-            }
-            .boxed()
-        });
+        (config.tasks_executor)(
+            "runtime-download".into(),
+            crate::TaskPriority::Background,
+            {
+                let runtime_service = runtime_service.clone();
+                async move {
+                    run_background(runtime_service).await;
+                }
+                .boxed()
+            },
+        );
+
+        if let Some(output_ready_rx) = output_ready_rx {
+            let _ = output_ready_rx.await;
+        }
 
         runtime_service
     }
@@ -318,15 +400,21 @@ impl RuntimeService {
             (code, heap_pages)
         };
 
-        let vm = match executor::host::HostVmPrototype::new(
-            code.as_ref()
+        let vm = match executor::host::HostVmPrototype::new(executor::host::Config {
+            module: code
+                .as_ref()
                 .ok_or(RuntimeError::CodeNotFound)
                 .map_err(RuntimeCallError::InvalidRuntime)?,
-            executor::storage_heap_pages_to_value(heap_pages.as_deref())
+            heap_pages: executor::storage_heap_pages_to_value(heap_pages.as_deref())
                 .map_err(RuntimeError::InvalidHeapPages)
                 .map_err(RuntimeCallError::InvalidRuntime)?,
-            executor::vm::ExecHint::CompileAheadOfTime,
-        ) {
+            exec_hint: executor::vm::ExecHint::CompileAheadOfTime,
+            // Runtimes that import functions that smoldot doesn't support can still be used
+            // for the calls that don't use these functions.
+            allow_unresolved_imports: true,
+            max_memory_size: Some(MAX_RUNTIME_MEMORY_SIZE),
+            metered: false,
+        }) {
             Ok(vm) => vm,
             Err(error) => {
                 log::warn!(
@@ -408,8 +496,8 @@ impl RuntimeService {
     /// `buffer_size` block notifications are buffered in the channel. If the channel is full
     /// when a new notification is attempted to be pushed, the channel gets closed.
     ///
-    /// The channel also gets closed if a gap in the finality happens, such as after a Grandpa
-    /// warp syncing.
+    /// If a gap in the finality happens, such as after a Grandpa warp syncing, a
+    /// [`sync_service::Notification::GapDetected`] is sent on the channel.
     ///
     /// See [`sync_service::SubscribeAll`] for information about the return value.
     pub async fn subscribe_all(
@@ -421,38 +509,15 @@ impl RuntimeService {
         debug_assert!(guarded.tree.as_ref().unwrap().has_output());
         guarded.all_blocks_subscriptions.push(tx);
 
-        let tree = guarded.tree.as_ref().unwrap();
-
-        let non_finalized_blocks_ancestry_order: Vec<_> = tree
-            .non_finalized_blocks_headers_ancestry_order()
-            .map(|(scale_encoded_header, is_new_best)| {
-                let parent_hash = *header::decode(scale_encoded_header).unwrap().parent_hash; // TODO: correct? if yes, document
-                debug_assert!(
-                    parent_hash == *tree.finalized_block_hash()
-                        || tree
-                            .non_finalized_blocks_headers_ancestry_order()
-                            .any(|(h, _)| parent_hash == header::hash_from_scale_encoded_header(h))
-                );
-                sync_service::BlockNotification {
-                    is_new_best,
-                    parent_hash,
-                    scale_encoded_header: scale_encoded_header.to_vec(),
-                }
-            })
-            .collect();
-
-        debug_assert!(matches!(
-            non_finalized_blocks_ancestry_order
-                .iter()
-                .filter(|b| b.is_new_best)
-                .count(),
-            0 | 1
-        ));
-
         sync_service::SubscribeAll {
-            finalized_block_scale_encoded_header: tree.finalized_block_header().to_vec(),
+            finalized_block_scale_encoded_header: guarded
+                .tree
+                .as_ref()
+                .unwrap()
+                .finalized_block_header()
+                .to_vec(),
             new_blocks,
-            non_finalized_blocks_ancestry_order,
+            non_finalized_blocks_ancestry_order: guarded.non_finalized_blocks_ancestry_order(),
         }
     }
 
@@ -569,7 +634,10 @@ impl RuntimeService {
                     break (Ok(metadata), virtual_machine);
                 }
                 metadata::Query::StorageGet(storage_get) => {
-                    match runtime_call_lock.storage_entry(&storage_get.key_as_vec()) {
+                    let child_trie = storage_get.child_trie().map(|c| c.as_ref().to_vec());
+                    match runtime_call_lock
+                        .storage_entry(child_trie.as_deref(), &storage_get.key_as_vec())
+                    {
                         Ok(v) => query = storage_get.inject_value(v.map(iter::once)),
                         Err(err) => {
                             break (
@@ -589,27 +657,89 @@ impl RuntimeService {
         metadata_result
     }
 
-    /// Returns true if it is believed that we are near the head of the chain.
+    /// Returns a snapshot of the metrics of the runtime service.
     ///
-    /// The way this method is implemented is opaque and cannot be relied on. The return value
-    /// should only ever be shown to the user and not used for any meaningful logic.
-    pub async fn is_near_head_of_chain_heuristic(&self) -> bool {
-        // The runtime service adds a delay between the moment a best block is reported by the
-        // sync service and the moment it is reported by the runtime service.
-        // Because of this, any "far from head of chain" to "near head of chain" transition
-        // must take that delay into account. The other way around ("near" to "far") is
-        // unaffected.
+    /// The return value should only ever be shown to the user and not used for any meaningful
+    /// logic.
+    pub async fn metrics(&self) -> Metrics {
+        let (compiled_runtimes, runtimes_memory_bytes, blocks_memory_bytes) = {
+            let guarded = self.guarded.lock().await;
+            guarded.tree.as_ref().map_or((0, 0, 0), |tree| {
+                let mut compiled_runtimes = 0;
+                let mut runtimes_memory_bytes = 0;
+                for (_, runtime) in tree.runtimes_iter() {
+                    runtimes_memory_bytes += runtime.runtime_code.as_ref().map_or(0, |c| c.len());
+                    runtimes_memory_bytes += runtime.heap_pages.as_ref().map_or(0, |h| h.len());
+                    if let Ok(runtime) = &runtime.runtime {
+                        compiled_runtimes += 1;
+                        runtimes_memory_bytes += runtime.metadata.as_ref().map_or(0, |m| m.len());
+                        // The virtual machine is missing if it is currently being used, in which
+                        // case its memory is simply not accounted for.
+                        runtimes_memory_bytes += runtime
+                            .virtual_machine
+                            .as_ref()
+                            .map_or(0, |vm| usize::try_from(vm.memory_size()).unwrap());
+                    }
+                }
 
-        // If the sync service is far from the head, the runtime service is also far.
-        if !self.sync_service.is_near_head_of_chain_heuristic().await {
-            return false;
+                let mut blocks_memory_bytes = tree
+                    .non_finalized_blocks_headers_ancestry_order()
+                    .map(|(header, _)| header.len())
+                    .sum::<usize>();
+                if tree.has_output() {
+                    blocks_memory_bytes += tree.finalized_block_header().len();
+                }
+
+                (
+                    compiled_runtimes,
+                    runtimes_memory_bytes,
+                    blocks_memory_bytes,
+                )
+            })
+        };
+
+        let caches_memory_bytes = self
+            .proof_nodes_cache
+            .lock()
+            .await
+            .iter()
+            .map(|(_, cache)| cache.len() * CACHED_PROOF_NODE_SIZE_ESTIMATE)
+            .sum::<usize>();
+
+        Metrics {
+            runtime_downloads_started: self
+                .counters
+                .runtime_downloads_started
+                .load(atomic::Ordering::Relaxed),
+            runtime_downloads_failed: self
+                .counters
+                .runtime_downloads_failed
+                .load(atomic::Ordering::Relaxed),
+            compiled_runtimes: u64::try_from(compiled_runtimes).unwrap(),
+            runtimes_memory_bytes: u64::try_from(runtimes_memory_bytes).unwrap(),
+            blocks_memory_bytes: u64::try_from(blocks_memory_bytes).unwrap(),
+            caches_memory_bytes: u64::try_from(caches_memory_bytes).unwrap(),
         }
+    }
 
-        // If the sync service is near, report the result of `is_near_head_of_chain_heuristic()`
-        // when called at the latest best block that the runtime service reported through its API,
-        // to make sure that we don't report "near" while having reported only blocks that were
-        // far.
-        self.guarded.lock().await.best_near_head_of_chain
+    /// Returns the current status of the syncing, from the point of view of the runtime service.
+    ///
+    /// The return value should only ever be shown to the user and not used for any meaningful
+    /// logic.
+    pub async fn status(&self) -> SyncStatus {
+        self.guarded.lock().await.status()
+    }
+
+    /// Returns the current status of the syncing, from the point of view of the runtime service,
+    /// plus an unlimited stream that produces one item every time the status changes.
+    ///
+    /// If the status changes multiple times while the stream isn't being polled, only the latest
+    /// status is reported.
+    pub async fn subscribe_status(&self) -> (SyncStatus, NotificationsReceiver<SyncStatus>) {
+        let (tx, rx) = lossy_channel::channel();
+        let mut guarded = self.guarded.lock().await;
+        guarded.status_subscriptions.push(tx);
+        (guarded.reported_status.clone(), rx)
     }
 }
 
@@ -718,6 +848,25 @@ impl<'a> RuntimeLock<'a> {
             .await
             .map_err(RuntimeCallError::CallProof);
 
+        // Decode the call proof, re-using the nodes decoded by previous calls against the same
+        // block.
+        let call_proof = match call_proof {
+            Ok(call_proof) => {
+                let state_root = *header::decode(&runtime_block_header).unwrap().state_root;
+                let mut proof_nodes_cache = self.service.proof_nodes_cache.lock().await;
+                if proof_nodes_cache.get_mut(&state_root).is_none() {
+                    proof_nodes_cache.put(state_root, proof_decode::NodesCache::new());
+                }
+
+                proof_decode::decode_and_verify_proof(proof_decode::Config {
+                    proof: call_proof.iter().map(|v| &v[..]),
+                    cache: proof_nodes_cache.get_mut(&state_root),
+                })
+                .map_err(RuntimeCallError::StorageRetrieval)
+            }
+            Err(err) => Err(err),
+        };
+
         let (guarded, virtual_machine) = if let Some(virtual_machine) = virtual_machine {
             (None, virtual_machine)
         } else {
@@ -768,7 +917,7 @@ pub struct RuntimeCallLock<'a> {
     guarded: Option<MutexGuard<'a, Guarded>>,
     runtime_block_header: Vec<u8>,
     block_hash: [u8; 32],
-    call_proof: Result<Vec<Vec<u8>>, RuntimeCallError>,
+    call_proof: Result<proof_decode::DecodedProof, RuntimeCallError>,
 }
 
 impl<'a> RuntimeCallLock<'a> {
@@ -786,27 +935,88 @@ impl<'a> RuntimeCallLock<'a> {
 
     /// Finds the given key in the call proof and returns the associated storage value.
     ///
+    /// If `child_trie` is `Some`, the key is searched in the given child trie rather than in the
+    /// main trie. The child trie key must not include the `:child_storage:default:` prefix.
+    ///
     /// Returns an error if the key couldn't be found in the proof, meaning that the proof is
     /// invalid.
     // TODO: if proof is invalid, we should give the option to fetch another call proof
-    pub fn storage_entry(&self, requested_key: &[u8]) -> Result<Option<&[u8]>, RuntimeCallError> {
+    pub fn storage_entry(
+        &self,
+        child_trie: Option<&[u8]>,
+        requested_key: &[u8],
+    ) -> Result<Option<&[u8]>, RuntimeCallError> {
         let call_proof = match &self.call_proof {
             Ok(p) => p,
             Err(err) => return Err(err.clone()),
         };
 
-        match proof_verify::verify_proof(proof_verify::VerifyProofConfig {
-            requested_key: &requested_key,
-            trie_root_hash: self.block_storage_root(),
-            proof: call_proof.iter().map(|v| &v[..]),
-        }) {
-            Ok(v) => Ok(v),
-            Err(err) => Err(RuntimeCallError::StorageRetrieval(err)),
+        let trie_root_hash = match self.trie_root_hash(child_trie)? {
+            Some(h) => h,
+            None => return Ok(None),
+        };
+
+        call_proof
+            .storage_value(&trie_root_hash, requested_key)
+            .map_err(RuntimeCallError::StorageRetrieval)
+    }
+
+    /// Finds in the call proof the key that immediately follows `key` in lexicographic order.
+    ///
+    /// If `child_trie` is `Some`, the key is searched in the given child trie rather than in the
+    /// main trie. See [`RuntimeCallLock::storage_entry`].
+    ///
+    /// Returns an error if the proof doesn't contain enough information, meaning that the proof
+    /// is invalid.
+    // TODO: if proof is invalid, we should give the option to fetch another call proof
+    pub fn storage_next_key(
+        &self,
+        child_trie: Option<&[u8]>,
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>, RuntimeCallError> {
+        let call_proof = match &self.call_proof {
+            Ok(p) => p,
+            Err(err) => return Err(err.clone()),
+        };
+
+        let trie_root_hash = match self.trie_root_hash(child_trie)? {
+            Some(h) => h,
+            None => return Ok(None),
+        };
+
+        call_proof
+            .next_key(&trie_root_hash, key)
+            .map_err(RuntimeCallError::StorageRetrieval)
+    }
+
+    /// Returns the root hash of the given child trie, or of the main trie if `None`.
+    ///
+    /// The root of a child trie is stored in the main trie, and is thus also verified against
+    /// the call proof. Returns `Ok(None)` if the child trie is empty.
+    fn trie_root_hash(
+        &self,
+        child_trie: Option<&[u8]>,
+    ) -> Result<Option<[u8; 32]>, RuntimeCallError> {
+        let child_trie = match child_trie {
+            Some(c) => c,
+            None => return Ok(Some(*self.block_storage_root())),
+        };
+
+        let mut key = b":child_storage:default:".to_vec();
+        key.extend_from_slice(child_trie);
+        match self.storage_entry(None, &key)? {
+            Some(root) => <[u8; 32]>::try_from(root)
+                .map(Some)
+                .map_err(|_| RuntimeCallError::InvalidChildTrieRoot),
+            None => Ok(None),
         }
     }
 
     /// Finds in the call proof the list of keys that match a certain prefix.
     ///
+    /// If `child_trie` is `Some`, the keys are searched in the given child trie rather than in the
+    /// main trie. See [`RuntimeCallLock::storage_entry`].
+    ///
     /// Returns an error if not all the keys could be found in the proof, meaning that the proof
     /// is invalid.
     ///
@@ -814,54 +1024,23 @@ impl<'a> RuntimeCallLock<'a> {
     // TODO: if proof is invalid, we should give the option to fetch another call proof
     pub fn storage_prefix_keys_ordered(
         &'_ self,
+        child_trie: Option<&[u8]>,
         prefix: &[u8],
     ) -> Result<impl Iterator<Item = impl AsRef<[u8]> + '_>, RuntimeCallError> {
-        // TODO: this is sub-optimal as we iterate over the proof multiple times and do a lot of Vec allocations
-        let mut to_find = vec![trie::bytes_to_nibbles(prefix.iter().copied()).collect::<Vec<_>>()];
-        let mut output = Vec::new();
-
         let call_proof = match &self.call_proof {
             Ok(p) => p,
             Err(err) => return Err(err.clone()),
         };
 
-        for key in mem::replace(&mut to_find, Vec::new()) {
-            let node_info = proof_verify::trie_node_info(proof_verify::TrieNodeInfoConfig {
-                requested_key: key.iter().cloned(),
-                trie_root_hash: &self.block_storage_root(),
-                proof: call_proof.iter().map(|v| &v[..]),
-            })
-            .map_err(RuntimeCallError::StorageRetrieval)?;
-
-            if node_info.storage_value.is_some() {
-                assert_eq!(key.len() % 2, 0);
-                output.push(trie::nibbles_to_bytes_extend(key.iter().copied()).collect::<Vec<_>>());
-            }
-
-            match node_info.children {
-                proof_verify::Children::None => {}
-                proof_verify::Children::One(nibble) => {
-                    let mut child = key.clone();
-                    child.push(nibble);
-                    to_find.push(child);
-                }
-                proof_verify::Children::Multiple { children_bitmap } => {
-                    for nibble in trie::all_nibbles() {
-                        if (children_bitmap & (1 << u8::from(nibble))) == 0 {
-                            continue;
-                        }
-
-                        let mut child = key.clone();
-                        child.push(nibble);
-                        to_find.push(child);
-                    }
-                }
-            }
-        }
+        let trie_root_hash = match self.trie_root_hash(child_trie)? {
+            Some(h) => h,
+            None => return Ok(Vec::new().into_iter()),
+        };
 
-        // TODO: maybe we could iterate over the proof in an ordered way rather than sorting at the end
-        output.sort();
-        Ok(output.into_iter())
+        let keys = call_proof
+            .prefix_keys_ordered(&trie_root_hash, prefix)
+            .map_err(RuntimeCallError::StorageRetrieval)?;
+        Ok(keys.into_iter())
     }
 
     /// End the runtime call.
@@ -926,6 +1105,8 @@ pub enum RuntimeCallError {
     /// Error while querying the storage of the block.
     #[display(fmt = "Error while querying block storage: {}", _0)]
     StorageQuery(sync_service::StorageQueryError),
+    /// The root of a child trie found in the storage isn't a valid hash.
+    InvalidChildTrieRoot,
 }
 
 impl RuntimeCallError {
@@ -941,6 +1122,7 @@ impl RuntimeCallError {
             RuntimeCallError::InvalidBlockHeader(_) => false,
             RuntimeCallError::NetworkBlockRequest => true,
             RuntimeCallError::StorageQuery(err) => err.is_network_problem(),
+            RuntimeCallError::InvalidChildTrieRoot => false,
         }
     }
 }
@@ -978,16 +1160,104 @@ struct Guarded {
     /// See [`RuntimeService::subscribe_best`].
     best_blocks_subscriptions: Vec<lossy_channel::Sender<Vec<u8>>>,
 
-    /// Return value of calling [`sync_service::SyncService::is_near_head_of_chain_heuristic`]
-    /// after the latest best block update.
-    best_near_head_of_chain: bool,
+    /// List of senders that get notified when the value returned by [`Guarded::status`]
+    /// changes.
+    /// See [`RuntimeService::subscribe_status`].
+    status_subscriptions: Vec<lossy_channel::Sender<SyncStatus>>,
+
+    /// Status that has most recently been sent to the elements of `status_subscriptions`.
+    reported_status: SyncStatus,
+
+    /// Latest known status of the sync service.
+    sync_status: SyncStatus,
+
+    /// Status of the sync service at the time of the latest best block update.
+    best_sync_status: SyncStatus,
 
     /// Tree of blocks. Holds the state of the download of everything. Always `true` when the
     /// `Mutex` is being locked. Switched to `None` during some operations.
     tree: Option<download_tree::DownloadTree<ffi::Instant, Runtime>>,
+
+    /// If `Some`, the runtime of the finalized block isn't known yet, and a message must be sent
+    /// on this channel once `tree` has an output. See [`RuntimeService::new`].
+    output_ready: Option<oneshot::Sender<()>>,
 }
 
 impl Guarded {
+    /// Returns the status of the syncing from the point of view of the runtime service.
+    fn status(&self) -> SyncStatus {
+        // The runtime service adds a delay between the moment a best block is reported by the
+        // sync service and the moment it is reported by the runtime service.
+        // Because of this, any "far from head of chain" to "near head of chain" transition
+        // must take that delay into account. The other way around ("near" to "far") is
+        // unaffected.
+
+        // If the sync service is far from the head, the runtime service is also far.
+        if self.sync_status != SyncStatus::NearHead {
+            return self.sync_status.clone();
+        }
+
+        // If the sync service is near, report the status of the sync service at the latest best
+        // block that the runtime service reported through its API, to make sure that we don't
+        // report "near" while having reported only blocks that were far.
+        self.best_sync_status.clone()
+    }
+
+    /// Notifies the status subscribers if the value returned by [`Guarded::status`] has changed
+    /// since the latest notification.
+    fn notify_status_subscribers(&mut self) {
+        let status = self.status();
+        if status == self.reported_status {
+            return;
+        }
+
+        // Elements are removed one by one and inserted back if the channel is still open.
+        for index in (0..self.status_subscriptions.len()).rev() {
+            let mut subscription = self.status_subscriptions.swap_remove(index);
+            if subscription.send(status.clone()).is_err() {
+                continue;
+            }
+
+            self.status_subscriptions.push(subscription);
+        }
+
+        self.reported_status = status;
+    }
+
+    /// Returns the list of all non-finalized blocks of the tree, ordered so that parents are
+    /// always found before their children.
+    fn non_finalized_blocks_ancestry_order(&self) -> Vec<sync_service::BlockNotification> {
+        let tree = self.tree.as_ref().unwrap();
+
+        let non_finalized_blocks_ancestry_order: Vec<_> = tree
+            .non_finalized_blocks_headers_ancestry_order()
+            .map(|(scale_encoded_header, is_new_best)| {
+                let parent_hash = *header::decode(scale_encoded_header).unwrap().parent_hash; // TODO: correct? if yes, document
+                debug_assert!(
+                    parent_hash == *tree.finalized_block_hash()
+                        || tree
+                            .non_finalized_blocks_headers_ancestry_order()
+                            .any(|(h, _)| parent_hash == header::hash_from_scale_encoded_header(h))
+                );
+                sync_service::BlockNotification {
+                    is_new_best,
+                    parent_hash,
+                    scale_encoded_header: scale_encoded_header.to_vec(),
+                }
+            })
+            .collect();
+
+        debug_assert!(matches!(
+            non_finalized_blocks_ancestry_order
+                .iter()
+                .filter(|b| b.is_new_best)
+                .count(),
+            0 | 1
+        ));
+
+        non_finalized_blocks_ancestry_order
+    }
+
     /// Notifies the subscribers about changes to the best and finalized blocks.
     fn notify_subscribers(
         &mut self,
@@ -1053,18 +1323,43 @@ impl Guarded {
 }
 
 async fn run_background(original_runtime_service: Arc<RuntimeService>) {
+    // Stream of updates to the status of the sync service. Kept across resets of the background.
+    let mut sync_status_updates = original_runtime_service
+        .sync_service
+        .subscribe_status()
+        .await
+        .1;
+
+    // Set to `Some` when the sync service reports a gap in the blocks. Contains the new state of
+    // the chain and the stream of blocks updates, which can be kept as is.
+    let mut after_gap = None;
+
     loop {
-        // The buffer size should be large enough so that, if the CPU is busy, it doesn't
-        // become full before the execution of the runtime service resumes.
-        let subscription = original_runtime_service
-            .sync_service
-            .subscribe_all(16)
-            .await;
+        let (
+            finalized_block_scale_encoded_header,
+            non_finalized_blocks_ancestry_order,
+            blocks_stream,
+        ) = match after_gap.take() {
+            Some(after_gap) => after_gap,
+            None => {
+                // The buffer size should be large enough so that, if the CPU is busy, it
+                // doesn't become full before the execution of the runtime service resumes.
+                let subscription = original_runtime_service
+                    .sync_service
+                    .subscribe_all(16)
+                    .await;
+                (
+                    subscription.finalized_block_scale_encoded_header,
+                    subscription.non_finalized_blocks_ancestry_order,
+                    subscription.new_blocks.boxed(),
+                )
+            }
+        };
 
         log::debug!(
             target: &original_runtime_service.log_target,
             "Reinitialized background worker to finalized block {}",
-            HashDisplay(&header::hash_from_scale_encoded_header(&subscription.finalized_block_scale_encoded_header))
+            HashDisplay(&header::hash_from_scale_encoded_header(&finalized_block_scale_encoded_header))
             // TODO: print block height
         );
 
@@ -1073,29 +1368,36 @@ async fn run_background(original_runtime_service: Arc<RuntimeService>) {
         // Later, when the `Guarded` contains at least a finalized runtime, it will be written
         // over the original runtime service.
         // TODO: if subscription.finalized is equal to current finalized, skip the whole process below?
+        let original_status = original_runtime_service.status().await;
         let mut background = Background {
             runtime_service: Arc::new(RuntimeService {
                 log_target: original_runtime_service.log_target.clone(),
                 sync_service: original_runtime_service.sync_service.clone(),
+                code_substitutes: original_runtime_service.code_substitutes.clone(),
+                max_non_finalized_fork_depth: original_runtime_service.max_non_finalized_fork_depth,
                 guarded: Mutex::new(Guarded {
                     all_blocks_subscriptions: Vec::new(),
                     best_blocks_subscriptions: Vec::new(),
                     finalized_blocks_subscriptions: Vec::new(),
                     runtime_version_subscriptions: Vec::new(),
-                    best_near_head_of_chain: original_runtime_service
-                        .is_near_head_of_chain_heuristic()
-                        .await,
+                    status_subscriptions: Vec::new(),
+                    reported_status: original_status.clone(),
+                    sync_status: original_status.clone(),
+                    best_sync_status: original_status,
                     tree: Some(download_tree::DownloadTree::from_finalized_block(
-                        subscription.finalized_block_scale_encoded_header,
+                        finalized_block_scale_encoded_header,
                     )),
+                    output_ready: None,
                 }),
+                proof_nodes_cache: Mutex::new(lru::LruCache::new(4)),
+                counters: original_runtime_service.counters.clone(),
             }),
-            blocks_stream: subscription.new_blocks.boxed(),
+            blocks_stream,
             wake_up_new_necessary_download: future::pending().boxed().fuse(),
             runtime_downloads: stream::FuturesUnordered::new(),
         };
 
-        for block in subscription.non_finalized_blocks_ancestry_order {
+        for block in non_finalized_blocks_ancestry_order {
             let _ = background
                 .runtime_service
                 .guarded
@@ -1126,15 +1428,46 @@ async fn run_background(original_runtime_service: Arc<RuntimeService>) {
                     );
 
                     let mut original_guarded = original_runtime_service.guarded.lock().await;
-                    original_guarded.best_near_head_of_chain =
-                        temporary_guarded.best_near_head_of_chain;
+                    original_guarded.best_sync_status = temporary_guarded.best_sync_status.clone();
                     original_guarded.tree = Some(temporary_guarded.tree.take().unwrap());
+                    if let Some(output_ready) = original_guarded.output_ready.take() {
+                        let _ = output_ready.send(());
+                    }
 
                     drop(temporary_guarded);
 
-                    original_guarded.all_blocks_subscriptions.clear();
+                    // The subscriptions to all blocks are informed of the new state of the
+                    // chain, as it isn't necessarily a continuation of what has been reported
+                    // to them before.
+                    let new_finalized_block_scale_encoded_header = original_guarded
+                        .tree
+                        .as_ref()
+                        .unwrap()
+                        .finalized_block_header()
+                        .to_vec();
+                    let non_finalized_blocks_ancestry_order =
+                        original_guarded.non_finalized_blocks_ancestry_order();
+                    // Elements are removed one by one and inserted back if the channel is still
+                    // open.
+                    for index in (0..original_guarded.all_blocks_subscriptions.len()).rev() {
+                        let mut subscription =
+                            original_guarded.all_blocks_subscriptions.swap_remove(index);
+                        let notification = sync_service::Notification::GapDetected {
+                            new_finalized_block_scale_encoded_header:
+                                new_finalized_block_scale_encoded_header.clone(),
+                            non_finalized_blocks_ancestry_order:
+                                non_finalized_blocks_ancestry_order.clone(),
+                        };
+                        if subscription.try_send(notification).is_err() {
+                            continue;
+                        }
+
+                        original_guarded.all_blocks_subscriptions.push(subscription);
+                    }
+
                     // TODO: correct? especially for the runtime?
                     original_guarded.notify_subscribers(true, true, true);
+                    original_guarded.notify_status_subscribers();
 
                     background.runtime_service = original_runtime_service.clone();
                 }
@@ -1144,6 +1477,15 @@ async fn run_background(original_runtime_service: Arc<RuntimeService>) {
                 _ = &mut background.wake_up_new_necessary_download => {
                     background.start_necessary_downloads().await;
                 },
+                sync_status = sync_status_updates.next() => {
+                    // Note that the status of the sync service is always written to the original
+                    // runtime service, as it is the one exposed through the API.
+                    if let Some(sync_status) = sync_status {
+                        let mut original_guarded = original_runtime_service.guarded.lock().await;
+                        original_guarded.sync_status = sync_status;
+                        original_guarded.notify_status_subscribers();
+                    }
+                },
                 notification = background.blocks_stream.next().fuse() => {
                     match notification {
                         None => break, // Break out of the inner loop in order to reset the background.
@@ -1156,14 +1498,31 @@ async fn run_background(original_runtime_service: Arc<RuntimeService>) {
                                 new_block.is_new_best
                             );
 
-                            let near_head_of_chain = background.runtime_service.sync_service.is_near_head_of_chain_heuristic().await;
+                            let sync_status = background.runtime_service.sync_service.status().await;
 
                             let mut guarded = background.runtime_service.guarded.lock().await;
                             // TODO: note that this code is never reached for parachains
                             if new_block.is_new_best {
-                                guarded.best_near_head_of_chain = near_head_of_chain;
+                                guarded.best_sync_status = sync_status;
+                                guarded.notify_status_subscribers();
                             }
                             guarded.tree.as_mut().unwrap().input_insert_block(new_block.scale_encoded_header, &new_block.parent_hash, new_block.is_new_best);
+
+                            // The sync service normally discards old forks. As a safety measure,
+                            // the tree is also bounded here.
+                            let max_fork_depth = background.runtime_service.max_non_finalized_fork_depth;
+                            if guarded.tree.as_ref().unwrap().input_max_fork_depth() > max_fork_depth {
+                                log::warn!(
+                                    target: &original_runtime_service.log_target,
+                                    "Fork older than {} blocks detected; resetting background worker",
+                                    max_fork_depth
+                                );
+
+                                // Break out of the inner loop in order to reset the background.
+                                // A new subscription to the sync service is created.
+                                break;
+                            }
+
                             background.advance_and_notify_subscribers(&mut guarded);
                         },
                         Some(sync_service::Notification::Finalized { hash, best_block_hash }) => {
@@ -1175,12 +1534,28 @@ async fn run_background(original_runtime_service: Arc<RuntimeService>) {
 
                             background.finalize(hash, best_block_hash).await;
                         }
+                        Some(sync_service::Notification::GapDetected { new_finalized_block_scale_encoded_header, non_finalized_blocks_ancestry_order }) => {
+                            log::debug!(
+                                target: &original_runtime_service.log_target,
+                                "Sync service gap detected: new_finalized={}",
+                                HashDisplay(&header::hash_from_scale_encoded_header(&new_finalized_block_scale_encoded_header))
+                            );
+
+                            // Break out of the inner loop in order to reset the background, while
+                            // keeping the same stream of blocks updates.
+                            after_gap = Some((
+                                new_finalized_block_scale_encoded_header,
+                                non_finalized_blocks_ancestry_order,
+                                mem::replace(&mut background.blocks_stream, stream::pending().boxed()),
+                            ));
+                            break;
+                        }
                     };
 
                     // TODO: process any other pending event from blocks_stream before doing that; otherwise we might start download for blocks that we don't care about because they're immediately overwritten by others
                     background.start_necessary_downloads().await;
                 },
-                (download_id, download_result) = background.runtime_downloads.select_next_some() => {
+                (download_id, block_hash, download_result) = background.runtime_downloads.select_next_some() => {
                     match download_result {
                         Ok((storage_code, storage_heap_pages)) => {
                             log::debug!(
@@ -1190,9 +1565,13 @@ async fn run_background(original_runtime_service: Arc<RuntimeService>) {
                             );
 
                             // TODO: the line below is a complete hack; the code that updates this value is never reached for parachains, and as such the line below is here to update this field
-                            background.runtime_service.guarded.lock().await.best_near_head_of_chain = true;
+                            {
+                                let mut guarded = background.runtime_service.guarded.lock().await;
+                                guarded.best_sync_status = SyncStatus::NearHead;
+                                guarded.notify_status_subscribers();
+                            }
 
-                            background.runtime_download_finished(download_id, storage_code, storage_heap_pages).await;
+                            background.runtime_download_finished(download_id, &block_hash, storage_code, storage_heap_pages).await;
                         }
                         Err(error) => {
                             log::log!(
@@ -1207,6 +1586,8 @@ async fn run_background(original_runtime_service: Arc<RuntimeService>) {
                                 error
                             );
 
+                            background.runtime_service.counters.runtime_downloads_failed.fetch_add(1, atomic::Ordering::Relaxed);
+
                             let mut guarded = background.runtime_service.guarded.lock().await;
                             guarded.tree.as_mut().unwrap().runtime_download_failure(download_id, &ffi::Instant::now());
                         }
@@ -1227,13 +1608,14 @@ struct Background {
     blocks_stream: Pin<Box<dyn Stream<Item = sync_service::Notification> + Send>>,
 
     /// List of runtimes currently being downloaded from the network.
-    /// For each item, the download id, storage value of `:code`, and storage value of
-    /// `:heappages`.
+    /// For each item, the download id, hash of the block, storage value of `:code`, and storage
+    /// value of `:heappages`.
     runtime_downloads: stream::FuturesUnordered<
         future::BoxFuture<
             'static,
             (
                 download_tree::DownloadId,
+                [u8; 32],
                 Result<(Option<Vec<u8>>, Option<Vec<u8>>), StorageQueryError>,
             ),
         >,
@@ -1248,17 +1630,29 @@ impl Background {
     async fn runtime_download_finished(
         &mut self,
         download_id: download_tree::DownloadId,
+        block_hash: &[u8; 32],
         storage_code: Option<Vec<u8>>,
         storage_heap_pages: Option<Vec<u8>>,
     ) {
         let mut guarded = self.runtime_service.guarded.lock().await;
 
+        // The blocks whose hash is found in the code substitutes use their unmodified runtime.
+        let substitute_exempt = self
+            .runtime_service
+            .code_substitutes
+            .iter()
+            .any(|substitute| substitute.block_hash == *block_hash);
+
         let existing_runtime = guarded
             .tree
             .as_ref()
             .unwrap()
             .runtimes_iter()
-            .find(|(_, rt)| rt.runtime_code == storage_code && rt.heap_pages == storage_heap_pages)
+            .find(|(_, rt)| {
+                rt.runtime_code == storage_code
+                    && rt.heap_pages == storage_heap_pages
+                    && rt.substitute_exempt == substitute_exempt
+            })
             .map(|(id, _)| id);
 
         if let Some(existing_runtime) = existing_runtime {
@@ -1268,7 +1662,34 @@ impl Background {
                 .unwrap()
                 .runtime_download_finished_existing(download_id, existing_runtime)
         } else {
-            let runtime = SuccessfulRuntime::from_params(&storage_code, &storage_heap_pages).await;
+            let mut runtime =
+                SuccessfulRuntime::from_params(&storage_code, &storage_heap_pages).await;
+
+            // If the on-chain runtime has the same `spec_version` as one of the code
+            // substitutes, the substitute is used instead.
+            let substitute = match &runtime {
+                Ok(runtime) if !substitute_exempt => {
+                    let spec_version = runtime.runtime_spec.decode().spec_version;
+                    self.runtime_service
+                        .code_substitutes
+                        .iter()
+                        .find(|substitute| substitute.spec_version == spec_version)
+                }
+                _ => None,
+            };
+            if let Some(substitute) = substitute {
+                log::debug!(
+                    target: &self.runtime_service.log_target,
+                    "Using code substitute of block {} for block {}",
+                    HashDisplay(&substitute.block_hash),
+                    HashDisplay(block_hash)
+                );
+                runtime = SuccessfulRuntime::from_params(
+                    &Some(substitute.code.clone()),
+                    &storage_heap_pages,
+                )
+                .await;
+            }
 
             guarded
                 .tree
@@ -1280,6 +1701,7 @@ impl Background {
                         heap_pages: storage_heap_pages,
                         runtime_code: storage_code,
                         runtime,
+                        substitute_exempt,
                     },
                 );
         }
@@ -1405,6 +1827,11 @@ impl Background {
                 HashDisplay(&download_params.block_hash)
             );
 
+            self.runtime_service
+                .counters
+                .runtime_downloads_started
+                .fetch_add(1, atomic::Ordering::Relaxed);
+
             // Dispatches a runtime download task to `runtime_downloads`.
             self.runtime_downloads.push(Box::pin({
                 let sync_service = self.runtime_service.sync_service.clone();
@@ -1427,7 +1854,7 @@ impl Background {
                         Err(error) => Err(error),
                     };
 
-                    (download_params.id, result)
+                    (download_params.id, download_params.block_hash, result)
                 }
             }));
         }
@@ -1470,6 +1897,51 @@ struct Runtime {
     /// build.
     // TODO: consider storing hash instead
     heap_pages: Option<Vec<u8>>,
+
+    /// `true` if this runtime has been built for a block whose hash is one of the keys of the
+    /// code substitutes, in which case no substitution has been performed.
+    ///
+    /// Note that [`Runtime::runtime_code`] is always the on-chain code, even if
+    /// [`Runtime::runtime`] has been built from a substitute.
+    substitute_exempt: bool,
+}
+
+/// Snapshot of the metrics of a [`RuntimeService`]. See [`RuntimeService::metrics`].
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    /// Number of downloads of `:code` and `:heappages` started since the service has started.
+    pub runtime_downloads_started: u64,
+    /// Number of these downloads that have failed.
+    pub runtime_downloads_failed: u64,
+    /// Number of successfully compiled runtimes currently kept in memory.
+    pub compiled_runtimes: u64,
+    /// Approximate number of bytes used by the runtimes kept in memory, including their code,
+    /// their metadata, and the memory of their virtual machine.
+    pub runtimes_memory_bytes: u64,
+    /// Approximate number of bytes used by the headers of the blocks tracked by the service.
+    pub blocks_memory_bytes: u64,
+    /// Approximate number of bytes used by the cache of decoded call proof nodes.
+    pub caches_memory_bytes: u64,
+}
+
+/// Rough estimate of the number of bytes used by each entry of a [`proof_decode::NodesCache`],
+/// as the cache doesn't expose the size of its entries.
+const CACHED_PROOF_NODE_SIZE_ESTIMATE: usize = 256;
+
+/// See [`RuntimeService::counters`].
+struct Counters {
+    runtime_downloads_started: atomic::AtomicU64,
+    runtime_downloads_failed: atomic::AtomicU64,
+}
+
+/// Runtime code substitution found in the chain specification.
+struct CodeSubstitute {
+    /// Hash of the block after which the substitution applies.
+    block_hash: [u8; 32],
+    /// Wasm runtime code to use instead of the on-chain code.
+    code: Vec<u8>,
+    /// `spec_version` of the runtime of [`CodeSubstitute::code`].
+    spec_version: u32,
 }
 
 struct SuccessfulRuntime {
@@ -1509,12 +1981,17 @@ impl SuccessfulRuntime {
         // once after.
         super::yield_once().await;
 
-        let vm = match executor::host::HostVmPrototype::new(
-            code.as_ref().ok_or(RuntimeError::CodeNotFound)?,
-            executor::storage_heap_pages_to_value(heap_pages.as_deref())
+        let vm = match executor::host::HostVmPrototype::new(executor::host::Config {
+            module: code.as_ref().ok_or(RuntimeError::CodeNotFound)?,
+            heap_pages: executor::storage_heap_pages_to_value(heap_pages.as_deref())
                 .map_err(RuntimeError::InvalidHeapPages)?,
-            executor::vm::ExecHint::CompileAheadOfTime,
-        ) {
+            exec_hint: executor::vm::ExecHint::CompileAheadOfTime,
+            // Runtimes that import functions that smoldot doesn't support can still be used
+            // for the calls that don't use these functions.
+            allow_unresolved_imports: true,
+            max_memory_size: Some(MAX_RUNTIME_MEMORY_SIZE),
+            metered: false,
+        }) {
             Ok(vm) => vm,
             Err(error) => {
                 return Err(RuntimeError::Build(error));
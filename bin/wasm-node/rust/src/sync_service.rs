@@ -36,12 +36,13 @@ use futures::{
 use smoldot::{
     chain, header,
     libp2p::PeerId,
-    network::{protocol, service},
+    network::{protocol, reputation, service},
     trie::{self, prefix_proof, proof_verify},
 };
 use std::{fmt, num::NonZeroU32, pin::Pin, sync::Arc};
 
 pub use crate::lossy_channel::Receiver as NotificationsReceiver;
+pub use smoldot::sync::all::{FinalityProof, Status as SyncStatus};
 
 mod parachain;
 mod relay_chain;
@@ -57,8 +58,11 @@ pub struct Config {
     /// State of the finalized chain.
     pub chain_information: chain::chain_information::ValidChainInformation,
 
-    /// Closure that spawns background tasks.
-    pub tasks_executor: Box<dyn FnMut(String, Pin<Box<dyn Future<Output = ()> + Send>>) + Send>,
+    /// Closure that spawns background tasks. The [`crate::TaskPriority`] is a hint indicating
+    /// how the task should be scheduled.
+    pub tasks_executor: Box<
+        dyn FnMut(String, crate::TaskPriority, Pin<Box<dyn Future<Output = ()> + Send>>) + Send,
+    >,
 
     /// Access to the network, and index of the chain to sync from the point of view of the
     /// network service.
@@ -68,6 +72,35 @@ pub struct Config {
     /// [`network_service::NetworkService::new`].
     pub network_events_receiver: mpsc::Receiver<network_service::Event>,
 
+    /// Maximum distance, in number of blocks, between the best block and the point where a fork
+    /// branches off from the best chain. Forks that are further behind are discarded, and
+    /// subscribers are notified with a [`Notification::GapDetected`].
+    ///
+    /// This bounds the number of non-finalized blocks kept in memory when the finality of the
+    /// chain is stalled. If [`Config::parachain`] is `Some`, the syncing is instead reset when
+    /// the tree of relay chain blocks contains a fork that is further behind.
+    pub max_non_finalized_fork_depth: u64,
+
+    /// Minimum number of distinct peers that must have announced a block (or one of its
+    /// descendants), or provided it in response to a request, before this block is verified and
+    /// reported to the subscribers.
+    ///
+    /// A value higher than `1` protects against a single peer feeding blocks that the rest of the
+    /// network isn't aware of, at the cost of a slower propagation of new blocks. If fewer peers
+    /// than this value are connected, no new block is ever reported. Ignored if
+    /// [`Config::parachain`] is `Some`.
+    pub min_sources_per_block: NonZeroU32,
+
+    /// List of hashes of blocks that are known to be bad, typically found in the chain
+    /// specification. These blocks and their descendants are never reported to the subscribers.
+    /// Ignored if [`Config::parachain`] is `Some`.
+    pub banned_blocks: Vec<[u8; 32]>,
+
+    /// List of block heights and hashes, typically found in the chain specification. Blocks
+    /// whose height is found in this list but whose hash doesn't match, and their descendants,
+    /// are never reported to the subscribers. Ignored if [`Config::parachain`] is `Some`.
+    pub fork_blocks: Vec<(u64, [u8; 32])>,
+
     /// Extra fields used when the chain is a parachain.
     /// If `None`, this chain is a standalone chain or a relay chain.
     pub parachain: Option<ConfigParachain>,
@@ -100,6 +133,12 @@ pub struct SyncService {
     network_service: Arc<network_service::NetworkService>,
     /// See [`Config::network_service`].
     network_chain_index: usize,
+
+    /// For each block hash, the peer that has most recently successfully answered a storage
+    /// proof or call proof request concerning this block. This peer is queried first for the
+    /// follow-up requests concerning the same block, as it is known to not have pruned its
+    /// storage.
+    proofs_affinity: Mutex<lru::LruCache<[u8; 32], PeerId>>,
 }
 
 impl SyncService {
@@ -111,11 +150,13 @@ impl SyncService {
         if let Some(config_parachain) = config.parachain {
             (config.tasks_executor)(
                 "sync-para".into(),
+                crate::TaskPriority::Background,
                 Box::pin(parachain::start_parachain(
                     log_target,
                     config.chain_information,
                     config_parachain.relay_chain_sync.clone(),
                     config_parachain.parachain_id,
+                    config.max_non_finalized_fork_depth,
                     from_foreground,
                     config.network_service.1,
                     config.network_events_receiver,
@@ -124,6 +165,7 @@ impl SyncService {
         } else {
             (config.tasks_executor)(
                 "sync-relay".into(),
+                crate::TaskPriority::Background,
                 Box::pin(
                     relay_chain::start_relay_chain(
                         log_target,
@@ -132,6 +174,10 @@ impl SyncService {
                         config.network_service.0.clone(),
                         config.network_service.1,
                         config.network_events_receiver,
+                        config.max_non_finalized_fork_depth,
+                        config.min_sources_per_block,
+                        config.banned_blocks,
+                        config.fork_blocks,
                     )
                     .await,
                 ),
@@ -142,6 +188,7 @@ impl SyncService {
             to_background: Mutex::new(to_background),
             network_service: config.network_service.0,
             network_chain_index: config.network_service.1,
+            proofs_affinity: Mutex::new(lru::LruCache::new(64)),
         }
     }
 
@@ -151,8 +198,8 @@ impl SyncService {
     /// in the channel. If the channel is full when a new notification is attempted to be pushed,
     /// the channel gets closed.
     ///
-    /// The channel also gets closed if a gap in the finality happens, such as after a Grandpa
-    /// warp syncing.
+    /// If a gap in the finality happens, such as after a Grandpa warp syncing, a
+    /// [`Notification::GapDetected`] is sent on the channel.
     ///
     /// See [`SubscribeAll`] for information about the return value.
     pub async fn subscribe_all(&self, buffer_size: usize) -> SubscribeAll {
@@ -171,17 +218,80 @@ impl SyncService {
         rx.await.unwrap()
     }
 
-    /// Returns true if it is believed that we are near the head of the chain.
+    /// Returns the current status of the syncing.
+    ///
+    /// The return value should only ever be shown to the user and not used for any meaningful
+    /// logic.
+    pub async fn status(&self) -> SyncStatus {
+        let (send_back, rx) = oneshot::channel();
+
+        self.to_background
+            .lock()
+            .await
+            .send(ToBackground::Status { send_back })
+            .await
+            .unwrap();
+
+        rx.await.unwrap()
+    }
+
+    /// Returns the current status of the syncing, plus an unlimited stream that produces one
+    /// item every time the status changes.
+    ///
+    /// If the status changes multiple times while the stream isn't being polled, only the latest
+    /// status is reported.
+    pub async fn subscribe_status(&self) -> (SyncStatus, NotificationsReceiver<SyncStatus>) {
+        let (send_back, rx) = oneshot::channel();
+
+        self.to_background
+            .lock()
+            .await
+            .send(ToBackground::SubscribeStatus { send_back })
+            .await
+            .unwrap();
+
+        rx.await.unwrap()
+    }
+
+    /// Returns the number and hash of the current finalized block, and the GrandPa justification
+    /// or commit message that has been verified and that proves that this block is finalized.
+    ///
+    /// Returns `None` if no such proof is available, for example if the finalized block has been
+    /// reached through a warp sync, or if this chain is a parachain.
+    ///
+    /// This function is subject to race condition. The finalized block can change at any moment.
+    /// The proof returned by this function, however, is always valid for the returned block.
+    pub async fn finality_proof(&self) -> Option<(u64, [u8; 32], FinalityProof)> {
+        let (send_back, rx) = oneshot::channel();
+
+        self.to_background
+            .lock()
+            .await
+            .send(ToBackground::FinalityProof { send_back })
+            .await
+            .unwrap();
+
+        rx.await.unwrap()
+    }
+
+    /// Serializes the state of the finalized chain, including the header of the finalized block
+    /// and the GrandPa and Babe information necessary to verify its descendants, using the
+    /// format of [`smoldot::database::finalized_serialize`].
+    ///
+    /// The returned string can later be passed back as [`Config::chain_information`] (after
+    /// decoding) in order to resume syncing from the finalized block.
+    ///
+    /// Returns `None` if this chain is a parachain, as the finality of parachains is derived
+    /// from the finality of the relay chain.
     ///
-    /// The way this method is implemented is opaque and cannot be relied on. The return value
-    /// should only ever be shown to the user and not used for any meaningful logic.
-    pub async fn is_near_head_of_chain_heuristic(&self) -> bool {
+    /// This function is subject to race condition. The finalized block can change at any moment.
+    pub async fn serialize_chain_information(&self) -> Option<String> {
         let (send_back, rx) = oneshot::channel();
 
         self.to_background
             .lock()
             .await
-            .send(ToBackground::IsNearHeadOfChainHeuristic { send_back })
+            .send(ToBackground::SerializeChainInformation { send_back })
             .await
             .unwrap();
 
@@ -259,9 +369,13 @@ impl SyncService {
             fields: fields.clone(),
         };
 
-        // TODO: better peers selection ; don't just take the first 3
+        // Peers with the lowest latency are tried first.
         // TODO: must only ask the peers that know about this block
-        for target in self.network_service.peers_list().await.take(NUM_ATTEMPTS) {
+        let targets = self
+            .network_service
+            .sort_by_ping_time(self.network_service.peers_list().await)
+            .await;
+        for target in targets.into_iter().take(NUM_ATTEMPTS) {
             let mut result = match self
                 .network_service
                 .clone()
@@ -301,11 +415,19 @@ impl SyncService {
             }) {
                 continue;
             }
-            match (&result.header, &result.body) {
-                (Some(_), Some(_)) => {
-                    // TODO: verify correctness of body
+            if let (Some(header), Some(body)) = (&result.header, &result.body) {
+                // Verify that the body matches the extrinsics root found in the header. The
+                // version of the trie entries format used to calculate this root depends on the
+                // runtime, and both versions are consequently accepted.
+                let extrinsics_root = *header::decode(header).unwrap().extrinsics_root;
+                let body_matches = [trie::TrieEntryVersion::V0, trie::TrieEntryVersion::V1]
+                    .iter()
+                    .any(|version| {
+                        header::extrinsics_root(body.iter(), *version) == extrinsics_root
+                    });
+                if !body_matches {
+                    continue;
                 }
-                _ => {}
             }
 
             return Ok(result);
@@ -339,15 +461,20 @@ impl SyncService {
 
         let mut outcome_errors = Vec::with_capacity(NUM_ATTEMPTS);
 
-        // TODO: better peers selection ; don't just take the first 3
+        // Peers with the lowest latency are tried first.
         // TODO: must only ask the peers that know about this block
-        for target in self.network_service.peers_list().await.take(NUM_ATTEMPTS) {
+        let targets = self
+            .network_service
+            .sort_by_ping_time(self.network_service.peers_list().await)
+            .await;
+        let targets = self.prioritize_affinity(block_hash, targets).await;
+        for target in targets.into_iter().take(NUM_ATTEMPTS) {
             let result = self
                 .network_service
                 .clone()
                 .storage_proof_request(
                     self.network_chain_index,
-                    target,
+                    target.clone(),
                     protocol::StorageProofRequestConfig {
                         block_hash: *block_hash,
                         keys: requested_keys.clone(),
@@ -373,8 +500,16 @@ impl SyncService {
                 });
 
             match result {
-                Ok(values) => return Ok(values),
+                Ok(values) => {
+                    self.proofs_affinity.lock().await.put(*block_hash, target);
+                    return Ok(values);
+                }
                 Err(err) => {
+                    if err.is_invalid_proof() {
+                        self.network_service
+                            .report_peer(&target, reputation::Penalty::InvalidProof)
+                            .await;
+                    }
                     outcome_errors.push(err);
                 }
             }
@@ -402,18 +537,22 @@ impl SyncService {
 
             let mut outcome_errors = Vec::with_capacity(NUM_ATTEMPTS);
 
-            // TODO: better peers selection ; don't just take the first 3
-            for target in self
-                .peers_assumed_know_blocks(block_number, block_hash)
-                .await
-                .take(NUM_ATTEMPTS)
-            {
+            // Peers with the lowest latency are tried first.
+            let targets = self
+                .network_service
+                .sort_by_ping_time(
+                    self.peers_assumed_know_blocks(block_number, block_hash)
+                        .await,
+                )
+                .await;
+            let targets = self.prioritize_affinity(block_hash, targets).await;
+            for target in targets.into_iter().take(NUM_ATTEMPTS) {
                 let result = self
                     .network_service
                     .clone()
                     .storage_proof_request(
                         self.network_chain_index,
-                        target,
+                        target.clone(),
                         protocol::StorageProofRequestConfig {
                             block_hash: *block_hash,
                             keys: prefix_scan.requested_keys().map(|nibbles| {
@@ -429,16 +568,23 @@ impl SyncService {
                         match prefix_scan.resume(proof.iter().map(|v| &v[..])) {
                             Ok(prefix_proof::ResumeOutcome::InProgress(scan)) => {
                                 // Continue next step of the proof.
+                                self.proofs_affinity.lock().await.put(*block_hash, target);
                                 prefix_scan = scan;
                                 continue 'main_scan;
                             }
                             Ok(prefix_proof::ResumeOutcome::Success { keys }) => {
+                                self.proofs_affinity.lock().await.put(*block_hash, target);
                                 return Ok(keys);
                             }
                             Err((scan, err)) => {
                                 prefix_scan = scan;
-                                outcome_errors
-                                    .push(StorageQueryErrorDetail::ProofVerification(err));
+                                let err = StorageQueryErrorDetail::ProofVerification(err);
+                                if err.is_invalid_proof() {
+                                    self.network_service
+                                        .report_peer(&target, reputation::Penalty::InvalidProof)
+                                        .await;
+                                }
+                                outcome_errors.push(err);
                             }
                         }
                     }
@@ -468,20 +614,30 @@ impl SyncService {
 
         let mut outcome_errors = Vec::with_capacity(NUM_ATTEMPTS);
 
-        // TODO: better peers selection ; don't just take the first 3
-        for target in self
-            .peers_assumed_know_blocks(block_number, &config.block_hash)
-            .await
-            .take(NUM_ATTEMPTS)
-        {
+        // Peers with the lowest latency are tried first.
+        let targets = self
+            .network_service
+            .sort_by_ping_time(
+                self.peers_assumed_know_blocks(block_number, &config.block_hash)
+                    .await,
+            )
+            .await;
+        let targets = self.prioritize_affinity(&config.block_hash, targets).await;
+        for target in targets.into_iter().take(NUM_ATTEMPTS) {
             let result = self
                 .network_service
                 .clone()
-                .call_proof_request(self.network_chain_index, target, config.clone())
+                .call_proof_request(self.network_chain_index, target.clone(), config.clone())
                 .await;
 
             match result {
-                Ok(value) if !value.is_empty() => return Ok(value),
+                Ok(value) if !value.is_empty() => {
+                    self.proofs_affinity
+                        .lock()
+                        .await
+                        .put(config.block_hash, target);
+                    return Ok(value);
+                }
                 // TODO: this check of emptiness is a bit of a hack; it is necessary because Substrate responds to requests about blocks it doesn't know with an empty proof
                 Ok(_) => outcome_errors.push(service::CallProofRequestError::Request(
                     smoldot::libp2p::peers::RequestError::Connection(
@@ -498,6 +654,23 @@ impl SyncService {
             errors: outcome_errors,
         })
     }
+
+    /// Moves to the front of `targets` the peer that has most recently answered a proof request
+    /// concerning the given block, if any.
+    async fn prioritize_affinity(
+        &self,
+        block_hash: &[u8; 32],
+        mut targets: Vec<PeerId>,
+    ) -> Vec<PeerId> {
+        if let Some(preferred) = self.proofs_affinity.lock().await.get(block_hash) {
+            if let Some(position) = targets.iter().position(|t| t == preferred) {
+                let preferred = targets.remove(position);
+                targets.insert(0, preferred);
+            }
+        }
+
+        targets
+    }
 }
 
 /// Error that can happen when calling [`SyncService::storage_query`].
@@ -549,6 +722,21 @@ pub enum StorageQueryErrorDetail {
     ProofVerification(proof_verify::Error),
 }
 
+impl StorageQueryErrorDetail {
+    /// Returns `true` if the remote has sent back a proof that is invalid, as opposed to a proof
+    /// that doesn't concern the requested block.
+    fn is_invalid_proof(&self) -> bool {
+        match self {
+            StorageQueryErrorDetail::Network(_) => false,
+            // See the note in `StorageQueryError::is_network_problem`.
+            StorageQueryErrorDetail::ProofVerification(proof_verify::Error::TrieRootNotFound) => {
+                false
+            }
+            StorageQueryErrorDetail::ProofVerification(_) => true,
+        }
+    }
+}
+
 /// Error that can happen when calling [`SyncService::call_proof_query`].
 #[derive(Debug, Clone)]
 pub struct CallProofQueryError {
@@ -594,6 +782,9 @@ pub struct SubscribeAll {
 
     /// Channel onto which new blocks are sent. The channel gets closed if it is full when a new
     /// block needs to be reported.
+    ///
+    /// In case of a gap in the finality, a [`Notification::GapDetected`] is sent rather than the
+    /// channel being closed.
     pub new_blocks: mpsc::Receiver<Notification>,
 }
 
@@ -629,6 +820,28 @@ pub enum Notification {
 
     /// A new block has been added to the list of unfinalized blocks.
     Block(BlockNotification),
+
+    /// The chain has jumped to a finalized block that isn't necessarily a descendant of the
+    /// blocks that have been reported earlier, such as after a Grandpa warp syncing, or some
+    /// of the non-finalized blocks reported earlier have been discarded because they belong to
+    /// forks that are too old (see [`Config::max_non_finalized_fork_depth`]).
+    ///
+    /// All the blocks that have been reported before this notification should be discarded, and
+    /// the state of the chain should be reset to the content of this notification. Following
+    /// notifications refer to the blocks found in this notification.
+    GapDetected {
+        /// SCALE-encoded header of the new finalized block.
+        new_finalized_block_scale_encoded_header: Vec<u8>,
+
+        /// List of all known non-finalized blocks that descend from the new finalized block.
+        ///
+        /// Only one element in this list has [`BlockNotification::is_new_best`] equal to true,
+        /// unless the list is empty.
+        ///
+        /// The blocks are guaranteed to be ordered so that parents are always found before their
+        /// children.
+        non_finalized_blocks_ancestry_order: Vec<BlockNotification>,
+    },
 }
 
 /// Notification about a new block.
@@ -662,8 +875,14 @@ pub struct BlockNotification {
 }
 
 enum ToBackground {
-    /// See [`SyncService::is_near_head_of_chain_heuristic`].
-    IsNearHeadOfChainHeuristic { send_back: oneshot::Sender<bool> },
+    /// See [`SyncService::status`].
+    Status {
+        send_back: oneshot::Sender<SyncStatus>,
+    },
+    /// See [`SyncService::subscribe_status`].
+    SubscribeStatus {
+        send_back: oneshot::Sender<(SyncStatus, NotificationsReceiver<SyncStatus>)>,
+    },
     /// See [`SyncService::subscribe_all`].
     SubscribeAll {
         send_back: oneshot::Sender<SubscribeAll>,
@@ -679,4 +898,12 @@ enum ToBackground {
     SyncingPeers {
         send_back: oneshot::Sender<Vec<(PeerId, protocol::Role, u64, [u8; 32])>>,
     },
+    /// See [`SyncService::finality_proof`].
+    FinalityProof {
+        send_back: oneshot::Sender<Option<(u64, [u8; 32], FinalityProof)>>,
+    },
+    /// See [`SyncService::serialize_chain_information`].
+    SerializeChainInformation {
+        send_back: oneshot::Sender<Option<String>>,
+    },
 }
@@ -15,8 +15,8 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use super::{BlockNotification, Notification, SubscribeAll, ToBackground};
-use crate::{ffi, network_service, runtime_service};
+use super::{BlockNotification, Notification, SubscribeAll, SyncStatus, ToBackground};
+use crate::{ffi, lossy_channel, network_service, runtime_service};
 
 use futures::{channel::mpsc, prelude::*};
 use smoldot::{
@@ -30,11 +30,13 @@ use smoldot::{
 };
 use std::{collections::HashMap, iter, sync::Arc};
 
+#[allow(clippy::too_many_arguments)]
 pub(super) async fn start_parachain(
     log_target: String,
     chain_information: chain::chain_information::ValidChainInformation,
     relay_chain_sync: Arc<runtime_service::RuntimeService>,
     parachain_id: u32,
+    max_non_finalized_fork_depth: u64,
     mut from_foreground: mpsc::Receiver<ToBackground>,
     network_chain_index: usize,
     mut from_network_service: mpsc::Receiver<network_service::Event>,
@@ -55,9 +57,19 @@ pub(super) async fn start_parachain(
     // Maps `PeerId`s to their indices within `sync_sources`.
     let mut sync_sources_map = HashMap::new();
 
-    // `true` after a parachain block has been fetched from the parachain.
-    // TODO: handled in a hacky way; unclear how to handle properly
-    let mut is_near_head_of_chain;
+    // Status of the syncing of the relay chain, and stream of updates to this status.
+    let (mut relay_chain_status, mut relay_chain_status_updates) =
+        relay_chain_sync.subscribe_status().await;
+
+    // List of senders that get notified when the status of the syncing changes, and status
+    // that has been reported to them most recently.
+    let mut status_subscriptions = Vec::<lossy_channel::Sender<SyncStatus>>::new();
+    let mut reported_status = parachain_status(&relay_chain_status, false);
+
+    // List of senders that get notified when the tree of blocks is modified.
+    // Note that this list is kept across resets of the syncing. Whenever the syncing is reset,
+    // a [`Notification::GapDetected`] is sent to all the senders.
+    let mut all_subscriptions = Vec::<mpsc::Sender<_>>::new();
 
     loop {
         // Stream of blocks of the relay chain this parachain is registered on.
@@ -70,8 +82,6 @@ pub(super) async fn start_parachain(
             ))
         );
 
-        is_near_head_of_chain = relay_chain_sync.is_near_head_of_chain_heuristic().await;
-
         // Block the rest of the syncing before we could determine the parahead of the relay
         // chain finalized block.
         let (finalized_parahead, finalized_parahead_valid) = if let Ok(finalized) = parahead(
@@ -109,10 +119,27 @@ pub(super) async fn start_parachain(
             async_tree.input_insert_block(hash, parent, false, block.is_new_best);
         }
 
-        // List of senders that get notified when the tree of blocks is modified.
-        // Note that this list is created in the inner loop, as to be cleared if the relay chain
-        // blocks stream has a gap.
-        let mut all_subscriptions = Vec::<mpsc::Sender<_>>::new();
+        // Notify the existing subscriptions that the state of the chain has been reset. The
+        // paraheads of the non-finalized relay chain blocks haven't been fetched yet, and the
+        // list of non-finalized blocks is thus empty.
+        // If the finalized parahead couldn't be fetched, the existing subscriptions are left
+        // untouched, as nothing will be reported to them until the next reset.
+        if finalized_parahead_valid {
+            // Elements in `all_subscriptions` are removed one by one and inserted back if the
+            // channel is still open.
+            for index in (0..all_subscriptions.len()).rev() {
+                let mut sender = all_subscriptions.swap_remove(index);
+                let notif = Notification::GapDetected {
+                    new_finalized_block_scale_encoded_header: async_tree
+                        .finalized_async_user_data()
+                        .clone(),
+                    non_finalized_blocks_ancestry_order: Vec::new(),
+                };
+                if sender.try_send(notif).is_ok() {
+                    all_subscriptions.push(sender);
+                }
+            }
+        }
 
         // List of in-progress parahead fetching operations.
         let mut in_progress_paraheads = stream::FuturesUnordered::new();
@@ -121,6 +148,21 @@ pub(super) async fn start_parachain(
         let mut wakeup_deadline = future::Either::Right(future::pending());
 
         loop {
+            // Notify the subscriptions if the status of the syncing has changed.
+            let new_status = parachain_status(&relay_chain_status, finalized_parahead_valid);
+            if new_status != reported_status {
+                reported_status = new_status;
+
+                // Elements in `status_subscriptions` are removed one by one and inserted back
+                // if the channel is still open.
+                for index in (0..status_subscriptions.len()).rev() {
+                    let mut sender = status_subscriptions.swap_remove(index);
+                    if sender.send(reported_status.clone()).is_ok() {
+                        status_subscriptions.push(sender);
+                    }
+                }
+            }
+
             // Start fetching paraheads of new blocks whose parahead needs to be fetched.
             if finalized_parahead_valid {
                 loop {
@@ -160,14 +202,20 @@ pub(super) async fn start_parachain(
                     // Do nothing. This is simply to wake up and loop again.
                 },
 
+                new_relay_chain_status = relay_chain_status_updates.next() => {
+                    // The stream is never closed unless the relay chain runtime service has
+                    // shut down.
+                    if let Some(new_relay_chain_status) = new_relay_chain_status {
+                        relay_chain_status = new_relay_chain_status;
+                    }
+                },
+
                 relay_chain_notif = relay_chain_subscribe_all.new_blocks.next() => {
                     let relay_chain_notif = match relay_chain_notif {
                         Some(n) => n,
                         None => break, // Jumps to the outer loop to recreate the channel.
                     };
 
-                    is_near_head_of_chain = relay_chain_sync.is_near_head_of_chain_heuristic().await;
-
                     match relay_chain_notif {
                         Notification::Finalized { hash, best_block_hash } => {
                             log::debug!(
@@ -198,6 +246,29 @@ pub(super) async fn start_parachain(
 
                             let parent = async_tree.input_iter_unordered().find(|(_, b, _, _)| **b == block.parent_hash).map(|b| b.0); // TODO: check if finalized
                             async_tree.input_insert_block(hash, parent, false, block.is_new_best);
+
+                            // The relay chain sync service normally discards old forks. As a
+                            // safety measure, the tree is also bounded here.
+                            if async_tree.input_max_fork_depth() > max_non_finalized_fork_depth {
+                                log::warn!(
+                                    target: &log_target,
+                                    "Relay chain fork older than {} blocks detected; resetting parachain syncing",
+                                    max_non_finalized_fork_depth
+                                );
+
+                                // Jumps to the outer loop in order to reset the syncing.
+                                break;
+                            }
+                        }
+                        Notification::GapDetected { new_finalized_block_scale_encoded_header, .. } => {
+                            log::debug!(
+                                target: &log_target,
+                                "Gap in relay chain blocks detected; new finalized block is 0x{}",
+                                HashDisplay(&header::hash_from_scale_encoded_header(&new_finalized_block_scale_encoded_header))
+                            );
+
+                            // Jumps to the outer loop in order to reset the syncing.
+                            break;
                         }
                     };
 
@@ -298,7 +369,7 @@ pub(super) async fn start_parachain(
                             // parachains later.
                             log::log!(
                                 target: &log_target,
-                                if is_near_head_of_chain && !error.is_network_problem() { // TODO: is is_near_head_of_chain the correct flag?
+                                if relay_chain_status == SyncStatus::NearHead && !error.is_network_problem() { // TODO: is the relay chain status the correct flag?
                                     log::Level::Error
                                 } else {
                                     log::Level::Debug
@@ -325,8 +396,13 @@ pub(super) async fn start_parachain(
                     // but care should be taken about this.
 
                     match foreground_message {
-                        ToBackground::IsNearHeadOfChainHeuristic { send_back } => {
-                            let _ = send_back.send(is_near_head_of_chain && finalized_parahead_valid);
+                        ToBackground::Status { send_back } => {
+                            let _ = send_back.send(parachain_status(&relay_chain_status, finalized_parahead_valid));
+                        },
+                        ToBackground::SubscribeStatus { send_back } => {
+                            let (tx, rx) = lossy_channel::channel();
+                            status_subscriptions.push(tx);
+                            let _ = send_back.send((reported_status.clone(), rx));
                         },
                         ToBackground::SubscribeAll { send_back, buffer_size } => {
                             let (tx, new_blocks) = mpsc::channel(buffer_size.saturating_sub(1));
@@ -378,6 +454,16 @@ pub(super) async fn start_parachain(
                                 (peer_id, role, height, *hash)
                             }).collect());
                         }
+                        ToBackground::FinalityProof { send_back } => {
+                            // Parachains don't use GrandPa. Their finality is derived from the
+                            // finality of the relay chain.
+                            let _ = send_back.send(None);
+                        }
+                        ToBackground::SerializeChainInformation { send_back } => {
+                            // The finalized block of a parachain is determined by the relay
+                            // chain, and there is thus nothing worth persisting.
+                            let _ = send_back.send(None);
+                        }
                     }
                 },
 
@@ -478,7 +564,10 @@ async fn parahead(
                 return Err(ParaheadError::ReadOnlyRuntime(error.detail));
             }
             read_only_runtime_host::RuntimeHostVm::StorageGet(get) => {
-                let storage_value = match runtime_call_lock.storage_entry(&get.key_as_vec()) {
+                let child_trie = get.child_trie().map(|c| c.as_ref().to_vec());
+                let storage_value = match runtime_call_lock
+                    .storage_entry(child_trie.as_deref(), &get.key_as_vec())
+                {
                     Ok(v) => v,
                     Err(err) => {
                         runtime_call_lock.unlock(
@@ -489,8 +578,20 @@ async fn parahead(
                 };
                 runtime_call = get.inject_value(storage_value.map(iter::once));
             }
-            read_only_runtime_host::RuntimeHostVm::NextKey(_) => {
-                todo!() // TODO:
+            read_only_runtime_host::RuntimeHostVm::NextKey(next_key) => {
+                let child_trie = next_key.child_trie().map(|c| c.as_ref().to_vec());
+                let key = next_key.key().as_ref().to_vec();
+                let key = match runtime_call_lock.storage_next_key(child_trie.as_deref(), &key) {
+                    Ok(v) => v,
+                    Err(err) => {
+                        runtime_call_lock.unlock(
+                            read_only_runtime_host::RuntimeHostVm::NextKey(next_key)
+                                .into_prototype(),
+                        );
+                        return Err(ParaheadError::Call(err));
+                    }
+                };
+                runtime_call = next_key.inject_key(key);
             }
             read_only_runtime_host::RuntimeHostVm::StorageRoot(storage_root) => {
                 runtime_call = storage_root.resume(runtime_call_lock.block_storage_root());
@@ -508,6 +609,19 @@ async fn parahead(
     }
 }
 
+/// Returns the status of the syncing of the parachain, given the status of the syncing of the
+/// relay chain and whether the parahead of the relay chain finalized block is known.
+fn parachain_status(relay_chain_status: &SyncStatus, finalized_parahead_valid: bool) -> SyncStatus {
+    match relay_chain_status {
+        // The parachain can't be near the head of its chain if the parahead of the relay chain
+        // finalized block is unknown. The number of blocks to download isn't known either.
+        SyncStatus::NearHead if !finalized_parahead_valid => {
+            SyncStatus::DownloadingBlocks { behind_by: 0 }
+        }
+        status => status.clone(),
+    }
+}
+
 #[derive(derive_more::Display)]
 enum ParaheadError {
     Call(runtime_service::RuntimeCallError),
@@ -15,12 +15,14 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use super::{BlockNotification, Notification, SubscribeAll, ToBackground};
-use crate::{ffi, network_service};
+use super::{BlockNotification, Notification, SubscribeAll, SyncStatus, ToBackground};
+use crate::{ffi, lossy_channel, network_service};
 
 use futures::{channel::mpsc, prelude::*};
 use smoldot::{
-    chain, header,
+    chain::{self, blocks_tree},
+    database::finalized_serialize,
+    header,
     informant::HashDisplay,
     libp2p,
     network::{self, protocol},
@@ -32,8 +34,14 @@ use std::{
     convert::TryFrom as _,
     num::{NonZeroU32, NonZeroU64},
     sync::Arc,
+    time::Duration,
 };
 
+/// Duration after which a GrandPa catch-up request that hasn't led to any progress is
+/// considered as failed, in which case a new request can be sent to another peer.
+const GRANDPA_CATCH_UP_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[allow(clippy::too_many_arguments)]
 pub(super) async fn start_relay_chain(
     log_target: String,
     chain_information: chain::chain_information::ValidChainInformation,
@@ -41,6 +49,10 @@ pub(super) async fn start_relay_chain(
     network_service: Arc<network_service::NetworkService>,
     network_chain_index: usize,
     mut from_network_service: mpsc::Receiver<network_service::Event>,
+    max_non_finalized_fork_depth: u64,
+    min_sources_per_block: NonZeroU32,
+    banned_blocks: Vec<[u8; 32]>,
+    fork_blocks: Vec<(u64, [u8; 32])>,
 ) -> impl Future<Output = ()> {
     // TODO: implicit generics
     let mut sync = all::AllSync::<_, (libp2p::PeerId, protocol::Role), ()>::new(all::Config {
@@ -52,6 +64,9 @@ pub(super) async fn start_relay_chain(
         },
         max_disjoint_headers: 1024,
         max_requests_per_block: NonZeroU32::new(3).unwrap(),
+        min_sources_per_block,
+        banned_blocks,
+        fork_blocks,
         download_ahead_blocks: {
             // Verifying a block mostly consists in:
             //
@@ -83,9 +98,26 @@ pub(super) async fn start_relay_chain(
         let mut pending_storage_requests = stream::FuturesUnordered::new();
         let mut all_notifications = Vec::<mpsc::Sender<Notification>>::new();
 
+        // List of senders that get notified when the status of the syncing changes, and status
+        // that has been reported to them most recently.
+        let mut status_subscriptions = Vec::<lossy_channel::Sender<SyncStatus>>::new();
+        let mut reported_status = sync.status();
+
         let mut has_new_best = false;
         let mut has_new_finalized = false;
 
+        // If `Some`, a GrandPa catch-up request has been sent to the given peer since the
+        // finalized block has last changed, and no other request should be sent before the given
+        // moment. Used in order to not send catch-up requests to every single peer that is ahead
+        // of the local node.
+        let mut grandpa_catch_up_requested: Option<(libp2p::PeerId, ffi::Instant)> = None;
+
+        // Parent hash and height of each non-finalized block that has been verified, plus its
+        // SCALE-encoded header if it contains a change in the list of GrandPa authorities. Used
+        // in order to report these changes to the network service even when multiple blocks
+        // are finalized at once.
+        let mut non_finalized_blocks = HashMap::<[u8; 32], ([u8; 32], u64, Option<Vec<u8>>)>::new();
+
         // Main loop of the syncing logic.
         loop {
             loop {
@@ -314,6 +346,65 @@ pub(super) async fn start_relay_chain(
                                 }
 
                                 sync = sync_out;
+
+                                if let Some(header) = sync
+                                    .non_finalized_blocks_ancestry_order()
+                                    .find(|h| h.hash() == verified_hash)
+                                {
+                                    let has_grandpa_change = header.digest.logs().any(|log| {
+                                        matches!(
+                                            log,
+                                            header::DigestItemRef::GrandpaConsensus(
+                                                header::GrandpaConsensusLogRef::ScheduledChange(_)
+                                                    | header::GrandpaConsensusLogRef::ForcedChange { .. }
+                                            )
+                                        )
+                                    });
+                                    non_finalized_blocks.insert(
+                                        verified_hash,
+                                        (
+                                            *header.parent_hash,
+                                            header.number,
+                                            if has_grandpa_change {
+                                                Some(header.scale_encoding_vec())
+                                            } else {
+                                                None
+                                            },
+                                        ),
+                                    );
+                                }
+
+                                if is_new_finalized {
+                                    report_grandpa_finalized_blocks(
+                                        &sync,
+                                        &mut non_finalized_blocks,
+                                        &network_service,
+                                        network_chain_index,
+                                    )
+                                    .await;
+                                }
+
+                                // If finality is stalled, forks that are too far behind the
+                                // best block are discarded in order to bound the memory usage.
+                                let pruned = sync.prune_old_forks(max_non_finalized_fork_depth);
+                                if !pruned.is_empty() {
+                                    log::warn!(
+                                        target: &log_target,
+                                        "Discarded {} non-finalized block(s) belonging to forks \
+                                        more than {} blocks behind the best block",
+                                        pruned.len(),
+                                        max_non_finalized_fork_depth
+                                    );
+
+                                    for (header, _) in &pruned {
+                                        non_finalized_blocks.remove(&header.hash());
+                                    }
+
+                                    // Subscribers might be tracking the discarded blocks and
+                                    // are thus notified of the new state of the chain.
+                                    notify_gap(&sync, &mut all_notifications);
+                                }
+
                                 continue;
                             }
                             all::HeaderVerifyOutcome::Error {
@@ -362,6 +453,7 @@ pub(super) async fn start_relay_chain(
             // TODO: handle this differently
             if has_new_finalized {
                 has_new_finalized = false;
+                grandpa_catch_up_requested = None;
 
                 // If the chain uses GrandPa, the networking has to be kept up-to-date with the
                 // state of finalization for other peers to send back relevant gossip messages.
@@ -401,6 +493,22 @@ pub(super) async fn start_relay_chain(
                 crate::yield_once().await;
             }
 
+            // Notify the subscriptions if the status of the syncing has changed.
+            let new_status = sync.status();
+            if new_status != reported_status {
+                reported_status = new_status;
+
+                // Elements in `status_subscriptions` are removed one by one and inserted back
+                // if the channel is still open.
+                for index in (0..status_subscriptions.len()).rev() {
+                    let mut subscription = status_subscriptions.swap_remove(index);
+                    if subscription.send(reported_status.clone()).is_err() {
+                        continue;
+                    }
+                    status_subscriptions.push(subscription);
+                }
+            }
+
             // All requests have been started.
             // Now waiting for some event to happen: a network event, a request from the frontend
             // of the sync service, or a request being finished.
@@ -417,6 +525,10 @@ pub(super) async fn start_relay_chain(
                         },
                     };
 
+                    // SCALE-encoded GrandPa commit message to verify, if any, and the peer
+                    // that has sent it.
+                    let mut grandpa_commit = None;
+
                     match network_event {
                         network_service::Event::Connected { peer_id, role, chain_index, best_block_number, best_block_hash }
                             if chain_index == network_chain_index =>
@@ -482,43 +594,125 @@ pub(super) async fn start_relay_chain(
                                 },
                             }
                         },
-                        network_service::Event::GrandpaCommitMessage { chain_index, message }
+                        network_service::Event::GrandpaNeighborPacket { chain_index, peer_id, state }
                             if chain_index == network_chain_index =>
                         {
-                            match sync.grandpa_commit_message(&message.as_encoded()) {
-                                Ok(()) => {
-                                    has_new_finalized = true;
-                                    has_new_best = true;  // TODO: done in case finality changes the best block; make this clearer in the sync layer
-
-                                    // Elements in `all_notifications` are removed one by one and
-                                    // inserted back if the channel is still open.
-                                    for index in (0..all_notifications.len()).rev() {
-                                        let mut subscription = all_notifications.swap_remove(index);
-                                        if subscription
-                                            .try_send(Notification::Finalized {
-                                                hash: sync.finalized_block_header().hash(),
-                                                best_block_hash: sync.best_block_hash(),
-                                            })
-                                            .is_err()
-                                        {
-                                            continue;
-                                        }
-                                        all_notifications.push(subscription);
-                                    }
-                                },
-                                Err(err) => {
-                                    log::warn!(
-                                        target: &log_target,
-                                        "Error when verifying GrandPa commit message: {}", err
-                                    );
-                                }
+                            // If the peer has finalized blocks that the local node hasn't, ask
+                            // it for the votes of its latest round. This makes finality progress
+                            // even if the peers don't gossip commit messages.
+                            let local_set_id =
+                                if let chain::chain_information::ChainInformationFinalityRef::Grandpa {
+                                    after_finalized_block_authorities_set_id,
+                                    ..
+                                } = sync.as_chain_information().as_ref().finality
+                                {
+                                    Some(after_finalized_block_authorities_set_id)
+                                } else {
+                                    None
+                                };
+
+                            // If a previous request hasn't led to any progress before its timeout,
+                            // a new request is sent to a different peer.
+                            let can_request = grandpa_catch_up_requested
+                                .as_ref()
+                                .map_or(true, |(requested_peer_id, not_before)| {
+                                    *requested_peer_id != peer_id && *not_before <= ffi::Instant::now()
+                                });
+
+                            if can_request
+                                && local_set_id == Some(state.set_id)
+                                && u64::from(state.commit_finalized_height) > sync.finalized_block_header().number
+                            {
+                                grandpa_catch_up_requested =
+                                    Some((peer_id.clone(), ffi::Instant::now() + GRANDPA_CATCH_UP_TIMEOUT));
+                                network_service
+                                    .send_grandpa_catch_up_request(
+                                        network_chain_index,
+                                        &peer_id,
+                                        protocol::CatchUpRequest {
+                                            round_number: state.round_number,
+                                            set_id: state.set_id,
+                                        },
+                                    )
+                                    .await;
                             }
                         },
+                        network_service::Event::GrandpaCommitMessage { chain_index, peer_id, message }
+                            if chain_index == network_chain_index =>
+                        {
+                            grandpa_commit = Some((message.as_encoded().to_vec(), peer_id));
+                        },
+                        network_service::Event::GrandpaCatchUp { chain_index, peer_id, catch_up }
+                            if chain_index == network_chain_index =>
+                        {
+                            log::debug!(
+                                target: &log_target,
+                                "Processing GrandPa catch-up from {}", peer_id
+                            );
+
+                            // Allow sending a new catch-up request to another peer in case
+                            // this one doesn't lead to any progress.
+                            grandpa_catch_up_requested = None;
+
+                            // The pre-commits of the catch-up are turned into a commit message
+                            // that finalizes the base of the catch-up.
+                            grandpa_commit = Some((catch_up.base_commit_message(), peer_id));
+                        },
                         _ => {
                             // Different chain index.
                         }
                     }
 
+                    if let Some((grandpa_commit, sender)) = grandpa_commit {
+                        match sync.grandpa_commit_message(&grandpa_commit) {
+                            Ok(()) => {
+                                has_new_finalized = true;
+                                has_new_best = true;  // TODO: done in case finality changes the best block; make this clearer in the sync layer
+
+                                report_grandpa_finalized_blocks(
+                                    &sync,
+                                    &mut non_finalized_blocks,
+                                    &network_service,
+                                    network_chain_index,
+                                )
+                                .await;
+
+                                // Elements in `all_notifications` are removed one by one and
+                                // inserted back if the channel is still open.
+                                for index in (0..all_notifications.len()).rev() {
+                                    let mut subscription = all_notifications.swap_remove(index);
+                                    if subscription
+                                        .try_send(Notification::Finalized {
+                                            hash: sync.finalized_block_header().hash(),
+                                            best_block_hash: sync.best_block_hash(),
+                                        })
+                                        .is_err()
+                                    {
+                                        continue;
+                                    }
+                                    all_notifications.push(subscription);
+                                }
+                            },
+                            Err(blocks_tree::CommitVerifyError::VerificationFailed(err)) => {
+                                // The signatures of the message don't match the authorities
+                                // set. The message has been crafted by the sender.
+                                log::warn!(
+                                    target: &log_target,
+                                    "Invalid GrandPa commit message from {}: {}", sender, err
+                                );
+                                network_service
+                                    .report_peer(&sender, network::reputation::Penalty::InvalidGrandpaMessage)
+                                    .await;
+                            }
+                            Err(err) => {
+                                log::warn!(
+                                    target: &log_target,
+                                    "Error when verifying GrandPa commit message: {}", err
+                                );
+                            }
+                        }
+                    }
+
                     continue;
                 }
 
@@ -534,8 +728,13 @@ pub(super) async fn start_relay_chain(
                     };
 
                     match message {
-                        ToBackground::IsNearHeadOfChainHeuristic { send_back } => {
-                            let _ = send_back.send(sync.is_near_head_of_chain_heuristic());
+                        ToBackground::Status { send_back } => {
+                            let _ = send_back.send(sync.status());
+                        }
+                        ToBackground::SubscribeStatus { send_back } => {
+                            let (tx, rx) = lossy_channel::channel();
+                            status_subscriptions.push(tx);
+                            let _ = send_back.send((reported_status.clone(), rx));
                         }
                         ToBackground::SubscribeAll { send_back, buffer_size } => {
                             let (tx, new_blocks) = mpsc::channel(buffer_size.saturating_sub(1));
@@ -586,6 +785,18 @@ pub(super) async fn start_relay_chain(
                                 .collect::<Vec<_>>();
                             let _ = send_back.send(out);
                         }
+                        ToBackground::FinalityProof { send_back } => {
+                            let proof = sync.finality_proof().map(|proof| {
+                                let finalized_header = sync.finalized_block_header();
+                                (finalized_header.number, finalized_header.hash(), proof.clone())
+                            });
+                            let _ = send_back.send(proof);
+                        }
+                        ToBackground::SerializeChainInformation { send_back } => {
+                            let _ = send_back.send(Some(finalized_serialize::encode_chain(
+                                sync.as_chain_information(),
+                            )));
+                        }
                     };
 
                     continue;
@@ -672,11 +883,136 @@ pub(super) async fn start_relay_chain(
                     );
                     has_new_finalized = true;
                     has_new_best = true;
-                    // Since there is a gap in the blocks, all active notifications to all blocks
-                    // must be cleared.
-                    all_notifications.clear();
+
+                    report_grandpa_finalized_blocks(
+                        &sync,
+                        &mut non_finalized_blocks,
+                        &network_service,
+                        network_chain_index,
+                    )
+                    .await;
+
+                    // Since there is a gap in the blocks, all active subscriptions are notified
+                    // of the new state of the chain.
+                    notify_gap(&sync, &mut all_notifications);
                 }
             }
         }
     }
 }
+
+/// Reports the current finalized block of `sync` and its justification, if any, to the network
+/// service, in order for it to be able to answer the GrandPa warp sync requests of other nodes.
+/// The newly-finalized blocks found in `non_finalized_blocks` that change the list of
+/// authorities are reported beforehand, and the finalized blocks are removed from
+/// `non_finalized_blocks`.
+///
+/// The returned future doesn't borrow `sync` or `non_finalized_blocks`.
+fn report_grandpa_finalized_blocks<TRq, TSrc, TBl>(
+    sync: &all::AllSync<TRq, TSrc, TBl>,
+    non_finalized_blocks: &mut HashMap<[u8; 32], ([u8; 32], u64, Option<Vec<u8>>)>,
+    network_service: &Arc<network_service::NetworkService>,
+    network_chain_index: usize,
+) -> impl Future<Output = ()> {
+    // Walk the ancestry of the finalized block in order to find the newly-finalized blocks that
+    // change the list of authorities.
+    let finalized_block_header = sync.finalized_block_header();
+    let mut authorities_changes = Vec::new();
+    let mut cursor = *finalized_block_header.parent_hash;
+    while let Some((parent_hash, _, scale_encoded_header)) = non_finalized_blocks.get(&cursor) {
+        if let Some(scale_encoded_header) = scale_encoded_header {
+            authorities_changes.push(scale_encoded_header.clone());
+        }
+        cursor = *parent_hash;
+    }
+    authorities_changes.reverse();
+
+    let finalized_block_number = finalized_block_header.number;
+    non_finalized_blocks.retain(|_, (_, number, _)| *number > finalized_block_number);
+
+    let authorities_set_id = match sync.as_chain_information().as_ref().finality {
+        chain::chain_information::ChainInformationFinalityRef::Grandpa {
+            after_finalized_block_authorities_set_id,
+            ..
+        } => Some(after_finalized_block_authorities_set_id),
+        _ => None,
+    };
+
+    // Commit messages can't be used as part of a warp sync proof.
+    let justification = match sync.finality_proof() {
+        Some(all::FinalityProof::GrandpaJustification(justification)) => {
+            Some(justification.clone())
+        }
+        _ => None,
+    };
+
+    let scale_encoded_header = sync.finalized_block_header().scale_encoding_vec();
+    let network_service = network_service.clone();
+
+    async move {
+        if let Some(authorities_set_id) = authorities_set_id {
+            // The identifier of the authorities set of these blocks is deduced by the network
+            // service.
+            for scale_encoded_header in authorities_changes {
+                network_service
+                    .report_grandpa_finalized_block(
+                        network_chain_index,
+                        scale_encoded_header,
+                        None,
+                        None,
+                    )
+                    .await;
+            }
+
+            network_service
+                .report_grandpa_finalized_block(
+                    network_chain_index,
+                    scale_encoded_header,
+                    justification,
+                    Some(authorities_set_id),
+                )
+                .await;
+        }
+    }
+}
+
+/// Sends a [`Notification::GapDetected`] containing the current state of `sync` to all the
+/// elements of `all_notifications`.
+///
+/// Elements of `all_notifications` whose channel is closed are removed.
+fn notify_gap<TRq, TSrc, TBl>(
+    sync: &all::AllSync<TRq, TSrc, TBl>,
+    all_notifications: &mut Vec<mpsc::Sender<Notification>>,
+) {
+    let new_finalized_block_scale_encoded_header =
+        sync.finalized_block_header().scale_encoding_vec();
+    let non_finalized_blocks_ancestry_order = {
+        let best_hash = sync.best_block_hash();
+        sync.non_finalized_blocks_ancestry_order()
+            .map(|h| {
+                let scale_encoding = h.scale_encoding_vec();
+                BlockNotification {
+                    is_new_best: header::hash_from_scale_encoded_header(&scale_encoding)
+                        == best_hash,
+                    scale_encoded_header: scale_encoding,
+                    parent_hash: *h.parent_hash,
+                }
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // Elements in `all_notifications` are removed one by one and inserted back if the channel
+    // is still open.
+    for index in (0..all_notifications.len()).rev() {
+        let mut subscription = all_notifications.swap_remove(index);
+        let notification = Notification::GapDetected {
+            new_finalized_block_scale_encoded_header: new_finalized_block_scale_encoded_header
+                .clone(),
+            non_finalized_blocks_ancestry_order: non_finalized_blocks_ancestry_order.clone(),
+        };
+        if subscription.try_send(notification).is_err() {
+            continue;
+        }
+        all_notifications.push(subscription);
+    }
+}
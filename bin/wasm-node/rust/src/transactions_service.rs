@@ -71,13 +71,18 @@ use crate::{ffi, network_service, runtime_service, sync_service};
 
 use futures::{channel::mpsc, lock::Mutex, prelude::*, stream::FuturesUnordered};
 use smoldot::{
+    executor::{host, read_only_runtime_host},
     header,
     informant::HashDisplay,
+    json_rpc::{methods, payment_info},
     libp2p::peer_id::PeerId,
-    network::protocol,
-    transactions::{light_pool, validate},
+    network::{protocol, reputation},
+    transactions::{era, light_pool, validate},
+};
+use std::{
+    cmp, collections::HashSet, convert::TryFrom as _, iter, mem, num::NonZeroU32, pin::Pin,
+    sync::Arc, time::Duration,
 };
-use std::{cmp, convert::TryFrom as _, iter, num::NonZeroU32, pin::Pin, sync::Arc, time::Duration};
 
 /// Configuration for a [`TransactionsService`].
 pub struct Config {
@@ -87,8 +92,11 @@ pub struct Config {
     /// >           have been filtered out from this name.
     pub log_name: String,
 
-    /// Closure that spawns background tasks.
-    pub tasks_executor: Box<dyn FnMut(String, Pin<Box<dyn Future<Output = ()> + Send>>) + Send>,
+    /// Closure that spawns background tasks. The [`crate::TaskPriority`] is a hint indicating
+    /// how the task should be scheduled.
+    pub tasks_executor: Box<
+        dyn FnMut(String, crate::TaskPriority, Pin<Box<dyn Future<Output = ()> + Send>>) + Send,
+    >,
 
     /// Service responsible for synchronizing the chain.
     pub sync_service: Arc<sync_service::SyncService>,
@@ -100,11 +108,24 @@ pub struct Config {
     /// of view of the network service.
     pub network_service: (Arc<network_service::NetworkService>, usize),
 
+    /// Receiver for events coming from the network, as returned by
+    /// [`network_service::NetworkService::new`]. Used to receive the transactions gossiped by
+    /// peers.
+    pub network_events_receiver: mpsc::Receiver<network_service::Event>,
+
     /// Maximum number of pending transactions allowed in the service.
     ///
-    /// Any extra transaction will lead to [`TransactionStatus::Dropped`].
+    /// When this limit is reached, submitting a new transaction evicts a pending transaction
+    /// with a lower priority, or leads to [`TransactionStatus::Dropped`] for the new transaction
+    /// if there isn't any.
     pub max_pending_transactions: NonZeroU32,
 
+    /// Maximum total size, in bytes, of the pending transactions allowed in the service.
+    ///
+    /// Similar to [`Config::max_pending_transactions`], but applies to the sum of the sizes of
+    /// the pending transactions.
+    pub max_pending_transactions_bytes: NonZeroU32,
+
     /// Maximum number of block body downloads that can be performed in parallel.
     ///
     /// > **Note**: This is the maximum number of *blocks* whose body is being download, not the
@@ -119,26 +140,34 @@ pub struct Config {
 pub struct TransactionsService {
     /// Sending messages to the background task.
     to_background: Mutex<mpsc::Sender<ToBackground>>,
+
+    /// See [`Config::runtime_service`]. Used to estimate the fees of transactions.
+    runtime_service: Arc<runtime_service::RuntimeService>,
 }
 
 impl TransactionsService {
     /// Builds a new service.
     pub async fn new(mut config: Config) -> Self {
         let (to_background, from_foreground) = mpsc::channel(8);
+        let runtime_service = config.runtime_service.clone();
 
         (config.tasks_executor)(
             "transactions-service".into(),
+            crate::TaskPriority::Background,
             Box::pin(background_task(
                 config.log_name,
                 config.sync_service,
                 config.runtime_service,
                 config.network_service.0,
                 config.network_service.1,
+                config.network_events_receiver,
                 from_foreground,
                 usize::try_from(config.max_concurrent_downloads.get())
                     .unwrap_or(usize::max_value()),
                 usize::try_from(config.max_pending_transactions.get())
                     .unwrap_or(usize::max_value()),
+                usize::try_from(config.max_pending_transactions_bytes.get())
+                    .unwrap_or(usize::max_value()),
                 usize::try_from(config.max_concurrent_validations.get())
                     .unwrap_or(usize::max_value()),
             )),
@@ -146,9 +175,45 @@ impl TransactionsService {
 
         TransactionsService {
             to_background: Mutex::new(to_background),
+            runtime_service,
         }
     }
 
+    /// Estimates the fees that the given transaction would pay if it was included in the chain.
+    ///
+    /// Must pass as parameter the double-SCALE-encoded transaction.
+    ///
+    /// This calls the `TransactionPaymentApi_query_info` and
+    /// `TransactionPaymentApi_query_fee_details` runtime functions against a recent best block.
+    /// The transaction isn't submitted.
+    pub async fn estimate_fee(
+        &self,
+        transaction_bytes: &[u8],
+    ) -> Result<FeeEstimate, EstimateFeeError> {
+        let dispatch_info = read_only_runtime_call(
+            &self.runtime_service,
+            payment_info::PAYMENT_FEES_FUNCTION_NAME,
+            payment_info::payment_info_parameters(transaction_bytes),
+        )
+        .await?;
+        let dispatch_info =
+            payment_info::decode_payment_info(&dispatch_info).map_err(EstimateFeeError::Decode)?;
+
+        let fee_details = read_only_runtime_call(
+            &self.runtime_service,
+            payment_info::FEE_DETAILS_FUNCTION_NAME,
+            payment_info::payment_info_parameters(transaction_bytes),
+        )
+        .await?;
+        let fee_details =
+            payment_info::decode_fee_details(&fee_details).map_err(EstimateFeeError::Decode)?;
+
+        Ok(FeeEstimate {
+            dispatch_info,
+            fee_details,
+        })
+    }
+
     /// Adds a transaction to the service. The service will try to send it out as soon as
     /// possible.
     ///
@@ -197,22 +262,135 @@ impl TransactionsService {
             .await
             .unwrap();
     }
+
+    /// Returns a channel that receives the changes to the offchain storage that the runtime has
+    /// made through the offchain indexing host functions while validating transactions.
+    ///
+    /// The channel is closed if the receiver doesn't process the changes quickly enough.
+    pub async fn subscribe_offchain_storage_changes(
+        &self,
+        channel_size: usize,
+    ) -> mpsc::Receiver<Vec<(Vec<u8>, Option<Vec<u8>>)>> {
+        let (sender, rx) = mpsc::channel(channel_size);
+
+        self.to_background
+            .lock()
+            .await
+            .send(ToBackground::SubscribeOffchainStorageChanges { sender })
+            .await
+            .unwrap();
+
+        rx
+    }
+
+    /// Similar to [`TransactionsService::submit_and_watch_extrinsic`], but waits until the
+    /// transaction has been included in a finalized block, and returns the hash of this block.
+    ///
+    /// Returns an error if the transaction can't be included, or if it hasn't been included
+    /// in a finalized block after `timeout`.
+    ///
+    /// > **Note**: In case of a timeout, the transaction isn't removed from the service and
+    /// >           might still be included later.
+    pub async fn submit_and_wait_finalized(
+        &self,
+        transaction_bytes: Vec<u8>,
+        timeout: Duration,
+    ) -> Result<[u8; 32], SubmitAndWaitFinalizedError> {
+        let mut updates = self.submit_and_watch_extrinsic(transaction_bytes, 32).await;
+        let mut timeout = ffi::Delay::new(timeout).fuse();
+
+        loop {
+            let update = futures::select! {
+                update = updates.next() => update,
+                () = timeout => return Err(SubmitAndWaitFinalizedError::Timeout),
+            };
+
+            match update {
+                Some(TransactionStatus::Finalized(block_hash)) => return Ok(block_hash),
+                Some(TransactionStatus::Dropped) => {
+                    return Err(SubmitAndWaitFinalizedError::Dropped)
+                }
+                Some(TransactionStatus::Invalid(error)) => {
+                    return Err(SubmitAndWaitFinalizedError::Invalid(error))
+                }
+                Some(TransactionStatus::Usurped(by)) => {
+                    return Err(SubmitAndWaitFinalizedError::Usurped(by))
+                }
+                Some(TransactionStatus::Broadcast(_))
+                | Some(TransactionStatus::InBlock(_))
+                | Some(TransactionStatus::Retracted(_)) => {}
+                None => return Err(SubmitAndWaitFinalizedError::UpdatesInterrupted),
+            }
+        }
+    }
+}
+
+/// Error potentially returned by [`TransactionsService::submit_and_wait_finalized`].
+#[derive(Debug, derive_more::Display)]
+pub enum SubmitAndWaitFinalizedError {
+    /// The transaction hasn't been included in a finalized block before the timeout.
+    Timeout,
+    /// See [`TransactionStatus::Dropped`].
+    Dropped,
+    /// See [`TransactionStatus::Invalid`].
+    #[display(fmt = "Invalid transaction: {:?}", _0)]
+    Invalid(validate::TransactionValidityError),
+    /// See [`TransactionStatus::Usurped`].
+    #[display(fmt = "Transaction usurped by {}", "HashDisplay(_0)")]
+    Usurped([u8; 32]),
+    /// The service has stopped reporting updates about the transaction, for example because
+    /// too many updates were generated at once.
+    UpdatesInterrupted,
+}
+
+/// Outcome of [`TransactionsService::estimate_fee`].
+#[derive(Debug, Clone)]
+pub struct FeeEstimate {
+    /// Weight and class of the transaction, and total fee that it would pay, not including the
+    /// tip.
+    pub dispatch_info: methods::RuntimeDispatchInfo,
+
+    /// Breakdown of the fee that the transaction would pay.
+    pub fee_details: payment_info::FeeDetails,
+}
+
+/// Error potentially returned by [`TransactionsService::estimate_fee`].
+#[derive(Debug, derive_more::Display)]
+pub enum EstimateFeeError {
+    /// Error while performing the runtime call.
+    #[display(fmt = "{}", _0)]
+    Call(runtime_service::RuntimeCallError),
+    /// Error while starting the runtime call.
+    #[display(fmt = "{}", _0)]
+    StartError(host::StartErr),
+    /// Error while executing the runtime call.
+    #[display(fmt = "{}", _0)]
+    ReadOnlyRuntime(read_only_runtime_host::ErrorDetail),
+    /// Failed to decode the output of the runtime call.
+    #[display(fmt = "{}", _0)]
+    Decode(payment_info::DecodeError),
 }
 
 /// Update on the state of an extrinsic in the service.
 ///
-/// > **Note**: Because this code isn't an *actual* transactions pool that leverages the runtime,
-/// >           some variants (e.g. `Invalid`) are missing compared to the ones that can be found
-/// >           in Substrate, as they can't possibly be generated by this implementation.
+/// > **Note**: Because this code isn't an *actual* transactions pool, some variants (e.g.
+/// >           `Future`) are missing compared to the ones that can be found in Substrate, as
+/// >           they can't possibly be generated by this implementation.
 /// >           Additionally, an equivalent to the `Ready` state in Substrate is missing as it
 /// >           is the default state.
 #[derive(Debug, Clone)]
 pub enum TransactionStatus {
     /// Transaction has been broadcasted to the given peers.
+    ///
+    /// The transaction is periodically announced again. Only the peers the transaction hadn't
+    /// been broadcasted to before are reported. Never empty.
     Broadcast(Vec<PeerId>),
 
     /// Detected a block that is part of the best chain and that contains this transaction.
     ///
+    /// The body of the block has been downloaded from the network and verified against the
+    /// extrinsics root found in its header.
+    ///
     /// Contains the hash of the block that contains the transaction.
     InBlock([u8; 32]),
 
@@ -220,12 +398,30 @@ pub enum TransactionStatus {
     /// current best tree of blocks no longer contains the transaction.
     ///
     /// Contains the same block as was previously passed in [`TransactionStatus::InBlock`].
+    ///
+    /// Unless it is part of the new best chain as well, the transaction is then validated and
+    /// gossiped again, and [`TransactionStatus::InBlock`] is sent again if it gets included.
     Retracted([u8; 32]),
 
     /// Transaction has been dropped because the service was full, too slow, or generally
     /// encountered a problem.
     Dropped,
 
+    /// The runtime has indicated that the transaction is invalid against the current best block.
+    /// The transaction has been removed from the service and will no longer be gossiped to other
+    /// peers.
+    ///
+    /// > **Note**: The transaction might have been gossiped before, if it was valid against a
+    /// >           previous best block.
+    Invalid(validate::TransactionValidityError),
+
+    /// Transaction has been removed from the service because another transaction, with a higher
+    /// priority, is mutually exclusive with it. This typically happens when a transaction with
+    /// the same sender and nonce is submitted.
+    ///
+    /// Contains the hash of the transaction that has replaced this one.
+    Usurped([u8; 32]),
+
     /// Transaction has been included in a finalized block.
     Finalized([u8; 32]),
 }
@@ -236,6 +432,9 @@ enum ToBackground {
         transaction_bytes: Vec<u8>,
         updates_report: Option<mpsc::Sender<TransactionStatus>>,
     },
+    SubscribeOffchainStorageChanges {
+        sender: mpsc::Sender<Vec<(Vec<u8>, Option<Vec<u8>>)>>,
+    },
 }
 
 /// Background task running in parallel of the front service.
@@ -245,9 +444,11 @@ async fn background_task(
     runtime_service: Arc<runtime_service::RuntimeService>,
     network_service: Arc<network_service::NetworkService>,
     network_chain_index: usize,
+    mut network_events_receiver: mpsc::Receiver<network_service::Event>,
     mut from_foreground: mpsc::Receiver<ToBackground>,
     max_concurrent_downloads: usize,
     max_pending_transactions: usize,
+    max_pending_transactions_bytes: usize,
     max_concurrent_validations: usize,
 ) {
     let transactions_capacity = cmp::min(8, max_pending_transactions);
@@ -268,22 +469,39 @@ async fn background_task(
         next_reannounce: FuturesUnordered::new(),
         max_concurrent_downloads,
         max_pending_transactions,
+        max_pending_transactions_bytes,
+        best_block_number: 0,
+        finalized_block_number: 0,
+        offchain_storage_subscribers: Vec::new(),
     };
 
     let log_target = format!("tx-service-{}", log_name);
 
     // TODO: must periodically re-send transactions that aren't included in block yet
 
+    // Set to `Some` when the syncing service reports a gap in the blocks. Contains the new state
+    // of the chain and the existing channel of notifications, which can be kept as is.
+    let mut after_gap = None;
+
     'channels_rebuild: loop {
-        // This loop is entered when it is necessary to rebuild the subscriptions with the syncing
+        // This loop is entered when it is necessary to rebuild the state of the transactions
         // service. This happens when there is a gap in the blocks, either intentionally (e.g.
         // after a Grandpa warp sync) or because the transactions service was too busy to process
-        // the new blocks.
+        // the new blocks. In the latter case, the subscription with the syncing service is
+        // rebuilt as well.
 
-        let mut subscribe_all = worker.sync_service.subscribe_all(32).await;
+        let mut subscribe_all = match after_gap.take() {
+            Some(subscribe_all) => subscribe_all,
+            None => worker.sync_service.subscribe_all(32).await,
+        };
         let initial_finalized_block_hash = header::hash_from_scale_encoded_header(
             &subscribe_all.finalized_block_scale_encoded_header,
         );
+        worker.finalized_block_number =
+            header::decode(&subscribe_all.finalized_block_scale_encoded_header)
+                .unwrap()
+                .number;
+        worker.best_block_number = worker.finalized_block_number;
 
         // Drop all pending transactions of the pool.
         for (_, pending) in worker.pending_transactions.transactions_iter_mut() {
@@ -303,6 +521,7 @@ async fn background_task(
                 hash,
                 &block.parent_hash,
                 Block {
+                    number: header::decode(&block.scale_encoded_header).unwrap().number,
                     failed_downloads: 0,
                     downloading: false,
                 },
@@ -419,7 +638,9 @@ async fn background_task(
                         block_hash,
                         protocol::BlocksRequestFields {
                             body: true,
-                            header: true, // TODO: must be true in order for the body to be verified; fix the sync_service to not require that
+                            // The header is necessary in order for the body to be verified
+                            // against the extrinsics root.
+                            header: true,
                             justification: false,
                         },
                     );
@@ -450,6 +671,45 @@ async fn background_task(
                 }
             }
 
+            // Remove from the pool the mortal transactions that can no longer be included in
+            // the chain, as their death is inferior or equal to the number of the first
+            // non-finalized block.
+            // This is only done if all the finalized blocks have been removed from the pool, as
+            // otherwise a transaction might have been included in a finalized block whose body
+            // hasn't been downloaded yet.
+            if worker.pending_transactions.oldest_block_finality_lag() == 0 {
+                let first_non_finalized = worker.finalized_block_number.saturating_add(1);
+                let expired = worker
+                    .pending_transactions
+                    .transactions_iter()
+                    .filter(|(_, tx)| {
+                        tx.death_block_number
+                            .map_or(false, |death| death <= first_non_finalized)
+                    })
+                    .map(|(tx_id, _)| tx_id)
+                    .collect::<Vec<_>>();
+
+                for tx_id in expired {
+                    log::debug!(
+                        target: &log_target,
+                        "Discarding transaction {} whose mortality period has passed",
+                        HashDisplay(&blake2_hash(
+                            worker
+                                .pending_transactions
+                                .double_scale_encoding(tx_id)
+                                .unwrap()
+                        ))
+                    );
+
+                    let mut tx = worker.pending_transactions.remove_transaction(tx_id);
+                    tx.update_status(TransactionStatus::Invalid(
+                        validate::TransactionValidityError::Invalid(
+                            validate::InvalidTransaction::AncientBirthBlock,
+                        ),
+                    ));
+                }
+            }
+
             futures::select! {
                 notification = subscribe_all.new_blocks.next() => {
                     match notification {
@@ -459,6 +719,7 @@ async fn background_task(
                                 header::hash_from_scale_encoded_header(&new_block.scale_encoded_header),
                                 &new_block.parent_hash,
                                 Block {
+                                    number: header::decode(&new_block.scale_encoded_header).unwrap().number,
                                     failed_downloads: 0,
                                     downloading: false,
                                 },
@@ -469,6 +730,9 @@ async fn background_task(
                         },
                         Some(sync_service::Notification::Finalized { hash, best_block_hash }) => {
                             worker.set_best_block(&best_block_hash);
+                            if let Some(block) = worker.pending_transactions.block_user_data(&hash) {
+                                worker.finalized_block_number = block.number;
+                            }
                             for _ in worker
                                 .pending_transactions
                                 .set_finalized_block(&hash)
@@ -478,6 +742,14 @@ async fn background_task(
                                 // but it is not worth the effort.
                             }
                         },
+                        Some(sync_service::Notification::GapDetected { new_finalized_block_scale_encoded_header, non_finalized_blocks_ancestry_order }) => {
+                            after_gap = Some(sync_service::SubscribeAll {
+                                finalized_block_scale_encoded_header: new_finalized_block_scale_encoded_header,
+                                non_finalized_blocks_ancestry_order,
+                                new_blocks: subscribe_all.new_blocks,
+                            });
+                            continue 'channels_rebuild
+                        },
                         None => continue 'channels_rebuild
                     }
                 },
@@ -568,11 +840,16 @@ async fn background_task(
                         )
                         .await;
 
-                    // TODO: is this correct? and what should we do if announcing the same transaction multiple times? is it cumulative? `Broadcast` isn't super well documented
-                    if !peers_sent.is_empty() {
-                        worker.pending_transactions
-                            .transaction_user_data_mut(maybe_reannounce_tx_id).unwrap()
-                            .update_status(TransactionStatus::Broadcast(peers_sent));
+                    // Only report the peers the transaction has been sent to for the first time, in
+                    // order to not repeatedly report the same peers at each re-announce.
+                    let tx = worker.pending_transactions
+                        .transaction_user_data_mut(maybe_reannounce_tx_id).unwrap();
+                    let new_peers = peers_sent
+                        .into_iter()
+                        .filter(|peer| tx.broadcast_peers.insert(peer.clone()))
+                        .collect::<Vec<_>>();
+                    if !new_peers.is_empty() {
+                        tx.update_status(TransactionStatus::Broadcast(new_peers));
                     }
                 },
 
@@ -584,7 +861,7 @@ async fn background_task(
 
                     // Try extract the validation result of this transaction, or `continue` if it
                     // is a false positive.
-                    let validation_result = match worker.pending_transactions.transaction_user_data_mut(maybe_validated_tx_id) {
+                    let mut validation_result = match worker.pending_transactions.transaction_user_data_mut(maybe_validated_tx_id) {
                         None => continue,  // Normal. `maybe_validated_tx_id` is just a hint.
                         Some(tx) => match tx.validation_in_progress.as_mut().and_then(|f| f.now_or_never()) {
                             None => continue,  // Normal. `maybe_validated_tx_id` is just a hint.
@@ -595,8 +872,16 @@ async fn background_task(
                         },
                     };
 
+                    // Changes to the offchain storage are reported no matter whether the
+                    // transaction is valid.
+                    if let Ok((_, offchain_storage_changes, _)) = &mut validation_result {
+                        if !offchain_storage_changes.is_empty() {
+                            worker.report_offchain_storage_changes(mem::take(offchain_storage_changes));
+                        }
+                    }
+
                     match validation_result {
-                        Ok((block_hash, Ok(result))) => {
+                        Ok((block_hash, _, Ok(result))) => {
                             // The validation is made using the runtime service, while the state
                             // of the chain is tracked using the sync service. As such, it is
                             // possible for the validation to have been performed against a block
@@ -618,15 +903,39 @@ async fn background_task(
                                 result
                             );
 
-                            worker.pending_transactions
+                            let validation_outcome = worker.pending_transactions
                                 .set_validation_result(maybe_validated_tx_id, &block_hash, Ok(result));
 
+                            match validation_outcome {
+                                light_pool::SetValidationResult::Stored { usurped } => {
+                                    // Transactions that provide the same tags as this one (e.g.
+                                    // same sender and same nonce) but with a lower priority
+                                    // have been removed from the pool.
+                                    let tx_hash = blake2_hash(worker.pending_transactions.double_scale_encoding(maybe_validated_tx_id).unwrap());
+                                    for (_, mut usurped_tx) in usurped {
+                                        usurped_tx.update_status(TransactionStatus::Usurped(tx_hash));
+                                    }
+                                }
+                                light_pool::SetValidationResult::TooLowPriority { existing } => {
+                                    log::debug!(
+                                        target: &log_target,
+                                        "Discarding transaction {} in favour of {} with a higher or equal priority",
+                                        HashDisplay(&blake2_hash(worker.pending_transactions.double_scale_encoding(maybe_validated_tx_id).unwrap())),
+                                        HashDisplay(&blake2_hash(worker.pending_transactions.double_scale_encoding(existing).unwrap())),
+                                    );
+
+                                    let mut tx = worker.pending_transactions.remove_transaction(maybe_validated_tx_id);
+                                    tx.update_status(TransactionStatus::Dropped);
+                                    continue;
+                                }
+                            }
+
                             // Schedule this transaction for announcement.
                             worker.next_reannounce.push(async move {
                                 maybe_validated_tx_id
                             }.boxed());
                         }
-                        Ok((_, Err(error))) => {
+                        Ok((_, _, Err(error))) => {
                             log::warn!(
                                 target: &log_target,
                                 "Discarding invalid transaction {}: {:?}",
@@ -634,10 +943,29 @@ async fn background_task(
                                 error,
                             );
 
+                            // The peer that has gossiped this transaction, if any, is punished.
+                            let gossiped_by = worker.pending_transactions
+                                .transaction_user_data_mut(maybe_validated_tx_id)
+                                .unwrap()
+                                .gossiped_by
+                                .take();
+                            if let Some(peer_id) = gossiped_by {
+                                worker.network_service
+                                    .report_peer(&peer_id, reputation::Penalty::InvalidTransaction)
+                                    .await;
+                            }
+
                             // The validation itself has completed, but the runtime indicated
-                            // that the transaction was invalid. Drop the transaction.
+                            // that the transaction was invalid. Transactions are only gossiped
+                            // while they are valid against the current best block. However, this
+                            // validation might be a re-validation that follows a change of best
+                            // block or a re-org, in which case the transaction might have been
+                            // gossiped while it was valid against a previous best block.
+                            // Either way, the transaction can't be included in the current best
+                            // chain. Drop it, which stops any further gossiping, and report it as
+                            // invalid.
                             let mut tx = worker.pending_transactions.remove_transaction(maybe_validated_tx_id);
-                            tx.update_status(TransactionStatus::Dropped);
+                            tx.update_status(TransactionStatus::Invalid(error));
                         }
                         Err(error) => {
                             log::warn!(
@@ -682,19 +1010,30 @@ async fn background_task(
                                 continue;
                             }
 
-                            // We intentionally limit the number of transactions in the pool,
-                            // and immediately drop new transactions of this limit is reached.
-                            if worker.pending_transactions.num_transactions() >= worker.max_pending_transactions {
+                            // We intentionally limit the number and size of transactions in the
+                            // pool, and immediately drop new transactions if this limit is
+                            // reached and no transaction can be evicted.
+                            if !worker.make_room(transaction_bytes.len()) {
                                 if let Some(mut updates_report) = updates_report {
                                     let _ = updates_report.try_send(TransactionStatus::Dropped);
                                 }
                                 continue;
                             }
 
+                            // If the transaction is mortal, determine the block starting from
+                            // which it can no longer be included. The transaction is assumed to
+                            // have been built against a block close to the current best block.
+                            let death_block_number = era::extrinsic_era(&transaction_bytes)
+                                .and_then(|era| match era {
+                                    era::Era::Immortal => None,
+                                    era::Era::Mortal { .. } => Some(era.death(worker.best_block_number)),
+                                });
+
                             // Success path. Inserting in pool.
                             worker
                                 .pending_transactions
                                 .add_unvalidated(transaction_bytes, PendingTransaction {
+                                    submission_time: ffi::Instant::now(),
                                     when_reannounce: ffi::Instant::now(),
                                     status_update: {
                                         let mut vec = Vec::with_capacity(1);
@@ -704,9 +1043,63 @@ async fn background_task(
                                         vec
                                     },
                                     latest_status: None,
+                                    broadcast_peers: HashSet::default(),
+                                    gossiped_by: None,
                                     validation_in_progress: None,
+                                    death_block_number,
                                 });
                         }
+                        ToBackground::SubscribeOffchainStorageChanges { sender } => {
+                            worker.offchain_storage_subscribers.push(sender);
+                        }
+                    }
+                },
+
+                network_event = network_events_receiver.next().fuse() => {
+                    let (peer_id, transactions) = match network_event {
+                        Some(network_service::Event::Transactions { peer_id, chain_index, transactions })
+                            if chain_index == worker.network_chain_index => (peer_id, transactions),
+                        Some(_) => continue,
+                        None => return,
+                    };
+
+                    for transaction_bytes in transactions.decode() {
+                        // Transactions that are already in the pool are ignored. In particular,
+                        // the peer isn't punished if the transaction turns out to be invalid.
+                        if worker.pending_transactions
+                            .find_transaction(transaction_bytes)
+                            .next()
+                            .is_some()
+                        {
+                            continue;
+                        }
+
+                        // Transactions gossiped by peers are silently dropped if the pool is
+                        // full.
+                        if !worker.make_room(transaction_bytes.len()) {
+                            continue;
+                        }
+
+                        let death_block_number = era::extrinsic_era(transaction_bytes)
+                            .and_then(|era| match era {
+                                era::Era::Immortal => None,
+                                era::Era::Mortal { .. } => Some(era.death(worker.best_block_number)),
+                            });
+
+                        worker
+                            .pending_transactions
+                            .add_unvalidated(transaction_bytes.to_vec(), PendingTransaction {
+                                submission_time: ffi::Instant::now(),
+                                when_reannounce: ffi::Instant::now(),
+                                status_update: Vec::new(),
+                                latest_status: None,
+                                // The transaction is obviously not announced back to the peer
+                                // that has gossiped it.
+                                broadcast_peers: iter::once(peer_id.clone()).collect(),
+                                gossiped_by: Some(peer_id.clone()),
+                                validation_in_progress: None,
+                                death_block_number,
+                            });
                     }
                 }
             }
@@ -742,6 +1135,9 @@ struct Worker {
     /// See [`Config::max_pending_transactions`].
     max_pending_transactions: usize,
 
+    /// See [`Config::max_pending_transactions_bytes`].
+    max_pending_transactions_bytes: usize,
+
     /// List of ongoing block body downloads.
     /// The output of the future is a block hash and a block body.
     block_downloads:
@@ -767,12 +1163,90 @@ struct Worker {
     /// See [`Config::max_concurrent_downloads`]. Maximum number of elements in
     /// [`Worker::block_downloads`].
     max_concurrent_downloads: usize,
+
+    /// Number of the current best block.
+    best_block_number: u64,
+
+    /// Number of the current finalized block.
+    finalized_block_number: u64,
+
+    /// Channels to send changes to the offchain storage to. See
+    /// [`TransactionsService::subscribe_offchain_storage_changes`].
+    offchain_storage_subscribers: Vec<mpsc::Sender<Vec<(Vec<u8>, Option<Vec<u8>>)>>>,
 }
 
 impl Worker {
+    /// Sends the given changes to the offchain storage to all the subscribers, removing the ones
+    /// whose channel is closed or full.
+    fn report_offchain_storage_changes(&mut self, changes: Vec<(Vec<u8>, Option<Vec<u8>>)>) {
+        for n in (0..self.offchain_storage_subscribers.len()).rev() {
+            let mut channel = self.offchain_storage_subscribers.swap_remove(n);
+            if channel.try_send(changes.clone()).is_ok() {
+                self.offchain_storage_subscribers.push(channel);
+            }
+        }
+    }
+
+    /// Evicts transactions from the pool until a new transaction of the given size fits within
+    /// the limits of [`Worker::max_pending_transactions`] and
+    /// [`Worker::max_pending_transactions_bytes`].
+    ///
+    /// Transactions are evicted by increasing priority, then from the oldest to the most
+    /// recently submitted. Transactions that have never been successfully validated are
+    /// considered as having a priority of 0, which is also the priority assumed for the new
+    /// transaction. Consequently, only transactions with a priority of 0 are evicted.
+    /// Transactions that are included in the best chain are never evicted.
+    ///
+    /// Evicted transactions are reported as [`TransactionStatus::Dropped`].
+    ///
+    /// Returns `false` if not enough room could be made, in which case the new transaction must
+    /// be discarded.
+    fn make_room(&mut self, new_transaction_bytes: usize) -> bool {
+        if new_transaction_bytes > self.max_pending_transactions_bytes {
+            return false;
+        }
+
+        loop {
+            if self.pending_transactions.num_transactions() < self.max_pending_transactions
+                && self.pending_transactions.transactions_bytes() + new_transaction_bytes
+                    <= self.max_pending_transactions_bytes
+            {
+                return true;
+            }
+
+            let to_evict = self
+                .pending_transactions
+                .transactions_iter()
+                .filter(|(tx_id, _)| !self.pending_transactions.is_included_best_chain(*tx_id))
+                .map(|(tx_id, tx)| {
+                    let priority = self
+                        .pending_transactions
+                        .transaction_priority(tx_id)
+                        .unwrap_or(0);
+                    (priority, tx.submission_time, tx_id)
+                })
+                .min();
+
+            let tx_id = match to_evict {
+                Some((0, _, tx_id)) => tx_id,
+                _ => return false,
+            };
+
+            let mut tx = self.pending_transactions.remove_transaction(tx_id);
+            tx.update_status(TransactionStatus::Dropped);
+        }
+    }
+
     /// Update the best block. Must have been previously inserted with
     /// [`light_pool::LightPool::add_block`].
     fn set_best_block(&mut self, new_best_block_hash: &[u8; 32]) {
+        if let Some(block) = self
+            .pending_transactions
+            .block_user_data(new_best_block_hash)
+        {
+            self.best_block_number = block.number;
+        }
+
         let updates = self
             .pending_transactions
             .set_best_block(new_best_block_hash);
@@ -783,12 +1257,12 @@ impl Worker {
         // In that situation we need to first signal `Retracted`, then only `InBlock`.
         // Consequently, process `retracted_transactions` first.
 
-        for (tx_id, hash) in updates.retracted_transactions {
+        for (tx_id, hash) in &updates.retracted_transactions {
             let tx = self
                 .pending_transactions
-                .transaction_user_data_mut(tx_id)
+                .transaction_user_data_mut(*tx_id)
                 .unwrap();
-            tx.update_status(TransactionStatus::Retracted(hash));
+            tx.update_status(TransactionStatus::Retracted(*hash));
         }
 
         for (tx_id, hash) in updates.included_transactions {
@@ -798,10 +1272,31 @@ impl Worker {
                 .unwrap();
             tx.update_status(TransactionStatus::InBlock(hash));
         }
+
+        // Transactions that have been retracted and that aren't part of the new best chain need
+        // to be included again. Their previous validation results might no longer be accurate,
+        // and they are consequently validated again then re-gossiped, as is done for newly
+        // submitted transactions.
+        for (tx_id, _) in updates.retracted_transactions {
+            if self.pending_transactions.is_included_best_chain(tx_id) {
+                continue;
+            }
+
+            self.pending_transactions.clear_validation_results(tx_id);
+            let tx = self
+                .pending_transactions
+                .transaction_user_data_mut(tx_id)
+                .unwrap();
+            tx.when_reannounce = ffi::Instant::now();
+            tx.broadcast_peers.clear();
+        }
     }
 }
 
 struct Block {
+    /// Number of the block.
+    number: u64,
+
     /// Number of previous downloads that have failed.
     failed_downloads: u8,
 
@@ -810,6 +1305,9 @@ struct Block {
 }
 
 struct PendingTransaction {
+    /// Moment when the transaction has been submitted.
+    submission_time: ffi::Instant,
+
     /// Earliest moment when to gossip the transaction on the network again.
     ///
     /// This should be interpreted as the moment before which to not reannounce, rather than the
@@ -826,12 +1324,24 @@ struct PendingTransaction {
     /// [`PendingTransaction::status_update`].
     latest_status: Option<TransactionStatus>,
 
+    /// List of peers the transaction has been successfully announced to.
+    broadcast_peers: HashSet<PeerId, fnv::FnvBuildHasher>,
+
+    /// Peer that has gossiped this transaction, if it hasn't been submitted locally. Reported
+    /// to the network service if the transaction turns out to be invalid.
+    gossiped_by: Option<PeerId>,
+
+    /// Number of the first block in which the transaction can no longer be included, if the
+    /// transaction is mortal. Determined by decoding the era of the transaction.
+    death_block_number: Option<u64>,
+
     /// If `Some`, will receive the result of the validation of the transaction.
     validation_in_progress: Option<
         future::RemoteHandle<
             Result<
                 (
                     [u8; 32],
+                    Vec<(Vec<u8>, Option<Vec<u8>>)>,
                     Result<validate::ValidTransaction, validate::TransactionValidityError>,
                 ),
                 ValidateTransactionError,
@@ -866,7 +1376,8 @@ impl PendingTransaction {
 /// Actual transaction validation logic. Validates the transaction against a recent best block
 /// of the [`runtime_service::RuntimeService`].
 ///
-/// Returns the result of the validation, and the hash of the block it was validated against.
+/// Returns the result of the validation, the hash of the block it was validated against, and the
+/// changes to the offchain storage made by the runtime during the validation.
 async fn validate_transaction(
     log_target: &str,
     relay_chain_sync: &Arc<runtime_service::RuntimeService>,
@@ -875,6 +1386,7 @@ async fn validate_transaction(
 ) -> Result<
     (
         [u8; 32],
+        Vec<(Vec<u8>, Option<Vec<u8>>)>,
         Result<validate::ValidTransaction, validate::TransactionValidityError>,
     ),
     ValidateTransactionError,
@@ -917,23 +1429,32 @@ async fn validate_transaction(
             validate::Query::Finished {
                 result: Ok(success),
                 virtual_machine,
+                offchain_storage_changes,
             } => {
                 // TODO: provide hash as method of runtime_call_lock?
                 let block_hash = header::hash_from_scale_encoded_header(
                     runtime_call_lock.block_scale_encoded_header(),
                 );
                 runtime_call_lock.unlock(virtual_machine);
-                break Ok((block_hash, success));
+                break Ok((
+                    block_hash,
+                    offchain_storage_changes.into_iter().collect(),
+                    success,
+                ));
             }
             validate::Query::Finished {
                 result: Err(error),
                 virtual_machine,
+                ..
             } => {
                 runtime_call_lock.unlock(virtual_machine);
                 break Err(ValidateTransactionError::Validation(error));
             }
             validate::Query::StorageGet(get) => {
-                let storage_value = match runtime_call_lock.storage_entry(&get.key_as_vec()) {
+                let child_trie = get.child_trie().map(|c| c.as_ref().to_vec());
+                let storage_value = match runtime_call_lock
+                    .storage_entry(child_trie.as_deref(), &get.key_as_vec())
+                {
                     Ok(v) => v,
                     Err(err) => {
                         runtime_call_lock.unlock(validate::Query::StorageGet(get).into_prototype());
@@ -942,14 +1463,25 @@ async fn validate_transaction(
                 };
                 validation_in_progress = get.inject_value(storage_value.map(iter::once));
             }
-            validate::Query::NextKey(_) => {
-                todo!() // TODO:
+            validate::Query::NextKey(next_key) => {
+                let child_trie = next_key.child_trie().map(|c| c.as_ref().to_vec());
+                let key = next_key.key().as_ref().to_vec();
+                let key = match runtime_call_lock.storage_next_key(child_trie.as_deref(), &key) {
+                    Ok(v) => v,
+                    Err(err) => {
+                        runtime_call_lock
+                            .unlock(validate::Query::NextKey(next_key).into_prototype());
+                        return Err(ValidateTransactionError::Call(err));
+                    }
+                };
+                validation_in_progress = next_key.inject_key(key);
             }
             validate::Query::PrefixKeys(prefix) => {
                 // TODO: lots of allocations because I couldn't figure how to make this annoying borrow checker happy
                 let rq_prefix = prefix.prefix().as_ref().to_owned();
+                let rq_child_trie = prefix.child_trie().map(|c| c.as_ref().to_owned());
                 let result = runtime_call_lock
-                    .storage_prefix_keys_ordered(&rq_prefix)
+                    .storage_prefix_keys_ordered(rq_child_trie.as_deref(), &rq_prefix)
                     .map(|i| i.map(|v| v.as_ref().to_owned()).collect::<Vec<_>>());
                 match result {
                     Ok(v) => validation_in_progress = prefix.inject_keys_ordered(v.into_iter()),
@@ -971,6 +1503,82 @@ enum ValidateTransactionError {
     Validation(validate::Error),
 }
 
+/// Calls the given runtime function against a recent best block of the
+/// [`runtime_service::RuntimeService`], and returns the output of the call.
+///
+/// The runtime function must not modify the storage.
+async fn read_only_runtime_call(
+    relay_chain_sync: &Arc<runtime_service::RuntimeService>,
+    function_to_call: &str,
+    parameter: impl Iterator<Item = impl AsRef<[u8]>> + Clone,
+) -> Result<Vec<u8>, EstimateFeeError> {
+    let (runtime_call_lock, virtual_machine) = relay_chain_sync
+        .recent_best_block_runtime_lock()
+        .await
+        .start(function_to_call, parameter.clone())
+        .await
+        .map_err(EstimateFeeError::Call)?;
+
+    let mut runtime_call = match read_only_runtime_host::run(read_only_runtime_host::Config {
+        virtual_machine,
+        function_to_call,
+        parameter,
+    }) {
+        Ok(vm) => vm,
+        Err((err, prototype)) => {
+            runtime_call_lock.unlock(prototype);
+            return Err(EstimateFeeError::StartError(err));
+        }
+    };
+
+    loop {
+        match runtime_call {
+            read_only_runtime_host::RuntimeHostVm::Finished(Ok(success)) => {
+                let output = success.virtual_machine.value().as_ref().to_vec();
+                runtime_call_lock.unlock(success.virtual_machine.into_prototype());
+                break Ok(output);
+            }
+            read_only_runtime_host::RuntimeHostVm::Finished(Err(error)) => {
+                runtime_call_lock.unlock(error.prototype);
+                break Err(EstimateFeeError::ReadOnlyRuntime(error.detail));
+            }
+            read_only_runtime_host::RuntimeHostVm::StorageGet(get) => {
+                let child_trie = get.child_trie().map(|c| c.as_ref().to_vec());
+                let storage_value = match runtime_call_lock
+                    .storage_entry(child_trie.as_deref(), &get.key_as_vec())
+                {
+                    Ok(v) => v,
+                    Err(err) => {
+                        runtime_call_lock.unlock(
+                            read_only_runtime_host::RuntimeHostVm::StorageGet(get).into_prototype(),
+                        );
+                        return Err(EstimateFeeError::Call(err));
+                    }
+                };
+                runtime_call = get.inject_value(storage_value.map(iter::once));
+            }
+            read_only_runtime_host::RuntimeHostVm::NextKey(next_key) => {
+                let child_trie = next_key.child_trie().map(|c| c.as_ref().to_vec());
+                let key = next_key.key().as_ref().to_vec();
+                let key = match runtime_call_lock.storage_next_key(child_trie.as_deref(), &key) {
+                    Ok(v) => v,
+                    Err(err) => {
+                        runtime_call_lock.unlock(
+                            read_only_runtime_host::RuntimeHostVm::NextKey(next_key)
+                                .into_prototype(),
+                        );
+                        return Err(EstimateFeeError::Call(err));
+                    }
+                };
+                runtime_call = next_key.inject_key(key);
+            }
+            read_only_runtime_host::RuntimeHostVm::StorageRoot(storage_root) => {
+                runtime_call = storage_root.resume(runtime_call_lock.block_storage_root());
+            }
+        }
+    }
+}
+
 /// Utility. Calculates the blake2 hash of the given bytes.
 fn blake2_hash(bytes: &[u8]) -> [u8; 32] {
     <[u8; 32]>::try_from(blake2_rfc::blake2b::blake2b(32, &[], bytes).as_bytes()).unwrap()
@@ -290,6 +290,12 @@ impl StorageGet {
         self.0.key_as_vec()
     }
 
+    /// If `Some`, the key must be read from the child trie whose key is returned rather than from
+    /// the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.0.child_trie()
+    }
+
     /// Injects the corresponding storage value.
     pub fn inject_value(
         self,
@@ -310,6 +316,12 @@ impl PrefixKeys {
         self.0.prefix()
     }
 
+    /// If `Some`, the keys must be loaded from the child trie whose key is returned rather than
+    /// from the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.0.child_trie()
+    }
+
     /// Injects the list of keys ordered lexicographically.
     pub fn inject_keys_ordered(
         self,
@@ -330,6 +342,12 @@ impl NextKey {
         self.0.key()
     }
 
+    /// If `Some`, the key must be searched in the child trie whose key is returned rather than
+    /// in the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.0.child_trie()
+    }
+
     /// Injects the key.
     ///
     /// # Panic
@@ -103,6 +103,9 @@ pub struct Success {
     pub parent_runtime: host::HostVmPrototype,
     /// List of changes to the storage top trie that the block performs.
     pub storage_top_trie_changes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    /// List of changes to the storage child tries that the block performs, indexed by child
+    /// trie.
+    pub storage_child_tries_changes: BTreeMap<Vec<u8>, BTreeMap<Vec<u8>, Option<Vec<u8>>>>,
     /// List of changes to the offchain storage that this block performs.
     pub offchain_storage_changes: HashMap<Vec<u8>, Option<Vec<u8>>, fnv::FnvBuildHasher>,
     /// Cache used for calculating the top trie root of the new block.
@@ -180,6 +183,7 @@ pub fn build_block(config: Config) -> BlockBuild {
         },
         top_trie_root_calculation_cache: config.top_trie_root_calculation_cache,
         storage_top_trie_changes: Default::default(),
+        storage_child_tries_changes: Default::default(),
         offchain_storage_changes: Default::default(),
     });
 
@@ -266,6 +270,11 @@ impl BlockBuild {
                 (Inner::Runtime(runtime_host::RuntimeHostVm::NextKey(inner)), _) => {
                     return BlockBuild::NextKey(NextKey(inner, shared))
                 }
+                (Inner::Runtime(runtime_host::RuntimeHostVm::SignatureBatchVerify(req)), _) => {
+                    // Runtimes only start batch verifications when executing a block, not when
+                    // building one. The batch is thus verified on the current thread.
+                    inner = Inner::Runtime(req.verify_and_resume());
+                }
 
                 (
                     Inner::Runtime(runtime_host::RuntimeHostVm::Finished(Ok(success))),
@@ -282,6 +291,7 @@ impl BlockBuild {
                         shared,
                         parent_runtime: success.virtual_machine.into_prototype(),
                         storage_top_trie_changes: success.storage_top_trie_changes,
+                        storage_child_tries_changes: success.storage_child_tries_changes,
                         offchain_storage_changes: success.offchain_storage_changes,
                         top_trie_root_calculation_cache: success.top_trie_root_calculation_cache,
                     });
@@ -324,6 +334,7 @@ impl BlockBuild {
                             success.top_trie_root_calculation_cache,
                         ),
                         storage_top_trie_changes: success.storage_top_trie_changes,
+                        storage_child_tries_changes: success.storage_child_tries_changes,
                         offchain_storage_changes: success.offchain_storage_changes,
                     });
 
@@ -340,6 +351,7 @@ impl BlockBuild {
                         shared,
                         parent_runtime: success.virtual_machine.into_prototype(),
                         storage_top_trie_changes: success.storage_top_trie_changes,
+                        storage_child_tries_changes: success.storage_child_tries_changes,
                         offchain_storage_changes: success.offchain_storage_changes,
                         top_trie_root_calculation_cache: success.top_trie_root_calculation_cache,
                     });
@@ -412,6 +424,7 @@ impl BlockBuild {
                             shared,
                             parent_runtime: success.virtual_machine.into_prototype(),
                             storage_top_trie_changes: success.storage_top_trie_changes,
+                            storage_child_tries_changes: success.storage_child_tries_changes,
                             offchain_storage_changes: success.offchain_storage_changes,
                             top_trie_root_calculation_cache: success
                                 .top_trie_root_calculation_cache,
@@ -430,6 +443,7 @@ impl BlockBuild {
                         body: shared.block_body,
                         parent_runtime: success.virtual_machine.into_prototype(),
                         storage_top_trie_changes: success.storage_top_trie_changes,
+                        storage_child_tries_changes: success.storage_child_tries_changes,
                         offchain_storage_changes: success.offchain_storage_changes,
                         top_trie_root_calculation_cache: success.top_trie_root_calculation_cache,
                         logs: shared.logs,
@@ -473,6 +487,7 @@ pub struct InherentExtrinsics {
     shared: Shared,
     parent_runtime: host::HostVmPrototype,
     storage_top_trie_changes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    storage_child_tries_changes: BTreeMap<Vec<u8>, BTreeMap<Vec<u8>, Option<Vec<u8>>>>,
     offchain_storage_changes: HashMap<Vec<u8>, Option<Vec<u8>>, fnv::FnvBuildHasher>,
     top_trie_root_calculation_cache: calculate_root::CalculationCache,
 }
@@ -518,6 +533,7 @@ impl InherentExtrinsics {
             },
             top_trie_root_calculation_cache: Some(self.top_trie_root_calculation_cache),
             storage_top_trie_changes: self.storage_top_trie_changes,
+            storage_child_tries_changes: self.storage_child_tries_changes,
             offchain_storage_changes: self.offchain_storage_changes,
         });
 
@@ -608,6 +624,7 @@ pub struct ApplyExtrinsic {
     shared: Shared,
     parent_runtime: host::HostVmPrototype,
     storage_top_trie_changes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    storage_child_tries_changes: BTreeMap<Vec<u8>, BTreeMap<Vec<u8>, Option<Vec<u8>>>>,
     offchain_storage_changes: HashMap<Vec<u8>, Option<Vec<u8>>, fnv::FnvBuildHasher>,
     top_trie_root_calculation_cache: calculate_root::CalculationCache,
 }
@@ -629,6 +646,7 @@ impl ApplyExtrinsic {
             },
             top_trie_root_calculation_cache: Some(self.top_trie_root_calculation_cache),
             storage_top_trie_changes: self.storage_top_trie_changes,
+            storage_child_tries_changes: self.storage_child_tries_changes,
             offchain_storage_changes: self.offchain_storage_changes,
         });
 
@@ -652,6 +670,7 @@ impl ApplyExtrinsic {
             parameter: iter::empty::<&[u8]>(),
             top_trie_root_calculation_cache: Some(self.top_trie_root_calculation_cache),
             storage_top_trie_changes: self.storage_top_trie_changes,
+            storage_child_tries_changes: self.storage_child_tries_changes,
             offchain_storage_changes: self.offchain_storage_changes,
         });
 
@@ -681,6 +700,12 @@ impl StorageGet {
         self.0.key_as_vec()
     }
 
+    /// If `Some`, the key must be read from the child trie whose key is returned rather than from
+    /// the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.0.child_trie()
+    }
+
     /// Injects the corresponding storage value.
     pub fn inject_value(self, value: Option<impl Iterator<Item = impl AsRef<[u8]>>>) -> BlockBuild {
         BlockBuild::from_inner(self.0.inject_value(value), self.1)
@@ -698,6 +723,12 @@ impl PrefixKeys {
         self.0.prefix()
     }
 
+    /// If `Some`, the keys must be loaded from the child trie whose key is returned rather than
+    /// from the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.0.child_trie()
+    }
+
     /// Injects the list of keys ordered lexicographically.
     pub fn inject_keys_ordered(self, keys: impl Iterator<Item = impl AsRef<[u8]>>) -> BlockBuild {
         BlockBuild::from_inner(self.0.inject_keys_ordered(keys), self.1)
@@ -715,6 +746,12 @@ impl NextKey {
         self.0.key()
     }
 
+    /// If `Some`, the key must be searched in the child trie whose key is returned rather than
+    /// in the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.0.child_trie()
+    }
+
     /// Injects the key.
     ///
     /// # Panic
@@ -29,15 +29,21 @@ fn block_building_works() {
     let parent_runtime = {
         let code = chain_specs
             .genesis_storage()
+            .into_genesis_items()
+            .unwrap()
+            .iter()
             .filter(|(k, _)| k == b":code")
             .next()
             .unwrap()
             .1;
-        crate::executor::host::HostVmPrototype::new(
-            code,
-            crate::executor::DEFAULT_HEAP_PAGES,
-            crate::executor::vm::ExecHint::Oneshot,
-        )
+        crate::executor::host::HostVmPrototype::new(crate::executor::host::Config {
+            module: code,
+            heap_pages: crate::executor::DEFAULT_HEAP_PAGES,
+            exec_hint: crate::executor::vm::ExecHint::Oneshot,
+            allow_unresolved_imports: false,
+            max_memory_size: None,
+            metered: false,
+        })
         .unwrap()
     };
 
@@ -74,6 +80,9 @@ fn block_building_works() {
                 let key = get.key_as_vec();
                 let value = chain_specs
                     .genesis_storage()
+                    .into_genesis_items()
+                    .unwrap()
+                    .iter()
                     .find(|(k, _)| *k == key)
                     .map(|(_, v)| iter::once(v));
                 builder = get.inject_value(value);
@@ -83,6 +92,9 @@ fn block_building_works() {
                 let p = prefix.prefix().as_ref().to_owned();
                 let list = chain_specs
                     .genesis_storage()
+                    .into_genesis_items()
+                    .unwrap()
+                    .iter()
                     .filter(move |(k, _)| k.starts_with(&p))
                     .map(|(k, _)| k);
                 builder = prefix.inject_keys_ordered(list)
@@ -72,6 +72,7 @@ use hashbrown::HashMap;
 
 mod best_block;
 mod finality;
+mod tests;
 mod verify;
 
 pub use self::finality::*;
@@ -290,6 +291,65 @@ impl<T> NonFinalizedTree<T> {
             node_index,
         })
     }
+
+    /// Removes from the tree the forks that branch off from the chain of the current best block
+    /// at a height inferior or equal to the height of the best block minus `max_depth`.
+    ///
+    /// In other words, a non-finalized block is removed if it isn't an ancestor of the current
+    /// best block and if its first ancestor that isn't an ancestor of the best block has a height
+    /// inferior or equal to `best_block_height - max_depth`. The best block and its ancestors are
+    /// never removed.
+    ///
+    /// This function is meant to be called in order to limit the size of the tree when the
+    /// finality of the chain is stalled.
+    ///
+    /// Returns the list of blocks that have been removed, in an unspecified order.
+    pub fn prune_old_forks(&mut self, max_depth: u64) -> Vec<(header::Header, T)> {
+        let inner = self.inner.as_mut().unwrap();
+
+        let current_best = match inner.current_best {
+            Some(b) => b,
+            None => return Vec::new(),
+        };
+
+        let best_block_height = inner.blocks.get(current_best).unwrap().header.number;
+        let threshold = match best_block_height.checked_sub(max_depth) {
+            Some(t) => t,
+            None => return Vec::new(),
+        };
+
+        // Find the blocks that aren't ancestors of the best block but whose parent is either an
+        // ancestor of the best block or the finalized block. These blocks are the roots of the
+        // forks to remove.
+        let forks_roots = inner
+            .blocks
+            .iter_unordered()
+            .filter(|(index, block)| {
+                if block.header.number > threshold || inner.blocks.is_ancestor(*index, current_best)
+                {
+                    return false;
+                }
+
+                match inner.blocks.parent(*index) {
+                    Some(parent) => inner.blocks.is_ancestor(parent, current_best),
+                    None => true,
+                }
+            })
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+
+        let mut removed = Vec::new();
+        for fork_root in forks_roots {
+            removed.extend(
+                inner
+                    .blocks
+                    .prune_subtree(fork_root)
+                    .into_iter()
+                    .map(|node| (node.user_data.header, node.user_data.user_data)),
+            );
+        }
+        removed
+    }
 }
 
 impl<T> fmt::Debug for NonFinalizedTree<T>
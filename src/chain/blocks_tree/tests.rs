@@ -0,0 +1,112 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+#![cfg(test)]
+
+use super::{Config, HeaderVerifySuccess, NonFinalizedTree};
+use crate::{chain::chain_information, header};
+
+use core::{convert::TryFrom as _, time::Duration};
+
+/// Builds a header with the given parent and height. `discriminant` is written in the state
+/// root in order to be able to build multiple different children of the same parent.
+fn build_header(parent: &header::Header, discriminant: u8) -> header::Header {
+    header::Header {
+        parent_hash: parent.hash(),
+        number: parent.number + 1,
+        state_root: [discriminant; 32],
+        extrinsics_root: [0; 32],
+        digest: header::DigestRef::empty().into(),
+    }
+}
+
+fn insert(tree: &mut NonFinalizedTree<()>, header: &header::Header) {
+    match tree.verify_header(header.scale_encoding_vec(), Duration::new(0, 0)) {
+        Ok(HeaderVerifySuccess::Insert { insert, .. }) => insert.insert(()),
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn prune_old_forks() {
+    let genesis = header::Header {
+        parent_hash: [0; 32],
+        number: 0,
+        state_root: [0; 32],
+        extrinsics_root: [0; 32],
+        digest: header::DigestRef::empty().into(),
+    };
+
+    let mut tree = NonFinalizedTree::new(Config {
+        chain_information: chain_information::ValidChainInformation::try_from(
+            chain_information::ChainInformation {
+                finalized_block_header: genesis.clone(),
+                consensus: chain_information::ChainInformationConsensus::AllAuthorized,
+                finality: chain_information::ChainInformationFinality::Outsourced,
+            },
+        )
+        .unwrap(),
+        blocks_capacity: 16,
+    });
+
+    // Best chain of 10 blocks.
+    let mut best_chain = vec![genesis.clone()];
+    for _ in 0..10 {
+        let header = build_header(best_chain.last().unwrap(), 0);
+        insert(&mut tree, &header);
+        best_chain.push(header);
+    }
+
+    // Fork branching off from the finalized block.
+    let fork_from_finalized = build_header(&genesis, 1);
+    insert(&mut tree, &fork_from_finalized);
+    // Fork branching off from the block at height 2, and containing two blocks.
+    let old_fork1 = build_header(&best_chain[2], 1);
+    insert(&mut tree, &old_fork1);
+    let old_fork2 = build_header(&old_fork1, 1);
+    insert(&mut tree, &old_fork2);
+    // Fork branching off from the block at height 8.
+    let recent_fork = build_header(&best_chain[8], 1);
+    insert(&mut tree, &recent_fork);
+
+    assert_eq!(tree.best_block_hash(), best_chain[10].hash());
+    assert_eq!(tree.len(), 14);
+
+    let mut pruned = tree
+        .prune_old_forks(5)
+        .into_iter()
+        .map(|(header, ())| header.hash())
+        .collect::<Vec<_>>();
+    pruned.sort();
+    let mut expected = vec![
+        fork_from_finalized.hash(),
+        old_fork1.hash(),
+        old_fork2.hash(),
+    ];
+    expected.sort();
+    assert_eq!(pruned, expected);
+
+    assert_eq!(tree.len(), 11);
+    assert_eq!(tree.best_block_hash(), best_chain[10].hash());
+    assert!(tree.contains_non_finalized_block(&recent_fork.hash()));
+    for header in &best_chain[1..] {
+        assert!(tree.contains_non_finalized_block(&header.hash()));
+    }
+
+    // The remaining fork is too recent to be pruned.
+    assert!(tree.prune_old_forks(5).is_empty());
+}
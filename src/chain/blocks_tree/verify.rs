@@ -493,6 +493,12 @@ impl<T> VerifyContext<T> {
                     inner,
                 })
             }
+            verify::header_body::Verify::SignatureBatchVerify(inner) => {
+                BodyVerifyStep2::SignatureBatchVerify(SignatureBatchVerify {
+                    context: self,
+                    inner,
+                })
+            }
         }
     }
 }
@@ -728,6 +734,8 @@ pub enum BodyVerifyStep2<T> {
     /// This variant doesn't require any specific input from the user, but is provided in order to
     /// make it possible to benchmark the time it takes to compile runtimes.
     RuntimeCompilation(RuntimeCompilation<T>),
+    /// Verifying whether a batch of signatures is valid is required in order to continue.
+    SignatureBatchVerify(SignatureBatchVerify<T>),
 }
 
 /// Error while verifying a block body.
@@ -759,6 +767,12 @@ impl<T> StorageGet<T> {
         self.inner.key_as_vec()
     }
 
+    /// If `Some`, the key must be read from the child trie whose key is returned rather than from
+    /// the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.inner.child_trie()
+    }
+
     /// Access to the Nth ancestor's information and hierarchy. Returns `None` if `n` is too
     /// large. A value of `0` for `n` corresponds to the parent block. A value of `1` corresponds
     /// to the parent's parent. And so on.
@@ -803,6 +817,13 @@ impl<T> StorageGet<T> {
         let inner = self.inner.inject_value(value);
         self.context.with_body_verify(inner)
     }
+
+    /// Stops the verification, as the requested storage can't be provided. The block is then
+    /// considered as failing to verify.
+    pub fn inject_unavailable(self) -> BodyVerifyStep2<T> {
+        let inner = self.inner.inject_unavailable();
+        self.context.with_body_verify(inner)
+    }
 }
 
 /// Fetching the list of keys with a given prefix is required in order to continue.
@@ -818,6 +839,12 @@ impl<T> StoragePrefixKeys<T> {
         self.inner.prefix()
     }
 
+    /// If `Some`, the keys must be loaded from the child trie whose key is returned rather than
+    /// from the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.inner.child_trie()
+    }
+
     /// Access to the Nth ancestor's information and hierarchy. Returns `None` if `n` is too
     /// large. A value of `0` for `n` corresponds to the parent block. A value of `1` corresponds
     /// to the parent's parent. And so on.
@@ -862,6 +889,13 @@ impl<T> StoragePrefixKeys<T> {
         let inner = self.inner.inject_keys_ordered(keys);
         self.context.with_body_verify(inner)
     }
+
+    /// Stops the verification, as the requested storage can't be provided. The block is then
+    /// considered as failing to verify.
+    pub fn inject_unavailable(self) -> BodyVerifyStep2<T> {
+        let inner = self.inner.inject_unavailable();
+        self.context.with_body_verify(inner)
+    }
 }
 
 /// Fetching the key that follows a given one is required in order to continue.
@@ -877,6 +911,12 @@ impl<T> StorageNextKey<T> {
         self.inner.key()
     }
 
+    /// If `Some`, the key must be searched in the child trie whose key is returned rather than
+    /// in the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.inner.child_trie()
+    }
+
     /// Access to the Nth ancestor's information and hierarchy. Returns `None` if `n` is too
     /// large. A value of `0` for `n` corresponds to the parent block. A value of `1` corresponds
     /// to the parent's parent. And so on.
@@ -923,6 +963,13 @@ impl<T> StorageNextKey<T> {
         let inner = self.inner.inject_key(key);
         self.context.with_body_verify(inner)
     }
+
+    /// Stops the verification, as the requested storage can't be provided. The block is then
+    /// considered as failing to verify.
+    pub fn inject_unavailable(self) -> BodyVerifyStep2<T> {
+        let inner = self.inner.inject_unavailable();
+        self.context.with_body_verify(inner)
+    }
 }
 
 /// A new runtime must be compiled.
@@ -943,6 +990,33 @@ impl<T> RuntimeCompilation<T> {
     }
 }
 
+/// Verifying whether a batch of signatures is valid is required in order to continue.
+#[must_use]
+pub struct SignatureBatchVerify<T> {
+    inner: verify::header_body::SignatureBatchVerify,
+    context: VerifyContext<T>,
+}
+
+impl<T> SignatureBatchVerify<T> {
+    /// Returns the signatures of the batch.
+    pub fn signatures(&self) -> &[host::SignatureVerification] {
+        self.inner.signatures()
+    }
+
+    /// Resumes the verification. `all_valid` must be `true` if and only if all the signatures
+    /// of the batch are valid.
+    pub fn resume(self, all_valid: bool) -> BodyVerifyStep2<T> {
+        let inner = self.inner.resume(all_valid);
+        self.context.with_body_verify(inner)
+    }
+
+    /// Verifies the signatures one after the other, then resumes the verification.
+    pub fn verify_and_resume(self) -> BodyVerifyStep2<T> {
+        let inner = self.inner.verify_and_resume();
+        self.context.with_body_verify(inner)
+    }
+}
+
 ///
 #[derive(Debug)]
 pub enum HeaderVerifySuccess<'c, T> {
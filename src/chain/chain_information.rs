@@ -141,7 +141,16 @@ pub struct ChainInformation {
 
 impl ChainInformation {
     /// Builds the [`ChainInformation`] corresponding to the genesis block contained in the chain spec.
+    ///
+    /// Returns [`FromGenesisStorageError::UnknownStorageItems`] if the chain spec doesn't contain
+    /// the genesis storage items. Use the checkpoint found in the chain spec instead, if any.
+    /// See [`ChainSpec::light_sync_state`].
     pub fn from_chain_spec(chain_spec: &ChainSpec) -> Result<Self, FromGenesisStorageError> {
+        // The consensus and finality configurations can only be loaded from the storage items.
+        if chain_spec.genesis_storage().into_genesis_items().is_none() {
+            return Err(FromGenesisStorageError::UnknownStorageItems);
+        }
+
         let consensus = {
             let aura_genesis_config =
                 aura_config::AuraGenesisConfiguration::from_genesis_storage(|k| {
@@ -413,6 +422,9 @@ pub enum FromGenesisStorageError {
     BabeConfigLoad(babe_config::FromGenesisStorageError),
     /// Multiple consensus algorithms have been detected.
     MultipleConsensusAlgorithms,
+    /// The chain spec contains only the root hash of the genesis storage, and not the storage
+    /// items necessary to load the configuration.
+    UnknownStorageItems,
 }
 
 /// Equivalent to a [`ChainInformation`] but referencing an existing structure. Cheap to copy.
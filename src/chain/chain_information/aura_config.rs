@@ -48,8 +48,15 @@ impl AuraGenesisConfiguration {
         let heap_pages =
             executor::storage_heap_pages_to_value(genesis_storage_access(b":heappages").as_deref())
                 .map_err(FromGenesisStorageError::HeapPagesDecode)?;
-        let vm = host::HostVmPrototype::new(&wasm_code, heap_pages, vm::ExecHint::Oneshot)
-            .map_err(FromGenesisStorageError::VmInitialization)?;
+        let vm = host::HostVmPrototype::new(host::Config {
+            module: &wasm_code,
+            heap_pages,
+            exec_hint: vm::ExecHint::Oneshot,
+            allow_unresolved_imports: false,
+            max_memory_size: None,
+            metered: false,
+        })
+        .map_err(FromGenesisStorageError::VmInitialization)?;
         let (cfg, _) = Self::from_virtual_machine_prototype(vm, genesis_storage_access)
             .map_err(FromGenesisStorageError::VmError)?;
         Ok(cfg)
@@ -83,7 +90,7 @@ impl AuraGenesisConfiguration {
                 }
                 host::HostVm::Error { .. } => return Err(FromVmPrototypeError::Trapped),
 
-                host::HostVm::ExternalStorageGet(req) => {
+                host::HostVm::ExternalStorageGet(req) if req.child_trie().is_none() => {
                     let value = genesis_storage_access(req.key().as_ref());
                     vm = req.resume_full_value(value.as_ref().map(|v| &v[..]));
                 }
@@ -112,7 +119,7 @@ impl AuraGenesisConfiguration {
                 }
                 host::HostVm::Error { .. } => return Err(FromVmPrototypeError::Trapped),
 
-                host::HostVm::ExternalStorageGet(req) => {
+                host::HostVm::ExternalStorageGet(req) if req.child_trie().is_none() => {
                     let value = genesis_storage_access(req.key().as_ref());
                     vm = req.resume_full_value(value.as_ref().map(|v| &v[..]));
                 }
@@ -161,6 +161,12 @@ impl StorageGet {
         self.0.key_as_vec()
     }
 
+    /// If `Some`, the key must be read from the child trie whose key is returned rather than from
+    /// the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.0.child_trie()
+    }
+
     /// Injects the corresponding storage value.
     pub fn inject_value(self, value: Option<impl Iterator<Item = impl AsRef<[u8]>>>) -> Query {
         Query::from_inner(self.0.inject_value(value))
@@ -177,6 +183,12 @@ impl NextKey {
         self.0.key()
     }
 
+    /// If `Some`, the key must be searched in the child trie whose key is returned rather than
+    /// in the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.0.child_trie()
+    }
+
     /// Injects the key.
     ///
     /// # Panic
@@ -51,8 +51,8 @@
 //! assert!(tree.get(node2).is_some());
 //! ```
 
-use alloc::vec::Vec;
-use core::{fmt, iter};
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+use core::{convert::TryFrom as _, fmt, iter};
 
 /// Tree of nodes. Each node contains a value of type `T`.
 pub struct ForkTree<T> {
@@ -257,6 +257,100 @@ impl<T> ForkTree<T> {
         }
     }
 
+    /// Removes from the tree the given node and all of its descendants.
+    ///
+    /// Returns the list of removed nodes, in an unspecified order. The value of
+    /// [`PrunedNode::is_prune_target_ancestor`] is always `false`.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`NodeIndex`] is invalid.
+    ///
+    pub fn prune_subtree(&mut self, node_index: NodeIndex) -> Vec<PrunedNode<T>> {
+        // Detach the target node from its parent and siblings.
+        {
+            let node = &self.nodes[node_index.0];
+            let (parent, previous_sibling, next_sibling) =
+                (node.parent, node.previous_sibling, node.next_sibling);
+
+            if let Some(previous_sibling) = previous_sibling {
+                self.nodes[previous_sibling].next_sibling = next_sibling;
+            } else if let Some(parent) = parent {
+                debug_assert_eq!(self.nodes[parent].first_child, Some(node_index.0));
+                self.nodes[parent].first_child = next_sibling;
+            } else {
+                debug_assert_eq!(self.first_root, Some(node_index.0));
+                self.first_root = next_sibling;
+            }
+
+            if let Some(next_sibling) = next_sibling {
+                self.nodes[next_sibling].previous_sibling = previous_sibling;
+            }
+        }
+
+        // Walk through the target node and its descendants and remove them.
+        let mut to_remove = vec![node_index.0];
+        let mut removed = Vec::new();
+        while let Some(index) = to_remove.pop() {
+            let node = self.nodes.remove(index);
+
+            let mut child = node.first_child;
+            while let Some(c) = child {
+                to_remove.push(c);
+                child = self.nodes[c].next_sibling;
+            }
+
+            removed.push(PrunedNode {
+                index: NodeIndex(index),
+                is_prune_target_ancestor: false,
+                user_data: node.data,
+            });
+        }
+
+        removed
+    }
+
+    /// Returns the distance, in number of nodes, between `reference` and the node from which the
+    /// oldest fork that doesn't contain `reference` branches off.
+    ///
+    /// In other words, for each node that isn't an ancestor of `reference` but whose parent is
+    /// either an ancestor of `reference` or the virtual root of the tree, calculates the distance
+    /// between this parent and `reference`, and returns the highest of these values. Returns `0`
+    /// if all the nodes of the tree are either ancestors or descendants of `reference`.
+    ///
+    /// A value of `None` for `reference` designates the virtual root of the tree, in which case
+    /// `0` is always returned.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`NodeIndex`] is invalid.
+    ///
+    pub fn max_fork_depth(&self, reference: Option<NodeIndex>) -> u64 {
+        let reference = match reference {
+            Some(r) => r,
+            None => return 0,
+        };
+
+        // Maps each ancestor of `reference` (including `reference` itself) to its distance to
+        // `reference`.
+        let ancestors = self
+            .node_to_root_path(reference)
+            .enumerate()
+            .map(|(distance, node)| (node.0, u64::try_from(distance).unwrap()))
+            .collect::<BTreeMap<_, _>>();
+        let root_distance = u64::try_from(ancestors.len()).unwrap();
+
+        self.nodes
+            .iter()
+            .filter(|(index, _)| !ancestors.contains_key(index))
+            .filter_map(|(_, node)| match node.parent {
+                Some(parent) => ancestors.get(&parent).copied(),
+                None => Some(root_distance),
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
     /// Returns the common ancestor between `node1` and `node2`, if any is known.
     ///
     /// # Panic
@@ -584,7 +678,8 @@ impl<'a, T> Drop for PruneAncestorsIter<'a, T> {
     }
 }
 
-/// Node removed by [`ForkTree::prune_ancestors`].
+/// Node removed by [`ForkTree::prune_ancestors`], [`ForkTree::prune_uncles`], or
+/// [`ForkTree::prune_subtree`].
 pub struct PrunedNode<T> {
     /// Former index of the node. This index is no longer valid.
     pub index: NodeIndex,
@@ -691,5 +786,77 @@ mod tests {
         assert_eq!(tree.common_ancestor(node0, node1), None);
     }
 
+    #[test]
+    fn max_fork_depth() {
+        let mut tree = ForkTree::new();
+
+        let node0 = tree.insert(None, ());
+        let node1 = tree.insert(Some(node0), ());
+        let node2 = tree.insert(Some(node1), ());
+        let node3 = tree.insert(Some(node2), ());
+        let node4 = tree.insert(Some(node3), ());
+
+        assert_eq!(tree.max_fork_depth(None), 0);
+        assert_eq!(tree.max_fork_depth(Some(node4)), 0);
+        assert_eq!(tree.max_fork_depth(Some(node2)), 0);
+
+        let node5 = tree.insert(Some(node3), ());
+        assert_eq!(tree.max_fork_depth(Some(node4)), 1);
+
+        let node6 = tree.insert(Some(node1), ());
+        let _node7 = tree.insert(Some(node6), ());
+        assert_eq!(tree.max_fork_depth(Some(node4)), 3);
+        assert_eq!(tree.max_fork_depth(Some(node5)), 3);
+        assert_eq!(tree.max_fork_depth(Some(node2)), 1);
+
+        let _node8 = tree.insert(None, ());
+        assert_eq!(tree.max_fork_depth(Some(node4)), 5);
+        assert_eq!(tree.max_fork_depth(Some(node0)), 1);
+        assert_eq!(tree.max_fork_depth(None), 0);
+    }
+
+    #[test]
+    fn prune_subtree() {
+        let mut tree = ForkTree::new();
+
+        let node0 = tree.insert(None, 0);
+        let node1 = tree.insert(Some(node0), 1);
+        let node2 = tree.insert(Some(node1), 2);
+        let node3 = tree.insert(Some(node1), 3);
+        let node4 = tree.insert(Some(node0), 4);
+        let node5 = tree.insert(Some(node4), 5);
+        let node6 = tree.insert(None, 6);
+
+        let mut removed = tree
+            .prune_subtree(node1)
+            .into_iter()
+            .map(|n| n.index)
+            .collect::<Vec<_>>();
+        removed.sort();
+        assert_eq!(removed, vec![node1, node2, node3]);
+
+        assert_eq!(tree.len(), 4);
+        assert!(tree.get(node1).is_none());
+        assert!(tree.get(node2).is_none());
+        assert!(tree.get(node3).is_none());
+        assert_eq!(
+            tree.node_to_root_path(node5).collect::<Vec<_>>(),
+            &[node5, node4, node0]
+        );
+
+        let removed = tree
+            .prune_subtree(node6)
+            .into_iter()
+            .map(|n| n.index)
+            .collect::<Vec<_>>();
+        assert_eq!(removed, vec![node6]);
+        assert_eq!(
+            tree.iter_ancestry_order()
+                .map(|(_, v)| *v)
+                .collect::<Vec<_>>(),
+            vec![0, 4, 5]
+        );
+    }
+
     // TODO: add more testing for the order of elements returned by `prune_ancestors`
 }
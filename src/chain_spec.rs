@@ -25,7 +25,8 @@
 //! Chain specs contain, notably:
 //!
 //! - The state of the genesis block. In other words, the initial content of the database. This
-//! includes the Wasm runtime code of the genesis block.
+//! includes the Wasm runtime code of the genesis block. Alternatively, chain specs can contain
+//! only the root hash of this state, in which case a checkpoint is necessary in order to sync.
 //! - The list of bootstrap nodes. These are the IP addresses of the machines we need to connect
 //! to.
 //! - The default telemetry endpoints, to which we should send telemetry information to.
@@ -35,17 +36,30 @@
 //!
 
 use crate::chain::chain_information::{
-    BabeEpochInformation, ChainInformation, ChainInformationConsensus, ChainInformationFinality,
-    ValidChainInformation,
+    BabeEpochInformation, BabeEpochInformationRef, ChainInformation, ChainInformationConsensus,
+    ChainInformationConsensusRef, ChainInformationFinality, ChainInformationFinalityRef,
+    ValidChainInformation, ValidityError,
 };
-use alloc::{string::String, vec::Vec};
-use core::{convert::TryInto as _, num::NonZeroU64};
+use alloc::{string::String, vec, vec::Vec};
+use core::{convert::TryInto as _, fmt, num::NonZeroU64};
 
+mod builder;
 mod light_sync_state;
 mod structs;
+mod validate;
 
+pub use builder::{ChainSpecBuilder, InvalidJsonError};
+pub use validate::{ValidationError, ValidationProblem};
+
+/// Checkpoint found in the `lightSyncState` field of a chain spec.
+///
+/// Contains the header of a finalized block and the consensus and finality information required
+/// to verify its descendants. Light clients can use this checkpoint as a trusted starting point
+/// for the synchronization, instead of starting from the genesis block.
+#[derive(Debug, Clone)]
 pub struct LightSyncState {
-    inner: light_sync_state::DecodedLightSyncState,
+    chain_information: ValidChainInformation,
+    babe_finalized_block_weight: u32,
 }
 
 fn convert_epoch(epoch: &light_sync_state::BabeEpoch) -> BabeEpochInformation {
@@ -69,15 +83,16 @@ fn convert_epoch(epoch: &light_sync_state::BabeEpoch) -> BabeEpochInformation {
 }
 
 impl LightSyncState {
-    pub fn as_chain_information(&self) -> ValidChainInformation {
+    fn from_decoded(
+        decoded: light_sync_state::DecodedLightSyncState,
+    ) -> Result<Self, LightSyncStateError> {
         // Create a sorted list of all regular epochs that haven't been pruned from the sync state.
-        let mut epochs: Vec<_> = self
-            .inner
+        let mut epochs: Vec<_> = decoded
             .babe_epoch_changes
             .epochs
             .iter()
             .filter(|((_, block_num), _)| {
-                *block_num as u64 <= self.inner.finalized_block_header.number
+                *block_num as u64 <= decoded.finalized_block_header.number
             })
             .filter_map(|((_, block_num), epoch)| match epoch {
                 light_sync_state::PersistedEpoch::Regular(epoch) => Some((block_num, epoch)),
@@ -91,64 +106,182 @@ impl LightSyncState {
         epochs.dedup_by_key(|(_, epoch)| epoch.epoch_index);
 
         // Get the latest two epochs.
+        if epochs.len() < 2 {
+            return Err(LightSyncStateError::MissingBabeEpochs);
+        }
         let current_epoch = &epochs[epochs.len() - 2].1;
         let next_epoch = &epochs[epochs.len() - 1].1;
 
-        ChainInformation {
-            finalized_block_header: self.inner.finalized_block_header.clone(),
+        let chain_information = ChainInformation {
+            finalized_block_header: decoded.finalized_block_header.clone(),
             consensus: ChainInformationConsensus::Babe {
-                slots_per_epoch: NonZeroU64::new(current_epoch.duration).unwrap(),
+                slots_per_epoch: NonZeroU64::new(current_epoch.duration)
+                    .ok_or(LightSyncStateError::ZeroBabeEpochDuration)?,
                 finalized_block_epoch_information: Some(convert_epoch(current_epoch)),
                 finalized_next_epoch_transition: convert_epoch(next_epoch),
             },
             finality: ChainInformationFinality::Grandpa {
-                after_finalized_block_authorities_set_id: self.inner.grandpa_authority_set.set_id,
-                finalized_triggered_authorities: {
-                    self.inner
-                        .grandpa_authority_set
-                        .current_authorities
-                        .iter()
-                        .map(|authority| crate::header::GrandpaAuthority {
+                after_finalized_block_authorities_set_id: decoded.grandpa_authority_set.set_id,
+                finalized_triggered_authorities: decoded
+                    .grandpa_authority_set
+                    .current_authorities
+                    .iter()
+                    .map(|authority| {
+                        Ok(crate::header::GrandpaAuthority {
                             public_key: authority.public_key,
-                            weight: NonZeroU64::new(authority.weight).unwrap(),
+                            weight: NonZeroU64::new(authority.weight)
+                                .ok_or(LightSyncStateError::ZeroGrandpaAuthorityWeight)?,
                         })
-                        .collect()
-                },
+                    })
+                    .collect::<Result<_, _>>()?,
                 finalized_scheduled_change: None, // TODO: unimplemented
             },
+        };
+
+        Ok(LightSyncState {
+            chain_information: chain_information
+                .try_into()
+                .map_err(LightSyncStateError::InvalidChainInformation)?,
+            babe_finalized_block_weight: decoded.babe_finalized_block_weight,
+        })
+    }
+
+    /// Returns the information about the finalized block of the checkpoint, in a format that can
+    /// be used to initialize the synchronization.
+    pub fn as_chain_information(&self) -> ValidChainInformation {
+        self.chain_information.clone()
+    }
+
+    /// Returns the header of the finalized block of the checkpoint.
+    pub fn finalized_block_header(&self) -> crate::header::HeaderRef {
+        self.chain_information.as_ref().finalized_block_header
+    }
+
+    /// Returns the identifier of the GrandPa authorities set in use after the finalized block
+    /// of the checkpoint.
+    pub fn grandpa_authorities_set_id(&self) -> u64 {
+        match self.chain_information.as_ref().finality {
+            ChainInformationFinalityRef::Grandpa {
+                after_finalized_block_authorities_set_id,
+                ..
+            } => after_finalized_block_authorities_set_id,
+            ChainInformationFinalityRef::Outsourced => unreachable!(),
         }
-        .try_into()
-        .unwrap() // TODO: don't unwrap /!\ should fail when parsing the chain spec instead
+    }
+
+    /// Returns the list of GrandPa authorities in use after the finalized block of the
+    /// checkpoint.
+    pub fn grandpa_authorities(&self) -> &[crate::header::GrandpaAuthority] {
+        match self.chain_information.as_ref().finality {
+            ChainInformationFinalityRef::Grandpa {
+                finalized_triggered_authorities,
+                ..
+            } => finalized_triggered_authorities,
+            ChainInformationFinalityRef::Outsourced => unreachable!(),
+        }
+    }
+
+    /// Returns the Babe epoch the finalized block of the checkpoint belongs to, and the epoch
+    /// that follows it.
+    pub fn babe_epochs(&self) -> (BabeEpochInformationRef, BabeEpochInformationRef) {
+        match self.chain_information.as_ref().consensus {
+            ChainInformationConsensusRef::Babe {
+                finalized_block_epoch_information: Some(current),
+                finalized_next_epoch_transition: next,
+                ..
+            } => (current, next),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the Babe weight of the finalized block of the checkpoint, in other words the
+    /// number of primary slots claims in the chain up to this block.
+    pub fn babe_finalized_block_weight(&self) -> u32 {
+        self.babe_finalized_block_weight
     }
 }
 
+/// Error that can happen when interpreting the `lightSyncState` field of a chain spec.
+#[derive(Debug, derive_more::Display)]
+enum LightSyncStateError {
+    /// Failed to decode one of the fields.
+    #[display(fmt = "{}", _0)]
+    Decode(light_sync_state::DecodeError),
+    /// The Babe epoch changes contain fewer than two epochs.
+    MissingBabeEpochs,
+    /// The duration of a Babe epoch is zero.
+    ZeroBabeEpochDuration,
+    /// One of the GrandPa authorities has a weight of zero.
+    ZeroGrandpaAuthorityWeight,
+    /// The checkpoint isn't coherent.
+    #[display(fmt = "{}", _0)]
+    InvalidChainInformation(ValidityError),
+}
+
+/// Error in the `lightSyncState` field of a chain spec. See
+/// [`ValidationProblem::InvalidLightSyncState`].
+#[derive(Debug, derive_more::Display)]
+pub struct InvalidLightSyncStateError(LightSyncStateError);
+
 /// A configuration of a chain. Can be used to build a genesis block.
 #[derive(Clone)]
 pub struct ChainSpec {
     client_spec: structs::ClientSpec,
+    light_sync_state: Option<LightSyncState>,
 }
 
 impl ChainSpec {
-    pub fn light_sync_state(&self) -> Option<LightSyncState> {
-        self.client_spec
-            .light_sync_state
-            .as_ref()
-            .map(|state| LightSyncState {
-                inner: state.decode(),
-            })
+    /// Returns the checkpoint found in the `lightSyncState` field of the chain spec, if any.
+    pub fn light_sync_state(&self) -> Option<&LightSyncState> {
+        self.light_sync_state.as_ref()
     }
 
     /// Parse JSON content into a [`ChainSpec`].
+    ///
+    /// In addition to the structure of the JSON document, the content of the fields is verified.
+    /// All the problems found are reported at once. See [`ParseError::validation_errors`].
     pub fn from_json_bytes(json: impl AsRef<[u8]>) -> Result<Self, ParseError> {
-        let client_spec: structs::ClientSpec =
-            serde_json::from_slice(json.as_ref()).map_err(ParseError)?;
+        let client_spec: structs::ClientSpec = serde_json::from_slice(json.as_ref())
+            .map_err(|err| ParseError(ParseErrorInner::Serde(err)))?;
+
+        let mut errors = validate::validate(&client_spec);
+
+        let light_sync_state = match &client_spec.light_sync_state {
+            Some(state) => match state
+                .decode()
+                .map_err(LightSyncStateError::Decode)
+                .and_then(LightSyncState::from_decoded)
+            {
+                Ok(state) => Some(state),
+                Err(err) => {
+                    errors.push(ValidationError {
+                        json_path: "lightSyncState".into(),
+                        problem: ValidationProblem::InvalidLightSyncState(
+                            InvalidLightSyncStateError(err),
+                        ),
+                    });
+                    None
+                }
+            },
+            None => None,
+        };
 
-        // TODO: we don't support child tries in the genesis block
-        assert!({
-            let structs::Genesis::Raw(genesis) = &client_spec.genesis;
-            genesis.children_default.is_empty()
-        });
-        Ok(ChainSpec { client_spec })
+        if !errors.is_empty() {
+            return Err(ParseError(ParseErrorInner::Validation(ValidationErrors(
+                errors,
+            ))));
+        }
+
+        Ok(ChainSpec {
+            client_spec,
+            light_sync_state,
+        })
+    }
+
+    /// Serializes the chain spec into JSON. The output can be parsed back with
+    /// [`ChainSpec::from_json_bytes`].
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&self.client_spec).unwrap()
     }
 
     /// Returns the name of the chain. Meant to be displayed to the user.
@@ -190,14 +323,20 @@ impl ChainSpec {
         &self.client_spec.boot_nodes
     }
 
-    /// Returns the list of libp2p multiaddresses of the default telemetry servers of the chain.
-    // TODO: more strongly typed?
-    pub fn telemetry_endpoints(&'_ self) -> impl Iterator<Item = impl AsRef<str> + '_> + '_ {
+    /// Returns the list of addresses of the default telemetry servers of the chain, each with
+    /// its verbosity level.
+    ///
+    /// Addresses are either libp2p multiaddresses or URLs. See
+    /// [`crate::telemetry::parse_endpoint`].
+    pub fn telemetry_endpoints(&'_ self) -> impl Iterator<Item = (&'_ str, u8)> + '_ {
         self.client_spec
             .telemetry_endpoints
             .as_ref()
             .into_iter()
-            .flat_map(|ep| ep.iter().map(|e| &e.0))
+            .flat_map(|ep| {
+                ep.iter()
+                    .map(|(address, verbosity)| (&address[..], *verbosity))
+            })
     }
 
     /// Returns the network protocol id that uniquely identifies a chain. Used to prevent nodes
@@ -209,7 +348,18 @@ impl ChainSpec {
         self.client_spec.protocol_id.as_deref().unwrap_or("sup")
     }
 
-    // TODO: this API is probably unstable, as the meaning of the string is unclear
+    /// Returns the fork id of the chain, if any.
+    ///
+    /// Chains that have been forked from another chain, and consequently share the same genesis
+    /// block, are given a fork id in order to differentiate them from the original chain.
+    pub fn fork_id(&self) -> Option<&str> {
+        self.client_spec.fork_id.as_deref()
+    }
+
+    /// If the chain is a parachain, returns the identifier of its relay chain and its
+    /// parachain id.
+    ///
+    /// This is a shortcut for calling [`ChainSpec::relay_chain_id`] and [`ChainSpec::para_id`].
     pub fn relay_chain(&self) -> Option<(&str, u32)> {
         self.client_spec
             .parachain
@@ -217,16 +367,100 @@ impl ChainSpec {
             .map(|p| (p.relay_chain.as_str(), p.para_id))
     }
 
-    /// Returns the list of storage keys and values of the genesis block.
-    pub fn genesis_storage(&self) -> impl ExactSizeIterator<Item = (&[u8], &[u8])> + Clone {
-        let structs::Genesis::Raw(genesis) = &self.client_spec.genesis;
-        genesis.top.iter().map(|(k, v)| (&k.0[..], &v.0[..]))
+    /// If the chain is a parachain, returns the identifier of its relay chain, as found in the
+    /// `relay_chain` field of the chain spec.
+    ///
+    /// This identifier is meant to be compared with the [`ChainSpec::id`] of the chain specs of
+    /// the potential relay chains.
+    pub fn relay_chain_id(&self) -> Option<&str> {
+        self.client_spec
+            .parachain
+            .as_ref()
+            .map(|p| p.relay_chain.as_str())
+    }
+
+    /// If the chain is a parachain, returns its identifier within its relay chain, as found in
+    /// the `para_id` field of the chain spec.
+    pub fn para_id(&self) -> Option<u32> {
+        self.client_spec.parachain.as_ref().map(|p| p.para_id)
+    }
+
+    /// Returns the names of the non-standard fields, called extensions, found in the chain spec.
+    pub fn extensions_names(&'_ self) -> impl Iterator<Item = &'_ str> + '_ {
+        self.client_spec.extensions.keys().map(|name| name.as_str())
+    }
+
+    /// Returns the value of the non-standard field, called extension, with the given name.
+    ///
+    /// The returned value is JSON-formatted, for example `{"foo":"bar"}`.
+    pub fn extension(&self, name: &str) -> Option<String> {
+        self.client_spec
+            .extensions
+            .get(name)
+            .map(|value| serde_json::to_string(value).unwrap())
+    }
+
+    /// Returns the storage of the genesis block.
+    ///
+    /// Chain specs can either contain the full list of storage keys and values of the genesis
+    /// block, or only the root hash of the storage trie of the genesis block.
+    pub fn genesis_storage(&self) -> GenesisStorage {
+        match &self.client_spec.genesis {
+            structs::Genesis::Raw(raw) => GenesisStorage::Items(GenesisStorageItems { raw }),
+            structs::Genesis::StateRootHash(hash) => GenesisStorage::TrieRootHash(&hash.0),
+        }
+    }
+
+    /// Returns the list of runtime code substitutions, as found in the `codeSubstitutes` field.
+    ///
+    /// Each item is the hash of a block and a Wasm runtime code. The descendants of this block
+    /// whose on-chain runtime has the same `spec_version` as the substitute runtime code must
+    /// use the substitute instead. The substitution thus stops when the `spec_version` is
+    /// modified. The block with that hash uses its unmodified runtime code.
+    ///
+    /// This is used in order to replace faulty runtimes that have historically been deployed on
+    /// a chain with functioning ones.
+    pub fn code_substitutes(&'_ self) -> impl Iterator<Item = (&'_ [u8; 32], &'_ [u8])> + '_ {
+        self.client_spec
+            .code_substitutes
+            .iter()
+            // The length of the keys is verified when parsing the chain spec.
+            .map(|(hash, code)| ((&hash.0[..]).try_into().unwrap(), &code.0[..]))
+    }
+
+    /// Returns the list of hashes of blocks that are known to be bad and must never be
+    /// considered as part of the chain, nor any of their descendants.
+    ///
+    /// This is typically used after a contentious fork, in order to make sure that the node
+    /// doesn't follow the wrong side of the fork.
+    pub fn bad_blocks_hashes(&'_ self) -> impl Iterator<Item = &'_ [u8; 32]> + '_ {
+        self.client_spec
+            .bad_blocks
+            .as_ref()
+            .into_iter()
+            .flat_map(|list| list.iter().map(|hash| &hash.0))
+    }
+
+    /// Returns a list of block heights and hashes. The block at each of these heights in the
+    /// canonical chain must have the corresponding hash. Any other block at these heights, and
+    /// its descendants, must be considered as bad.
+    ///
+    /// This is typically used after a contentious fork, in order to make sure that the node
+    /// follows the right side of the fork.
+    pub fn fork_blocks(&'_ self) -> impl Iterator<Item = (u64, &'_ [u8; 32])> + '_ {
+        self.client_spec
+            .fork_blocks
+            .as_ref()
+            .into_iter()
+            .flat_map(|list| list.iter().map(|(height, hash)| (*height, &hash.0)))
     }
 
     /// Returns the genesis storage value for a key
+    ///
+    /// Always returns `None` if the chain spec contains only the root hash of the genesis
+    /// storage. See [`ChainSpec::genesis_storage`].
     pub fn genesis_storage_value(&self, key: &[u8]) -> Option<&[u8]> {
-        let structs::Genesis::Raw(genesis) = &self.client_spec.genesis;
-        genesis.top.get(key).map(|value| &value.0[..])
+        self.genesis_storage().into_genesis_items()?.value(key)
     }
 
     /// Returns a list of arbitrary properties contained in the chain specs, such as the name of
@@ -236,6 +470,9 @@ impl ChainSpec {
     /// to a UI.
     ///
     /// The returned value is a JSON-formatted map, for example `{"foo":"bar"}`.
+    ///
+    /// See also [`ChainSpec::ss58_format`] and [`ChainSpec::tokens`] for typed accessors to the
+    /// most common properties.
     pub fn properties(&self) -> &str {
         self.client_spec
             .properties
@@ -243,15 +480,158 @@ impl ChainSpec {
             .map(|p| p.get())
             .unwrap_or("{}")
     }
+
+    /// Returns the prefix of the SS58 addresses of the chain, as found in the `ss58Format`
+    /// property.
+    ///
+    /// Returns `None` if the property is missing or isn't a valid prefix.
+    pub fn ss58_format(&self) -> Option<u16> {
+        self.properties_map()
+            .get("ss58Format")?
+            .as_u64()?
+            .try_into()
+            .ok()
+    }
+
+    /// Returns the list of tokens of the chain, as found in the `tokenSymbol` and
+    /// `tokenDecimals` properties. The first token, if any, is the native token of the chain.
+    ///
+    /// Both properties can either contain a single value or an array of values, in which case
+    /// the symbols and decimals are matched by index. Symbols that aren't strings are ignored.
+    pub fn tokens(&self) -> Vec<Token> {
+        let properties = self.properties_map();
+
+        let values = |name: &str| match properties.get(name) {
+            Some(serde_json::Value::Array(list)) => list.clone(),
+            Some(value) => vec![value.clone()],
+            None => Vec::new(),
+        };
+
+        let decimals = values("tokenDecimals");
+        values("tokenSymbol")
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, symbol)| match symbol {
+                serde_json::Value::String(symbol) => Some(Token {
+                    symbol,
+                    decimals: decimals
+                        .get(index)
+                        .and_then(|d| d.as_u64())
+                        .and_then(|d| d.try_into().ok()),
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns the properties of the chain decoded as a JSON object. Returns an empty object if
+    /// the properties aren't a JSON object.
+    fn properties_map(&self) -> serde_json::Map<String, serde_json::Value> {
+        serde_json::from_str(self.properties()).unwrap_or_default()
+    }
+}
+
+/// Token of a chain. See [`ChainSpec::tokens`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    /// Symbol of the token, for example `DOT`.
+    pub symbol: String,
+    /// Number of decimals of the token, in other words the base-10 logarithm of the number of
+    /// indivisible units that make up one token. `None` if unknown.
+    pub decimals: Option<u8>,
+}
+
+/// See [`ChainSpec::genesis_storage`].
+#[derive(Debug, Clone)]
+pub enum GenesisStorage<'a> {
+    /// The chain spec contains the full list of storage items of the genesis block.
+    Items(GenesisStorageItems<'a>),
+    /// The chain spec contains only the root hash of the storage trie of the genesis block.
+    TrieRootHash(&'a [u8; 32]),
+}
+
+impl<'a> GenesisStorage<'a> {
+    /// Returns the list of storage items, or `None` if only the trie root hash is known.
+    pub fn into_genesis_items(self) -> Option<GenesisStorageItems<'a>> {
+        match self {
+            GenesisStorage::Items(items) => Some(items),
+            GenesisStorage::TrieRootHash(_) => None,
+        }
+    }
+
+    /// Returns the trie root hash, or `None` if the full list of storage items is known instead.
+    pub fn into_trie_root_hash(self) -> Option<&'a [u8; 32]> {
+        match self {
+            GenesisStorage::Items(_) => None,
+            GenesisStorage::TrieRootHash(hash) => Some(hash),
+        }
+    }
+}
+
+/// List of storage items of the genesis block. See [`GenesisStorage::Items`].
+#[derive(Debug, Clone)]
+pub struct GenesisStorageItems<'a> {
+    raw: &'a structs::RawGenesis,
+}
+
+impl<'a> GenesisStorageItems<'a> {
+    /// Returns the list of storage keys and values, ordered by key.
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = (&'a [u8], &'a [u8])> + Clone {
+        let raw: &'a structs::RawGenesis = self.raw;
+        raw.top.iter().map(|(k, v)| (&k.0[..], &v.0[..]))
+    }
+
+    /// Returns the value associated to the given key, if any.
+    pub fn value(&self, key: &[u8]) -> Option<&'a [u8]> {
+        self.raw.top.get(key).map(|value| &value.0[..])
+    }
 }
 
 /// Error that can happen when parsing a chain spec JSON.
 #[derive(Debug, derive_more::Display)]
-pub struct ParseError(serde_json::Error);
+pub struct ParseError(ParseErrorInner);
+
+impl ParseError {
+    /// Returns the list of problems found in the content of the chain spec.
+    ///
+    /// Empty if the JSON document is malformed or doesn't have the structure of a chain spec, in
+    /// which case only the [`fmt::Display`] implementation describes the error.
+    pub fn validation_errors(&self) -> &[ValidationError] {
+        match &self.0 {
+            ParseErrorInner::Serde(_) => &[],
+            ParseErrorInner::Validation(errors) => &errors.0,
+        }
+    }
+}
+
+#[derive(Debug, derive_more::Display)]
+enum ParseErrorInner {
+    #[display(fmt = "{}", _0)]
+    Serde(serde_json::Error),
+    #[display(fmt = "{}", _0)]
+    Validation(ValidationErrors),
+}
+
+/// List of problems found in a chain spec. Always contains at least one element.
+#[derive(Debug)]
+struct ValidationErrors(Vec<ValidationError>);
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid chain spec: ")?;
+        for (index, error) in self.0.iter().enumerate() {
+            if index != 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", error)?;
+        }
+        Ok(())
+    }
+}
 
 #[cfg(test)]
 mod tests {
-    use super::ChainSpec;
+    use super::{ChainSpec, ChainSpecBuilder, Token};
 
     #[test]
     fn can_decode_polkadot_genesis() {
@@ -259,4 +639,163 @@ mod tests {
         let specs = ChainSpec::from_json_bytes(&spec).unwrap();
         assert_eq!(specs.id(), "polkadot");
     }
+
+    #[test]
+    fn builder_roundtrip() {
+        let specs = ChainSpecBuilder::new("Test", "test")
+            .with_chain_type("Local")
+            .with_boot_node(
+                "/ip4/127.0.0.1/tcp/30333/p2p/12D3KooWEdsXX9657ppNqqrRuaCHFvuNemasgU5msLDwSJ6WqsKc",
+            )
+            .with_protocol_id("tst")
+            .with_fork_id("fork")
+            .with_properties(r#"{"tokenSymbol":"TST"}"#)
+            .unwrap()
+            .with_genesis_storage(vec![(b"foo".to_vec(), b"bar".to_vec())])
+            .with_parachain("relay", 1000)
+            .with_extension("foo", r#"{"bar":5}"#)
+            .unwrap()
+            .with_bad_block([1; 32])
+            .with_fork_block(5, [2; 32])
+            .with_code_substitute([3; 32], vec![0, 1, 2])
+            .build();
+
+        let decoded = ChainSpec::from_json_bytes(specs.to_json()).unwrap();
+        assert_eq!(decoded.name(), "Test");
+        assert_eq!(decoded.chain_type(), "Local");
+        assert_eq!(
+            decoded.boot_nodes(),
+            &[
+                "/ip4/127.0.0.1/tcp/30333/p2p/12D3KooWEdsXX9657ppNqqrRuaCHFvuNemasgU5msLDwSJ6WqsKc"
+                    .to_owned()
+            ]
+        );
+        assert_eq!(decoded.protocol_id(), "tst");
+        assert_eq!(decoded.fork_id(), Some("fork"));
+        assert_eq!(decoded.properties(), r#"{"tokenSymbol":"TST"}"#);
+        assert_eq!(decoded.genesis_storage_value(b"foo"), Some(&b"bar"[..]));
+        assert_eq!(decoded.relay_chain_id(), Some("relay"));
+        assert_eq!(decoded.para_id(), Some(1000));
+        assert_eq!(decoded.extension("foo").unwrap(), r#"{"bar":5}"#);
+        assert_eq!(decoded.extensions_names().collect::<Vec<_>>(), vec!["foo"]);
+        assert_eq!(
+            decoded.bad_blocks_hashes().collect::<Vec<_>>(),
+            vec![&[1u8; 32]]
+        );
+        assert_eq!(
+            decoded.fork_blocks().collect::<Vec<_>>(),
+            vec![(5, &[2u8; 32])]
+        );
+        assert_eq!(
+            decoded.code_substitutes().collect::<Vec<_>>(),
+            vec![(&[3u8; 32], &[0u8, 1, 2][..])]
+        );
+        assert!(decoded.light_sync_state().is_none());
+    }
+
+    #[test]
+    fn typed_properties() {
+        let specs = ChainSpecBuilder::new("Test", "test")
+            .with_properties(r#"{"ss58Format":2,"tokenSymbol":["KSM","FOO"],"tokenDecimals":12}"#)
+            .unwrap()
+            .build();
+        assert_eq!(specs.ss58_format(), Some(2));
+        assert_eq!(
+            specs.tokens(),
+            vec![
+                Token {
+                    symbol: "KSM".into(),
+                    decimals: Some(12)
+                },
+                Token {
+                    symbol: "FOO".into(),
+                    decimals: None
+                }
+            ]
+        );
+
+        let specs = ChainSpecBuilder::new("Test", "test")
+            .with_properties(r#"{"ss58Format":"bad","tokenSymbol":"DOT"}"#)
+            .unwrap()
+            .build();
+        assert_eq!(specs.ss58_format(), None);
+        assert_eq!(
+            specs.tokens(),
+            vec![Token {
+                symbol: "DOT".into(),
+                decimals: None
+            }]
+        );
+    }
+
+    #[test]
+    fn state_root_hash_only() {
+        let spec = &include_bytes!("../bin/polkadot.json")[..];
+        let full = ChainSpec::from_json_bytes(&spec).unwrap();
+        let genesis_block_header = crate::calculate_genesis_block_header(&full);
+
+        let mut json: serde_json::Value = serde_json::from_slice(spec).unwrap();
+        json["genesis"] = serde_json::json!({
+            "stateRootHash": format!("0x{}", hex::encode(&genesis_block_header.state_root)),
+        });
+        let state_root_only = ChainSpec::from_json_bytes(json.to_string()).unwrap();
+
+        assert_eq!(
+            state_root_only.genesis_storage().into_trie_root_hash(),
+            Some(&genesis_block_header.state_root)
+        );
+        assert!(state_root_only.genesis_storage_value(b":code").is_none());
+        assert_eq!(
+            crate::calculate_genesis_block_header(&state_root_only).hash(),
+            genesis_block_header.hash()
+        );
+        assert!(matches!(
+            crate::chain::chain_information::ChainInformation::from_chain_spec(&state_root_only),
+            Err(crate::chain::chain_information::FromGenesisStorageError::UnknownStorageItems)
+        ));
+        assert!(state_root_only.light_sync_state().is_some());
+    }
+
+    #[test]
+    fn reports_all_validation_errors() {
+        let specs = ChainSpecBuilder::new("Test", "test")
+            .with_boot_node("/ip4/127.0.0.1/tcp/30333")
+            .with_boot_node("not a multiaddr")
+            .with_telemetry_endpoint("http://example.com", 0)
+            .with_genesis_storage(vec![(b":heappages".to_vec(), vec![1, 2, 3])])
+            .with_bad_block([2; 32])
+            .with_fork_block(5, [2; 32])
+            .build();
+
+        let error = match ChainSpec::from_json_bytes(specs.to_json()) {
+            Err(err) => err,
+            Ok(_) => panic!(),
+        };
+        let paths = error
+            .validation_errors()
+            .iter()
+            .map(|err| &err.json_path[..])
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            vec![
+                "bootNodes[0]",
+                "bootNodes[1]",
+                "telemetryEndpoints[0][0]",
+                "genesis.raw.top[\"0x3a686561707061676573\"]",
+                "forkBlocks[0]",
+            ]
+        );
+    }
+
+    #[test]
+    fn can_decode_light_sync_state() {
+        let spec = &include_bytes!("../bin/polkadot.json")[..];
+        let specs = ChainSpec::from_json_bytes(&spec).unwrap();
+        let light_sync_state = specs.light_sync_state().unwrap();
+        assert_eq!(light_sync_state.finalized_block_header().number, 4879872);
+        assert_eq!(light_sync_state.grandpa_authorities().len(), 297);
+        let (current, next) = light_sync_state.babe_epochs();
+        assert_eq!(current.epoch_index + 1, next.epoch_index);
+    }
 }
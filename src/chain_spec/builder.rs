@@ -0,0 +1,228 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Construction of chain specs in code.
+//!
+//! See [`ChainSpecBuilder`].
+
+use super::{structs, ChainSpec};
+
+use alloc::{borrow::ToOwned as _, collections::BTreeMap, string::String, vec::Vec};
+
+/// Builds a [`ChainSpec`] field by field.
+///
+/// # Example
+///
+/// ```
+/// use smoldot::chain_spec::ChainSpecBuilder;
+///
+/// let chain_spec = ChainSpecBuilder::new("Test chain", "test")
+///     .with_chain_type("Development")
+///     .with_boot_node("/dns/example.com/tcp/30333/p2p/12D3KooWEyoppNCUx8Yx66oV9fJnriXwCcXwDDUA2kj6vnc6iDEp")
+///     .with_genesis_storage(vec![(b":code".to_vec(), vec![0; 16])])
+///     .build();
+///
+/// assert_eq!(chain_spec.id(), "test");
+/// assert_eq!(chain_spec.genesis_storage_value(b":code"), Some(&[0; 16][..]));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ChainSpecBuilder {
+    client_spec: structs::ClientSpec,
+}
+
+impl ChainSpecBuilder {
+    /// Starts building a chain spec with the given name and identifier. See [`ChainSpec::name`]
+    /// and [`ChainSpec::id`].
+    ///
+    /// All the other fields are empty by default, and the chain type is `Live`.
+    pub fn new(name: impl Into<String>, id: impl Into<String>) -> Self {
+        ChainSpecBuilder {
+            client_spec: structs::ClientSpec {
+                name: name.into(),
+                id: id.into(),
+                chain_type: Default::default(),
+                code_substitutes: Default::default(),
+                boot_nodes: Vec::new(),
+                telemetry_endpoints: None,
+                protocol_id: None,
+                fork_id: None,
+                properties: None,
+                fork_blocks: None,
+                bad_blocks: None,
+                consensus_engine: serde_json::Value::Null,
+                genesis: structs::Genesis::Raw(structs::RawGenesis {
+                    top: BTreeMap::new(),
+                    children_default: BTreeMap::new(),
+                }),
+                light_sync_state: None,
+                parachain: None,
+                extensions: Default::default(),
+            },
+        }
+    }
+
+    /// Sets the type of the chain. See [`ChainSpec::chain_type`].
+    ///
+    /// `Development`, `Local` and `Live` are recognized. Any other value is a custom type.
+    pub fn with_chain_type(mut self, chain_type: &str) -> Self {
+        self.client_spec.chain_type = match chain_type {
+            "Development" => structs::ChainType::Development,
+            "Local" => structs::ChainType::Local,
+            "Live" => structs::ChainType::Live,
+            other => structs::ChainType::Custom(other.to_owned()),
+        };
+        self
+    }
+
+    /// Adds a bootnode address. See [`ChainSpec::boot_nodes`].
+    pub fn with_boot_node(mut self, address: impl Into<String>) -> Self {
+        self.client_spec.boot_nodes.push(address.into());
+        self
+    }
+
+    /// Adds a telemetry endpoint, with the given verbosity level. See
+    /// [`ChainSpec::telemetry_endpoints`].
+    pub fn with_telemetry_endpoint(mut self, address: impl Into<String>, verbosity: u8) -> Self {
+        self.client_spec
+            .telemetry_endpoints
+            .get_or_insert_with(Vec::new)
+            .push((address.into(), verbosity));
+        self
+    }
+
+    /// Sets the network protocol id. See [`ChainSpec::protocol_id`].
+    pub fn with_protocol_id(mut self, protocol_id: impl Into<String>) -> Self {
+        self.client_spec.protocol_id = Some(protocol_id.into());
+        self
+    }
+
+    /// Sets the fork id. See [`ChainSpec::fork_id`].
+    pub fn with_fork_id(mut self, fork_id: impl Into<String>) -> Self {
+        self.client_spec.fork_id = Some(fork_id.into());
+        self
+    }
+
+    /// Sets the arbitrary properties of the chain. See [`ChainSpec::properties`].
+    ///
+    /// Returns an error if `properties` isn't valid JSON.
+    pub fn with_properties(mut self, properties: &str) -> Result<Self, InvalidJsonError> {
+        let properties = serde_json::value::RawValue::from_string(properties.to_owned())
+            .map_err(InvalidJsonError)?;
+        self.client_spec.properties = Some(properties);
+        Ok(self)
+    }
+
+    /// Adds entries to the storage of the genesis block. Overwrites the values of the keys that
+    /// have already been added. See [`ChainSpec::genesis_storage`].
+    ///
+    /// Overrides any value previously passed to [`ChainSpecBuilder::with_genesis_state_root_hash`].
+    pub fn with_genesis_storage(
+        mut self,
+        entries: impl IntoIterator<Item = (impl Into<Vec<u8>>, impl Into<Vec<u8>>)>,
+    ) -> Self {
+        if let structs::Genesis::StateRootHash(_) = self.client_spec.genesis {
+            self.client_spec.genesis = structs::Genesis::Raw(structs::RawGenesis {
+                top: BTreeMap::new(),
+                children_default: BTreeMap::new(),
+            });
+        }
+
+        let genesis = match &mut self.client_spec.genesis {
+            structs::Genesis::Raw(genesis) => genesis,
+            structs::Genesis::StateRootHash(_) => unreachable!(),
+        };
+        for (key, value) in entries {
+            genesis.top.insert(
+                structs::HexString(key.into()),
+                structs::HexString(value.into()),
+            );
+        }
+        self
+    }
+
+    /// Adds a runtime code substitution. See [`ChainSpec::code_substitutes`].
+    pub fn with_code_substitute(mut self, block_hash: [u8; 32], code: impl Into<Vec<u8>>) -> Self {
+        self.client_spec.code_substitutes.insert(
+            structs::HexString(block_hash.to_vec()),
+            structs::HexString(code.into()),
+        );
+        self
+    }
+
+    /// Sets the root hash of the storage trie of the genesis block, instead of providing the
+    /// full genesis storage. See [`ChainSpec::genesis_storage`].
+    ///
+    /// Discards any entry previously passed to [`ChainSpecBuilder::with_genesis_storage`].
+    pub fn with_genesis_state_root_hash(mut self, hash: [u8; 32]) -> Self {
+        self.client_spec.genesis = structs::Genesis::StateRootHash(structs::HashHexString(hash));
+        self
+    }
+
+    /// Adds the hash of a block known to be bad. See [`ChainSpec::bad_blocks_hashes`].
+    pub fn with_bad_block(mut self, hash: [u8; 32]) -> Self {
+        self.client_spec
+            .bad_blocks
+            .get_or_insert_with(Default::default)
+            .insert(structs::HashHexString(hash));
+        self
+    }
+
+    /// Forces the block at the given height to have the given hash. See
+    /// [`ChainSpec::fork_blocks`].
+    pub fn with_fork_block(mut self, height: u64, hash: [u8; 32]) -> Self {
+        self.client_spec
+            .fork_blocks
+            .get_or_insert_with(Vec::new)
+            .push((height, structs::HashHexString(hash)));
+        self
+    }
+
+    /// Marks the chain as a parachain of the given relay chain. See [`ChainSpec::relay_chain`].
+    pub fn with_parachain(mut self, relay_chain: impl Into<String>, para_id: u32) -> Self {
+        self.client_spec.parachain = Some(structs::ChainSpecParachain {
+            relay_chain: relay_chain.into(),
+            para_id,
+        });
+        self
+    }
+
+    /// Adds a non-standard field, called extension. See [`ChainSpec::extension`].
+    ///
+    /// Returns an error if `value` isn't valid JSON.
+    pub fn with_extension(
+        mut self,
+        name: impl Into<String>,
+        value: &str,
+    ) -> Result<Self, InvalidJsonError> {
+        let value = serde_json::from_str(value).map_err(InvalidJsonError)?;
+        self.client_spec.extensions.insert(name.into(), value);
+        Ok(self)
+    }
+
+    /// Finishes building the chain spec.
+    pub fn build(self) -> ChainSpec {
+        ChainSpec {
+            client_spec: self.client_spec,
+            light_sync_state: None,
+        }
+    }
+}
+
+/// Error potentially returned by [`ChainSpecBuilder::with_properties`] and
+/// [`ChainSpecBuilder::with_extension`].
+#[derive(Debug, derive_more::Display)]
+pub struct InvalidJsonError(serde_json::Error);
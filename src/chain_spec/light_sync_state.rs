@@ -32,27 +32,43 @@ pub(super) struct LightSyncState {
 }
 
 impl LightSyncState {
-    pub(super) fn decode(&self) -> DecodedLightSyncState {
+    pub(super) fn decode(&self) -> Result<DecodedLightSyncState, DecodeError> {
         let grandpa_authority_set_slice = &self.grandpa_authority_set.0[..];
         let babe_epoch_changes_slice = &self.babe_epoch_changes.0[..];
 
         let decoded = DecodedLightSyncState {
             babe_finalized_block_weight: self.babe_finalized_block_weight,
             finalized_block_header: crate::header::decode(&self.finalized_block_header.0[..])
-                .unwrap()
+                .map_err(DecodeError::FinalizedBlockHeader)?
                 .into(),
-            grandpa_authority_set: AuthoritySet::decode_all(&grandpa_authority_set_slice).unwrap(),
-            babe_epoch_changes: EpochChanges::decode_all(&babe_epoch_changes_slice).unwrap(),
+            grandpa_authority_set: AuthoritySet::decode_all(&grandpa_authority_set_slice)
+                .map_err(DecodeError::GrandpaAuthoritySet)?,
+            babe_epoch_changes: EpochChanges::decode_all(&babe_epoch_changes_slice)
+                .map_err(DecodeError::BabeEpochChanges)?,
         };
 
-        decoded
+        Ok(decoded)
     }
 }
 
+/// Error potentially returned by [`LightSyncState::decode`].
+#[derive(Debug, derive_more::Display)]
+pub(super) enum DecodeError {
+    /// Failed to decode the finalized block header.
+    #[display(fmt = "Failed to decode finalized block header: {}", _0)]
+    FinalizedBlockHeader(crate::header::Error),
+    /// Failed to decode the GrandPa authority set.
+    #[display(fmt = "Failed to decode GrandPa authority set: {}", _0)]
+    GrandpaAuthoritySet(parity_scale_codec::Error),
+    /// Failed to decode the Babe epoch changes.
+    #[display(fmt = "Failed to decode Babe epoch changes: {}", _0)]
+    BabeEpochChanges(parity_scale_codec::Error),
+}
+
 #[derive(Debug)]
 pub(super) struct DecodedLightSyncState {
     pub(super) babe_epoch_changes: EpochChanges,
-    babe_finalized_block_weight: u32,
+    pub(super) babe_finalized_block_weight: u32,
     pub(super) finalized_block_header: crate::header::Header,
     pub(super) grandpa_authority_set: AuthoritySet,
 }
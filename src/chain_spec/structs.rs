@@ -27,9 +27,10 @@ use fnv::FnvBuildHasher;
 use hashbrown::{HashMap, HashSet};
 use serde::{Deserialize, Serialize};
 
+// Note: `deny_unknown_fields` isn't used, as the fields that aren't known are collected in
+// `extensions`.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
-#[serde(deny_unknown_fields)]
 pub(super) struct ClientSpec {
     pub(super) name: String,
     pub(super) id: String,
@@ -48,22 +49,26 @@ pub(super) struct ClientSpec {
     ///
     /// See also <https://github.com/paritytech/substrate/pull/8898>.
     #[serde(default)]
-    // TODO: make use of this
     pub(super) code_substitutes: HashMap<HexString, HexString, fnv::FnvBuildHasher>,
     pub(super) boot_nodes: Vec<String>,
     pub(super) telemetry_endpoints: Option<Vec<(String, u8)>>,
     pub(super) protocol_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) fork_id: Option<String>,
     pub(super) properties: Option<Box<serde_json::value::RawValue>>,
-    // TODO: make use of this
     pub(super) fork_blocks: Option<Vec<(u64, HashHexString)>>,
-    // TODO: make use of this
     pub(super) bad_blocks: Option<HashSet<HashHexString, FnvBuildHasher>>,
-    // Unused but for some reason still part of the chain specs.
-    pub(super) consensus_engine: (),
+    // Unused but for some reason still part of the chain specs. Always `null`, which is
+    // verified when validating the chain spec.
+    pub(super) consensus_engine: serde_json::Value,
     pub(super) genesis: Genesis,
     pub(super) light_sync_state: Option<LightSyncState>,
     #[serde(flatten)]
     pub(super) parachain: Option<ChainSpecParachain>,
+    /// Fields that aren't part of the list above. Chain specs can be extended with arbitrary
+    /// fields, called extensions.
+    #[serde(flatten)]
+    pub(super) extensions: serde_json::Map<String, serde_json::Value>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -92,6 +97,7 @@ impl Default for ChainType {
 #[serde(deny_unknown_fields)]
 pub(super) enum Genesis {
     Raw(RawGenesis),
+    StateRootHash(HashHexString),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
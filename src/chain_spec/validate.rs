@@ -0,0 +1,169 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Validation of the content of a chain spec.
+//!
+//! Deserializing the JSON document only guarantees that the chain spec has the expected
+//! structure. The function in this module verifies the content of the fields, and reports all
+//! the problems found at once, each with the location of the problem within the document.
+
+use super::{structs, InvalidLightSyncStateError};
+use crate::{executor, libp2p::multiaddr, libp2p::peer_id::PeerId, telemetry};
+
+use alloc::{collections::BTreeMap, format, string::String, vec::Vec};
+
+/// Problem found in a chain spec. See [`super::ParseError::validation_errors`].
+#[derive(Debug, derive_more::Display)]
+#[display(fmt = "{}: {}", json_path, problem)]
+pub struct ValidationError {
+    /// Location of the problem within the JSON document, for example `bootNodes[2]`.
+    pub json_path: String,
+    /// Description of the problem.
+    pub problem: ValidationProblem,
+}
+
+/// See [`ValidationError::problem`].
+#[derive(Debug, derive_more::Display)]
+pub enum ValidationProblem {
+    /// Bootnode address isn't a valid multiaddress.
+    #[display(fmt = "Invalid multiaddress: {}", _0)]
+    InvalidBootNodeAddress(multiaddr::Error),
+    /// Bootnode address doesn't end with a `/p2p/` component containing a valid peer id.
+    #[display(fmt = "Multiaddress doesn't end with a valid /p2p/ component")]
+    BootNodeMissingPeerId,
+    /// Telemetry endpoint can't be parsed.
+    #[display(fmt = "{}", _0)]
+    InvalidTelemetryEndpoint(telemetry::ParseEndpointError),
+    /// Key of a code substitute isn't a 32 bytes block hash.
+    #[display(fmt = "Key isn't a 32 bytes block hash")]
+    InvalidCodeSubstituteHash,
+    /// Genesis storage contains child tries, which aren't supported.
+    #[display(fmt = "Child tries in the genesis storage aren't supported")]
+    UnsupportedChildTries,
+    /// The `:heappages` value of the genesis storage is invalid.
+    #[display(fmt = "Invalid number of heap pages: {}", _0)]
+    InvalidHeapPages(executor::InvalidHeapPagesError),
+    /// The `consensusEngine` field is deprecated and must always be `null`.
+    #[display(fmt = "Unknown consensus engine fields; must be null")]
+    UnknownConsensusEngine,
+    /// The same height appears multiple times with different hashes.
+    #[display(fmt = "Conflicting hashes for the same block height")]
+    ConflictingForkBlocks,
+    /// The hash of a fork block is also in the list of bad blocks.
+    #[display(fmt = "Block is also in the list of bad blocks")]
+    ForkBlockMarkedBad,
+    /// Failed to interpret the checkpoint.
+    #[display(fmt = "{}", _0)]
+    InvalidLightSyncState(InvalidLightSyncStateError),
+}
+
+/// Verifies the content of the given chain spec and returns the list of problems found.
+///
+/// The `lightSyncState` field isn't verified by this function, as decoding it is necessary
+/// anyway in order to build the chain spec.
+pub(super) fn validate(client_spec: &structs::ClientSpec) -> Vec<ValidationError> {
+    let mut errors = Vec::new();
+
+    for (index, address) in client_spec.boot_nodes.iter().enumerate() {
+        let problem = match address.parse::<multiaddr::Multiaddr>() {
+            Ok(mut address) => match address.pop() {
+                Some(multiaddr::Protocol::P2p(peer_id)) => match PeerId::from_multihash(peer_id) {
+                    Ok(_) => continue,
+                    Err(_) => ValidationProblem::BootNodeMissingPeerId,
+                },
+                _ => ValidationProblem::BootNodeMissingPeerId,
+            },
+            Err(err) => ValidationProblem::InvalidBootNodeAddress(err),
+        };
+
+        errors.push(ValidationError {
+            json_path: format!("bootNodes[{}]", index),
+            problem,
+        });
+    }
+
+    for (index, (address, _)) in client_spec.telemetry_endpoints.iter().flatten().enumerate() {
+        if let Err(err) = telemetry::parse_endpoint(address) {
+            errors.push(ValidationError {
+                json_path: format!("telemetryEndpoints[{}][0]", index),
+                problem: ValidationProblem::InvalidTelemetryEndpoint(err),
+            });
+        }
+    }
+
+    for block_hash in client_spec.code_substitutes.keys() {
+        if block_hash.0.len() != 32 {
+            errors.push(ValidationError {
+                json_path: format!("codeSubstitutes[\"0x{}\"]", hex::encode(&block_hash.0)),
+                problem: ValidationProblem::InvalidCodeSubstituteHash,
+            });
+        }
+    }
+
+    if !client_spec.consensus_engine.is_null() {
+        errors.push(ValidationError {
+            json_path: "consensusEngine".into(),
+            problem: ValidationProblem::UnknownConsensusEngine,
+        });
+    }
+
+    if let structs::Genesis::Raw(genesis) = &client_spec.genesis {
+        if !genesis.children_default.is_empty() {
+            errors.push(ValidationError {
+                json_path: "genesis.raw.childrenDefault".into(),
+                problem: ValidationProblem::UnsupportedChildTries,
+            });
+        }
+        if let Err(err) = executor::storage_heap_pages_to_value(
+            genesis
+                .top
+                .get(&b":heappages"[..])
+                .map(|value| &value.0[..]),
+        ) {
+            errors.push(ValidationError {
+                json_path: format!("genesis.raw.top[\"0x{}\"]", hex::encode(b":heappages")),
+                problem: ValidationProblem::InvalidHeapPages(err),
+            });
+        }
+    }
+
+    let mut fork_blocks = BTreeMap::new();
+    for (index, (height, hash)) in client_spec.fork_blocks.iter().flatten().enumerate() {
+        if client_spec
+            .bad_blocks
+            .as_ref()
+            .map_or(false, |bad| bad.contains(hash))
+        {
+            errors.push(ValidationError {
+                json_path: format!("forkBlocks[{}]", index),
+                problem: ValidationProblem::ForkBlockMarkedBad,
+            });
+        }
+
+        if fork_blocks
+            .insert(*height, hash)
+            .map_or(false, |previous| previous != hash)
+        {
+            errors.push(ValidationError {
+                json_path: format!("forkBlocks[{}]", index),
+                problem: ValidationProblem::ConflictingForkBlocks,
+            });
+        }
+    }
+
+    errors
+}
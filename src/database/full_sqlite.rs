@@ -40,6 +40,12 @@
 //! its ancestors is lost, and the only way to reconstruct it is to execute all blocks starting
 //! from the genesis to the desired one.
 //!
+//! The storage of the finalized block and of its descendants can be read using, for example,
+//! [`SqliteFullDatabase::block_storage_top_trie_get`]. In addition to the storage items, the
+//! database holds the Merkle value of each node of the trie of the finalized block, which makes
+//! it possible to build storage proofs with [`SqliteFullDatabase::block_storage_top_trie_proof`]
+//! without loading the entire storage. Child tries aren't stored.
+//!
 //! # About errors handling
 //!
 //! Most of the functions and methods in this module return a `Result` containing notably an
@@ -60,7 +66,7 @@
 #![cfg(feature = "database-sqlite")]
 #![cfg_attr(docsrs, doc(cfg(feature = "database-sqlite")))]
 
-use crate::{chain::chain_information, header, util};
+use crate::{chain::chain_information, header, trie, util};
 
 use core::{
     convert::TryFrom,
@@ -69,10 +75,15 @@ use core::{
     num::NonZeroU64,
 };
 use parking_lot::Mutex;
+use std::collections::BTreeMap;
 
 pub use open::{open, Config, ConfigTy, DatabaseEmpty, DatabaseOpen};
 
 mod open;
+mod trie_nodes;
+
+#[cfg(test)]
+mod tests;
 
 /// An open database. Holds file descriptors.
 pub struct SqliteFullDatabase {
@@ -294,8 +305,9 @@ impl SqliteFullDatabase {
 
     /// Insert a new block in the database.
     ///
-    /// Must pass the header and body of the block, and the changes to the storage that this block
-    /// performs relative to its parent.
+    /// Must pass the header and body of the block, the changes to the storage that this block
+    /// performs relative to its parent, and the version of the trie entries with which the
+    /// storage root of the block has been calculated.
     ///
     /// Blocks must be inserted in the correct order. An error is returned if the parent of the
     /// newly-inserted block isn't present in the database.
@@ -306,6 +318,7 @@ impl SqliteFullDatabase {
         body: impl ExactSizeIterator<Item = impl AsRef<[u8]>>,
         storage_top_trie_changes: impl Iterator<Item = (impl AsRef<[u8]>, Option<impl AsRef<[u8]>>)>
             + Clone,
+        trie_entries_version: trie::TrieEntryVersion,
     ) -> Result<(), InsertError> {
         // Calculate the hash of the new best block.
         let block_hash = header::hash_from_scale_encoded_header(scale_encoded_header);
@@ -335,7 +348,7 @@ impl SqliteFullDatabase {
 
         let mut statement = connection
             .prepare(
-                "INSERT INTO blocks(number, hash, header, justification) VALUES (?, ?, ?, NULL)",
+                "INSERT INTO blocks(number, hash, header, justification, trie_entries_version) VALUES (?, ?, ?, NULL, ?)",
             )
             .unwrap();
         statement
@@ -343,6 +356,9 @@ impl SqliteFullDatabase {
             .unwrap();
         statement.bind(2, &block_hash[..]).unwrap();
         statement.bind(3, scale_encoded_header).unwrap();
+        statement
+            .bind(4, trie_entries_version_to_number(trie_entries_version))
+            .unwrap();
         statement.next().unwrap();
 
         let mut statement = connection
@@ -508,6 +524,26 @@ impl SqliteFullDatabase {
                     AccessError::Corrupted(CorruptedError::MissingBlockHeader),
                 ))?;
 
+            // The Merkle values of the nodes of the trie are calculated with the trie entries
+            // version of the finalized block. If this version changes, the Merkle values of all
+            // the nodes must be calculated again, which is done after the storage is updated.
+            let trie_entries_version = block_trie_entries_version(&connection, &block_hash)?;
+            let trie_nodes_view = if trie_entries_version
+                == block_trie_entries_version(&connection, &block_header.parent_hash)?
+            {
+                let mut view = trie_nodes::StorageView::new(
+                    &connection,
+                    block_storage_changes(&connection, &block_hash)?
+                        .into_iter()
+                        .collect(),
+                    trie_entries_version,
+                );
+                view.update_nodes()?;
+                Some(view)
+            } else {
+                None
+            };
+
             let mut statement = connection
                 .prepare(
                     "DELETE FROM finalized_storage_top_trie
@@ -537,6 +573,18 @@ impl SqliteFullDatabase {
             statement.bind(1, &block_hash[..]).unwrap();
             statement.next().unwrap();
 
+            if let Some(trie_nodes_view) = trie_nodes_view {
+                trie_nodes_view.write_nodes()?;
+            } else {
+                connection
+                    .execute("DELETE FROM finalized_storage_top_trie_nodes")
+                    .unwrap();
+                let mut view =
+                    trie_nodes::StorageView::new_all_nodes(&connection, trie_entries_version);
+                view.update_nodes()?;
+                view.write_nodes()?;
+            }
+
             // TODO: the code below is very verbose and redundant with other similar code in smoldot ; could be improved
 
             if let Some((new_epoch, next_config)) = block_header.digest.babe_epoch_information() {
@@ -796,6 +844,184 @@ impl SqliteFullDatabase {
 
         Ok(out)
     }
+
+    /// Returns the value associated to a key in the storage of the given block.
+    ///
+    /// The block must be either the finalized block or one of its descendants.
+    pub fn block_storage_top_trie_get(
+        &self,
+        block_hash: &[u8; 32],
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>, StorageAccessError> {
+        let connection = self.database.lock();
+        let view = block_storage_view(&connection, block_hash)?;
+        Ok(view.get(key)?)
+    }
+
+    /// Returns the key in the storage of the given block that immediately follows the key
+    /// passed as parameter.
+    ///
+    /// The block must be either the finalized block or one of its descendants.
+    pub fn block_storage_top_trie_next_key(
+        &self,
+        block_hash: &[u8; 32],
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>, StorageAccessError> {
+        let connection = self.database.lock();
+        let view = block_storage_view(&connection, block_hash)?;
+        Ok(view.next_key(key)?)
+    }
+
+    /// Builds a proof containing all the node values necessary to find the storage values of
+    /// the given keys in the storage of the given block, or to prove their absence.
+    ///
+    /// The block must be either the finalized block or one of its descendants.
+    ///
+    /// Only the storage entries and trie nodes necessary for the proof are read from the
+    /// database. If the block isn't the finalized block, the Merkle values of the trie nodes
+    /// affected by the storage changes between the finalized block and the requested block are
+    /// calculated again.
+    pub fn block_storage_top_trie_proof(
+        &self,
+        block_hash: &[u8; 32],
+        keys: impl Iterator<Item = impl AsRef<[u8]>>,
+    ) -> Result<Vec<Vec<u8>>, StorageAccessError> {
+        let connection = self.database.lock();
+        let mut view = block_storage_view(&connection, block_hash)?;
+
+        // The Merkle values stored in the database have been calculated with the trie entries
+        // version of the finalized block. If the version of the requested block is different,
+        // the Merkle values of all the nodes would have to be calculated again.
+        let finalized_hash = finalized_hash(&connection)?;
+        if block_trie_entries_version(&connection, block_hash)?
+            != block_trie_entries_version(&connection, &finalized_hash)?
+        {
+            return Err(StorageAccessError::TrieEntriesVersionMismatch);
+        }
+
+        view.update_nodes()?;
+        Ok(view.build_proof(keys)?)
+    }
+
+    /// Returns the ed25519 private key previously stored using
+    /// [`SqliteFullDatabase::set_network_identity_key`], or `None` if no key has been stored.
+    pub fn network_identity_key(&self) -> Result<Option<[u8; 32]>, AccessError> {
+        let connection = self.database.lock();
+
+        let val = match meta_get_blob(&connection, "network_identity_key")? {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+
+        <[u8; 32]>::try_from(&val[..])
+            .map(Some)
+            .map_err(|_| AccessError::Corrupted(CorruptedError::InvalidNetworkIdentityKeyLen))
+    }
+
+    /// Stores the ed25519 private key used as the identity of the local node on the
+    /// peer-to-peer network, so that it can be retrieved after a restart using
+    /// [`SqliteFullDatabase::network_identity_key`].
+    pub fn set_network_identity_key(&self, key: &[u8; 32]) -> Result<(), AccessError> {
+        let connection = self.database.lock();
+        meta_set_blob(&connection, "network_identity_key", &key[..])?;
+        Ok(())
+    }
+
+    /// Returns the list of network addresses previously stored using
+    /// [`SqliteFullDatabase::set_network_addresses`], in no specific order.
+    pub fn network_addresses(&self) -> Result<Vec<NetworkAddress>, AccessError> {
+        let connection = self.database.lock();
+
+        let mut statement = connection
+            .prepare(r#"SELECT peer_id, address, consecutive_failures FROM network_addresses"#)
+            .map_err(InternalError)
+            .map_err(CorruptedError::Internal)?;
+
+        let mut out = Vec::new();
+        while matches!(
+            statement
+                .next()
+                .map_err(InternalError)
+                .map_err(CorruptedError::Internal)?,
+            sqlite::State::Row
+        ) {
+            let peer_id = statement
+                .read::<Vec<u8>>(0)
+                .map_err(InternalError)
+                .map_err(CorruptedError::Internal)?;
+            let address = statement
+                .read::<Vec<u8>>(1)
+                .map_err(InternalError)
+                .map_err(CorruptedError::Internal)?;
+            let consecutive_failures = statement
+                .read::<i64>(2)
+                .map_err(InternalError)
+                .map_err(CorruptedError::Internal)?;
+            let consecutive_failures = u32::try_from(consecutive_failures)
+                .map_err(|_| AccessError::Corrupted(CorruptedError::InvalidNumber))?;
+
+            out.push(NetworkAddress {
+                peer_id,
+                address,
+                consecutive_failures,
+            });
+        }
+
+        Ok(out)
+    }
+
+    /// Replaces the list of network addresses stored in the database with the given list.
+    ///
+    /// The database doesn't interpret the content of the addresses. It is the responsibility of
+    /// the API user to make sure that they are valid.
+    ///
+    /// The replacement is atomic: if an error happens, the list stored in the database is left
+    /// untouched.
+    pub fn set_network_addresses(
+        &self,
+        addresses: impl Iterator<Item = NetworkAddress>,
+    ) -> Result<(), AccessError> {
+        let connection = self.database.lock();
+
+        // The database is always within a transaction (see the documentation of `database`).
+        // A savepoint is used in order to be able to revert the changes below without reverting
+        // the rest of the transaction.
+        connection
+            .execute("SAVEPOINT set_network_addresses")
+            .map_err(InternalError)
+            .map_err(CorruptedError::Internal)?;
+
+        match replace_network_addresses(&connection, addresses) {
+            Ok(()) => {
+                connection
+                    .execute("RELEASE set_network_addresses")
+                    .map_err(InternalError)
+                    .map_err(CorruptedError::Internal)?;
+                Ok(())
+            }
+            Err(err) => {
+                connection
+                    .execute("ROLLBACK TO set_network_addresses; RELEASE set_network_addresses")
+                    .map_err(InternalError)
+                    .map_err(CorruptedError::Internal)?;
+                Err(err)
+            }
+        }
+    }
+}
+
+/// Address of a node of the peer-to-peer network, as stored in the database.
+///
+/// See [`SqliteFullDatabase::network_addresses`] and [`SqliteFullDatabase::set_network_addresses`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkAddress {
+    /// Binary representation of the identity of the node.
+    pub peer_id: Vec<u8>,
+    /// Binary representation of the multiaddress of the node.
+    pub address: Vec<u8>,
+    /// Number of consecutive failed attempts at connecting to this address. `0` if the most
+    /// recent attempt has succeeded.
+    pub consecutive_failures: u32,
 }
 
 impl fmt::Debug for SqliteFullDatabase {
@@ -864,6 +1090,19 @@ pub enum FinalizedAccessError {
     Obsolete,
 }
 
+/// Error while accessing the storage of a block.
+#[derive(Debug, derive_more::Display, derive_more::From)]
+pub enum StorageAccessError {
+    /// Error accessing the database.
+    Access(AccessError),
+    /// Block isn't in the database, or is an ancestor of the finalized block, in which case its
+    /// storage is no longer available.
+    UnknownBlock,
+    /// The version of the trie entries of the block is different from the one of the finalized
+    /// block. Proofs can only be built once the block is finalized.
+    TrieEntriesVersionMismatch,
+}
+
 /// Error in the content of the database.
 // TODO: document and see if any entry is unused
 #[derive(Debug, derive_more::Display)]
@@ -890,6 +1129,12 @@ pub enum CorruptedError {
     ConsensusAlgorithmMix,
     /// The information about a Babe epoch found in the database has failed to decode.
     InvalidBabeEpochInformation,
+    /// The network identity key is expected to be 32 bytes. This isn't the case.
+    InvalidNetworkIdentityKeyLen,
+    /// The version of the trie entries of a block is neither 0 nor 1.
+    InvalidTrieEntriesVersion,
+    /// A node of the trie of the storage of the finalized block is missing or invalid.
+    MissingTrieNode,
     Internal(InternalError),
 }
 
@@ -1080,6 +1325,154 @@ fn block_header(
     }
 }
 
+fn block_trie_entries_version(
+    database: &sqlite::Connection,
+    hash: &[u8; 32],
+) -> Result<trie::TrieEntryVersion, AccessError> {
+    let mut statement = database
+        .prepare(r#"SELECT trie_entries_version FROM blocks WHERE hash = ?"#)
+        .map_err(InternalError)
+        .map_err(CorruptedError::Internal)
+        .map_err(AccessError::Corrupted)?;
+    statement.bind(1, &hash[..]).unwrap();
+
+    if !matches!(statement.next().unwrap(), sqlite::State::Row) {
+        return Err(AccessError::Corrupted(CorruptedError::MissingBlockHeader));
+    }
+
+    let version = statement
+        .read::<i64>(0)
+        .map_err(InternalError)
+        .map_err(CorruptedError::Internal)
+        .map_err(AccessError::Corrupted)?;
+
+    u32::try_from(version)
+        .ok()
+        .and_then(trie::TrieEntryVersion::from_state_version)
+        .ok_or(AccessError::Corrupted(
+            CorruptedError::InvalidTrieEntriesVersion,
+        ))
+}
+
+fn trie_entries_version_to_number(version: trie::TrieEntryVersion) -> i64 {
+    match version {
+        trie::TrieEntryVersion::V0 => 0,
+        trie::TrieEntryVersion::V1 => 1,
+    }
+}
+
+/// Returns the changes to the storage that the given non-finalized block performs relative to
+/// its parent.
+fn block_storage_changes(
+    database: &sqlite::Connection,
+    hash: &[u8; 32],
+) -> Result<Vec<(Vec<u8>, Option<Vec<u8>>)>, AccessError> {
+    let mut statement = database
+        .prepare(r#"SELECT key, value FROM non_finalized_changes WHERE hash = ?"#)
+        .map_err(InternalError)
+        .map_err(CorruptedError::Internal)
+        .map_err(AccessError::Corrupted)?;
+    statement.bind(1, &hash[..]).unwrap();
+
+    let mut out = Vec::new();
+    while matches!(statement.next().unwrap(), sqlite::State::Row) {
+        let key = statement
+            .read::<Vec<u8>>(0)
+            .map_err(InternalError)
+            .map_err(CorruptedError::Internal)
+            .map_err(AccessError::Corrupted)?;
+        let value = statement
+            .read::<Option<Vec<u8>>>(1)
+            .map_err(InternalError)
+            .map_err(CorruptedError::Internal)
+            .map_err(AccessError::Corrupted)?;
+        out.push((key, value));
+    }
+
+    Ok(out)
+}
+
+/// Builds a view of the storage of the given block, made of the storage of the finalized block
+/// and the changes performed by the blocks between the finalized block and the given block.
+fn block_storage_view<'a>(
+    database: &'a sqlite::Connection,
+    hash: &[u8; 32],
+) -> Result<trie_nodes::StorageView<'a>, StorageAccessError> {
+    let finalized_hash = finalized_hash(database)?;
+    let finalized_num = finalized_num(database)?;
+
+    let header = block_header(database, hash)?.ok_or(StorageAccessError::UnknownBlock)?;
+    if header.number < finalized_num || (header.number == finalized_num && *hash != finalized_hash)
+    {
+        return Err(StorageAccessError::UnknownBlock);
+    }
+
+    // List of the non-finalized blocks between the finalized block and the requested block,
+    // from the requested block to the child of the finalized block.
+    let mut ancestry = Vec::new();
+    let mut current = (*hash, header);
+    while current.0 != finalized_hash {
+        if current.1.number <= finalized_num {
+            return Err(AccessError::Corrupted(CorruptedError::BrokenChain).into());
+        }
+        let parent_hash = current.1.parent_hash;
+        let parent_header = block_header(database, &parent_hash)?
+            .ok_or(AccessError::Corrupted(CorruptedError::BrokenChain))?;
+        ancestry.push(current.0);
+        current = (parent_hash, parent_header);
+    }
+
+    let mut changes = BTreeMap::new();
+    for block_hash in ancestry.iter().rev() {
+        changes.extend(block_storage_changes(database, block_hash)?);
+    }
+
+    Ok(trie_nodes::StorageView::new(
+        database,
+        changes,
+        block_trie_entries_version(database, hash)?,
+    ))
+}
+
+fn replace_network_addresses(
+    database: &sqlite::Connection,
+    addresses: impl Iterator<Item = NetworkAddress>,
+) -> Result<(), AccessError> {
+    database
+        .execute("DELETE FROM network_addresses")
+        .map_err(InternalError)
+        .map_err(CorruptedError::Internal)?;
+
+    let mut statement = database
+        .prepare("INSERT OR REPLACE INTO network_addresses(peer_id, address, consecutive_failures) VALUES (?, ?, ?)")
+        .map_err(InternalError)
+        .map_err(CorruptedError::Internal)?;
+    for address in addresses {
+        statement
+            .bind(1, &address.peer_id[..])
+            .map_err(InternalError)
+            .map_err(CorruptedError::Internal)?;
+        statement
+            .bind(2, &address.address[..])
+            .map_err(InternalError)
+            .map_err(CorruptedError::Internal)?;
+        statement
+            .bind(3, i64::from(address.consecutive_failures))
+            .map_err(InternalError)
+            .map_err(CorruptedError::Internal)?;
+        statement
+            .next()
+            .map_err(InternalError)
+            .map_err(CorruptedError::Internal)?;
+        statement
+            .reset()
+            .map_err(InternalError)
+            .map_err(CorruptedError::Internal)?;
+    }
+
+    Ok(())
+}
+
 fn flush(database: &sqlite::Connection) -> Result<(), AccessError> {
     database.execute("COMMIT; BEGIN TRANSACTION;").unwrap();
     Ok(())
@@ -19,8 +19,11 @@
 //!
 //! Contains everything related to the opening and initialization of the database.
 
-use super::{encode_babe_epoch_information, AccessError, SqliteFullDatabase};
-use crate::chain::chain_information;
+use super::{
+    encode_babe_epoch_information, trie_entries_version_to_number, trie_nodes, AccessError,
+    SqliteFullDatabase,
+};
+use crate::{chain::chain_information, trie};
 
 use std::{convert::TryFrom as _, fs, path::Path};
 
@@ -41,8 +44,8 @@ pub fn open(config: Config) -> Result<DatabaseOpen, super::InternalError> {
 
     let database = match config.ty {
         ConfigTy::Disk(path) => {
-            // We put a `/v1/` behind the path in case we change the schema.
-            let path = path.join("v1");
+            // We put a `/v2/` behind the path in case we change the schema.
+            let path = path.join("v2");
             // Ignoring errors in `create_dir_all`, in order to avoid making the API of this
             // function more complex. If `create_dir_all` fails, opening the database will most
             // likely fail too.
@@ -99,6 +102,9 @@ Keys in that table:
  finalized block is block #0, then this contains information about epoch #0. Missing if and
  only if the chain doesn't use Babe.
 
+ - `network_identity_key` (blob): 32 bytes ed25519 private key used as the identity of the
+ local node on the peer-to-peer network. Missing if no key has been stored yet.
+
 */
 CREATE TABLE IF NOT EXISTS meta(
     key STRING NOT NULL PRIMARY KEY,
@@ -116,8 +122,12 @@ CREATE TABLE IF NOT EXISTS blocks(
     number INTEGER NOT NULL,
     header BLOB NOT NULL,
     justification BLOB,
+    -- Version of the trie entries of the storage of the block, as indicated by the runtime
+    -- that has executed the block. Either 0 or 1.
+    trie_entries_version INTEGER NOT NULL,
     UNIQUE(number, hash),
-    CHECK(length(hash) == 32)
+    CHECK(length(hash) == 32),
+    CHECK(trie_entries_version IN (0, 1))
 );
 CREATE INDEX IF NOT EXISTS blocks_by_number ON blocks(number);
 
@@ -144,6 +154,19 @@ CREATE TABLE IF NOT EXISTS finalized_storage_top_trie(
     value BLOB NOT NULL
 );
 
+/*
+Merkle values of all the nodes of the trie of the storage of the finalized block, calculated
+with the trie entries version of the finalized block. Updated at the same time as
+`finalized_storage_top_trie`.
+The key of each node is stored with one nibble per byte. `partial_key_len` contains the number of
+nibbles of the key of the node that aren't part of the key of its parent plus the child index.
+*/
+CREATE TABLE IF NOT EXISTS finalized_storage_top_trie_nodes(
+    key BLOB NOT NULL PRIMARY KEY,
+    partial_key_len INTEGER NOT NULL,
+    merkle_value BLOB NOT NULL
+);
+
 /*
 For non-finalized blocks (i.e. blocks that descend from the finalized block), contains changes
 that this block performs on the storage.
@@ -191,6 +214,19 @@ CREATE TABLE IF NOT EXISTS aura_finalized_authorities(
     CHECK(length(public_key) == 32)
 );
 
+/*
+Addresses of nodes of the peer-to-peer network that have been dialed in the past. Used in order
+to be able to reconnect to the network after a restart without relying solely on the bootnodes.
+*/
+CREATE TABLE IF NOT EXISTS network_addresses(
+    peer_id BLOB NOT NULL,
+    address BLOB NOT NULL,
+    -- Number of consecutive failed attempts at connecting to this address. 0 if the most recent
+    -- attempt has succeeded.
+    consecutive_failures INTEGER NOT NULL,
+    UNIQUE(peer_id, address)
+);
+
     "#,
         )
         .map_err(super::InternalError)?;
@@ -255,13 +291,15 @@ impl DatabaseEmpty {
     /// Inserts the given [`chain_information::ChainInformationRef`] in the database prototype in
     /// order to turn it into an actual database.
     ///
-    /// Must also pass the body, justification, and state of the storage of the finalized block.
+    /// Must also pass the body, justification, and state of the storage of the finalized block,
+    /// and the version of the trie entries of this storage.
     pub fn initialize<'a>(
         self,
         chain_information: impl Into<chain_information::ChainInformationRef<'a>>,
         finalized_block_body: impl ExactSizeIterator<Item = &'a [u8]>,
         finalized_block_justification: Option<Vec<u8>>,
         finalized_block_storage_top_trie_entries: impl Iterator<Item = (&'a [u8], &'a [u8])> + Clone,
+        finalized_block_trie_entries_version: trie::TrieEntryVersion,
     ) -> Result<SqliteFullDatabase, AccessError> {
         let chain_information = chain_information.into();
 
@@ -288,11 +326,20 @@ impl DatabaseEmpty {
             }
         }
 
+        {
+            let mut view = trie_nodes::StorageView::new_all_nodes(
+                &self.database,
+                finalized_block_trie_entries_version,
+            );
+            view.update_nodes()?;
+            view.write_nodes()?;
+        }
+
         {
             let mut statement = self
                 .database
                 .prepare(
-                    "INSERT INTO blocks(hash, number, header, justification) VALUES(?, ?, ?, ?)",
+                    "INSERT INTO blocks(hash, number, header, justification, trie_entries_version) VALUES(?, ?, ?, ?, ?)",
                 )
                 .unwrap();
             statement.bind(1, &finalized_block_hash[..]).unwrap();
@@ -312,6 +359,12 @@ impl DatabaseEmpty {
             } else {
                 statement.bind(4, ()).unwrap();
             }
+            statement
+                .bind(
+                    5,
+                    trie_entries_version_to_number(finalized_block_trie_entries_version),
+                )
+                .unwrap();
             statement.next().unwrap();
         }
 
@@ -0,0 +1,432 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+#![cfg(test)]
+
+use super::{
+    open, Config, ConfigTy, DatabaseOpen, NetworkAddress, SqliteFullDatabase, StorageAccessError,
+};
+use crate::{chain::chain_information, chain_spec, header, trie};
+
+use core::{convert::TryFrom as _, iter};
+use std::collections::BTreeMap;
+
+fn empty_database() -> SqliteFullDatabase {
+    let spec = chain_spec::ChainSpec::from_json_bytes(
+        &include_bytes!("../../chain_spec/example.json")[..],
+    )
+    .unwrap();
+    let genesis_chain_information =
+        chain_information::ChainInformation::from_chain_spec(&spec).unwrap();
+
+    match open(Config {
+        ty: ConfigTy::Memory,
+    })
+    .unwrap()
+    {
+        DatabaseOpen::Empty(empty) => empty
+            .initialize(
+                &genesis_chain_information,
+                iter::empty(),
+                None,
+                spec.genesis_storage().into_genesis_items().unwrap().iter(),
+                crate::trie::TrieEntryVersion::V0,
+            )
+            .unwrap(),
+        DatabaseOpen::Open(_) => panic!(),
+    }
+}
+
+fn address(peer_id: u8, address: u8, consecutive_failures: u32) -> NetworkAddress {
+    NetworkAddress {
+        peer_id: vec![peer_id; 8],
+        address: vec![address; 4],
+        consecutive_failures,
+    }
+}
+
+#[test]
+fn network_identity_key_roundtrip() {
+    let database = empty_database();
+    assert_eq!(database.network_identity_key().unwrap(), None);
+
+    database.set_network_identity_key(&[7; 32]).unwrap();
+    assert_eq!(database.network_identity_key().unwrap(), Some([7; 32]));
+}
+
+#[test]
+fn network_addresses_roundtrip() {
+    let database = empty_database();
+    assert!(database.network_addresses().unwrap().is_empty());
+
+    let addresses = vec![address(1, 1, 0), address(1, 2, 3), address(2, 1, 1)];
+    database
+        .set_network_addresses(addresses.clone().into_iter())
+        .unwrap();
+
+    let mut stored = database.network_addresses().unwrap();
+    stored.sort_by(|a, b| (&a.peer_id, &a.address).cmp(&(&b.peer_id, &b.address)));
+    assert_eq!(stored, addresses);
+}
+
+#[test]
+fn network_addresses_replaced() {
+    let database = empty_database();
+
+    database
+        .set_network_addresses(vec![address(1, 1, 0), address(2, 2, 0)].into_iter())
+        .unwrap();
+    database
+        .set_network_addresses(iter::once(address(3, 3, 5)))
+        .unwrap();
+
+    assert_eq!(
+        database.network_addresses().unwrap(),
+        vec![address(3, 3, 5)]
+    );
+
+    database.set_network_addresses(iter::empty()).unwrap();
+    assert!(database.network_addresses().unwrap().is_empty());
+}
+
+/// Builds a database whose finalized block has the given storage.
+fn database_with_storage(
+    storage: &BTreeMap<Vec<u8>, Vec<u8>>,
+    trie_entries_version: trie::TrieEntryVersion,
+) -> SqliteFullDatabase {
+    let spec = chain_spec::ChainSpec::from_json_bytes(
+        &include_bytes!("../../chain_spec/example.json")[..],
+    )
+    .unwrap();
+    let genesis_chain_information =
+        chain_information::ChainInformation::from_chain_spec(&spec).unwrap();
+
+    match open(Config {
+        ty: ConfigTy::Memory,
+    })
+    .unwrap()
+    {
+        DatabaseOpen::Empty(empty) => empty
+            .initialize(
+                &genesis_chain_information,
+                iter::empty(),
+                None,
+                storage.iter().map(|(k, v)| (&k[..], &v[..])),
+                trie_entries_version,
+            )
+            .unwrap(),
+        DatabaseOpen::Open(_) => panic!(),
+    }
+}
+
+/// Storage containing keys that share prefixes of various lengths, and values of various
+/// lengths.
+fn test_storage() -> BTreeMap<Vec<u8>, Vec<u8>> {
+    let mut storage = BTreeMap::new();
+    for n in 0..200u32 {
+        let seed = n.wrapping_mul(2654435761);
+        let mut key = vec![0xab, u8::try_from(seed % 7).unwrap()];
+        key.extend((0..(seed % 5)).map(|i| u8::try_from((seed >> i) & 0xff).unwrap()));
+        let value = vec![u8::try_from(n % 256).unwrap(); usize::try_from(seed % 50).unwrap()];
+        storage.insert(key, value);
+    }
+    storage.insert(b":code".to_vec(), vec![1; 64]);
+    storage.insert(b":heappages".to_vec(), vec![8, 0, 0, 0, 0, 0, 0, 0]);
+    storage
+}
+
+/// Inserts a non-finalized block with the given parent and storage changes, and returns its
+/// hash.
+fn insert_block(
+    database: &SqliteFullDatabase,
+    parent_hash: [u8; 32],
+    number: u64,
+    changes: &BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    trie_entries_version: trie::TrieEntryVersion,
+) -> [u8; 32] {
+    let header = header::Header {
+        parent_hash,
+        number,
+        state_root: [0; 32],
+        extrinsics_root: trie::empty_trie_merkle_value(),
+        digest: header::DigestRef::empty().into(),
+    };
+    database
+        .insert(
+            &header.scale_encoding_vec(),
+            true,
+            iter::empty::<Vec<u8>>(),
+            changes.iter().map(|(k, v)| (k, v.as_ref())),
+            trie_entries_version,
+        )
+        .unwrap();
+    header.hash()
+}
+
+/// Checks that the proofs built by the database for the given block match the ones built from
+/// the entire storage.
+fn check_proofs(
+    database: &SqliteFullDatabase,
+    block_hash: &[u8; 32],
+    storage: &BTreeMap<Vec<u8>, Vec<u8>>,
+    trie_entries_version: trie::TrieEntryVersion,
+    extra_keys: &[&[u8]],
+) {
+    let mut builder = trie::proof_encode::ProofBuilder::new(
+        trie_entries_version,
+        storage.iter().map(|(k, v)| (k, v.clone())),
+    );
+    let trie_root_hash = builder.root_merkle_value();
+
+    let keys = storage
+        .keys()
+        .step_by(7)
+        .map(|k| &k[..])
+        .chain(extra_keys.iter().copied())
+        .collect::<Vec<_>>();
+
+    for key in keys {
+        let proof = database
+            .block_storage_top_trie_proof(block_hash, iter::once(key))
+            .unwrap();
+        assert_eq!(proof, builder.build_proof(iter::once(key)));
+
+        let value = trie::proof_verify::verify_proof(trie::proof_verify::VerifyProofConfig {
+            requested_key: key,
+            trie_root_hash: &trie_root_hash,
+            proof: proof.iter().map(|v| &v[..]),
+        })
+        .unwrap();
+        assert_eq!(value, storage.get(key).map(|v| &v[..]));
+        assert_eq!(
+            database
+                .block_storage_top_trie_get(block_hash, key)
+                .unwrap()
+                .as_deref(),
+            storage.get(key).map(|v| &v[..])
+        );
+    }
+}
+
+/// Applies the given changes to `storage`.
+fn apply_changes(
+    storage: &mut BTreeMap<Vec<u8>, Vec<u8>>,
+    changes: &BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+) {
+    for (key, value) in changes {
+        match value {
+            Some(value) => {
+                storage.insert(key.clone(), value.clone());
+            }
+            None => {
+                storage.remove(key);
+            }
+        }
+    }
+}
+
+#[test]
+fn finalized_storage_proofs() {
+    for &version in &[trie::TrieEntryVersion::V0, trie::TrieEntryVersion::V1] {
+        let storage = test_storage();
+        let database = database_with_storage(&storage, version);
+        let finalized_hash = database.finalized_block_hash().unwrap();
+        check_proofs(
+            &database,
+            &finalized_hash,
+            &storage,
+            version,
+            &[b"", b"\xab", b"\xab\x03\x00", b"missing"],
+        );
+    }
+}
+
+#[test]
+fn genesis_storage_proof_matches_state_root() {
+    let spec = chain_spec::ChainSpec::from_json_bytes(
+        &include_bytes!("../../chain_spec/example.json")[..],
+    )
+    .unwrap();
+    let state_root = crate::calculate_genesis_block_header(&spec).state_root;
+
+    let database = empty_database();
+    let finalized_hash = database.finalized_block_hash().unwrap();
+    let proof = database
+        .block_storage_top_trie_proof(&finalized_hash, iter::once(b":heappages"))
+        .unwrap();
+
+    let value = trie::proof_verify::verify_proof(trie::proof_verify::VerifyProofConfig {
+        requested_key: b":heappages",
+        trie_root_hash: &state_root,
+        proof: proof.iter().map(|v| &v[..]),
+    })
+    .unwrap();
+    assert_eq!(
+        value,
+        spec.genesis_storage()
+            .into_genesis_items()
+            .unwrap()
+            .value(b":heappages")
+    );
+}
+
+#[test]
+fn empty_storage_proof() {
+    let database = database_with_storage(&BTreeMap::new(), trie::TrieEntryVersion::V0);
+    let finalized_hash = database.finalized_block_hash().unwrap();
+    assert_eq!(
+        database
+            .block_storage_top_trie_proof(&finalized_hash, iter::once(b"foo"))
+            .unwrap(),
+        vec![vec![0]]
+    );
+}
+
+#[test]
+fn non_finalized_storage_proofs() {
+    let version = trie::TrieEntryVersion::V0;
+    let mut storage = test_storage();
+    let database = database_with_storage(&storage, version);
+    let finalized_hash = database.finalized_block_hash().unwrap();
+
+    // Block 1 modifies, adds and removes values, including keys that create new branch nodes.
+    let removed_key = storage.keys().nth(10).unwrap().clone();
+    let changes1 = [
+        (b":code".to_vec(), Some(vec![2; 40])),
+        (b"\xab\x00\xff\xff".to_vec(), Some(vec![3; 3])),
+        (b"\xab".to_vec(), Some(vec![4; 100])),
+        (removed_key.clone(), None),
+    ]
+    .iter()
+    .cloned()
+    .collect::<BTreeMap<_, _>>();
+    let block1 = insert_block(&database, finalized_hash, 1, &changes1, version);
+
+    // Block 2 removes a value added by its parent and removes entire groups of keys.
+    let changes2 = storage
+        .keys()
+        .filter(|k| k.starts_with(b"\xab\x02"))
+        .map(|k| (k.clone(), None))
+        .chain(iter::once((b"\xab".to_vec(), None)))
+        .collect::<BTreeMap<_, _>>();
+    let block2 = insert_block(&database, block1, 2, &changes2, version);
+
+    let storage_before = storage.clone();
+    apply_changes(&mut storage, &changes1);
+    let storage1 = storage.clone();
+    apply_changes(&mut storage, &changes2);
+    let storage2 = storage.clone();
+
+    let extra_keys: &[&[u8]] = &[&removed_key, b"\xab", b"\xab\x00\xff\xff", b"\xab\x02"];
+    check_proofs(
+        &database,
+        &finalized_hash,
+        &storage_before,
+        version,
+        extra_keys,
+    );
+    check_proofs(&database, &block1, &storage1, version, extra_keys);
+    check_proofs(&database, &block2, &storage2, version, extra_keys);
+
+    // Once finalized, the Merkle values of the nodes stored in the database are updated.
+    database.set_finalized(&block2).unwrap();
+    check_proofs(&database, &block2, &storage2, version, extra_keys);
+    assert!(matches!(
+        database.block_storage_top_trie_proof(&block1, iter::once(b"foo")),
+        Err(StorageAccessError::UnknownBlock)
+    ));
+}
+
+#[test]
+fn trie_entries_version_change_on_finalization() {
+    let mut storage = test_storage();
+    let database = database_with_storage(&storage, trie::TrieEntryVersion::V0);
+    let finalized_hash = database.finalized_block_hash().unwrap();
+
+    let changes = iter::once((b":code".to_vec(), Some(vec![5; 70]))).collect::<BTreeMap<_, _>>();
+    let block1 = insert_block(
+        &database,
+        finalized_hash,
+        1,
+        &changes,
+        trie::TrieEntryVersion::V1,
+    );
+    apply_changes(&mut storage, &changes);
+
+    assert!(matches!(
+        database.block_storage_top_trie_proof(&block1, iter::once(b"foo")),
+        Err(StorageAccessError::TrieEntriesVersionMismatch)
+    ));
+    assert_eq!(
+        database
+            .block_storage_top_trie_get(&block1, b":code")
+            .unwrap(),
+        Some(vec![5; 70])
+    );
+
+    database.set_finalized(&block1).unwrap();
+    check_proofs(
+        &database,
+        &block1,
+        &storage,
+        trie::TrieEntryVersion::V1,
+        &[b":code"],
+    );
+}
+
+#[test]
+fn non_finalized_next_key() {
+    let storage = test_storage();
+    let database = database_with_storage(&storage, trie::TrieEntryVersion::V0);
+    let finalized_hash = database.finalized_block_hash().unwrap();
+
+    let first_key = storage.keys().next().unwrap().clone();
+    let second_key = storage.keys().nth(1).unwrap().clone();
+    let changes = [
+        (second_key.clone(), None),
+        (b"\x00".to_vec(), Some(vec![1])),
+    ]
+    .iter()
+    .cloned()
+    .collect::<BTreeMap<_, _>>();
+    let block1 = insert_block(
+        &database,
+        finalized_hash,
+        1,
+        &changes,
+        trie::TrieEntryVersion::V0,
+    );
+
+    assert_eq!(
+        database
+            .block_storage_top_trie_next_key(&block1, b"")
+            .unwrap(),
+        Some(b"\x00".to_vec())
+    );
+    assert_eq!(
+        database
+            .block_storage_top_trie_next_key(&block1, &first_key)
+            .unwrap(),
+        storage.keys().nth(2).cloned()
+    );
+    assert_eq!(
+        database
+            .block_storage_top_trie_next_key(&finalized_hash, &first_key)
+            .unwrap(),
+        Some(second_key)
+    );
+}
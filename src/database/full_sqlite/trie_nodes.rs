@@ -0,0 +1,653 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Access to the storage of a block and to the nodes of its trie.
+//!
+//! The database stores the storage of the finalized block as a list of keys and values, and the
+//! storage of each non-finalized block as a list of changes relative to its parent. In order to
+//! be able to build proofs without loading the entire storage in memory, the Merkle value of
+//! each node of the trie of the storage of the finalized block is stored as well, in the
+//! `finalized_storage_top_trie_nodes` table, and is updated whenever blocks are finalized.
+//!
+//! The nodes of the trie of a non-finalized block are the same as the ones of the finalized
+//! block, except for the nodes affected by the changes between the finalized block and this
+//! block. The Merkle values of these nodes are calculated again in memory.
+//!
+//! The keys of the nodes are stored with one nibble per byte.
+
+use super::{AccessError, CorruptedError, InternalError};
+use crate::trie::{self, node_value, TrieEntryVersion};
+
+use core::{convert::TryFrom as _, ops::Bound};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Storage of a block, made of the storage of the finalized block and a list of changes.
+pub(super) struct StorageView<'a> {
+    /// See [`super::SqliteFullDatabase::database`].
+    connection: &'a sqlite::Connection,
+
+    /// Changes to apply on top of `finalized_storage_top_trie`. Values are `None` if the key
+    /// is removed.
+    changes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+
+    /// Keys of [`StorageView::changes`], as nibbles. Nodes whose key is a prefix of one of these
+    /// keys are affected by the changes. `None` if all the nodes must be considered affected.
+    changed_keys: Option<BTreeSet<Vec<u8>>>,
+
+    /// Version of the trie entries of the storage.
+    version: TrieEntryVersion,
+
+    /// Nodes whose Merkle value has been calculated by [`StorageView::update_nodes`]. Override
+    /// the content of `finalized_storage_top_trie_nodes`. `None` if the node no longer exists.
+    nodes: BTreeMap<Vec<u8>, Option<Node>>,
+}
+
+#[derive(Clone)]
+struct Node {
+    /// Length, in nibbles, of the partial key of the node. The Merkle value of a node found in
+    /// `finalized_storage_top_trie_nodes` can only be reused if its partial key is unchanged.
+    partial_key_len: usize,
+    merkle_value: Vec<u8>,
+}
+
+impl<'a> StorageView<'a> {
+    /// Builds a view of the storage made of the storage of the finalized block and the given
+    /// changes.
+    ///
+    /// [`StorageView::update_nodes`] must be called before building proofs, unless `changes`
+    /// is empty.
+    pub(super) fn new(
+        connection: &'a sqlite::Connection,
+        changes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+        version: TrieEntryVersion,
+    ) -> Self {
+        let changed_keys = changes
+            .keys()
+            .map(|key| key_nibbles(key))
+            .collect::<BTreeSet<_>>();
+
+        StorageView {
+            connection,
+            changes,
+            changed_keys: Some(changed_keys),
+            version,
+            nodes: BTreeMap::new(),
+        }
+    }
+
+    /// Builds a view of the storage of the finalized block where the Merkle values found in
+    /// `finalized_storage_top_trie_nodes` are ignored.
+    ///
+    /// Calling [`StorageView::update_nodes`] then calculates the Merkle values of all the nodes.
+    pub(super) fn new_all_nodes(
+        connection: &'a sqlite::Connection,
+        version: TrieEntryVersion,
+    ) -> Self {
+        StorageView {
+            connection,
+            changes: BTreeMap::new(),
+            changed_keys: None,
+            version,
+            nodes: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the storage value associated with the given key.
+    pub(super) fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, AccessError> {
+        if let Some(value) = self.changes.get(key) {
+            return Ok(value.clone());
+        }
+
+        let mut statement = self
+            .connection
+            .prepare(r#"SELECT value FROM finalized_storage_top_trie WHERE key = ?"#)
+            .map_err(InternalError)
+            .map_err(CorruptedError::Internal)
+            .map_err(AccessError::Corrupted)?;
+        statement.bind(1, key).unwrap();
+
+        if !matches!(statement.next().unwrap(), sqlite::State::Row) {
+            return Ok(None);
+        }
+
+        let value = statement
+            .read::<Vec<u8>>(0)
+            .map_err(InternalError)
+            .map_err(CorruptedError::Internal)
+            .map_err(AccessError::Corrupted)?;
+        Ok(Some(value))
+    }
+
+    /// Returns the key of the storage that immediately follows the given key.
+    pub(super) fn next_key(&self, key: &[u8]) -> Result<Option<Vec<u8>>, AccessError> {
+        self.key_in_range(Bound::Excluded(key.to_vec()), Bound::Unbounded, false)
+    }
+
+    /// Calculates the Merkle values of the nodes affected by the changes.
+    pub(super) fn update_nodes(&mut self) -> Result<(), AccessError> {
+        if let Some(root_key) = self.root_node_key()? {
+            self.calculate_node(&root_key, None)?;
+        }
+
+        // Nodes that are affected by the changes but haven't been calculated again no longer
+        // exist. Since their key is necessarily the prefix of a changed key, finding them
+        // consists in following the path towards each changed key in the stored trie.
+        let mut removed_nodes = Vec::new();
+        for changed_key in self.changed_keys.iter().flatten() {
+            let mut lower_bound = Vec::new();
+            loop {
+                let node_key = match stored_node_key_after(self.connection, &lower_bound)? {
+                    Some(k) if k.starts_with(&lower_bound) && changed_key.starts_with(&k) => k,
+                    _ => break,
+                };
+
+                if !matches!(self.nodes.get(&node_key), Some(Some(_))) {
+                    removed_nodes.push(node_key.clone());
+                }
+
+                if node_key.len() == changed_key.len() {
+                    break;
+                }
+                lower_bound = node_key;
+                lower_bound.push(changed_key[lower_bound.len()]);
+            }
+        }
+
+        for node_key in removed_nodes {
+            self.nodes.insert(node_key, None);
+        }
+
+        Ok(())
+    }
+
+    /// Writes in `finalized_storage_top_trie_nodes` the Merkle values calculated by
+    /// [`StorageView::update_nodes`].
+    pub(super) fn write_nodes(&self) -> Result<(), AccessError> {
+        let mut insert_statement = self
+            .connection
+            .prepare("INSERT OR REPLACE INTO finalized_storage_top_trie_nodes(key, partial_key_len, merkle_value) VALUES(?, ?, ?)")
+            .map_err(InternalError)
+            .map_err(CorruptedError::Internal)
+            .map_err(AccessError::Corrupted)?;
+        let mut remove_statement = self
+            .connection
+            .prepare("DELETE FROM finalized_storage_top_trie_nodes WHERE key = ?")
+            .map_err(InternalError)
+            .map_err(CorruptedError::Internal)
+            .map_err(AccessError::Corrupted)?;
+
+        for (key, node) in &self.nodes {
+            if let Some(node) = node {
+                insert_statement.bind(1, &key[..]).unwrap();
+                insert_statement
+                    .bind(2, i64::try_from(node.partial_key_len).unwrap())
+                    .unwrap();
+                insert_statement.bind(3, &node.merkle_value[..]).unwrap();
+                insert_statement.next().unwrap();
+                insert_statement.reset().unwrap();
+            } else {
+                remove_statement.bind(1, &key[..]).unwrap();
+                remove_statement.next().unwrap();
+                remove_statement.reset().unwrap();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Builds a proof containing all the node values necessary to find the storage value of the
+    /// given keys, or to prove their absence.
+    ///
+    /// Only the nodes between the root and the given keys, and their direct children, are
+    /// accessed.
+    pub(super) fn build_proof(
+        &self,
+        keys: impl Iterator<Item = impl AsRef<[u8]>>,
+    ) -> Result<Vec<Vec<u8>>, AccessError> {
+        let root_key = match self.root_node_key()? {
+            Some(k) => k,
+            // The proof of absence of a key in an empty trie consists in the node value of the
+            // root node of an empty trie.
+            None => return Ok(Vec::from([Vec::from([0u8])])),
+        };
+
+        let mut proof = BTreeSet::new();
+
+        for key in keys {
+            let key = key_nibbles(key.as_ref());
+            let mut node_key = root_key.clone();
+            let mut parent_key_len = None;
+
+            loop {
+                let (node_value, children_keys) = self.node_value(&node_key, parent_key_len)?;
+                // Node values that are smaller than 32 bytes are directly included within the
+                // node value of their parent, and don't need to be part of the proof.
+                if parent_key_len.is_none() || node_value.len() >= 32 {
+                    proof.insert(node_value);
+                }
+
+                if !key.starts_with(&node_key) {
+                    break;
+                }
+
+                if key.len() == node_key.len() {
+                    // The storage value of the requested key, if hashed within the node value,
+                    // must be provided alongside the node value.
+                    if self.version == TrieEntryVersion::V1 {
+                        if let Some(value) = self.get(&key_bytes(&node_key))? {
+                            if value.len() >= 33 {
+                                proof.insert(value);
+                            }
+                        }
+                    }
+                    break;
+                }
+
+                match &children_keys[usize::from(key[node_key.len()])] {
+                    Some(child_key) => {
+                        parent_key_len = Some(node_key.len());
+                        node_key = child_key.clone();
+                    }
+                    None => break,
+                }
+            }
+        }
+
+        Ok(proof.into_iter().collect())
+    }
+
+    /// Calculates the Merkle value of the given node, and stores it in [`StorageView::nodes`].
+    ///
+    /// The Merkle value of nodes that aren't affected by the changes and whose partial key is
+    /// unchanged is taken from `finalized_storage_top_trie_nodes` instead.
+    fn calculate_node(
+        &mut self,
+        key: &[u8],
+        parent_key_len: Option<usize>,
+    ) -> Result<Vec<u8>, AccessError> {
+        let partial_key_len = match parent_key_len {
+            Some(parent_key_len) => key.len() - parent_key_len - 1,
+            None => key.len(),
+        };
+
+        if !self.is_affected(key) {
+            if let Some(node) = stored_node(self.connection, key)? {
+                if node.partial_key_len == partial_key_len {
+                    return Ok(node.merkle_value);
+                }
+            }
+        }
+
+        let mut children = Vec::with_capacity(16);
+        for nibble in 0..16 {
+            children.push(match self.child_node_key(key, nibble)? {
+                Some(child_key) => Some(node_value::Output::from_bytes(
+                    &self.calculate_node(&child_key, Some(key.len()))?,
+                )),
+                None => None,
+            });
+        }
+
+        let node_value = self.encode_node_value(key, partial_key_len, &children)?;
+        let merkle_value = if parent_key_len.is_none() || node_value.len() >= 32 {
+            blake2_rfc::blake2b::blake2b(32, &[], &node_value)
+                .as_bytes()
+                .to_vec()
+        } else {
+            node_value
+        };
+
+        self.nodes.insert(
+            key.to_vec(),
+            Some(Node {
+                partial_key_len,
+                merkle_value: merkle_value.clone(),
+            }),
+        );
+
+        Ok(merkle_value)
+    }
+
+    /// Returns the node value of the given node, and the keys of its children.
+    ///
+    /// The Merkle values of the children must be up to date.
+    fn node_value(
+        &self,
+        key: &[u8],
+        parent_key_len: Option<usize>,
+    ) -> Result<(Vec<u8>, Vec<Option<Vec<u8>>>), AccessError> {
+        let mut children_keys = Vec::with_capacity(16);
+        let mut children = Vec::with_capacity(16);
+        for nibble in 0..16 {
+            let child_key = self.child_node_key(key, nibble)?;
+            children.push(match &child_key {
+                Some(child_key) => {
+                    let node = match self.nodes.get(child_key) {
+                        Some(node) => node.clone(),
+                        None => stored_node(self.connection, child_key)?,
+                    }
+                    .ok_or(AccessError::Corrupted(CorruptedError::MissingTrieNode))?;
+                    Some(node_value::Output::from_bytes(&node.merkle_value))
+                }
+                None => None,
+            });
+            children_keys.push(child_key);
+        }
+
+        let partial_key_len = match parent_key_len {
+            Some(parent_key_len) => key.len() - parent_key_len - 1,
+            None => key.len(),
+        };
+
+        let node_value = self.encode_node_value(key, partial_key_len, &children)?;
+        Ok((node_value, children_keys))
+    }
+
+    /// Encodes the node value of the given node from the Merkle values of its children.
+    fn encode_node_value(
+        &self,
+        key: &[u8],
+        partial_key_len: usize,
+        children: &[Option<node_value::Output>],
+    ) -> Result<Vec<u8>, AccessError> {
+        // Only keys made of an even number of nibbles can have a storage value.
+        let storage_value = if key.len() % 2 == 0 {
+            self.get(&key_bytes(key))?
+        } else {
+            None
+        };
+
+        let partial_key = key[key.len() - partial_key_len..]
+            .iter()
+            .map(|n| trie::Nibble::try_from(*n).unwrap());
+
+        Ok(node_value::calculate_node_value(node_value::Config {
+            ty: node_value::NodeTy::NonRoot { partial_key },
+            children: children.iter().map(|c| c.as_ref()),
+            stored_value: storage_value,
+            version: self.version,
+        }))
+    }
+
+    /// Returns `true` if the given node is affected by the changes.
+    fn is_affected(&self, key: &[u8]) -> bool {
+        match &self.changed_keys {
+            Some(changed_keys) => changed_keys
+                .range::<[u8], _>((Bound::Included(key), Bound::Unbounded))
+                .next()
+                .map_or(false, |changed_key| changed_key.starts_with(key)),
+            None => true,
+        }
+    }
+
+    /// Returns the key of the root node of the trie, or `None` if the trie is empty.
+    fn root_node_key(&self) -> Result<Option<Vec<u8>>, AccessError> {
+        self.common_prefix_node_key(&[])
+    }
+
+    /// Returns the key of the child of the given node at the given index, if any.
+    fn child_node_key(&self, key: &[u8], nibble: u8) -> Result<Option<Vec<u8>>, AccessError> {
+        let mut prefix = Vec::with_capacity(key.len() + 1);
+        prefix.extend_from_slice(key);
+        prefix.push(nibble);
+        self.common_prefix_node_key(&prefix)
+    }
+
+    /// Returns the longest prefix shared by all the keys of the storage that start with the
+    /// given nibbles. This is the key of the highest node whose key starts with these nibbles.
+    fn common_prefix_node_key(&self, prefix: &[u8]) -> Result<Option<Vec<u8>>, AccessError> {
+        let (start, end) = nibbles_range(prefix);
+
+        let first = match self.key_in_range(start.clone(), end.clone(), false)? {
+            Some(k) => key_nibbles(&k),
+            None => return Ok(None),
+        };
+        let last = match self.key_in_range(start, end, true)? {
+            Some(k) => key_nibbles(&k),
+            None => return Ok(None),
+        };
+
+        // Since keys are ordered, the prefix shared by the first and last key is shared by all
+        // the keys in between.
+        let common_len = first
+            .iter()
+            .zip(last.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        Ok(Some(first[..common_len].to_vec()))
+    }
+
+    /// Returns the first (or last if `reverse` is `true`) key of the storage within the given
+    /// range.
+    fn key_in_range(
+        &self,
+        start: Bound<Vec<u8>>,
+        end: Bound<Vec<u8>>,
+        reverse: bool,
+    ) -> Result<Option<Vec<u8>>, AccessError> {
+        let in_changes = {
+            let mut iter = self
+                .changes
+                .range((start.clone(), end.clone()))
+                .filter(|(_, value)| value.is_some())
+                .map(|(key, _)| key);
+            if reverse {
+                iter.next_back()
+            } else {
+                iter.next()
+            }
+        };
+
+        // Keys removed by the changes must be skipped.
+        let (mut start, mut end) = (start, end);
+        let in_table = loop {
+            let key = match stored_key_in_range(self.connection, &start, &end, reverse)? {
+                Some(k) => k,
+                None => break None,
+            };
+
+            if matches!(self.changes.get(&key), Some(None)) {
+                if reverse {
+                    end = Bound::Excluded(key);
+                } else {
+                    start = Bound::Excluded(key);
+                }
+                continue;
+            }
+
+            break Some(key);
+        };
+
+        Ok(match (in_changes, in_table) {
+            (Some(a), Some(b)) if reverse => Some(if *a > b { a.clone() } else { b }),
+            (Some(a), Some(b)) => Some(if *a < b { a.clone() } else { b }),
+            (Some(a), None) => Some(a.clone()),
+            (None, b) => b,
+        })
+    }
+}
+
+/// Returns the first (or last if `reverse` is `true`) key of `finalized_storage_top_trie`
+/// within the given range.
+fn stored_key_in_range(
+    connection: &sqlite::Connection,
+    start: &Bound<Vec<u8>>,
+    end: &Bound<Vec<u8>>,
+    reverse: bool,
+) -> Result<Option<Vec<u8>>, AccessError> {
+    let mut query = String::from("SELECT key FROM finalized_storage_top_trie WHERE 1");
+    let mut bound_keys = Vec::with_capacity(2);
+    match start {
+        Bound::Included(key) => {
+            query.push_str(" AND key >= ?");
+            bound_keys.push(key);
+        }
+        Bound::Excluded(key) => {
+            query.push_str(" AND key > ?");
+            bound_keys.push(key);
+        }
+        Bound::Unbounded => {}
+    }
+    match end {
+        Bound::Included(key) => {
+            query.push_str(" AND key <= ?");
+            bound_keys.push(key);
+        }
+        Bound::Excluded(key) => {
+            query.push_str(" AND key < ?");
+            bound_keys.push(key);
+        }
+        Bound::Unbounded => {}
+    }
+    query.push_str(if reverse {
+        " ORDER BY key DESC LIMIT 1"
+    } else {
+        " ORDER BY key ASC LIMIT 1"
+    });
+
+    let mut statement = connection
+        .prepare(&query)
+        .map_err(InternalError)
+        .map_err(CorruptedError::Internal)
+        .map_err(AccessError::Corrupted)?;
+    for (index, key) in bound_keys.into_iter().enumerate() {
+        statement.bind(index + 1, &key[..]).unwrap();
+    }
+
+    if !matches!(statement.next().unwrap(), sqlite::State::Row) {
+        return Ok(None);
+    }
+
+    let key = statement
+        .read::<Vec<u8>>(0)
+        .map_err(InternalError)
+        .map_err(CorruptedError::Internal)
+        .map_err(AccessError::Corrupted)?;
+    Ok(Some(key))
+}
+
+/// Returns the node of `finalized_storage_top_trie_nodes` with the given key, if any.
+fn stored_node(connection: &sqlite::Connection, key: &[u8]) -> Result<Option<Node>, AccessError> {
+    let mut statement = connection
+        .prepare(r#"SELECT partial_key_len, merkle_value FROM finalized_storage_top_trie_nodes WHERE key = ?"#)
+        .map_err(InternalError)
+        .map_err(CorruptedError::Internal)
+        .map_err(AccessError::Corrupted)?;
+    statement.bind(1, key).unwrap();
+
+    if !matches!(statement.next().unwrap(), sqlite::State::Row) {
+        return Ok(None);
+    }
+
+    let partial_key_len = statement
+        .read::<i64>(0)
+        .map_err(InternalError)
+        .map_err(CorruptedError::Internal)
+        .map_err(AccessError::Corrupted)?;
+    let merkle_value = statement
+        .read::<Vec<u8>>(1)
+        .map_err(InternalError)
+        .map_err(CorruptedError::Internal)
+        .map_err(AccessError::Corrupted)?;
+
+    Ok(Some(Node {
+        partial_key_len: usize::try_from(partial_key_len)
+            .map_err(|_| AccessError::Corrupted(CorruptedError::MissingTrieNode))?,
+        merkle_value,
+    }))
+}
+
+/// Returns the lowest key of `finalized_storage_top_trie_nodes` that is superior or equal to
+/// the given one.
+///
+/// Since the key of a node is a prefix of the keys of all its descendants, the lowest key that
+/// starts with a certain prefix is the key of the highest node whose key starts with this
+/// prefix.
+fn stored_node_key_after(
+    connection: &sqlite::Connection,
+    key: &[u8],
+) -> Result<Option<Vec<u8>>, AccessError> {
+    let mut statement = connection
+        .prepare(r#"SELECT key FROM finalized_storage_top_trie_nodes WHERE key >= ? ORDER BY key ASC LIMIT 1"#)
+        .map_err(InternalError)
+        .map_err(CorruptedError::Internal)
+        .map_err(AccessError::Corrupted)?;
+    statement.bind(1, key).unwrap();
+
+    if !matches!(statement.next().unwrap(), sqlite::State::Row) {
+        return Ok(None);
+    }
+
+    let key = statement
+        .read::<Vec<u8>>(0)
+        .map_err(InternalError)
+        .map_err(CorruptedError::Internal)
+        .map_err(AccessError::Corrupted)?;
+    Ok(Some(key))
+}
+
+/// Turns a storage key into nibbles, with one nibble per byte.
+fn key_nibbles(key: &[u8]) -> Vec<u8> {
+    trie::bytes_to_nibbles(key.iter().copied())
+        .map(u8::from)
+        .collect()
+}
+
+/// Turns nibbles, with one nibble per byte, into a storage key.
+///
+/// Must only be called with an even number of nibbles.
+fn key_bytes(nibbles: &[u8]) -> Vec<u8> {
+    debug_assert_eq!(nibbles.len() % 2, 0);
+    nibbles
+        .chunks(2)
+        .map(|pair| (pair[0] << 4) | pair[1])
+        .collect()
+}
+
+/// Returns the range of storage keys that start with the given nibbles.
+fn nibbles_range(prefix: &[u8]) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+    // If the number of nibbles is odd, a `0` nibble is added at the end, which gives the lowest
+    // key that starts with the given nibbles.
+    let start = trie::nibbles_to_bytes_extend(
+        prefix
+            .iter()
+            .map(|nibble| trie::Nibble::try_from(*nibble).unwrap()),
+    )
+    .collect::<Vec<_>>();
+
+    // The end of the range is obtained by incrementing the nibbles. If all nibbles are `0xf`,
+    // the range is unbounded.
+    let mut end = prefix.to_vec();
+    loop {
+        match end.pop() {
+            Some(0xf) => {}
+            Some(nibble) => {
+                end.push(nibble + 1);
+                break;
+            }
+            None => return (Bound::Included(start), Bound::Unbounded),
+        }
+    }
+
+    let end = trie::nibbles_to_bytes_extend(
+        end.iter()
+            .map(|nibble| trie::Nibble::try_from(*nibble).unwrap()),
+    )
+    .collect::<Vec<_>>();
+
+    (Bound::Included(start), Bound::Excluded(end))
+}
@@ -179,6 +179,13 @@ pub struct CoreVersionRef<'a> {
     ///
     /// Older versions of Substrate didn't provide this field. `None` if the field is missing.
     pub transaction_version: Option<u32>,
+
+    /// Version of the trie format used by the storage of blocks using this runtime. See
+    /// [`crate::trie::TrieEntryVersion::from_state_version`].
+    ///
+    /// Older versions of Substrate didn't provide this field. `None` if the field is missing,
+    /// in which case version 0 must be assumed.
+    pub state_version: Option<u8>,
 }
 
 /// Iterator to a list of APIs. See [`CoreVersionRef::apis`].
@@ -267,8 +274,19 @@ fn decode(scale_encoded: &[u8]) -> Result<CoreVersionRef, ()> {
                 |inner| CoreVersionApisRefIter { inner },
             ),
             nom::branch::alt((
-                nom::combinator::map(nom::number::complete::le_u32, Some),
-                nom::combinator::map(nom::combinator::eof, |_| None),
+                nom::combinator::map(
+                    nom::sequence::tuple((
+                        nom::number::complete::le_u32,
+                        nom::branch::alt((
+                            nom::combinator::map(nom::number::complete::u8, Some),
+                            nom::combinator::map(nom::combinator::eof, |_| None),
+                        )),
+                    )),
+                    |(transaction_version, state_version)| {
+                        (Some(transaction_version), state_version)
+                    },
+                ),
+                nom::combinator::map(nom::combinator::eof, |_| (None, None)),
             )),
         )),
         |(
@@ -278,7 +296,7 @@ fn decode(scale_encoded: &[u8]) -> Result<CoreVersionRef, ()> {
             spec_version,
             impl_version,
             apis,
-            transaction_version,
+            (transaction_version, state_version),
         )| CoreVersionRef {
             spec_name,
             impl_name,
@@ -287,6 +305,7 @@ fn decode(scale_encoded: &[u8]) -> Result<CoreVersionRef, ()> {
             impl_version,
             apis,
             transaction_version,
+            state_version,
         },
     ))(scale_encoded);
 
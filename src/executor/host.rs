@@ -33,6 +33,18 @@
 //! >           could theoretically be handled directly by this module, it might be useful for
 //! >           testing purposes to have the possibility to return a deterministic value.
 //!
+//! The `ext_sandbox_*` functions can only instantiate modules that don't import anything other
+//! than a memory named `memory` of the `env` module, as calling back the runtime from within a
+//! sandboxed module isn't supported. A memory imported by a sandboxed module is moved to it and
+//! can't grow, and reading or writing this memory through the `ext_sandbox_memory_*` functions
+//! accesses the memory of the sandboxed module. Calls to sandboxed modules are interrupted after
+//! a fixed number of instructions, and the memories of the sandbox count towards
+//! [`Config::max_memory_size`].
+//!
+//! Offchain HTTP requests aren't supported. The corresponding host functions are handled by this
+//! module as if no request could ever be started, which runtimes are expected to handle
+//! gracefully.
+//!
 //! Contrary to most programs, runtime code doesn't have a singe `main` or `start` function.
 //! Instead, it exposes several entry points. Which one to call indicates which action it has to
 //! perform. Not all entry points are necessarily available on all runtimes.
@@ -133,17 +145,20 @@
 //! ## Example
 //!
 //! ```
-//! use smoldot::executor::host::{HeapPages, HostVm, HostVmPrototype};
+//! use smoldot::executor::host::{Config, HeapPages, HostVm, HostVmPrototype};
 //!
 //! # let wasm_binary_code: &[u8] = return;
 //!
 //! // Start executing a function on the runtime.
 //! let mut vm: HostVm = {
-//!     let prototype = HostVmPrototype::new(
-//!         &wasm_binary_code,
-//!         HeapPages::from(2048),
-//!         smoldot::executor::vm::ExecHint::Oneshot
-//!     ).unwrap();
+//!     let prototype = HostVmPrototype::new(Config {
+//!         module: &wasm_binary_code,
+//!         heap_pages: HeapPages::from(2048),
+//!         exec_hint: smoldot::executor::vm::ExecHint::Oneshot,
+//!         allow_unresolved_imports: false,
+//!         max_memory_size: None,
+//!         metered: false,
+//!     }).unwrap();
 //!     prototype.run_no_param("Core_version").unwrap().into()
 //! };
 //!
@@ -181,11 +196,11 @@
 //! ```
 
 use super::{allocator, vm};
-use crate::util;
+use crate::{trie, util};
 
-use alloc::{borrow::ToOwned as _, format, string::String, vec::Vec};
-use core::{convert::TryFrom as _, fmt, hash::Hasher as _, iter, str};
-use parity_scale_codec::DecodeAll as _;
+use alloc::{borrow::ToOwned as _, collections::VecDeque, format, string::String, vec, vec::Vec};
+use core::{cmp, convert::TryFrom as _, fmt, hash::Hasher as _, iter, str};
+use parity_scale_codec::{Decode, DecodeAll as _, Encode};
 use sha2::Digest as _;
 use tiny_keccak::Hasher as _;
 
@@ -194,6 +209,46 @@ pub use zstd::Error as ModuleFormatError;
 
 mod zstd;
 
+/// Configuration for [`HostVmPrototype::new`].
+pub struct Config<TModule> {
+    /// Bytes of the WebAssembly module.
+    ///
+    /// The module can be either directly Wasm bytecode, or zstandard-compressed.
+    pub module: TModule,
+
+    /// Number of pages of heap available to the virtual machine, in addition to the initial
+    /// size of the memory.
+    pub heap_pages: HeapPages,
+
+    /// Hint used by the implementation to decide which kind of virtual machine to use.
+    pub exec_hint: vm::ExecHint,
+
+    /// If `false`, an error is returned if the module imports a function that isn't supported by
+    /// this module. If `true`, such a function is instead resolved to a stub that returns
+    /// [`Error::UnresolvedFunctionCalled`] when called.
+    ///
+    /// This makes it possible to use runtimes that import functions that aren't supported yet,
+    /// as long as the functions that are actually called are supported.
+    pub allow_unresolved_imports: bool,
+
+    /// Maximum size, in bytes, of the memory of the virtual machine, or `None` for no limit.
+    /// Rounded down to a multiple of 64kiB.
+    ///
+    /// This limit is set as the maximum size of the linear memory of the virtual machine. If
+    /// the memory of the module, including the heap pages, is larger than this value,
+    /// [`HostVmPrototype::new`] returns an error. The `memory.grow` instruction fails if the
+    /// memory would grow beyond this value.
+    pub max_memory_size: Option<u32>,
+
+    /// If `true`, the Wasm code is instrumented in order for each instruction that it executes
+    /// to consume one unit of [fuel](HostVmPrototype::set_fuel). If `false`, only the calls to
+    /// host functions consume fuel.
+    ///
+    /// The instrumentation slows down the execution, and should only be enabled when executing
+    /// untrusted code with a fuel limit.
+    pub metered: bool,
+}
+
 /// Prototype for an [`HostVm`].
 ///
 /// > **Note**: This struct implements `Clone`. Cloning a [`HostVmPrototype`] allocates memory
@@ -217,30 +272,92 @@ pub struct HostVmPrototype {
     /// The keys of this `Vec` (i.e. the `usize` indices) have been passed to the virtual machine
     /// executor. Whenever the Wasm code invokes a host function, we obtain its index, and look
     /// within this `Vec` to know what to do.
-    registered_functions: Vec<HostFunction>,
+    registered_functions: Vec<FunctionImport>,
 
-    /// Value of `heap_pages` passed to [`HostVmPrototype::new`].
+    /// Value of [`Config::heap_pages`] passed to [`HostVmPrototype::new`].
     heap_pages: HeapPages,
+
+    /// Value of [`Config::allow_unresolved_imports`] passed to [`HostVmPrototype::new`].
+    allow_unresolved_imports: bool,
+
+    /// Value of [`Config::max_memory_size`] passed to [`HostVmPrototype::new`].
+    max_memory_size: Option<u32>,
+
+    /// Fuel given to each call. See [`HostVmPrototype::set_fuel`].
+    fuel: Option<u64>,
+
+    /// See [`HostVmPrototype::set_trace_host_calls`].
+    trace_host_calls: bool,
 }
 
 impl HostVmPrototype {
     /// Creates a new [`HostVmPrototype`]. Parses and potentially JITs the module.
-    ///
-    /// The module can be either directly Wasm bytecode, or zstandard-compressed.
     // TODO: document `heap_pages`; I know it comes from storage, but it's unclear what it means exactly
-    pub fn new(
-        module: impl AsRef<[u8]>,
-        heap_pages: HeapPages,
-        exec_hint: vm::ExecHint,
+    pub fn new(config: Config<impl AsRef<[u8]>>) -> Result<Self, NewErr> {
+        // TODO: configurable maximum allowed size? a uniform value is important for consensus
+        let module = zstd::zstd_decode_if_necessary(config.module.as_ref(), 50 * 1024 * 1024)
+            .map_err(NewErr::BadFormat)?;
+        let module = compile_module(&module, &config)?;
+        Self::from_module(
+            module,
+            config.heap_pages,
+            config.allow_unresolved_imports,
+            config.max_memory_size,
+        )
+    }
+
+    /// Same as [`HostVmPrototype::new`], except that the compiled module is looked up in and
+    /// inserted into the given [`ModulesCache`].
+    ///
+    /// If a module with the same code, the same [`Config::exec_hint`], the same
+    /// [`Config::max_memory_size`], and the same [`Config::metered`] is found in the cache, the
+    /// decompression and compilation steps are skipped.
+    pub fn new_cached(
+        cache: &mut ModulesCache,
+        config: Config<impl AsRef<[u8]>>,
     ) -> Result<Self, NewErr> {
+        let code_hash = <[u8; 32]>::try_from(
+            blake2_rfc::blake2b::blake2b(32, &[], config.module.as_ref()).as_bytes(),
+        )
+        .unwrap();
+        let cache_key = CacheKey {
+            code_hash,
+            exec_hint: config.exec_hint,
+            max_memory_size: config.max_memory_size,
+            metered: config.metered,
+        };
+
+        if let Some(module) = cache.get(&cache_key) {
+            return Self::from_module(
+                module,
+                config.heap_pages,
+                config.allow_unresolved_imports,
+                config.max_memory_size,
+            );
+        }
+
         // TODO: configurable maximum allowed size? a uniform value is important for consensus
-        let module = zstd::zstd_decode_if_necessary(module.as_ref(), 50 * 1024 * 1024)
+        let module = zstd::zstd_decode_if_necessary(config.module.as_ref(), 50 * 1024 * 1024)
             .map_err(NewErr::BadFormat)?;
-        let module = vm::Module::new(module, exec_hint)?;
-        Self::from_module(module, heap_pages)
+        let module = compile_module(&module, &config)?;
+        let prototype = Self::from_module(
+            module.clone(),
+            config.heap_pages,
+            config.allow_unresolved_imports,
+            config.max_memory_size,
+        )?;
+        // Modules are only inserted after `from_module` has succeeded, so that a module in the
+        // cache is always known to be valid.
+        cache.insert(cache_key, module);
+        Ok(prototype)
     }
 
-    fn from_module(module: vm::Module, heap_pages: HeapPages) -> Result<Self, NewErr> {
+    fn from_module(
+        module: vm::Module,
+        heap_pages: HeapPages,
+        allow_unresolved_imports: bool,
+        max_memory_size: Option<u32>,
+    ) -> Result<Self, NewErr> {
         // Initialize the virtual machine.
         // Each symbol requested by the Wasm runtime will be put in `registered_functions`. Later,
         // when a function is invoked, the Wasm virtual machine will pass indices within that
@@ -252,13 +369,28 @@ impl HostVmPrototype {
                 heap_pages,
                 // This closure is called back for each function that the runtime imports.
                 |mod_name, f_name, _signature| {
-                    if mod_name != "env" {
-                        return Err(());
+                    let id = registered_functions.len();
+
+                    if module.is_metered()
+                        && mod_name == vm::METERING_MODULE_NAME
+                        && f_name == vm::METERING_FUNCTION_NAME
+                    {
+                        registered_functions.push(FunctionImport::Metering);
+                        return Ok(id);
                     }
 
-                    let id = registered_functions.len();
-                    registered_functions.push(match HostFunction::by_name(f_name) {
-                        Some(f) => f,
+                    let function = if mod_name == "env" {
+                        HostFunction::by_name(f_name)
+                    } else {
+                        None
+                    };
+
+                    registered_functions.push(match function {
+                        Some(f) => FunctionImport::Resolved(f),
+                        None if allow_unresolved_imports => FunctionImport::Unresolved {
+                            module: mod_name.to_owned(),
+                            name: f_name.to_owned(),
+                        },
                         None => return Err(()),
                     });
                     Ok(id)
@@ -280,6 +412,10 @@ impl HostVmPrototype {
             heap_base,
             registered_functions,
             heap_pages,
+            allow_unresolved_imports,
+            max_memory_size,
+            fuel: None,
+            trace_host_calls: false,
         })
     }
 
@@ -288,6 +424,62 @@ impl HostVmPrototype {
         self.heap_pages
     }
 
+    /// Returns the size of the memory of the virtual machine, in bytes.
+    ///
+    /// This doesn't include the memory used to hold the compiled code.
+    pub fn memory_size(&self) -> u32 {
+        self.vm_proto.memory_size()
+    }
+
+    /// Returns the value of [`Config::max_memory_size`] that was passed to
+    /// [`HostVmPrototype::new`].
+    pub fn max_memory_size(&self) -> Option<u32> {
+        self.max_memory_size
+    }
+
+    /// Returns the value of [`Config::metered`] that was passed to [`HostVmPrototype::new`].
+    pub fn metered(&self) -> bool {
+        self.module.is_metered()
+    }
+
+    /// Returns the fuel given to each call. See [`HostVmPrototype::set_fuel`].
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Sets the fuel given to each call started with this prototype, or `None` for no limit.
+    /// Defaults to `None`.
+    ///
+    /// Each host function called by the Wasm code consumes one unit of fuel. If
+    /// [`Config::metered`] was `true`, each instruction executed by the Wasm code also consumes
+    /// one unit of fuel. Each instruction executed by a module instantiated through the
+    /// `ext_sandbox_*` functions consumes one unit of fuel in all cases. If the fuel runs out,
+    /// the execution is aborted and [`Error::OutOfFuel`] is returned.
+    ///
+    /// This makes it possible to abort calls to misbehaving runtime functions, such as functions
+    /// that perform an infinite number of storage accesses or memory allocations.
+    ///
+    /// > **Note**: If [`Config::metered`] was `false`, Wasm code that loops indefinitely without
+    /// >           ever calling a host function isn't affected by this limit.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    /// Returns whether the calls to host functions are traced. See
+    /// [`HostVmPrototype::set_trace_host_calls`].
+    pub fn trace_host_calls(&self) -> bool {
+        self.trace_host_calls
+    }
+
+    /// Sets whether the virtual machine records the calls to host functions made by the Wasm
+    /// code. Defaults to `false`.
+    ///
+    /// If `true`, the list of calls is available through [`Finished::host_calls`] once the
+    /// execution has succeeded. This makes it possible to profile calls to the runtime.
+    pub fn set_trace_host_calls(&mut self, trace: bool) {
+        self.trace_host_calls = trace;
+    }
+
     /// Starts the VM, calling the function passed as parameter.
     pub fn run(self, function_to_call: &str, data: &[u8]) -> Result<ReadyToRun, (StartErr, Self)> {
         self.run_vectored(function_to_call, iter::once(data))
@@ -355,8 +547,20 @@ impl HostVmPrototype {
                 heap_base: self.heap_base,
                 heap_pages: self.heap_pages,
                 registered_functions: self.registered_functions,
+                allow_unresolved_imports: self.allow_unresolved_imports,
+                max_memory_size: self.max_memory_size,
                 within_storage_transaction: false,
+                batch_verification: None,
+                host_calls: if self.trace_host_calls {
+                    Some(Vec::new())
+                } else {
+                    None
+                },
+                sandbox_memories: Vec::new(),
+                sandbox_instances: Vec::new(),
                 allocator,
+                fuel: self.fuel,
+                remaining_fuel: self.fuel,
             },
         })
     }
@@ -367,7 +571,16 @@ impl Clone for HostVmPrototype {
         // The `from_module` function returns an error if the format of the module is invalid.
         // Since we have successfully called `from_module` with that same `module` earlier, it
         // is assumed that errors cannot happen.
-        Self::from_module(self.module.clone(), self.heap_pages).unwrap()
+        let mut clone = Self::from_module(
+            self.module.clone(),
+            self.heap_pages,
+            self.allow_unresolved_imports,
+            self.max_memory_size,
+        )
+        .unwrap();
+        clone.fuel = self.fuel;
+        clone.trace_host_calls = self.trace_host_calls;
+        clone
     }
 }
 
@@ -377,6 +590,175 @@ impl fmt::Debug for HostVmPrototype {
     }
 }
 
+/// Pool of [`HostVmPrototype`]s of the same runtime.
+///
+/// Creating a [`HostVmPrototype`] requires allocating and initializing the memory of the virtual
+/// machine. When the same runtime is called repeatedly, it is faster to give back the prototype
+/// to the pool with [`HostVmPool::release`] once a call is over, and obtain it again with
+/// [`HostVmPool::acquire`] for the next call.
+///
+/// The memory and the global variables of the prototypes given back to the pool are restored to
+/// the state they were in right after the prototype has been created, meaning that a call can't
+/// observe what previous calls have left behind.
+pub struct HostVmPool {
+    /// Prototypes ready to be used, all in the same state as right after their creation.
+    /// Never empty. When it contains a single element, this element is cloned rather than
+    /// returned by [`HostVmPool::acquire`].
+    idle: Vec<HostVmPrototype>,
+    /// Maximum number of elements in [`HostVmPool::idle`].
+    capacity: usize,
+}
+
+impl HostVmPool {
+    /// Initializes a new pool containing the given prototype. At most `capacity` prototypes are
+    /// kept in the pool. A `capacity` of 0 is treated as 1.
+    ///
+    /// The [fuel](HostVmPrototype::set_fuel) and the
+    /// [tracing](HostVmPrototype::set_trace_host_calls) settings of `prototype` are applied to
+    /// all the prototypes returned by [`HostVmPool::acquire`].
+    pub fn new(mut prototype: HostVmPrototype, capacity: usize) -> Self {
+        if prototype.vm_proto.reset(&prototype.module).is_err() {
+            // Cloning a prototype creates a new virtual machine.
+            prototype = prototype.clone();
+        }
+
+        let capacity = cmp::max(capacity, 1);
+        let mut idle = Vec::with_capacity(capacity);
+        idle.push(prototype);
+        HostVmPool { idle, capacity }
+    }
+
+    /// Returns the number of prototypes in the pool.
+    pub fn num_prototypes(&self) -> usize {
+        self.idle.len()
+    }
+
+    /// Returns a prototype from the pool, or creates a new one if the pool only contains one
+    /// prototype.
+    pub fn acquire(&mut self) -> HostVmPrototype {
+        if self.idle.len() >= 2 {
+            self.idle.pop().unwrap()
+        } else {
+            self.idle[0].clone()
+        }
+    }
+
+    /// Gives back a prototype, typically obtained by calling [`HostVm::into_prototype`] after a
+    /// call has finished, in order for it to be returned by a later call to
+    /// [`HostVmPool::acquire`].
+    ///
+    /// The prototype is silently discarded if the pool is full or if its state can't be
+    /// restored.
+    ///
+    /// The prototype must have been obtained from [`HostVmPool::acquire`]. Giving back a
+    /// prototype of a different runtime is a logic error.
+    pub fn release(&mut self, mut prototype: HostVmPrototype) {
+        if self.idle.len() >= self.capacity {
+            return;
+        }
+
+        if prototype.vm_proto.reset(&prototype.module).is_err() {
+            return;
+        }
+
+        // The fuel and tracing settings might have been modified by the user.
+        prototype.fuel = self.idle[0].fuel;
+        prototype.trace_host_calls = self.idle[0].trace_host_calls;
+        self.idle.push(prototype);
+    }
+}
+
+impl fmt::Debug for HostVmPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HostVmPool")
+            .field("len", &self.idle.len())
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
+
+/// Cache of compiled modules, keyed by the blake2 hash of their code.
+///
+/// Pass this cache to [`HostVmPrototype::new_cached`] in order to avoid compiling the same code
+/// multiple times, for example when the same runtime is used by multiple chains.
+///
+/// > **Note**: Cloning a compiled module is cheap, and the cache doesn't copy the code of the
+/// >           modules. Each entry, however, keeps its compiled module alive.
+pub struct ModulesCache {
+    /// Entries of the cache, ordered from the least recently used to the most recently used.
+    entries: VecDeque<(CacheKey, vm::Module)>,
+    /// Maximum number of elements in [`ModulesCache::entries`].
+    capacity: usize,
+}
+
+/// Parameters that a module of [`ModulesCache`] has been compiled with.
+#[derive(Debug, PartialEq)]
+struct CacheKey {
+    /// BLAKE2 hash of the value of [`Config::module`].
+    code_hash: [u8; 32],
+    /// Value of [`Config::exec_hint`].
+    exec_hint: vm::ExecHint,
+    /// Value of [`Config::max_memory_size`].
+    max_memory_size: Option<u32>,
+    /// Value of [`Config::metered`].
+    metered: bool,
+}
+
+impl ModulesCache {
+    /// Initializes a new empty cache that contains at most `capacity` modules.
+    pub fn new(capacity: usize) -> Self {
+        ModulesCache {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Returns the number of modules in the cache.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if the cache doesn't contain any module.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Removes all the modules from the cache.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Returns the module with the given key, and marks it as the most recently used.
+    fn get(&mut self, key: &CacheKey) -> Option<vm::Module> {
+        let position = self.entries.iter().position(|(k, _)| k == key)?;
+        let entry = self.entries.remove(position).unwrap();
+        let module = entry.1.clone();
+        self.entries.push_back(entry);
+        Some(module)
+    }
+
+    /// Inserts a module in the cache, evicting the least recently used module if necessary.
+    fn insert(&mut self, key: CacheKey, module: vm::Module) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+        }
+
+        self.entries.push_back((key, module));
+    }
+}
+
+impl fmt::Debug for ModulesCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries(self.entries.iter().map(|(k, _)| k))
+            .finish()
+    }
+}
+
 /// Running virtual machine.
 #[must_use]
 #[derive(derive_more::From)]
@@ -418,6 +800,22 @@ pub enum HostVm {
     /// Must the set value of an offchain storage entry.
     #[from]
     ExternalOffchainStorageSet(ExternalOffchainStorageSet),
+    /// Must load a value from the local storage of the offchain worker.
+    #[from]
+    ExternalOffchainLocalStorageGet(ExternalOffchainLocalStorageGet),
+    /// Must set or remove a value of the local storage of the offchain worker.
+    #[from]
+    ExternalOffchainLocalStorageSet(ExternalOffchainLocalStorageSet),
+    /// Must set a value of the local storage of the offchain worker if the current value matches
+    /// an expected one.
+    #[from]
+    ExternalOffchainLocalStorageCompareAndSet(ExternalOffchainLocalStorageCompareAndSet),
+    /// Need to provide the current UNIX timestamp.
+    #[from]
+    OffchainTimestamp(OffchainTimestamp),
+    /// Need to provide a randomly-generated seed.
+    #[from]
+    OffchainRandomSeed(OffchainRandomSeed),
     /// Need to call `Core_version` on the given Wasm code and return the raw output (i.e.
     /// still SCALE-encoded), or an error if the call has failed.
     #[from]
@@ -446,6 +844,10 @@ pub enum HostVm {
     /// Runtime has emitted a log entry.
     #[from]
     LogEmit(LogEmit),
+    /// Runtime has finished a batch verification of signatures. The signatures of the batch
+    /// must be verified in order to continue.
+    #[from]
+    SignatureBatchVerify(SignatureBatchVerify),
 }
 
 impl HostVm {
@@ -463,10 +865,18 @@ impl HostVm {
             HostVm::ExternalStorageChangesRoot(inner) => inner.inner.into_prototype(),
             HostVm::ExternalStorageNextKey(inner) => inner.inner.into_prototype(),
             HostVm::ExternalOffchainStorageSet(inner) => inner.inner.into_prototype(),
+            HostVm::ExternalOffchainLocalStorageGet(inner) => inner.inner.into_prototype(),
+            HostVm::ExternalOffchainLocalStorageSet(inner) => inner.inner.into_prototype(),
+            HostVm::ExternalOffchainLocalStorageCompareAndSet(inner) => {
+                inner.inner.into_prototype()
+            }
+            HostVm::OffchainTimestamp(inner) => inner.inner.into_prototype(),
+            HostVm::OffchainRandomSeed(inner) => inner.inner.into_prototype(),
             HostVm::CallRuntimeVersion(inner) => inner.inner.into_prototype(),
             HostVm::StartStorageTransaction(inner) => inner.inner.into_prototype(),
             HostVm::EndStorageTransaction { resume, .. } => resume.inner.into_prototype(),
             HostVm::LogEmit(inner) => inner.inner.into_prototype(),
+            HostVm::SignatureBatchVerify(inner) => inner.inner.into_prototype(),
         }
     }
 }
@@ -493,7 +903,9 @@ impl ReadyToRun {
     fn run_once(mut self) -> HostVm {
         // `vm::ExecOutcome::Interrupted` is by far the variant that requires the most
         // handling code. As such, special-case all other variants before.
-        let (id, params) = match self.inner.vm.run(self.resume_value) {
+        let outcome = self.inner.vm.run(self.resume_value);
+
+        let (id, params) = match outcome {
             Ok(vm::ExecOutcome::Interrupted { id, params }) => (id, params),
 
             Ok(vm::ExecOutcome::Finished {
@@ -575,9 +987,60 @@ impl ReadyToRun {
             }
         };
 
+        // Calls to the metering function report the number of instructions about to be
+        // executed, and aren't calls to host functions.
+        if let FunctionImport::Metering = self.inner.registered_functions[id] {
+            let num_instructions = match params.first() {
+                Some(vm::WasmValue::I32(n)) => u64::from(u32::from_ne_bytes(n.to_ne_bytes())),
+                // The metering function is called by code injected by `vm::Module::new_metered`,
+                // which always passes a single `i32`.
+                _ => unreachable!(),
+            };
+
+            if let Some(remaining_fuel) = &mut self.inner.remaining_fuel {
+                match remaining_fuel.checked_sub(num_instructions) {
+                    Some(r) => *remaining_fuel = r,
+                    None => {
+                        return HostVm::Error {
+                            prototype: self.inner.into_prototype(),
+                            error: Error::OutOfFuel,
+                        };
+                    }
+                }
+            }
+
+            return HostVm::ReadyToRun(ReadyToRun {
+                inner: self.inner,
+                resume_value: None,
+            });
+        }
+
+        // Each host function call consumes one unit of fuel.
+        if let Some(remaining_fuel) = &mut self.inner.remaining_fuel {
+            if *remaining_fuel == 0 {
+                return HostVm::Error {
+                    prototype: self.inner.into_prototype(),
+                    error: Error::OutOfFuel,
+                };
+            }
+            *remaining_fuel -= 1;
+        }
+
         // The Wasm code has called an host_fn. The `id` is a value that we passed
         // at initialization, and corresponds to an index in `registered_functions`.
-        let host_fn = *self.inner.registered_functions.get_mut(id).unwrap();
+        let host_fn = match &self.inner.registered_functions[id] {
+            FunctionImport::Resolved(f) => *f,
+            FunctionImport::Metering => unreachable!(),
+            FunctionImport::Unresolved { module, name } => {
+                return HostVm::Error {
+                    error: Error::UnresolvedFunctionCalled {
+                        module_name: module.clone(),
+                        function: name.clone(),
+                    },
+                    prototype: self.inner.into_prototype(),
+                };
+            }
+        };
 
         // Check that the actual number of parameters matches the expected number.
         // This is done ahead of time in order to not forget.
@@ -593,6 +1056,27 @@ impl ReadyToRun {
             };
         }
 
+        if let Some(host_calls) = &mut self.inner.host_calls {
+            host_calls.push(HostCall {
+                function: host_fn.name(),
+                params_sizes: vec![0; params.len()],
+            });
+        }
+
+        // Records the number of bytes of a parameter in the call being traced, if any.
+        macro_rules! trace_param_size {
+            ($num:expr, $size:expr) => {{
+                if let Some(host_call) = self
+                    .inner
+                    .host_calls
+                    .as_mut()
+                    .and_then(|calls| calls.last_mut())
+                {
+                    host_call.params_sizes[$num] = $size;
+                }
+            }};
+        }
+
         // Passed a parameter index. Produces an `impl AsRef<[u8]>`.
         macro_rules! expect_pointer_size {
             ($num:expr) => {{
@@ -613,6 +1097,7 @@ impl ReadyToRun {
 
                 let len = u32::try_from(val >> 32).unwrap();
                 let ptr = u32::try_from(val & 0xffffffff).unwrap();
+                trace_param_size!($num, len);
 
                 let result = self.inner.vm.read_memory(ptr, len);
                 match result {
@@ -652,6 +1137,7 @@ impl ReadyToRun {
 
                 let len = u32::try_from(val >> 32).unwrap();
                 let ptr = u32::try_from(val & 0xffffffff).unwrap();
+                trace_param_size!($num, len);
 
                 if len.saturating_add(ptr) > self.inner.vm.memory_size() {
                     return HostVm::Error {
@@ -686,6 +1172,7 @@ impl ReadyToRun {
                     }
                 };
 
+                trace_param_size!($num, $size);
                 let result = self.inner.vm.read_memory(ptr, $size);
                 match result {
                     Ok(v) => v,
@@ -708,7 +1195,10 @@ impl ReadyToRun {
         macro_rules! expect_u32 {
             ($num:expr) => {{
                 match &params[$num] {
-                    vm::WasmValue::I32(v) => u32::from_ne_bytes(v.to_ne_bytes()),
+                    vm::WasmValue::I32(v) => {
+                        trace_param_size!($num, 4);
+                        u32::from_ne_bytes(v.to_ne_bytes())
+                    }
                     v => {
                         return HostVm::Error {
                             error: Error::WrongParamTy {
@@ -724,6 +1214,25 @@ impl ReadyToRun {
             }};
         }
 
+        // Extracts the state version passed as parameter to some of the trie-related functions.
+        macro_rules! expect_state_version {
+            ($num:expr) => {{
+                let state_version = expect_u32!($num);
+                match trie::TrieEntryVersion::from_state_version(state_version) {
+                    Some(v) => v,
+                    None => {
+                        return HostVm::Error {
+                            error: Error::UnknownStateVersion {
+                                function: host_fn.name(),
+                                state_version,
+                            },
+                            prototype: self.inner.into_prototype(),
+                        }
+                    }
+                }
+            }};
+        }
+
         // Handle the function calls.
         // Some of these enum variants simply change the state of `self`, while most of them
         // instead return an `ExternalVm` to the user.
@@ -734,6 +1243,7 @@ impl ReadyToRun {
                 HostVm::ExternalStorageSet(ExternalStorageSet {
                     key_ptr,
                     key_size,
+                    child_trie_ptr_size: None,
                     value: Some((value_ptr, value_size)),
                     inner: self.inner,
                 })
@@ -743,6 +1253,7 @@ impl ReadyToRun {
                 HostVm::ExternalStorageGet(ExternalStorageGet {
                     key_ptr,
                     key_size,
+                    child_trie_ptr_size: None,
                     calling: id,
                     value_out_ptr: None,
                     offset: 0,
@@ -757,6 +1268,7 @@ impl ReadyToRun {
                 HostVm::ExternalStorageGet(ExternalStorageGet {
                     key_ptr,
                     key_size,
+                    child_trie_ptr_size: None,
                     calling: id,
                     value_out_ptr: Some(value_out_ptr),
                     offset,
@@ -769,6 +1281,7 @@ impl ReadyToRun {
                 HostVm::ExternalStorageSet(ExternalStorageSet {
                     key_ptr,
                     key_size,
+                    child_trie_ptr_size: None,
                     value: None,
                     inner: self.inner,
                 })
@@ -778,6 +1291,7 @@ impl ReadyToRun {
                 HostVm::ExternalStorageGet(ExternalStorageGet {
                     key_ptr,
                     key_size,
+                    child_trie_ptr_size: None,
                     calling: id,
                     value_out_ptr: None,
                     offset: 0,
@@ -790,6 +1304,8 @@ impl ReadyToRun {
                 HostVm::ExternalStorageClearPrefix(ExternalStorageClearPrefix {
                     prefix_ptr,
                     prefix_size,
+                    child_trie_ptr_size: None,
+                    calling: id,
                     inner: self.inner,
                     max_keys_to_remove: None,
                 })
@@ -812,12 +1328,28 @@ impl ReadyToRun {
                 HostVm::ExternalStorageClearPrefix(ExternalStorageClearPrefix {
                     prefix_ptr,
                     prefix_size,
+                    child_trie_ptr_size: None,
+                    calling: id,
                     inner: self.inner,
                     max_keys_to_remove,
                 })
             }
             HostFunction::ext_storage_root_version_1 => {
-                HostVm::ExternalStorageRoot(ExternalStorageRoot { inner: self.inner })
+                HostVm::ExternalStorageRoot(ExternalStorageRoot {
+                    inner: self.inner,
+                    calling: id,
+                    child_trie_ptr_size: None,
+                    state_trie_version: trie::TrieEntryVersion::V0,
+                })
+            }
+            HostFunction::ext_storage_root_version_2 => {
+                let state_trie_version = expect_state_version!(0);
+                HostVm::ExternalStorageRoot(ExternalStorageRoot {
+                    inner: self.inner,
+                    calling: id,
+                    child_trie_ptr_size: None,
+                    state_trie_version,
+                })
             }
             HostFunction::ext_storage_changes_root_version_1 => {
                 // TODO: there's a parameter
@@ -828,6 +1360,7 @@ impl ReadyToRun {
                 HostVm::ExternalStorageNextKey(ExternalStorageNextKey {
                     key_ptr,
                     key_size,
+                    child_trie_ptr_size: None,
                     inner: self.inner,
                 })
             }
@@ -890,80 +1423,254 @@ impl ReadyToRun {
                     rollback: false,
                 }
             }
-            HostFunction::ext_default_child_storage_get_version_1 => todo!(),
-            HostFunction::ext_default_child_storage_read_version_1 => todo!(),
-            HostFunction::ext_default_child_storage_storage_kill_version_1 => todo!(),
-            HostFunction::ext_default_child_storage_storage_kill_version_2 => todo!(),
-            HostFunction::ext_default_child_storage_storage_kill_version_3 => todo!(),
-            HostFunction::ext_default_child_storage_clear_prefix_version_1 => todo!(),
-            HostFunction::ext_default_child_storage_clear_prefix_version_2 => todo!(),
-            HostFunction::ext_default_child_storage_set_version_1 => todo!(),
-            HostFunction::ext_default_child_storage_clear_version_1 => todo!(),
-            HostFunction::ext_default_child_storage_exists_version_1 => todo!(),
-            HostFunction::ext_default_child_storage_next_key_version_1 => todo!(),
-            HostFunction::ext_default_child_storage_root_version_1 => todo!(),
-            HostFunction::ext_crypto_ed25519_public_keys_version_1 => todo!(),
-            HostFunction::ext_crypto_ed25519_generate_version_1 => todo!(),
-            HostFunction::ext_crypto_ed25519_sign_version_1 => todo!(),
-            HostFunction::ext_crypto_ed25519_verify_version_1 => {
-                let success = {
-                    // TODO: copy overhead?
-                    let public_key = ed25519_zebra::VerificationKey::try_from(
-                        expect_pointer_constant_size!(2, 32).as_ref(),
-                    );
-                    if let Ok(public_key) = public_key {
-                        // TODO: copy overhead?
-                        let signature = ed25519_zebra::Signature::from(
-                            <[u8; 64]>::try_from(expect_pointer_constant_size!(0, 64).as_ref())
-                                .unwrap(),
-                        );
-                        let message = expect_pointer_size!(1).as_ref().to_owned(); // TODO: to_owned() :-/
-                        public_key.verify(&signature, &message).is_ok()
-                    } else {
-                        false
-                    }
-                };
-
-                HostVm::ReadyToRun(ReadyToRun {
-                    resume_value: Some(vm::WasmValue::I32(if success { 1 } else { 0 })),
+            HostFunction::ext_default_child_storage_get_version_1 => {
+                let child_trie_ptr_size = expect_pointer_size_raw!(0);
+                let (key_ptr, key_size) = expect_pointer_size_raw!(1);
+                HostVm::ExternalStorageGet(ExternalStorageGet {
+                    key_ptr,
+                    key_size,
+                    child_trie_ptr_size: Some(child_trie_ptr_size),
+                    calling: id,
+                    value_out_ptr: None,
+                    offset: 0,
+                    max_size: u32::max_value(),
                     inner: self.inner,
                 })
             }
-            HostFunction::ext_crypto_sr25519_public_keys_version_1 => todo!(),
-            HostFunction::ext_crypto_sr25519_generate_version_1 => todo!(),
-            HostFunction::ext_crypto_sr25519_sign_version_1 => todo!(),
-            HostFunction::ext_crypto_sr25519_verify_version_1 => {
-                let success = {
-                    // The `unwrap()` below can only panic if the input is the wrong length, which
-                    // we know can't happen.
-                    // TODO: copy overhead?
-                    let signing_public_key = schnorrkel::PublicKey::from_bytes(
-                        expect_pointer_constant_size!(2, 32).as_ref(),
-                    )
-                    .unwrap();
-
-                    let message = expect_pointer_size!(1).as_ref().to_owned(); // TODO: to_owned() :-/
-                    let signature = expect_pointer_constant_size!(0, 64).as_ref().to_owned(); // TODO: to_owned() :-/
-
-                    signing_public_key
-                        .verify_simple_preaudit_deprecated(b"substrate", &message, &signature)
-                        .is_ok()
-                };
-
-                HostVm::ReadyToRun(ReadyToRun {
-                    resume_value: Some(vm::WasmValue::I32(if success { 1 } else { 0 })),
+            HostFunction::ext_default_child_storage_read_version_1 => {
+                let child_trie_ptr_size = expect_pointer_size_raw!(0);
+                let (key_ptr, key_size) = expect_pointer_size_raw!(1);
+                let (value_out_ptr, value_out_size) = expect_pointer_size_raw!(2);
+                let offset = expect_u32!(3);
+                HostVm::ExternalStorageGet(ExternalStorageGet {
+                    key_ptr,
+                    key_size,
+                    child_trie_ptr_size: Some(child_trie_ptr_size),
+                    calling: id,
+                    value_out_ptr: Some(value_out_ptr),
+                    offset,
+                    max_size: value_out_size,
                     inner: self.inner,
                 })
             }
-            HostFunction::ext_crypto_sr25519_verify_version_2 => {
-                let success = {
-                    // The two `unwrap()`s below can only panic if the input is the wrong
-                    // length, which we know can't happen.
-                    // TODO: copy overhead?
-                    let signing_public_key = schnorrkel::PublicKey::from_bytes(
-                        expect_pointer_constant_size!(2, 32).as_ref(),
-                    )
-                    .unwrap();
+            HostFunction::ext_default_child_storage_storage_kill_version_1 => {
+                let child_trie_ptr_size = expect_pointer_size_raw!(0);
+                HostVm::ExternalStorageClearPrefix(ExternalStorageClearPrefix {
+                    prefix_ptr: 0,
+                    prefix_size: 0,
+                    child_trie_ptr_size: Some(child_trie_ptr_size),
+                    calling: id,
+                    inner: self.inner,
+                    max_keys_to_remove: None,
+                })
+            }
+            HostFunction::ext_default_child_storage_storage_kill_version_2
+            | HostFunction::ext_default_child_storage_storage_kill_version_3 => {
+                let child_trie_ptr_size = expect_pointer_size_raw!(0);
+
+                let max_keys_to_remove =
+                    Option::<u32>::decode_all(expect_pointer_size!(1).as_ref());
+                let max_keys_to_remove = match max_keys_to_remove {
+                    Ok(l) => l,
+                    Err(err) => {
+                        return HostVm::Error {
+                            error: Error::ParamDecodeError(err),
+                            prototype: self.inner.into_prototype(),
+                        };
+                    }
+                };
+
+                HostVm::ExternalStorageClearPrefix(ExternalStorageClearPrefix {
+                    prefix_ptr: 0,
+                    prefix_size: 0,
+                    child_trie_ptr_size: Some(child_trie_ptr_size),
+                    calling: id,
+                    inner: self.inner,
+                    max_keys_to_remove,
+                })
+            }
+            HostFunction::ext_default_child_storage_clear_prefix_version_1 => {
+                let child_trie_ptr_size = expect_pointer_size_raw!(0);
+                let (prefix_ptr, prefix_size) = expect_pointer_size_raw!(1);
+                HostVm::ExternalStorageClearPrefix(ExternalStorageClearPrefix {
+                    prefix_ptr,
+                    prefix_size,
+                    child_trie_ptr_size: Some(child_trie_ptr_size),
+                    calling: id,
+                    inner: self.inner,
+                    max_keys_to_remove: None,
+                })
+            }
+            HostFunction::ext_default_child_storage_clear_prefix_version_2 => {
+                let child_trie_ptr_size = expect_pointer_size_raw!(0);
+                let (prefix_ptr, prefix_size) = expect_pointer_size_raw!(1);
+
+                let max_keys_to_remove =
+                    Option::<u32>::decode_all(expect_pointer_size!(2).as_ref());
+                let max_keys_to_remove = match max_keys_to_remove {
+                    Ok(l) => l,
+                    Err(err) => {
+                        return HostVm::Error {
+                            error: Error::ParamDecodeError(err),
+                            prototype: self.inner.into_prototype(),
+                        };
+                    }
+                };
+
+                HostVm::ExternalStorageClearPrefix(ExternalStorageClearPrefix {
+                    prefix_ptr,
+                    prefix_size,
+                    child_trie_ptr_size: Some(child_trie_ptr_size),
+                    calling: id,
+                    inner: self.inner,
+                    max_keys_to_remove,
+                })
+            }
+            HostFunction::ext_default_child_storage_set_version_1 => {
+                let child_trie_ptr_size = expect_pointer_size_raw!(0);
+                let (key_ptr, key_size) = expect_pointer_size_raw!(1);
+                let (value_ptr, value_size) = expect_pointer_size_raw!(2);
+                HostVm::ExternalStorageSet(ExternalStorageSet {
+                    key_ptr,
+                    key_size,
+                    child_trie_ptr_size: Some(child_trie_ptr_size),
+                    value: Some((value_ptr, value_size)),
+                    inner: self.inner,
+                })
+            }
+            HostFunction::ext_default_child_storage_clear_version_1 => {
+                let child_trie_ptr_size = expect_pointer_size_raw!(0);
+                let (key_ptr, key_size) = expect_pointer_size_raw!(1);
+                HostVm::ExternalStorageSet(ExternalStorageSet {
+                    key_ptr,
+                    key_size,
+                    child_trie_ptr_size: Some(child_trie_ptr_size),
+                    value: None,
+                    inner: self.inner,
+                })
+            }
+            HostFunction::ext_default_child_storage_exists_version_1 => {
+                let child_trie_ptr_size = expect_pointer_size_raw!(0);
+                let (key_ptr, key_size) = expect_pointer_size_raw!(1);
+                HostVm::ExternalStorageGet(ExternalStorageGet {
+                    key_ptr,
+                    key_size,
+                    child_trie_ptr_size: Some(child_trie_ptr_size),
+                    calling: id,
+                    value_out_ptr: None,
+                    offset: 0,
+                    max_size: 0,
+                    inner: self.inner,
+                })
+            }
+            HostFunction::ext_default_child_storage_next_key_version_1 => {
+                let child_trie_ptr_size = expect_pointer_size_raw!(0);
+                let (key_ptr, key_size) = expect_pointer_size_raw!(1);
+                HostVm::ExternalStorageNextKey(ExternalStorageNextKey {
+                    key_ptr,
+                    key_size,
+                    child_trie_ptr_size: Some(child_trie_ptr_size),
+                    inner: self.inner,
+                })
+            }
+            HostFunction::ext_default_child_storage_root_version_1 => {
+                let child_trie_ptr_size = expect_pointer_size_raw!(0);
+                HostVm::ExternalStorageRoot(ExternalStorageRoot {
+                    inner: self.inner,
+                    calling: id,
+                    child_trie_ptr_size: Some(child_trie_ptr_size),
+                    state_trie_version: trie::TrieEntryVersion::V0,
+                })
+            }
+            HostFunction::ext_default_child_storage_root_version_2 => {
+                let child_trie_ptr_size = expect_pointer_size_raw!(0);
+                let state_trie_version = expect_state_version!(1);
+                HostVm::ExternalStorageRoot(ExternalStorageRoot {
+                    inner: self.inner,
+                    calling: id,
+                    child_trie_ptr_size: Some(child_trie_ptr_size),
+                    state_trie_version,
+                })
+            }
+            HostFunction::ext_crypto_ed25519_public_keys_version_1 => todo!(),
+            HostFunction::ext_crypto_ed25519_generate_version_1 => todo!(),
+            HostFunction::ext_crypto_ed25519_sign_version_1 => todo!(),
+            HostFunction::ext_crypto_ed25519_verify_version_1 => {
+                let success = {
+                    // TODO: copy overhead?
+                    let public_key = ed25519_zebra::VerificationKey::try_from(
+                        expect_pointer_constant_size!(2, 32).as_ref(),
+                    );
+                    if let Ok(public_key) = public_key {
+                        // TODO: copy overhead?
+                        let signature = ed25519_zebra::Signature::from(
+                            <[u8; 64]>::try_from(expect_pointer_constant_size!(0, 64).as_ref())
+                                .unwrap(),
+                        );
+                        let message = expect_pointer_size!(1).as_ref().to_owned(); // TODO: to_owned() :-/
+                        public_key.verify(&signature, &message).is_ok()
+                    } else {
+                        false
+                    }
+                };
+
+                HostVm::ReadyToRun(ReadyToRun {
+                    resume_value: Some(vm::WasmValue::I32(if success { 1 } else { 0 })),
+                    inner: self.inner,
+                })
+            }
+            HostFunction::ext_crypto_ed25519_batch_verify_version_1 => {
+                // The values are read in separate statements, as the memory is borrowed until
+                // the end of the statement.
+                let signature =
+                    <[u8; 64]>::try_from(expect_pointer_constant_size!(0, 64).as_ref()).unwrap();
+                let message = expect_pointer_size!(1).as_ref().to_vec();
+                let public_key =
+                    <[u8; 32]>::try_from(expect_pointer_constant_size!(2, 32).as_ref()).unwrap();
+                let signature = SignatureVerification {
+                    inner: SignatureVerificationInner::Ed25519 {
+                        signature,
+                        message,
+                        public_key,
+                    },
+                };
+                self.inner.verify_or_defer(signature)
+            }
+            HostFunction::ext_crypto_sr25519_public_keys_version_1 => todo!(),
+            HostFunction::ext_crypto_sr25519_generate_version_1 => todo!(),
+            HostFunction::ext_crypto_sr25519_sign_version_1 => todo!(),
+            HostFunction::ext_crypto_sr25519_verify_version_1 => {
+                let success = {
+                    // The `unwrap()` below can only panic if the input is the wrong length, which
+                    // we know can't happen.
+                    // TODO: copy overhead?
+                    let signing_public_key = schnorrkel::PublicKey::from_bytes(
+                        expect_pointer_constant_size!(2, 32).as_ref(),
+                    )
+                    .unwrap();
+
+                    let message = expect_pointer_size!(1).as_ref().to_owned(); // TODO: to_owned() :-/
+                    let signature = expect_pointer_constant_size!(0, 64).as_ref().to_owned(); // TODO: to_owned() :-/
+
+                    signing_public_key
+                        .verify_simple_preaudit_deprecated(b"substrate", &message, &signature)
+                        .is_ok()
+                };
+
+                HostVm::ReadyToRun(ReadyToRun {
+                    resume_value: Some(vm::WasmValue::I32(if success { 1 } else { 0 })),
+                    inner: self.inner,
+                })
+            }
+            HostFunction::ext_crypto_sr25519_verify_version_2 => {
+                let success = {
+                    // The two `unwrap()`s below can only panic if the input is the wrong
+                    // length, which we know can't happen.
+                    // TODO: copy overhead?
+                    let signing_public_key = schnorrkel::PublicKey::from_bytes(
+                        expect_pointer_constant_size!(2, 32).as_ref(),
+                    )
+                    .unwrap();
                     // TODO: copy overhead?
                     let signature = schnorrkel::Signature::from_bytes(
                         expect_pointer_constant_size!(0, 64).as_ref(),
@@ -980,7 +1687,98 @@ impl ReadyToRun {
                     inner: self.inner,
                 })
             }
+            HostFunction::ext_crypto_sr25519_batch_verify_version_1 => {
+                // The values are read in separate statements, as the memory is borrowed until
+                // the end of the statement.
+                let signature =
+                    <[u8; 64]>::try_from(expect_pointer_constant_size!(0, 64).as_ref()).unwrap();
+                let message = expect_pointer_size!(1).as_ref().to_vec();
+                let public_key =
+                    <[u8; 32]>::try_from(expect_pointer_constant_size!(2, 32).as_ref()).unwrap();
+                let signature = SignatureVerification {
+                    inner: SignatureVerificationInner::Sr25519 {
+                        signature,
+                        message,
+                        public_key,
+                    },
+                };
+                self.inner.verify_or_defer(signature)
+            }
+            HostFunction::ext_crypto_ecdsa_public_keys_version_1 => {
+                // There isn't any keystore. Returns a SCALE-encoded empty `Vec`.
+                self.inner
+                    .alloc_write_and_return_pointer_size(host_fn.name(), iter::once(&[0]))
+            }
             HostFunction::ext_crypto_ecdsa_generate_version_1 => todo!(),
+            HostFunction::ext_crypto_ecdsa_sign_version_1
+            | HostFunction::ext_crypto_ecdsa_sign_prehashed_version_1 => {
+                // There isn't any keystore, and thus the requested key is never found. Returns a
+                // SCALE-encoded `None`.
+                self.inner
+                    .alloc_write_and_return_pointer_size(host_fn.name(), iter::once(&[0]))
+            }
+            HostFunction::ext_crypto_ecdsa_verify_version_1
+            | HostFunction::ext_crypto_ecdsa_verify_version_2 => {
+                let success = {
+                    let message_hash = {
+                        let message = expect_pointer_size!(1);
+                        blake2_rfc::blake2b::blake2b(32, &[], message.as_ref())
+                    };
+                    // The first version accepts overflowing signatures.
+                    let overflowing =
+                        matches!(host_fn, HostFunction::ext_crypto_ecdsa_verify_version_1);
+                    // TODO: to_owned() :-/ difficult-to-solve borrowck issues otherwise
+                    let signature = expect_pointer_constant_size!(0, 65).as_ref().to_owned();
+                    ecdsa_verify(
+                        &signature,
+                        message_hash.as_bytes(),
+                        expect_pointer_constant_size!(2, 33).as_ref(),
+                        overflowing,
+                    )
+                };
+
+                HostVm::ReadyToRun(ReadyToRun {
+                    resume_value: Some(vm::WasmValue::I32(if success { 1 } else { 0 })),
+                    inner: self.inner,
+                })
+            }
+            HostFunction::ext_crypto_ecdsa_batch_verify_version_1 => {
+                let message_hash = {
+                    let message = expect_pointer_size!(1);
+                    blake2_rfc::blake2b::blake2b(32, &[], message.as_ref())
+                };
+                let signature =
+                    <[u8; 65]>::try_from(expect_pointer_constant_size!(0, 65).as_ref()).unwrap();
+                let public_key =
+                    <[u8; 33]>::try_from(expect_pointer_constant_size!(2, 33).as_ref()).unwrap();
+                let signature = SignatureVerification {
+                    inner: SignatureVerificationInner::Ecdsa {
+                        signature,
+                        message_hash: <[u8; 32]>::try_from(message_hash.as_bytes()).unwrap(),
+                        public_key,
+                    },
+                };
+                self.inner.verify_or_defer(signature)
+            }
+            HostFunction::ext_crypto_ecdsa_verify_prehashed_version_1 => {
+                let success = {
+                    // TODO: to_owned() :-/ difficult-to-solve borrowck issues otherwise
+                    let signature = expect_pointer_constant_size!(0, 65).as_ref().to_owned();
+                    // TODO: to_owned() :-/ difficult-to-solve borrowck issues otherwise
+                    let message_hash = expect_pointer_constant_size!(1, 32).as_ref().to_owned();
+                    ecdsa_verify(
+                        &signature,
+                        &message_hash,
+                        expect_pointer_constant_size!(2, 33).as_ref(),
+                        false,
+                    )
+                };
+
+                HostVm::ReadyToRun(ReadyToRun {
+                    resume_value: Some(vm::WasmValue::I32(if success { 1 } else { 0 })),
+                    inner: self.inner,
+                })
+            }
             HostFunction::ext_crypto_secp256k1_ecdsa_recover_version_1
             | HostFunction::ext_crypto_secp256k1_ecdsa_recover_version_2 => {
                 // TODO: clean up
@@ -1077,18 +1875,48 @@ impl ReadyToRun {
                     iter::once(&result_encoded),
                 )
             }
+            HostFunction::ext_crypto_bls377_generate_version_1
+            | HostFunction::ext_crypto_bls381_generate_version_1
+            | HostFunction::ext_crypto_ecdsa_bls377_generate_version_1 => {
+                // Generating a key requires storing it in a keystore. The BLS signatures are
+                // verified by the runtime itself, and these functions are thus only ever called
+                // by runtimes that sign messages, which isn't possible here.
+                HostVm::Error {
+                    error: Error::KeystoreUnavailable {
+                        function: host_fn.name(),
+                    },
+                    prototype: self.inner.into_prototype(),
+                }
+            }
             HostFunction::ext_crypto_start_batch_verify_version_1 => {
+                if self.inner.batch_verification.is_some() {
+                    return HostVm::Error {
+                        error: Error::AlreadyBatchVerifying,
+                        prototype: self.inner.into_prototype(),
+                    };
+                }
+
+                self.inner.batch_verification = Some(Vec::new());
+
                 HostVm::ReadyToRun(ReadyToRun {
                     resume_value: None,
                     inner: self.inner,
                 })
             }
             HostFunction::ext_crypto_finish_batch_verify_version_1 => {
-                HostVm::ReadyToRun(ReadyToRun {
-                    // TODO: wrong! this is a dummy implementation meaning that all
-                    // signature verifications are always successful
-                    resume_value: Some(vm::WasmValue::I32(1)),
+                let signatures = match self.inner.batch_verification.take() {
+                    Some(s) => s,
+                    None => {
+                        return HostVm::Error {
+                            error: Error::NoBatchVerification,
+                            prototype: self.inner.into_prototype(),
+                        }
+                    }
+                };
+
+                HostVm::SignatureBatchVerify(SignatureBatchVerify {
                     inner: self.inner,
+                    signatures,
                 })
             }
             HostFunction::ext_hashing_keccak_256_version_1 => {
@@ -1200,31 +2028,630 @@ impl ReadyToRun {
                     inner: self.inner,
                 })
             }
-            HostFunction::ext_offchain_is_validator_version_1 => todo!(),
-            HostFunction::ext_offchain_submit_transaction_version_1 => todo!(),
-            HostFunction::ext_offchain_network_state_version_1 => todo!(),
-            HostFunction::ext_offchain_timestamp_version_1 => todo!(),
-            HostFunction::ext_offchain_sleep_until_version_1 => todo!(),
-            HostFunction::ext_offchain_random_seed_version_1 => todo!(),
-            HostFunction::ext_offchain_local_storage_set_version_1 => todo!(),
-            HostFunction::ext_offchain_local_storage_compare_and_set_version_1 => todo!(),
-            HostFunction::ext_offchain_local_storage_get_version_1 => todo!(),
-            HostFunction::ext_offchain_local_storage_clear_version_1 => todo!(),
-            HostFunction::ext_offchain_http_request_start_version_1 => todo!(),
-            HostFunction::ext_offchain_http_request_add_header_version_1 => todo!(),
-            HostFunction::ext_offchain_http_request_write_body_version_1 => todo!(),
-            HostFunction::ext_offchain_http_response_wait_version_1 => todo!(),
-            HostFunction::ext_offchain_http_response_headers_version_1 => todo!(),
-            HostFunction::ext_offchain_http_response_read_body_version_1 => todo!(),
-            HostFunction::ext_sandbox_instantiate_version_1 => todo!(),
-            HostFunction::ext_sandbox_invoke_version_1 => todo!(),
-            HostFunction::ext_sandbox_memory_new_version_1 => todo!(),
-            HostFunction::ext_sandbox_memory_get_version_1 => todo!(),
-            HostFunction::ext_sandbox_memory_set_version_1 => todo!(),
-            HostFunction::ext_sandbox_memory_teardown_version_1 => todo!(),
-            HostFunction::ext_sandbox_instance_teardown_version_1 => todo!(),
-            HostFunction::ext_sandbox_get_global_val_version_1 => todo!(),
-            HostFunction::ext_trie_blake2_256_root_version_1 => {
+            HostFunction::ext_offchain_is_validator_version_1 => {
+                // The node is never considered as a validator.
+                HostVm::ReadyToRun(ReadyToRun {
+                    resume_value: Some(vm::WasmValue::I32(0)),
+                    inner: self.inner,
+                })
+            }
+            HostFunction::ext_offchain_submit_transaction_version_1 => {
+                // Submitting transactions isn't supported. Returns a SCALE-encoded
+                // `Result::<(), ()>::Err(())`.
+                self.inner
+                    .alloc_write_and_return_pointer_size(host_fn.name(), iter::once(&[1]))
+            }
+            HostFunction::ext_offchain_network_state_version_1 => {
+                // The network state isn't exposed to the runtime. Returns a SCALE-encoded
+                // `Result::<_, ()>::Err(())`.
+                self.inner
+                    .alloc_write_and_return_pointer_size(host_fn.name(), iter::once(&[1]))
+            }
+            HostFunction::ext_offchain_timestamp_version_1 => {
+                HostVm::OffchainTimestamp(OffchainTimestamp { inner: self.inner })
+            }
+            HostFunction::ext_offchain_sleep_until_version_1 => {
+                // Sleeping is implemented as a no-op, as the runtime can't observe the difference
+                // with a sleep that has been interrupted.
+                HostVm::ReadyToRun(ReadyToRun {
+                    resume_value: None,
+                    inner: self.inner,
+                })
+            }
+            HostFunction::ext_offchain_random_seed_version_1 => {
+                HostVm::OffchainRandomSeed(OffchainRandomSeed { inner: self.inner })
+            }
+            HostFunction::ext_offchain_local_storage_set_version_1 => {
+                let kind = match OffchainStorageKind::from_u32(expect_u32!(0)) {
+                    Some(k) => k,
+                    None => {
+                        return HostVm::Error {
+                            error: Error::UnknownOffchainStorageKind {
+                                function: host_fn.name(),
+                            },
+                            prototype: self.inner.into_prototype(),
+                        }
+                    }
+                };
+                let (key_ptr, key_size) = expect_pointer_size_raw!(1);
+                let (value_ptr, value_size) = expect_pointer_size_raw!(2);
+                HostVm::ExternalOffchainLocalStorageSet(ExternalOffchainLocalStorageSet {
+                    kind,
+                    key_ptr,
+                    key_size,
+                    value: Some((value_ptr, value_size)),
+                    inner: self.inner,
+                })
+            }
+            HostFunction::ext_offchain_local_storage_compare_and_set_version_1 => {
+                let kind = match OffchainStorageKind::from_u32(expect_u32!(0)) {
+                    Some(k) => k,
+                    None => {
+                        return HostVm::Error {
+                            error: Error::UnknownOffchainStorageKind {
+                                function: host_fn.name(),
+                            },
+                            prototype: self.inner.into_prototype(),
+                        }
+                    }
+                };
+                let (key_ptr, key_size) = expect_pointer_size_raw!(1);
+                let old_value = Option::<Vec<u8>>::decode_all(expect_pointer_size!(2).as_ref());
+                let old_value = match old_value {
+                    Ok(v) => v,
+                    Err(err) => {
+                        return HostVm::Error {
+                            error: Error::ParamDecodeError(err),
+                            prototype: self.inner.into_prototype(),
+                        };
+                    }
+                };
+                let (value_ptr, value_size) = expect_pointer_size_raw!(3);
+                HostVm::ExternalOffchainLocalStorageCompareAndSet(
+                    ExternalOffchainLocalStorageCompareAndSet {
+                        kind,
+                        key_ptr,
+                        key_size,
+                        old_value,
+                        value_ptr,
+                        value_size,
+                        inner: self.inner,
+                    },
+                )
+            }
+            HostFunction::ext_offchain_local_storage_get_version_1 => {
+                let kind = match OffchainStorageKind::from_u32(expect_u32!(0)) {
+                    Some(k) => k,
+                    None => {
+                        return HostVm::Error {
+                            error: Error::UnknownOffchainStorageKind {
+                                function: host_fn.name(),
+                            },
+                            prototype: self.inner.into_prototype(),
+                        }
+                    }
+                };
+                let (key_ptr, key_size) = expect_pointer_size_raw!(1);
+                HostVm::ExternalOffchainLocalStorageGet(ExternalOffchainLocalStorageGet {
+                    kind,
+                    key_ptr,
+                    key_size,
+                    inner: self.inner,
+                })
+            }
+            HostFunction::ext_offchain_local_storage_clear_version_1 => {
+                let kind = match OffchainStorageKind::from_u32(expect_u32!(0)) {
+                    Some(k) => k,
+                    None => {
+                        return HostVm::Error {
+                            error: Error::UnknownOffchainStorageKind {
+                                function: host_fn.name(),
+                            },
+                            prototype: self.inner.into_prototype(),
+                        }
+                    }
+                };
+                let (key_ptr, key_size) = expect_pointer_size_raw!(1);
+                HostVm::ExternalOffchainLocalStorageSet(ExternalOffchainLocalStorageSet {
+                    kind,
+                    key_ptr,
+                    key_size,
+                    value: None,
+                    inner: self.inner,
+                })
+            }
+            // HTTP requests aren't supported. Since no request can ever be started, all the
+            // functions below report that the request identifier is invalid.
+            HostFunction::ext_offchain_http_request_start_version_1 => {
+                // SCALE-encoded `Result::<HttpRequestId, ()>::Err(())`.
+                self.inner
+                    .alloc_write_and_return_pointer_size(host_fn.name(), iter::once(&[1]))
+            }
+            HostFunction::ext_offchain_http_request_add_header_version_1 => {
+                // SCALE-encoded `Result::<(), ()>::Err(())`.
+                self.inner
+                    .alloc_write_and_return_pointer_size(host_fn.name(), iter::once(&[1]))
+            }
+            HostFunction::ext_offchain_http_request_write_body_version_1
+            | HostFunction::ext_offchain_http_response_read_body_version_1 => {
+                // SCALE-encoded `Result::<_, HttpError>::Err(HttpError::Invalid)`.
+                self.inner
+                    .alloc_write_and_return_pointer_size(host_fn.name(), iter::once(&[1, 2]))
+            }
+            HostFunction::ext_offchain_http_response_wait_version_1 => {
+                let ids = Vec::<u16>::decode_all(expect_pointer_size!(0).as_ref());
+                let num_ids = match ids {
+                    Ok(ids) => ids.len(),
+                    Err(err) => {
+                        return HostVm::Error {
+                            error: Error::ParamDecodeError(err),
+                            prototype: self.inner.into_prototype(),
+                        };
+                    }
+                };
+
+                // SCALE-encoded `Vec<HttpRequestStatus>` where each element is
+                // `HttpRequestStatus::Invalid`.
+                self.inner.alloc_write_and_return_pointer_size(
+                    host_fn.name(),
+                    iter::once(either::Left(util::encode_scale_compact_usize(num_ids)))
+                        .chain(iter::once(either::Right(vec![2; num_ids]))),
+                )
+            }
+            HostFunction::ext_offchain_http_response_headers_version_1 => {
+                // SCALE-encoded empty `Vec`.
+                self.inner
+                    .alloc_write_and_return_pointer_size(host_fn.name(), iter::once(&[0]))
+            }
+            HostFunction::ext_sandbox_instantiate_version_1 => {
+                // Calling the functions that the sandboxed module imports would require calling
+                // back the dispatch thunk of the runtime while the runtime is itself in the
+                // middle of a host function call, which isn't supported. As such, only modules
+                // that don't import anything other than `env.memory` can be instantiated.
+                let code = expect_pointer_size!(1).as_ref().to_vec();
+                let environment =
+                    Vec::<SandboxEnvironmentEntry>::decode_all(expect_pointer_size!(2).as_ref());
+
+                let ret = match environment {
+                    Ok(environment) => {
+                        let memory_index =
+                            environment
+                                .into_iter()
+                                .find_map(|entry| match entry.entity {
+                                    SandboxExternEntity::Memory(index)
+                                        if entry.module_name == b"env"
+                                            && entry.field_name == b"memory" =>
+                                    {
+                                        Some(index)
+                                    }
+                                    _ => None,
+                                });
+
+                        self.inner
+                            .sandbox_instantiate(&code, memory_index)
+                            .unwrap_or(SANDBOX_ERR_MODULE)
+                    }
+                    Err(_) => SANDBOX_ERR_MODULE,
+                };
+
+                HostVm::ReadyToRun(ReadyToRun {
+                    resume_value: Some(vm::WasmValue::I32(i32::from_ne_bytes(ret.to_ne_bytes()))),
+                    inner: self.inner,
+                })
+            }
+            HostFunction::ext_sandbox_invoke_version_1 => {
+                let instance_index = expect_u32!(0);
+                let function_name =
+                    str::from_utf8(expect_pointer_size!(1).as_ref()).map(|s| s.to_owned());
+                let function_name = match function_name {
+                    Ok(n) => n,
+                    Err(error) => {
+                        return HostVm::Error {
+                            error: Error::Utf8Error {
+                                function: host_fn.name(),
+                                param_num: 1,
+                                error,
+                            },
+                            prototype: self.inner.into_prototype(),
+                        };
+                    }
+                };
+                let arguments = Vec::<SandboxValue>::decode_all(expect_pointer_size!(2).as_ref());
+                let arguments = match arguments {
+                    Ok(a) => a,
+                    Err(err) => {
+                        return HostVm::Error {
+                            error: Error::ParamDecodeError(err),
+                            prototype: self.inner.into_prototype(),
+                        };
+                    }
+                };
+                let return_value_ptr = expect_u32!(3);
+                let return_value_len = expect_u32!(4);
+
+                let instance = match self
+                    .inner
+                    .sandbox_instances
+                    .get_mut(usize::try_from(instance_index).unwrap_or(usize::max_value()))
+                    .and_then(|i| i.take())
+                {
+                    Some(i) => i,
+                    None => {
+                        return HostVm::Error {
+                            error: Error::InvalidSandboxIndex {
+                                function: host_fn.name(),
+                                index: instance_index,
+                            },
+                            prototype: self.inner.into_prototype(),
+                        };
+                    }
+                };
+
+                // Floating point numbers aren't supported by the virtual machine.
+                let arguments = arguments
+                    .into_iter()
+                    .map(|arg| match arg {
+                        SandboxValue::I32(v) => Some(vm::WasmValue::I32(v)),
+                        SandboxValue::I64(v) => Some(vm::WasmValue::I64(v)),
+                        SandboxValue::F32(_) | SandboxValue::F64(_) => None,
+                    })
+                    .collect::<Option<Vec<_>>>();
+
+                let mut out_of_fuel = false;
+                let (instance, outcome) = match arguments {
+                    Some(arguments) => match instance.start(&function_name, &arguments) {
+                        Ok(mut vm) => {
+                            // The only function that the sandboxed module can call is the
+                            // metering function, which indicates the number of instructions
+                            // about to be executed. The execution is stopped if the total
+                            // exceeds `SANDBOX_MAX_INSTRUCTIONS`, and each instruction consumes
+                            // one unit of fuel.
+                            let mut remaining_instructions = SANDBOX_MAX_INSTRUCTIONS;
+                            let outcome = loop {
+                                let num_instructions = match vm.run(None) {
+                                    Ok(vm::ExecOutcome::Finished {
+                                        return_value: Ok(value),
+                                    }) => break Some(value),
+                                    Ok(vm::ExecOutcome::Interrupted { params, .. }) => match params
+                                        .first()
+                                        .and_then(|p| p.into_i32())
+                                        .and_then(|n| u64::try_from(n).ok())
+                                    {
+                                        Some(n) => n,
+                                        None => break None,
+                                    },
+                                    _ => break None,
+                                };
+
+                                remaining_instructions =
+                                    match remaining_instructions.checked_sub(num_instructions) {
+                                        Some(r) => r,
+                                        None => break None,
+                                    };
+
+                                if let Some(remaining_fuel) = &mut self.inner.remaining_fuel {
+                                    match remaining_fuel.checked_sub(num_instructions) {
+                                        Some(r) => *remaining_fuel = r,
+                                        None => {
+                                            out_of_fuel = true;
+                                            break None;
+                                        }
+                                    }
+                                }
+                            };
+                            (vm.into_prototype(), outcome)
+                        }
+                        Err((_, instance)) => (instance, None),
+                    },
+                    None => (instance, None),
+                };
+
+                self.inner.sandbox_instances[usize::try_from(instance_index).unwrap()] =
+                    Some(instance);
+
+                if out_of_fuel {
+                    return HostVm::Error {
+                        prototype: self.inner.into_prototype(),
+                        error: Error::OutOfFuel,
+                    };
+                }
+
+                // SCALE-encoded `ReturnValue`.
+                let ret = match outcome {
+                    Some(value) => {
+                        let encoded: Vec<u8> = match value {
+                            None => vec![0],
+                            Some(vm::WasmValue::I32(v)) => {
+                                iter::once(1).chain(SandboxValue::I32(v).encode()).collect()
+                            }
+                            Some(vm::WasmValue::I64(v)) => {
+                                iter::once(1).chain(SandboxValue::I64(v).encode()).collect()
+                            }
+                        };
+
+                        if u32::try_from(encoded.len()).unwrap() > return_value_len {
+                            SANDBOX_ERR_OUT_OF_BOUNDS
+                        } else if self
+                            .inner
+                            .vm
+                            .write_memory(return_value_ptr, &encoded)
+                            .is_err()
+                        {
+                            SANDBOX_ERR_OUT_OF_BOUNDS
+                        } else {
+                            SANDBOX_ERR_OK
+                        }
+                    }
+                    None => SANDBOX_ERR_EXECUTION,
+                };
+
+                HostVm::ReadyToRun(ReadyToRun {
+                    resume_value: Some(vm::WasmValue::I32(i32::from_ne_bytes(ret.to_ne_bytes()))),
+                    inner: self.inner,
+                })
+            }
+            HostFunction::ext_sandbox_memory_new_version_1 => {
+                let initial = expect_u32!(0);
+                let maximum = expect_u32!(1);
+
+                // A `maximum` equal to `u32::max_value()` means that there is no maximum.
+                if initial > SANDBOX_MEMORY_MAX_PAGES
+                    || (maximum != u32::max_value() && initial > maximum)
+                {
+                    return HostVm::Error {
+                        error: Error::InvalidSandboxMemorySize { initial, maximum },
+                        prototype: self.inner.into_prototype(),
+                    };
+                }
+
+                let size = usize::try_from(initial).unwrap() * 64 * 1024;
+                if let Some(max_memory_size) = self.inner.max_memory_size {
+                    if self.inner.total_memory_size() + u64::try_from(size).unwrap()
+                        > u64::from(max_memory_size)
+                    {
+                        return HostVm::Error {
+                            error: Error::MemoryLimitExceeded {
+                                limit: max_memory_size,
+                            },
+                            prototype: self.inner.into_prototype(),
+                        };
+                    }
+                }
+
+                self.inner
+                    .sandbox_memories
+                    .push(Some(SandboxMemory::Standalone(vec![0; size])));
+                let index = u32::try_from(self.inner.sandbox_memories.len() - 1).unwrap();
+
+                HostVm::ReadyToRun(ReadyToRun {
+                    resume_value: Some(vm::WasmValue::I32(i32::from_ne_bytes(index.to_ne_bytes()))),
+                    inner: self.inner,
+                })
+            }
+            HostFunction::ext_sandbox_memory_get_version_1 => {
+                let memory_index = expect_u32!(0);
+                let offset = expect_u32!(1);
+                let buffer_ptr = expect_u32!(2);
+                let buffer_len = expect_u32!(3);
+
+                let memory = match self
+                    .inner
+                    .sandbox_memories
+                    .get(usize::try_from(memory_index).unwrap_or(usize::max_value()))
+                    .and_then(|m| m.as_ref())
+                {
+                    Some(m) => m,
+                    None => {
+                        return HostVm::Error {
+                            error: Error::InvalidSandboxIndex {
+                                function: host_fn.name(),
+                                index: memory_index,
+                            },
+                            prototype: self.inner.into_prototype(),
+                        };
+                    }
+                };
+
+                let ret = match memory {
+                    SandboxMemory::Standalone(memory) => match usize::try_from(offset)
+                        .ok()
+                        .and_then(|start| {
+                            Some((start, start.checked_add(usize::try_from(buffer_len).ok()?)?))
+                        })
+                        .and_then(|(start, end)| memory.get(start..end))
+                    {
+                        Some(data) => match self.inner.vm.write_memory(buffer_ptr, data) {
+                            Ok(()) => SANDBOX_ERR_OK,
+                            Err(vm::OutOfBoundsError) => SANDBOX_ERR_OUT_OF_BOUNDS,
+                        },
+                        None => SANDBOX_ERR_OUT_OF_BOUNDS,
+                    },
+                    SandboxMemory::Instance(instance) => {
+                        let instance = self.inner.sandbox_instances[*instance].as_ref().unwrap();
+                        match instance.read_memory(offset, buffer_len) {
+                            Ok(data) => match self.inner.vm.write_memory(buffer_ptr, data.as_ref())
+                            {
+                                Ok(()) => SANDBOX_ERR_OK,
+                                Err(vm::OutOfBoundsError) => SANDBOX_ERR_OUT_OF_BOUNDS,
+                            },
+                            Err(vm::OutOfBoundsError) => SANDBOX_ERR_OUT_OF_BOUNDS,
+                        }
+                    }
+                };
+
+                HostVm::ReadyToRun(ReadyToRun {
+                    resume_value: Some(vm::WasmValue::I32(i32::from_ne_bytes(ret.to_ne_bytes()))),
+                    inner: self.inner,
+                })
+            }
+            HostFunction::ext_sandbox_memory_set_version_1 => {
+                let memory_index = expect_u32!(0);
+                let offset = expect_u32!(1);
+                let value_ptr = expect_u32!(2);
+                let value_len = expect_u32!(3);
+
+                let value = match self.inner.vm.read_memory(value_ptr, value_len) {
+                    Ok(v) => Some(v.as_ref().to_vec()),
+                    Err(vm::OutOfBoundsError) => None,
+                };
+
+                let memory = match self
+                    .inner
+                    .sandbox_memories
+                    .get_mut(usize::try_from(memory_index).unwrap_or(usize::max_value()))
+                    .and_then(|m| m.as_mut())
+                {
+                    Some(m) => m,
+                    None => {
+                        return HostVm::Error {
+                            error: Error::InvalidSandboxIndex {
+                                function: host_fn.name(),
+                                index: memory_index,
+                            },
+                            prototype: self.inner.into_prototype(),
+                        };
+                    }
+                };
+
+                let ret = match (memory, &value) {
+                    (SandboxMemory::Standalone(memory), Some(value)) => {
+                        let destination = usize::try_from(offset).ok().and_then(|start| {
+                            memory.get_mut(start..start.checked_add(value.len())?)
+                        });
+                        match destination {
+                            Some(destination) => {
+                                destination.copy_from_slice(value);
+                                SANDBOX_ERR_OK
+                            }
+                            None => SANDBOX_ERR_OUT_OF_BOUNDS,
+                        }
+                    }
+                    (SandboxMemory::Instance(instance), Some(value)) => {
+                        let instance = self.inner.sandbox_instances[*instance].as_mut().unwrap();
+                        match instance.write_memory(offset, value) {
+                            Ok(()) => SANDBOX_ERR_OK,
+                            Err(vm::OutOfBoundsError) => SANDBOX_ERR_OUT_OF_BOUNDS,
+                        }
+                    }
+                    (_, None) => SANDBOX_ERR_OUT_OF_BOUNDS,
+                };
+
+                HostVm::ReadyToRun(ReadyToRun {
+                    resume_value: Some(vm::WasmValue::I32(i32::from_ne_bytes(ret.to_ne_bytes()))),
+                    inner: self.inner,
+                })
+            }
+            HostFunction::ext_sandbox_memory_teardown_version_1 => {
+                let memory_index = expect_u32!(0);
+                match self
+                    .inner
+                    .sandbox_memories
+                    .get_mut(usize::try_from(memory_index).unwrap_or(usize::max_value()))
+                    .and_then(|m| m.take())
+                {
+                    Some(_) => HostVm::ReadyToRun(ReadyToRun {
+                        resume_value: None,
+                        inner: self.inner,
+                    }),
+                    None => HostVm::Error {
+                        error: Error::InvalidSandboxIndex {
+                            function: host_fn.name(),
+                            index: memory_index,
+                        },
+                        prototype: self.inner.into_prototype(),
+                    },
+                }
+            }
+            HostFunction::ext_sandbox_instance_teardown_version_1 => {
+                let instance_index = expect_u32!(0);
+                match self
+                    .inner
+                    .sandbox_instances
+                    .get_mut(usize::try_from(instance_index).unwrap_or(usize::max_value()))
+                    .and_then(|i| i.take())
+                {
+                    Some(instance) => {
+                        // The memory imported by the instance, if any, is given back its content.
+                        for memory in &mut self.inner.sandbox_memories {
+                            if matches!(memory, Some(SandboxMemory::Instance(i)) if u32::try_from(*i) == Ok(instance_index))
+                            {
+                                let content = instance
+                                    .read_memory(0, instance.memory_size())
+                                    .unwrap()
+                                    .as_ref()
+                                    .to_vec();
+                                *memory = Some(SandboxMemory::Standalone(content));
+                            }
+                        }
+
+                        HostVm::ReadyToRun(ReadyToRun {
+                            resume_value: None,
+                            inner: self.inner,
+                        })
+                    }
+                    None => HostVm::Error {
+                        error: Error::InvalidSandboxIndex {
+                            function: host_fn.name(),
+                            index: instance_index,
+                        },
+                        prototype: self.inner.into_prototype(),
+                    },
+                }
+            }
+            HostFunction::ext_sandbox_get_global_val_version_1 => {
+                let instance_index = expect_u32!(0);
+                let global_name =
+                    str::from_utf8(expect_pointer_size!(1).as_ref()).map(|s| s.to_owned());
+                let global_name = match global_name {
+                    Ok(n) => n,
+                    Err(error) => {
+                        return HostVm::Error {
+                            error: Error::Utf8Error {
+                                function: host_fn.name(),
+                                param_num: 1,
+                                error,
+                            },
+                            prototype: self.inner.into_prototype(),
+                        };
+                    }
+                };
+
+                let instance = match self
+                    .inner
+                    .sandbox_instances
+                    .get_mut(usize::try_from(instance_index).unwrap_or(usize::max_value()))
+                    .and_then(|i| i.as_mut())
+                {
+                    Some(i) => i,
+                    None => {
+                        return HostVm::Error {
+                            error: Error::InvalidSandboxIndex {
+                                function: host_fn.name(),
+                                index: instance_index,
+                            },
+                            prototype: self.inner.into_prototype(),
+                        };
+                    }
+                };
+
+                // SCALE-encoded `Option<Value>`. Only `i32` globals are supported.
+                let encoded: Vec<u8> = match instance.global_value(&global_name) {
+                    Ok(value) => iter::once(1)
+                        .chain(SandboxValue::I32(i32::from_ne_bytes(value.to_ne_bytes())).encode())
+                        .collect(),
+                    Err(_) => vec![0],
+                };
+
+                self.inner
+                    .alloc_write_and_return_pointer_size(host_fn.name(), iter::once(encoded))
+            }
+            HostFunction::ext_trie_blake2_256_root_version_1
+            | HostFunction::ext_trie_blake2_256_root_version_2 => {
+                let state_trie_version = match host_fn {
+                    HostFunction::ext_trie_blake2_256_root_version_1 => trie::TrieEntryVersion::V0,
+                    _ => expect_state_version!(1),
+                };
+
                 let decode_result =
                     Vec::<(Vec<u8>, Vec<u8>)>::decode_all(expect_pointer_size!(0).as_ref());
 
@@ -1243,12 +2670,20 @@ impl ReadyToRun {
                 for (key, value) in elements {
                     trie.insert(&key, value);
                 }
-                let out = trie.root_merkle_value(None);
+                let out = trie.root_merkle_value(state_trie_version, None);
 
                 self.inner
                     .alloc_write_and_return_pointer(host_fn.name(), iter::once(&out))
             }
-            HostFunction::ext_trie_blake2_256_ordered_root_version_1 => {
+            HostFunction::ext_trie_blake2_256_ordered_root_version_1
+            | HostFunction::ext_trie_blake2_256_ordered_root_version_2 => {
+                let state_trie_version = match host_fn {
+                    HostFunction::ext_trie_blake2_256_ordered_root_version_1 => {
+                        trie::TrieEntryVersion::V0
+                    }
+                    _ => expect_state_version!(1),
+                };
+
                 let decode_result = Vec::<Vec<u8>>::decode_all(expect_pointer_size!(0).as_ref());
 
                 let elements = match decode_result {
@@ -1261,13 +2696,7 @@ impl ReadyToRun {
                     }
                 };
 
-                // TODO: optimize this
-                let mut trie = crate::trie::Trie::new();
-                for (idx, value) in elements.into_iter().enumerate() {
-                    let key = util::encode_scale_compact_usize(idx);
-                    trie.insert(key.as_ref(), value);
-                }
-                let out = trie.root_merkle_value(None);
+                let out = trie::ordered_root(state_trie_version, elements.iter());
 
                 self.inner
                     .alloc_write_and_return_pointer(host_fn.name(), iter::once(&out))
@@ -1440,6 +2869,13 @@ impl Finished {
             .unwrap()
     }
 
+    /// Returns the calls to host functions made by the Wasm code, in chronological order.
+    ///
+    /// Always empty if [`HostVmPrototype::set_trace_host_calls`] hasn't been called with `true`.
+    pub fn host_calls(&self) -> &[HostCall] {
+        self.inner.host_calls.as_deref().unwrap_or(&[])
+    }
+
     /// Turns the virtual machine back into a prototype.
     pub fn into_prototype(self) -> HostVmPrototype {
         self.inner.into_prototype()
@@ -1460,7 +2896,8 @@ pub struct ExternalStorageGet {
     /// [`Inner::registered_functions`].
     calling: usize,
 
-    /// Used only for the `ext_storage_read_version_1` function. Stores the pointer where the
+    /// Used only for the `ext_storage_read_version_1` and
+    /// `ext_default_child_storage_read_version_1` functions. Stores the pointer where the
     /// output should be stored.
     value_out_ptr: Option<u32>,
 
@@ -1468,6 +2905,9 @@ pub struct ExternalStorageGet {
     key_ptr: u32,
     /// Size of the key whose value must be loaded. Guaranteed to be in range.
     key_size: u32,
+    /// Pointer and size of the key of the child trie the operation concerns, or `None` for the
+    /// main trie. Guaranteed to be in range.
+    child_trie_ptr_size: Option<(u32, u32)>,
     /// Offset within the value that the Wasm VM requires.
     offset: u32,
     /// Maximum size that the Wasm VM would accept.
@@ -1483,6 +2923,20 @@ impl ExternalStorageGet {
             .unwrap()
     }
 
+    /// If `Some`, the value must be read from the child trie whose key is returned, rather than
+    /// from the main trie.
+    ///
+    /// The returned key doesn't include the `:child_storage:default:` prefix. In other words, the
+    /// root of this child trie can be found in the main trie under the key
+    /// `:child_storage:default:` followed with the returned value.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        if let Some((ptr, size)) = self.child_trie_ptr_size {
+            Some(self.inner.vm.read_memory(ptr, size).unwrap())
+        } else {
+            None
+        }
+    }
+
     /// Offset within the value that is requested.
     pub fn offset(&self) -> u32 {
         self.offset
@@ -1556,9 +3010,13 @@ impl ExternalStorageGet {
         mut self,
         value: Option<(impl Iterator<Item = impl AsRef<[u8]>> + Clone, usize)>,
     ) -> HostVm {
-        let host_fn = self.inner.registered_functions[self.calling];
+        let host_fn = match self.inner.registered_functions[self.calling] {
+            FunctionImport::Resolved(f) => f,
+            FunctionImport::Unresolved { .. } | FunctionImport::Metering => unreachable!(),
+        };
         match host_fn {
-            HostFunction::ext_storage_get_version_1 => {
+            HostFunction::ext_storage_get_version_1
+            | HostFunction::ext_default_child_storage_get_version_1 => {
                 if let Some((value, value_total_len)) = value {
                     // Writing `Some(value)`.
                     debug_assert_eq!(
@@ -1579,7 +3037,8 @@ impl ExternalStorageGet {
                         .alloc_write_and_return_pointer_size(host_fn.name(), iter::once(&[0]))
                 }
             }
-            HostFunction::ext_storage_read_version_1 => {
+            HostFunction::ext_storage_read_version_1
+            | HostFunction::ext_default_child_storage_read_version_1 => {
                 let outcome = if let Some((value, value_total_len)) = value {
                     let mut remaining_max_allowed = usize::try_from(self.max_size).unwrap();
                     let mut offset = self.value_out_ptr.unwrap();
@@ -1604,14 +3063,17 @@ impl ExternalStorageGet {
                     iter::once(&outcome_encoded),
                 );
             }
-            HostFunction::ext_storage_exists_version_1 => HostVm::ReadyToRun(ReadyToRun {
-                inner: self.inner,
-                resume_value: Some(if value.is_some() {
-                    vm::WasmValue::I32(1)
-                } else {
-                    vm::WasmValue::I32(0)
-                }),
-            }),
+            HostFunction::ext_storage_exists_version_1
+            | HostFunction::ext_default_child_storage_exists_version_1 => {
+                HostVm::ReadyToRun(ReadyToRun {
+                    inner: self.inner,
+                    resume_value: Some(if value.is_some() {
+                        vm::WasmValue::I32(1)
+                    } else {
+                        vm::WasmValue::I32(0)
+                    }),
+                })
+            }
             _ => unreachable!(),
         }
     }
@@ -1631,6 +3093,9 @@ pub struct ExternalStorageSet {
     key_ptr: u32,
     /// Size of the key whose value must be set. Guaranteed to be in range.
     key_size: u32,
+    /// Pointer and size of the key of the child trie the operation concerns, or `None` for the
+    /// main trie. Guaranteed to be in range.
+    child_trie_ptr_size: Option<(u32, u32)>,
 
     /// Pointer and size of the value to set. `None` for clearing. Guaranteed to be in range.
     value: Option<(u32, u32)>,
@@ -1645,6 +3110,16 @@ impl ExternalStorageSet {
             .unwrap()
     }
 
+    /// If `Some`, the value must be set in the given child trie rather than in the main trie.
+    /// See [`ExternalStorageGet::child_trie`].
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        if let Some((ptr, size)) = self.child_trie_ptr_size {
+            Some(self.inner.vm.read_memory(ptr, size).unwrap())
+        } else {
+            None
+        }
+    }
+
     /// Returns the value to set.
     ///
     /// If `None` is returned, the key should be removed from the storage entirely.
@@ -1747,6 +3222,14 @@ pub struct ExternalStorageClearPrefix {
     /// Size of the prefix to remove. Guaranteed to be in range.
     prefix_size: u32,
 
+    /// Pointer and size of the key of the child trie the operation concerns, or `None` for the
+    /// main trie. Guaranteed to be in range.
+    child_trie_ptr_size: Option<(u32, u32)>,
+
+    /// Function currently being called by the Wasm code. Refers to an index within
+    /// [`Inner::registered_functions`].
+    calling: usize,
+
     /// Maximum number of keys to remove.
     max_keys_to_remove: Option<u32>,
 }
@@ -1760,17 +3243,64 @@ impl ExternalStorageClearPrefix {
             .unwrap()
     }
 
+    /// If `Some`, the keys must be removed from the given child trie rather than from the main
+    /// trie. See [`ExternalStorageGet::child_trie`].
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        if let Some((ptr, size)) = self.child_trie_ptr_size {
+            Some(self.inner.vm.read_memory(ptr, size).unwrap())
+        } else {
+            None
+        }
+    }
+
     /// Returns the maximum number of keys to remove. `None` means "infinity".
     pub fn max_keys_to_remove(&self) -> Option<u32> {
         self.max_keys_to_remove
     }
 
     /// Resumes execution after having cleared the values.
-    pub fn resume(self) -> HostVm {
-        HostVm::ReadyToRun(ReadyToRun {
-            inner: self.inner,
-            resume_value: None,
-        })
+    ///
+    /// Must be passed the number of keys that have been removed, and whether some keys with
+    /// the given prefix remain in the storage because of
+    /// [`ExternalStorageClearPrefix::max_keys_to_remove`].
+    pub fn resume(self, num_cleared: u32, some_keys_remain: bool) -> HostVm {
+        let host_fn = match self.inner.registered_functions[self.calling] {
+            FunctionImport::Resolved(f) => f,
+            FunctionImport::Unresolved { .. } | FunctionImport::Metering => unreachable!(),
+        };
+
+        match host_fn {
+            HostFunction::ext_storage_clear_prefix_version_1
+            | HostFunction::ext_default_child_storage_clear_prefix_version_1
+            | HostFunction::ext_default_child_storage_storage_kill_version_1 => {
+                HostVm::ReadyToRun(ReadyToRun {
+                    inner: self.inner,
+                    resume_value: None,
+                })
+            }
+            HostFunction::ext_default_child_storage_storage_kill_version_2 => {
+                HostVm::ReadyToRun(ReadyToRun {
+                    inner: self.inner,
+                    resume_value: Some(if some_keys_remain {
+                        vm::WasmValue::I32(0)
+                    } else {
+                        vm::WasmValue::I32(1)
+                    }),
+                })
+            }
+            HostFunction::ext_storage_clear_prefix_version_2
+            | HostFunction::ext_default_child_storage_clear_prefix_version_2
+            | HostFunction::ext_default_child_storage_storage_kill_version_3 => {
+                // Writing a SCALE-encoded `KillStorageResult`, whose variants are
+                // `AllRemoved(u32)` and `SomeRemaining(u32)`.
+                let mut outcome = [0; 5];
+                outcome[0] = if some_keys_remain { 1 } else { 0 };
+                outcome[1..].copy_from_slice(&num_cleared.to_le_bytes());
+                self.inner
+                    .alloc_write_and_return_pointer_size(host_fn.name(), iter::once(&outcome))
+            }
+            _ => unreachable!(),
+        }
     }
 }
 
@@ -1783,15 +3313,47 @@ impl fmt::Debug for ExternalStorageClearPrefix {
 /// Must provide the trie root hash of the storage.
 pub struct ExternalStorageRoot {
     inner: Inner,
+
+    /// Function currently being called by the Wasm code. Refers to an index within
+    /// [`Inner::registered_functions`].
+    calling: usize,
+
+    /// Pointer and size of the key of the child trie the operation concerns, or `None` for the
+    /// main trie. Guaranteed to be in range.
+    child_trie_ptr_size: Option<(u32, u32)>,
+
+    /// Version of the trie entries with which to calculate the root.
+    state_trie_version: trie::TrieEntryVersion,
 }
 
 impl ExternalStorageRoot {
+    /// If `Some`, the root hash of the given child trie must be provided rather than the one of
+    /// the main trie. See [`ExternalStorageGet::child_trie`].
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        if let Some((ptr, size)) = self.child_trie_ptr_size {
+            Some(self.inner.vm.read_memory(ptr, size).unwrap())
+        } else {
+            None
+        }
+    }
+
+    /// Returns the version of the trie entries with which the root must be calculated.
+    ///
+    /// Each storage value must be encoded in the trie according to this version when
+    /// calculating the root.
+    pub fn state_trie_version(&self) -> trie::TrieEntryVersion {
+        self.state_trie_version
+    }
+
     /// Writes the trie root hash to the Wasm VM and prepares it for resume.
     pub fn resume(self, hash: &[u8; 32]) -> HostVm {
-        self.inner.alloc_write_and_return_pointer_size(
-            HostFunction::ext_storage_root_version_1.name(),
-            iter::once(hash),
-        )
+        let host_fn = match self.inner.registered_functions[self.calling] {
+            FunctionImport::Resolved(f) => f,
+            FunctionImport::Unresolved { .. } | FunctionImport::Metering => unreachable!(),
+        };
+
+        self.inner
+            .alloc_write_and_return_pointer_size(host_fn.name(), iter::once(hash))
     }
 }
 
@@ -1840,6 +3402,9 @@ pub struct ExternalStorageNextKey {
     key_ptr: u32,
     /// Size of the key whose value must be set. Guaranteed to be in range.
     key_size: u32,
+    /// Pointer and size of the key of the child trie the operation concerns, or `None` for the
+    /// main trie. Guaranteed to be in range.
+    child_trie_ptr_size: Option<(u32, u32)>,
 }
 
 impl ExternalStorageNextKey {
@@ -1851,24 +3416,38 @@ impl ExternalStorageNextKey {
             .unwrap()
     }
 
+    /// If `Some`, the follow-up key must be searched in the given child trie rather than in the
+    /// main trie. See [`ExternalStorageGet::child_trie`].
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        if let Some((ptr, size)) = self.child_trie_ptr_size {
+            Some(self.inner.vm.read_memory(ptr, size).unwrap())
+        } else {
+            None
+        }
+    }
+
     /// Writes the follow-up key in the Wasm VM memory and prepares it for execution.
     ///
     /// Must be passed `None` if the key is the last one in the storage.
     pub fn resume(self, follow_up: Option<&[u8]>) -> HostVm {
+        let function_name = if self.child_trie_ptr_size.is_some() {
+            HostFunction::ext_default_child_storage_next_key_version_1.name()
+        } else {
+            HostFunction::ext_storage_next_key_version_1.name()
+        };
+
         if let Some(follow_up) = follow_up {
             let value_len_enc = util::encode_scale_compact_usize(follow_up.len());
             self.inner.alloc_write_and_return_pointer_size(
-                HostFunction::ext_storage_next_key_version_1.name(),
+                function_name,
                 iter::once(&[1][..])
                     .chain(iter::once(value_len_enc.as_ref()))
                     .chain(iter::once(follow_up)),
             )
         } else {
             // Write a SCALE-encoded `None`.
-            self.inner.alloc_write_and_return_pointer_size(
-                HostFunction::ext_storage_next_key_version_1.name(),
-                iter::once(&[0]),
-            )
+            self.inner
+                .alloc_write_and_return_pointer_size(function_name, iter::once(&[0]))
         }
     }
 }
@@ -1972,9 +3551,371 @@ impl ExternalOffchainStorageSet {
     }
 }
 
-impl fmt::Debug for ExternalOffchainStorageSet {
+impl fmt::Debug for ExternalOffchainStorageSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("ExternalOffchainStorageSet").finish()
+    }
+}
+
+/// Information about a call to a host function. See [`Finished::host_calls`].
+#[derive(Debug, Clone)]
+pub struct HostCall {
+    /// Name of the host function, such as `ext_storage_get_version_1`.
+    pub function: &'static str,
+    /// For each parameter of the host function, the number of bytes that it designates. This is
+    /// the size of the buffer for parameters that point to a buffer, and the size of the integer
+    /// otherwise.
+    ///
+    /// Contains 0 for parameters that haven't been read through the usual means, for example
+    /// because the host function has returned an error before reading them.
+    pub params_sizes: Vec<u32>,
+}
+
+/// Runtime has finished a batch verification of signatures.
+///
+/// Runtimes that verify many signatures, such as when executing blocks containing many
+/// transactions, group the verifications together using the
+/// `ext_crypto_start_batch_verify_version_1` and `ext_crypto_finish_batch_verify_version_1`
+/// host functions. The signatures of the batch are verified all at once, which makes it
+/// possible to spread the verifications over multiple threads.
+pub struct SignatureBatchVerify {
+    inner: Inner,
+    signatures: Vec<SignatureVerification>,
+}
+
+impl SignatureBatchVerify {
+    /// Returns the signatures of the batch.
+    pub fn signatures(&self) -> &[SignatureVerification] {
+        &self.signatures
+    }
+
+    /// Resumes execution after having verified the signatures.
+    ///
+    /// `all_valid` must be `true` if and only if [`SignatureVerification::verify`] returns
+    /// `true` for all the signatures of the batch.
+    pub fn resume(self, all_valid: bool) -> HostVm {
+        HostVm::ReadyToRun(ReadyToRun {
+            resume_value: Some(vm::WasmValue::I32(if all_valid { 1 } else { 0 })),
+            inner: self.inner,
+        })
+    }
+
+    /// Verifies the signatures one after the other, then resumes execution.
+    ///
+    /// > **Note**: This operation is CPU-intensive.
+    pub fn verify_and_resume(self) -> HostVm {
+        let all_valid = self.signatures.iter().all(|s| s.verify());
+        self.resume(all_valid)
+    }
+}
+
+impl fmt::Debug for SignatureBatchVerify {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("SignatureBatchVerify")
+            .field(&self.signatures)
+            .finish()
+    }
+}
+
+/// Signature whose verification has been deferred until the end of a batch verification.
+#[derive(Debug, Clone)]
+pub struct SignatureVerification {
+    inner: SignatureVerificationInner,
+}
+
+#[derive(Debug, Clone)]
+enum SignatureVerificationInner {
+    Ed25519 {
+        signature: [u8; 64],
+        message: Vec<u8>,
+        public_key: [u8; 32],
+    },
+    Sr25519 {
+        signature: [u8; 64],
+        message: Vec<u8>,
+        public_key: [u8; 32],
+    },
+    Ecdsa {
+        signature: [u8; 65],
+        message_hash: [u8; 32],
+        public_key: [u8; 33],
+    },
+}
+
+impl SignatureVerification {
+    /// Verifies the signature. Returns `true` if it is valid.
+    ///
+    /// > **Note**: This operation is CPU-intensive.
+    pub fn verify(&self) -> bool {
+        match &self.inner {
+            SignatureVerificationInner::Ed25519 {
+                signature,
+                message,
+                public_key,
+            } => match ed25519_zebra::VerificationKey::try_from(&public_key[..]) {
+                Ok(public_key) => public_key
+                    .verify(&ed25519_zebra::Signature::from(*signature), message)
+                    .is_ok(),
+                Err(_) => false,
+            },
+            SignatureVerificationInner::Sr25519 {
+                signature,
+                message,
+                public_key,
+            } => {
+                let public_key = match schnorrkel::PublicKey::from_bytes(public_key) {
+                    Ok(pk) => pk,
+                    Err(_) => return false,
+                };
+                let signature = match schnorrkel::Signature::from_bytes(signature) {
+                    Ok(s) => s,
+                    Err(_) => return false,
+                };
+                public_key
+                    .verify_simple(b"substrate", message, &signature)
+                    .is_ok()
+            }
+            SignatureVerificationInner::Ecdsa {
+                signature,
+                message_hash,
+                public_key,
+            } => ecdsa_verify(signature, message_hash, public_key, false),
+        }
+    }
+}
+
+/// Kind of local storage of the offchain worker.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum OffchainStorageKind {
+    /// Storage that is shared between all the offchain workers and that is persisted across
+    /// restarts.
+    Persistent,
+    /// Storage that is local to the node and that isn't necessarily persisted.
+    Local,
+}
+
+impl OffchainStorageKind {
+    fn from_u32(kind: u32) -> Option<Self> {
+        match kind {
+            1 => Some(OffchainStorageKind::Persistent),
+            2 => Some(OffchainStorageKind::Local),
+            _ => None,
+        }
+    }
+}
+
+/// Must load a value from the local storage of the offchain worker.
+pub struct ExternalOffchainLocalStorageGet {
+    inner: Inner,
+
+    /// Kind of storage to load the value from.
+    kind: OffchainStorageKind,
+    /// Pointer to the key whose value must be loaded. Guaranteed to be in range.
+    key_ptr: u32,
+    /// Size of the key whose value must be loaded. Guaranteed to be in range.
+    key_size: u32,
+}
+
+impl ExternalOffchainLocalStorageGet {
+    /// Returns the kind of storage to load the value from.
+    pub fn kind(&self) -> OffchainStorageKind {
+        self.kind
+    }
+
+    /// Returns the key whose value must be loaded.
+    pub fn key(&'_ self) -> impl AsRef<[u8]> + '_ {
+        self.inner
+            .vm
+            .read_memory(self.key_ptr, self.key_size)
+            .unwrap()
+    }
+
+    /// Writes the value to the memory and prepares for execution. Pass `None` if the storage
+    /// doesn't contain any value for this key.
+    pub fn resume(self, value: Option<&[u8]>) -> HostVm {
+        let function_name = HostFunction::ext_offchain_local_storage_get_version_1.name();
+
+        if let Some(value) = value {
+            self.inner.alloc_write_and_return_pointer_size(
+                function_name,
+                iter::once(either::Left([1]))
+                    .chain(iter::once(either::Right(either::Left(
+                        util::encode_scale_compact_usize(value.len()),
+                    ))))
+                    .chain(iter::once(either::Right(either::Right(value)))),
+            )
+        } else {
+            self.inner
+                .alloc_write_and_return_pointer_size(function_name, iter::once([0]))
+        }
+    }
+}
+
+impl fmt::Debug for ExternalOffchainLocalStorageGet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("ExternalOffchainLocalStorageGet").finish()
+    }
+}
+
+/// Must set or remove a value of the local storage of the offchain worker.
+pub struct ExternalOffchainLocalStorageSet {
+    inner: Inner,
+
+    /// Kind of storage to modify.
+    kind: OffchainStorageKind,
+    /// Pointer to the key whose value must be set. Guaranteed to be in range.
+    key_ptr: u32,
+    /// Size of the key whose value must be set. Guaranteed to be in range.
+    key_size: u32,
+    /// Pointer and size of the value to set. `None` for clearing. Guaranteed to be in range.
+    value: Option<(u32, u32)>,
+}
+
+impl ExternalOffchainLocalStorageSet {
+    /// Returns the kind of storage to modify.
+    pub fn kind(&self) -> OffchainStorageKind {
+        self.kind
+    }
+
+    /// Returns the key whose value must be set.
+    pub fn key(&'_ self) -> impl AsRef<[u8]> + '_ {
+        self.inner
+            .vm
+            .read_memory(self.key_ptr, self.key_size)
+            .unwrap()
+    }
+
+    /// Returns the value to set.
+    ///
+    /// If `None` is returned, the key should be removed from the storage entirely.
+    pub fn value(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        if let Some((ptr, size)) = self.value {
+            Some(self.inner.vm.read_memory(ptr, size).unwrap())
+        } else {
+            None
+        }
+    }
+
+    /// Resumes execution after having set the value.
+    pub fn resume(self) -> HostVm {
+        HostVm::ReadyToRun(ReadyToRun {
+            inner: self.inner,
+            resume_value: None,
+        })
+    }
+}
+
+impl fmt::Debug for ExternalOffchainLocalStorageSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("ExternalOffchainLocalStorageSet").finish()
+    }
+}
+
+/// Must set a value of the local storage of the offchain worker, but only if the current value
+/// is equal to [`ExternalOffchainLocalStorageCompareAndSet::old_value`].
+pub struct ExternalOffchainLocalStorageCompareAndSet {
+    inner: Inner,
+
+    /// Kind of storage to modify.
+    kind: OffchainStorageKind,
+    /// Pointer to the key whose value must be set. Guaranteed to be in range.
+    key_ptr: u32,
+    /// Size of the key whose value must be set. Guaranteed to be in range.
+    key_size: u32,
+    /// Value that the storage entry is expected to currently have.
+    old_value: Option<Vec<u8>>,
+    /// Pointer to the value to set. Guaranteed to be in range.
+    value_ptr: u32,
+    /// Size of the value to set. Guaranteed to be in range.
+    value_size: u32,
+}
+
+impl ExternalOffchainLocalStorageCompareAndSet {
+    /// Returns the kind of storage to modify.
+    pub fn kind(&self) -> OffchainStorageKind {
+        self.kind
+    }
+
+    /// Returns the key whose value must be set.
+    pub fn key(&'_ self) -> impl AsRef<[u8]> + '_ {
+        self.inner
+            .vm
+            .read_memory(self.key_ptr, self.key_size)
+            .unwrap()
+    }
+
+    /// Returns the value that the storage entry is expected to currently have. `None` means
+    /// that the storage entry is expected to not exist.
+    pub fn old_value(&self) -> Option<&[u8]> {
+        self.old_value.as_deref()
+    }
+
+    /// Returns the value to set.
+    pub fn value(&'_ self) -> impl AsRef<[u8]> + '_ {
+        self.inner
+            .vm
+            .read_memory(self.value_ptr, self.value_size)
+            .unwrap()
+    }
+
+    /// Resumes execution. `replaced` must be `true` if the current value matched
+    /// [`ExternalOffchainLocalStorageCompareAndSet::old_value`] and has been replaced.
+    pub fn resume(self, replaced: bool) -> HostVm {
+        HostVm::ReadyToRun(ReadyToRun {
+            inner: self.inner,
+            resume_value: Some(vm::WasmValue::I32(if replaced { 1 } else { 0 })),
+        })
+    }
+}
+
+impl fmt::Debug for ExternalOffchainLocalStorageCompareAndSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("ExternalOffchainLocalStorageCompareAndSet")
+            .finish()
+    }
+}
+
+/// Need to provide the current UNIX timestamp.
+pub struct OffchainTimestamp {
+    inner: Inner,
+}
+
+impl OffchainTimestamp {
+    /// Resumes execution after having provided the current UNIX timestamp, in milliseconds.
+    pub fn resume(self, timestamp_ms: u64) -> HostVm {
+        HostVm::ReadyToRun(ReadyToRun {
+            inner: self.inner,
+            resume_value: Some(vm::WasmValue::I64(i64::from_ne_bytes(
+                timestamp_ms.to_ne_bytes(),
+            ))),
+        })
+    }
+}
+
+impl fmt::Debug for OffchainTimestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("OffchainTimestamp").finish()
+    }
+}
+
+/// Need to provide a randomly-generated seed.
+pub struct OffchainRandomSeed {
+    inner: Inner,
+}
+
+impl OffchainRandomSeed {
+    /// Writes the seed to the memory and prepares for execution.
+    pub fn resume(self, seed: [u8; 32]) -> HostVm {
+        self.inner.alloc_write_and_return_pointer(
+            HostFunction::ext_offchain_random_seed_version_1.name(),
+            iter::once(&seed),
+        )
+    }
+}
+
+impl fmt::Debug for OffchainRandomSeed {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.debug_tuple("ExternalOffchainStorageSet").finish()
+        f.debug_tuple("OffchainRandomSeed").finish()
     }
 }
 
@@ -2060,14 +4001,108 @@ struct Inner {
     /// No further transaction start is allowed before the current one ends.
     within_storage_transaction: bool,
 
+    /// If `Some`, a batch verification of signatures has been started using
+    /// `ext_crypto_start_batch_verify_version_1`. Contains the signatures added to the batch so
+    /// far, which are verified when the batch is finished.
+    batch_verification: Option<Vec<SignatureVerification>>,
+
+    /// If `Some`, the calls to host functions are traced. Contains the calls made so far. See
+    /// [`HostVmPrototype::set_trace_host_calls`].
+    host_calls: Option<Vec<HostCall>>,
+
+    /// Memories created using `ext_sandbox_memory_new_version_1`, indexed by the value that has
+    /// been returned to the runtime. Contains `None` for memories that have been torn down.
+    sandbox_memories: Vec<Option<SandboxMemory>>,
+
+    /// Modules instantiated using `ext_sandbox_instantiate_version_1`, indexed by the value that
+    /// has been returned to the runtime. Contains `None` for instances that have been torn down.
+    sandbox_instances: Vec<Option<vm::VirtualMachinePrototype>>,
+
     /// See [`HostVmPrototype::registered_functions`].
-    registered_functions: Vec<HostFunction>,
+    registered_functions: Vec<FunctionImport>,
+
+    /// See [`HostVmPrototype::allow_unresolved_imports`].
+    allow_unresolved_imports: bool,
+
+    /// See [`HostVmPrototype::max_memory_size`].
+    max_memory_size: Option<u32>,
 
     /// Memory allocator in order to answer the calls to `malloc` and `free`.
     allocator: allocator::FreeingBumpHeapAllocator,
+
+    /// See [`HostVmPrototype::fuel`].
+    fuel: Option<u64>,
+
+    /// Fuel remaining for this call. `None` if there is no limit.
+    remaining_fuel: Option<u64>,
 }
 
 impl Inner {
+    /// Returns the total size, in bytes, of the memory of the virtual machine and of the
+    /// memories of the sandbox. This is the value that [`Config::max_memory_size`] limits.
+    fn total_memory_size(&self) -> u64 {
+        let sandbox_memories = self
+            .sandbox_memories
+            .iter()
+            .map(|memory| match memory {
+                Some(SandboxMemory::Standalone(memory)) => u64::try_from(memory.len()).unwrap(),
+                // Part of the memory of the instance.
+                Some(SandboxMemory::Instance(_)) | None => 0,
+            })
+            .sum::<u64>();
+        let sandbox_instances = self
+            .sandbox_instances
+            .iter()
+            .flatten()
+            .map(|instance| u64::from(instance.memory_size()))
+            .sum::<u64>();
+        u64::from(self.vm.memory_size()) + sandbox_memories + sandbox_instances
+    }
+
+    /// Instantiates a sandboxed module for `ext_sandbox_instantiate_version_1`, and returns its
+    /// index. `memory_index` is the index of the memory that the environment provides under the
+    /// name `env.memory`, if any.
+    ///
+    /// Returns `None` if the module can't be instantiated.
+    fn sandbox_instantiate(&mut self, code: &[u8], memory_index: Option<u32>) -> Option<u32> {
+        // The memory that the module defines is limited to what remains of the total.
+        let max_memory_pages = self.max_memory_size.map(|max| {
+            u32::try_from(u64::from(max).saturating_sub(self.total_memory_size()) / 65536).unwrap()
+        });
+        let module =
+            vm::Module::new_metered(code, vm::ExecHint::Untrusted, max_memory_pages).ok()?;
+        let symbols = |module_name: &str, function_name: &str, _: &vm::Signature| {
+            if module_name == vm::METERING_MODULE_NAME
+                && function_name == vm::METERING_FUNCTION_NAME
+            {
+                Ok(0)
+            } else {
+                Err(())
+            }
+        };
+
+        if !module.imports_memory() {
+            let instance =
+                vm::VirtualMachinePrototype::new(&module, HeapPages::new(0), symbols).ok()?;
+            self.sandbox_instances.push(Some(instance));
+            return Some(u32::try_from(self.sandbox_instances.len() - 1).unwrap());
+        }
+
+        // The imported memory is moved to the instance. It is already accounted for in the
+        // total, and thus isn't subject to `max_memory_pages`.
+        let memory_index = usize::try_from(memory_index?).ok()?;
+        let instance = match self.sandbox_memories.get(memory_index)? {
+            Some(SandboxMemory::Standalone(memory)) => {
+                vm::VirtualMachinePrototype::with_imported_memory(&module, memory, symbols).ok()?
+            }
+            Some(SandboxMemory::Instance(_)) | None => return None,
+        };
+        self.sandbox_instances.push(Some(instance));
+        let instance_index = self.sandbox_instances.len() - 1;
+        self.sandbox_memories[memory_index] = Some(SandboxMemory::Instance(instance_index));
+        Some(u32::try_from(instance_index).unwrap())
+    }
+
     /// Uses the memory allocator to allocate some memory for the given data, writes the data in
     /// memory, and returns an [`HostVm`] ready for the Wasm host_fn return.
     ///
@@ -2184,8 +4219,32 @@ impl Inner {
             heap_base: self.heap_base,
             registered_functions: self.registered_functions,
             heap_pages: self.heap_pages,
+            allow_unresolved_imports: self.allow_unresolved_imports,
+            max_memory_size: self.max_memory_size,
+            fuel: self.fuel,
+            trace_host_calls: self.host_calls.is_some(),
         }
     }
+
+    /// Verifies the given signature, or adds it to the current batch if a batch verification is
+    /// in progress, and returns an [`HostVm`] ready to resume with the outcome.
+    ///
+    /// When a batch verification is in progress, the outcome of the verification is reported at
+    /// the end of the batch, and `true` is returned here.
+    fn verify_or_defer(mut self, signature: SignatureVerification) -> HostVm {
+        let success = match &mut self.batch_verification {
+            Some(batch) => {
+                batch.push(signature);
+                true
+            }
+            None => signature.verify(),
+        };
+
+        HostVm::ReadyToRun(ReadyToRun {
+            resume_value: Some(vm::WasmValue::I32(if success { 1 } else { 0 })),
+            inner: self,
+        })
+    }
 }
 
 /// Error that can happen when initializing a VM.
@@ -2211,12 +4270,177 @@ pub enum StartErr {
     DataSizeOverflow,
 }
 
+/// Function imported by the Wasm code. See [`HostVmPrototype::registered_functions`].
+#[derive(Debug, Clone)]
+enum FunctionImport {
+    /// Function supported by this module.
+    Resolved(HostFunction),
+    /// Function that isn't supported. Only possible if [`Config::allow_unresolved_imports`] is
+    /// `true`.
+    Unresolved {
+        /// Name of the module the function is imported from.
+        module: String,
+        /// Name of the function.
+        name: String,
+    },
+    /// Function that the code injected by [`vm::Module::new_metered`] calls in order to report
+    /// the number of instructions that it executes. Only possible if [`Config::metered`] is
+    /// `true`.
+    Metering,
+}
+
+/// Compiles the decompressed Wasm code of a runtime according to the given configuration.
+fn compile_module(
+    code: &[u8],
+    config: &Config<impl AsRef<[u8]>>,
+) -> Result<vm::Module, vm::NewErr> {
+    let max_memory_pages = max_memory_pages(config.max_memory_size);
+    if config.metered {
+        vm::Module::new_metered(code, config.exec_hint, max_memory_pages)
+    } else {
+        vm::Module::new(code, config.exec_hint, max_memory_pages)
+    }
+}
+
+/// Converts a value of [`Config::max_memory_size`] into a number of 64kiB pages.
+fn max_memory_pages(max_memory_size: Option<u32>) -> Option<u32> {
+    max_memory_size.map(|size| size / 65536)
+}
+
+/// Value returned by the `ext_sandbox_*` functions in case of success.
+const SANDBOX_ERR_OK: u32 = 0;
+/// Value returned by `ext_sandbox_instantiate_version_1` if the module couldn't be instantiated.
+const SANDBOX_ERR_MODULE: u32 = u32::max_value();
+/// Value returned by the `ext_sandbox_*` functions if a memory access is out of bounds.
+const SANDBOX_ERR_OUT_OF_BOUNDS: u32 = u32::max_value() - 1;
+/// Value returned by `ext_sandbox_invoke_version_1` if the execution has failed.
+const SANDBOX_ERR_EXECUTION: u32 = u32::max_value() - 2;
+
+/// Maximum number of pages of a memory created with `ext_sandbox_memory_new_version_1`. Each
+/// page is 64kiB.
+const SANDBOX_MEMORY_MAX_PAGES: u32 = 2048;
+
+/// Maximum number of instructions that a call to `ext_sandbox_invoke_version_1` is allowed to
+/// execute. The call fails if the sandboxed code executes more instructions, which prevents it
+/// from blocking the runtime forever.
+const SANDBOX_MAX_INSTRUCTIONS: u64 = 1_000_000_000;
+
+/// Memory created with `ext_sandbox_memory_new_version_1`.
+enum SandboxMemory {
+    /// Memory not imported by any sandboxed instance.
+    Standalone(Vec<u8>),
+    /// Memory imported by the sandboxed instance with the given index. The content of the memory
+    /// is the memory of the instance.
+    Instance(usize),
+}
+
+/// Entry of the environment definition passed to `ext_sandbox_instantiate_version_1`, in the
+/// format expected by the runtime.
+#[derive(Debug, Decode)]
+struct SandboxEnvironmentEntry {
+    module_name: Vec<u8>,
+    field_name: Vec<u8>,
+    entity: SandboxExternEntity,
+}
+
+/// Object provided by the environment of a sandboxed module, in the format expected by the
+/// runtime.
+#[derive(Debug, Decode)]
+enum SandboxExternEntity {
+    /// Index of a function in the table of the runtime. Ignored, as sandboxed modules can't
+    /// import functions.
+    #[codec(index = 1)]
+    Function(#[allow(dead_code)] u32),
+    /// Index of a memory created with `ext_sandbox_memory_new_version_1`.
+    #[codec(index = 2)]
+    Memory(u32),
+}
+
+/// Value passed to or returned by a sandboxed function, in the format expected by the runtime.
+#[derive(Debug, Decode, Encode)]
+enum SandboxValue {
+    I32(i32),
+    I64(i64),
+    F32(u32),
+    F64(u64),
+}
+
 /// Reason why the Wasm blob isn't conforming to the runtime environment.
 #[derive(Debug, Clone, derive_more::Display)]
 pub enum Error {
     /// Error in the Wasm code execution.
     #[display(fmt = "{}", _0)]
     Trap(vm::Trap),
+    /// Called a function that the Wasm code imports but that isn't supported.
+    /// See [`Config::allow_unresolved_imports`].
+    #[display(fmt = "Called unresolved function `{}`:`{}`", module_name, function)]
+    UnresolvedFunctionCalled {
+        /// Name of the module the function is imported from.
+        module_name: String,
+        /// Name of the function.
+        function: String,
+    },
+    /// The call has used up all the fuel it was given. See [`HostVmPrototype::set_fuel`].
+    #[display(fmt = "The call has run out of fuel")]
+    OutOfFuel,
+    /// Called a host function that requires access to a keystore. No keystore is available.
+    #[display(fmt = "{} requires a keystore, which isn't available", function)]
+    KeystoreUnavailable {
+        /// Name of the function being called.
+        function: &'static str,
+    },
+    /// Called a sandbox host function with an index that doesn't correspond to any sandboxed
+    /// memory or instance.
+    #[display(
+        fmt = "Invalid sandbox memory or instance index {} in {}",
+        index,
+        function
+    )]
+    InvalidSandboxIndex {
+        /// Name of the function being called.
+        function: &'static str,
+        /// Index passed as parameter.
+        index: u32,
+    },
+    /// The memory of the virtual machine and the memories of the sandbox would exceed
+    /// [`Config::max_memory_size`].
+    #[display(fmt = "Memory size exceeds the limit of {} bytes", limit)]
+    MemoryLimitExceeded {
+        /// Value of [`Config::max_memory_size`].
+        limit: u32,
+    },
+    /// Called `ext_sandbox_memory_new_version_1` with an invalid size.
+    #[display(
+        fmt = "Invalid sandbox memory size (initial: {}, maximum: {})",
+        initial,
+        maximum
+    )]
+    InvalidSandboxMemorySize {
+        /// Initial number of pages requested.
+        initial: u32,
+        /// Maximum number of pages requested.
+        maximum: u32,
+    },
+    /// Attempted to start a batch verification of signatures while one is already in progress.
+    #[display(fmt = "Attempted to start a batch verification while one is already in progress")]
+    AlreadyBatchVerifying,
+    /// Attempted to finish a batch verification of signatures while none is in progress.
+    #[display(fmt = "Attempted to finish a batch verification while none is in progress")]
+    NoBatchVerification,
+    /// The state version passed to a host function is invalid.
+    #[display(fmt = "Unknown state version {} passed to {}", state_version, function)]
+    UnknownStateVersion {
+        /// Name of the function being called.
+        function: &'static str,
+        /// State version that has been passed.
+        state_version: u32,
+    },
+    /// The kind of offchain storage passed to a host function is invalid.
+    #[display(fmt = "Unknown offchain storage kind passed to {}", function)]
+    UnknownOffchainStorageKind {
+        /// Name of the function being called.
+        function: &'static str,
+    },
     /// A non-`i64` value has been returned by the Wasm entry point.
     #[display(fmt = "A non-I64 value has been returned: {:?}", actual)]
     BadReturnValue {
@@ -2386,6 +4610,7 @@ externalities! {
     ext_storage_clear_prefix_version_1,
     ext_storage_clear_prefix_version_2,
     ext_storage_root_version_1,
+    ext_storage_root_version_2,
     ext_storage_changes_root_version_1,
     ext_storage_next_key_version_1,
     ext_storage_append_version_1,
@@ -2413,20 +4638,33 @@ externalities! {
     ext_default_child_storage_exists_version_1,
     ext_default_child_storage_next_key_version_1,
     ext_default_child_storage_root_version_1,
+    ext_default_child_storage_root_version_2,
     ext_crypto_ed25519_public_keys_version_1,
     ext_crypto_ed25519_generate_version_1,
     ext_crypto_ed25519_sign_version_1,
     ext_crypto_ed25519_verify_version_1,
+    ext_crypto_ed25519_batch_verify_version_1,
     ext_crypto_sr25519_public_keys_version_1,
     ext_crypto_sr25519_generate_version_1,
     ext_crypto_sr25519_sign_version_1,
     ext_crypto_sr25519_verify_version_1,
     ext_crypto_sr25519_verify_version_2,
+    ext_crypto_sr25519_batch_verify_version_1,
+    ext_crypto_ecdsa_public_keys_version_1,
     ext_crypto_ecdsa_generate_version_1,
+    ext_crypto_ecdsa_sign_version_1,
+    ext_crypto_ecdsa_sign_prehashed_version_1,
+    ext_crypto_ecdsa_verify_version_1,
+    ext_crypto_ecdsa_verify_version_2,
+    ext_crypto_ecdsa_verify_prehashed_version_1,
+    ext_crypto_ecdsa_batch_verify_version_1,
     ext_crypto_secp256k1_ecdsa_recover_version_1,
     ext_crypto_secp256k1_ecdsa_recover_version_2,
     ext_crypto_secp256k1_ecdsa_recover_compressed_version_1,
     ext_crypto_secp256k1_ecdsa_recover_compressed_version_2,
+    ext_crypto_bls377_generate_version_1,
+    ext_crypto_bls381_generate_version_1,
+    ext_crypto_ecdsa_bls377_generate_version_1,
     ext_crypto_start_batch_verify_version_1,
     ext_crypto_finish_batch_verify_version_1,
     ext_hashing_keccak_256_version_1,
@@ -2463,7 +4701,9 @@ externalities! {
     ext_sandbox_instance_teardown_version_1,
     ext_sandbox_get_global_val_version_1,
     ext_trie_blake2_256_root_version_1,
+    ext_trie_blake2_256_root_version_2,
     ext_trie_blake2_256_ordered_root_version_1,
+    ext_trie_blake2_256_ordered_root_version_2,
     ext_trie_keccak_256_ordered_root_version_1,
     ext_misc_print_num_version_1,
     ext_misc_print_utf8_version_1,
@@ -2486,6 +4726,7 @@ impl HostFunction {
             HostFunction::ext_storage_clear_prefix_version_1 => 1,
             HostFunction::ext_storage_clear_prefix_version_2 => 2,
             HostFunction::ext_storage_root_version_1 => 0,
+            HostFunction::ext_storage_root_version_2 => 1,
             HostFunction::ext_storage_changes_root_version_1 => 1,
             HostFunction::ext_storage_next_key_version_1 => 1,
             HostFunction::ext_storage_append_version_1 => 2,
@@ -2501,32 +4742,45 @@ impl HostFunction {
             HostFunction::ext_storage_start_transaction_version_1 => 0,
             HostFunction::ext_storage_rollback_transaction_version_1 => 0,
             HostFunction::ext_storage_commit_transaction_version_1 => 0,
-            HostFunction::ext_default_child_storage_get_version_1 => todo!(),
-            HostFunction::ext_default_child_storage_read_version_1 => todo!(),
-            HostFunction::ext_default_child_storage_storage_kill_version_1 => todo!(),
-            HostFunction::ext_default_child_storage_storage_kill_version_2 => todo!(),
-            HostFunction::ext_default_child_storage_storage_kill_version_3 => todo!(),
-            HostFunction::ext_default_child_storage_clear_prefix_version_1 => todo!(),
-            HostFunction::ext_default_child_storage_clear_prefix_version_2 => todo!(),
-            HostFunction::ext_default_child_storage_set_version_1 => todo!(),
-            HostFunction::ext_default_child_storage_clear_version_1 => todo!(),
-            HostFunction::ext_default_child_storage_exists_version_1 => todo!(),
-            HostFunction::ext_default_child_storage_next_key_version_1 => todo!(),
-            HostFunction::ext_default_child_storage_root_version_1 => todo!(),
+            HostFunction::ext_default_child_storage_get_version_1 => 2,
+            HostFunction::ext_default_child_storage_read_version_1 => 4,
+            HostFunction::ext_default_child_storage_storage_kill_version_1 => 1,
+            HostFunction::ext_default_child_storage_storage_kill_version_2 => 2,
+            HostFunction::ext_default_child_storage_storage_kill_version_3 => 2,
+            HostFunction::ext_default_child_storage_clear_prefix_version_1 => 2,
+            HostFunction::ext_default_child_storage_clear_prefix_version_2 => 3,
+            HostFunction::ext_default_child_storage_set_version_1 => 3,
+            HostFunction::ext_default_child_storage_clear_version_1 => 2,
+            HostFunction::ext_default_child_storage_exists_version_1 => 2,
+            HostFunction::ext_default_child_storage_next_key_version_1 => 2,
+            HostFunction::ext_default_child_storage_root_version_1 => 1,
+            HostFunction::ext_default_child_storage_root_version_2 => 2,
             HostFunction::ext_crypto_ed25519_public_keys_version_1 => todo!(),
             HostFunction::ext_crypto_ed25519_generate_version_1 => todo!(),
             HostFunction::ext_crypto_ed25519_sign_version_1 => todo!(),
             HostFunction::ext_crypto_ed25519_verify_version_1 => 3,
+            HostFunction::ext_crypto_ed25519_batch_verify_version_1 => 3,
             HostFunction::ext_crypto_sr25519_public_keys_version_1 => todo!(),
             HostFunction::ext_crypto_sr25519_generate_version_1 => todo!(),
             HostFunction::ext_crypto_sr25519_sign_version_1 => todo!(),
             HostFunction::ext_crypto_sr25519_verify_version_1 => 3,
             HostFunction::ext_crypto_sr25519_verify_version_2 => 3,
+            HostFunction::ext_crypto_sr25519_batch_verify_version_1 => 3,
+            HostFunction::ext_crypto_ecdsa_public_keys_version_1 => 1,
             HostFunction::ext_crypto_ecdsa_generate_version_1 => todo!(),
+            HostFunction::ext_crypto_ecdsa_sign_version_1 => 3,
+            HostFunction::ext_crypto_ecdsa_sign_prehashed_version_1 => 3,
+            HostFunction::ext_crypto_ecdsa_verify_version_1 => 3,
+            HostFunction::ext_crypto_ecdsa_verify_version_2 => 3,
+            HostFunction::ext_crypto_ecdsa_verify_prehashed_version_1 => 3,
+            HostFunction::ext_crypto_ecdsa_batch_verify_version_1 => 3,
             HostFunction::ext_crypto_secp256k1_ecdsa_recover_version_1 => 2,
             HostFunction::ext_crypto_secp256k1_ecdsa_recover_version_2 => 2,
             HostFunction::ext_crypto_secp256k1_ecdsa_recover_compressed_version_1 => 2,
             HostFunction::ext_crypto_secp256k1_ecdsa_recover_compressed_version_2 => 2,
+            HostFunction::ext_crypto_bls377_generate_version_1 => 2,
+            HostFunction::ext_crypto_bls381_generate_version_1 => 2,
+            HostFunction::ext_crypto_ecdsa_bls377_generate_version_1 => 2,
             HostFunction::ext_crypto_start_batch_verify_version_1 => 0,
             HostFunction::ext_crypto_finish_batch_verify_version_1 => 0,
             HostFunction::ext_hashing_keccak_256_version_1 => 1,
@@ -2538,32 +4792,34 @@ impl HostFunction {
             HostFunction::ext_hashing_twox_256_version_1 => 1,
             HostFunction::ext_offchain_index_set_version_1 => 2,
             HostFunction::ext_offchain_index_clear_version_1 => 1,
-            HostFunction::ext_offchain_is_validator_version_1 => todo!(),
-            HostFunction::ext_offchain_submit_transaction_version_1 => todo!(),
-            HostFunction::ext_offchain_network_state_version_1 => todo!(),
-            HostFunction::ext_offchain_timestamp_version_1 => todo!(),
-            HostFunction::ext_offchain_sleep_until_version_1 => todo!(),
-            HostFunction::ext_offchain_random_seed_version_1 => todo!(),
-            HostFunction::ext_offchain_local_storage_set_version_1 => todo!(),
-            HostFunction::ext_offchain_local_storage_compare_and_set_version_1 => todo!(),
-            HostFunction::ext_offchain_local_storage_get_version_1 => todo!(),
-            HostFunction::ext_offchain_local_storage_clear_version_1 => todo!(),
-            HostFunction::ext_offchain_http_request_start_version_1 => todo!(),
-            HostFunction::ext_offchain_http_request_add_header_version_1 => todo!(),
-            HostFunction::ext_offchain_http_request_write_body_version_1 => todo!(),
-            HostFunction::ext_offchain_http_response_wait_version_1 => todo!(),
-            HostFunction::ext_offchain_http_response_headers_version_1 => todo!(),
-            HostFunction::ext_offchain_http_response_read_body_version_1 => todo!(),
-            HostFunction::ext_sandbox_instantiate_version_1 => todo!(),
-            HostFunction::ext_sandbox_invoke_version_1 => todo!(),
-            HostFunction::ext_sandbox_memory_new_version_1 => todo!(),
-            HostFunction::ext_sandbox_memory_get_version_1 => todo!(),
-            HostFunction::ext_sandbox_memory_set_version_1 => todo!(),
-            HostFunction::ext_sandbox_memory_teardown_version_1 => todo!(),
-            HostFunction::ext_sandbox_instance_teardown_version_1 => todo!(),
-            HostFunction::ext_sandbox_get_global_val_version_1 => todo!(),
+            HostFunction::ext_offchain_is_validator_version_1 => 0,
+            HostFunction::ext_offchain_submit_transaction_version_1 => 1,
+            HostFunction::ext_offchain_network_state_version_1 => 0,
+            HostFunction::ext_offchain_timestamp_version_1 => 0,
+            HostFunction::ext_offchain_sleep_until_version_1 => 1,
+            HostFunction::ext_offchain_random_seed_version_1 => 0,
+            HostFunction::ext_offchain_local_storage_set_version_1 => 3,
+            HostFunction::ext_offchain_local_storage_compare_and_set_version_1 => 4,
+            HostFunction::ext_offchain_local_storage_get_version_1 => 2,
+            HostFunction::ext_offchain_local_storage_clear_version_1 => 2,
+            HostFunction::ext_offchain_http_request_start_version_1 => 3,
+            HostFunction::ext_offchain_http_request_add_header_version_1 => 3,
+            HostFunction::ext_offchain_http_request_write_body_version_1 => 3,
+            HostFunction::ext_offchain_http_response_wait_version_1 => 2,
+            HostFunction::ext_offchain_http_response_headers_version_1 => 1,
+            HostFunction::ext_offchain_http_response_read_body_version_1 => 3,
+            HostFunction::ext_sandbox_instantiate_version_1 => 4,
+            HostFunction::ext_sandbox_invoke_version_1 => 6,
+            HostFunction::ext_sandbox_memory_new_version_1 => 2,
+            HostFunction::ext_sandbox_memory_get_version_1 => 4,
+            HostFunction::ext_sandbox_memory_set_version_1 => 4,
+            HostFunction::ext_sandbox_memory_teardown_version_1 => 1,
+            HostFunction::ext_sandbox_instance_teardown_version_1 => 1,
+            HostFunction::ext_sandbox_get_global_val_version_1 => 2,
             HostFunction::ext_trie_blake2_256_root_version_1 => 1,
+            HostFunction::ext_trie_blake2_256_root_version_2 => 2,
             HostFunction::ext_trie_blake2_256_ordered_root_version_1 => 1,
+            HostFunction::ext_trie_blake2_256_ordered_root_version_2 => 2,
             HostFunction::ext_trie_keccak_256_ordered_root_version_1 => todo!(),
             HostFunction::ext_misc_print_num_version_1 => 1,
             HostFunction::ext_misc_print_utf8_version_1 => 1,
@@ -2577,6 +4833,42 @@ impl HostFunction {
     }
 }
 
+/// Verifies an ECDSA signature against the given message hash and compressed public key.
+///
+/// If `overflowing` is `true`, the `r` and `s` components of the signature are allowed to
+/// overflow the curve order.
+fn ecdsa_verify(
+    signature: &[u8],
+    message_hash: &[u8],
+    compressed_public_key: &[u8],
+    overflowing: bool,
+) -> bool {
+    let rs = if overflowing {
+        libsecp256k1::Signature::parse_overflowing_slice(&signature[..64])
+    } else {
+        libsecp256k1::Signature::parse_standard_slice(&signature[..64])
+    };
+    let rs = match rs {
+        Ok(rs) => rs,
+        Err(_) => return false,
+    };
+
+    let v = match libsecp256k1::RecoveryId::parse(signature[64]) {
+        Ok(v) => v,
+        Err(_) => return false,
+    };
+
+    let message = match libsecp256k1::Message::parse_slice(message_hash) {
+        Ok(m) => m,
+        Err(_) => return false,
+    };
+
+    match libsecp256k1::recover(&message, &rs, &v) {
+        Ok(public_key) => public_key.serialize_compressed()[..] == *compressed_public_key,
+        Err(_) => false,
+    }
+}
+
 // Glue between the `allocator` module and the `vm` module.
 struct MemAccess<'a>(&'a mut vm::VirtualMachine);
 impl<'a> allocator::Memory for MemAccess<'a> {
@@ -2600,11 +4892,999 @@ impl<'a> allocator::Memory for MemAccess<'a> {
 
 #[cfg(test)]
 mod tests {
-    use super::HostVm;
+    use super::{
+        vm, Config, Error, HeapPages, HostVm, HostVmPool, HostVmPrototype, ModulesCache,
+        OffchainStorageKind, SignatureBatchVerify,
+    };
+    use core::convert::TryFrom as _;
 
     #[test]
     fn is_send() {
         fn req<T: Send>() {}
         req::<HostVm>();
     }
+
+    /// Returns the example runtime in the format found in the chain storage, which is a zstd
+    /// frame preceded with [`super::zstd::ZSTD_PREFIX`].
+    fn example_runtime() -> Vec<u8> {
+        let mut code = super::zstd::ZSTD_PREFIX.to_vec();
+        code.extend_from_slice(include_bytes!("./host/zstd/example-runtime"));
+        code
+    }
+
+    #[test]
+    fn modules_cache() {
+        let code = &example_runtime()[..];
+        let mut cache = ModulesCache::new(1);
+
+        HostVmPrototype::new_cached(
+            &mut cache,
+            Config {
+                module: code,
+                heap_pages: HeapPages::new(1024),
+                exec_hint: vm::ExecHint::Oneshot,
+                allow_unresolved_imports: false,
+                max_memory_size: None,
+                metered: false,
+            },
+        )
+        .unwrap();
+        assert_eq!(cache.len(), 1);
+
+        HostVmPrototype::new_cached(
+            &mut cache,
+            Config {
+                module: code,
+                heap_pages: HeapPages::new(2048),
+                exec_hint: vm::ExecHint::Oneshot,
+                allow_unresolved_imports: false,
+                max_memory_size: None,
+                metered: false,
+            },
+        )
+        .unwrap();
+        assert_eq!(cache.len(), 1);
+
+        assert!(HostVmPrototype::new_cached(
+            &mut cache,
+            Config {
+                module: &[1, 2, 3][..],
+                heap_pages: HeapPages::new(1024),
+                exec_hint: vm::ExecHint::Oneshot,
+                allow_unresolved_imports: false,
+                max_memory_size: None,
+                metered: false,
+            }
+        )
+        .is_err());
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn pool_reuses_prototypes() {
+        let code = &example_runtime()[..];
+        let prototype = HostVmPrototype::new(Config {
+            module: code,
+            heap_pages: HeapPages::new(1024),
+            exec_hint: vm::ExecHint::Oneshot,
+            allow_unresolved_imports: false,
+            max_memory_size: None,
+            metered: false,
+        })
+        .unwrap();
+
+        let mut pool = HostVmPool::new(prototype, 2);
+        assert_eq!(pool.num_prototypes(), 1);
+
+        let first = pool.acquire();
+        let second = pool.acquire();
+        assert_eq!(pool.num_prototypes(), 1);
+
+        let vm = HostVm::from(first.run_no_param("Core_version").unwrap());
+        pool.release(vm.into_prototype());
+        assert_eq!(pool.num_prototypes(), 2);
+
+        // The pool is full.
+        pool.release(second);
+        assert_eq!(pool.num_prototypes(), 2);
+
+        pool.acquire();
+        assert_eq!(pool.num_prototypes(), 1);
+    }
+
+    #[test]
+    fn out_of_fuel() {
+        let code = &example_runtime()[..];
+        let mut prototype = HostVmPrototype::new(Config {
+            module: code,
+            heap_pages: HeapPages::new(1024),
+            exec_hint: vm::ExecHint::Oneshot,
+            allow_unresolved_imports: false,
+            max_memory_size: None,
+            metered: false,
+        })
+        .unwrap();
+        prototype.set_fuel(Some(0));
+
+        match prototype.run_no_param("Core_version").unwrap().run() {
+            HostVm::Error {
+                error: Error::OutOfFuel,
+                prototype,
+            } => assert_eq!(prototype.fuel(), Some(0)),
+            _ => panic!(),
+        }
+    }
+
+    /// Builds a Wasm module from its sections, given as pairs of section id and content.
+    fn wasm_module(sections: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut out = b"\0asm\x01\0\0\0".to_vec();
+        for (id, content) in sections {
+            out.push(*id);
+            out.extend(crate::util::leb128::encode_usize(content.len()));
+            out.extend_from_slice(content);
+        }
+        out
+    }
+
+    /// Returns the content of a code section containing a single function without locals.
+    fn code_section(body: &[u8]) -> Vec<u8> {
+        let mut code = vec![1];
+        code.extend(crate::util::leb128::encode_usize(body.len() + 2));
+        code.push(0);
+        code.extend_from_slice(body);
+        code.push(0x0b);
+        code
+    }
+
+    /// Encodes an integer in the signed LEB128 format used by the `const` instructions.
+    fn sleb128(mut value: i64) -> Vec<u8> {
+        let mut out = Vec::new();
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+                out.push(byte);
+                return out;
+            }
+            out.push(byte | 0x80);
+        }
+    }
+
+    fn i32_const(value: i32) -> Vec<u8> {
+        let mut out = vec![0x41];
+        out.extend(sleb128(i64::from(value)));
+        out
+    }
+
+    /// `i64.const` pushing a pointer-size pair.
+    fn pointer_size(ptr: u32, size: u32) -> Vec<u8> {
+        let mut out = vec![0x42];
+        out.extend(sleb128(i64::from_ne_bytes(
+            ((u64::from(size) << 32) | u64::from(ptr)).to_ne_bytes(),
+        )));
+        out
+    }
+
+    /// Builds a runtime whose `test` function executes `body`. `types` is the content of the
+    /// type section, whose first type must be the one of `test`, and `host_functions` the list
+    /// of imported host functions with their type index. The memory of the runtime is two pages
+    /// large and starts with `data`.
+    fn test_runtime(
+        types: &[u8],
+        host_functions: &[(&str, u8)],
+        data: &[u8],
+        body: &[u8],
+    ) -> Vec<u8> {
+        let mut imports =
+            crate::util::leb128::encode_usize(host_functions.len()).collect::<Vec<_>>();
+        for (name, ty) in host_functions {
+            imports.push(3);
+            imports.extend_from_slice(b"env");
+            imports.extend(crate::util::leb128::encode_usize(name.len()));
+            imports.extend_from_slice(name.as_bytes());
+            imports.extend_from_slice(&[0, *ty]);
+        }
+
+        let mut globals = vec![1, 0x7f, 0];
+        globals.extend(i32_const(65536));
+        globals.push(0x0b);
+
+        let mut exports = vec![3, 6];
+        exports.extend_from_slice(b"memory");
+        exports.extend_from_slice(&[2, 0, 11]);
+        exports.extend_from_slice(b"__heap_base");
+        exports.extend_from_slice(&[3, 0, 4]);
+        exports.extend_from_slice(b"test");
+        exports.push(0);
+        exports.extend(crate::util::leb128::encode_usize(host_functions.len()));
+
+        let mut segments = vec![1, 0];
+        segments.extend(i32_const(0));
+        segments.push(0x0b);
+        segments.extend(crate::util::leb128::encode_usize(data.len()));
+        segments.extend_from_slice(data);
+
+        wasm_module(&[
+            (1, types),
+            (2, &imports),
+            (3, &[1, 0]),
+            (5, &[1, 0, 2]),
+            (6, &globals),
+            (7, &exports),
+            (10, &code_section(body)),
+            (11, &segments),
+        ])
+    }
+
+    /// Builds a runtime whose `test` function executes `body`. The runtime imports, in this
+    /// order, `ext_sandbox_instantiate_version_1`, `ext_sandbox_invoke_version_1`,
+    /// `ext_sandbox_memory_new_version_1`, `ext_sandbox_memory_get_version_1`,
+    /// `ext_sandbox_memory_set_version_1`, and `ext_sandbox_instance_teardown_version_1`. Its
+    /// memory is two pages large and starts with `data`.
+    fn sandbox_runtime(data: &[u8], body: &[u8]) -> Vec<u8> {
+        let types = [
+            6, 0x60, 2, 0x7f, 0x7f, 1, 0x7e, 0x60, 4, 0x7f, 0x7e, 0x7e, 0x7f, 1, 0x7f, 0x60, 6,
+            0x7f, 0x7e, 0x7e, 0x7f, 0x7f, 0x7f, 1, 0x7f, 0x60, 2, 0x7f, 0x7f, 1, 0x7f, 0x60, 4,
+            0x7f, 0x7f, 0x7f, 0x7f, 1, 0x7f, 0x60, 1, 0x7f, 0,
+        ];
+
+        test_runtime(
+            &types,
+            &[
+                ("ext_sandbox_instantiate_version_1", 1),
+                ("ext_sandbox_invoke_version_1", 2),
+                ("ext_sandbox_memory_new_version_1", 3),
+                ("ext_sandbox_memory_get_version_1", 4),
+                ("ext_sandbox_memory_set_version_1", 4),
+                ("ext_sandbox_instance_teardown_version_1", 5),
+            ],
+            data,
+            body,
+        )
+    }
+
+    fn run_sandbox_runtime(
+        runtime: &[u8],
+        max_memory_size: Option<u32>,
+        fuel: Option<u64>,
+    ) -> HostVm {
+        let mut prototype = HostVmPrototype::new(Config {
+            module: runtime,
+            heap_pages: HeapPages::new(1),
+            exec_hint: vm::ExecHint::Oneshot,
+            allow_unresolved_imports: false,
+            max_memory_size,
+            metered: false,
+        })
+        .unwrap();
+        prototype.set_fuel(fuel);
+        prototype.run_no_param("test").unwrap().run()
+    }
+
+    #[test]
+    fn sandbox_infinite_loop_out_of_fuel() {
+        // Sandboxed module exporting a function `f` that loops forever.
+        let sandboxed = wasm_module(&[
+            (1, &[1, 0x60, 0, 0]),
+            (3, &[1, 0]),
+            (7, &[1, 1, b'f', 0, 0]),
+            (10, &code_section(&[0x03, 0x40, 0x0c, 0, 0x0b])),
+        ]);
+        let sandboxed_len = u32::try_from(sandboxed.len()).unwrap();
+
+        let mut data = vec![0; 0x2000];
+        data[..sandboxed.len()].copy_from_slice(&sandboxed);
+        data[0x1000] = b'f';
+
+        let mut body = Vec::new();
+        body.extend(i32_const(0));
+        body.extend(pointer_size(0, sandboxed_len));
+        // Empty environment definition.
+        body.extend(pointer_size(0x1010, 1));
+        body.extend(i32_const(0));
+        body.extend_from_slice(&[0x10, 0]);
+        body.extend(pointer_size(0x1000, 1));
+        // Empty list of arguments.
+        body.extend(pointer_size(0x1010, 1));
+        body.extend(i32_const(0x1020));
+        body.extend(i32_const(16));
+        body.extend(i32_const(0));
+        body.extend_from_slice(&[0x10, 1, 0x1a]);
+        body.extend(pointer_size(0, 0));
+
+        let runtime = sandbox_runtime(&data, &body);
+        assert!(matches!(
+            run_sandbox_runtime(&runtime, None, Some(100_000)),
+            HostVm::Error {
+                error: Error::OutOfFuel,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn sandbox_memories_count_towards_limit() {
+        // Creates two sandbox memories of one page each.
+        let mut body = Vec::new();
+        for _ in 0..2 {
+            body.extend(i32_const(1));
+            body.extend(i32_const(-1));
+            body.extend_from_slice(&[0x10, 2, 0x1a]);
+        }
+        body.extend(pointer_size(0, 0));
+        let runtime = sandbox_runtime(&[], &body);
+
+        // The memory of the runtime is two pages plus one heap page.
+        assert!(matches!(
+            run_sandbox_runtime(&runtime, Some(5 * 65536), None),
+            HostVm::Finished(_)
+        ));
+        assert!(matches!(
+            run_sandbox_runtime(&runtime, Some(4 * 65536), None),
+            HostVm::Error {
+                error: Error::MemoryLimitExceeded { limit },
+                ..
+            } if limit == 4 * 65536
+        ));
+    }
+
+    #[test]
+    fn sandbox_imported_memory() {
+        // Sandboxed module importing `env.memory` and exporting a function `f` that writes 42
+        // at address 4 and returns the value at address 0.
+        let mut import = vec![1, 3];
+        import.extend_from_slice(b"env");
+        import.push(6);
+        import.extend_from_slice(b"memory");
+        import.extend_from_slice(&[2, 0, 1]);
+        let sandboxed = wasm_module(&[
+            (1, &[1, 0x60, 0, 1, 0x7f]),
+            (2, &import),
+            (3, &[1, 0]),
+            (7, &[1, 1, b'f', 0, 0]),
+            (
+                10,
+                &code_section(&[0x41, 4, 0x41, 42, 0x36, 2, 0, 0x41, 0, 0x28, 2, 0]),
+            ),
+        ]);
+        let sandboxed_len = u32::try_from(sandboxed.len()).unwrap();
+
+        // Environment definition providing the memory with index 0 as `env.memory`.
+        let mut environment = vec![4, 12];
+        environment.extend_from_slice(b"env");
+        environment.push(24);
+        environment.extend_from_slice(b"memory");
+        environment.extend_from_slice(&[2, 0, 0, 0, 0]);
+        let environment_len = u32::try_from(environment.len()).unwrap();
+
+        let mut data = vec![0; 0x4000];
+        data[..sandboxed.len()].copy_from_slice(&sandboxed);
+        data[0x1000] = b'f';
+        data[0x1020..][..environment.len()].copy_from_slice(&environment);
+        data[0x3000] = 7;
+
+        let mut body = Vec::new();
+        // Creates the memory with index 0, and writes 7 at address 0.
+        body.extend(i32_const(1));
+        body.extend(i32_const(-1));
+        body.extend_from_slice(&[0x10, 2, 0x1a]);
+        body.extend(i32_const(0));
+        body.extend(i32_const(0));
+        body.extend(i32_const(0x3000));
+        body.extend(i32_const(4));
+        body.extend_from_slice(&[0x10, 4, 0x1a]);
+        // Stores the result of calling `f` at 0x8010, and its return value at 0x8000.
+        body.extend(i32_const(0x8010));
+        body.extend(i32_const(0));
+        body.extend(pointer_size(0, sandboxed_len));
+        body.extend(pointer_size(0x1020, environment_len));
+        body.extend(i32_const(0));
+        body.extend_from_slice(&[0x10, 0]);
+        body.extend(pointer_size(0x1000, 1));
+        body.extend(pointer_size(0x1010, 1));
+        body.extend(i32_const(0x8000));
+        body.extend(i32_const(16));
+        body.extend(i32_const(0));
+        body.extend_from_slice(&[0x10, 1, 0x36, 2, 0]);
+        // Copies the value at address 4 to 0x8014, then again after the instance has been
+        // torn down to 0x8018.
+        for buffer in [0x8014, 0x8018] {
+            if buffer == 0x8018 {
+                body.extend(i32_const(0));
+                body.extend_from_slice(&[0x10, 5]);
+            }
+            body.extend(i32_const(0));
+            body.extend(i32_const(4));
+            body.extend(i32_const(buffer));
+            body.extend(i32_const(4));
+            body.extend_from_slice(&[0x10, 3, 0x1a]);
+        }
+        body.extend(pointer_size(0x8000, 28));
+
+        let runtime = sandbox_runtime(&data, &body);
+        match run_sandbox_runtime(&runtime, None, None) {
+            HostVm::Finished(finished) => assert_eq!(
+                finished.value().as_ref(),
+                &[
+                    1, 0, 7, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 42, 0, 0, 0, 42, 0,
+                    0, 0
+                ][..]
+            ),
+            _ => panic!(),
+        }
+    }
+
+    /// Builds a runtime whose `test` function verifies, as part of a batch, the ed25519
+    /// signature found at address 0x100 of the one-byte message `0x72` found at address 0x200
+    /// with the public key found at address 0x300. It returns the value returned by
+    /// `ext_crypto_ed25519_batch_verify_version_1` followed with the value returned by
+    /// `ext_crypto_finish_batch_verify_version_1`.
+    fn batch_verify_runtime(signature: [u8; 64], public_key: [u8; 32]) -> Vec<u8> {
+        let types = [
+            4, 0x60, 2, 0x7f, 0x7f, 1, 0x7e, 0x60, 0, 0, 0x60, 3, 0x7f, 0x7e, 0x7f, 1, 0x7f, 0x60,
+            0, 1, 0x7f,
+        ];
+
+        let mut data = vec![0; 0x400];
+        data[0x100..][..64].copy_from_slice(&signature);
+        data[0x200] = 0x72;
+        data[0x300..][..32].copy_from_slice(&public_key);
+
+        let mut body = Vec::new();
+        body.extend_from_slice(&[0x10, 0]);
+        body.extend(i32_const(0x8000));
+        body.extend(i32_const(0x100));
+        body.extend(pointer_size(0x200, 1));
+        body.extend(i32_const(0x300));
+        body.extend_from_slice(&[0x10, 1, 0x36, 2, 0]);
+        body.extend(i32_const(0x8004));
+        body.extend_from_slice(&[0x10, 2, 0x36, 2, 0]);
+        body.extend(pointer_size(0x8000, 8));
+
+        test_runtime(
+            &types,
+            &[
+                ("ext_crypto_start_batch_verify_version_1", 1),
+                ("ext_crypto_ed25519_batch_verify_version_1", 2),
+                ("ext_crypto_finish_batch_verify_version_1", 3),
+            ],
+            &data,
+            &body,
+        )
+    }
+
+    /// Runs the `test` function of the given runtime until it finishes, verifying the signature
+    /// batches by calling `on_batch`.
+    fn run_batch_verify_runtime(
+        runtime: &[u8],
+        mut on_batch: impl FnMut(SignatureBatchVerify) -> HostVm,
+    ) -> Vec<u8> {
+        let prototype = HostVmPrototype::new(Config {
+            module: runtime,
+            heap_pages: HeapPages::new(1),
+            exec_hint: vm::ExecHint::Oneshot,
+            allow_unresolved_imports: false,
+            max_memory_size: None,
+            metered: false,
+        })
+        .unwrap();
+
+        let mut vm = HostVm::from(prototype.run_no_param("test").unwrap());
+        loop {
+            vm = match vm {
+                HostVm::ReadyToRun(r) => r.run(),
+                HostVm::SignatureBatchVerify(req) => on_batch(req),
+                HostVm::Finished(finished) => return finished.value().as_ref().to_vec(),
+                _ => panic!(),
+            };
+        }
+    }
+
+    #[test]
+    fn batch_verification_yielded_to_caller() {
+        let runtime = batch_verify_runtime([0; 64], [0; 32]);
+
+        let mut num_batches = 0;
+        let output = run_batch_verify_runtime(&runtime, |req| {
+            num_batches += 1;
+            assert_eq!(req.signatures().len(), 1);
+            req.resume(true)
+        });
+        assert_eq!(num_batches, 1);
+        assert_eq!(output, [1, 0, 0, 0, 1, 0, 0, 0]);
+
+        // The outcome of the batch is the one reported by the caller.
+        let output = run_batch_verify_runtime(&runtime, |req| req.resume(false));
+        assert_eq!(output, [1, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn batch_verify_and_resume() {
+        // Test vector 2 of RFC 8032.
+        let public_key = <[u8; 32]>::try_from(
+            &hex::decode("3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c")
+                .unwrap()[..],
+        )
+        .unwrap();
+        let signature = <[u8; 64]>::try_from(
+            &hex::decode(
+                "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da\
+                 085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+            )
+            .unwrap()[..],
+        )
+        .unwrap();
+
+        let runtime = batch_verify_runtime(signature, public_key);
+        let output = run_batch_verify_runtime(&runtime, |req| {
+            assert!(req.signatures()[0].verify());
+            req.verify_and_resume()
+        });
+        assert_eq!(output, [1, 0, 0, 0, 1, 0, 0, 0]);
+
+        // Invalid signature.
+        let runtime = batch_verify_runtime([0; 64], public_key);
+        let output = run_batch_verify_runtime(&runtime, SignatureBatchVerify::verify_and_resume);
+        assert_eq!(output, [1, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn host_calls_traced() {
+        let runtime = batch_verify_runtime([0; 64], [0; 32]);
+
+        for trace in [false, true] {
+            let mut prototype = HostVmPrototype::new(Config {
+                module: &runtime,
+                heap_pages: HeapPages::new(1),
+                exec_hint: vm::ExecHint::Oneshot,
+                allow_unresolved_imports: false,
+                max_memory_size: None,
+                metered: false,
+            })
+            .unwrap();
+            prototype.set_trace_host_calls(trace);
+
+            let finished = match prototype.run_no_param("test").unwrap().run() {
+                HostVm::SignatureBatchVerify(req) => match req.resume(true) {
+                    HostVm::ReadyToRun(r) => match r.run() {
+                        HostVm::Finished(finished) => finished,
+                        _ => panic!(),
+                    },
+                    _ => panic!(),
+                },
+                _ => panic!(),
+            };
+
+            if !trace {
+                assert!(finished.host_calls().is_empty());
+                continue;
+            }
+
+            let calls = finished
+                .host_calls()
+                .iter()
+                .map(|call| (call.function, call.params_sizes.clone()))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                calls,
+                [
+                    ("ext_crypto_start_batch_verify_version_1", vec![]),
+                    ("ext_crypto_ed25519_batch_verify_version_1", vec![64, 1, 32]),
+                    ("ext_crypto_finish_batch_verify_version_1", vec![]),
+                ]
+            );
+            assert!(finished.into_prototype().trace_host_calls());
+        }
+    }
+
+    #[test]
+    fn metered_infinite_loop_out_of_fuel() {
+        let types = [1, 0x60, 2, 0x7f, 0x7f, 1, 0x7e];
+        let mut body = vec![0x03, 0x40, 0x0c, 0, 0x0b];
+        body.extend(pointer_size(0, 0));
+        let looping = test_runtime(&types, &[], &[], &body);
+        let returning = test_runtime(&types, &[], &[], &pointer_size(0, 0));
+
+        let run = |runtime: &[u8], fuel: u64| {
+            let mut prototype = HostVmPrototype::new(Config {
+                module: runtime,
+                heap_pages: HeapPages::new(1),
+                exec_hint: vm::ExecHint::Oneshot,
+                allow_unresolved_imports: false,
+                max_memory_size: None,
+                metered: true,
+            })
+            .unwrap();
+            assert!(prototype.metered());
+            prototype.set_fuel(Some(fuel));
+            prototype.run_no_param("test").unwrap().run()
+        };
+
+        assert!(matches!(
+            run(&looping, 100_000),
+            HostVm::Error {
+                error: Error::OutOfFuel,
+                ..
+            }
+        ));
+        assert!(matches!(run(&returning, 100_000), HostVm::Finished(_)));
+        assert!(matches!(
+            run(&returning, 0),
+            HostVm::Error {
+                error: Error::OutOfFuel,
+                ..
+            }
+        ));
+    }
+
+    /// Runs the `test` function of the given runtime until it finishes, answering the requests
+    /// other than [`HostVm::ReadyToRun`] by calling `on_request`. Returns the value returned by
+    /// `test`.
+    fn run_test_runtime(runtime: &[u8], mut on_request: impl FnMut(HostVm) -> HostVm) -> Vec<u8> {
+        let prototype = HostVmPrototype::new(Config {
+            module: runtime,
+            heap_pages: HeapPages::new(1),
+            exec_hint: vm::ExecHint::Oneshot,
+            allow_unresolved_imports: false,
+            max_memory_size: None,
+            metered: false,
+        })
+        .unwrap();
+
+        let mut vm = HostVm::from(prototype.run_no_param("test").unwrap());
+        loop {
+            vm = match vm {
+                HostVm::ReadyToRun(r) => r.run(),
+                HostVm::Finished(finished) => return finished.value().as_ref().to_vec(),
+                HostVm::Error { error, .. } => panic!("{}", error),
+                other => on_request(other),
+            };
+        }
+    }
+
+    /// Builds a runtime whose `test` function executes `body`. The runtime imports, in this
+    /// order, `ext_default_child_storage_get_version_1`, `ext_default_child_storage_set_version_1`,
+    /// `ext_default_child_storage_clear_version_1`,
+    /// `ext_default_child_storage_storage_kill_version_3`, and
+    /// `ext_default_child_storage_root_version_1`. Its memory contains the child trie `child` at
+    /// address 0, the key `key` at address 0x10, the value `value` at address 0x20, and the
+    /// SCALE encoding of `Some(5u32)` at address 0x30.
+    fn child_storage_runtime(body: &[u8]) -> Vec<u8> {
+        let types = [
+            5, 0x60, 2, 0x7f, 0x7f, 1, 0x7e, 0x60, 2, 0x7e, 0x7e, 1, 0x7e, 0x60, 3, 0x7e, 0x7e,
+            0x7e, 0, 0x60, 2, 0x7e, 0x7e, 0, 0x60, 1, 0x7e, 1, 0x7e,
+        ];
+
+        let mut data = vec![0; 0x40];
+        data[..5].copy_from_slice(b"child");
+        data[0x10..][..3].copy_from_slice(b"key");
+        data[0x20..][..5].copy_from_slice(b"value");
+        data[0x30..][..5].copy_from_slice(&[1, 5, 0, 0, 0]);
+
+        test_runtime(
+            &types,
+            &[
+                ("ext_default_child_storage_get_version_1", 1),
+                ("ext_default_child_storage_set_version_1", 2),
+                ("ext_default_child_storage_clear_version_1", 3),
+                ("ext_default_child_storage_storage_kill_version_3", 1),
+                ("ext_default_child_storage_root_version_1", 4),
+            ],
+            &data,
+            body,
+        )
+    }
+
+    #[test]
+    fn child_storage_get() {
+        let mut body = Vec::new();
+        body.extend(pointer_size(0, 5));
+        body.extend(pointer_size(0x10, 3));
+        body.extend_from_slice(&[0x10, 0]);
+        let runtime = child_storage_runtime(&body);
+
+        for value in [None, Some(&b"foo"[..])] {
+            let output = run_test_runtime(&runtime, |req| match req {
+                HostVm::ExternalStorageGet(req) => {
+                    assert_eq!(req.child_trie().unwrap().as_ref(), b"child");
+                    assert_eq!(req.key().as_ref(), b"key");
+                    req.resume_full_value(value)
+                }
+                _ => panic!(),
+            });
+
+            match value {
+                Some(value) => {
+                    assert_eq!(output[..2], [1, 12]);
+                    assert_eq!(&output[2..], value);
+                }
+                None => assert_eq!(output, [0]),
+            }
+        }
+    }
+
+    #[test]
+    fn child_storage_set_and_clear() {
+        let mut body = Vec::new();
+        body.extend(pointer_size(0, 5));
+        body.extend(pointer_size(0x10, 3));
+        body.extend(pointer_size(0x20, 5));
+        body.extend_from_slice(&[0x10, 1]);
+        body.extend(pointer_size(0, 5));
+        body.extend(pointer_size(0x10, 3));
+        body.extend_from_slice(&[0x10, 2]);
+        body.extend(pointer_size(0, 0));
+        let runtime = child_storage_runtime(&body);
+
+        let mut changes = Vec::new();
+        run_test_runtime(&runtime, |req| match req {
+            HostVm::ExternalStorageSet(req) => {
+                changes.push((
+                    req.child_trie().unwrap().as_ref().to_vec(),
+                    req.key().as_ref().to_vec(),
+                    req.value().map(|v| v.as_ref().to_vec()),
+                ));
+                req.resume()
+            }
+            _ => panic!(),
+        });
+
+        assert_eq!(
+            changes,
+            [
+                (b"child".to_vec(), b"key".to_vec(), Some(b"value".to_vec())),
+                (b"child".to_vec(), b"key".to_vec(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn child_storage_kill() {
+        let mut body = Vec::new();
+        body.extend(pointer_size(0, 5));
+        body.extend(pointer_size(0x30, 5));
+        body.extend_from_slice(&[0x10, 3]);
+        let runtime = child_storage_runtime(&body);
+
+        for some_keys_remain in [false, true] {
+            let output = run_test_runtime(&runtime, |req| match req {
+                HostVm::ExternalStorageClearPrefix(req) => {
+                    assert_eq!(req.child_trie().unwrap().as_ref(), b"child");
+                    assert!(req.prefix().as_ref().is_empty());
+                    assert_eq!(req.max_keys_to_remove(), Some(5));
+                    req.resume(3, some_keys_remain)
+                }
+                _ => panic!(),
+            });
+
+            // SCALE-encoded `KillStorageResult`.
+            assert_eq!(output, [if some_keys_remain { 1 } else { 0 }, 3, 0, 0, 0]);
+        }
+    }
+
+    #[test]
+    fn child_storage_root() {
+        let mut body = Vec::new();
+        body.extend(pointer_size(0, 5));
+        body.extend_from_slice(&[0x10, 4]);
+        let runtime = child_storage_runtime(&body);
+
+        let output = run_test_runtime(&runtime, |req| match req {
+            HostVm::ExternalStorageRoot(req) => {
+                assert_eq!(req.child_trie().unwrap().as_ref(), b"child");
+                req.resume(&[0xaa; 32])
+            }
+            _ => panic!(),
+        });
+        assert_eq!(output, [0xaa; 32]);
+    }
+
+    /// Builds a runtime whose `test` function executes `body`. The runtime imports, in this
+    /// order, `ext_offchain_timestamp_version_1`, `ext_offchain_random_seed_version_1`,
+    /// `ext_offchain_local_storage_set_version_1`, `ext_offchain_local_storage_get_version_1`,
+    /// `ext_offchain_local_storage_compare_and_set_version_1`,
+    /// `ext_offchain_local_storage_clear_version_1`, `ext_offchain_http_request_start_version_1`,
+    /// and `ext_offchain_is_validator_version_1`. Its memory contains the key `key` at address
+    /// 0x10, the value `value` at address 0x20, and the SCALE encoding of `Some(b"old")` at
+    /// address 0x30.
+    fn offchain_runtime(body: &[u8]) -> Vec<u8> {
+        let types = [
+            8, 0x60, 2, 0x7f, 0x7f, 1, 0x7e, 0x60, 0, 1, 0x7e, 0x60, 0, 1, 0x7f, 0x60, 3, 0x7f,
+            0x7e, 0x7e, 0, 0x60, 2, 0x7f, 0x7e, 1, 0x7e, 0x60, 4, 0x7f, 0x7e, 0x7e, 0x7e, 1, 0x7f,
+            0x60, 2, 0x7f, 0x7e, 0, 0x60, 3, 0x7e, 0x7e, 0x7e, 1, 0x7e,
+        ];
+
+        let mut data = vec![0; 0x40];
+        data[0x10..][..3].copy_from_slice(b"key");
+        data[0x20..][..5].copy_from_slice(b"value");
+        data[0x30..][..5].copy_from_slice(&[1, 12, b'o', b'l', b'd']);
+
+        test_runtime(
+            &types,
+            &[
+                ("ext_offchain_timestamp_version_1", 1),
+                ("ext_offchain_random_seed_version_1", 2),
+                ("ext_offchain_local_storage_set_version_1", 3),
+                ("ext_offchain_local_storage_get_version_1", 4),
+                ("ext_offchain_local_storage_compare_and_set_version_1", 5),
+                ("ext_offchain_local_storage_clear_version_1", 6),
+                ("ext_offchain_http_request_start_version_1", 7),
+                ("ext_offchain_is_validator_version_1", 2),
+            ],
+            &data,
+            body,
+        )
+    }
+
+    #[test]
+    fn offchain_timestamp() {
+        // Stores the timestamp at 0x100.
+        let mut body = Vec::new();
+        body.extend(i32_const(0x100));
+        body.extend_from_slice(&[0x10, 0, 0x37, 3, 0]);
+        body.extend(pointer_size(0x100, 8));
+        let runtime = offchain_runtime(&body);
+
+        let output = run_test_runtime(&runtime, |req| match req {
+            HostVm::OffchainTimestamp(req) => req.resume(1_234_567_890_123),
+            _ => panic!(),
+        });
+        assert_eq!(output, 1_234_567_890_123u64.to_le_bytes());
+    }
+
+    #[test]
+    fn offchain_random_seed() {
+        // Returns the 32 bytes found at the pointer returned by the host function.
+        let mut body = vec![0x10, 1, 0xad, 0x42];
+        body.extend(sleb128(32 << 32));
+        body.push(0x84);
+        let runtime = offchain_runtime(&body);
+
+        let output = run_test_runtime(&runtime, |req| match req {
+            HostVm::OffchainRandomSeed(req) => req.resume([0x5a; 32]),
+            _ => panic!(),
+        });
+        assert_eq!(output, [0x5a; 32]);
+    }
+
+    #[test]
+    fn offchain_local_storage_get() {
+        let mut body = Vec::new();
+        body.extend(i32_const(2));
+        body.extend(pointer_size(0x10, 3));
+        body.extend_from_slice(&[0x10, 3]);
+        let runtime = offchain_runtime(&body);
+
+        for value in [None, Some(&b"foo"[..])] {
+            let output = run_test_runtime(&runtime, |req| match req {
+                HostVm::ExternalOffchainLocalStorageGet(req) => {
+                    assert_eq!(req.kind(), OffchainStorageKind::Local);
+                    assert_eq!(req.key().as_ref(), b"key");
+                    req.resume(value)
+                }
+                _ => panic!(),
+            });
+
+            match value {
+                Some(value) => {
+                    assert_eq!(output[..2], [1, 12]);
+                    assert_eq!(&output[2..], value);
+                }
+                None => assert_eq!(output, [0]),
+            }
+        }
+    }
+
+    #[test]
+    fn offchain_local_storage_set_and_clear() {
+        let mut body = Vec::new();
+        body.extend(i32_const(1));
+        body.extend(pointer_size(0x10, 3));
+        body.extend(pointer_size(0x20, 5));
+        body.extend_from_slice(&[0x10, 2]);
+        body.extend(i32_const(2));
+        body.extend(pointer_size(0x10, 3));
+        body.extend_from_slice(&[0x10, 5]);
+        body.extend(pointer_size(0, 0));
+        let runtime = offchain_runtime(&body);
+
+        let mut changes = Vec::new();
+        run_test_runtime(&runtime, |req| match req {
+            HostVm::ExternalOffchainLocalStorageSet(req) => {
+                changes.push((
+                    req.kind(),
+                    req.key().as_ref().to_vec(),
+                    req.value().map(|v| v.as_ref().to_vec()),
+                ));
+                req.resume()
+            }
+            _ => panic!(),
+        });
+
+        assert_eq!(
+            changes,
+            [
+                (
+                    OffchainStorageKind::Persistent,
+                    b"key".to_vec(),
+                    Some(b"value".to_vec())
+                ),
+                (OffchainStorageKind::Local, b"key".to_vec(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn offchain_local_storage_compare_and_set() {
+        // Stores the value returned by the host function at 0x100.
+        let mut body = Vec::new();
+        body.extend(i32_const(0x100));
+        body.extend(i32_const(1));
+        body.extend(pointer_size(0x10, 3));
+        body.extend(pointer_size(0x30, 5));
+        body.extend(pointer_size(0x20, 5));
+        body.extend_from_slice(&[0x10, 4, 0x36, 2, 0]);
+        body.extend(pointer_size(0x100, 4));
+        let runtime = offchain_runtime(&body);
+
+        for replaced in [false, true] {
+            let output = run_test_runtime(&runtime, |req| match req {
+                HostVm::ExternalOffchainLocalStorageCompareAndSet(req) => {
+                    assert_eq!(req.kind(), OffchainStorageKind::Persistent);
+                    assert_eq!(req.key().as_ref(), b"key");
+                    assert_eq!(req.old_value(), Some(&b"old"[..]));
+                    assert_eq!(req.value().as_ref(), b"value");
+                    req.resume(replaced)
+                }
+                _ => panic!(),
+            });
+            assert_eq!(output, [if replaced { 1 } else { 0 }, 0, 0, 0]);
+        }
+    }
+
+    #[test]
+    fn offchain_local_storage_unknown_kind() {
+        let mut body = Vec::new();
+        body.extend(i32_const(3));
+        body.extend(pointer_size(0x10, 3));
+        body.extend_from_slice(&[0x10, 3]);
+        let runtime = offchain_runtime(&body);
+
+        let prototype = HostVmPrototype::new(Config {
+            module: &runtime,
+            heap_pages: HeapPages::new(1),
+            exec_hint: vm::ExecHint::Oneshot,
+            allow_unresolved_imports: false,
+            max_memory_size: None,
+            metered: false,
+        })
+        .unwrap();
+        assert!(matches!(
+            prototype.run_no_param("test").unwrap().run(),
+            HostVm::Error {
+                error: Error::UnknownOffchainStorageKind { .. },
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn offchain_unsupported_functions() {
+        // HTTP requests can't be started.
+        let mut body = Vec::new();
+        body.extend(pointer_size(0x10, 3));
+        body.extend(pointer_size(0x20, 5));
+        body.extend(pointer_size(0x20, 1));
+        body.extend_from_slice(&[0x10, 6]);
+        let runtime = offchain_runtime(&body);
+        assert_eq!(run_test_runtime(&runtime, |_| panic!()), [1]);
+
+        // The node is never a validator. Stores the value returned by the host function at 0x100.
+        let mut body = Vec::new();
+        body.extend(i32_const(0x100));
+        body.extend_from_slice(&[0x10, 7, 0x36, 2, 0]);
+        body.extend(pointer_size(0x100, 4));
+        let runtime = offchain_runtime(&body);
+        assert_eq!(run_test_runtime(&runtime, |_| panic!()), [0, 0, 0, 0]);
+    }
 }
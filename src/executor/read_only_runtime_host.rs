@@ -19,13 +19,16 @@
 
 // TODO: more docs
 
-use crate::executor::{self, host, vm};
+use crate::{
+    executor::{self, host, vm},
+    trie,
+};
 
 use alloc::{
     string::{String, ToString as _},
     vec::Vec,
 };
-use core::{fmt, iter};
+use core::{convert::TryFrom, fmt, iter};
 
 /// Configuration for [`run`].
 pub struct Config<'a, TParams> {
@@ -109,6 +112,8 @@ pub enum ErrorDetail {
     /// Size of the logs generated by the runtime exceeds the limit.
     LogsTooLong,
     ForbiddenHostCall,
+    /// The value of a `:child_storage:default:` key in the storage isn't a valid trie root hash.
+    InvalidChildTrieRoot,
 }
 
 /// Current state of the execution.
@@ -147,7 +152,16 @@ impl StorageGet {
     /// Returns the key whose value must be passed to [`StorageGet::inject_value`].
     pub fn key(&'_ self) -> impl Iterator<Item = impl AsRef<[u8]> + '_> + '_ {
         match &self.inner.vm {
-            host::HostVm::ExternalStorageGet(req) => iter::once(req.key()),
+            host::HostVm::ExternalStorageGet(req) => {
+                either::Left(iter::once(either::Left(req.key())))
+            }
+
+            // The root of a child trie is found in the main trie.
+            host::HostVm::ExternalStorageRoot(req) => either::Right(
+                iter::once(either::Right(either::Left(&b":child_storage:default:"[..]))).chain(
+                    iter::once(either::Right(either::Right(req.child_trie().unwrap()))),
+                ),
+            ),
 
             // We only create a `StorageGet` if the state is one of the above.
             _ => unreachable!(),
@@ -164,6 +178,18 @@ impl StorageGet {
         })
     }
 
+    /// If `Some`, the key must be read from the child trie whose key is returned rather than from
+    /// the main trie. The returned value doesn't include the `:child_storage:default:` prefix.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        match &self.inner.vm {
+            host::HostVm::ExternalStorageGet(req) => req.child_trie(),
+            host::HostVm::ExternalStorageRoot(_) => None,
+
+            // We only create a `StorageGet` if the state is one of the above.
+            _ => unreachable!(),
+        }
+    }
+
     /// Injects the corresponding storage value.
     pub fn inject_value(
         mut self,
@@ -182,6 +208,23 @@ impl StorageGet {
                 // TODO: should actually report the offset and max_size in the API
                 self.inner.vm = req.resume_full_value(value.as_ref().map(|v| &v[..]));
             }
+            host::HostVm::ExternalStorageRoot(req) => {
+                // An absent value means that the child trie is empty.
+                let hash = match value {
+                    Some(value) => match <[u8; 32]>::try_from(&value[..]) {
+                        Ok(hash) => hash,
+                        Err(_) => {
+                            return RuntimeHostVm::Finished(Err(Error {
+                                detail: ErrorDetail::InvalidChildTrieRoot,
+                                prototype: host::HostVm::ExternalStorageRoot(req).into_prototype(),
+                            }))
+                        }
+                    },
+                    None => trie::empty_trie_merkle_value(),
+                };
+
+                self.inner.vm = req.resume(&hash);
+            }
 
             // We only create a `StorageGet` if the state is one of the above.
             _ => unreachable!(),
@@ -206,6 +249,15 @@ impl NextKey {
         }
     }
 
+    /// If `Some`, the key must be searched in the child trie whose key is returned rather than
+    /// in the main trie. The returned value doesn't include the `:child_storage:default:` prefix.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        match &self.inner.vm {
+            host::HostVm::ExternalStorageNextKey(req) => req.child_trie(),
+            _ => unreachable!(),
+        }
+    }
+
     /// Injects the key.
     ///
     /// # Panic
@@ -302,11 +354,14 @@ impl Inner {
                     // upgrades are quite uncommon and that a caching system is rather non-trivial
                     // to set up, the approach of recompiling every single time is preferred here.
                     // TODO: number of heap pages?! we use the default here, but not sure whether that's correct or if we have to take the current heap pages
-                    let vm_prototype = match host::HostVmPrototype::new(
-                        req.wasm_code(),
-                        executor::DEFAULT_HEAP_PAGES,
-                        vm::ExecHint::Oneshot,
-                    ) {
+                    let vm_prototype = match host::HostVmPrototype::new(host::Config {
+                        module: req.wasm_code(),
+                        heap_pages: executor::DEFAULT_HEAP_PAGES,
+                        exec_hint: vm::ExecHint::Oneshot,
+                        allow_unresolved_imports: false,
+                        max_memory_size: None,
+                        metered: false,
+                    }) {
                         Ok(w) => w,
                         Err(_) => {
                             self.vm = req.resume(Err(()));
@@ -325,8 +380,22 @@ impl Inner {
                 }
 
                 host::HostVm::ExternalStorageRoot(req) => {
+                    // As the storage can't be modified, the root of a child trie is always the
+                    // value found in the main trie.
+                    let is_child_trie = req.child_trie().is_some();
                     self.vm = req.into();
-                    return RuntimeHostVm::StorageRoot(StorageRoot { inner: self });
+                    if is_child_trie {
+                        return RuntimeHostVm::StorageGet(StorageGet { inner: self });
+                    } else {
+                        return RuntimeHostVm::StorageRoot(StorageRoot { inner: self });
+                    }
+                }
+
+                host::HostVm::SignatureBatchVerify(req) => {
+                    // Read-only calls, such as the validation of a transaction, only verify a
+                    // small number of signatures. The batch is thus verified on the current
+                    // thread.
+                    self.vm = req.verify_and_resume();
                 }
 
                 host::HostVm::LogEmit(req) => {
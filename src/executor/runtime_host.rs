@@ -25,12 +25,14 @@
 //!
 //! In addition to the functionalities provided by the [`host`] module, the `runtime_host` module:
 //!
-//! - Keeps track of the changes to the storage and offchain storage made by the execution, and
-//!   provides them at the end. Any storage access takes into account the intermediary list of
-//!   changes.
+//! - Keeps track of the changes to the storage, including child tries, and offchain storage made
+//!   by the execution, and provides them at the end. Any storage access takes into account the
+//!   intermediary list of changes.
 //! - Keeps track of the logs generated by the call and concatenates them into a [`String`].
 //! - Automatically handles some externalities, such as calculating the Merkle root or storage
 //!   transactions.
+//! - Keeps the roots of the child tries stored in the main trie up to date with the changes made
+//!   to these child tries.
 //!
 //! These additional features considerably reduces the number of externals concepts to plug to
 //! the virtual machine.
@@ -39,12 +41,12 @@
 
 use crate::{
     executor::{self, host, vm},
-    trie::calculate_root,
+    trie::{self, calculate_root},
     util,
 };
 
 use alloc::{
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet},
     string::{String, ToString as _},
     vec::Vec,
 };
@@ -71,6 +73,10 @@ pub struct Config<'a, TParams> {
     /// execution will be pushed over the value in this field.
     pub storage_top_trie_changes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
 
+    /// Initial state of [`Success::storage_child_tries_changes`]. The changes made during this
+    /// execution will be pushed over the value in this field.
+    pub storage_child_tries_changes: BTreeMap<Vec<u8>, BTreeMap<Vec<u8>, Option<Vec<u8>>>>,
+
     /// Initial state of [`Success::offchain_storage_changes`]. The changes made during this
     /// execution will be pushed over the value in this field.
     pub offchain_storage_changes: HashMap<Vec<u8>, Option<Vec<u8>>, fnv::FnvBuildHasher>,
@@ -87,11 +93,17 @@ pub fn run(
             .into(),
         top_trie_changes: config.storage_top_trie_changes,
         top_trie_transaction_revert: None,
+        // The roots of the child tries found in the initial changes might not have been
+        // written in the main trie yet.
+        stale_child_tries_roots: config.storage_child_tries_changes.keys().cloned().collect(),
+        child_tries_changes: config.storage_child_tries_changes,
+        child_tries_transaction_revert: None,
         offchain_storage_changes: config.offchain_storage_changes,
         top_trie_root_calculation_cache: Some(
             config.top_trie_root_calculation_cache.unwrap_or_default(),
         ),
         root_calculation: None,
+        root_calculation_child_trie: None,
         logs: String::new(),
     }
     .run())
@@ -105,6 +117,13 @@ pub struct Success {
     pub virtual_machine: SuccessVirtualMachine,
     /// List of changes to the storage top trie that the block performs.
     pub storage_top_trie_changes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    /// List of changes to the storage child tries that the block performs, indexed by child
+    /// trie. The keys of this map don't include the `:child_storage:default:` prefix.
+    ///
+    /// > **Note**: The roots of the child tries found in [`Success::storage_top_trie_changes`]
+    /// >           are only up to date if the runtime has calculated the root of the top trie
+    /// >           after the last modification of these child tries.
+    pub storage_child_tries_changes: BTreeMap<Vec<u8>, BTreeMap<Vec<u8>, Option<Vec<u8>>>>,
     /// List of changes to the offchain storage that this block performs.
     pub offchain_storage_changes: HashMap<Vec<u8>, Option<Vec<u8>>, fnv::FnvBuildHasher>,
     /// Cache used for calculating the top trie root.
@@ -157,6 +176,11 @@ pub enum ErrorDetail {
     },
     /// Size of the logs generated by the runtime exceeds the limit.
     LogsTooLong,
+    /// Runtime has called a host function that isn't available during this call, such as the
+    /// functions accessing the local storage of the offchain worker.
+    ForbiddenHostCall,
+    /// Storage requested by the runtime couldn't be provided by the user.
+    StorageUnavailable,
 }
 
 /// Current state of the execution.
@@ -170,6 +194,8 @@ pub enum RuntimeHostVm {
     PrefixKeys(PrefixKeys),
     /// Fetching the key that follows a given one is required in order to continue.
     NextKey(NextKey),
+    /// Verifying whether a batch of signatures is valid is required in order to continue.
+    SignatureBatchVerify(SignatureBatchVerify),
 }
 
 impl RuntimeHostVm {
@@ -181,6 +207,7 @@ impl RuntimeHostVm {
             RuntimeHostVm::StorageGet(inner) => inner.inner.vm.into_prototype(),
             RuntimeHostVm::PrefixKeys(inner) => inner.inner.vm.into_prototype(),
             RuntimeHostVm::NextKey(inner) => inner.inner.vm.into_prototype(),
+            RuntimeHostVm::SignatureBatchVerify(inner) => inner.inner.vm.into_prototype(),
         }
     }
 }
@@ -232,6 +259,24 @@ impl StorageGet {
         })
     }
 
+    /// If `Some`, the key must be read from the child trie whose key is returned rather than from
+    /// the main trie. The returned value doesn't include the `:child_storage:default:` prefix.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        match &self.inner.vm {
+            host::HostVm::ExternalStorageGet(req) => req.child_trie().map(either::Left),
+            host::HostVm::ExternalStorageRoot(_) => self
+                .inner
+                .root_calculation_child_trie
+                .as_ref()
+                .map(either::Right),
+            host::HostVm::ExternalStorageAppend(_)
+            | host::HostVm::ExternalStorageChangesRoot(_) => None,
+
+            // We only create a `StorageGet` if the state is one of the above.
+            _ => unreachable!(),
+        }
+    }
+
     /// Injects the corresponding storage value.
     pub fn inject_value(
         mut self,
@@ -259,11 +304,13 @@ impl StorageGet {
                     .insert(req.key().as_ref().to_vec(), Some(value));
                 self.inner.vm = req.resume();
             }
-            host::HostVm::ExternalStorageRoot(_) => {
+            host::HostVm::ExternalStorageRoot(ref req) => {
+                let state_trie_version = req.state_trie_version();
                 if let calculate_root::RootMerkleValueCalculation::StorageValue(value_request) =
                     self.inner.root_calculation.take().unwrap()
                 {
-                    self.inner.root_calculation = Some(value_request.inject(value));
+                    self.inner.root_calculation =
+                        Some(value_request.inject(value.map(|v| (v, state_trie_version))));
                 } else {
                     // We only create a `StorageGet` if the state is `StorageValue`.
                     panic!()
@@ -284,6 +331,16 @@ impl StorageGet {
 
         self.inner.run()
     }
+
+    /// Stops the execution with [`ErrorDetail::StorageUnavailable`]. Must be used when the
+    /// requested storage can't be provided, for example because it belongs to a child trie
+    /// that isn't stored.
+    pub fn inject_unavailable(self) -> RuntimeHostVm {
+        RuntimeHostVm::Finished(Err(Error {
+            detail: ErrorDetail::StorageUnavailable,
+            prototype: self.inner.vm.into_prototype(),
+        }))
+    }
 }
 
 /// Fetching the list of keys with a given prefix is required in order to continue.
@@ -304,12 +361,80 @@ impl PrefixKeys {
         }
     }
 
+    /// If `Some`, the keys must be loaded from the child trie whose key is returned rather than
+    /// from the main trie. The returned value doesn't include the `:child_storage:default:`
+    /// prefix.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        match &self.inner.vm {
+            host::HostVm::ExternalStorageClearPrefix(req) => req.child_trie().map(either::Left),
+            host::HostVm::ExternalStorageRoot { .. } => self
+                .inner
+                .root_calculation_child_trie
+                .as_ref()
+                .map(either::Right),
+
+            // We only create a `PrefixKeys` if the state is one of the above.
+            _ => unreachable!(),
+        }
+    }
+
     /// Injects the list of keys ordered lexicographically.
     pub fn inject_keys_ordered(
         mut self,
         keys: impl Iterator<Item = impl AsRef<[u8]>>,
     ) -> RuntimeHostVm {
         match self.inner.vm {
+            host::HostVm::ExternalStorageClearPrefix(req) if req.child_trie().is_some() => {
+                let child_trie = req.child_trie().unwrap().as_ref().to_vec();
+                let prefix = req.prefix().as_ref().to_vec();
+
+                let max_keys_to_remove = req.max_keys_to_remove();
+                let mut keys_removed_so_far = 0u32;
+                let mut some_keys_remain = false;
+
+                // Keys to remove, both from the list passed by the user and from the pending
+                // changes.
+                let mut to_remove = Vec::new();
+                for key in keys {
+                    if max_keys_to_remove.map_or(false, |max| keys_removed_so_far >= max) {
+                        some_keys_remain = true;
+                        break;
+                    }
+
+                    to_remove.push(key.as_ref().to_vec());
+                    keys_removed_so_far = keys_removed_so_far.wrapping_add(1);
+                }
+
+                let changes = self
+                    .inner
+                    .child_tries_changes
+                    .entry(child_trie.clone())
+                    .or_default();
+                // TODO: O(n) complexity here
+                to_remove.extend(
+                    changes
+                        .iter()
+                        .filter(|(key, value)| key.starts_with(&prefix) && value.is_some())
+                        .map(|(key, _)| key.clone()),
+                );
+
+                for key in to_remove {
+                    let previous_value = changes.insert(key.clone(), None);
+                    if let Some(child_tries_transaction_revert) =
+                        self.inner.child_tries_transaction_revert.as_mut()
+                    {
+                        if let Entry::Vacant(entry) =
+                            child_tries_transaction_revert.entry((child_trie.clone(), key))
+                        {
+                            entry.insert(previous_value);
+                        }
+                    }
+                }
+
+                self.inner.stale_child_tries_roots.insert(child_trie);
+                self.inner.vm = req.resume(keys_removed_so_far, some_keys_remain);
+            }
+
             host::HostVm::ExternalStorageClearPrefix(req) => {
                 // TODO: use prefix_remove_update once optimized
                 //top_trie_root_calculation_cache.prefix_remove_update(storage_key);
@@ -320,10 +445,12 @@ impl PrefixKeys {
                 // to avoid converting the `u32` to a `usize`.
                 let max_keys_to_remove = req.max_keys_to_remove();
                 let mut keys_removed_so_far = 0u32;
+                let mut some_keys_remain = false;
 
                 for key in keys {
                     // Enforce the maximum number of keys to remove.
                     if max_keys_to_remove.map_or(false, |max| keys_removed_so_far >= max) {
+                        some_keys_remain = true;
                         break;
                     }
 
@@ -370,25 +497,29 @@ impl PrefixKeys {
                     *value = None;
                 }
 
-                self.inner.vm = req.resume();
+                self.inner.vm = req.resume(keys_removed_so_far, some_keys_remain);
             }
 
             host::HostVm::ExternalStorageRoot { .. } => {
                 if let calculate_root::RootMerkleValueCalculation::AllKeys(all_keys) =
                     self.inner.root_calculation.take().unwrap()
                 {
+                    let changes = match &self.inner.root_calculation_child_trie {
+                        Some(child_trie) => self.inner.child_tries_changes.get(child_trie),
+                        None => Some(&self.inner.top_trie_changes),
+                    };
+
                     // TODO: overhead
                     let mut list = keys
                         .filter(|v| {
-                            self.inner
-                                .top_trie_changes
-                                .get(v.as_ref())
+                            changes
+                                .and_then(|changes| changes.get(v.as_ref()))
                                 .map_or(true, |v| v.is_some())
                         })
                         .map(|v| v.as_ref().to_vec())
                         .collect::<HashSet<_, fnv::FnvBuildHasher>>();
                     // TODO: slow to iterate over everything?
-                    for (key, value) in self.inner.top_trie_changes.iter() {
+                    for (key, value) in changes.into_iter().flatten() {
                         if value.is_none() {
                             continue;
                         }
@@ -408,6 +539,16 @@ impl PrefixKeys {
 
         self.inner.run()
     }
+
+    /// Stops the execution with [`ErrorDetail::StorageUnavailable`]. Must be used when the
+    /// requested storage can't be provided, for example because it belongs to a child trie
+    /// that isn't stored.
+    pub fn inject_unavailable(self) -> RuntimeHostVm {
+        RuntimeHostVm::Finished(Err(Error {
+            detail: ErrorDetail::StorageUnavailable,
+            prototype: self.inner.vm.into_prototype(),
+        }))
+    }
 }
 
 /// Fetching the key that follows a given one is required in order to continue.
@@ -433,6 +574,15 @@ impl NextKey {
         }
     }
 
+    /// If `Some`, the key must be searched in the child trie whose key is returned rather than
+    /// in the main trie. The returned value doesn't include the `:child_storage:default:` prefix.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        match &self.inner.vm {
+            host::HostVm::ExternalStorageNextKey(req) => req.child_trie(),
+            _ => unreachable!(),
+        }
+    }
+
     /// Injects the key.
     ///
     /// # Panic
@@ -458,12 +608,16 @@ impl NextKey {
                 // The next key can be either the one passed by the user or one key in the current
                 // pending storage changes that has been inserted during the execution.
                 // As such, find the "next key" in the list of overlay changes.
-                let in_overlay = self
-                    .inner
-                    .top_trie_changes
-                    .range(requested_key.to_vec()..) // TODO: to_vec() :-/
-                    .find(|(k, _)| &***k > requested_key)
-                    .map(|(k, v)| (k, v.is_some()));
+                let changes = match req.child_trie() {
+                    Some(child_trie) => self.inner.child_tries_changes.get(child_trie.as_ref()),
+                    None => Some(&self.inner.top_trie_changes),
+                };
+                let in_overlay = changes.and_then(|changes| {
+                    changes
+                        .range(requested_key.to_vec()..) // TODO: to_vec() :-/
+                        .find(|(k, _)| &***k > requested_key)
+                        .map(|(k, v)| (k, v.is_some()))
+                });
 
                 let outcome = match (key, in_overlay) {
                     (Some(a), Some((b, true))) if a <= &b[..] => Some(a),
@@ -475,8 +629,7 @@ impl NextKey {
                         // The next key according to the parent storage has been erased earlier in
                         // the block execution. It is necessary to ask the user again, this time
                         // for the key after the one that has been erased.
-                        // This `clone()` is necessary, as `b` borrows from
-                        // `self.inner.top_trie_changes`.
+                        // This `clone()` is necessary, as `b` borrows from the pending changes.
                         let key_overwrite = Some(b.clone());
                         drop(req_key); // Solves borrowing errors.
                         self.inner.vm = host::HostVm::ExternalStorageNextKey(req);
@@ -505,6 +658,53 @@ impl NextKey {
 
         self.inner.run()
     }
+
+    /// Stops the execution with [`ErrorDetail::StorageUnavailable`]. Must be used when the
+    /// requested storage can't be provided, for example because it belongs to a child trie
+    /// that isn't stored.
+    pub fn inject_unavailable(self) -> RuntimeHostVm {
+        RuntimeHostVm::Finished(Err(Error {
+            detail: ErrorDetail::StorageUnavailable,
+            prototype: self.inner.vm.into_prototype(),
+        }))
+    }
+}
+
+/// Verifying whether a batch of signatures is valid is required in order to continue.
+#[must_use]
+pub struct SignatureBatchVerify {
+    inner: Inner,
+}
+
+impl SignatureBatchVerify {
+    /// Returns the signatures of the batch.
+    pub fn signatures(&self) -> &[host::SignatureVerification] {
+        match &self.inner.vm {
+            host::HostVm::SignatureBatchVerify(req) => req.signatures(),
+            // We only create a `SignatureBatchVerify` if the state is one of the above.
+            _ => unreachable!(),
+        }
+    }
+
+    /// Resumes the execution. `all_valid` must be `true` if and only if all the signatures of
+    /// the batch are valid.
+    pub fn resume(mut self, all_valid: bool) -> RuntimeHostVm {
+        match self.inner.vm {
+            host::HostVm::SignatureBatchVerify(req) => self.inner.vm = req.resume(all_valid),
+            // We only create a `SignatureBatchVerify` if the state is one of the above.
+            _ => unreachable!(),
+        };
+
+        self.inner.run()
+    }
+
+    /// Verifies the signatures one after the other, then resumes the execution.
+    ///
+    /// > **Note**: This operation is CPU-intensive.
+    pub fn verify_and_resume(self) -> RuntimeHostVm {
+        let all_valid = self.signatures().iter().all(|s| s.verify());
+        self.resume(all_valid)
+    }
 }
 
 /// Implementation detail of the execution. Shared by all the variants of [`RuntimeHostVm`]
@@ -524,6 +724,18 @@ struct Inner {
     top_trie_transaction_revert:
         Option<HashMap<Vec<u8>, Option<Option<Vec<u8>>>, fnv::FnvBuildHasher>>,
 
+    /// Pending changes to the child tries that this execution performs, indexed by child trie.
+    child_tries_changes: BTreeMap<Vec<u8>, BTreeMap<Vec<u8>, Option<Vec<u8>>>>,
+
+    /// Same as [`Inner::top_trie_transaction_revert`], but for [`Inner::child_tries_changes`].
+    /// Keys are a child trie and a key within that child trie.
+    child_tries_transaction_revert:
+        Option<HashMap<(Vec<u8>, Vec<u8>), Option<Option<Vec<u8>>>, fnv::FnvBuildHasher>>,
+
+    /// List of child tries that have been modified since their root has last been written in
+    /// the top trie. Their roots must be updated before the root of the top trie is calculated.
+    stale_child_tries_roots: BTreeSet<Vec<u8>>,
+
     /// Pending changes to the offchain storage that this execution performs.
     offchain_storage_changes: HashMap<Vec<u8>, Option<Vec<u8>>, fnv::FnvBuildHasher>,
 
@@ -534,6 +746,10 @@ struct Inner {
     /// Trie root calculation in progress.
     root_calculation: Option<calculate_root::RootMerkleValueCalculation>,
 
+    /// Child trie whose root is being calculated by [`Inner::root_calculation`], or `None` if
+    /// this is the top trie.
+    root_calculation_child_trie: Option<Vec<u8>>,
+
     /// Concatenation of all the log messages generated by the runtime.
     logs: String,
 }
@@ -559,6 +775,7 @@ impl Inner {
                     return RuntimeHostVm::Finished(Ok(Success {
                         virtual_machine: SuccessVirtualMachine(finished),
                         storage_top_trie_changes: self.top_trie_changes,
+                        storage_child_tries_changes: self.child_tries_changes,
                         offchain_storage_changes: self.offchain_storage_changes,
                         top_trie_root_calculation_cache: self
                             .top_trie_root_calculation_cache
@@ -568,7 +785,13 @@ impl Inner {
                 }
 
                 host::HostVm::ExternalStorageGet(req) => {
-                    let change = self.top_trie_changes.get(req.key().as_ref());
+                    let change = if let Some(child_trie) = req.child_trie() {
+                        self.child_tries_changes
+                            .get(child_trie.as_ref())
+                            .and_then(|changes| changes.get(req.key().as_ref()))
+                    } else {
+                        self.top_trie_changes.get(req.key().as_ref())
+                    };
                     if let Some(overlay) = change {
                         self.vm = req.resume_full_value(overlay.as_ref().map(|v| &v[..]));
                     } else {
@@ -577,6 +800,32 @@ impl Inner {
                     }
                 }
 
+                host::HostVm::ExternalStorageSet(req) if req.child_trie().is_some() => {
+                    let child_trie = req.child_trie().unwrap().as_ref().to_vec();
+
+                    let previous_value = self
+                        .child_tries_changes
+                        .entry(child_trie.clone())
+                        .or_default()
+                        .insert(
+                            req.key().as_ref().to_vec(),
+                            req.value().map(|v| v.as_ref().to_vec()),
+                        );
+
+                    if let Some(child_tries_transaction_revert) =
+                        self.child_tries_transaction_revert.as_mut()
+                    {
+                        if let Entry::Vacant(entry) = child_tries_transaction_revert
+                            .entry((child_trie.clone(), req.key().as_ref().to_vec()))
+                        {
+                            entry.insert(previous_value);
+                        }
+                    }
+
+                    self.stale_child_tries_roots.insert(child_trie);
+                    self.vm = req.resume();
+                }
+
                 host::HostVm::ExternalStorageSet(req) => {
                     self.top_trie_root_calculation_cache
                         .as_mut()
@@ -637,15 +886,67 @@ impl Inner {
 
                 host::HostVm::ExternalStorageRoot(req) => {
                     if self.root_calculation.is_none() {
-                        self.root_calculation = Some(calculate_root::root_merkle_value(Some(
-                            self.top_trie_root_calculation_cache.take().unwrap(),
-                        )));
+                        // Before calculating the root of the top trie, the roots of the child
+                        // tries that have been modified must be calculated and written to the
+                        // top trie.
+                        self.root_calculation_child_trie = match req.child_trie() {
+                            Some(child_trie) => Some(child_trie.as_ref().to_vec()),
+                            None => self.stale_child_tries_roots.iter().next().cloned(),
+                        };
+
+                        self.root_calculation =
+                            Some(if self.root_calculation_child_trie.is_some() {
+                                calculate_root::root_merkle_value(None)
+                            } else {
+                                calculate_root::root_merkle_value(Some(
+                                    self.top_trie_root_calculation_cache.take().unwrap(),
+                                ))
+                            });
                     }
 
                     match self.root_calculation.take().unwrap() {
                         calculate_root::RootMerkleValueCalculation::Finished { hash, cache } => {
-                            self.top_trie_root_calculation_cache = Some(cache);
-                            self.vm = req.resume(&hash);
+                            if let Some(child_trie) = self.root_calculation_child_trie.take() {
+                                if self.stale_child_tries_roots.remove(&child_trie) {
+                                    // Write the new root of the child trie in the top trie. An
+                                    // empty child trie is removed from the top trie altogether.
+                                    let mut key = b":child_storage:default:".to_vec();
+                                    key.extend_from_slice(&child_trie);
+                                    let value = if hash == trie::empty_trie_merkle_value() {
+                                        None
+                                    } else {
+                                        Some(hash.to_vec())
+                                    };
+
+                                    self.top_trie_root_calculation_cache
+                                        .as_mut()
+                                        .unwrap()
+                                        .storage_value_update(&key, value.is_some());
+
+                                    let previous_value =
+                                        self.top_trie_changes.insert(key.clone(), value);
+
+                                    if let Some(top_trie_transaction_revert) =
+                                        self.top_trie_transaction_revert.as_mut()
+                                    {
+                                        if let Entry::Vacant(entry) =
+                                            top_trie_transaction_revert.entry(key)
+                                        {
+                                            entry.insert(previous_value);
+                                        }
+                                    }
+                                }
+
+                                if req.child_trie().is_some() {
+                                    self.vm = req.resume(&hash);
+                                } else {
+                                    // Continue with the next child trie or with the top trie.
+                                    self.vm = req.into();
+                                }
+                            } else {
+                                self.top_trie_root_calculation_cache = Some(cache);
+                                self.vm = req.resume(&hash);
+                            }
                         }
                         calculate_root::RootMerkleValueCalculation::AllKeys(keys) => {
                             self.vm = req.into();
@@ -654,14 +955,21 @@ impl Inner {
                             return RuntimeHostVm::PrefixKeys(PrefixKeys { inner: self });
                         }
                         calculate_root::RootMerkleValueCalculation::StorageValue(value_request) => {
+                            let state_trie_version = req.state_trie_version();
                             self.vm = req.into();
+                            let changes = match &self.root_calculation_child_trie {
+                                Some(child_trie) => self.child_tries_changes.get(child_trie),
+                                None => Some(&self.top_trie_changes),
+                            };
+
                             // TODO: allocating a Vec, meh
-                            if let Some(overlay) = self
-                                .top_trie_changes
-                                .get(&value_request.key().collect::<Vec<_>>())
-                            {
-                                self.root_calculation =
-                                    Some(value_request.inject(overlay.as_ref()));
+                            if let Some(overlay) = changes.and_then(|changes| {
+                                changes.get(&value_request.key().collect::<Vec<_>>())
+                            }) {
+                                self.root_calculation = Some(
+                                    value_request
+                                        .inject(overlay.as_ref().map(|v| (v, state_trie_version))),
+                                );
                             } else {
                                 self.root_calculation =
                                     Some(calculate_root::RootMerkleValueCalculation::StorageValue(
@@ -703,11 +1011,14 @@ impl Inner {
                     // upgrades are quite uncommon and that a caching system is rather non-trivial
                     // to set up, the approach of recompiling every single time is preferred here.
                     // TODO: number of heap pages?! we use the default here, but not sure whether that's correct or if we have to take the current heap pages
-                    let vm_prototype = match host::HostVmPrototype::new(
-                        req.wasm_code(),
-                        executor::DEFAULT_HEAP_PAGES,
-                        vm::ExecHint::Oneshot,
-                    ) {
+                    let vm_prototype = match host::HostVmPrototype::new(host::Config {
+                        module: req.wasm_code(),
+                        heap_pages: executor::DEFAULT_HEAP_PAGES,
+                        exec_hint: vm::ExecHint::Oneshot,
+                        allow_unresolved_imports: false,
+                        max_memory_size: None,
+                        metered: false,
+                    }) {
                         Ok(w) => w,
                         Err(_) => {
                             self.vm = req.resume(Err(()));
@@ -727,6 +1038,7 @@ impl Inner {
 
                 host::HostVm::StartStorageTransaction(tx) => {
                     self.top_trie_transaction_revert = Some(Default::default());
+                    self.child_tries_transaction_revert = Some(Default::default());
                     self.vm = tx.resume();
                 }
 
@@ -744,14 +1056,37 @@ impl Inner {
                             }
                         }
 
+                        for ((child_trie, key), value) in
+                            self.child_tries_transaction_revert.take().unwrap()
+                        {
+                            let changes = self
+                                .child_tries_changes
+                                .entry(child_trie.clone())
+                                .or_default();
+                            if let Some(value) = value {
+                                let _ = changes.insert(key, value);
+                            } else {
+                                let _ = changes.remove(&key);
+                            }
+                            self.stale_child_tries_roots.insert(child_trie);
+                        }
+
                         // TODO: very slow; do this properly
                         self.top_trie_root_calculation_cache = Some(Default::default());
                     }
 
                     self.top_trie_transaction_revert = None;
+                    self.child_tries_transaction_revert = None;
                     self.vm = resume.resume();
                 }
 
+                host::HostVm::SignatureBatchVerify(req) => {
+                    self.vm = req.into();
+                    return RuntimeHostVm::SignatureBatchVerify(SignatureBatchVerify {
+                        inner: self,
+                    });
+                }
+
                 host::HostVm::LogEmit(req) => {
                     // We add a hardcoded limit to the logs generated by the runtime in order to
                     // make sure that there is no memory leak. In practice, the runtime should
@@ -770,6 +1105,17 @@ impl Inner {
                     self.logs.push_str(&message);
                     self.vm = req.resume();
                 }
+
+                other @ host::HostVm::ExternalOffchainLocalStorageGet(_)
+                | other @ host::HostVm::ExternalOffchainLocalStorageSet(_)
+                | other @ host::HostVm::ExternalOffchainLocalStorageCompareAndSet(_)
+                | other @ host::HostVm::OffchainTimestamp(_)
+                | other @ host::HostVm::OffchainRandomSeed(_) => {
+                    return RuntimeHostVm::Finished(Err(Error {
+                        detail: ErrorDetail::ForbiddenHostCall,
+                        prototype: other.into_prototype(),
+                    }))
+                }
             }
         }
     }
@@ -88,13 +88,39 @@
 //!
 //! The first variant used to be the default model when compiling to WebAssembly, but the second
 //! variant (importing memory objects) is preferred nowadays.
+//!
+//! # About the stack height
+//!
+//! The WebAssembly specification doesn't define a maximum depth of the stack. Instead, each
+//! implementation is free to choose its own limit, and a Wasm function that recurses too deeply
+//! would fail on some implementations and succeed on others.
+//!
+//! In order to guarantee that all nodes agree on the outcome of an execution, the Wasm code is
+//! instrumented before being compiled. The instrumentation keeps track of the logical height of
+//! the stack, and traps if it exceeds [`STACK_HEIGHT_LIMIT`]. This is the same mechanism and the
+//! same limit as Substrate's deterministic stack limit.
+//!
+//! # About post-MVP proposals
+//!
+//! In addition to the WebAssembly MVP, the Wasm code is allowed to use some of the instructions
+//! of more recent proposals. Before being compiled, these instructions are replaced with
+//! equivalent sequences of MVP instructions, meaning that all the execution backends support the
+//! same set of instructions and behave identically.
+//!
+//! Use [`is_feature_supported`] to determine which proposals are supported.
 
 mod interpreter;
-#[cfg(all(target_arch = "x86_64", feature = "std"))]
+#[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "std"))]
 mod jit;
-
-use alloc::{string::String, vec::Vec};
-use core::{convert::TryFrom, fmt};
+mod lowering;
+
+use alloc::{
+    format,
+    string::{String, ToString as _},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{cmp, convert::TryFrom, fmt};
 use smallvec::SmallVec;
 
 /// Compiled Wasm code.
@@ -104,23 +130,88 @@ use smallvec::SmallVec;
 #[derive(Clone)]
 pub struct Module {
     inner: ModuleInner,
+    /// State of the module right after its instantiation, or `None` if it couldn't be
+    /// determined. See [`VirtualMachinePrototype::reset`].
+    initial_state: Option<Arc<InitialState>>,
+    /// Value passed to [`Module::new`].
+    max_memory_pages: Option<u32>,
+    /// `true` if the module imports a memory. See [`Module::imports_memory`].
+    imports_memory: bool,
+    /// `true` if the module has been created with [`Module::new_metered`].
+    metered: bool,
+}
+
+/// See [`Module::initial_state`].
+struct InitialState {
+    /// Offsets within the memory and content of the data segments of the module.
+    data_segments: Vec<(u32, Vec<u8>)>,
+    /// Names under which the mutable globals of the module are exported, and their initial
+    /// value.
+    mutable_globals: Vec<(String, WasmValue)>,
 }
 
 #[derive(Clone)]
 enum ModuleInner {
-    #[cfg(all(target_arch = "x86_64", feature = "std"))]
+    #[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "std"))]
     Jit(jit::Module),
     Interpreter(interpreter::Module),
 }
 
 impl Module {
     /// Compiles the given Wasm code.
-    pub fn new(module: impl AsRef<[u8]>, exec_hint: ExecHint) -> Result<Self, NewErr> {
+    ///
+    /// The instructions of the post-MVP proposals are rewritten, and the code is instrumented in
+    /// order to trap if the height of the stack exceeds [`STACK_HEIGHT_LIMIT`]. See
+    /// [the module-level documentation](..).
+    ///
+    /// If `max_memory_pages` is `Some`, the memory of the virtual machines created from this
+    /// module can never be larger than this number of 64kiB pages. This limit is set as the
+    /// maximum of the linear memory, meaning that `memory.grow` fails if it would exceed it.
+    pub fn new(
+        module: impl AsRef<[u8]>,
+        exec_hint: ExecHint,
+        max_memory_pages: Option<u32>,
+    ) -> Result<Self, NewErr> {
+        Self::new_inner(module.as_ref(), exec_hint, max_memory_pages, false)
+    }
+
+    /// Same as [`Module::new`], except that the code is also instrumented in order to count the
+    /// number of instructions that it executes.
+    ///
+    /// The module is made to import a function named [`METERING_FUNCTION_NAME`] from the module
+    /// named [`METERING_MODULE_NAME`], which it calls with a single `i32` parameter before
+    /// executing this number of instructions. This function doesn't return any value. It is
+    /// passed to the closure of [`VirtualMachinePrototype::new`] like any other import, and the
+    /// execution can be stopped when the total exceeds a certain value.
+    pub fn new_metered(
+        module: impl AsRef<[u8]>,
+        exec_hint: ExecHint,
+        max_memory_pages: Option<u32>,
+    ) -> Result<Self, NewErr> {
+        Self::new_inner(module.as_ref(), exec_hint, max_memory_pages, true)
+    }
+
+    fn new_inner(
+        module: &[u8],
+        exec_hint: ExecHint,
+        max_memory_pages: Option<u32>,
+        metered: bool,
+    ) -> Result<Self, NewErr> {
+        let (module, initial_state, imports_memory) =
+            lower_and_inject_stack_limiter(module, metered, max_memory_pages)?;
+
         Ok(Module {
+            initial_state: initial_state.map(Arc::new),
+            max_memory_pages,
+            imports_memory,
+            metered,
             inner: match exec_hint {
-                #[cfg(all(target_arch = "x86_64", feature = "std"))]
+                #[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "std"))]
                 ExecHint::CompileAheadOfTime => ModuleInner::Jit(jit::Module::new(module)?),
-                #[cfg(not(all(target_arch = "x86_64", feature = "std")))]
+                #[cfg(not(all(
+                    any(target_arch = "x86_64", target_arch = "aarch64"),
+                    feature = "std"
+                )))]
                 ExecHint::CompileAheadOfTime => {
                     ModuleInner::Interpreter(interpreter::Module::new(module)?)
                 }
@@ -130,14 +221,234 @@ impl Module {
             },
         })
     }
+
+    /// Returns `true` if the module imports a memory rather than defining its own. See
+    /// [`VirtualMachinePrototype::with_imported_memory`].
+    pub fn imports_memory(&self) -> bool {
+        self.imports_memory
+    }
+
+    /// Returns `true` if the module has been created with [`Module::new_metered`].
+    pub fn is_metered(&self) -> bool {
+        self.metered
+    }
+}
+
+/// Name of the module from which the Wasm code imports the metering function. See
+/// [`Module::new_metered`].
+pub const METERING_MODULE_NAME: &str = "__smoldot_metering";
+
+/// Name of the metering function that the Wasm code imports. See [`Module::new_metered`].
+pub const METERING_FUNCTION_NAME: &str = "gas";
+
+/// Maximum logical height of the stack that the Wasm code is allowed to reach.
+///
+/// The logical height of the stack is, for each function in the call stack, the sum of the
+/// number of its locals, of the maximum height of its operand stack, and of 2 for the activation
+/// frame.
+pub const STACK_HEIGHT_LIMIT: u32 = 65536;
+
+/// WebAssembly proposal that the Wasm code might use. See [`is_feature_supported`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum WasmFeature {
+    /// Sign-extension operators, such as `i32.extend8_s`.
+    SignExtension,
+    /// The `memory.copy` and `memory.fill` instructions of the bulk memory proposal.
+    MemoryCopyAndFill,
+    /// The entire bulk memory proposal, including passive data segments and the table
+    /// instructions.
+    BulkMemory,
+    /// Fixed-width SIMD instructions.
+    Simd,
+}
+
+/// Returns `true` if Wasm code using the given proposal can be passed to [`Module::new`].
+///
+/// The returned value doesn't depend on the [`ExecHint`], as all the execution backends support
+/// the same proposals.
+pub const fn is_feature_supported(feature: WasmFeature) -> bool {
+    match feature {
+        WasmFeature::SignExtension | WasmFeature::MemoryCopyAndFill => true,
+        WasmFeature::BulkMemory | WasmFeature::Simd => false,
+    }
+}
+
+/// Parses the given Wasm code, rewrites the instructions of the post-MVP proposals, adds to it
+/// instructions that trap if the height of the stack exceeds [`STACK_HEIGHT_LIMIT`] and, if
+/// `metered` is `true`, calls to the metering function, exports its mutable globals, lowers the
+/// maximum of the memory it defines to `max_memory_pages`, then encodes it back.
+///
+/// Also returns the state of the module right after its instantiation, if it could be
+/// determined, and whether the module imports a memory.
+fn lower_and_inject_stack_limiter(
+    module: &[u8],
+    metered: bool,
+    max_memory_pages: Option<u32>,
+) -> Result<(Vec<u8>, Option<InitialState>, bool), NewErr> {
+    use wasm_instrument::{gas_metering, parity_wasm};
+
+    // The stack limiter is injected before the placeholders are lowered, in order for the
+    // stack heights to be calculated on code equivalent to the original.
+    let (module, placeholders) = lowering::replace_with_placeholders(module)?;
+    let module = parity_wasm::deserialize_buffer::<parity_wasm::elements::Module>(&module)
+        .map_err(|err| NewErr::ModuleError(ModuleError(err.to_string())))?;
+    let mut module = wasm_instrument::inject_stack_limiter(module, STACK_HEIGHT_LIMIT)
+        .map_err(|err| NewErr::ModuleError(ModuleError(err.to_string())))?;
+    lowering::lower(&mut module, placeholders);
+    // The metering is injected after the lowering, as the instructions being counted are the
+    // ones that are actually executed.
+    if metered {
+        module = gas_metering::inject(
+            module,
+            &gas_metering::ConstantCostRules::default(),
+            METERING_MODULE_NAME,
+        )
+        .map_err(|_| NewErr::ModuleError(ModuleError("Failed to inject the metering".into())))?;
+    }
+    // Must be done after the stack limiter has been injected, as the stack limiter adds a
+    // mutable global.
+    let initial_state = export_mutable_globals(&mut module);
+    if let Some(max_memory_pages) = max_memory_pages {
+        limit_memory(&mut module, max_memory_pages)?;
+    }
+    let imports_memory = module
+        .import_section()
+        .into_iter()
+        .flat_map(|section| section.entries())
+        .any(|entry| matches!(entry.external(), parity_wasm::elements::External::Memory(_)));
+    let module = parity_wasm::serialize(module)
+        .map_err(|err| NewErr::ModuleError(ModuleError(err.to_string())))?;
+    Ok((module, initial_state, imports_memory))
+}
+
+/// Sets the maximum of the memories defined by the given module to `max_memory_pages`, unless it
+/// is already lower.
+///
+/// Memories that the module imports are allocated by the execution backends, which apply the
+/// limit themselves.
+fn limit_memory(
+    module: &mut wasm_instrument::parity_wasm::elements::Module,
+    max_memory_pages: u32,
+) -> Result<(), NewErr> {
+    use wasm_instrument::parity_wasm::elements::MemoryType;
+
+    let memories = match module.memory_section_mut() {
+        Some(section) => section.entries_mut(),
+        None => return Ok(()),
+    };
+
+    for memory in memories {
+        let initial = memory.limits().initial();
+        if initial > max_memory_pages {
+            return Err(NewErr::MemoryLimitExceeded);
+        }
+
+        let maximum = match memory.limits().maximum() {
+            Some(maximum) => cmp::min(maximum, max_memory_pages),
+            None => max_memory_pages,
+        };
+        *memory = MemoryType::new(initial, Some(maximum));
+    }
+
+    Ok(())
+}
+
+/// Prefix of the names under which the mutable globals are exported. Followed with the index of
+/// the global.
+const MUTABLE_GLOBAL_EXPORT_PREFIX: &str = "__smoldot_mutable_global_";
+
+/// Exports all the mutable globals of the given module, in order to make it possible to restore
+/// their value. See [`VirtualMachinePrototype::reset`].
+///
+/// Returns the state of the module right after its instantiation, or `None` if it can't be
+/// determined, for example because a data segment has a non-constant offset.
+fn export_mutable_globals(
+    module: &mut wasm_instrument::parity_wasm::elements::Module,
+) -> Option<InitialState> {
+    use wasm_instrument::parity_wasm::elements::{
+        ExportEntry, ExportSection, ImportCountType, Instruction, Internal, Section,
+    };
+
+    let num_imported_globals = module.import_count(ImportCountType::Global);
+
+    let mut initial_state_known = true;
+    let mut mutable_globals = Vec::new();
+    for (index, global) in module
+        .global_section()
+        .map_or(&[][..], |section| section.entries())
+        .iter()
+        .enumerate()
+    {
+        if !global.global_type().is_mutable() {
+            continue;
+        }
+
+        let value = match global.init_expr().code() {
+            [Instruction::I32Const(v), Instruction::End] => WasmValue::I32(*v),
+            [Instruction::I64Const(v), Instruction::End] => WasmValue::I64(*v),
+            _ => {
+                initial_state_known = false;
+                continue;
+            }
+        };
+
+        let index = u32::try_from(num_imported_globals + index).unwrap();
+        mutable_globals.push((
+            format!("{}{}", MUTABLE_GLOBAL_EXPORT_PREFIX, index),
+            index,
+            value,
+        ));
+    }
+
+    let data_segments = module
+        .data_section()
+        .map_or(&[][..], |section| section.entries())
+        .iter()
+        .map(
+            |segment| match segment.offset().as_ref().map(|o| o.code()) {
+                Some([Instruction::I32Const(offset), Instruction::End]) => Some((
+                    u32::from_ne_bytes(offset.to_ne_bytes()),
+                    segment.value().to_vec(),
+                )),
+                _ => None,
+            },
+        )
+        .collect::<Option<Vec<_>>>();
+
+    if !mutable_globals.is_empty() {
+        if module.export_section().is_none() {
+            module
+                .insert_section(Section::Export(ExportSection::with_entries(Vec::new())))
+                .unwrap();
+        }
+
+        let exports = module.export_section_mut().unwrap().entries_mut();
+        for (name, index, _) in &mutable_globals {
+            exports.push(ExportEntry::new(name.clone(), Internal::Global(*index)));
+        }
+    }
+
+    if !initial_state_known {
+        return None;
+    }
+
+    Some(InitialState {
+        data_segments: data_segments?,
+        mutable_globals: mutable_globals
+            .into_iter()
+            .map(|(name, _, value)| (name, value))
+            .collect(),
+    })
 }
 
 pub struct VirtualMachinePrototype {
     inner: VirtualMachinePrototypeInner,
+    /// Size of the memory right after the instantiation. See [`VirtualMachinePrototype::reset`].
+    initial_memory_size: u32,
 }
 
 enum VirtualMachinePrototypeInner {
-    #[cfg(all(target_arch = "x86_64", feature = "std"))]
+    #[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "std"))]
     Jit(jit::JitPrototype),
     Interpreter(interpreter::InterpreterPrototype),
 }
@@ -157,25 +468,141 @@ impl VirtualMachinePrototype {
         heap_pages: HeapPages,
         symbols: impl FnMut(&str, &str, &Signature) -> Result<usize, ()>,
     ) -> Result<Self, NewErr> {
-        Ok(VirtualMachinePrototype {
-            inner: match &module.inner {
-                ModuleInner::Interpreter(module) => VirtualMachinePrototypeInner::Interpreter(
-                    interpreter::InterpreterPrototype::new(module, heap_pages, symbols)?,
-                ),
-                #[cfg(all(target_arch = "x86_64", feature = "std"))]
-                ModuleInner::Jit(module) => VirtualMachinePrototypeInner::Jit(
-                    jit::JitPrototype::new(module, heap_pages, symbols)?,
-                ),
-            },
+        Self::new_inner(module, heap_pages, None, symbols)
+    }
+
+    /// Same as [`VirtualMachinePrototype::new`], except that the memory that the module imports
+    /// is initialized with `memory` instead of being empty.
+    ///
+    /// The imported memory has the size of `memory`, rounded down to a multiple of 64kiB, and
+    /// can't grow. The data segments of the module are written on top of `memory`.
+    ///
+    /// `memory` is provided by the caller, and thus isn't subject to the limit passed to
+    /// [`Module::new`]. It is ignored if the module doesn't import any memory, which can be
+    /// determined with [`Module::imports_memory`].
+    pub fn with_imported_memory(
+        module: &Module,
+        memory: &[u8],
+        symbols: impl FnMut(&str, &str, &Signature) -> Result<usize, ()>,
+    ) -> Result<Self, NewErr> {
+        Self::new_inner(module, HeapPages::new(0), Some(memory), symbols)
+    }
+
+    fn new_inner(
+        module: &Module,
+        heap_pages: HeapPages,
+        imported_memory: Option<&[u8]>,
+        symbols: impl FnMut(&str, &str, &Signature) -> Result<usize, ()>,
+    ) -> Result<Self, NewErr> {
+        let inner = match &module.inner {
+            ModuleInner::Interpreter(inner) => {
+                VirtualMachinePrototypeInner::Interpreter(interpreter::InterpreterPrototype::new(
+                    inner,
+                    heap_pages,
+                    module.max_memory_pages,
+                    imported_memory,
+                    symbols,
+                )?)
+            }
+            #[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "std"))]
+            ModuleInner::Jit(inner) => VirtualMachinePrototypeInner::Jit(jit::JitPrototype::new(
+                inner,
+                heap_pages,
+                module.max_memory_pages,
+                imported_memory,
+                symbols,
+            )?),
+        };
+
+        let mut prototype = VirtualMachinePrototype {
+            inner,
+            initial_memory_size: 0,
+        };
+        prototype.initial_memory_size = prototype.memory_size();
+        Ok(prototype)
+    }
+
+    /// Restores the memory and the global variables of the virtual machine to the state they
+    /// were in right after [`VirtualMachinePrototype::new`].
+    ///
+    /// This makes it possible to use the same prototype for multiple unrelated calls, which is
+    /// faster than creating a new prototype for each call.
+    ///
+    /// `module` must be the [`Module`] that was passed to [`VirtualMachinePrototype::new`].
+    pub fn reset(&mut self, module: &Module) -> Result<(), ResetErr> {
+        let initial_state = module.initial_state.as_ref().ok_or(ResetErr::Unsupported)?;
+
+        // The memory can't be shrunk back to its original size.
+        if self.memory_size() != self.initial_memory_size {
+            return Err(ResetErr::MemoryGrown);
+        }
+
+        match &mut self.inner {
+            #[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "std"))]
+            VirtualMachinePrototypeInner::Jit(inner) => inner.reset(initial_state),
+            VirtualMachinePrototypeInner::Interpreter(inner) => inner.reset(initial_state),
+        }
+
+        Ok(())
+    }
+
+    /// Returns the size of the memory, in bytes.
+    ///
+    /// See also [`VirtualMachine::memory_size`].
+    pub fn memory_size(&self) -> u32 {
+        match &self.inner {
+            #[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "std"))]
+            VirtualMachinePrototypeInner::Jit(inner) => inner.memory_size(),
+            VirtualMachinePrototypeInner::Interpreter(inner) => inner.memory_size(),
+        }
+    }
+
+    /// Copies the given memory range into a `Vec<u8>`.
+    ///
+    /// Returns an error if the range is invalid or out of range.
+    ///
+    /// See also [`VirtualMachine::read_memory`].
+    pub fn read_memory(
+        &'_ self,
+        offset: u32,
+        size: u32,
+    ) -> Result<impl AsRef<[u8]> + '_, OutOfBoundsError> {
+        Ok(match &self.inner {
+            #[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "std"))]
+            VirtualMachinePrototypeInner::Jit(inner) => {
+                either::Left(inner.read_memory(offset, size)?)
+            }
+            #[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "std"))]
+            VirtualMachinePrototypeInner::Interpreter(inner) => {
+                either::Right(inner.read_memory(offset, size)?)
+            }
+            #[cfg(not(all(
+                any(target_arch = "x86_64", target_arch = "aarch64"),
+                feature = "std"
+            )))]
+            VirtualMachinePrototypeInner::Interpreter(inner) => inner.read_memory(offset, size)?,
         })
     }
 
+    /// Write the data at the given memory location.
+    ///
+    /// Returns an error if the range is invalid or out of range.
+    ///
+    /// See also [`VirtualMachine::write_memory`].
+    pub fn write_memory(&mut self, offset: u32, value: &[u8]) -> Result<(), OutOfBoundsError> {
+        match &mut self.inner {
+            #[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "std"))]
+            VirtualMachinePrototypeInner::Jit(inner) => inner.write_memory(offset, value),
+            VirtualMachinePrototypeInner::Interpreter(inner) => inner.write_memory(offset, value),
+        }
+    }
+
     /// Returns the value of a global that the module exports.
     ///
     /// The global variable must be a `u32`, otherwise an error is returned.
     pub fn global_value(&mut self, name: &str) -> Result<u32, GlobalValueErr> {
         match &mut self.inner {
-            #[cfg(all(target_arch = "x86_64", feature = "std"))]
+            #[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "std"))]
             VirtualMachinePrototypeInner::Jit(inner) => inner.global_value(name),
             VirtualMachinePrototypeInner::Interpreter(inner) => inner.global_value(name),
         }
@@ -189,8 +616,9 @@ impl VirtualMachinePrototype {
         params: &[WasmValue],
     ) -> Result<VirtualMachine, (StartErr, Self)> {
         Ok(VirtualMachine {
+            initial_memory_size: self.initial_memory_size,
             inner: match self.inner {
-                #[cfg(all(target_arch = "x86_64", feature = "std"))]
+                #[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "std"))]
                 VirtualMachinePrototypeInner::Jit(inner) => {
                     match inner.start(function_name, params) {
                         Ok(vm) => VirtualMachineInner::Jit(vm),
@@ -217,7 +645,7 @@ impl VirtualMachinePrototype {
 impl fmt::Debug for VirtualMachinePrototype {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match &self.inner {
-            #[cfg(all(target_arch = "x86_64", feature = "std"))]
+            #[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "std"))]
             VirtualMachinePrototypeInner::Jit(inner) => fmt::Debug::fmt(inner, f),
             VirtualMachinePrototypeInner::Interpreter(inner) => fmt::Debug::fmt(inner, f),
         }
@@ -226,10 +654,12 @@ impl fmt::Debug for VirtualMachinePrototype {
 
 pub struct VirtualMachine {
     inner: VirtualMachineInner,
+    /// See [`VirtualMachinePrototype::initial_memory_size`].
+    initial_memory_size: u32,
 }
 
 enum VirtualMachineInner {
-    #[cfg(all(target_arch = "x86_64", feature = "std"))]
+    #[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "std"))]
     Jit(jit::Jit),
     Interpreter(interpreter::Interpreter),
 }
@@ -244,7 +674,7 @@ impl VirtualMachine {
     /// that call.
     pub fn run(&mut self, value: Option<WasmValue>) -> Result<ExecOutcome, RunErr> {
         match &mut self.inner {
-            #[cfg(all(target_arch = "x86_64", feature = "std"))]
+            #[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "std"))]
             VirtualMachineInner::Jit(inner) => inner.run(value),
             VirtualMachineInner::Interpreter(inner) => inner.run(value),
         }
@@ -255,7 +685,7 @@ impl VirtualMachine {
     /// > **Note**: This can change over time if the Wasm code uses the `grow` opcode.
     pub fn memory_size(&self) -> u32 {
         match &self.inner {
-            #[cfg(all(target_arch = "x86_64", feature = "std"))]
+            #[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "std"))]
             VirtualMachineInner::Jit(inner) => inner.memory_size(),
             VirtualMachineInner::Interpreter(inner) => inner.memory_size(),
         }
@@ -270,13 +700,16 @@ impl VirtualMachine {
         size: u32,
     ) -> Result<impl AsRef<[u8]> + '_, OutOfBoundsError> {
         Ok(match &self.inner {
-            #[cfg(all(target_arch = "x86_64", feature = "std"))]
+            #[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "std"))]
             VirtualMachineInner::Jit(inner) => either::Left(inner.read_memory(offset, size)?),
-            #[cfg(all(target_arch = "x86_64", feature = "std"))]
+            #[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "std"))]
             VirtualMachineInner::Interpreter(inner) => {
                 either::Right(inner.read_memory(offset, size)?)
             }
-            #[cfg(not(all(target_arch = "x86_64", feature = "std")))]
+            #[cfg(not(all(
+                any(target_arch = "x86_64", target_arch = "aarch64"),
+                feature = "std"
+            )))]
             VirtualMachineInner::Interpreter(inner) => inner.read_memory(offset, size)?,
         })
     }
@@ -286,7 +719,7 @@ impl VirtualMachine {
     /// Returns an error if the range is invalid or out of range.
     pub fn write_memory(&mut self, offset: u32, value: &[u8]) -> Result<(), OutOfBoundsError> {
         match &mut self.inner {
-            #[cfg(all(target_arch = "x86_64", feature = "std"))]
+            #[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "std"))]
             VirtualMachineInner::Jit(inner) => inner.write_memory(offset, value),
             VirtualMachineInner::Interpreter(inner) => inner.write_memory(offset, value),
         }
@@ -295,8 +728,9 @@ impl VirtualMachine {
     /// Turns back this virtual machine into a prototype.
     pub fn into_prototype(self) -> VirtualMachinePrototype {
         VirtualMachinePrototype {
+            initial_memory_size: self.initial_memory_size,
             inner: match self.inner {
-                #[cfg(all(target_arch = "x86_64", feature = "std"))]
+                #[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "std"))]
                 VirtualMachineInner::Jit(inner) => {
                     VirtualMachinePrototypeInner::Jit(inner.into_prototype())
                 }
@@ -311,7 +745,7 @@ impl VirtualMachine {
 impl fmt::Debug for VirtualMachine {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match &self.inner {
-            #[cfg(all(target_arch = "x86_64", feature = "std"))]
+            #[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "std"))]
             VirtualMachineInner::Jit(inner) => fmt::Debug::fmt(inner, f),
             VirtualMachineInner::Interpreter(inner) => fmt::Debug::fmt(inner, f),
         }
@@ -323,6 +757,10 @@ impl fmt::Debug for VirtualMachine {
 pub enum ExecHint {
     /// The WebAssembly code will be instantiated once and run many times.
     /// If possible, compile this WebAssembly code ahead of time.
+    ///
+    /// When the `std` feature is enabled and the target architecture is `x86_64` or `aarch64`,
+    /// the code is compiled to native code using `wasmtime`. On other targets, in particular
+    /// `wasm32`, the interpreter is used instead.
     CompileAheadOfTime,
     /// The WebAssembly code is expected to be only run once.
     ///
@@ -419,7 +857,7 @@ impl<'a> TryFrom<&'a wasmi::Signature> for Signature {
     }
 }
 
-#[cfg(all(target_arch = "x86_64", feature = "std"))]
+#[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "std"))]
 impl<'a> TryFrom<&'a wasmtime::FuncType> for Signature {
     type Error = UnsupportedTypeError;
 
@@ -515,7 +953,7 @@ impl From<WasmValue> for wasmi::RuntimeValue {
     }
 }
 
-#[cfg(all(target_arch = "x86_64", feature = "std"))]
+#[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "std"))]
 impl From<WasmValue> for wasmtime::Val {
     fn from(val: WasmValue) -> Self {
         match val {
@@ -525,7 +963,7 @@ impl From<WasmValue> for wasmtime::Val {
     }
 }
 
-#[cfg(all(target_arch = "x86_64", feature = "std"))]
+#[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "std"))]
 impl<'a> TryFrom<&'a wasmtime::Val> for WasmValue {
     type Error = UnsupportedTypeError;
 
@@ -559,7 +997,7 @@ impl TryFrom<wasmi::ValueType> for ValueType {
     }
 }
 
-#[cfg(all(target_arch = "x86_64", feature = "std"))]
+#[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "std"))]
 impl TryFrom<wasmtime::ValType> for ValueType {
     type Error = UnsupportedTypeError;
 
@@ -607,10 +1045,60 @@ pub enum ExecOutcome {
     },
 }
 
-/// Opaque error that happened during execution, such as an `unreachable` instruction.
-#[derive(Debug, derive_more::Display, Clone)]
-#[display(fmt = "{}", _0)]
-pub struct Trap(String);
+/// Error that happened during execution, such as an `unreachable` instruction.
+#[derive(Debug, Clone)]
+pub struct Trap {
+    /// Opaque description of the error.
+    message: String,
+    /// See [`Trap::backtrace`].
+    backtrace: Vec<BacktraceFrame>,
+}
+
+impl Trap {
+    /// Returns the list of functions that were in the call stack when the error happened,
+    /// starting with the innermost one.
+    ///
+    /// > **Note**: Only the [`ExecHint::CompileAheadOfTime`] backend is capable of inspecting
+    /// >           the call stack. The list is always empty when the interpreter is used.
+    pub fn backtrace(&self) -> &[BacktraceFrame] {
+        &self.backtrace
+    }
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if !self.backtrace.is_empty() {
+            write!(f, "\nWasm backtrace:")?;
+            for (n, frame) in self.backtrace.iter().enumerate() {
+                write!(f, "\n  {}: {}", n, frame)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Function in the call stack of a [`Trap`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BacktraceFrame {
+    /// Index of the function within the Wasm code, where imported functions come first.
+    ///
+    /// The functions that are injected when the Wasm code is compiled are always found after
+    /// the functions of the original Wasm code, meaning that the indices of the functions of the
+    /// original Wasm code are unchanged.
+    pub function_index: u32,
+    /// Name of the function, if the Wasm code contains a name section.
+    pub function_name: Option<String>,
+}
+
+impl fmt::Display for BacktraceFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.function_name {
+            Some(name) => write!(f, "function #{} ({})", self.function_index, name),
+            None => write!(f, "function #{}", self.function_index),
+        }
+    }
+}
 
 /// Error that can happen when initializing a [`VirtualMachinePrototype`].
 #[derive(Debug, derive_more::Display, Clone)]
@@ -626,6 +1114,10 @@ pub enum NewErr {
     IndirectTableIsntTable,
     /// Failed to allocate memory for the virtual machine.
     CouldntAllocateMemory,
+    /// The memory of the module, including the heap pages, is larger than its maximum or than
+    /// the limit passed to [`Module::new`].
+    #[display(fmt = "The memory of the module exceeds its maximum size.")]
+    MemoryLimitExceeded,
 }
 
 /// Error that can happen when calling [`VirtualMachinePrototype::start`].
@@ -681,9 +1173,24 @@ pub enum GlobalValueErr {
     Invalid,
 }
 
+/// Error that can happen when calling [`VirtualMachinePrototype::reset`].
+#[derive(Debug, derive_more::Display)]
+pub enum ResetErr {
+    /// The state of the module right after its instantiation couldn't be determined, for example
+    /// because a data segment has a non-constant offset.
+    #[display(fmt = "Initial state of the module is unknown")]
+    Unsupported,
+    /// The Wasm code has grown the memory.
+    #[display(fmt = "Memory has grown")]
+    MemoryGrown,
+}
+
 #[cfg(test)]
 mod tests {
-    // TODO:
+    use super::{
+        ExecHint, ExecOutcome, HeapPages, Module, NewErr, VirtualMachinePrototype, WasmValue,
+    };
+    use core::convert::TryFrom as _;
 
     #[test]
     fn is_send() {
@@ -692,4 +1199,256 @@ mod tests {
         test::<super::VirtualMachine>();
         test::<super::VirtualMachinePrototype>();
     }
+
+    /// Builds a module that defines or imports a memory of one page without maximum, and that
+    /// exports this memory and a function named `f` returning an `i32` and whose body is `body`.
+    fn module_with_memory(import_memory: bool, body: &[u8]) -> Vec<u8> {
+        fn section(out: &mut Vec<u8>, id: u8, content: &[u8]) {
+            out.push(id);
+            out.extend(crate::util::leb128::encode_usize(content.len()));
+            out.extend_from_slice(content);
+        }
+
+        let mut out = b"\0asm\x01\0\0\0".to_vec();
+        section(&mut out, 1, &[1, 0x60, 0, 1, 0x7f]);
+        if import_memory {
+            let mut import = vec![1, 3];
+            import.extend_from_slice(b"env");
+            import.push(6);
+            import.extend_from_slice(b"memory");
+            import.extend_from_slice(&[2, 0, 1]);
+            section(&mut out, 2, &import);
+        }
+        section(&mut out, 3, &[1, 0]);
+        if !import_memory {
+            section(&mut out, 5, &[1, 0, 1]);
+        }
+        let mut exports = vec![2, 1, b'f', 0, 0, 6];
+        exports.extend_from_slice(b"memory");
+        exports.extend_from_slice(&[2, 0]);
+        section(&mut out, 7, &exports);
+
+        let mut function = vec![0];
+        function.extend_from_slice(body);
+        function.push(0x0b);
+        let mut code = vec![1];
+        code.extend(crate::util::leb128::encode_usize(function.len()));
+        code.extend_from_slice(&function);
+        section(&mut out, 10, &code);
+
+        out
+    }
+
+    /// Body of a function that grows the memory by one page twice, and returns the value
+    /// returned by the second `memory.grow`.
+    const GROW_TWICE: &[u8] = &[0x41, 1, 0x40, 0, 0x1a, 0x41, 1, 0x40, 0];
+
+    /// Instantiates the module with the interpreter, calls `f`, and returns its return value
+    /// and the size of the memory afterwards.
+    ///
+    /// Only the interpreter is used. The limit is applied by rewriting the module, which
+    /// doesn't depend on the execution backend.
+    fn grow_twice(max_memory_pages: Option<u32>, heap_pages: u32) -> (i32, u32) {
+        let module = Module::new(
+            module_with_memory(false, GROW_TWICE),
+            ExecHint::Oneshot,
+            max_memory_pages,
+        )
+        .unwrap();
+        let prototype =
+            VirtualMachinePrototype::new(&module, HeapPages::new(heap_pages), |_, _, _| Err(()))
+                .unwrap();
+        let mut vm = prototype.start("f", &[]).unwrap();
+        match vm.run(None).unwrap() {
+            ExecOutcome::Finished {
+                return_value: Ok(Some(WasmValue::I32(value))),
+            } => (value, vm.memory_size()),
+            _ => panic!(),
+        }
+    }
+
+    /// Calls `f` in a module where `f` increments the `i32` at address 0 then calls itself
+    /// without end, preceded with `prelude`. Returns the value at address 0 after the call has
+    /// trapped, in other words the number of frames that have been entered.
+    ///
+    /// Only the interpreter is used, as the stack limit is enforced by instrumenting the module,
+    /// which doesn't depend on the execution backend.
+    fn recursion_depth(prelude: &[u8]) -> u32 {
+        let mut body = prelude.to_vec();
+        // `i32.store(0, i32.load(0) + 1)`, then `call 0`.
+        body.extend_from_slice(&[
+            0x41, 0, 0x41, 0, 0x28, 2, 0, 0x41, 1, 0x6a, 0x36, 2, 0, 0x10, 0,
+        ]);
+
+        let module =
+            Module::new(module_with_memory(false, &body), ExecHint::Oneshot, None).unwrap();
+        let prototype =
+            VirtualMachinePrototype::new(&module, HeapPages::new(0), |_, _, _| Err(())).unwrap();
+        let mut vm = prototype.start("f", &[]).unwrap();
+        match vm.run(None).unwrap() {
+            ExecOutcome::Finished {
+                return_value: Err(_),
+            } => {}
+            _ => panic!(),
+        }
+
+        let counter = vm.read_memory(0, 4).unwrap();
+        u32::from_le_bytes(<[u8; 4]>::try_from(counter.as_ref()).unwrap())
+    }
+
+    #[test]
+    fn stack_overflow_traps_deterministically() {
+        // The maximum height of the operand stack of `f` is 3 and `f` has no local, meaning
+        // that each frame costs 3 plus 2 for the activation frame. This is the number of frames
+        // after which Substrate traps as well, as it uses the same instrumentation and the same
+        // limit.
+        assert_eq!(recursion_depth(&[]), super::STACK_HEIGHT_LIMIT / 5);
+
+        // `i32.const 0`, `i32.extend8_s`, `drop`. Sign-extension instructions are lowered to
+        // sequences of MVP instructions that use a higher operand stack, but the stack height
+        // is calculated on the original code.
+        assert_eq!(
+            recursion_depth(&[0x41, 0, 0xc0, 0x1a]),
+            super::STACK_HEIGHT_LIMIT / 5
+        );
+    }
+
+    #[test]
+    fn memory_grow_fails_beyond_limit() {
+        // 1 initial page, plus 2 heap pages, plus 1 page successfully grown.
+        assert_eq!(grow_twice(Some(4), 2), (-1, 4 * 65536));
+        assert_eq!(grow_twice(None, 2), (4, 5 * 65536));
+    }
+
+    #[test]
+    fn heap_pages_beyond_limit() {
+        let module = Module::new(
+            module_with_memory(false, GROW_TWICE),
+            ExecHint::Oneshot,
+            Some(4),
+        )
+        .unwrap();
+        assert!(
+            VirtualMachinePrototype::new(&module, HeapPages::new(3), |_, _, _| Err(())).is_ok()
+        );
+        assert!(matches!(
+            VirtualMachinePrototype::new(&module, HeapPages::new(4), |_, _, _| Err(())),
+            Err(NewErr::MemoryLimitExceeded)
+        ));
+    }
+
+    #[test]
+    fn initial_memory_beyond_limit() {
+        assert!(matches!(
+            Module::new(
+                module_with_memory(false, GROW_TWICE),
+                ExecHint::Oneshot,
+                Some(0),
+            ),
+            Err(NewErr::MemoryLimitExceeded)
+        ));
+    }
+
+    #[test]
+    fn imported_memory_beyond_limit() {
+        let module = Module::new(
+            module_with_memory(true, GROW_TWICE),
+            ExecHint::Oneshot,
+            Some(4),
+        )
+        .unwrap();
+
+        let prototype =
+            VirtualMachinePrototype::new(&module, HeapPages::new(3), |_, _, _| Err(())).unwrap();
+        assert_eq!(prototype.memory_size(), 4 * 65536);
+
+        assert!(matches!(
+            VirtualMachinePrototype::new(&module, HeapPages::new(4), |_, _, _| Err(())),
+            Err(NewErr::MemoryLimitExceeded)
+        ));
+    }
+
+    #[test]
+    fn metered_module_reports_instructions() {
+        let module = Module::new_metered(
+            module_with_memory(false, &[0x41, 7]),
+            ExecHint::Oneshot,
+            None,
+        )
+        .unwrap();
+        let prototype = VirtualMachinePrototype::new(&module, HeapPages::new(0), |m, f, _| {
+            if m == super::METERING_MODULE_NAME && f == super::METERING_FUNCTION_NAME {
+                Ok(3)
+            } else {
+                Err(())
+            }
+        })
+        .unwrap();
+        let mut vm = prototype.start("f", &[]).unwrap();
+
+        let mut instructions = 0;
+        loop {
+            match vm.run(None).unwrap() {
+                ExecOutcome::Interrupted { id: 3, params } => match params[..] {
+                    [WasmValue::I32(n)] => instructions += n,
+                    _ => panic!(),
+                },
+                ExecOutcome::Finished {
+                    return_value: Ok(Some(WasmValue::I32(7))),
+                } => break,
+                _ => panic!(),
+            }
+        }
+        assert!(instructions > 0);
+    }
+
+    #[test]
+    fn imported_memory_content() {
+        // `i32.load(0)`.
+        let module = Module::new(
+            module_with_memory(true, &[0x41, 0, 0x28, 2, 0]),
+            ExecHint::Oneshot,
+            None,
+        )
+        .unwrap();
+        assert!(module.imports_memory());
+
+        let mut content = vec![0; 65536];
+        content[..4].copy_from_slice(&5u32.to_le_bytes());
+        let mut prototype =
+            VirtualMachinePrototype::with_imported_memory(&module, &content, |_, _, _| Err(()))
+                .unwrap();
+        assert_eq!(prototype.memory_size(), 65536);
+        assert_eq!(prototype.read_memory(0, 4).unwrap().as_ref(), &[5, 0, 0, 0]);
+
+        prototype.write_memory(0, &[9, 0, 0, 0]).unwrap();
+        let mut vm = prototype.start("f", &[]).unwrap();
+        assert!(matches!(
+            vm.run(None).unwrap(),
+            ExecOutcome::Finished {
+                return_value: Ok(Some(WasmValue::I32(9))),
+            }
+        ));
+    }
+
+    #[test]
+    fn imported_memory_cannot_grow() {
+        let module = Module::new(
+            module_with_memory(true, GROW_TWICE),
+            ExecHint::Oneshot,
+            None,
+        )
+        .unwrap();
+        let prototype =
+            VirtualMachinePrototype::with_imported_memory(&module, &[0; 65536], |_, _, _| Err(()))
+                .unwrap();
+        let mut vm = prototype.start("f", &[]).unwrap();
+        assert!(matches!(
+            vm.run(None).unwrap(),
+            ExecOutcome::Finished {
+                return_value: Ok(Some(WasmValue::I32(-1))),
+            }
+        ));
+        assert_eq!(vm.memory_size(), 65536);
+    }
 }
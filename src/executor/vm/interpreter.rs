@@ -18,13 +18,13 @@
 //! Implements the API documented [in the parent module](..).
 
 use super::{
-    ExecOutcome, GlobalValueErr, HeapPages, ModuleError, NewErr, OutOfBoundsError, RunErr,
-    Signature, StartErr, Trap, ValueType, WasmValue,
+    ExecOutcome, GlobalValueErr, HeapPages, InitialState, ModuleError, NewErr, OutOfBoundsError,
+    RunErr, Signature, StartErr, Trap, ValueType, WasmValue,
 };
 
 use alloc::{borrow::ToOwned as _, boxed::Box, format, string::ToString as _, sync::Arc, vec::Vec};
 use core::{
-    cell::RefCell,
+    cell::{Cell, RefCell},
     convert::{TryFrom, TryInto as _},
     fmt,
 };
@@ -76,12 +76,19 @@ impl InterpreterPrototype {
     pub fn new(
         module: &Module,
         heap_pages: HeapPages,
+        max_memory_pages: Option<u32>,
+        imported_memory: Option<&[u8]>,
         mut symbols: impl FnMut(&str, &str, &Signature) -> Result<usize, ()>,
     ) -> Result<Self, NewErr> {
         struct ImportResolve<'a> {
             functions: RefCell<&'a mut dyn FnMut(&str, &str, &Signature) -> Result<usize, ()>>,
             import_memory: RefCell<&'a mut Option<wasmi::MemoryRef>>,
+            /// See [`super::VirtualMachinePrototype::with_imported_memory`].
+            imported_memory_content: Option<&'a [u8]>,
             heap_pages: usize,
+            max_memory_pages: Option<usize>,
+            /// Set to `true` if the imported memory exceeds `max_memory_pages`.
+            memory_limit_exceeded: Cell<bool>,
         }
 
         impl<'a> wasmi::ImportResolver for ImportResolve<'a> {
@@ -142,6 +149,28 @@ impl InterpreterPrototype {
                     Some(_) => Err(wasmi::Error::Instantiation(
                         "Memory can not be imported twice!".into(),
                     )),
+                    memory_ref @ None if self.imported_memory_content.is_some() => {
+                        let content = self.imported_memory_content.unwrap();
+                        let num_pages = content.len() / wasmi::memory_units::Pages::byte_size().0;
+                        if num_pages < memory_type.initial() as usize
+                            || matches!(memory_type.maximum(), Some(max) if num_pages > max as usize)
+                        {
+                            return Err(wasmi::Error::Instantiation(
+                                "Imported memory doesn't match the memory type".into(),
+                            ));
+                        }
+
+                        let memory = wasmi::MemoryInstance::alloc(
+                            wasmi::memory_units::Pages(num_pages),
+                            Some(wasmi::memory_units::Pages(num_pages)),
+                        )?;
+                        memory.set(
+                            0,
+                            &content[..num_pages * wasmi::memory_units::Pages::byte_size().0],
+                        )?;
+                        **memory_ref = Some(memory.clone());
+                        Ok(memory)
+                    }
                     memory_ref @ None => {
                         if memory_type
                             .maximum()
@@ -158,13 +187,18 @@ impl InterpreterPrototype {
                                     .unwrap(),
                             )))
                         } else {
+                            let num_pages =
+                                (memory_type.initial() as usize).saturating_add(self.heap_pages);
+                            if matches!(self.max_memory_pages, Some(max) if num_pages > max) {
+                                self.memory_limit_exceeded.set(true);
+                                return Err(wasmi::Error::Instantiation(
+                                    "Memory exceeds the maximum size".into(),
+                                ));
+                            }
+
                             let memory = wasmi::MemoryInstance::alloc(
-                                wasmi::memory_units::Pages(
-                                    memory_type.initial() as usize + self.heap_pages,
-                                ),
-                                Some(wasmi::memory_units::Pages(
-                                    memory_type.initial() as usize + self.heap_pages,
-                                )),
+                                wasmi::memory_units::Pages(num_pages),
+                                Some(wasmi::memory_units::Pages(num_pages)),
                             )?;
                             **memory_ref = Some(memory.clone());
                             Ok(memory)
@@ -192,11 +226,19 @@ impl InterpreterPrototype {
             let resolver = ImportResolve {
                 functions: RefCell::new(&mut symbols),
                 import_memory: RefCell::new(&mut import_memory),
+                imported_memory_content: imported_memory,
                 heap_pages,
+                max_memory_pages: max_memory_pages
+                    .map(|max| usize::try_from(max).unwrap_or(usize::MAX)),
+                memory_limit_exceeded: Cell::new(false),
             };
-            wasmi::ModuleInstance::new(&module.inner, &resolver)
-                .map_err(|err| ModuleError(err.to_string()))
-                .map_err(NewErr::ModuleError)?
+            match wasmi::ModuleInstance::new(&module.inner, &resolver) {
+                Ok(m) => m,
+                Err(_) if resolver.memory_limit_exceeded.get() => {
+                    return Err(NewErr::MemoryLimitExceeded)
+                }
+                Err(err) => return Err(NewErr::ModuleError(ModuleError(err.to_string()))),
+            }
         };
         // TODO: explain `assert_no_start`
         let module = not_started.assert_no_start();
@@ -205,8 +247,10 @@ impl InterpreterPrototype {
             Some(import_memory)
         } else if let Some(mem) = module.export_by_name("memory") {
             if let Some(mem) = mem.as_memory() {
-                // TODO: don't unwrap /!\ need to figure out how heap_pages really works
-                mem.grow(wasmi::memory_units::Pages(heap_pages)).unwrap();
+                // Fails if the memory would exceed its maximum, which includes the limit passed
+                // to `Module::new`.
+                mem.grow(wasmi::memory_units::Pages(heap_pages))
+                    .map_err(|_| NewErr::MemoryLimitExceeded)?;
                 Some(mem.clone())
             } else {
                 return Err(NewErr::MemoryIsntMemory);
@@ -232,6 +276,53 @@ impl InterpreterPrototype {
         })
     }
 
+    /// See [`super::VirtualMachinePrototype::memory_size`].
+    pub fn memory_size(&self) -> u32 {
+        let mem = match self.memory.as_ref() {
+            Some(m) => m,
+            None => return 0,
+        };
+
+        memory_size(mem)
+    }
+
+    /// See [`super::VirtualMachinePrototype::read_memory`].
+    pub fn read_memory(
+        &'_ self,
+        offset: u32,
+        size: u32,
+    ) -> Result<impl AsRef<[u8]> + '_, OutOfBoundsError> {
+        read_memory(self.memory.as_ref(), offset, size)
+    }
+
+    /// See [`super::VirtualMachinePrototype::write_memory`].
+    pub fn write_memory(&mut self, offset: u32, value: &[u8]) -> Result<(), OutOfBoundsError> {
+        write_memory(self.memory.as_ref(), offset, value)
+    }
+
+    /// See [`super::VirtualMachinePrototype::reset`].
+    pub fn reset(&mut self, initial_state: &InitialState) {
+        // The data segments have successfully been written to the memory during the
+        // instantiation, and the memory can't shrink, meaning that errors can't happen.
+        if let Some(memory) = &self.memory {
+            let size = memory.current_size().0 * wasmi::memory_units::Pages::byte_size().0;
+            memory.clear(0, 0, size).unwrap();
+            for (offset, data) in &initial_state.data_segments {
+                memory.set(*offset, data).unwrap();
+            }
+        }
+
+        for (name, value) in &initial_state.mutable_globals {
+            self.module
+                .export_by_name(name)
+                .unwrap()
+                .as_global()
+                .unwrap()
+                .set(wasmi::RuntimeValue::from(*value))
+                .unwrap();
+        }
+    }
+
     /// See [`super::VirtualMachinePrototype::global_value`].
     pub fn global_value(&self, name: &str) -> Result<u32, GlobalValueErr> {
         let heap_base_val = self
@@ -272,7 +363,11 @@ impl InterpreterPrototype {
                         .map(|v| wasmi::RuntimeValue::from(*v))
                         .collect::<Vec<_>>(),
                 )
-                .map_err(|err| Trap(err.to_string()))
+                .map_err(|err| Trap {
+                    message: err.to_string(),
+                    // `wasmi` doesn't provide any way to inspect the call stack.
+                    backtrace: Vec::new(),
+                })
             }
             None => return Err((StartErr::FunctionNotFound, self)),
             _ => return Err((StartErr::NotAFunction, self)),
@@ -439,7 +534,10 @@ impl Interpreter {
                 })
             }
             Err(wasmi::ResumableError::Trap(err)) => Ok(ExecOutcome::Finished {
-                return_value: Err(Trap(err.to_string())),
+                return_value: Err(Trap {
+                    message: err.to_string(),
+                    backtrace: Vec::new(),
+                }),
             }),
         }
     }
@@ -451,7 +549,7 @@ impl Interpreter {
             None => return 0,
         };
 
-        u32::try_from(mem.current_size().0 * wasmi::memory_units::Pages::byte_size().0).unwrap()
+        memory_size(mem)
     }
 
     /// See [`super::VirtualMachine::read_memory`].
@@ -460,73 +558,12 @@ impl Interpreter {
         offset: u32,
         size: u32,
     ) -> Result<impl AsRef<[u8]> + '_, OutOfBoundsError> {
-        let mem = match self.memory.as_ref() {
-            Some(m) => m,
-            None => {
-                return if offset == 0 && size == 0 {
-                    Ok(AccessOffset::Empty)
-                } else {
-                    Err(OutOfBoundsError)
-                }
-            }
-        };
-
-        let offset = usize::try_from(offset).map_err(|_| OutOfBoundsError)?;
-
-        let max = offset
-            .checked_add(size.try_into().map_err(|_| OutOfBoundsError)?)
-            .ok_or(OutOfBoundsError)?;
-
-        enum AccessOffset<T> {
-            Enabled {
-                access: T,
-                offset: usize,
-                max: usize,
-            },
-            Empty,
-        }
-
-        impl<T: AsRef<[u8]>> AsRef<[u8]> for AccessOffset<T> {
-            fn as_ref(&self) -> &[u8] {
-                if let AccessOffset::Enabled {
-                    access,
-                    offset,
-                    max,
-                } = self
-                {
-                    &access.as_ref()[*offset..*max]
-                } else {
-                    &[]
-                }
-            }
-        }
-
-        let access = mem.direct_access();
-        if max > access.as_ref().len() {
-            return Err(OutOfBoundsError);
-        }
-
-        Ok(AccessOffset::Enabled {
-            access,
-            offset,
-            max,
-        })
+        read_memory(self.memory.as_ref(), offset, size)
     }
 
     /// See [`super::VirtualMachine::write_memory`].
     pub fn write_memory(&mut self, offset: u32, value: &[u8]) -> Result<(), OutOfBoundsError> {
-        let mem = match self.memory.as_ref() {
-            Some(m) => m,
-            None => {
-                return if offset == 0 && value.is_empty() {
-                    Ok(())
-                } else {
-                    Err(OutOfBoundsError)
-                }
-            }
-        };
-
-        mem.set(offset, value).map_err(|_| OutOfBoundsError)
+        write_memory(self.memory.as_ref(), offset, value)
     }
 
     /// See [`super::VirtualMachine::into_prototype`].
@@ -556,3 +593,93 @@ impl fmt::Debug for Interpreter {
         f.debug_tuple("Interpreter").finish()
     }
 }
+
+/// Returns the size of the given memory, in bytes.
+///
+/// A memory of 65536 pages, the maximum allowed by the WebAssembly specification, is 4 GiB large
+/// and doesn't fit in a `u32`. Its size is clamped to `u32::MAX`.
+fn memory_size(memory: &wasmi::MemoryRef) -> u32 {
+    let size = memory.current_size().0 * wasmi::memory_units::Pages::byte_size().0;
+    u32::try_from(size).unwrap_or(u32::MAX)
+}
+
+/// Implementation of [`super::VirtualMachine::read_memory`] and
+/// [`super::VirtualMachinePrototype::read_memory`].
+fn read_memory(
+    memory: Option<&'_ wasmi::MemoryRef>,
+    offset: u32,
+    size: u32,
+) -> Result<impl AsRef<[u8]> + '_, OutOfBoundsError> {
+    let mem = match memory {
+        Some(m) => m,
+        None => {
+            return if offset == 0 && size == 0 {
+                Ok(AccessOffset::Empty)
+            } else {
+                Err(OutOfBoundsError)
+            }
+        }
+    };
+
+    let offset = usize::try_from(offset).map_err(|_| OutOfBoundsError)?;
+
+    let max = offset
+        .checked_add(size.try_into().map_err(|_| OutOfBoundsError)?)
+        .ok_or(OutOfBoundsError)?;
+
+    enum AccessOffset<T> {
+        Enabled {
+            access: T,
+            offset: usize,
+            max: usize,
+        },
+        Empty,
+    }
+
+    impl<T: AsRef<[u8]>> AsRef<[u8]> for AccessOffset<T> {
+        fn as_ref(&self) -> &[u8] {
+            if let AccessOffset::Enabled {
+                access,
+                offset,
+                max,
+            } = self
+            {
+                &access.as_ref()[*offset..*max]
+            } else {
+                &[]
+            }
+        }
+    }
+
+    let access = mem.direct_access();
+    if max > access.as_ref().len() {
+        return Err(OutOfBoundsError);
+    }
+
+    Ok(AccessOffset::Enabled {
+        access,
+        offset,
+        max,
+    })
+}
+
+/// Implementation of [`super::VirtualMachine::write_memory`] and
+/// [`super::VirtualMachinePrototype::write_memory`].
+fn write_memory(
+    memory: Option<&wasmi::MemoryRef>,
+    offset: u32,
+    value: &[u8],
+) -> Result<(), OutOfBoundsError> {
+    let mem = match memory {
+        Some(m) => m,
+        None => {
+            return if offset == 0 && value.is_empty() {
+                Ok(())
+            } else {
+                Err(OutOfBoundsError)
+            }
+        }
+    };
+
+    mem.set(offset, value).map_err(|_| OutOfBoundsError)
+}
@@ -18,11 +18,11 @@
 //! Implements the API documented [in the parent module](..).
 
 use super::{
-    ExecOutcome, GlobalValueErr, HeapPages, ModuleError, NewErr, OutOfBoundsError, RunErr,
-    Signature, StartErr, Trap, WasmValue,
+    BacktraceFrame, ExecOutcome, GlobalValueErr, HeapPages, InitialState, ModuleError, NewErr,
+    OutOfBoundsError, RunErr, Signature, StartErr, Trap, WasmValue,
 };
 
-use alloc::{boxed::Box, rc::Rc, string::String, vec::Vec};
+use alloc::{boxed::Box, rc::Rc, vec::Vec};
 use core::{
     cell::RefCell,
     cmp,
@@ -87,6 +87,8 @@ impl JitPrototype {
     pub fn new(
         module: &Module,
         heap_pages: HeapPages,
+        max_memory_pages: Option<u32>,
+        imported_memory_content: Option<&[u8]>,
         mut symbols: impl FnMut(&str, &str, &Signature) -> Result<usize, ()>,
     ) -> Result<Self, NewErr> {
         let store = wasmtime::Store::new(module.inner.engine());
@@ -180,11 +182,24 @@ impl JitPrototype {
                         )));
                     }
                     wasmtime::ExternType::Memory(m) => {
-                        let limits = {
+                        let limits = if let Some(content) = imported_memory_content {
+                            let num = u32::try_from(content.len() / 65536).unwrap_or(u32::MAX);
+                            if num < m.limits().min()
+                                || matches!(m.limits().max(), Some(max) if num > max)
+                            {
+                                return Err(NewErr::ModuleError(ModuleError(
+                                    "Imported memory doesn't match the memory type".to_string(),
+                                )));
+                            }
+                            wasmtime::Limits::new(num, Some(num))
+                        } else {
                             let heap_pages = u32::from(heap_pages);
                             let min = cmp::max(m.limits().min(), heap_pages);
                             let max = m.limits().max(); // TODO: make sure it's > to min, otherwise error
-                            let num = min + heap_pages;
+                            let num = min.saturating_add(heap_pages);
+                            if matches!(max_memory_pages, Some(max) if num > max) {
+                                return Err(NewErr::MemoryLimitExceeded);
+                            }
                             wasmtime::Limits::new(num, Some(num))
                         };
 
@@ -195,6 +210,16 @@ impl JitPrototype {
                             wasmtime::Memory::new(&store, wasmtime::MemoryType::new(limits))
                                 .map_err(|_| NewErr::CouldntAllocateMemory)?,
                         );
+                        if let Some(content) = imported_memory_content {
+                            let memory = imported_memory.as_ref().unwrap();
+                            // Soundness: the memory has just been created and can't be accessed
+                            // by anything else.
+                            unsafe {
+                                let data = memory.data_unchecked_mut();
+                                let len = data.len();
+                                data.copy_from_slice(&content[..len]);
+                            }
+                        }
                         imports.push(wasmtime::Extern::Memory(
                             imported_memory.as_ref().unwrap().clone(),
                         ));
@@ -214,8 +239,10 @@ impl JitPrototype {
 
         let exported_memory = if let Some(mem) = instance.get_export("memory") {
             if let Some(mem) = mem.into_memory() {
-                // TODO: do this properly
-                mem.grow(u32::try_from(heap_pages).unwrap()).unwrap();
+                // Fails if the memory would exceed its maximum, which includes the limit passed
+                // to `Module::new`.
+                mem.grow(u32::from(heap_pages))
+                    .map_err(|_| NewErr::MemoryLimitExceeded)?;
                 Some(mem)
             } else {
                 return Err(NewErr::MemoryIsntMemory);
@@ -249,6 +276,60 @@ impl JitPrototype {
         })
     }
 
+    /// See [`super::VirtualMachinePrototype::memory_size`].
+    pub fn memory_size(&self) -> u32 {
+        let mem = match self.memory.as_ref() {
+            Some(m) => m,
+            None => return 0,
+        };
+
+        // A memory of 65536 pages, the maximum allowed by the WebAssembly specification, is
+        // 4 GiB large and doesn't fit in a `u32`.
+        u32::try_from(mem.data_size()).unwrap_or(u32::MAX)
+    }
+
+    /// See [`super::VirtualMachinePrototype::read_memory`].
+    pub fn read_memory(
+        &'_ self,
+        offset: u32,
+        size: u32,
+    ) -> Result<impl AsRef<[u8]> + '_, OutOfBoundsError> {
+        read_memory(self.memory.as_ref(), offset, size)
+    }
+
+    /// See [`super::VirtualMachinePrototype::write_memory`].
+    pub fn write_memory(&mut self, offset: u32, value: &[u8]) -> Result<(), OutOfBoundsError> {
+        write_memory(self.memory.as_ref(), offset, value)
+    }
+
+    /// See [`super::VirtualMachinePrototype::reset`].
+    pub fn reset(&mut self, initial_state: &InitialState) {
+        if let Some(memory) = &self.memory {
+            // Soundness: the documentation of wasmtime precisely explains what is safe or not.
+            // Basically, we are safe as long as we are sure that we don't potentially grow the
+            // buffer (which would invalidate the buffer pointer).
+            unsafe {
+                let data = memory.data_unchecked_mut();
+                data.fill(0);
+                // The data segments have successfully been written to the memory during the
+                // instantiation, and the memory can't shrink, meaning that they fit.
+                for (offset, segment) in &initial_state.data_segments {
+                    let offset = usize::try_from(*offset).unwrap();
+                    data[offset..][..segment.len()].copy_from_slice(segment);
+                }
+            }
+        }
+
+        for (name, value) in &initial_state.mutable_globals {
+            self.instance
+                .get_export(name)
+                .and_then(|export| export.into_global())
+                .unwrap()
+                .set((*value).into())
+                .unwrap();
+        }
+    }
+
     /// See [`super::VirtualMachinePrototype::global_value`].
     pub fn global_value(&mut self, name: &str) -> Result<u32, GlobalValueErr> {
         match self.instance.get_export(name) {
@@ -280,10 +361,27 @@ impl JitPrototype {
         // Now running the `start` function of the Wasm code.
         let params = params.iter().map(|v| (*v).into()).collect::<Vec<_>>();
         let function_call = Box::pin(async move {
-            let result = start_function
-                .call_async(&params)
-                .await
-                .map_err(|err| err.to_string())?; // The type of error is from the `anyhow` library. By using `to_string()` we avoid having to deal with it.
+            let result = start_function.call_async(&params).await.map_err(|err| {
+                // The type of error is from the `anyhow` library. The call stack is only
+                // available if the error is a `wasmtime::Trap`.
+                let backtrace = err
+                    .downcast_ref::<wasmtime::Trap>()
+                    .map(|trap| {
+                        trap.trace()
+                            .iter()
+                            .map(|frame| BacktraceFrame {
+                                function_index: frame.func_index(),
+                                function_name: frame.func_name().map(|n| n.to_owned()),
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default();
+
+                Trap {
+                    message: err.to_string(),
+                    backtrace,
+                }
+            })?;
 
             // Execution resumes here when the Wasm code has gracefully finished.
             // The signature of the function has been chedk earlier, and as such it is
@@ -359,7 +457,7 @@ pub struct Jit {
     /// `Future` that drives the execution. Contains an invocation of
     /// `wasmtime::Func::call_async`.
     /// `None` if the execution has finished and future has returned `Poll::Ready` in the past.
-    function_call: Option<Pin<Box<dyn Future<Output = Result<Option<WasmValue>, String>>>>>,
+    function_call: Option<Pin<Box<dyn Future<Output = Result<Option<WasmValue>, Trap>>>>>,
 
     /// Shared between the "outside" and the external functions. See [`Shared`].
     shared: Rc<RefCell<Shared>>,
@@ -407,7 +505,7 @@ impl Jit {
             Poll::Ready(Err(err)) => {
                 self.function_call = None;
                 Ok(ExecOutcome::Finished {
-                    return_value: Err(Trap(err)),
+                    return_value: Err(err),
                 })
             }
             Poll::Pending => {
@@ -427,7 +525,9 @@ impl Jit {
             None => return 0,
         };
 
-        u32::try_from(mem.data_size()).unwrap()
+        // A memory of 65536 pages, the maximum allowed by the WebAssembly specification, is
+        // 4 GiB large and doesn't fit in a `u32`.
+        u32::try_from(mem.data_size()).unwrap_or(u32::MAX)
     }
 
     /// See [`super::VirtualMachine::read_memory`].
@@ -436,64 +536,12 @@ impl Jit {
         offset: u32,
         size: u32,
     ) -> Result<impl AsRef<[u8]> + '_, OutOfBoundsError> {
-        let mem = match self.memory.as_ref() {
-            Some(m) => m,
-            None => {
-                return if offset == 0 && size == 0 {
-                    Ok(&[][..])
-                } else {
-                    Err(OutOfBoundsError)
-                }
-            }
-        };
-
-        let start = usize::try_from(offset).map_err(|_| OutOfBoundsError)?;
-        let end = start
-            .checked_add(usize::try_from(size).map_err(|_| OutOfBoundsError)?)
-            .ok_or(OutOfBoundsError)?;
-
-        // Soundness: the documentation of wasmtime precisely explains what is safe or not.
-        // Basically, we are safe as long as we are sure that we don't potentially grow the
-        // buffer (which would invalidate the buffer pointer).
-        unsafe {
-            if end > mem.data_unchecked().len() {
-                return Err(OutOfBoundsError);
-            }
-
-            Ok(&mem.data_unchecked()[start..end])
-        }
+        read_memory(self.memory.as_ref(), offset, size)
     }
 
     /// See [`super::VirtualMachine::write_memory`].
     pub fn write_memory(&mut self, offset: u32, value: &[u8]) -> Result<(), OutOfBoundsError> {
-        let mem = match self.memory.as_ref() {
-            Some(m) => m,
-            None => {
-                return if offset == 0 && value.is_empty() {
-                    Ok(())
-                } else {
-                    Err(OutOfBoundsError)
-                }
-            }
-        };
-
-        let start = usize::try_from(offset).map_err(|_| OutOfBoundsError)?;
-        let end = start.checked_add(value.len()).ok_or(OutOfBoundsError)?;
-
-        // Soundness: the documentation of wasmtime precisely explains what is safe or not.
-        // Basically, we are safe as long as we are sure that we don't potentially grow the
-        // buffer (which would invalidate the buffer pointer).
-        unsafe {
-            if end > mem.data_unchecked().len() {
-                return Err(OutOfBoundsError);
-            }
-
-            if !value.is_empty() {
-                mem.data_unchecked_mut()[start..end].copy_from_slice(value);
-            }
-        }
-
-        Ok(())
+        write_memory(self.memory.as_ref(), offset, value)
     }
 
     /// See [`super::VirtualMachine::into_prototype`].
@@ -537,3 +585,75 @@ impl fmt::Debug for Jit {
         f.debug_tuple("Jit").finish()
     }
 }
+
+/// Implementation of [`super::VirtualMachine::read_memory`] and
+/// [`super::VirtualMachinePrototype::read_memory`].
+fn read_memory(
+    memory: Option<&'_ wasmtime::Memory>,
+    offset: u32,
+    size: u32,
+) -> Result<impl AsRef<[u8]> + '_, OutOfBoundsError> {
+    let mem = match memory {
+        Some(m) => m,
+        None => {
+            return if offset == 0 && size == 0 {
+                Ok(&[][..])
+            } else {
+                Err(OutOfBoundsError)
+            }
+        }
+    };
+
+    let start = usize::try_from(offset).map_err(|_| OutOfBoundsError)?;
+    let end = start
+        .checked_add(usize::try_from(size).map_err(|_| OutOfBoundsError)?)
+        .ok_or(OutOfBoundsError)?;
+
+    // Soundness: the documentation of wasmtime precisely explains what is safe or not.
+    // Basically, we are safe as long as we are sure that we don't potentially grow the
+    // buffer (which would invalidate the buffer pointer).
+    unsafe {
+        if end > mem.data_unchecked().len() {
+            return Err(OutOfBoundsError);
+        }
+
+        Ok(&mem.data_unchecked()[start..end])
+    }
+}
+
+/// Implementation of [`super::VirtualMachine::write_memory`] and
+/// [`super::VirtualMachinePrototype::write_memory`].
+fn write_memory(
+    memory: Option<&wasmtime::Memory>,
+    offset: u32,
+    value: &[u8],
+) -> Result<(), OutOfBoundsError> {
+    let mem = match memory {
+        Some(m) => m,
+        None => {
+            return if offset == 0 && value.is_empty() {
+                Ok(())
+            } else {
+                Err(OutOfBoundsError)
+            }
+        }
+    };
+
+    let start = usize::try_from(offset).map_err(|_| OutOfBoundsError)?;
+    let end = start.checked_add(value.len()).ok_or(OutOfBoundsError)?;
+
+    // Soundness: the documentation of wasmtime precisely explains what is safe or not.
+    // Basically, we are safe as long as we are sure that we don't potentially grow the
+    // buffer (which would invalidate the buffer pointer).
+    unsafe {
+        if end > mem.data_unchecked().len() {
+            return Err(OutOfBoundsError);
+        }
+
+        if !value.is_empty() {
+            mem.data_unchecked_mut()[start..end].copy_from_slice(value);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,776 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Rewriting of the instructions of post-MVP WebAssembly proposals into MVP instructions.
+//!
+//! The interpreter only supports the WebAssembly MVP. In order to be able to execute code that
+//! uses some of the instructions of more recent proposals, these instructions are replaced with
+//! equivalent sequences of MVP instructions before the module is compiled.
+//!
+//! The following instructions are rewritten:
+//!
+//! - All the instructions of the sign-extension proposal are replaced with a pair of shifts.
+//! - The `memory.copy` and `memory.fill` instructions of the bulk memory proposal are replaced
+//! with calls to functions injected in the module.
+//!
+//! The other instructions of the bulk memory proposal, and passive data segments, aren't
+//! supported.
+//!
+//! # Two steps
+//!
+//! The rewriting is done in two steps:
+//!
+//! - [`replace_with_placeholders`] is applied on the encoded module, and replaces each post-MVP
+//! instruction with a placeholder: MVP instructions that have the same effect on the operand
+//! stack. An `i32.extend8_s` becomes for example an `i32.popcnt`.
+//! - [`lower`] is applied on the module once the stack limiter has been injected, and replaces
+//! the placeholders with the actual implementation of the original instructions.
+//!
+//! Because the stack limiter is injected while the module contains the placeholders, the stack
+//! heights that it calculates are the same as if it had been applied on the original code. The
+//! functions that replace `memory.copy` and `memory.fill` are injected after the stack limiter
+//! and are thus not accounted for, in the same way as the instructions they replace.
+//!
+//! The first step parses the module manually rather than through `parity_wasm`, as `parity_wasm`
+//! can only parse these instructions if some of its Cargo features are enabled, and these
+//! features are incompatible with `wasmi`.
+
+use super::{ModuleError, NewErr};
+use crate::util::leb128;
+
+use alloc::{
+    format,
+    string::{String, ToString as _},
+    vec,
+    vec::Vec,
+};
+use core::{convert::TryFrom as _, mem};
+use wasm_instrument::parity_wasm::elements::{
+    BlockType, Func, FuncBody, FunctionType, Instruction, Instructions, Module, Type, ValueType,
+};
+
+/// Placeholders inserted by [`replace_with_placeholders`], to pass to [`lower`].
+#[derive(Debug, Default)]
+pub(super) struct Placeholders {
+    /// Index within the code section of the functions that contain placeholders, and the
+    /// placeholders they contain, in order of appearance.
+    bodies: Vec<(usize, Vec<Placeholder>)>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Placeholder {
+    /// Opcode of the first instruction of the placeholder.
+    opcode: u8,
+    /// Number of instructions with opcode [`Placeholder::opcode`] that precede the placeholder
+    /// within the function.
+    ordinal: u32,
+    /// Instruction that has been replaced.
+    original: Original,
+}
+
+/// Post-MVP instruction replaced with a placeholder.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Original {
+    I32Extend8S,
+    I32Extend16S,
+    I64Extend8S,
+    I64Extend16S,
+    I64Extend32S,
+    MemoryCopy,
+    MemoryFill,
+}
+
+impl Original {
+    /// Returns the opcodes of the placeholder of this instruction.
+    fn placeholder(&self) -> &'static [u8] {
+        match self {
+            // `i32.popcnt` and `i64.popcnt`, which pop one value and push one value of the same
+            // type.
+            Original::I32Extend8S | Original::I32Extend16S => &[0x69],
+            Original::I64Extend8S | Original::I64Extend16S | Original::I64Extend32S => &[0x7b],
+            // Three `drop`s, as `memory.copy` and `memory.fill` pop three `i32`s.
+            Original::MemoryCopy | Original::MemoryFill => &[0x1a, 0x1a, 0x1a],
+        }
+    }
+}
+
+/// Replaces the post-MVP instructions of the given encoded module with placeholders. See
+/// [the module-level documentation](..).
+pub(super) fn replace_with_placeholders(module: &[u8]) -> Result<(Vec<u8>, Placeholders), NewErr> {
+    let mut placeholders = Placeholders::default();
+
+    let mut reader = Reader {
+        bytes: module,
+        offset: 0,
+    };
+
+    if reader.bytes(8)? != b"\0asm\x01\0\0\0" {
+        return Err(parse_error("Invalid header".to_string()));
+    }
+
+    let mut out = Vec::with_capacity(module.len());
+    out.extend_from_slice(&module[..8]);
+
+    while !reader.is_empty() {
+        let section_start = reader.offset;
+        let section_id = reader.byte()?;
+        let section_len = reader.u32()?;
+        let section = reader.bytes(usize::try_from(section_len).unwrap())?;
+
+        // Sections other than the code section are copied unmodified.
+        if section_id != 10 {
+            out.extend_from_slice(&module[section_start..reader.offset]);
+            continue;
+        }
+
+        let mut reader = Reader {
+            bytes: section,
+            offset: 0,
+        };
+
+        let num_bodies = reader.u32()?;
+        let mut new_section = leb128::encode(num_bodies).collect::<Vec<_>>();
+        for body_index in 0..num_bodies {
+            let body_len = reader.u32()?;
+            let mut body = Reader {
+                bytes: reader.bytes(usize::try_from(body_len).unwrap())?,
+                offset: 0,
+            };
+
+            let (new_body, body_placeholders) = replace_in_body(&mut body)?;
+            new_section.extend(leb128::encode_usize(new_body.len()));
+            new_section.extend_from_slice(&new_body);
+            if !body_placeholders.is_empty() {
+                placeholders
+                    .bodies
+                    .push((usize::try_from(body_index).unwrap(), body_placeholders));
+            }
+        }
+
+        if !reader.is_empty() {
+            return Err(parse_error("Trailing data in code section".to_string()));
+        }
+
+        out.push(section_id);
+        out.extend(leb128::encode_usize(new_section.len()));
+        out.extend_from_slice(&new_section);
+    }
+
+    Ok((out, placeholders))
+}
+
+/// Replaces the post-MVP instructions of the given function body, including its locals, with
+/// placeholders.
+fn replace_in_body(body: &mut Reader) -> Result<(Vec<u8>, Vec<Placeholder>), NewErr> {
+    let mut out = Vec::with_capacity(body.bytes.len());
+    let mut placeholders = Vec::new();
+
+    // Number of instructions encountered so far, indexed by opcode.
+    let mut opcode_counts = [0u32; 256];
+
+    let num_local_groups = body.u32()?;
+    for _ in 0..num_local_groups {
+        let _ = body.u32()?;
+        let _ = body.byte()?;
+    }
+    out.extend_from_slice(&body.bytes[..body.offset]);
+
+    while !body.is_empty() {
+        let instruction_start = body.offset;
+        let opcode = body.byte()?;
+
+        let original = match opcode {
+            0xc0 => Some(Original::I32Extend8S),
+            0xc1 => Some(Original::I32Extend16S),
+            0xc2 => Some(Original::I64Extend8S),
+            0xc3 => Some(Original::I64Extend16S),
+            0xc4 => Some(Original::I64Extend32S),
+            0xfc => match body.u32()? {
+                10 => {
+                    // Destination and source memory indices.
+                    if body.u32()? != 0 || body.u32()? != 0 {
+                        return Err(parse_error("Invalid memory index".to_string()));
+                    }
+                    Some(Original::MemoryCopy)
+                }
+                11 => {
+                    if body.u32()? != 0 {
+                        return Err(parse_error("Invalid memory index".to_string()));
+                    }
+                    Some(Original::MemoryFill)
+                }
+                other => {
+                    return Err(NewErr::ModuleError(ModuleError(format!(
+                        "Unsupported instruction: 0xfc {}",
+                        other
+                    ))))
+                }
+            },
+            _ => {
+                skip_immediates(opcode, body)?;
+                None
+            }
+        };
+
+        match original {
+            Some(original) => {
+                let placeholder = original.placeholder();
+                placeholders.push(Placeholder {
+                    opcode: placeholder[0],
+                    ordinal: opcode_counts[usize::from(placeholder[0])],
+                    original,
+                });
+                for opcode in placeholder {
+                    opcode_counts[usize::from(*opcode)] += 1;
+                }
+                out.extend_from_slice(placeholder);
+            }
+            None => {
+                opcode_counts[usize::from(opcode)] += 1;
+                out.extend_from_slice(&body.bytes[instruction_start..body.offset]);
+            }
+        }
+    }
+
+    Ok((out, placeholders))
+}
+
+/// Advances `body` past the immediates of the MVP instruction whose opcode is `opcode`.
+fn skip_immediates(opcode: u8, body: &mut Reader) -> Result<(), NewErr> {
+    match opcode {
+        // `unreachable`, `nop`, `else`, `end`, `return`, `drop`, `select`, and the numeric
+        // instructions.
+        0x00 | 0x01 | 0x05 | 0x0b | 0x0f | 0x1a | 0x1b | 0x45..=0xbf => {}
+        // `block`, `loop`, `if`. The block type is a signed LEB128 number.
+        0x02..=0x04 => {
+            let _ = body.u64()?;
+        }
+        // `br`, `br_if`, `call`, and the instructions that access locals and globals.
+        0x0c | 0x0d | 0x10 | 0x20..=0x24 => {
+            let _ = body.u32()?;
+        }
+        // `br_table`.
+        0x0e => {
+            let num_targets = body.u32()?;
+            for _ in 0..=num_targets {
+                let _ = body.u32()?;
+            }
+        }
+        // `call_indirect`.
+        0x11 => {
+            let _ = body.u32()?;
+            let _ = body.byte()?;
+        }
+        // Loads and stores, followed with an alignment and an offset.
+        0x28..=0x3e => {
+            let _ = body.u32()?;
+            let _ = body.u32()?;
+        }
+        // `memory.size` and `memory.grow`.
+        0x3f | 0x40 => {
+            let _ = body.byte()?;
+        }
+        // `i32.const` and `i64.const`.
+        0x41 | 0x42 => {
+            let _ = body.u64()?;
+        }
+        // `f32.const` and `f64.const`.
+        0x43 => {
+            let _ = body.bytes(4)?;
+        }
+        0x44 => {
+            let _ = body.bytes(8)?;
+        }
+        other => {
+            return Err(NewErr::ModuleError(ModuleError(format!(
+                "Unsupported instruction: {:#x}",
+                other
+            ))))
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_error(message: String) -> NewErr {
+    NewErr::ModuleError(ModuleError(format!("Failed to parse module: {}", message)))
+}
+
+/// Cursor within an encoded module.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.offset == self.bytes.len()
+    }
+
+    fn byte(&mut self) -> Result<u8, NewErr> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], NewErr> {
+        let bytes = self
+            .bytes
+            .get(self.offset..)
+            .and_then(|bytes| bytes.get(..len))
+            .ok_or_else(|| parse_error("Unexpected end of data".to_string()))?;
+        self.offset += bytes.len();
+        Ok(bytes)
+    }
+
+    /// Reads an LEB128-encoded number of at most 32 bits.
+    fn u32(&mut self) -> Result<u32, NewErr> {
+        u32::try_from(self.leb128(5)?).map_err(|_| parse_error("Number out of range".to_string()))
+    }
+
+    /// Reads an LEB128-encoded number of at most 64 bits, signed or not.
+    fn u64(&mut self) -> Result<u64, NewErr> {
+        self.leb128(10)
+    }
+
+    fn leb128(&mut self, max_bytes: usize) -> Result<u64, NewErr> {
+        let mut value = 0u64;
+        for n in 0..max_bytes {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f)
+                .checked_shl(u32::try_from(n * 7).unwrap())
+                .unwrap_or(0);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(parse_error("Number too large".to_string()))
+    }
+}
+
+/// Replaces the placeholders inserted by [`replace_with_placeholders`] with the actual
+/// implementation of the instructions they stand for. See
+/// [the module-level documentation](..).
+pub(super) fn lower(module: &mut Module, placeholders: Placeholders) {
+    let uses_bulk_memory = placeholders.bodies.iter().any(|(_, list)| {
+        list.iter()
+            .any(|p| matches!(p.original, Original::MemoryCopy | Original::MemoryFill))
+    });
+
+    // Indices of the functions that replace `memory.fill` and `memory.copy`. The functions are
+    // appended at the end of the module, meaning that the indices of the existing functions
+    // aren't modified.
+    let fill_index = u32::try_from(module.functions_space()).unwrap();
+    let copy_index = fill_index + 1;
+
+    for (body_index, body_placeholders) in placeholders.bodies {
+        // The stack limiter only adds instructions around `call` instructions and appends new
+        // functions. The function bodies are thus still in the same order, and their instructions
+        // other than `call` are still in the same order.
+        let code = module.code_section_mut().unwrap().bodies_mut()[body_index]
+            .code_mut()
+            .elements_mut();
+        let original = mem::take(code);
+        code.reserve(original.len());
+
+        let mut opcode_counts = [0u32; 3];
+        let mut body_placeholders = body_placeholders.into_iter().peekable();
+        let mut original = original.into_iter();
+
+        while let Some(instruction) = original.next() {
+            let counter = match instruction {
+                Instruction::I32Popcnt => Some((0, 0x69)),
+                Instruction::I64Popcnt => Some((1, 0x7b)),
+                Instruction::Drop => Some((2, 0x1a)),
+                _ => None,
+            };
+
+            let (counter, opcode) = match counter {
+                Some(c) => c,
+                None => {
+                    code.push(instruction);
+                    continue;
+                }
+            };
+
+            let ordinal = opcode_counts[counter];
+            opcode_counts[counter] += 1;
+
+            match body_placeholders.peek() {
+                Some(p) if p.opcode == opcode && p.ordinal == ordinal => {}
+                _ => {
+                    code.push(instruction);
+                    continue;
+                }
+            }
+
+            match body_placeholders.next().unwrap().original {
+                Original::I32Extend8S => code.extend(i32_sign_extend(24)),
+                Original::I32Extend16S => code.extend(i32_sign_extend(16)),
+                Original::I64Extend8S => code.extend(i64_sign_extend(56)),
+                Original::I64Extend16S => code.extend(i64_sign_extend(48)),
+                Original::I64Extend32S => code.extend(i64_sign_extend(32)),
+                bulk @ (Original::MemoryCopy | Original::MemoryFill) => {
+                    // Skip the two other `drop`s of the placeholder.
+                    for _ in 0..2 {
+                        let next = original.next();
+                        debug_assert!(matches!(next, Some(Instruction::Drop)));
+                        opcode_counts[counter] += 1;
+                    }
+
+                    code.push(Instruction::Call(if bulk == Original::MemoryCopy {
+                        copy_index
+                    } else {
+                        fill_index
+                    }));
+                }
+            }
+        }
+
+        debug_assert!(body_placeholders.next().is_none());
+    }
+
+    if uses_bulk_memory {
+        // The type section, function section, and code section are guaranteed to exist, as
+        // `uses_bulk_memory` is `true` only if there exists at least one function body.
+        let types = module.type_section_mut().unwrap().types_mut();
+        let type_index = u32::try_from(types.len()).unwrap();
+        types.push(Type::Function(FunctionType::new(
+            vec![ValueType::I32; 3],
+            Vec::new(),
+        )));
+
+        let functions = module.function_section_mut().unwrap().entries_mut();
+        functions.push(Func::new(type_index));
+        functions.push(Func::new(type_index));
+
+        let bodies = module.code_section_mut().unwrap().bodies_mut();
+        bodies.push(FuncBody::new(Vec::new(), Instructions::new(memory_fill())));
+        bodies.push(FuncBody::new(Vec::new(), Instructions::new(memory_copy())));
+    }
+}
+
+/// Returns instructions that sign-extend the lowest `32 - shift` bits of the `i32` at the top
+/// of the stack.
+fn i32_sign_extend(shift: i32) -> [Instruction; 4] {
+    [
+        Instruction::I32Const(shift),
+        Instruction::I32Shl,
+        Instruction::I32Const(shift),
+        Instruction::I32ShrS,
+    ]
+}
+
+/// Returns instructions that sign-extend the lowest `64 - shift` bits of the `i64` at the top
+/// of the stack.
+fn i64_sign_extend(shift: i64) -> [Instruction; 4] {
+    [
+        Instruction::I64Const(shift),
+        Instruction::I64Shl,
+        Instruction::I64Const(shift),
+        Instruction::I64ShrS,
+    ]
+}
+
+/// Returns instructions that trap if `local(address) + local(len)` is out of the bounds of the
+/// memory.
+fn bounds_check(address: u32, len: u32) -> Vec<Instruction> {
+    vec![
+        Instruction::GetLocal(address),
+        Instruction::I64ExtendUI32,
+        Instruction::GetLocal(len),
+        Instruction::I64ExtendUI32,
+        Instruction::I64Add,
+        Instruction::CurrentMemory(0),
+        Instruction::I64ExtendUI32,
+        Instruction::I64Const(16),
+        Instruction::I64Shl,
+        Instruction::I64GtU,
+        Instruction::If(BlockType::NoResult),
+        Instruction::Unreachable,
+        Instruction::End,
+    ]
+}
+
+/// Body of a function with the same signature and behaviour as `memory.fill`.
+///
+/// Parameters are the destination, the value, and the number of bytes.
+fn memory_fill() -> Vec<Instruction> {
+    let mut out = bounds_check(0, 2);
+    out.extend_from_slice(&[
+        Instruction::Block(BlockType::NoResult),
+        Instruction::Loop(BlockType::NoResult),
+        // Exit if the number of bytes remaining is 0.
+        Instruction::GetLocal(2),
+        Instruction::I32Eqz,
+        Instruction::BrIf(1),
+        // Write one byte.
+        Instruction::GetLocal(0),
+        Instruction::GetLocal(1),
+        Instruction::I32Store8(0, 0),
+        // Advance the destination and decrease the number of bytes remaining.
+        Instruction::GetLocal(0),
+        Instruction::I32Const(1),
+        Instruction::I32Add,
+        Instruction::SetLocal(0),
+        Instruction::GetLocal(2),
+        Instruction::I32Const(1),
+        Instruction::I32Sub,
+        Instruction::SetLocal(2),
+        Instruction::Br(0),
+        Instruction::End,
+        Instruction::End,
+        Instruction::End,
+    ]);
+    out
+}
+
+/// Body of a function with the same signature and behaviour as `memory.copy`.
+///
+/// Parameters are the destination, the source, and the number of bytes.
+fn memory_copy() -> Vec<Instruction> {
+    let mut out = bounds_check(0, 2);
+    out.extend(bounds_check(1, 2));
+    out.extend_from_slice(&[
+        // The copy is done forward if the destination is before the source, and backward
+        // otherwise, in order to properly handle overlapping ranges.
+        Instruction::GetLocal(0),
+        Instruction::GetLocal(1),
+        Instruction::I32LeU,
+        Instruction::If(BlockType::NoResult),
+        Instruction::Block(BlockType::NoResult),
+        Instruction::Loop(BlockType::NoResult),
+        Instruction::GetLocal(2),
+        Instruction::I32Eqz,
+        Instruction::BrIf(1),
+        Instruction::GetLocal(0),
+        Instruction::GetLocal(1),
+        Instruction::I32Load8U(0, 0),
+        Instruction::I32Store8(0, 0),
+        Instruction::GetLocal(0),
+        Instruction::I32Const(1),
+        Instruction::I32Add,
+        Instruction::SetLocal(0),
+        Instruction::GetLocal(1),
+        Instruction::I32Const(1),
+        Instruction::I32Add,
+        Instruction::SetLocal(1),
+        Instruction::GetLocal(2),
+        Instruction::I32Const(1),
+        Instruction::I32Sub,
+        Instruction::SetLocal(2),
+        Instruction::Br(0),
+        Instruction::End,
+        Instruction::End,
+        Instruction::Else,
+        Instruction::Block(BlockType::NoResult),
+        Instruction::Loop(BlockType::NoResult),
+        Instruction::GetLocal(2),
+        Instruction::I32Eqz,
+        Instruction::BrIf(1),
+        Instruction::GetLocal(2),
+        Instruction::I32Const(1),
+        Instruction::I32Sub,
+        Instruction::SetLocal(2),
+        Instruction::GetLocal(0),
+        Instruction::GetLocal(2),
+        Instruction::I32Add,
+        Instruction::GetLocal(1),
+        Instruction::GetLocal(2),
+        Instruction::I32Add,
+        Instruction::I32Load8U(0, 0),
+        Instruction::I32Store8(0, 0),
+        Instruction::Br(0),
+        Instruction::End,
+        Instruction::End,
+        Instruction::End,
+        Instruction::End,
+    ]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use core::convert::TryFrom as _;
+
+    use super::super::{
+        ExecHint, ExecOutcome, HeapPages, Module, VirtualMachinePrototype, WasmValue,
+    };
+
+    /// Builds a module that exports a memory of one page named `memory` and a function named `f`
+    /// with the given parameters, optional result, and instructions.
+    fn module(params: &[u8], result: Option<u8>, instructions: &[u8]) -> Vec<u8> {
+        fn section(out: &mut Vec<u8>, id: u8, content: &[u8]) {
+            out.push(id);
+            out.extend(crate::util::leb128::encode_usize(content.len()));
+            out.extend_from_slice(content);
+        }
+
+        let mut out = b"\0asm\x01\0\0\0".to_vec();
+
+        let mut types = vec![1, 0x60, u8::try_from(params.len()).unwrap()];
+        types.extend_from_slice(params);
+        types.extend(result.map_or(vec![0], |r| vec![1, r]));
+        section(&mut out, 1, &types);
+        section(&mut out, 3, &[1, 0]);
+        section(&mut out, 5, &[1, 0, 1]);
+        section(
+            &mut out,
+            7,
+            &[
+                2, 1, b'f', 0, 0, 6, b'm', b'e', b'm', b'o', b'r', b'y', 2, 0,
+            ],
+        );
+
+        let mut body = vec![0];
+        body.extend_from_slice(instructions);
+        body.push(0x0b);
+        let mut code = vec![1];
+        code.extend(crate::util::leb128::encode_usize(body.len()));
+        code.extend_from_slice(&body);
+        section(&mut out, 10, &code);
+
+        out
+    }
+
+    /// Calls `f` and returns its return value and the first 32 bytes of the memory.
+    ///
+    /// The rewriting is done before the module is passed to the execution backend, and thus
+    /// doesn't depend on it. Only the interpreter is used.
+    fn run(module: &[u8], params: &[WasmValue]) -> (Result<Option<i64>, ()>, Vec<u8>) {
+        let module = Module::new(module, ExecHint::Oneshot, None).unwrap();
+        let prototype =
+            VirtualMachinePrototype::new(&module, HeapPages::new(0), |_, _, _| Err(())).unwrap();
+        let mut vm = prototype.start("f", params).unwrap();
+        let return_value = match vm.run(None).unwrap() {
+            ExecOutcome::Finished { return_value } => return_value
+                .map(|value| {
+                    value.map(|value| match value {
+                        WasmValue::I32(v) => i64::from(v),
+                        WasmValue::I64(v) => v,
+                    })
+                })
+                .map_err(|_| ()),
+            ExecOutcome::Interrupted { .. } => panic!(),
+        };
+        let memory = vm.read_memory(0, 32).unwrap().as_ref().to_vec();
+        (return_value, memory)
+    }
+
+    #[test]
+    fn placeholders() {
+        let (lowered, placeholders) = super::replace_with_placeholders(&module(
+            &[0x7f],
+            Some(0x7f),
+            &[0x20, 0, 0xc0, 0x69, 0xc1],
+        ))
+        .unwrap();
+
+        assert!(lowered.ends_with(&[0, 0x20, 0, 0x69, 0x69, 0x69, 0x0b]));
+        assert_eq!(placeholders.bodies.len(), 1);
+        assert_eq!(
+            placeholders.bodies[0].1,
+            vec![
+                super::Placeholder {
+                    opcode: 0x69,
+                    ordinal: 0,
+                    original: super::Original::I32Extend8S
+                },
+                super::Placeholder {
+                    opcode: 0x69,
+                    ordinal: 2,
+                    original: super::Original::I32Extend16S
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn no_post_mvp_instruction() {
+        let module = module(&[0x7f], Some(0x7f), &[0x20, 0, 0x69]);
+        let (lowered, placeholders) = super::replace_with_placeholders(&module).unwrap();
+        assert_eq!(lowered, module);
+        assert!(placeholders.bodies.is_empty());
+    }
+
+    #[test]
+    fn sign_extension() {
+        // `local.get 0`, `i32.extend8_s`
+        let module_i32 = module(&[0x7f], Some(0x7f), &[0x20, 0, 0xc0]);
+        for (input, expected) in [(0x80, -128), (0x17f, 127), (-1, -1)] {
+            let (result, _) = run(&module_i32, &[WasmValue::I32(input)]);
+            assert_eq!(result, Ok(Some(expected)));
+        }
+
+        // `local.get 0`, `i64.extend32_s`
+        let module_i64 = module(&[0x7e], Some(0x7e), &[0x20, 0, 0xc4]);
+        for (input, expected) in [(0x8000_0000, -0x8000_0000), (0x1_7fff_ffff, 0x7fff_ffff)] {
+            let (result, _) = run(&module_i64, &[WasmValue::I64(input)]);
+            assert_eq!(result, Ok(Some(expected)));
+        }
+    }
+
+    #[test]
+    fn existing_instructions_not_replaced() {
+        // `local.get 0`, `i32.popcnt`, `i32.extend16_s`. Replacing the wrong `i32.popcnt` would
+        // return 17.
+        let module = module(&[0x7f], Some(0x7f), &[0x20, 0, 0x69, 0xc1]);
+        let (result, _) = run(&module, &[WasmValue::I32(0x8000)]);
+        assert_eq!(result, Ok(Some(1)));
+    }
+
+    #[test]
+    fn memory_fill_and_copy() {
+        let module = module(
+            &[],
+            None,
+            &[
+                // `drop` that isn't part of a placeholder.
+                0x41, 0, 0x1a, // `memory.fill(10, 0xab, 5)`
+                0x41, 10, 0x41, 0xab, 0x01, 0x41, 5, 0xfc, 11, 0,
+                // `memory.fill(11, 0xcd, 1)`
+                0x41, 11, 0x41, 0xcd, 0x01, 0x41, 1, 0xfc, 11, 0,
+                // `memory.copy(12, 10, 5)`, which overlaps.
+                0x41, 12, 0x41, 10, 0x41, 5, 0xfc, 10, 0, 0,
+                // `memory.copy(0, 11, 3)`, which doesn't overlap.
+                0x41, 0, 0x41, 11, 0x41, 3, 0xfc, 10, 0, 0,
+            ],
+        );
+
+        let (result, memory) = run(&module, &[]);
+        assert_eq!(result, Ok(None));
+        assert_eq!(&memory[..3], &[0xcd, 0xab, 0xcd]);
+        assert_eq!(&memory[10..17], &[0xab, 0xcd, 0xab, 0xcd, 0xab, 0xab, 0xab]);
+    }
+
+    #[test]
+    fn memory_fill_out_of_bounds() {
+        // `memory.fill(65535, 0, 2)`
+        let module = module(
+            &[],
+            None,
+            &[0x41, 0xff, 0xff, 0x03, 0x41, 0, 0x41, 2, 0xfc, 11, 0],
+        );
+        let (result, _) = run(&module, &[]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn unsupported_instruction() {
+        // `i32.trunc_sat_f32_s`
+        let module = module(&[0x7d], Some(0x7f), &[0x20, 0, 0xfc, 0]);
+        assert!(Module::new(&module, ExecHint::Oneshot, None).is_err());
+    }
+}
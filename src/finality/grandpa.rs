@@ -18,3 +18,4 @@
 pub mod chain_config;
 pub mod commit;
 pub mod warp_sync;
+pub mod warp_sync_server;
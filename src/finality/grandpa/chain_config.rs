@@ -64,8 +64,15 @@ impl GrandpaGenesisConfiguration {
                 genesis_storage_access(b":heappages").as_deref(),
             )
             .map_err(FromGenesisStorageError::HeapPagesDecode)?;
-            let vm = host::HostVmPrototype::new(&wasm_code, heap_pages, vm::ExecHint::Oneshot)
-                .map_err(FromGenesisStorageError::VmInitialization)?;
+            let vm = host::HostVmPrototype::new(host::Config {
+                module: &wasm_code,
+                heap_pages,
+                exec_hint: vm::ExecHint::Oneshot,
+                allow_unresolved_imports: false,
+                max_memory_size: None,
+                metered: false,
+            })
+            .map_err(FromGenesisStorageError::VmInitialization)?;
             Self::from_virtual_machine_prototype(vm, genesis_storage_access)
                 .map_err(FromGenesisStorageError::VmError)?
         };
@@ -91,7 +98,7 @@ impl GrandpaGenesisConfiguration {
                 }
                 host::HostVm::Error { .. } => return Err(FromVmPrototypeError::Trapped),
 
-                host::HostVm::ExternalStorageGet(rq) => {
+                host::HostVm::ExternalStorageGet(rq) if rq.child_trie().is_none() => {
                     let value = genesis_storage_access(rq.key().as_ref());
                     vm = rq.resume_full_value(value.as_ref().map(|v| &v[..]));
                 }
@@ -0,0 +1,442 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Generation of GrandPa warp sync proofs.
+//!
+//! This module is the counterpart of [the `warp_sync` module](super::warp_sync). It allows
+//! answering the GrandPa warp sync requests sent by other nodes. See also
+//! [`crate::network::protocol::GrandpaWarpSyncResponse`].
+//!
+//! A GrandPa warp sync proof consists in the list of all the blocks that contain a change in the
+//! list of GrandPa authorities, each accompanied with a justification, followed with a recent
+//! finalized block and its justification.
+//!
+//! The [`WarpSyncServer`] must be informed, through [`WarpSyncServer::inject_finalized`], of the
+//! blocks that get finalized. Only the blocks whose finality has been proven with a
+//! justification are useful, but all the blocks that schedule or enact a change in the list of
+//! authorities must be reported, with or without justification, in order for the
+//! [`WarpSyncServer`] to keep track of the identifier of the authorities set and to detect when
+//! it is no longer capable of generating a proof.
+//!
+//! Proofs can only be generated starting from the block passed in [`Config`], or from a block
+//! that contains a change in the list of authorities and that has been reported with a
+//! justification.
+//!
+//! > **Note**: Similarly to [the `warp_sync` module](super::warp_sync), only the changes in the
+//! >           list of authorities that are enacted immediately, in other words that have a
+//! >           delay of `0`, can be part of a proof. Enacting a change that has a non-zero delay
+//! >           or that is forced breaks the chain of proofs, and proofs can then only be
+//! >           generated starting from the enacting block.
+
+use crate::header;
+
+use alloc::vec::Vec;
+use core::convert::TryFrom as _;
+
+/// Configuration for a [`WarpSyncServer`].
+#[derive(Debug)]
+pub struct Config {
+    /// Hash of the block from which proofs can be generated. Typically the genesis block.
+    pub start_block_hash: [u8; 32],
+
+    /// Height of the block whose hash is [`Config::start_block_hash`].
+    pub start_block_number: u64,
+
+    /// Identifier of the GrandPa authorities set that must finalize the children of the block
+    /// whose hash is [`Config::start_block_hash`].
+    pub start_authorities_set_id: u64,
+
+    /// Maximum number of fragments to put in a single response. If a proof is longer than that,
+    /// it is cut, and the requester is expected to send a follow-up request.
+    pub max_fragments_per_response: usize,
+}
+
+/// Collection of the information necessary to generate GrandPa warp sync proofs. See
+/// [the module-level documentation](..).
+#[derive(Debug)]
+pub struct WarpSyncServer {
+    /// Hash and height of the block from which proofs can be generated. Always the block that
+    /// precedes the first element of [`WarpSyncServer::fragments`].
+    start_block: ([u8; 32], u64),
+
+    /// Identifier of the authorities set that must finalize the blocks that follow the last
+    /// block reported through [`WarpSyncServer::inject_finalized`].
+    authorities_set_id: u64,
+
+    /// Height of the last block reported through [`WarpSyncServer::inject_finalized`].
+    finalized_block_number: u64,
+
+    /// Heights of the blocks that will enact a change in the list of authorities that has been
+    /// scheduled with a non-zero delay, or that has been forced, by a block reported through
+    /// [`WarpSyncServer::inject_finalized`].
+    pending_changes: Vec<u64>,
+
+    /// List of all the blocks that contain a change in the list of authorities since
+    /// [`WarpSyncServer::start_block`], ordered by increasing height.
+    fragments: Vec<Fragment>,
+
+    /// Latest finalized block that has a justification, if it is more recent than the last
+    /// element of [`WarpSyncServer::fragments`].
+    latest_finalized: Option<Fragment>,
+
+    /// See [`Config::max_fragments_per_response`].
+    max_fragments_per_response: usize,
+}
+
+#[derive(Debug)]
+struct Fragment {
+    /// Hash of the block.
+    hash: [u8; 32],
+    /// Height of the block.
+    number: u64,
+    /// SCALE-encoded header of the block.
+    scale_encoded_header: Vec<u8>,
+    /// SCALE-encoded GrandPa justification whose target is the block.
+    scale_encoded_justification: Vec<u8>,
+}
+
+impl WarpSyncServer {
+    /// Initializes a new [`WarpSyncServer`].
+    pub fn new(config: Config) -> Self {
+        WarpSyncServer {
+            start_block: (config.start_block_hash, config.start_block_number),
+            authorities_set_id: config.start_authorities_set_id,
+            finalized_block_number: config.start_block_number,
+            pending_changes: Vec::new(),
+            fragments: Vec::new(),
+            latest_finalized: None,
+            max_fragments_per_response: config.max_fragments_per_response,
+        }
+    }
+
+    /// Reports a block that has been finalized, alongside with the SCALE-encoded GrandPa
+    /// justification that proves its finality, if any.
+    ///
+    /// `authorities_set_id` must be the identifier of the GrandPa authorities set that must
+    /// finalize the children of this block, if known. If `None`, it is deduced from the changes
+    /// in the list of authorities found in the reported blocks.
+    ///
+    /// Blocks must be reported in increasing height. Blocks that aren't more recent than the
+    /// last reported block are ignored.
+    ///
+    /// If the identifier of the authorities set isn't the one expected, meaning that a change in
+    /// the list of authorities has been missed, or if a change in the list of authorities that
+    /// can't be proven is enacted, for example because the enacting block is reported without a
+    /// justification, all the information gathered so far is discarded and proofs can only be
+    /// generated starting from this block.
+    pub fn inject_finalized(
+        &mut self,
+        scale_encoded_header: Vec<u8>,
+        scale_encoded_justification: Option<Vec<u8>>,
+        authorities_set_id: Option<u64>,
+    ) -> Result<(), InjectError> {
+        let decoded = header::decode(&scale_encoded_header).map_err(InjectError::BadHeader)?;
+        if decoded.number <= self.finalized_block_number {
+            return Ok(());
+        }
+
+        let hash = decoded.hash();
+        let number = decoded.number;
+
+        // Changes that are scheduled with a delay of `0` are enacted by this block, and can be
+        // proven. Other changes are enacted later.
+        let mut has_authorities_change = false;
+        for log in decoded.digest.logs() {
+            match log {
+                header::DigestItemRef::GrandpaConsensus(
+                    header::GrandpaConsensusLogRef::ScheduledChange(change),
+                ) if change.delay == 0 => has_authorities_change = true,
+                header::DigestItemRef::GrandpaConsensus(
+                    header::GrandpaConsensusLogRef::ScheduledChange(change),
+                )
+                | header::DigestItemRef::GrandpaConsensus(
+                    header::GrandpaConsensusLogRef::ForcedChange { change, .. },
+                ) => self.pending_changes.push(number + u64::from(change.delay)),
+                _ => {}
+            }
+        }
+
+        // Changes that are enacted by this block or that should have been enacted by a block
+        // that hasn't been reported.
+        let num_unprovable_changes = self
+            .pending_changes
+            .iter()
+            .filter(|height| **height <= number)
+            .count();
+        self.pending_changes.retain(|height| *height > number);
+
+        let expected_set_id = self.authorities_set_id
+            + u64::try_from(num_unprovable_changes).unwrap()
+            + if has_authorities_change { 1 } else { 0 };
+        let authorities_set_id = authorities_set_id.unwrap_or(expected_set_id);
+
+        self.finalized_block_number = number;
+        self.authorities_set_id = authorities_set_id;
+
+        match scale_encoded_justification {
+            _ if expected_set_id != authorities_set_id || num_unprovable_changes != 0 => {
+                // A change in the list of authorities has been missed or can't be proven.
+                self.start_block = (hash, number);
+                self.fragments.clear();
+                self.latest_finalized = None;
+            }
+            Some(scale_encoded_justification) => {
+                let fragment = Fragment {
+                    hash,
+                    number,
+                    scale_encoded_header,
+                    scale_encoded_justification,
+                };
+
+                if has_authorities_change {
+                    self.fragments.push(fragment);
+                    self.latest_finalized = None;
+                } else {
+                    self.latest_finalized = Some(fragment);
+                }
+            }
+            None if !has_authorities_change => {}
+            None => {
+                // The change in the list of authorities can't be proven.
+                self.start_block = (hash, number);
+                self.fragments.clear();
+                self.latest_finalized = None;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Builds the response to a GrandPa warp sync request whose starting point is the given
+    /// block hash.
+    ///
+    /// Returns `None` if no proof can be generated starting from this block, for example because
+    /// this block is unknown.
+    pub fn build_response(&self, begin_hash: &[u8; 32]) -> Option<Response<'_>> {
+        let begin_number = if *begin_hash == self.start_block.0 {
+            self.start_block.1
+        } else {
+            self.fragments
+                .iter()
+                .chain(self.latest_finalized.iter())
+                .find(|f| f.hash == *begin_hash)?
+                .number
+        };
+
+        let mut fragments = self
+            .fragments
+            .iter()
+            .filter(|f| f.number > begin_number)
+            .map(|f| {
+                (
+                    &f.scale_encoded_header[..],
+                    &f.scale_encoded_justification[..],
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let is_finished = fragments.len() <= self.max_fragments_per_response;
+        fragments.truncate(self.max_fragments_per_response);
+
+        if is_finished {
+            if let Some(latest) = self
+                .latest_finalized
+                .as_ref()
+                .filter(|f| f.number > begin_number)
+            {
+                fragments.push((
+                    &latest.scale_encoded_header[..],
+                    &latest.scale_encoded_justification[..],
+                ));
+            }
+        }
+
+        Some(Response {
+            fragments,
+            is_finished,
+        })
+    }
+}
+
+/// Response to a GrandPa warp sync request. See [`WarpSyncServer::build_response`].
+#[derive(Debug)]
+pub struct Response<'a> {
+    /// List of SCALE-encoded headers and SCALE-encoded justifications, ordered by increasing
+    /// height.
+    pub fragments: Vec<(&'a [u8], &'a [u8])>,
+
+    /// `true` if the proof hasn't been cut. See
+    /// [`crate::network::protocol::GrandpaWarpSyncResponse::is_finished`].
+    pub is_finished: bool,
+}
+
+/// Error potentially returned by [`WarpSyncServer::inject_finalized`].
+#[derive(Debug, derive_more::Display)]
+pub enum InjectError {
+    /// Failed to decode the header.
+    BadHeader(header::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, WarpSyncServer};
+    use crate::header;
+
+    fn header(number: u64, parent_hash: [u8; 32]) -> header::Header {
+        header::Header {
+            parent_hash,
+            number,
+            state_root: [0; 32],
+            extrinsics_root: [0; 32],
+            digest: header::DigestRef::empty().into(),
+        }
+    }
+
+    fn header_with_change(number: u64, parent_hash: [u8; 32], delay: u32) -> header::Header {
+        let logs = [header::DigestItem::GrandpaConsensus(
+            header::GrandpaConsensusLog::ScheduledChange(header::GrandpaScheduledChange {
+                next_authorities: Vec::new(),
+                delay,
+            }),
+        )];
+
+        header::Header {
+            digest: header::DigestRef::from_slice(&logs).unwrap().into(),
+            ..header(number, parent_hash)
+        }
+    }
+
+    #[test]
+    fn latest_finalized() {
+        let genesis = header(0, [0; 32]);
+        let mut server = WarpSyncServer::new(Config {
+            start_block_hash: genesis.hash(),
+            start_block_number: 0,
+            start_authorities_set_id: 0,
+            max_fragments_per_response: 8,
+        });
+
+        let block1 = header(1, genesis.hash());
+        let block2 = header(2, block1.hash());
+        server
+            .inject_finalized(block1.scale_encoding_vec(), Some(vec![1]), Some(0))
+            .unwrap();
+        server
+            .inject_finalized(block2.scale_encoding_vec(), None, Some(0))
+            .unwrap();
+
+        // Only the latest block with a justification is included.
+        let response = server.build_response(&genesis.hash()).unwrap();
+        assert!(response.is_finished);
+        assert_eq!(response.fragments.len(), 1);
+        assert_eq!(response.fragments[0].0, &block1.scale_encoding_vec()[..]);
+        assert_eq!(response.fragments[0].1, &[1]);
+
+        assert!(server.build_response(&block2.hash()).is_none());
+    }
+
+    #[test]
+    fn missed_authorities_change() {
+        let genesis = header(0, [0; 32]);
+        let mut server = WarpSyncServer::new(Config {
+            start_block_hash: genesis.hash(),
+            start_block_number: 0,
+            start_authorities_set_id: 0,
+            max_fragments_per_response: 8,
+        });
+
+        // The authorities set identifier has changed without a block containing a change.
+        let block1 = header(1, genesis.hash());
+        server
+            .inject_finalized(block1.scale_encoding_vec(), Some(vec![1]), Some(1))
+            .unwrap();
+
+        assert!(server.build_response(&genesis.hash()).is_none());
+        let response = server.build_response(&block1.hash()).unwrap();
+        assert!(response.fragments.is_empty());
+    }
+
+    #[test]
+    fn immediate_authorities_change() {
+        let genesis = header(0, [0; 32]);
+        let mut server = WarpSyncServer::new(Config {
+            start_block_hash: genesis.hash(),
+            start_block_number: 0,
+            start_authorities_set_id: 0,
+            max_fragments_per_response: 8,
+        });
+
+        let block1 = header_with_change(1, genesis.hash(), 0);
+        let block2 = header(2, block1.hash());
+        server
+            .inject_finalized(block1.scale_encoding_vec(), Some(vec![1]), None)
+            .unwrap();
+        server
+            .inject_finalized(block2.scale_encoding_vec(), Some(vec![2]), Some(1))
+            .unwrap();
+
+        let response = server.build_response(&genesis.hash()).unwrap();
+        assert_eq!(response.fragments.len(), 2);
+        assert_eq!(response.fragments[0].0, &block1.scale_encoding_vec()[..]);
+        assert_eq!(response.fragments[1].0, &block2.scale_encoding_vec()[..]);
+        let response = server.build_response(&block1.hash()).unwrap();
+        assert_eq!(response.fragments.len(), 1);
+    }
+
+    #[test]
+    fn delayed_authorities_change() {
+        let genesis = header(0, [0; 32]);
+        let mut server = WarpSyncServer::new(Config {
+            start_block_hash: genesis.hash(),
+            start_block_number: 0,
+            start_authorities_set_id: 0,
+            max_fragments_per_response: 8,
+        });
+
+        // The change is scheduled by block 1 and enacted by block 3, which isn't reported.
+        let block1 = header_with_change(1, genesis.hash(), 2);
+        let block2 = header(2, block1.hash());
+        let block3 = header(3, block2.hash());
+        let block4 = header(4, block3.hash());
+        server
+            .inject_finalized(block1.scale_encoding_vec(), None, None)
+            .unwrap();
+        server
+            .inject_finalized(block2.scale_encoding_vec(), Some(vec![2]), Some(0))
+            .unwrap();
+        assert_eq!(
+            server
+                .build_response(&genesis.hash())
+                .unwrap()
+                .fragments
+                .len(),
+            1
+        );
+
+        // The authorities set identifier of block 4 is the expected one, but the change can't be
+        // proven.
+        server
+            .inject_finalized(block4.scale_encoding_vec(), Some(vec![4]), Some(1))
+            .unwrap();
+        assert!(server.build_response(&genesis.hash()).is_none());
+        assert!(server
+            .build_response(&block4.hash())
+            .unwrap()
+            .fragments
+            .is_empty());
+    }
+}
@@ -28,5 +28,34 @@
 //! When a justification is received from a third party, it must first be verified. See the
 //! [`verify`] module.
 
+use alloc::vec::Vec;
+
 pub mod decode;
 pub mod verify;
+
+/// Builds the SCALE encoding of a GrandPa finality proof, as returned for example by the
+/// `grandpa_proveFinality` JSON-RPC function.
+///
+/// A finality proof contains the hash of a finalized block, a justification whose target is
+/// this block, and the list of headers between the two. Since the justification must target the
+/// given block, this list is always empty.
+pub fn encode_grandpa_finality_proof(
+    block_hash: &[u8; 32],
+    scale_encoded_justification: &[u8],
+) -> Vec<u8> {
+    let justification_len =
+        crate::util::encode_scale_compact_usize(scale_encoded_justification.len());
+    let unknown_headers_len = crate::util::encode_scale_compact_usize(0);
+
+    let mut out = Vec::with_capacity(
+        block_hash.len()
+            + justification_len.as_ref().len()
+            + scale_encoded_justification.len()
+            + unknown_headers_len.as_ref().len(),
+    );
+    out.extend_from_slice(block_hash);
+    out.extend_from_slice(justification_len.as_ref());
+    out.extend_from_slice(scale_encoded_justification);
+    out.extend_from_slice(unknown_headers_len.as_ref());
+    out
+}
@@ -71,7 +71,7 @@
 // TODO: consider rewriting the encoding/decoding into a more legible style
 // TODO: consider nom for decoding
 
-use crate::util;
+use crate::{trie, util};
 
 use alloc::{vec, vec::Vec};
 use core::{convert::TryFrom, fmt, iter, slice};
@@ -113,6 +113,29 @@ pub fn hash_from_scale_encoded_header_vectored(
     out
 }
 
+/// Returns the value of the [`HeaderRef::extrinsics_root`] field of a block whose body consists
+/// of the given single-SCALE-encoded extrinsics.
+///
+/// The extrinsics root is the root of the trie whose keys are the SCALE-compact-encoded indices
+/// of the extrinsics and whose values are the double-SCALE-encoded extrinsics. The version of
+/// the trie entries format to use depends on the runtime of the chain.
+pub fn extrinsics_root(
+    extrinsics: impl Iterator<Item = impl AsRef<[u8]>>,
+    version: trie::TrieEntryVersion,
+) -> [u8; 32] {
+    trie::ordered_root(
+        version,
+        extrinsics.map(|extrinsic| {
+            let extrinsic = extrinsic.as_ref();
+            let mut double_encoded = Vec::with_capacity(extrinsic.len() + 4);
+            double_encoded
+                .extend_from_slice(util::encode_scale_compact_usize(extrinsic.len()).as_ref());
+            double_encoded.extend_from_slice(extrinsic);
+            double_encoded
+        }),
+    )
+}
+
 /// Attempt to decode the given SCALE-encoded header.
 pub fn decode(scale_encoded: &[u8]) -> Result<HeaderRef, Error> {
     let (header, remainder) = decode_partial(scale_encoded)?;
@@ -17,6 +17,8 @@
 
 #![cfg(test)]
 
+use core::convert::TryFrom as _;
+
 #[test]
 fn decode_rococo() {
     // Rococo block taken 2021-04-08 around 11:00 UTC.
@@ -56,3 +58,35 @@ fn decode_reencode_kusama_7472481() {
     let decoded = super::decode(expected).unwrap();
     assert_eq!(decoded.scale_encoding_vec(), expected);
 }
+
+#[test]
+fn extrinsics_root_matches_trie() {
+    let extrinsics: &[&[u8]] = &[b"foo", &[0xaa; 40], b""];
+
+    let mut trie = crate::trie::Trie::new();
+    for (index, extrinsic) in extrinsics.iter().enumerate() {
+        // All the lengths and indices are inferior to 64, and their SCALE-compact encoding is
+        // thus a single byte.
+        let mut value = vec![u8::try_from(extrinsic.len() << 2).unwrap()];
+        value.extend_from_slice(extrinsic);
+        trie.insert(&[u8::try_from(index << 2).unwrap()], value);
+    }
+
+    for version in [
+        crate::trie::TrieEntryVersion::V0,
+        crate::trie::TrieEntryVersion::V1,
+    ] {
+        assert_eq!(
+            super::extrinsics_root(extrinsics.iter(), version),
+            trie.root_merkle_value(version, None)
+        );
+    }
+
+    assert_eq!(
+        super::extrinsics_root(
+            core::iter::empty::<&[u8]>(),
+            crate::trie::TrieEntryVersion::V0
+        ),
+        crate::trie::empty_trie_merkle_value()
+    );
+}
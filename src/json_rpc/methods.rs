@@ -22,6 +22,7 @@ use crate::{header, util};
 
 use alloc::{
     boxed::Box,
+    collections::BTreeMap,
     format,
     string::{String, ToString as _},
     vec::Vec,
@@ -299,9 +300,10 @@ define_methods! {
     childstate_getStorage() -> (), // TODO:
     childstate_getStorageHash() -> (), // TODO:
     childstate_getStorageSize() -> (), // TODO:
+    grandpa_proveFinality(block_number: u64) -> Option<HexString>,
     grandpa_roundState() -> (), // TODO:
-    offchain_localStorageGet() -> (), // TODO:
-    offchain_localStorageSet() -> (), // TODO:
+    offchain_localStorageGet(kind: StorageKind, key: HexString) -> Option<HexString>,
+    offchain_localStorageSet(kind: StorageKind, key: HexString, value: HexString) -> (),
     payment_queryInfo(extrinsic: HexString, hash: Option<HashHexString>) -> RuntimeDispatchInfo,
     /// Returns a list of all JSON-RPC methods that are available.
     rpc_methods() -> RpcMethods,
@@ -332,7 +334,7 @@ define_methods! {
     system_localPeerId() -> &'a str,
     /// Returns, as an opaque string, the name of the client serving these JSON-RPC requests.
     system_name() -> &'a str,
-    system_networkState() -> (), // TODO:
+    system_networkState() -> NetworkState,
     system_nodeRoles() -> (), // TODO:
     system_peers() -> Vec<SystemPeer>,
     system_properties() -> Box<serde_json::value::RawValue>,
@@ -510,6 +512,18 @@ pub enum DispatchClass {
     Mandatory,
 }
 
+/// Kind of offchain storage targeted by `offchain_localStorageGet` and
+/// `offchain_localStorageSet`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, serde::Deserialize)]
+pub enum StorageKind {
+    /// Storage that is persisted across restarts.
+    #[serde(rename = "PERSISTENT")]
+    Persistent,
+    /// Storage that is local to the node and that isn't necessarily persisted.
+    #[serde(rename = "LOCAL")]
+    Local,
+}
+
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct StorageChangeSet {
     pub block: HashHexString,
@@ -523,6 +537,28 @@ pub struct SystemHealth {
     pub should_have_peers: bool,
 }
 
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct NetworkState {
+    #[serde(rename = "peerId")]
+    pub peer_id: String,
+    /// Keys are the base58 encoding of the [`PeerId`](crate::libp2p::PeerId)s of the peers.
+    #[serde(rename = "connectedPeers")]
+    pub connected_peers: BTreeMap<String, NetworkStatePeer>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct NetworkStatePeer {
+    /// Agent version reported by the peer through the identify protocol. `None` if the peer
+    /// hasn't answered the identify request.
+    #[serde(rename = "versionString")]
+    pub version_string: Option<String>,
+    /// Addresses the peer reports listening on.
+    #[serde(rename = "knownAddresses")]
+    pub known_addresses: Vec<String>,
+    /// Names of the protocols the peer reports supporting.
+    pub protocols: Vec<String>,
+}
+
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct SystemPeer {
     #[serde(rename = "peerId")]
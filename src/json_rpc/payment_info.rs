@@ -43,6 +43,54 @@ pub fn decode_payment_info(
     }
 }
 
+/// Name of the runtime function to call in order to obtain the breakdown of the payment fees.
+///
+/// The input to pass to this function is the same as for [`PAYMENT_FEES_FUNCTION_NAME`], and
+/// can be obtained with [`payment_info_parameters`].
+pub const FEE_DETAILS_FUNCTION_NAME: &str = "TransactionPaymentApi_query_fee_details";
+
+/// Breakdown of the fees of a transaction, as returned by
+/// the `TransactionPaymentApi_query_fee_details` runtime call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeeDetails {
+    /// Fees paid in order for the transaction to be included in a block. `None` for
+    /// transactions that don't pay any inclusion fee, such as unsigned transactions.
+    pub inclusion_fee: Option<InclusionFee>,
+    /// Tip paid by the transaction on top of the inclusion fee.
+    pub tip: u128,
+}
+
+/// See [`FeeDetails::inclusion_fee`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InclusionFee {
+    /// Minimum fee paid by any transaction.
+    pub base_fee: u128,
+    /// Fee proportional to the length of the transaction.
+    pub len_fee: u128,
+    /// Fee proportional to the weight of the transaction, after having been adjusted according
+    /// to the congestion of the chain.
+    pub adjusted_weight_fee: u128,
+}
+
+impl InclusionFee {
+    /// Returns the sum of all the fees.
+    pub fn total(&self) -> u128 {
+        self.base_fee
+            .saturating_add(self.len_fee)
+            .saturating_add(self.adjusted_weight_fee)
+    }
+}
+
+/// Attempt to decode the output of the `TransactionPaymentApi_query_fee_details` runtime call.
+pub fn decode_fee_details(scale_encoded: &'_ [u8]) -> Result<FeeDetails, DecodeError> {
+    match nom::combinator::all_consuming(nom_decode_fee_details::<nom::error::Error<&'_ [u8]>>)(
+        scale_encoded,
+    ) {
+        Ok((_, details)) => Ok(details),
+        Err(_) => Err(DecodeError()),
+    }
+}
+
 /// Potential error when decoding payment information runtime output.
 #[derive(Debug, derive_more::Display)]
 #[display(fmt = "Payment info parsing error")]
@@ -70,3 +118,54 @@ fn nom_decode_payment_info<'a, E: nom::error::ParseError<&'a [u8]>>(
         },
     )(value)
 }
+
+fn nom_decode_fee_details<'a, E: nom::error::ParseError<&'a [u8]>>(
+    value: &'a [u8],
+) -> nom::IResult<&'a [u8], FeeDetails, E> {
+    // TODO: the balances are actually of type `Balance`; figure out how to find that type
+    nom::combinator::map(
+        nom::sequence::tuple((
+            crate::util::nom_option_decode(nom_decode_inclusion_fee),
+            nom::number::complete::le_u128,
+        )),
+        |(inclusion_fee, tip)| FeeDetails { inclusion_fee, tip },
+    )(value)
+}
+
+fn nom_decode_inclusion_fee<'a, E: nom::error::ParseError<&'a [u8]>>(
+    value: &'a [u8],
+) -> nom::IResult<&'a [u8], InclusionFee, E> {
+    nom::combinator::map(
+        nom::sequence::tuple((
+            nom::number::complete::le_u128,
+            nom::number::complete::le_u128,
+            nom::number::complete::le_u128,
+        )),
+        |(base_fee, len_fee, adjusted_weight_fee)| InclusionFee {
+            base_fee,
+            len_fee,
+            adjusted_weight_fee,
+        },
+    )(value)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn decode_fee_details() {
+        let mut encoded = vec![1];
+        encoded.extend_from_slice(&1u128.to_le_bytes());
+        encoded.extend_from_slice(&2u128.to_le_bytes());
+        encoded.extend_from_slice(&3u128.to_le_bytes());
+        encoded.extend_from_slice(&4u128.to_le_bytes());
+
+        let decoded = super::decode_fee_details(&encoded).unwrap();
+        assert_eq!(decoded.inclusion_fee.as_ref().unwrap().total(), 6);
+        assert_eq!(decoded.tip, 4);
+
+        let mut encoded = vec![0];
+        encoded.extend_from_slice(&4u128.to_le_bytes());
+        let decoded = super::decode_fee_details(&encoded).unwrap();
+        assert!(decoded.inclusion_fee.is_none());
+    }
+}
@@ -181,7 +181,8 @@
 //! documentation.
 //! - A JSON-RPC client, in order to put a convenient-to-use UI on top of the client. See the
 //! [`json_rpc`] module.
-//! - TODO: telemetry
+//! - The capacity to report information about the node to telemetry servers. See the
+//! [`telemetry`] module.
 //!
 
 // The library part of `smoldot` should as pure as possible and shouldn't rely on any environment
@@ -209,6 +210,7 @@ pub mod libp2p;
 pub mod metadata;
 pub mod network;
 pub mod sync;
+pub mod telemetry;
 pub mod transactions;
 pub mod trie;
 pub mod verify;
@@ -217,6 +219,9 @@ mod util;
 
 /// Builds the header of the genesis block, from the values in storage.
 ///
+/// If the chain spec contains only the root hash of the genesis storage, this hash is used
+/// directly.
+///
 /// # Example
 ///
 /// ```no_run
@@ -228,25 +233,18 @@ mod util;
 /// println!("{:?}", genesis_block_header);
 /// ```
 pub fn calculate_genesis_block_header(chain_spec: &chain_spec::ChainSpec) -> header::Header {
-    let state_root = {
-        let mut calculation = trie::calculate_root::root_merkle_value(None);
-
-        loop {
-            match calculation {
-                trie::calculate_root::RootMerkleValueCalculation::Finished { hash, .. } => {
-                    break hash
-                }
-                trie::calculate_root::RootMerkleValueCalculation::AllKeys(keys) => {
-                    calculation =
-                        keys.inject(chain_spec.genesis_storage().map(|(k, _)| k.iter().cloned()));
-                }
-                trie::calculate_root::RootMerkleValueCalculation::StorageValue(val) => {
-                    let key: alloc::vec::Vec<u8> = val.key().collect();
-                    let value = chain_spec.genesis_storage_value(&key[..]);
-                    calculation = val.inject(value);
-                }
+    let state_root = match chain_spec.genesis_storage() {
+        chain_spec::GenesisStorage::Items(items) => {
+            // The genesis storage is ordered by key, which makes it possible to stream it.
+            let mut calculation = trie::streaming_root::StreamingRootCalculation::new(
+                genesis_trie_entry_version(&items),
+            );
+            for (key, value) in items.iter() {
+                calculation.push(key, value);
             }
+            calculation.finish()
         }
+        chain_spec::GenesisStorage::TrieRootHash(hash) => *hash,
     };
 
     header::Header {
@@ -257,3 +255,42 @@ pub fn calculate_genesis_block_header(chain_spec: &chain_spec::ChainSpec) -> hea
         digest: header::DigestRef::empty().into(),
     }
 }
+
+/// Returns the version of the trie entries of the genesis storage, as indicated by the
+/// `Core_version` function of the genesis runtime.
+///
+/// Version 0 is assumed if the runtime can't be executed or doesn't indicate any version, which
+/// is the case for runtimes that predate the introduction of trie version 1.
+pub fn genesis_trie_entry_version(
+    items: &chain_spec::GenesisStorageItems,
+) -> trie::TrieEntryVersion {
+    let code = match items.value(b":code") {
+        Some(code) => code,
+        None => return trie::TrieEntryVersion::V0,
+    };
+    let heap_pages = match executor::storage_heap_pages_to_value(items.value(b":heappages")) {
+        Ok(heap_pages) => heap_pages,
+        Err(_) => return trie::TrieEntryVersion::V0,
+    };
+
+    let vm = match executor::host::HostVmPrototype::new(executor::host::Config {
+        module: code,
+        heap_pages,
+        exec_hint: executor::vm::ExecHint::Oneshot,
+        allow_unresolved_imports: true,
+        max_memory_size: None,
+        metered: false,
+    }) {
+        Ok(vm) => vm,
+        Err(_) => return trie::TrieEntryVersion::V0,
+    };
+
+    match executor::core_version(vm).0 {
+        Ok(version) => version
+            .decode()
+            .state_version
+            .and_then(|v| trie::TrieEntryVersion::from_state_version(u32::from(v)))
+            .unwrap_or(trie::TrieEntryVersion::V0),
+        Err(_) => trie::TrieEntryVersion::V0,
+    }
+}
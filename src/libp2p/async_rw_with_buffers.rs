@@ -22,11 +22,18 @@
 //! buffer.
 //!
 //! While this module is generic, the targeted use-case is TCP connections.
+//!
+//! The [`drive`] function uses a [`WithBuffers`] in order to drive a state machine that
+//! synchronizes through a [`ReadWrite`], such as a connection of the
+//! [`collection`](super::collection) module, until the socket is closed.
 
 // TODO: usage and example
 
-use core::{fmt, pin::Pin, task::Poll};
+use super::read_write::ReadWrite;
+
+use core::{fmt, ops::Sub, pin::Pin, task::Poll, time::Duration};
 use futures::{
+    future::BoxFuture,
     io::{AsyncRead, AsyncWrite},
     prelude::*,
 };
@@ -423,6 +430,113 @@ where
     }
 }
 
+/// Drives the given socket until it is closed.
+///
+/// At each iteration, a [`ReadWrite`] is built from the buffers of the socket and passed to
+/// `process`, which is expected to synchronize the state machine of the connection with it.
+/// The socket is then processed until either data can be read or written, the
+/// [`ReadWrite::wake_up_future`] is ready, or the [`ReadWrite::wake_up_after`] has been reached.
+///
+/// `get_now` must return the current time, and `sleep` must return a future that is ready after
+/// the given duration.
+///
+/// Returns `Ok` once both sides of the socket have been closed. Returns an error if an error
+/// happens on the socket or if `process` returns an error.
+pub async fn drive<T, TNow, TDelay, E>(
+    socket: T,
+    mut get_now: impl FnMut() -> TNow,
+    mut sleep: impl FnMut(Duration) -> TDelay,
+    mut process: impl for<'a, 'b> FnMut(&'a mut ReadWrite<'b, TNow>) -> BoxFuture<'a, Result<(), E>>,
+) -> Result<(), DriveError<E>>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+    TNow: Clone + Ord + Sub<TNow, Output = Duration>,
+    TDelay: Future<Output = ()>,
+{
+    let socket = WithBuffers::new(socket);
+    futures::pin_mut!(socket);
+
+    loop {
+        let (read_buffer, write_buffer) = match socket.buffers() {
+            Ok(b) => b,
+            Err(err) => {
+                return Err(DriveError::Socket(io::Error::new(
+                    err.kind(),
+                    err.to_string(),
+                )))
+            }
+        };
+
+        let read_closed = read_buffer.is_none();
+        let now = get_now();
+
+        let mut read_write = ReadWrite {
+            now: now.clone(),
+            incoming_buffer: read_buffer.map(|b| b.0),
+            outgoing_buffer: write_buffer,
+            read_bytes: 0,
+            written_bytes: 0,
+            wake_up_after: None,
+            wake_up_future: None,
+        };
+
+        process(&mut read_write)
+            .await
+            .map_err(DriveError::Process)?;
+
+        if read_write.outgoing_buffer.is_none() && read_closed {
+            // Make sure to finish closing the socket.
+            socket.flush_close().await;
+            return Ok(());
+        }
+
+        let read_bytes = read_write.read_bytes;
+        let written_bytes = read_write.written_bytes;
+        let write_closed = read_write.outgoing_buffer.is_none();
+        let wake_up_after = read_write.wake_up_after;
+        let wake_up_future = if let Some(wake_up_future) = read_write.wake_up_future {
+            future::Either::Left(wake_up_future)
+        } else {
+            future::Either::Right(future::pending())
+        };
+
+        if write_closed && !socket.is_closed() {
+            socket.close();
+        }
+
+        socket.advance(read_bytes, written_bytes);
+
+        let poll_after = if let Some(wake_up) = wake_up_after {
+            if wake_up > now {
+                future::Either::Left(sleep(wake_up - now))
+            } else {
+                continue;
+            }
+        } else {
+            future::Either::Right(future::pending())
+        }
+        .fuse();
+        futures::pin_mut!(poll_after);
+
+        futures::select! {
+            () = socket.as_mut().process().fuse() => {},
+            () = wake_up_future.fuse() => {},
+            () = poll_after => {},
+        }
+    }
+}
+
+/// Error potentially returned by [`drive`].
+#[derive(Debug, derive_more::Display)]
+pub enum DriveError<E> {
+    /// Error on the socket.
+    #[display(fmt = "{}", _0)]
+    Socket(io::Error),
+    /// Error returned by the processing closure.
+    #[display(fmt = "{}", _0)]
+    Process(E),
+}
+
 impl<T: fmt::Debug> fmt::Debug for WithBuffers<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_tuple("WithBuffers").field(&self.socket).finish()
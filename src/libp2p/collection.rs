@@ -163,6 +163,16 @@ pub struct Config {
     /// Name of the ping protocol on the network.
     pub ping_protocol: String,
 
+    /// Interval between two consecutive outgoing ping attempts on each connection.
+    pub ping_interval: Duration,
+
+    /// Time after which an outgoing ping is considered failed.
+    pub ping_timeout: Duration,
+
+    /// If `Some`, a connection that doesn't have any substream other than the ping substreams
+    /// for this amount of time generates an [`Event::IdleTimeout`].
+    pub idle_connection_timeout: Option<Duration>,
+
     /// Key used for the encryption layer.
     /// This is a Noise static key, according to the Noise specification.
     /// Signed using the actual libp2p key.
@@ -182,6 +192,25 @@ pub struct Config {
     /// This value is important if [`Network::next_event`] is called at a slower than the calls to
     /// [`Network::read_write`] generate events.
     pub pending_api_events_buffer_size: NonZeroUsize,
+
+    /// Number of bytes the remote is allowed to send at once on a substream used to receive the
+    /// response to an outgoing request.
+    ///
+    /// A large value speeds up the download of large responses, at the cost of more data
+    /// potentially buffered in memory. A good default value is 1MiB.
+    pub request_receive_window: u64,
+
+    /// Number of bytes the remote is allowed to send at once on a notifications substream.
+    ///
+    /// A good default value is 256kiB, which is the minimum allowed by the yamux protocol.
+    pub notifications_receive_window: u64,
+
+    /// Maximum value, per connection, of the sum of the maximum response sizes of all the
+    /// outgoing requests in progress. Starting a request that would exceed this limit fails with
+    /// a `Backpressure` error, and the request should be sent to a different peer or later.
+    ///
+    /// A single request is always allowed, even if its maximum response size exceeds this limit.
+    pub max_pending_response_bytes: usize,
 }
 
 /// Configuration for a specific overlay network.
@@ -239,6 +268,24 @@ pub struct Network<TConn, TNow> {
     /// See [`Config::ping_protocol`].
     ping_protocol: String,
 
+    /// See [`Config::ping_interval`].
+    ping_interval: Duration,
+
+    /// See [`Config::ping_timeout`].
+    ping_timeout: Duration,
+
+    /// See [`Config::idle_connection_timeout`].
+    idle_connection_timeout: Option<Duration>,
+
+    /// See [`Config::request_receive_window`].
+    request_receive_window: u64,
+
+    /// See [`Config::notifications_receive_window`].
+    notifications_receive_window: u64,
+
+    /// See [`Config::max_pending_response_bytes`].
+    max_pending_response_bytes: usize,
+
     /// Receiver connected to [`Guarded::events_tx`].
     events_rx: Mutex<mpsc::Receiver<Event<TConn>>>,
 }
@@ -328,6 +375,12 @@ where
             notification_protocols,
             request_response_protocols: config.request_response_protocols,
             ping_protocol: config.ping_protocol,
+            ping_interval: config.ping_interval,
+            ping_timeout: config.ping_timeout,
+            idle_connection_timeout: config.idle_connection_timeout,
+            request_receive_window: config.request_receive_window,
+            notifications_receive_window: config.notifications_receive_window,
+            max_pending_response_bytes: config.max_pending_response_bytes,
             events_rx: Mutex::new(events_rx),
             guarded: Mutex::new(Guarded {
                 events_tx,
@@ -446,7 +499,9 @@ where
     /// - Negotiating the requested protocol (`protocol_index`) on this substream using the
     ///   *multistream-select* protocol.
     /// - Sending the request (`request_data` parameter), prefixed with its length.
-    /// - Waiting for the response (prefixed with its length), which is then returned.
+    /// - Waiting for the response (prefixed with its length), which is then returned alongside
+    ///   with the time it took to receive it, measured using `now` and the `now` passed to
+    ///   [`ReadWrite::now`] when the response has been received.
     ///
     /// An error happens if the provided [`ConnectionId`] is invalid, if the connection closes
     /// while the request is in progress, if the request or response doesn't respect the protocol
@@ -470,7 +525,7 @@ where
         target: ConnectionId,
         protocol_index: usize,
         request_data: Vec<u8>,
-    ) -> Result<Vec<u8>, RequestError> {
+    ) -> Result<(Vec<u8>, Duration), RequestError> {
         // Obtain the connect to use to send the request.
         let connection_arc: Arc<Mutex<Connection<_, _>>> = {
             let guarded = self.guarded.lock().await;
@@ -493,11 +548,15 @@ where
 
         // Actually start the request by updating the underlying state machine specific to that
         // connection.
+        let started = now.clone();
         connection_lock
             .connection
             .as_established()
             .ok_or(RequestError::ConnectionClosed)?
-            .add_request(now, protocol_index, request_data, send_back);
+            .add_request(now, protocol_index, request_data, send_back)
+            .map_err(|err| match err {
+                established::AddRequestError::Backpressure => RequestError::Backpressure,
+            })?;
 
         // Note that no update of the `Guarded` is necessary. The `Guarded` doesn't track ongoing
         // requests.
@@ -519,7 +578,15 @@ where
         // Wait for the result of the request. Can take a long time (i.e. several seconds).
         // TODO: cancel the request if the future is dropped?
         match receive_result.await {
-            Ok(r) => r,
+            Ok(Ok((response, received))) => {
+                let elapsed = if received > started {
+                    received - started
+                } else {
+                    Duration::new(0, 0)
+                };
+                Ok((response, elapsed))
+            }
+            Ok(Err(err)) => Err(err),
             Err(_) => Err(RequestError::ConnectionClosed),
         }
     }
@@ -1061,9 +1128,13 @@ where
             request_protocols: self.request_response_protocols.clone(),
             randomness_seed,
             ping_protocol: self.ping_protocol.clone(), // TODO: cloning :-/
-            ping_interval: Duration::from_secs(20),    // TODO: hardcoded
-            ping_timeout: Duration::from_secs(10),     // TODO: hardcoded
+            ping_interval: self.ping_interval,
+            ping_timeout: self.ping_timeout,
+            idle_timeout: self.idle_connection_timeout,
             first_out_ping: now.clone() + Duration::from_secs(2), // TODO: hardcoded
+            request_receive_window: self.request_receive_window,
+            notifications_receive_window: self.notifications_receive_window,
+            max_pending_response_bytes: self.max_pending_response_bytes,
         }
     }
 }
@@ -1192,6 +1263,8 @@ pub enum Event<TConn> {
         id: ConnectionId,
         /// Copy of the user data provided when creating the connection.
         user_data: TConn,
+        /// Round-trip time of the ping.
+        ping_time: Duration,
     },
     /// An outgoing ping has failed. This event is generated automatically over time for each
     /// connection in the collection.
@@ -1200,6 +1273,12 @@ pub enum Event<TConn> {
         /// Copy of the user data provided when creating the connection.
         user_data: TConn,
     },
+    /// A connection has been idle for longer than [`Config::idle_connection_timeout`].
+    IdleTimeout {
+        id: ConnectionId,
+        /// Copy of the user data provided when creating the connection.
+        user_data: TConn,
+    },
 }
 
 impl<TConn> Event<TConn> {
@@ -1217,6 +1296,7 @@ impl<TConn> Event<TConn> {
             Event::NotificationsInClose { id, .. } => *id,
             Event::PingOutSuccess { id, .. } => *id,
             Event::PingOutFailed { id, .. } => *id,
+            Event::IdleTimeout { id, .. } => *id,
         }
     }
 
@@ -1234,6 +1314,7 @@ impl<TConn> Event<TConn> {
             Event::NotificationsInClose { user_data, .. } => user_data,
             Event::PingOutSuccess { user_data, .. } => user_data,
             Event::PingOutFailed { user_data, .. } => user_data,
+            Event::IdleTimeout { user_data, .. } => user_data,
         }
     }
 }
@@ -1305,7 +1386,7 @@ struct Connection<TConn, TNow> {
 
     /// Event that has just happened on the connection, but that the [`Guarded`] isn't yet aware
     /// of. See the implementations note at the top of the file for more information.
-    pending_event: Option<PendingEvent>,
+    pending_event: Option<PendingEvent<TNow>>,
 
     /// A sender is stored here when the user calls [`Network::read_write`]. The receiving part
     /// notifies the user that they must call [`Network::read_write`] again.
@@ -1348,9 +1429,26 @@ where
                             }
                         }
 
-                        if let Some(event) = event {
-                            debug_assert!(self.pending_event.is_none());
-                            self.pending_event = Some(PendingEvent::Inner(event));
+                        match event {
+                            // Responses to requests don't need to update the `Guarded` and are
+                            // sent back immediately, alongside with the current time in order
+                            // for `Network::request` to be able to measure the response time.
+                            Some(established::Event::Response {
+                                response,
+                                user_data: send_back,
+                                ..
+                            }) => {
+                                let _ = send_back.send(
+                                    response
+                                        .map(|response| (response, read_write.now.clone()))
+                                        .map_err(RequestError::Connection),
+                                );
+                            }
+                            Some(event) => {
+                                debug_assert!(self.pending_event.is_none());
+                                self.pending_event = Some(PendingEvent::Inner(event));
+                            }
+                            None => {}
                         }
                     }
                     Err(err) => {
@@ -1527,12 +1625,9 @@ where
                     })
                     .unwrap();
             }
-            PendingEvent::Inner(established::Event::Response {
-                response,
-                user_data: send_back,
-                ..
-            }) => {
-                let _ = send_back.send(response.map_err(RequestError::Connection));
+            PendingEvent::Inner(established::Event::Response { .. }) => {
+                // Responses are sent back directly in `Connection::read_write`.
+                unreachable!()
             }
             PendingEvent::Inner(established::Event::NotificationsInOpen {
                 id: substream_id,
@@ -1690,12 +1785,13 @@ where
                     })
                     .unwrap();
             }
-            PendingEvent::Inner(established::Event::PingOutSuccess) => {
+            PendingEvent::Inner(established::Event::PingOutSuccess { ping_time }) => {
                 guarded
                     .events_tx
                     .try_send(Event::PingOutSuccess {
                         id: self.id,
                         user_data: self.user_data.clone(),
+                        ping_time,
                     })
                     .unwrap();
             }
@@ -1708,6 +1804,15 @@ where
                     })
                     .unwrap();
             }
+            PendingEvent::Inner(established::Event::IdleTimeout) => {
+                guarded
+                    .events_tx
+                    .try_send(Event::IdleTimeout {
+                        id: self.id,
+                        user_data: self.user_data.clone(),
+                    })
+                    .unwrap();
+            }
             PendingEvent::Disconnect => {
                 let substreams = guarded
                     .connection_overlays
@@ -1781,9 +1886,7 @@ enum ConnectionInner<TNow> {
         /// When the handshake times out.
         timeout: TNow,
     },
-    Established(
-        established::Established<TNow, oneshot::Sender<Result<Vec<u8>, RequestError>>, usize>,
-    ),
+    Established(established::Established<TNow, ResponseSender<TNow>, usize>),
     Errored(ConnectionError),
     /// [`Network::start_shutdown`] has been called.
     ForcedShutdown,
@@ -1794,9 +1897,7 @@ enum ConnectionInner<TNow> {
 impl<TNow> ConnectionInner<TNow> {
     fn as_established(
         &mut self,
-    ) -> Option<
-        &mut established::Established<TNow, oneshot::Sender<Result<Vec<u8>, RequestError>>, usize>,
-    > {
+    ) -> Option<&mut established::Established<TNow, ResponseSender<TNow>, usize>> {
         if let ConnectionInner::Established(c) = self {
             Some(c)
         } else {
@@ -1805,12 +1906,16 @@ impl<TNow> ConnectionInner<TNow> {
     }
 }
 
-enum PendingEvent {
+enum PendingEvent<TNow> {
     HandshakeFinished(PeerId),
-    Inner(established::Event<oneshot::Sender<Result<Vec<u8>, RequestError>>, usize>),
+    Inner(established::Event<ResponseSender<TNow>, usize>),
     Disconnect,
 }
 
+/// Sending side of the channel on which the response to a request is sent back, alongside with
+/// the moment when it has been received.
+type ResponseSender<TNow> = oneshot::Sender<Result<(Vec<u8>, TNow), RequestError>>;
+
 /// Error potentially returned by [`Network::request`].
 #[derive(Debug, derive_more::Display)]
 pub enum RequestError {
@@ -1824,6 +1929,10 @@ pub enum RequestError {
     /// Connection has been unexpectedly closed by the remote during the request.
     ConnectionClosed,
 
+    /// Too many responses are already being received on this connection. See
+    /// [`Config::max_pending_response_bytes`].
+    Backpressure,
+
     /// Error in the context of the connection.
     Connection(established::RequestError),
 }
@@ -64,6 +64,13 @@ pub use substream::{
     RespondInRequestError,
 };
 
+/// Priority of the ping and notifications substreams. See [`yamux::SubstreamMut::set_priority`].
+///
+/// Notifications, such as block announces and GrandPa messages, are small and time-sensitive,
+/// while requests and responses can be large. The former are sent before the latter when the
+/// bandwidth is constrained.
+const HIGH_SUBSTREAM_PRIORITY: u8 = 1;
+
 /// State machine of a fully-established connection.
 pub struct Established<TNow, TRqUd, TNotifUd> {
     /// Encryption layer applied directly on top of the incoming data and outgoing data.
@@ -112,6 +119,18 @@ struct Inner<TNow, TRqUd, TNotifUd> {
     ping_interval: Duration,
     /// See [`Config::ping_timeout`].
     ping_timeout: Duration,
+    /// See [`Config::idle_timeout`].
+    idle_timeout: Option<Duration>,
+    /// When the connection has started being idle, in other words the moment since when it
+    /// doesn't have any substream apart from the ping substreams. `None` if the connection isn't
+    /// idle.
+    idle_since: Option<TNow>,
+    /// See [`Config::request_receive_window`].
+    request_receive_window: u64,
+    /// See [`Config::notifications_receive_window`].
+    notifications_receive_window: u64,
+    /// See [`Config::max_pending_response_bytes`].
+    max_pending_response_bytes: usize,
 
     /// Buffer used for intermediary data. When it is necessary, data is first copied here before
     /// being turned into a `Vec`.
@@ -166,11 +185,39 @@ where
 
         // Start any outgoing peer if necessary.
         if read_write.now >= self.inner.next_ping {
-            self.queue_ping(read_write.now.clone() + self.inner.ping_timeout);
+            self.queue_ping(
+                read_write.now.clone(),
+                read_write.now.clone() + self.inner.ping_timeout,
+            );
             self.inner.next_ping = read_write.now.clone() + self.inner.ping_interval;
         }
         read_write.wake_up_after(&self.inner.next_ping);
 
+        // Check whether the connection has been idle for too long.
+        if let Some(idle_timeout) = self.inner.idle_timeout {
+            let is_idle = self
+                .inner
+                .yamux
+                .user_datas()
+                .all(|(_, substream)| substream.as_ref().map_or(true, |s| s.is_ping()));
+
+            if !is_idle {
+                self.inner.idle_since = None;
+            } else {
+                let idle_since = self
+                    .inner
+                    .idle_since
+                    .get_or_insert_with(|| read_write.now.clone());
+                let deadline = idle_since.clone() + idle_timeout;
+                if read_write.now >= deadline {
+                    // Reset the timer so that the event isn't generated repeatedly.
+                    self.inner.idle_since = None;
+                    return Ok((self, Some(Event::IdleTimeout)));
+                }
+                read_write.wake_up_after(&deadline);
+            }
+        }
+
         // Decoding the incoming data.
         loop {
             if let Some(event) = self.inner.pending_events.pop_front() {
@@ -346,15 +393,12 @@ where
                 break;
             }
 
-            let (_read, written) = self.encryption.encrypt(
-                buffers,
-                match read_write.outgoing_buffer.as_mut() {
-                    Some((a, b)) => (a, b),
-                    None => (&mut [], &mut []),
-                },
-            );
-            debug_assert!(_read <= bytes_out);
-            read_write.advance_write(written);
+            let encryption = &mut self.encryption;
+            read_write.write_out_scatter(|a, b| {
+                let (_read, written) = encryption.encrypt(buffers, (a, b));
+                debug_assert!(_read <= bytes_out);
+                written
+            });
         }
 
         Ok((self, None))
@@ -467,13 +511,18 @@ where
             let event_to_yield = match event {
                 None => None,
                 Some(substream::Event::InboundNegotiated(protocol)) => {
-                    let substream = inner
-                        .yamux
-                        .substream_by_id(substream_id)
-                        .unwrap()
-                        .into_user_data()
-                        .as_mut()
-                        .unwrap();
+                    let mut yamux_substream = inner.yamux.substream_by_id(substream_id).unwrap();
+
+                    if protocol == inner.ping_protocol
+                        || inner
+                            .notifications_protocols
+                            .iter()
+                            .any(|p| p.name == protocol)
+                    {
+                        yamux_substream.set_priority(HIGH_SUBSTREAM_PRIORITY);
+                    }
+
+                    let substream = yamux_substream.into_user_data().as_mut().unwrap();
 
                     if protocol == inner.ping_protocol {
                         substream.set_inbound_ty(substream::InboundTy::Ping);
@@ -584,7 +633,7 @@ where
                 id: SubstreamId(substream_id),
                 user_data,
             },
-            substream::Event::PingOutSuccess => Event::PingOutSuccess,
+            substream::Event::PingOutSuccess { ping_time } => Event::PingOutSuccess { ping_time },
             substream::Event::PingOutError { .. } => {
                 // Because ping events are automatically generated by the external API without any
                 // guarantee, it is safe to merge multiple failed pings into one.
@@ -605,13 +654,32 @@ where
     ///
     /// After the remote has sent back a response, an [`Event::Response`] event will be generated
     /// locally. The `user_data` parameter will be passed back.
+    ///
+    /// An error is returned if starting this request would exceed
+    /// [`Config::max_pending_response_bytes`]. The request should then be retried later or on a
+    /// different connection.
     pub fn add_request(
         &mut self,
         now: TNow,
         protocol_index: usize,
         request: Vec<u8>,
         user_data: TRqUd,
-    ) -> SubstreamId {
+    ) -> Result<SubstreamId, AddRequestError> {
+        let max_response_size = self.inner.request_protocols[protocol_index].max_response_size;
+
+        let pending_response_bytes = self
+            .inner
+            .yamux
+            .user_datas()
+            .filter_map(|(_, substream)| substream.as_ref()?.pending_response_max_size())
+            .fold(0usize, |sum, size| sum.saturating_add(size));
+        if pending_response_bytes != 0
+            && pending_response_bytes.saturating_add(max_response_size)
+                > self.inner.max_pending_response_bytes
+        {
+            return Err(AddRequestError::Backpressure);
+        }
+
         let has_length_prefix = match self.inner.request_protocols[protocol_index].inbound_config {
             ConfigRequestResponseIn::Payload { max_size } => {
                 // TODO: turn this assert into something that can't panic?
@@ -627,24 +695,24 @@ where
 
         let timeout = now + self.inner.request_protocols[protocol_index].timeout;
 
-        let substream = self
-            .inner
-            .yamux
-            .open_substream(Some(substream::Substream::request_out(
-                self.inner.request_protocols[protocol_index].name.clone(), // TODO: clone :-/
-                timeout,
-                if has_length_prefix {
-                    Some(request)
-                } else {
-                    None
-                },
-                self.inner.request_protocols[protocol_index].max_response_size,
-                user_data,
-            )));
+        let mut substream =
+            self.inner
+                .yamux
+                .open_substream(Some(substream::Substream::request_out(
+                    self.inner.request_protocols[protocol_index].name.clone(), // TODO: clone :-/
+                    timeout,
+                    if has_length_prefix {
+                        Some(request)
+                    } else {
+                        None
+                    },
+                    max_response_size,
+                    user_data,
+                )));
 
-        // TODO: ? do this? substream.reserve_window(128 * 1024 * 1024 + 128); // TODO: proper max size
+        substream.set_receive_window(self.inner.request_receive_window);
 
-        SubstreamId(substream.id())
+        Ok(SubstreamId(substream.id()))
     }
 
     /// Returns the user dat associated to a notifications substream.
@@ -692,7 +760,7 @@ where
 
         let timeout = now + Duration::from_secs(20); // TODO:
 
-        let substream =
+        let mut substream =
             self.inner
                 .yamux
                 .open_substream(Some(substream::Substream::notifications_out(
@@ -705,6 +773,9 @@ where
                     user_data,
                 )));
 
+        substream.set_receive_window(self.inner.notifications_receive_window);
+        substream.set_priority(HIGH_SUBSTREAM_PRIORITY);
+
         SubstreamId(substream.id())
     }
 
@@ -723,10 +794,9 @@ where
     ) {
         let max_notification_size = 16 * 1024 * 1024; // TODO: hack
                                                       // TODO: self.inner.notifications_protocols[protocol_index].max_notification_size;
-        self.inner
-            .yamux
-            .substream_by_id(substream_id.0)
-            .unwrap()
+        let mut substream = self.inner.yamux.substream_by_id(substream_id.0).unwrap();
+        substream.set_receive_window(self.inner.notifications_receive_window);
+        substream
             .into_user_data()
             .as_mut()
             .unwrap()
@@ -847,9 +917,9 @@ where
             .respond_in_request(response)
     }
 
-    /// Queues an outgoing ping. Must be passed the moment when this ping will be considered as
-    /// failed.
-    fn queue_ping(&mut self, timeout: TNow) {
+    /// Queues an outgoing ping. Must be passed the current time and the moment when this ping
+    /// will be considered as failed.
+    fn queue_ping(&mut self, now: TNow, timeout: TNow) {
         // It might be that the remote has reset the ping substream, in which case the out ping
         // substream no longer exists and we immediately consider the ping as failed.
         if let Some(substream) = self.inner.yamux.substream_by_id(self.inner.outgoing_pings) {
@@ -857,7 +927,7 @@ where
                 .into_user_data()
                 .as_mut()
                 .unwrap()
-                .queue_ping(&[0xff; 32], timeout); // TODO: proper random payload
+                .queue_ping(&[0xff; 32], now, timeout); // TODO: proper random payload
         } else {
             self.inner.pending_events.push_back(Event::PingOutFailed);
         }
@@ -1006,9 +1076,16 @@ pub enum Event<TRqUd, TNotifUd> {
     },
 
     /// An outgoing ping has succeeded. This event is generated automatically over time.
-    PingOutSuccess,
+    PingOutSuccess {
+        /// Round-trip time of the ping.
+        ping_time: Duration,
+    },
     /// An outgoing ping has failed. This event is generated automatically over time.
     PingOutFailed,
+
+    /// The connection hasn't had any substream other than the ping substreams for longer than
+    /// [`Config::idle_timeout`]. The connection should most likely be shut down.
+    IdleTimeout,
 }
 
 /// Error during a connection. The connection should be shut down.
@@ -1020,6 +1097,13 @@ pub enum Error {
     Yamux(yamux::Error),
 }
 
+/// Error potentially returned by [`Established::add_request`].
+#[derive(Debug, derive_more::Display)]
+pub enum AddRequestError {
+    /// Starting this request would exceed [`Config::max_pending_response_bytes`].
+    Backpressure,
+}
+
 /// Successfully negotiated connection. Ready to be turned into a [`Established`].
 pub struct ConnectionPrototype {
     encryption: noise::Noise,
@@ -1037,7 +1121,7 @@ impl ConnectionPrototype {
         config: Config<TNow>,
     ) -> Established<TNow, TRqUd, TNotifUd>
     where
-        TNow: Clone + Ord,
+        TNow: Clone + Sub<TNow, Output = Duration> + Ord,
     {
         // TODO: check conflicts between protocol names?
 
@@ -1047,11 +1131,13 @@ impl ConnectionPrototype {
             randomness_seed: config.randomness_seed,
         });
 
-        let outgoing_pings = yamux
-            .open_substream(Some(substream::Substream::ping_out(
+        let outgoing_pings = {
+            let mut substream = yamux.open_substream(Some(substream::Substream::ping_out(
                 config.ping_protocol.clone(),
-            )))
-            .id();
+            )));
+            substream.set_priority(HIGH_SUBSTREAM_PRIORITY);
+            substream.id()
+        };
 
         Established {
             encryption: self.encryption,
@@ -1065,6 +1151,11 @@ impl ConnectionPrototype {
                 ping_protocol: config.ping_protocol,
                 ping_interval: config.ping_interval,
                 ping_timeout: config.ping_timeout,
+                idle_timeout: config.idle_timeout,
+                idle_since: None,
+                request_receive_window: config.request_receive_window,
+                notifications_receive_window: config.notifications_receive_window,
+                max_pending_response_bytes: config.max_pending_response_bytes,
                 intermediary_buffer: vec![0u8; 2048].into_boxed_slice(),
             },
         }
@@ -1093,6 +1184,22 @@ pub struct Config<TNow> {
     pub ping_interval: Duration,
     /// Time after which an outgoing ping is considered failed.
     pub ping_timeout: Duration,
+    /// If `Some`, an [`Event::IdleTimeout`] is generated when the connection hasn't had any
+    /// substream other than the ping substreams for this amount of time.
+    pub idle_timeout: Option<Duration>,
+    /// Number of bytes the remote is allowed to send at once on a substream used to receive the
+    /// response to an outgoing request. See [`yamux::SubstreamMut::set_receive_window`].
+    pub request_receive_window: u64,
+    /// Number of bytes the remote is allowed to send at once on a notifications substream. See
+    /// [`yamux::SubstreamMut::set_receive_window`].
+    pub notifications_receive_window: u64,
+    /// Maximum value of the sum of the maximum response sizes of all the outgoing requests in
+    /// progress on this connection. This bounds the amount of memory that the responses can
+    /// occupy. Starting a request that would exceed this limit returns
+    /// [`AddRequestError::Backpressure`].
+    ///
+    /// A single request is always allowed, even if its maximum response size exceeds this limit.
+    pub max_pending_response_bytes: usize,
     /// Entropy used for the randomness specific to this connection.
     pub randomness_seed: [u8; 32],
 }
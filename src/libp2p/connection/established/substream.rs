@@ -31,7 +31,7 @@ use alloc::{
     string::String,
     vec::{self, Vec},
 };
-use core::{fmt, num::NonZeroUsize};
+use core::{fmt, num::NonZeroUsize, ops::Sub, time::Duration};
 
 /// State machine containing the state of a single substream of an established connection.
 pub struct Substream<TNow, TRqUd, TNotifUd> {
@@ -194,14 +194,14 @@ enum SubstreamInner<TNow, TRqUd, TNotifUd> {
         /// negotiating, no ping has been sent out, and this is thus always equal to 32 times the
         /// number of queued pings.
         outgoing_payload: VecDeque<u8>,
-        /// FIFO queue of pings waiting to be answered. For each ping, when the ping will time
-        /// out, or `None` if the timeout has already occured.
-        queued_pings: smallvec::SmallVec<[Option<TNow>; 1]>,
+        /// FIFO queue of pings waiting to be answered. For each ping, when the ping has been
+        /// queued and when it will time out, or `None` if the timeout has already occured.
+        queued_pings: smallvec::SmallVec<[Option<(TNow, TNow)>; 1]>,
     },
     /// Failed to negotiate a protocol for an outgoing ping substream.
     PingOutFailed {
         /// FIFO queue of pings that will immediately fail.
-        queued_pings: smallvec::SmallVec<[Option<TNow>; 1]>,
+        queued_pings: smallvec::SmallVec<[Option<(TNow, TNow)>; 1]>,
     },
     /// Outbound ping substream.
     PingOut {
@@ -210,15 +210,15 @@ enum SubstreamInner<TNow, TRqUd, TNotifUd> {
         /// Data waiting to be received from the remote. Any mismatch will cause an error.
         /// Contains even the data that is still queued in `outgoing_payload`.
         expected_payload: VecDeque<u8>,
-        /// FIFO queue of pings waiting to be answered. For each ping, when the ping will time
-        /// out, or `None` if the timeout has already occured.
-        queued_pings: smallvec::SmallVec<[Option<TNow>; 1]>,
+        /// FIFO queue of pings waiting to be answered. For each ping, when the ping has been
+        /// queued and when it will time out, or `None` if the timeout has already occured.
+        queued_pings: smallvec::SmallVec<[Option<(TNow, TNow)>; 1]>,
     },
 }
 
 impl<TNow, TRqUd, TNotifUd> Substream<TNow, TRqUd, TNotifUd>
 where
-    TNow: Clone + Ord,
+    TNow: Clone + Sub<TNow, Output = Duration> + Ord,
 {
     /// Initializes an new ingoing substream.
     ///
@@ -355,6 +355,20 @@ where
         }
     }
 
+    /// Returns the maximum size of the response expected on this substream.
+    ///
+    /// Returns `None` if the substream isn't an outgoing request substream, or if the response
+    /// has already been received.
+    pub fn pending_response_max_size(&self) -> Option<usize> {
+        match &self.inner {
+            SubstreamInner::RequestOutNegotiating {
+                max_response_size, ..
+            } => Some(*max_response_size),
+            SubstreamInner::RequestOut { response, .. } => Some(response.max_len()),
+            _ => None,
+        }
+    }
+
     /// Returns the user data associated to a notifications substream.
     ///
     /// Returns `None` if the substream isn't a notifications substream.
@@ -368,6 +382,17 @@ where
         }
     }
 
+    /// Returns `true` if this substream is used to send or answer pings.
+    pub fn is_ping(&self) -> bool {
+        matches!(
+            self.inner,
+            SubstreamInner::PingIn { .. }
+                | SubstreamInner::PingOutNegotiating { .. }
+                | SubstreamInner::PingOutFailed { .. }
+                | SubstreamInner::PingOut { .. }
+        )
+    }
+
     /// Reads data coming from the socket, updates the internal state machine, and writes data
     /// destined to the socket through the [`read_write::ReadWrite`].
     ///
@@ -983,9 +1008,12 @@ where
                 mut queued_pings,
                 mut outgoing_payload,
             } => {
-                for timeout in queued_pings.iter_mut() {
-                    if timeout.as_ref().map_or(false, |t| *t < read_write.now) {
-                        *timeout = None;
+                for ping in queued_pings.iter_mut() {
+                    if ping
+                        .as_ref()
+                        .map_or(false, |(_, timeout)| *timeout < read_write.now)
+                    {
+                        *ping = None;
                         return (
                             Some(SubstreamInner::PingOutNegotiating {
                                 negotiation,
@@ -998,7 +1026,7 @@ where
                         );
                     }
 
-                    if let Some(timeout) = timeout {
+                    if let Some((_, timeout)) = ping {
                         read_write.wake_up_after(timeout);
                     }
                 }
@@ -1056,9 +1084,12 @@ where
 
                 // We check the timeouts before checking the incoming data, as otherwise pings
                 // might succeed after their timeout.
-                for timeout in queued_pings.iter_mut() {
-                    if timeout.as_ref().map_or(false, |t| *t < read_write.now) {
-                        *timeout = None;
+                for ping in queued_pings.iter_mut() {
+                    if ping
+                        .as_ref()
+                        .map_or(false, |(_, timeout)| *timeout < read_write.now)
+                    {
+                        *ping = None;
                         return (
                             Some(SubstreamInner::PingOut {
                                 expected_payload,
@@ -1071,7 +1102,7 @@ where
                         );
                     }
 
-                    if let Some(timeout) = timeout {
+                    if let Some((_, timeout)) = ping {
                         read_write.wake_up_after(timeout);
                     }
                 }
@@ -1085,14 +1116,15 @@ where
                     // bytes in `expected_payload`.
                     if expected_payload.len() % 32 == 0 {
                         debug_assert!(!queued_pings.is_empty()); // `expected_payload.pop_front()` should have returned `None` above otherwise
-                        if queued_pings.remove(0).is_some() {
+                        if let Some((queued_at, _)) = queued_pings.remove(0) {
+                            let ping_time = read_write.now.clone() - queued_at;
                             return (
                                 Some(SubstreamInner::PingOut {
                                     expected_payload,
                                     outgoing_payload,
                                     queued_pings,
                                 }),
-                                Some(Event::PingOutSuccess),
+                                Some(Event::PingOutSuccess { ping_time }),
                             );
                         }
                     }
@@ -1276,18 +1308,21 @@ where
     }
 
     /// Queues a ping on the given substream. Must be passed a randomly-generated payload of 32
-    /// bytes, the time after which this ping is considered as failed.
+    /// bytes, the current time, and the time after which this ping is considered as failed.
+    ///
+    /// The current time is used in order to later report the round-trip time of the ping in
+    /// [`Event::PingOutSuccess`].
     ///
     /// # Panic
     ///
     /// Panics if the substream isn't an outgoing ping substream.
     ///
-    pub fn queue_ping(&mut self, payload: &[u8; 32], timeout: TNow) {
+    pub fn queue_ping(&mut self, payload: &[u8; 32], now: TNow, timeout: TNow) {
         match &mut self.inner {
             SubstreamInner::PingOut { queued_pings, .. }
             | SubstreamInner::PingOutNegotiating { queued_pings, .. }
             | SubstreamInner::PingOutFailed { queued_pings, .. } => {
-                queued_pings.push(Some(timeout));
+                queued_pings.push(Some((now, timeout)));
             }
             _ => panic!(),
         }
@@ -1527,7 +1562,11 @@ pub enum Event<TRqUd, TNotifUd> {
     },
 
     /// A ping has been successfully answered by the remote.
-    PingOutSuccess,
+    PingOutSuccess {
+        /// Time between the moment the ping has been queued and the moment the answer has been
+        /// received.
+        ping_time: Duration,
+    },
     /// Remote has failed to answer one or more pings.
     PingOutError {
         /// Number of pings that the remote has failed to answer.
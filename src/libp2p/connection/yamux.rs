@@ -93,6 +93,10 @@ struct Substream<T> {
     /// If non-zero, a window update frame must be sent to the remote to grant this number of
     /// bytes.
     remote_window_pending_increase: u64,
+    /// Number of bytes the remote should be allowed to transmit at any given time. Every time
+    /// data is received, [`Substream::remote_allowed_window`] is refilled up to this value.
+    /// See [`SubstreamMut::set_receive_window`].
+    receive_window: u64,
     /// Amount of data the local node is allowed to transmit to the remote.
     allowed_window: u64,
     /// True if the writing side of the local node is closed for this substream.
@@ -108,6 +112,8 @@ struct Substream<T> {
     /// Number of bytes in `self.write_buffers[0]` has have already been written out to the
     /// socket.
     first_write_buffer_offset: usize,
+    /// Priority of the data of this substream. See [`SubstreamMut::set_priority`].
+    priority: u8,
     /// Data chosen by the user.
     user_data: T,
 }
@@ -228,11 +234,13 @@ impl<T> Yamux<T> {
             first_message_queued: false,
             remote_allowed_window: DEFAULT_FRAME_SIZE,
             remote_window_pending_increase: 0,
+            receive_window: DEFAULT_FRAME_SIZE,
             allowed_window: DEFAULT_FRAME_SIZE,
             local_write_closed: false,
             remote_write_closed: false,
             write_buffers: Vec::with_capacity(16),
             first_write_buffer_offset: 0,
+            priority: 0,
             user_data,
         });
 
@@ -534,7 +542,16 @@ impl<T> Yamux<T> {
                                 .ok_or(Error::CreditsExceeded)?;
 
                             substream.first_message_queued = true;
-                            substream.remote_window_pending_increase += 256 * 1024;
+
+                            // Refill the window of the remote up to the configured receive
+                            // window. The data of the frame is immediately processed, and there
+                            // is thus no need to wait before granting more credits.
+                            substream.remote_window_pending_increase = cmp::max(
+                                substream.remote_window_pending_increase,
+                                substream
+                                    .receive_window
+                                    .saturating_sub(substream.remote_allowed_window),
+                            );
                         }
 
                         self.incoming = Incoming::DataFrame {
@@ -682,18 +699,27 @@ impl<T> Yamux<T> {
                 continue;
             }
 
-            // Start writing more data from another substream.
-            // TODO: choose substreams in some sort of round-robin way
+            // Start writing more data from another substream. The substream with the highest
+            // priority is chosen. Substreams that aren't allowed to send any data are ignored, as
+            // they would otherwise prevent substreams with a lower priority from sending data.
+            // TODO: choose substreams of equal priority in some sort of round-robin way
             if let Some((id, sub)) = self
                 .substreams
                 .iter_mut()
-                .find(|(_, s)| !s.write_buffers.is_empty())
+                .filter(|(_, s)| !s.write_buffers.is_empty() && s.allowed_window != 0)
+                .max_by_key(|(_, s)| s.priority)
                 .map(|(id, sub)| (*id, sub))
             {
+                // The size of data frames is capped, so that a substream with a higher priority
+                // that has data to send later doesn't need to wait for a large frame to be
+                // entirely written out.
                 let pending_len = sub.write_buffers.iter().fold(0, |l, b| l + b.len());
                 let len_out = cmp::min(
-                    u32::try_from(pending_len).unwrap_or(u32::max_value()),
-                    u32::try_from(sub.allowed_window).unwrap_or(u32::max_value()),
+                    cmp::min(
+                        u32::try_from(pending_len).unwrap_or(u32::max_value()),
+                        u32::try_from(sub.allowed_window).unwrap_or(u32::max_value()),
+                    ),
+                    MAX_OUTGOING_DATA_FRAME_LEN,
                 );
                 let len_out_usize = usize::try_from(len_out).unwrap();
                 sub.allowed_window -= u64::from(len_out);
@@ -734,11 +760,13 @@ impl<T> Yamux<T> {
                         first_message_queued: false,
                         remote_allowed_window: DEFAULT_FRAME_SIZE,
                         remote_window_pending_increase: 0,
+                        receive_window: DEFAULT_FRAME_SIZE,
                         allowed_window: DEFAULT_FRAME_SIZE + u64::from(extra_window),
                         local_write_closed: false,
                         remote_write_closed: data_frame_size == 0 && fin,
                         write_buffers: Vec::new(),
                         first_write_buffer_offset: 0,
+                        priority: 0,
                         user_data,
                     },
                 );
@@ -968,6 +996,36 @@ impl<'a, T> SubstreamMut<'a, T> {
             cmp::max(substream.remote_window_pending_increase, bytes);
     }
 
+    /// Sets the number of bytes the remote is allowed to send on this substream without waiting
+    /// for the local node to grant more. Every time data is received on this substream, the
+    /// window of the remote is refilled up to this value.
+    ///
+    /// The default value is 256kiB, as defined by the yamux protocol. Because credits that have
+    /// already been granted can't be taken back, a value lower than this default only takes
+    /// effect once the remote has used its current credits.
+    ///
+    /// A large value improves the throughput of substreams transferring a lot of data, at the
+    /// cost of a larger amount of data potentially buffered in memory.
+    pub fn set_receive_window(&mut self, bytes: u64) {
+        let substream = self.substream.get_mut();
+        substream.receive_window = bytes;
+        substream.remote_window_pending_increase = cmp::max(
+            substream.remote_window_pending_increase,
+            bytes.saturating_sub(substream.remote_allowed_window),
+        );
+    }
+
+    /// Sets the priority of the data queued on this substream. When multiple substreams have
+    /// data to send out, the data of the substream with the highest priority is sent first.
+    ///
+    /// The default priority is 0.
+    ///
+    /// This is useful in situations where the bandwidth is constrained, in order for small
+    /// time-sensitive messages to not be delayed by large transfers.
+    pub fn set_priority(&mut self, priority: u8) {
+        self.substream.get_mut().priority = priority;
+    }
+
     /// Returns the number of bytes queued for writing on this substream.
     pub fn queued_bytes(&self) -> usize {
         let substream = self.substream.get();
@@ -1143,3 +1201,7 @@ pub enum Error {
 
 /// By default, all new substreams have this implicit window size.
 const DEFAULT_FRAME_SIZE: u64 = 256 * 1024;
+
+/// Maximum number of bytes of data in an outgoing data frame. Once a data frame has started being
+/// written out, no other substream can send data until the frame is finished.
+const MAX_OUTGOING_DATA_FRAME_LEN: u32 = 16 * 1024;
@@ -71,6 +71,10 @@ pub use collection::{
     ReadWrite,
 };
 
+/// Weight of a new ping measurement when updating the average ping time of a peer. A new
+/// measurement accounts for `1 / PING_TIME_SMOOTHING` of the new average.
+const PING_TIME_SMOOTHING: u32 = 8;
+
 /// Configuration for a [`Peers`].
 pub struct Config {
     /// Seed for the randomness within the networking state machine.
@@ -89,6 +93,17 @@ pub struct Config {
     /// Name of the ping protocol on the network.
     pub ping_protocol: String,
 
+    /// Interval between two consecutive outgoing ping attempts on each connection.
+    pub ping_interval: Duration,
+
+    /// Time after which an outgoing ping is considered failed. Connections whose ping fails are
+    /// shut down.
+    pub ping_timeout: Duration,
+
+    /// If `Some`, connections that don't have any substream other than the ping substreams for
+    /// this amount of time are shut down.
+    pub idle_connection_timeout: Option<Duration>,
+
     /// Amount of time after which a connection handshake is considered to have taken too long
     /// and must be aborted.
     pub handshake_timeout: Duration,
@@ -113,6 +128,25 @@ pub struct Config {
     /// [`Peers::read_write`] generate events.
     pub pending_api_events_buffer_size: NonZeroUsize,
 
+    /// Number of bytes the remote is allowed to send at once on a substream used to receive the
+    /// response to an outgoing request.
+    ///
+    /// A large value speeds up the download of large responses, at the cost of more data
+    /// potentially buffered in memory. A good default value is 1MiB.
+    pub request_receive_window: u64,
+
+    /// Number of bytes the remote is allowed to send at once on a notifications substream.
+    ///
+    /// A good default value is 256kiB, which is the minimum allowed by the yamux protocol.
+    pub notifications_receive_window: u64,
+
+    /// Maximum value, per connection, of the sum of the maximum response sizes of all the
+    /// outgoing requests in progress. Starting a request that would exceed this limit fails with
+    /// a `Backpressure` error, and the request should be sent to a different peer or later.
+    ///
+    /// A single request is always allowed, even if its maximum response size exceeds this limit.
+    pub max_pending_response_bytes: usize,
+
     // TODO: don't use BTreeSet
     pub initial_desired_peers: BTreeSet<PeerId>,
 
@@ -158,6 +192,7 @@ where
                 let peer_index = peers.insert(Peer {
                     desired: true,
                     peer_id: entry.key().clone(),
+                    average_ping_time: None,
                 });
 
                 entry.insert(peer_index);
@@ -171,6 +206,7 @@ where
                     let peer_index = peers.insert(Peer {
                         desired: true,
                         peer_id: entry.key().clone(),
+                        average_ping_time: None,
                     });
 
                     *entry.insert(peer_index)
@@ -194,9 +230,15 @@ where
                 notification_protocols: config.notification_protocols,
                 request_response_protocols: config.request_response_protocols,
                 ping_protocol: config.ping_protocol,
+                ping_interval: config.ping_interval,
+                ping_timeout: config.ping_timeout,
+                idle_connection_timeout: config.idle_connection_timeout,
                 handshake_timeout: config.handshake_timeout,
                 randomness_seed: randomness.sample(rand::distributions::Standard),
                 pending_api_events_buffer_size: config.pending_api_events_buffer_size,
+                request_receive_window: config.request_receive_window,
+                notifications_receive_window: config.notifications_receive_window,
+                max_pending_response_bytes: config.max_pending_response_bytes,
             }),
             guarded: Mutex::new(Guarded {
                 pending_desired_out_notifs: VecDeque::with_capacity(0), // TODO: capacity?
@@ -724,8 +766,26 @@ where
                 }
 
                 collection::Event::PingOutSuccess { .. } => {
-                    // We don't care about or report successful pings at the moment.
-                    guarded.pending_inner_event = None;
+                    // Successful pings aren't reported as events, but are used in order to
+                    // update the average ping time of the peer.
+                    if let Some(collection::Event::PingOutSuccess {
+                        user_data: local_connection_index,
+                        ping_time,
+                        ..
+                    }) = guarded.pending_inner_event.take()
+                    {
+                        let peer_index = guarded.connections[local_connection_index].0.unwrap();
+                        let average = &mut guarded.peers[peer_index].average_ping_time;
+                        *average = Some(match *average {
+                            Some(average) => {
+                                (average * (PING_TIME_SMOOTHING - 1) + ping_time)
+                                    / PING_TIME_SMOOTHING
+                            }
+                            None => ping_time,
+                        });
+                    } else {
+                        unreachable!()
+                    }
                 }
 
                 collection::Event::PingOutFailed { id, .. } => {
@@ -733,6 +793,12 @@ where
                     self.inner.start_shutdown(*id).await;
                     guarded.pending_inner_event = None;
                 }
+
+                collection::Event::IdleTimeout { id, .. } => {
+                    // Idle connections are gracefully shut down.
+                    self.inner.start_shutdown(*id).await;
+                    guarded.pending_inner_event = None;
+                }
             }
         }
     }
@@ -795,6 +861,7 @@ where
             let idx = guarded.peers.insert(Peer {
                 desired: false,
                 peer_id: expected_peer_id.clone(),
+                average_ping_time: None,
             });
             guarded.peer_indices.insert(expected_peer_id.clone(), idx);
             idx
@@ -811,6 +878,17 @@ where
         connection_id
     }
 
+    /// Returns the number of connections, both handshaking and established, whose user data
+    /// matches the given predicate.
+    pub async fn num_connections_matching(&self, mut filter: impl FnMut(&TConn) -> bool) -> usize {
+        let guarded = self.guarded.lock().await;
+        guarded
+            .connections
+            .iter()
+            .filter(|(_, (_, user_data))| filter(user_data))
+            .count()
+    }
+
     /// Returns the list of [`PeerId`]s that have been marked as desired, but that don't have any
     /// associated connection. An associated connection is either a fully established connection
     /// with that peer, or an outgoing connection that is still handshaking but expects to reach
@@ -1185,7 +1263,8 @@ where
     /// - Negotiating the requested protocol (`protocol_index`) on this substream using the
     ///   *multistream-select* protocol.
     /// - Sending the request (`request_data` parameter), prefixed with its length.
-    /// - Waiting for the response (prefixed with its length), which is then returned.
+    /// - Waiting for the response (prefixed with its length), which is then returned alongside
+    ///   with the time it took to receive it.
     ///
     /// An error happens if there is no suitable connection for that request, if the connection
     /// closes while the request is in progress, if the request or response doesn't respect
@@ -1210,7 +1289,7 @@ where
         target: &PeerId,
         protocol_index: usize,
         request_data: Vec<u8>,
-    ) -> Result<Vec<u8>, RequestError> {
+    ) -> Result<(Vec<u8>, Duration), RequestError> {
         let target = {
             let mut guarded = self.guarded.lock().await;
             match self.connection_id_for_peer(&mut *guarded, target) {
@@ -1228,6 +1307,7 @@ where
             Ok(r) => Ok(r),
             Err(collection::RequestError::InvalidConnection) => Err(RequestError::ConnectionClosed),
             Err(collection::RequestError::ConnectionClosed) => Err(RequestError::ConnectionClosed),
+            Err(collection::RequestError::Backpressure) => Err(RequestError::Backpressure),
             Err(collection::RequestError::Connection(err)) => Err(RequestError::Connection(err)),
         }
     }
@@ -1310,6 +1390,44 @@ where
             .count()
     }
 
+    /// Starts shutting down all the connections with the given peer, including the ones that are
+    /// still handshaking.
+    ///
+    /// Has no effect if there isn't any connection with this peer. This doesn't modify whether the
+    /// peer is marked as desired.
+    pub async fn disconnect(&self, peer_id: &PeerId) {
+        let connections = {
+            let guarded = self.guarded.lock().await;
+            let peer_index = match guarded.peer_indices.get(peer_id) {
+                Some(idx) => *idx,
+                None => return,
+            };
+
+            guarded
+                .connections_by_peer
+                .range(
+                    (peer_index, ConnectionId::min_value())
+                        ..=(peer_index, ConnectionId::max_value()),
+                )
+                .map(|((_, connection_id), _)| *connection_id)
+                .collect::<Vec<_>>()
+        };
+
+        for connection_id in connections {
+            self.inner.start_shutdown(connection_id).await;
+        }
+    }
+
+    /// Returns the average round-trip time of the pings sent to the given peer, or `None` if the
+    /// peer is unknown or if no ping to this peer has succeeded yet.
+    ///
+    /// The average is an exponential moving average, and thus gives more weight to recent pings.
+    pub async fn peer_average_ping_time(&self, peer_id: &PeerId) -> Option<Duration> {
+        let guarded = self.guarded.lock().await;
+        let peer_index = *guarded.peer_indices.get(peer_id)?;
+        guarded.peers[peer_index].average_ping_time
+    }
+
     /// Picks the connection to use to send requests or notifications to the given peer.
     fn connection_id_for_peer(
         &self,
@@ -1618,6 +1736,9 @@ pub enum RequestError {
     NotConnected,
     /// Connection has been unexpectedly closed by the remote during the request.
     ConnectionClosed,
+    /// Too many responses are already being received from this peer. The request should be sent
+    /// to a different peer or later. See [`Config::max_pending_response_bytes`].
+    Backpressure,
     /// Error in the context of the connection.
     Connection(libp2p::connection::established::RequestError),
 }
@@ -1712,6 +1833,7 @@ impl<TConn> Guarded<TConn> {
         let index = self.peers.insert(Peer {
             desired: false,
             peer_id: peer_id.clone(),
+            average_ping_time: None,
         });
 
         self.peer_indices.insert(peer_id.clone(), index);
@@ -1786,4 +1908,7 @@ enum NotificationsOutOpenState {
 struct Peer {
     peer_id: PeerId,
     desired: bool,
+    /// Exponential moving average of the round-trip time of the pings sent to this peer, across
+    /// all connections. `None` if no ping has succeeded yet.
+    average_ping_time: Option<Duration>,
 }
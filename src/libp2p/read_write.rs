@@ -22,6 +22,14 @@ use alloc::collections::VecDeque;
 use core::{cmp, mem};
 use futures::future::{self, BoxFuture, Future, FutureExt as _};
 
+pub mod incoming_buffers;
+pub mod instrumented;
+pub mod rate_limited;
+
+pub use incoming_buffers::IncomingBuffers;
+pub use instrumented::InstrumentedReadWrite;
+pub use rate_limited::RateLimitedReadWrite;
+
 // TODO: documentation
 
 #[must_use]
@@ -185,6 +193,61 @@ impl<'a, TNow> ReadWrite<'a, TNow> {
         self.advance_write(data.len());
     }
 
+    /// Copies the content of all the buffers of `data`, one after the other, to
+    /// [`ReadWrite::outgoing_buffer`] and increases [`ReadWrite::written_bytes`].
+    ///
+    /// This is equivalent to calling [`ReadWrite::write_out`] with the concatenation of all the
+    /// buffers, but without having to allocate this concatenation.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the total length of `data` is superior to
+    /// [`ReadWrite::outgoing_buffer_available`].
+    ///
+    pub fn write_out_vectored(&mut self, data: &[&[u8]]) {
+        let total_len = data.iter().fold(0, |a, b| a + b.len());
+        assert!(total_len <= self.outgoing_buffer_available());
+
+        for buf in data {
+            self.write_out(buf);
+        }
+    }
+
+    /// Passes the two buffers of [`ReadWrite::outgoing_buffer`] to `write`, which must write
+    /// data to them, starting with the first buffer, and return the number of bytes that have
+    /// been written. [`ReadWrite::outgoing_buffer`] is then advanced by this number of bytes,
+    /// and [`ReadWrite::written_bytes`] increased.
+    ///
+    /// If the writing side is closed, `write` is passed two empty buffers.
+    ///
+    /// Returns the value returned by `write`.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `write` returns a value superior to the total length of the two buffers.
+    ///
+    pub fn write_out_scatter(
+        &mut self,
+        write: impl FnOnce(&mut [u8], &mut [u8]) -> usize,
+    ) -> usize {
+        let written = match self.outgoing_buffer.as_mut() {
+            Some((a, b)) => {
+                let available = a.len() + b.len();
+                let written = write(a, b);
+                assert!(written <= available);
+                written
+            }
+            None => {
+                let written = write(&mut [], &mut []);
+                assert_eq!(written, 0);
+                written
+            }
+        };
+
+        self.advance_write(written);
+        written
+    }
+
     /// Copies as much as possible from the content of `data` to [`ReadWrite::outgoing_buffer`]
     /// and increases [`ReadWrite::written_bytes`]. The bytes that have been written are removed
     /// from `data`.
@@ -402,6 +465,59 @@ mod tests {
         assert_eq!(rw.written_bytes, 9);
     }
 
+    #[test]
+    fn write_out_vectored() {
+        let mut buf1 = [0, 0, 0];
+        let mut buf2 = [0, 0, 0];
+
+        let mut rw = ReadWrite {
+            now: 0,
+            incoming_buffer: None,
+            outgoing_buffer: Some((&mut buf1, &mut buf2)),
+            read_bytes: 0,
+            written_bytes: 5,
+            wake_up_after: None,
+            wake_up_future: None,
+        };
+
+        rw.write_out_vectored(&[&[1, 2], &[], &[3, 4, 5]]);
+        assert_eq!(rw.outgoing_buffer.as_ref().unwrap().0, &[0]);
+        assert!(rw.outgoing_buffer.as_ref().unwrap().1.is_empty());
+        assert_eq!(rw.written_bytes, 10);
+        assert_eq!(&buf1, &[1, 2, 3]);
+        assert_eq!(&buf2, &[4, 5, 0]);
+    }
+
+    #[test]
+    fn write_out_scatter() {
+        let mut buf1 = [0, 0];
+        let mut buf2 = [0, 0, 0];
+
+        let mut rw = ReadWrite {
+            now: 0,
+            incoming_buffer: None,
+            outgoing_buffer: Some((&mut buf1, &mut buf2)),
+            read_bytes: 0,
+            written_bytes: 5,
+            wake_up_after: None,
+            wake_up_future: None,
+        };
+
+        let written = rw.write_out_scatter(|a, b| {
+            assert_eq!(a.len(), 2);
+            assert_eq!(b.len(), 3);
+            a.copy_from_slice(&[1, 2]);
+            b[0] = 3;
+            3
+        });
+        assert_eq!(written, 3);
+        assert_eq!(rw.outgoing_buffer.as_ref().unwrap().0, &[0, 0]);
+        assert!(rw.outgoing_buffer.as_ref().unwrap().1.is_empty());
+        assert_eq!(rw.written_bytes, 8);
+        assert_eq!(&buf1, &[1, 2]);
+        assert_eq!(&buf2, &[3, 0, 0]);
+    }
+
     #[test]
     fn write_from_vec_deque_smaller() {
         let mut buf1 = [0, 0, 0];
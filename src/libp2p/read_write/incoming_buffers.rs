@@ -0,0 +1,181 @@
+// Copyright 2019-2021 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Queue of owned buffers of incoming data.
+//!
+//! Some platforms, such as WebSocket, deliver the data received on a connection as individual
+//! messages. The [`IncomingBuffers`] takes ownership of these messages, without copying them,
+//! and exposes them one by one as the [`ReadWrite::incoming_buffer`](super::ReadWrite).
+//!
+//! Buffers that have been entirely processed are kept in a pool and can be reused with
+//! [`IncomingBuffers::alloc`] in order to receive further data, avoiding an allocation per
+//! message.
+
+use alloc::{collections::VecDeque, vec::Vec};
+
+/// Queue of owned buffers of incoming data, plus a pool of buffers to reuse.
+pub struct IncomingBuffers {
+    /// Buffers of data waiting to be processed, in the order in which they have been received.
+    /// Never contains empty buffers.
+    queue: VecDeque<Vec<u8>>,
+    /// Position of the read cursor within the first element of [`IncomingBuffers::queue`].
+    first_offset: usize,
+    /// Buffers that have been fully processed and that can be reused.
+    pool: Vec<Vec<u8>>,
+    /// Maximum number of elements in [`IncomingBuffers::pool`].
+    max_pooled_buffers: usize,
+}
+
+impl IncomingBuffers {
+    /// Initializes a new empty queue. At most `max_pooled_buffers` processed buffers are kept
+    /// for later reuse.
+    pub fn new(max_pooled_buffers: usize) -> Self {
+        IncomingBuffers {
+            queue: VecDeque::new(),
+            first_offset: 0,
+            pool: Vec::with_capacity(max_pooled_buffers),
+            max_pooled_buffers,
+        }
+    }
+
+    /// Returns a buffer of `len` bytes, reusing a previously-processed buffer if possible.
+    ///
+    /// The content of the returned buffer is unspecified. It is meant to be filled with data
+    /// then passed to [`IncomingBuffers::push`].
+    pub fn alloc(&mut self, len: usize) -> Vec<u8> {
+        let mut buffer = self.pool.pop().unwrap_or_default();
+        buffer.resize(len, 0);
+        buffer
+    }
+
+    /// Pushes a buffer of incoming data at the back of the queue.
+    pub fn push(&mut self, buffer: Vec<u8>) {
+        if buffer.is_empty() {
+            self.recycle(buffer);
+            return;
+        }
+
+        self.queue.push_back(buffer);
+    }
+
+    /// Returns `true` if no data is waiting to be processed.
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Returns the total number of bytes waiting to be processed.
+    pub fn len(&self) -> usize {
+        self.queue.iter().fold(0, |a, b| a + b.len()) - self.first_offset
+    }
+
+    /// Returns the data of the first buffer of the queue that hasn't been processed yet. Returns
+    /// an empty slice if the queue is empty.
+    ///
+    /// Meant to be passed as [`ReadWrite::incoming_buffer`](super::ReadWrite).
+    pub fn front(&self) -> &[u8] {
+        match self.queue.front() {
+            Some(buffer) => &buffer[self.first_offset..],
+            None => &[],
+        }
+    }
+
+    /// Advances the read cursor by the given number of bytes. The first `bytes` will no longer
+    /// be returned by [`IncomingBuffers::front`]. If the first buffer has been entirely
+    /// processed, it is moved to the pool.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `bytes` is larger than the size of the slice returned by
+    /// [`IncomingBuffers::front`].
+    ///
+    pub fn advance(&mut self, bytes: usize) {
+        assert!(bytes <= self.front().len());
+        if bytes == 0 {
+            return;
+        }
+
+        self.first_offset += bytes;
+        if self.first_offset == self.queue.front().unwrap().len() {
+            let buffer = self.queue.pop_front().unwrap();
+            self.first_offset = 0;
+            self.recycle(buffer);
+        }
+    }
+
+    fn recycle(&mut self, mut buffer: Vec<u8>) {
+        if self.pool.len() < self.max_pooled_buffers {
+            buffer.clear();
+            self.pool.push(buffer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IncomingBuffers;
+
+    #[test]
+    fn basic_queue() {
+        let mut buffers = IncomingBuffers::new(2);
+        assert!(buffers.is_empty());
+        assert!(buffers.front().is_empty());
+
+        buffers.push(vec![1, 2, 3]);
+        buffers.push(Vec::new());
+        buffers.push(vec![4, 5]);
+        assert_eq!(buffers.len(), 5);
+
+        buffers.advance(2);
+        assert_eq!(buffers.front(), &[3]);
+        assert_eq!(buffers.len(), 3);
+
+        buffers.advance(1);
+        assert_eq!(buffers.front(), &[4, 5]);
+
+        buffers.advance(2);
+        assert!(buffers.is_empty());
+        assert_eq!(buffers.len(), 0);
+    }
+
+    #[test]
+    fn buffers_reused() {
+        let mut buffers = IncomingBuffers::new(1);
+
+        let mut buffer = buffers.alloc(4);
+        assert_eq!(buffer.len(), 4);
+        buffer.copy_from_slice(&[1, 2, 3, 4]);
+        let ptr = buffer.as_ptr();
+        buffers.push(buffer);
+        buffers.advance(4);
+
+        let buffer = buffers.alloc(2);
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer.as_ptr(), ptr);
+    }
+
+    #[test]
+    #[should_panic]
+    fn advance_too_far() {
+        let mut buffers = IncomingBuffers::new(0);
+        buffers.push(vec![1, 2]);
+        buffers.push(vec![3]);
+        buffers.advance(3);
+    }
+}
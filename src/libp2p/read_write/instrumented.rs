@@ -0,0 +1,218 @@
+// Copyright 2019-2021 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Collection of statistics about a connection.
+//!
+//! The [`InstrumentedReadWrite`] records, for a single connection, the number of bytes read and
+//! written, how often the connection asks to be woken up, and for how long the connection has
+//! been unable to write out data because the outgoing buffer was full.
+//!
+//! Call [`InstrumentedReadWrite::processing_start`] before and
+//! [`InstrumentedReadWrite::processing_end`] after each time the connection is processed, and
+//! [`InstrumentedReadWrite::statistics`] to obtain the statistics collected so far.
+
+use super::ReadWrite;
+
+use core::{cmp, convert::TryFrom as _, ops::Sub, time::Duration};
+
+/// Statistics about a connection. See [`InstrumentedReadWrite::statistics`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statistics {
+    /// Total number of bytes read from [`ReadWrite::incoming_buffer`].
+    pub read_bytes: u64,
+    /// Total number of bytes written to [`ReadWrite::outgoing_buffer`].
+    pub written_bytes: u64,
+    /// Number of times the connection has been processed.
+    pub num_processed: u64,
+    /// Number of times the connection has asked to be woken up, through
+    /// [`ReadWrite::wake_up_after`] or [`ReadWrite::wake_up_future`].
+    pub num_wake_up_requests: u64,
+    /// Total duration during which the outgoing buffer was full while the writing side was
+    /// open.
+    pub stalled: Duration,
+    /// Longest continuous duration during which the outgoing buffer was full while the writing
+    /// side was open.
+    pub longest_stall: Duration,
+}
+
+/// Records statistics about the processing of a connection.
+///
+/// One instance of this struct must be used per connection.
+pub struct InstrumentedReadWrite<TNow> {
+    /// Statistics collected so far.
+    statistics: Statistics,
+    /// If `Some`, the outgoing buffer was full the last time the connection has been processed,
+    /// and contains the moment when it has been found full for the first time.
+    stall_start: Option<TNow>,
+    /// State of the [`ReadWrite`] when [`InstrumentedReadWrite::processing_start`] has been
+    /// called, or `None` if the connection isn't being processed.
+    processing: Option<ProcessingStart>,
+}
+
+/// See [`InstrumentedReadWrite::processing`].
+struct ProcessingStart {
+    read_bytes: usize,
+    written_bytes: usize,
+    had_wake_up_after: bool,
+    had_wake_up_future: bool,
+}
+
+impl<TNow> InstrumentedReadWrite<TNow>
+where
+    TNow: Clone + Sub<TNow, Output = Duration> + Ord,
+{
+    /// Initializes a new [`InstrumentedReadWrite`] with empty statistics.
+    pub fn new() -> Self {
+        InstrumentedReadWrite {
+            statistics: Statistics {
+                read_bytes: 0,
+                written_bytes: 0,
+                num_processed: 0,
+                num_wake_up_requests: 0,
+                stalled: Duration::new(0, 0),
+                longest_stall: Duration::new(0, 0),
+            },
+            stall_start: None,
+            processing: None,
+        }
+    }
+
+    /// Must be called right before the connection is processed using `read_write`.
+    ///
+    /// # Panic
+    ///
+    /// Panics if [`InstrumentedReadWrite::processing_end`] hasn't been called after the previous
+    /// call to this method.
+    ///
+    pub fn processing_start(&mut self, read_write: &ReadWrite<TNow>) {
+        assert!(self.processing.is_none());
+        self.processing = Some(ProcessingStart {
+            read_bytes: read_write.read_bytes,
+            written_bytes: read_write.written_bytes,
+            had_wake_up_after: read_write.wake_up_after.is_some(),
+            had_wake_up_future: read_write.wake_up_future.is_some(),
+        });
+
+        // Update the stall duration. The connection is considered stalled if the outgoing
+        // buffer is full at the start of the processing.
+        let now = &read_write.now;
+        let is_stalled =
+            read_write.outgoing_buffer.is_some() && read_write.outgoing_buffer_available() == 0;
+        match (is_stalled, self.stall_start.take()) {
+            (true, None) => self.stall_start = Some(now.clone()),
+            (true, Some(start)) => self.stall_start = Some(start),
+            (false, Some(start)) => {
+                let duration = if *now > start {
+                    now.clone() - start
+                } else {
+                    Duration::new(0, 0)
+                };
+                self.statistics.stalled += duration;
+                self.statistics.longest_stall = cmp::max(self.statistics.longest_stall, duration);
+            }
+            (false, None) => {}
+        }
+    }
+
+    /// Must be called right after the connection has been processed, with the same `read_write`
+    /// as was passed to [`InstrumentedReadWrite::processing_start`].
+    ///
+    /// # Panic
+    ///
+    /// Panics if [`InstrumentedReadWrite::processing_start`] hasn't been called beforehand.
+    ///
+    pub fn processing_end(&mut self, read_write: &ReadWrite<TNow>) {
+        let start = self.processing.take().unwrap();
+
+        self.statistics.num_processed += 1;
+        self.statistics.read_bytes +=
+            u64::try_from(read_write.read_bytes - start.read_bytes).unwrap_or(u64::max_value());
+        self.statistics.written_bytes +=
+            u64::try_from(read_write.written_bytes - start.written_bytes)
+                .unwrap_or(u64::max_value());
+        if (!start.had_wake_up_after && read_write.wake_up_after.is_some())
+            || (!start.had_wake_up_future && read_write.wake_up_future.is_some())
+        {
+            self.statistics.num_wake_up_requests += 1;
+        }
+    }
+
+    /// Returns the statistics collected so far.
+    pub fn statistics(&self) -> &Statistics {
+        &self.statistics
+    }
+}
+
+impl<TNow> Default for InstrumentedReadWrite<TNow>
+where
+    TNow: Clone + Sub<TNow, Output = Duration> + Ord,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::ReadWrite, InstrumentedReadWrite};
+    use core::time::Duration;
+
+    #[test]
+    fn records_statistics() {
+        let mut instrumented = InstrumentedReadWrite::new();
+
+        let incoming = [1, 2, 3];
+        let mut out = [0; 2];
+
+        let mut rw = ReadWrite {
+            now: Duration::from_secs(0),
+            incoming_buffer: Some(&incoming),
+            outgoing_buffer: Some((&mut out, &mut [])),
+            read_bytes: 0,
+            written_bytes: 0,
+            wake_up_after: None,
+            wake_up_future: None,
+        };
+
+        instrumented.processing_start(&rw);
+        rw.advance_read(2);
+        rw.write_out(&[4, 5]);
+        rw.wake_up_after(&Duration::from_secs(5));
+        instrumented.processing_end(&rw);
+
+        // The outgoing buffer is now full.
+        rw.now = Duration::from_secs(2);
+        instrumented.processing_start(&rw);
+        instrumented.processing_end(&rw);
+
+        rw.now = Duration::from_secs(5);
+        rw.outgoing_buffer = None;
+        instrumented.processing_start(&rw);
+        instrumented.processing_end(&rw);
+
+        let statistics = instrumented.statistics();
+        assert_eq!(statistics.read_bytes, 2);
+        assert_eq!(statistics.written_bytes, 2);
+        assert_eq!(statistics.num_processed, 3);
+        assert_eq!(statistics.num_wake_up_requests, 1);
+        assert_eq!(statistics.stalled, Duration::from_secs(3));
+        assert_eq!(statistics.longest_stall, Duration::from_secs(3));
+    }
+}
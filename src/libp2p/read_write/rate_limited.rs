@@ -0,0 +1,222 @@
+// Copyright 2019-2021 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Limiting of the throughput of a connection.
+//!
+//! The [`RateLimitedReadWrite`] keeps track of the number of bytes read from and written to a
+//! connection during the current time slice. Use [`RateLimitedReadWrite::read_write`] to pass
+//! to a state machine a [`ReadWrite`] whose buffers are truncated to what remains of the
+//! budget of the current time slice.
+
+use super::ReadWrite;
+
+use core::{cmp, ops::Add, time::Duration};
+
+/// Configuration for a [`RateLimitedReadWrite`].
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Duration of a time slice. The budgets of bytes are reset at the start of each time slice.
+    pub slice_duration: Duration,
+
+    /// Maximum number of bytes that can be read from [`ReadWrite::incoming_buffer`] during a
+    /// time slice.
+    pub max_read_per_slice: usize,
+
+    /// Maximum number of bytes that can be written to [`ReadWrite::outgoing_buffer`] during a
+    /// time slice.
+    pub max_written_per_slice: usize,
+}
+
+/// Caps the number of bytes read and written on a connection per time slice.
+///
+/// One instance of this struct must be used per connection.
+pub struct RateLimitedReadWrite<TNow> {
+    /// See [`Config::slice_duration`].
+    slice_duration: Duration,
+    /// See [`Config::max_read_per_slice`].
+    max_read_per_slice: usize,
+    /// See [`Config::max_written_per_slice`].
+    max_written_per_slice: usize,
+    /// Moment when the current time slice ends. `None` if no time slice has started yet.
+    slice_end: Option<TNow>,
+    /// Number of bytes read during the current time slice.
+    read_in_slice: usize,
+    /// Number of bytes written during the current time slice.
+    written_in_slice: usize,
+}
+
+impl<TNow> RateLimitedReadWrite<TNow>
+where
+    TNow: Clone + Add<Duration, Output = TNow> + Ord,
+{
+    /// Initializes a new [`RateLimitedReadWrite`].
+    pub fn new(config: Config) -> Self {
+        RateLimitedReadWrite {
+            slice_duration: config.slice_duration,
+            max_read_per_slice: config.max_read_per_slice,
+            max_written_per_slice: config.max_written_per_slice,
+            slice_end: None,
+            read_in_slice: 0,
+            written_in_slice: 0,
+        }
+    }
+
+    /// Calls `process` with a [`ReadWrite`] whose incoming and outgoing buffers are the ones of
+    /// `read_write`, truncated in order to not exceed the budgets of the current time slice.
+    ///
+    /// Afterwards, `read_write` is updated according to what `process` has read and written.
+    /// If a budget has been exhausted, [`ReadWrite::wake_up_after`] is set to the end of the
+    /// current time slice.
+    ///
+    /// Returns the value returned by `process`.
+    pub fn read_write<T>(
+        &mut self,
+        read_write: &mut ReadWrite<'_, TNow>,
+        process: impl FnOnce(&mut ReadWrite<'_, TNow>) -> T,
+    ) -> T {
+        // Start a new time slice if necessary.
+        if self
+            .slice_end
+            .as_ref()
+            .map_or(true, |end| read_write.now >= *end)
+        {
+            self.slice_end = Some(read_write.now.clone() + self.slice_duration);
+            self.read_in_slice = 0;
+            self.written_in_slice = 0;
+        }
+
+        let read_budget = self.max_read_per_slice - self.read_in_slice;
+        let write_budget = self.max_written_per_slice - self.written_in_slice;
+
+        let incoming_available = read_write.incoming_buffer_available();
+        let outgoing_available = read_write.outgoing_buffer_available();
+
+        let (output, read, written, write_closed, wake_up_after, wake_up_future) = {
+            let mut limited = ReadWrite {
+                now: read_write.now.clone(),
+                incoming_buffer: read_write
+                    .incoming_buffer
+                    .map(|buf| &buf[..cmp::min(buf.len(), read_budget)]),
+                outgoing_buffer: read_write.outgoing_buffer.as_mut().map(|(a, b)| {
+                    let len_a = cmp::min(a.len(), write_budget);
+                    let len_b = cmp::min(b.len(), write_budget - len_a);
+                    (&mut a[..len_a], &mut b[..len_b])
+                }),
+                read_bytes: 0,
+                written_bytes: 0,
+                wake_up_after: None,
+                wake_up_future: None,
+            };
+
+            let output = process(&mut limited);
+
+            (
+                output,
+                limited.read_bytes,
+                limited.written_bytes,
+                limited.outgoing_buffer.is_none(),
+                limited.wake_up_after,
+                limited.wake_up_future,
+            )
+        };
+
+        read_write.advance_read(read);
+        read_write.advance_write(written);
+        if write_closed {
+            read_write.close_write();
+        }
+        if let Some(wake_up_after) = wake_up_after {
+            read_write.wake_up_after(&wake_up_after);
+        }
+        if let Some(wake_up_future) = wake_up_future {
+            read_write.wake_up_when_boxed(wake_up_future);
+        }
+
+        self.read_in_slice += read;
+        self.written_in_slice += written;
+
+        // If a budget has been exhausted while there remained data to read or space to write,
+        // the connection must be processed again once the budgets are reset.
+        if (self.read_in_slice == self.max_read_per_slice && incoming_available > read)
+            || (self.written_in_slice == self.max_written_per_slice && outgoing_available > written)
+        {
+            read_write.wake_up_after(self.slice_end.as_ref().unwrap());
+        }
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::ReadWrite, Config, RateLimitedReadWrite};
+    use core::time::Duration;
+
+    #[test]
+    fn caps_per_slice() {
+        let mut limiter = RateLimitedReadWrite::new(Config {
+            slice_duration: Duration::from_secs(1),
+            max_read_per_slice: 2,
+            max_written_per_slice: 3,
+        });
+
+        let incoming = [1, 2, 3, 4];
+        let mut out1 = [0; 2];
+        let mut out2 = [0; 3];
+
+        let mut rw = ReadWrite {
+            now: Duration::from_secs(0),
+            incoming_buffer: Some(&incoming),
+            outgoing_buffer: Some((&mut out1, &mut out2)),
+            read_bytes: 0,
+            written_bytes: 0,
+            wake_up_after: None,
+            wake_up_future: None,
+        };
+
+        limiter.read_write(&mut rw, |rw| {
+            assert_eq!(rw.incoming_buffer_available(), 2);
+            assert_eq!(rw.outgoing_buffer_available(), 3);
+            rw.discard_all_incoming();
+            rw.write_out(&[5, 6, 7]);
+        });
+
+        assert_eq!(rw.read_bytes, 2);
+        assert_eq!(rw.written_bytes, 3);
+        assert_eq!(rw.incoming_buffer.unwrap(), &[3, 4]);
+        assert_eq!(rw.wake_up_after, Some(Duration::from_secs(1)));
+
+        // Budgets are exhausted until the end of the time slice.
+        limiter.read_write(&mut rw, |rw| {
+            assert_eq!(rw.incoming_buffer_available(), 0);
+            assert_eq!(rw.outgoing_buffer_available(), 0);
+        });
+
+        rw.now = Duration::from_secs(1);
+        limiter.read_write(&mut rw, |rw| {
+            assert_eq!(rw.incoming_buffer_available(), 2);
+            assert_eq!(rw.outgoing_buffer_available(), 2);
+        });
+
+        drop(rw);
+        assert_eq!(&out1, &[5, 6]);
+        assert_eq!(&out2, &[7, 0, 0]);
+    }
+}
@@ -55,11 +55,6 @@ pub fn decode(scale_encoded_metadata: &[u8]) -> Result<decode::MetadataRef, deco
     decode::decode(scale_encoded_metadata)
 }
 
-// TODO: functions that generate transactions
-// - https://github.com/paritytech/substrate/blob/4cc4b76e361f55de8ae5dd2bae8226cacf4addcb/primitives/runtime/src/generic/unchecked_extrinsic.rs#L38-L48
-// - https://github.com/paritytech/substrate-subxt/blob/e85d01ed08e54374d2383e390cd5c2f09b400063/src/extrinsic/mod.rs#L44-L82
-// - https://github.com/paritytech/substrate-subxt/blob/e85d01ed08e54374d2383e390cd5c2f09b400063/src/metadata.rs#L190-L196
-
 // TODO: functions that decode events?
 // - storage key: https://github.com/paritytech/substrate-subxt/blob/271775bf99092bb890fe8c15eabc87f5b8d3966f/src/rpc.rs#L282-L284
 // - decoding this storage entry: https://github.com/paritytech/substrate-subxt/blob/e85d01ed08e54374d2383e390cd5c2f09b400063/src/events.rs#L195-L243
@@ -21,7 +21,10 @@
 *********************************************************/
 
 pub mod kademlia;
+pub mod mdns;
+pub mod peerset;
 pub mod protocol;
+pub mod reputation;
 pub mod service;
 
 pub use parity_multiaddr::Multiaddr;
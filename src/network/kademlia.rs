@@ -19,7 +19,7 @@
 
 use crate::libp2p::{multiaddr, peer_id};
 
-use alloc::vec::Vec;
+use alloc::{vec, vec::Vec};
 use core::convert::TryFrom as _;
 use prost::Message as _;
 
@@ -73,15 +73,179 @@ pub fn decode_find_node_response(
         return Err(DecodeFindNodeResponseError::BadResponseTy);
     }
 
-    let mut result = Vec::with_capacity(response.closer_peers.len());
-    for peer in response.closer_peers {
+    decode_peers(response.closer_peers).map_err(|err| match err {
+        DecodePeerError::BadPeerId(err) => DecodeFindNodeResponseError::BadPeerId(err),
+        DecodePeerError::BadMultiaddr(err) => DecodeFindNodeResponseError::BadMultiaddr(err),
+    })
+}
+
+/// Builds a wire message to send on the Kademlia request-response protocol to ask the target to
+/// return the peers that provide the given key, and the nodes closest to that key.
+pub fn build_get_providers_request(key: &[u8]) -> Vec<u8> {
+    let protobuf = dht_proto::Message {
+        r#type: dht_proto::message::MessageType::GetProviders as i32,
+        key: key.to_vec(),
+        ..Default::default()
+    };
+
+    let mut buf = Vec::with_capacity(protobuf.encoded_len());
+    protobuf.encode(&mut buf).unwrap();
+    buf
+}
+
+/// Decodes a response to a request built using [`build_get_providers_request`].
+// TODO: return a borrow of the response bytes ; we're limited by protobuf library
+pub fn decode_get_providers_response(
+    response_bytes: &[u8],
+) -> Result<GetProvidersResponse, DecodeGetProvidersResponseError> {
+    let response = dht_proto::Message::decode(response_bytes)
+        .map_err(ProtobufDecodeError)
+        .map_err(DecodeGetProvidersResponseError::ProtobufDecode)?;
+
+    if response.r#type != dht_proto::message::MessageType::GetProviders as i32 {
+        return Err(DecodeGetProvidersResponseError::BadResponseTy);
+    }
+
+    let map_err = |err| match err {
+        DecodePeerError::BadPeerId(err) => DecodeGetProvidersResponseError::BadPeerId(err),
+        DecodePeerError::BadMultiaddr(err) => DecodeGetProvidersResponseError::BadMultiaddr(err),
+    };
+
+    Ok(GetProvidersResponse {
+        providers: decode_peers(response.provider_peers).map_err(map_err)?,
+        closer_peers: decode_peers(response.closer_peers).map_err(map_err)?,
+    })
+}
+
+/// Response to a request built using [`build_get_providers_request`].
+#[derive(Debug)]
+pub struct GetProvidersResponse {
+    /// Peers that provide the requested key, and their addresses.
+    pub providers: Vec<(peer_id::PeerId, Vec<multiaddr::Multiaddr>)>,
+    /// Nodes closest to the requested key, and their addresses. Can be used to continue the
+    /// search if [`GetProvidersResponse::providers`] is empty.
+    pub closer_peers: Vec<(peer_id::PeerId, Vec<multiaddr::Multiaddr>)>,
+}
+
+/// Builds a wire message to send on the Kademlia request-response protocol to announce to the
+/// target that the given peer provides the given key.
+///
+/// Contrary to the other Kademlia requests, the target doesn't send back any response.
+pub fn build_add_provider_request(
+    key: &[u8],
+    provider_peer_id: &peer_id::PeerId,
+    provider_addrs: impl Iterator<Item = multiaddr::Multiaddr>,
+) -> Vec<u8> {
+    let protobuf = dht_proto::Message {
+        r#type: dht_proto::message::MessageType::AddProvider as i32,
+        key: key.to_vec(),
+        provider_peers: vec![dht_proto::message::Peer {
+            id: provider_peer_id.as_bytes().to_vec(),
+            addrs: provider_addrs.map(|addr| addr.to_vec()).collect(),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    let mut buf = Vec::with_capacity(protobuf.encoded_len());
+    protobuf.encode(&mut buf).unwrap();
+    buf
+}
+
+/// Decodes a request received on the Kademlia request-response protocol.
+///
+/// Only the kinds of requests that are listed in [`Request`] are supported.
+// TODO: return a borrow of the request bytes ; we're limited by protobuf library
+pub fn decode_request(request_bytes: &[u8]) -> Result<Request, DecodeRequestError> {
+    let request = dht_proto::Message::decode(request_bytes)
+        .map_err(ProtobufDecodeError)
+        .map_err(DecodeRequestError::ProtobufDecode)?;
+
+    let map_err = |err| match err {
+        DecodePeerError::BadPeerId(err) => DecodeRequestError::BadPeerId(err),
+        DecodePeerError::BadMultiaddr(err) => DecodeRequestError::BadMultiaddr(err),
+    };
+
+    match request.r#type {
+        ty if ty == dht_proto::message::MessageType::FindNode as i32 => {
+            Ok(Request::FindNode { key: request.key })
+        }
+        ty if ty == dht_proto::message::MessageType::GetProviders as i32 => {
+            Ok(Request::GetProviders { key: request.key })
+        }
+        ty if ty == dht_proto::message::MessageType::AddProvider as i32 => {
+            Ok(Request::AddProvider {
+                key: request.key,
+                providers: decode_peers(request.provider_peers).map_err(map_err)?,
+            })
+        }
+        _ => Err(DecodeRequestError::UnsupportedRequestTy),
+    }
+}
+
+/// Request decoded by [`decode_request`].
+#[derive(Debug)]
+pub enum Request {
+    /// Remote asks for the nodes closest to the given key.
+    FindNode {
+        /// Key whose closest nodes are requested.
+        key: Vec<u8>,
+    },
+    /// Remote asks for the peers that provide the given key. Should be answered with a message
+    /// built using [`build_get_providers_response`].
+    GetProviders {
+        /// Key whose providers are requested.
+        key: Vec<u8>,
+    },
+    /// Remote announces that the given peers provide the given key. No response is expected.
+    AddProvider {
+        /// Key that is provided.
+        key: Vec<u8>,
+        /// Peers that provide the key, and their addresses.
+        providers: Vec<(peer_id::PeerId, Vec<multiaddr::Multiaddr>)>,
+    },
+}
+
+/// Builds a wire message to send back on the Kademlia request-response protocol as a response
+/// to a get providers request. See [`Request::GetProviders`].
+pub fn build_get_providers_response<'a>(
+    key: &[u8],
+    providers: impl Iterator<Item = (&'a peer_id::PeerId, &'a [multiaddr::Multiaddr])>,
+    closer_peers: impl Iterator<Item = (&'a peer_id::PeerId, &'a [multiaddr::Multiaddr])>,
+) -> Vec<u8> {
+    let encode_peer =
+        |(peer_id, addrs): (&peer_id::PeerId, &[multiaddr::Multiaddr])| dht_proto::message::Peer {
+            id: peer_id.as_bytes().to_vec(),
+            addrs: addrs.iter().map(|addr| addr.to_vec()).collect(),
+            ..Default::default()
+        };
+
+    let protobuf = dht_proto::Message {
+        r#type: dht_proto::message::MessageType::GetProviders as i32,
+        key: key.to_vec(),
+        provider_peers: providers.map(encode_peer).collect(),
+        closer_peers: closer_peers.map(encode_peer).collect(),
+        ..Default::default()
+    };
+
+    let mut buf = Vec::with_capacity(protobuf.encoded_len());
+    protobuf.encode(&mut buf).unwrap();
+    buf
+}
+
+/// Decodes a list of peers found in a Kademlia message.
+fn decode_peers(
+    peers: Vec<dht_proto::message::Peer>,
+) -> Result<Vec<(peer_id::PeerId, Vec<multiaddr::Multiaddr>)>, DecodePeerError> {
+    let mut result = Vec::with_capacity(peers.len());
+    for peer in peers {
         let peer_id = peer_id::PeerId::from_bytes(peer.id)
-            .map_err(|(err, _)| DecodeFindNodeResponseError::BadPeerId(err))?;
+            .map_err(|(err, _)| DecodePeerError::BadPeerId(err))?;
 
         let mut multiaddrs = Vec::with_capacity(peer.addrs.len());
         for addr in peer.addrs {
-            let addr = multiaddr::Multiaddr::try_from(addr)
-                .map_err(DecodeFindNodeResponseError::BadMultiaddr)?;
+            let addr =
+                multiaddr::Multiaddr::try_from(addr).map_err(DecodePeerError::BadMultiaddr)?;
             multiaddrs.push(addr);
         }
 
@@ -91,6 +255,12 @@ pub fn decode_find_node_response(
     Ok(result)
 }
 
+/// Error potentially returned by [`decode_peers`].
+enum DecodePeerError {
+    BadPeerId(peer_id::FromBytesError),
+    BadMultiaddr(multiaddr::Error),
+}
+
 /// Error potentially returned by [`decode_find_node_response`].
 #[derive(Debug, derive_more::Display)]
 pub enum DecodeFindNodeResponseError {
@@ -104,7 +274,113 @@ pub enum DecodeFindNodeResponseError {
     BadMultiaddr(multiaddr::Error),
 }
 
+/// Error potentially returned by [`decode_get_providers_response`].
+#[derive(Debug, derive_more::Display)]
+pub enum DecodeGetProvidersResponseError {
+    /// Error while decoding the protobuf encoding.
+    ProtobufDecode(ProtobufDecodeError),
+    /// Response isn't a response to a get providers request.
+    BadResponseTy,
+    /// Error while parsing a [`peer_id::PeerId`] in the response.
+    BadPeerId(peer_id::FromBytesError),
+    /// Error while parsing a [`multiaddr::Multiaddr`] in the response.
+    BadMultiaddr(multiaddr::Error),
+}
+
+/// Error potentially returned by [`decode_request`].
+#[derive(Debug, derive_more::Display)]
+pub enum DecodeRequestError {
+    /// Error while decoding the protobuf encoding.
+    ProtobufDecode(ProtobufDecodeError),
+    /// Request is of a kind that isn't supported.
+    UnsupportedRequestTy,
+    /// Error while parsing a [`peer_id::PeerId`] in the request.
+    BadPeerId(peer_id::FromBytesError),
+    /// Error while parsing a [`multiaddr::Multiaddr`] in the request.
+    BadMultiaddr(multiaddr::Error),
+}
+
 /// Error while decoding the protobuf encoding.
 #[derive(Debug, derive_more::Display)]
 #[display(fmt = "{}", _0)]
 pub struct ProtobufDecodeError(prost::DecodeError);
+
+#[cfg(test)]
+mod tests {
+    use super::dht_proto;
+    use crate::libp2p::{multiaddr, peer_id};
+    use prost::Message as _;
+
+    #[test]
+    fn decode_get_providers_response() {
+        let provider = peer_id::PeerId::from_public_key(&peer_id::PublicKey::Ed25519([1; 32]));
+        let closer = peer_id::PeerId::from_public_key(&peer_id::PublicKey::Ed25519([2; 32]));
+
+        let response = {
+            let protobuf = dht_proto::Message {
+                r#type: dht_proto::message::MessageType::GetProviders as i32,
+                key: b"foo".to_vec(),
+                provider_peers: vec![dht_proto::message::Peer {
+                    id: provider.as_bytes().to_vec(),
+                    ..Default::default()
+                }],
+                closer_peers: vec![dht_proto::message::Peer {
+                    id: closer.as_bytes().to_vec(),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            };
+            let mut buf = Vec::new();
+            protobuf.encode(&mut buf).unwrap();
+            buf
+        };
+
+        let decoded = super::decode_get_providers_response(&response).unwrap();
+        assert_eq!(decoded.providers, vec![(provider, Vec::new())]);
+        assert_eq!(decoded.closer_peers, vec![(closer, Vec::new())]);
+
+        // A response to a different kind of request must be refused.
+        assert!(super::decode_find_node_response(&response).is_err());
+    }
+
+    #[test]
+    fn get_providers_response_roundtrip() {
+        let provider = peer_id::PeerId::from_public_key(&peer_id::PublicKey::Ed25519([1; 32]));
+        let addr = "/ip4/1.2.3.4/tcp/30333"
+            .parse::<multiaddr::Multiaddr>()
+            .unwrap();
+
+        let response = super::build_get_providers_response(
+            b"foo",
+            core::iter::once((&provider, &[addr.clone()][..])),
+            core::iter::empty(),
+        );
+
+        let decoded = super::decode_get_providers_response(&response).unwrap();
+        assert_eq!(decoded.providers, vec![(provider, vec![addr])]);
+        assert!(decoded.closer_peers.is_empty());
+    }
+
+    #[test]
+    fn decode_add_provider_request() {
+        let provider = peer_id::PeerId::from_public_key(&peer_id::PublicKey::Ed25519([1; 32]));
+        let addr = "/ip4/1.2.3.4/tcp/30333"
+            .parse::<multiaddr::Multiaddr>()
+            .unwrap();
+
+        let request =
+            super::build_add_provider_request(b"foo", &provider, core::iter::once(addr.clone()));
+        match super::decode_request(&request).unwrap() {
+            super::Request::AddProvider { key, providers } => {
+                assert_eq!(key, b"foo");
+                assert_eq!(providers, vec![(provider, vec![addr])]);
+            }
+            _ => panic!(),
+        }
+
+        match super::decode_request(&super::build_get_providers_request(b"bar")).unwrap() {
+            super::Request::GetProviders { key } => assert_eq!(key, b"bar"),
+            _ => panic!(),
+        }
+    }
+}
@@ -0,0 +1,258 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Local network discovery through multicast DNS (mDNS).
+//!
+//! The libp2p mDNS discovery mechanism consists in sending DNS queries for the
+//! `_p2p._udp.local` service to the multicast address [`MULTICAST_ADDRESS_V4`] on port
+//! [`PORT`]. Nodes of the local network that have mDNS enabled answer with a DNS response
+//! containing `TXT` records of the form `dnsaddr=<multiaddr>`, where `<multiaddr>` ends with
+//! `/p2p/<peer-id>`.
+//!
+//! This module only contains the encoding of queries and the decoding of responses. Sending and
+//! receiving UDP packets is the responsibility of the API user.
+//!
+//! > **Note**: As defined in RFC 6762, a query sent from a port other than [`PORT`] is answered
+//! >           directly to the sender of the query. It is therefore not necessary to bind to [`PORT`] or join the
+//! >           multicast group in order to discover nodes.
+
+use crate::libp2p::{multiaddr, peer_id};
+
+use alloc::vec::Vec;
+use core::{convert::TryFrom as _, str};
+use nom::Finish as _;
+
+/// IPv4 multicast address that mDNS queries must be sent to.
+pub const MULTICAST_ADDRESS_V4: [u8; 4] = [224, 0, 0, 251];
+
+/// UDP port that mDNS queries must be sent to.
+pub const PORT: u16 = 5353;
+
+/// Name of the DNS service that libp2p nodes advertise, encoded as a list of DNS labels.
+const SERVICE_NAME: &[&[u8]] = &[b"_p2p", b"_udp", b"local"];
+
+/// DNS record type of a `PTR` record.
+const TYPE_PTR: u16 = 12;
+/// DNS record type of a `TXT` record.
+const TYPE_TXT: u16 = 16;
+/// DNS class of Internet records.
+const CLASS_IN: u16 = 1;
+
+/// Builds a DNS query packet asking for the libp2p nodes of the local network.
+///
+/// The `transaction_id` is an arbitrary value. mDNS responders don't necessarily copy it back
+/// in their response, and it can thus be set to 0.
+pub fn build_query(transaction_id: u16) -> Vec<u8> {
+    let mut out = Vec::with_capacity(33);
+
+    // Header: identifier, flags, then number of questions, answers, authority records, and
+    // additional records.
+    out.extend_from_slice(&transaction_id.to_be_bytes());
+    out.extend_from_slice(&0u16.to_be_bytes());
+    out.extend_from_slice(&1u16.to_be_bytes());
+    out.extend_from_slice(&[0; 6]);
+
+    // Question.
+    for label in SERVICE_NAME {
+        out.push(u8::try_from(label.len()).unwrap());
+        out.extend_from_slice(label);
+    }
+    out.push(0);
+    out.extend_from_slice(&TYPE_PTR.to_be_bytes());
+    out.extend_from_slice(&CLASS_IN.to_be_bytes());
+
+    out
+}
+
+/// Decodes a DNS packet received on the mDNS socket.
+///
+/// On success, returns the list of nodes and their addresses advertised in the packet. Returns
+/// an empty list if the packet is a query rather than a response. Addresses that can't be parsed
+/// or that don't end with a `/p2p/<peer-id>` component are silently ignored.
+///
+/// > **Note**: The content of the packet is untrusted. Any node of the local network can send
+/// >           arbitrary responses.
+pub fn decode_response(
+    packet: &[u8],
+) -> Result<Vec<(peer_id::PeerId, multiaddr::Multiaddr)>, DecodeResponseError> {
+    let result: Result<_, nom::error::Error<_>> =
+        nom::combinator::all_consuming(message)(packet).finish();
+
+    let (is_response, records) = match result {
+        Ok((_, v)) => v,
+        Err(err) => return Err(DecodeResponseError(err.code)),
+    };
+
+    let mut out = Vec::new();
+    if !is_response {
+        return Ok(out);
+    }
+
+    for (record_ty, record_data) in records {
+        if record_ty != TYPE_TXT {
+            continue;
+        }
+
+        let result: Result<_, nom::error::Error<_>> = nom::combinator::all_consuming(
+            nom::multi::many0(nom::multi::length_data(nom::number::complete::u8)),
+        )(record_data)
+        .finish();
+
+        let strings = match result {
+            Ok((_, s)) => s,
+            Err(err) => return Err(DecodeResponseError(err.code)),
+        };
+
+        for string in strings {
+            let mut address = match string
+                .strip_prefix(b"dnsaddr=")
+                .and_then(|a| str::from_utf8(a).ok())
+                .and_then(|a| a.parse::<multiaddr::Multiaddr>().ok())
+            {
+                Some(a) => a,
+                None => continue,
+            };
+
+            let peer_id = match address.pop() {
+                Some(multiaddr::Protocol::P2p(peer_id)) => {
+                    match peer_id::PeerId::from_multihash(peer_id) {
+                        Ok(p) => p,
+                        Err(_) => continue,
+                    }
+                }
+                _ => continue,
+            };
+
+            out.push((peer_id, address));
+        }
+    }
+
+    Ok(out)
+}
+
+/// Error potentially returned by [`decode_response`].
+#[derive(Debug, derive_more::Display)]
+#[display(fmt = "Failed to decode an mDNS packet")]
+pub struct DecodeResponseError(nom::error::ErrorKind);
+
+/// Parses a DNS message. Returns whether the message is a response, and the type and data of all
+/// the records that it contains.
+fn message(bytes: &[u8]) -> nom::IResult<&[u8], (bool, Vec<(u16, &[u8])>)> {
+    let (bytes, (_id, flags, num_questions, num_answers, num_authority, num_additional)) =
+        nom::sequence::tuple((
+            nom::number::complete::be_u16,
+            nom::number::complete::be_u16,
+            nom::number::complete::be_u16,
+            nom::number::complete::be_u16,
+            nom::number::complete::be_u16,
+            nom::number::complete::be_u16,
+        ))(bytes)?;
+
+    let num_questions = usize::from(num_questions);
+    let (bytes, _) = nom::multi::many_m_n(num_questions, num_questions, question)(bytes)?;
+
+    let num_records =
+        usize::from(num_answers) + usize::from(num_authority) + usize::from(num_additional);
+    let (bytes, records) = nom::multi::many_m_n(num_records, num_records, record)(bytes)?;
+
+    Ok((bytes, ((flags & 0x8000) != 0, records)))
+}
+
+/// Parses a question of a DNS message and discards it.
+fn question(bytes: &[u8]) -> nom::IResult<&[u8], ()> {
+    let (bytes, _) = name(bytes)?;
+    let (bytes, _ty) = nom::number::complete::be_u16(bytes)?;
+    let (bytes, _class) = nom::number::complete::be_u16(bytes)?;
+    Ok((bytes, ()))
+}
+
+/// Parses a resource record of a DNS message. Returns its type and data.
+fn record(bytes: &[u8]) -> nom::IResult<&[u8], (u16, &[u8])> {
+    let (bytes, _) = name(bytes)?;
+    let (bytes, ty) = nom::number::complete::be_u16(bytes)?;
+    let (bytes, _class) = nom::number::complete::be_u16(bytes)?;
+    let (bytes, _ttl) = nom::number::complete::be_u32(bytes)?;
+    let (bytes, data) = nom::multi::length_data(nom::number::complete::be_u16)(bytes)?;
+    Ok((bytes, (ty, data)))
+}
+
+/// Parses a DNS name and discards it. Compressed names are accepted, but the pointer isn't
+/// followed.
+fn name(mut bytes: &[u8]) -> nom::IResult<&[u8], ()> {
+    loop {
+        let (rest, len) = nom::number::complete::u8(bytes)?;
+        match len {
+            0 => return Ok((rest, ())),
+            l if (l & 0xc0) == 0xc0 => {
+                let (rest, _) = nom::number::complete::u8(rest)?;
+                return Ok((rest, ()));
+            }
+            l if (l & 0xc0) != 0 => {
+                return Err(nom::Err::Error(nom::error::make_error(
+                    bytes,
+                    nom::error::ErrorKind::Verify,
+                )));
+            }
+            l => {
+                let (rest, _) = nom::bytes::complete::take(l)(rest)?;
+                bytes = rest;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::libp2p::peer_id;
+    use core::convert::TryFrom as _;
+
+    #[test]
+    fn query_is_not_response() {
+        let query = super::build_query(12);
+        assert!(super::decode_response(&query).unwrap().is_empty());
+    }
+
+    #[test]
+    fn decode_txt_records() {
+        let peer_id = peer_id::PeerId::from_public_key(&peer_id::PublicKey::Ed25519([1; 32]));
+        let valid = format!("dnsaddr=/ip4/192.168.1.2/tcp/30333/p2p/{}", peer_id);
+        let invalid = b"dnsaddr=/ip4/192.168.1.2/tcp/30333";
+
+        let mut txt_data = Vec::new();
+        txt_data.push(u8::try_from(valid.len()).unwrap());
+        txt_data.extend_from_slice(valid.as_bytes());
+        txt_data.push(u8::try_from(invalid.len()).unwrap());
+        txt_data.extend_from_slice(invalid);
+
+        let mut packet = vec![0, 0, 0x84, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+        packet.extend_from_slice(&[5, b'p', b'e', b'e', b'r', b'1', 0xc0, 12]);
+        packet.extend_from_slice(&[0, 16, 0, 1, 0, 0, 0, 120]);
+        packet.extend_from_slice(&u16::try_from(txt_data.len()).unwrap().to_be_bytes());
+        packet.extend_from_slice(&txt_data);
+
+        let decoded = super::decode_response(&packet).unwrap();
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(decoded[0].0, peer_id);
+        assert_eq!(decoded[0].1, "/ip4/192.168.1.2/tcp/30333".parse().unwrap());
+    }
+
+    #[test]
+    fn decode_truncated() {
+        let query = super::build_query(0);
+        assert!(super::decode_response(&query[..query.len() - 1]).is_err());
+    }
+}
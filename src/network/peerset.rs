@@ -0,0 +1,473 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Allocation of slots to peers.
+//!
+//! For each chain, the local node only maintains a limited number of peers with which it
+//! exchanges block announces, transactions, and GrandPa messages. Each of these peers occupies a
+//! *slot*.
+//!
+//! Slots are either *inbound*, meaning that the remote has opened the block announces substream
+//! first, or *outbound*, meaning that the local node has chosen this peer, for example after a
+//! discovery round. Slots are additionally split between *full* slots, for full nodes and
+//! authorities, and *light* slots, for light clients. The limits of each category are found in
+//! [`SlotsConfig`].
+//!
+//! When an outbound slot is allocated, the role of the peer isn't known yet. Its role is later
+//! reported with [`Peerset::set_role`], at which point the slot is released if the slots of the
+//! corresponding category are all occupied.
+//!
+//! When all the inbound slots of a category are occupied, a new peer can replace the occupant
+//! with the lowest score, provided that its score is strictly higher. See
+//! [`Peerset::in_slot_candidate`].
+//!
+//! This data structure is purely informative and doesn't perform any networking operation by
+//! itself.
+
+use super::protocol::Role;
+use crate::libp2p::PeerId;
+
+use alloc::vec::Vec;
+use core::convert::TryFrom as _;
+use rand::{RngCore as _, SeedableRng as _};
+
+/// Configuration for a [`Peerset`].
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Seed for the randomness within the data structure.
+    pub randomness_seed: [u8; 32],
+
+    /// For each chain, the number of slots available. The indices of this list are the chain
+    /// indices used in the rest of the API.
+    pub chains: Vec<SlotsConfig>,
+}
+
+/// Number of slots of each category of a chain.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SlotsConfig {
+    /// Number of inbound slots for full nodes and authorities.
+    pub in_full: u32,
+    /// Number of inbound slots for light clients.
+    pub in_light: u32,
+    /// Number of outbound slots for full nodes and authorities.
+    pub out_full: u32,
+    /// Number of outbound slots for light clients.
+    pub out_light: u32,
+}
+
+/// Direction of a slot.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Direction {
+    /// The remote has opened the block announces substream first.
+    In,
+    /// The local node has chosen to connect to this peer.
+    Out,
+}
+
+/// Category of a slot.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SlotKind {
+    /// Slot reserved for full nodes and authorities.
+    Full,
+    /// Slot reserved for light clients.
+    Light,
+}
+
+impl From<Role> for SlotKind {
+    fn from(role: Role) -> SlotKind {
+        match role {
+            Role::Full | Role::Authority => SlotKind::Full,
+            Role::Light => SlotKind::Light,
+        }
+    }
+}
+
+/// Outcome of [`Peerset::in_slot_candidate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InSlotCandidate {
+    /// An inbound slot is available.
+    Free,
+    /// All the inbound slots are occupied, but the given peer, which has a lower score, can be
+    /// removed in order to make space.
+    Replace(PeerId),
+    /// All the inbound slots are occupied.
+    Full,
+}
+
+/// Outcome of [`Peerset::set_role`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SetRoleOutcome {
+    /// The peer keeps its slot.
+    Kept,
+    /// The peer had no slot, or all the slots corresponding to its role were already occupied,
+    /// in which case its slot has been released.
+    Released,
+}
+
+/// Collection of the slots of all chains. See [the module-level documentation](..).
+pub struct Peerset {
+    /// For each chain in [`Config::chains`], the corresponding state.
+    chains: Vec<Chain>,
+}
+
+struct Chain {
+    /// See [`Config::chains`].
+    config: SlotsConfig,
+
+    /// List of peers that have a slot. The kind is `None` for outbound slots whose peer's role
+    /// isn't known yet.
+    slots: hashbrown::HashMap<PeerId, (Direction, Option<SlotKind>), ahash::RandomState>,
+}
+
+impl Peerset {
+    /// Initializes a new collection where all slots are free.
+    pub fn new(config: Config) -> Self {
+        let mut randomness = rand_chacha::ChaCha20Rng::from_seed(config.randomness_seed);
+
+        Peerset {
+            chains: config
+                .chains
+                .into_iter()
+                .map(|slots_config| Chain {
+                    slots: hashbrown::HashMap::with_capacity_and_hasher(
+                        usize::try_from(
+                            u64::from(slots_config.in_full)
+                                + u64::from(slots_config.in_light)
+                                + u64::from(slots_config.out_full)
+                                + u64::from(slots_config.out_light),
+                        )
+                        .unwrap_or(0),
+                        ahash::RandomState::with_seeds(
+                            randomness.next_u64(),
+                            randomness.next_u64(),
+                            randomness.next_u64(),
+                            randomness.next_u64(),
+                        ),
+                    ),
+                    config: slots_config,
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns the slot of the given peer on the given chain, if any. The kind is `None` if the
+    /// role of the peer isn't known yet.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `chain_index` is out of range.
+    ///
+    pub fn slot(
+        &self,
+        chain_index: usize,
+        peer_id: &PeerId,
+    ) -> Option<(Direction, Option<SlotKind>)> {
+        self.chains[chain_index].slots.get(peer_id).copied()
+    }
+
+    /// Returns the list of peers that have a slot on the given chain.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `chain_index` is out of range.
+    ///
+    pub fn slots(
+        &self,
+        chain_index: usize,
+    ) -> impl ExactSizeIterator<Item = (&PeerId, Direction, Option<SlotKind>)> {
+        self.chains[chain_index]
+            .slots
+            .iter()
+            .map(|(peer_id, (direction, kind))| (peer_id, *direction, *kind))
+    }
+
+    /// Returns the configuration of the slots of the given chain.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `chain_index` is out of range.
+    ///
+    pub fn slots_config(&self, chain_index: usize) -> &SlotsConfig {
+        &self.chains[chain_index].config
+    }
+
+    /// Returns `true` if [`Peerset::assign_out`] could succeed for a peer of unknown role.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `chain_index` is out of range.
+    ///
+    pub fn has_free_out_slot(&self, chain_index: usize) -> bool {
+        let chain = &self.chains[chain_index];
+        chain.count(Direction::Out, None)
+            < u64::from(chain.config.out_full) + u64::from(chain.config.out_light)
+    }
+
+    /// Allocates an outbound slot to the given peer.
+    ///
+    /// If the peer already has an inbound slot, it is turned into an outbound slot. Returns
+    /// `false` if the peer already has an outbound slot or if no slot is available.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `chain_index` is out of range.
+    ///
+    pub fn assign_out(&mut self, chain_index: usize, peer_id: &PeerId) -> bool {
+        let chain = &mut self.chains[chain_index];
+
+        let kind = match chain.slots.get(peer_id) {
+            Some((Direction::Out, _)) => return false,
+            Some((Direction::In, kind)) => *kind,
+            None => None,
+        };
+
+        let has_room = match kind {
+            None => {
+                chain.count(Direction::Out, None)
+                    < u64::from(chain.config.out_full) + u64::from(chain.config.out_light)
+            }
+            Some(kind) => {
+                chain.count(Direction::Out, Some(kind)) < chain.config.limit(Direction::Out, kind)
+            }
+        };
+
+        if !has_room {
+            return false;
+        }
+
+        chain.slots.insert(peer_id.clone(), (Direction::Out, kind));
+        true
+    }
+
+    /// Reports the role of a peer that has a slot on the given chain.
+    ///
+    /// If the peer has an outbound slot whose role wasn't known yet and all the outbound slots
+    /// of the category corresponding to this role are occupied, the slot is released.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `chain_index` is out of range.
+    ///
+    pub fn set_role(&mut self, chain_index: usize, peer_id: &PeerId, role: Role) -> SetRoleOutcome {
+        let chain = &mut self.chains[chain_index];
+        let kind = SlotKind::from(role);
+
+        let direction = match chain.slots.get(peer_id) {
+            Some((_, Some(_))) => return SetRoleOutcome::Kept,
+            Some((direction, None)) => *direction,
+            None => return SetRoleOutcome::Released,
+        };
+
+        if chain.count(direction, Some(kind)) >= chain.config.limit(direction, kind) {
+            chain.slots.remove(peer_id);
+            return SetRoleOutcome::Released;
+        }
+
+        chain.slots.insert(peer_id.clone(), (direction, Some(kind)));
+        SetRoleOutcome::Kept
+    }
+
+    /// Determines whether a peer with the given role and score could be allocated an inbound
+    /// slot.
+    ///
+    /// `scores` is used to obtain the score of the peers that already have an inbound slot. If
+    /// all the inbound slots of the category are occupied, the occupant with the lowest score is
+    /// returned if its score is strictly lower than `candidate_score`. It is then the
+    /// responsibility of the API user to call [`Peerset::unassign`] on it before calling
+    /// [`Peerset::assign_in`].
+    ///
+    /// # Panic
+    ///
+    /// Panics if `chain_index` is out of range.
+    ///
+    pub fn in_slot_candidate(
+        &self,
+        chain_index: usize,
+        role: Role,
+        candidate_score: i32,
+        mut scores: impl FnMut(&PeerId) -> i32,
+    ) -> InSlotCandidate {
+        let chain = &self.chains[chain_index];
+        let kind = SlotKind::from(role);
+
+        if chain.count(Direction::In, Some(kind)) < chain.config.limit(Direction::In, kind) {
+            return InSlotCandidate::Free;
+        }
+
+        let lowest = chain
+            .slots
+            .iter()
+            .filter(|(_, (direction, k))| *direction == Direction::In && *k == Some(kind))
+            .map(|(peer_id, _)| (peer_id, scores(peer_id)))
+            .min_by_key(|(_, score)| *score);
+
+        match lowest {
+            Some((peer_id, score)) if score < candidate_score => {
+                InSlotCandidate::Replace(peer_id.clone())
+            }
+            _ => InSlotCandidate::Full,
+        }
+    }
+
+    /// Allocates an inbound slot to the given peer. Returns `false` if the peer already has a
+    /// slot or if all the inbound slots of the category corresponding to its role are occupied.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `chain_index` is out of range.
+    ///
+    pub fn assign_in(&mut self, chain_index: usize, peer_id: &PeerId, role: Role) -> bool {
+        let chain = &mut self.chains[chain_index];
+        let kind = SlotKind::from(role);
+
+        if chain.slots.contains_key(peer_id)
+            || chain.count(Direction::In, Some(kind)) >= chain.config.limit(Direction::In, kind)
+        {
+            return false;
+        }
+
+        chain
+            .slots
+            .insert(peer_id.clone(), (Direction::In, Some(kind)));
+        true
+    }
+
+    /// Releases the slot of the given peer on the given chain. Returns the direction of the slot
+    /// that has been released, if any.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `chain_index` is out of range.
+    ///
+    pub fn unassign(&mut self, chain_index: usize, peer_id: &PeerId) -> Option<Direction> {
+        self.chains[chain_index]
+            .slots
+            .remove(peer_id)
+            .map(|(direction, _)| direction)
+    }
+}
+
+impl Chain {
+    /// Returns the number of slots in the given direction and of the given kind. If `kind` is
+    /// `None`, counts the slots of all kinds.
+    fn count(&self, direction: Direction, kind: Option<SlotKind>) -> u64 {
+        let num = self
+            .slots
+            .values()
+            .filter(|(d, k)| *d == direction && (kind.is_none() || *k == kind))
+            .count();
+        u64::try_from(num).unwrap()
+    }
+}
+
+impl SlotsConfig {
+    fn limit(&self, direction: Direction, kind: SlotKind) -> u64 {
+        u64::from(match (direction, kind) {
+            (Direction::In, SlotKind::Full) => self.in_full,
+            (Direction::In, SlotKind::Light) => self.in_light,
+            (Direction::Out, SlotKind::Full) => self.out_full,
+            (Direction::Out, SlotKind::Light) => self.out_light,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Config, Direction, InSlotCandidate, Peerset, SetRoleOutcome, SlotKind, SlotsConfig,
+    };
+    use crate::libp2p::peer_id::{PeerId, PublicKey};
+    use crate::network::protocol::Role;
+
+    fn peerset() -> Peerset {
+        Peerset::new(Config {
+            randomness_seed: [0; 32],
+            chains: vec![SlotsConfig {
+                in_full: 1,
+                in_light: 1,
+                out_full: 2,
+                out_light: 0,
+            }],
+        })
+    }
+
+    fn peer(n: u8) -> PeerId {
+        PeerId::from_public_key(&PublicKey::Ed25519([n; 32]))
+    }
+
+    #[test]
+    fn out_slots() {
+        let mut peerset = peerset();
+
+        assert!(peerset.assign_out(0, &peer(1)));
+        assert!(!peerset.assign_out(0, &peer(1)));
+        assert!(peerset.assign_out(0, &peer(2)));
+        assert!(!peerset.has_free_out_slot(0));
+        assert!(!peerset.assign_out(0, &peer(3)));
+
+        // No outbound light slot.
+        assert_eq!(
+            peerset.set_role(0, &peer(1), Role::Light),
+            SetRoleOutcome::Released
+        );
+        assert_eq!(
+            peerset.set_role(0, &peer(2), Role::Authority),
+            SetRoleOutcome::Kept
+        );
+        assert_eq!(
+            peerset.slot(0, &peer(2)),
+            Some((Direction::Out, Some(SlotKind::Full)))
+        );
+
+        assert!(peerset.assign_out(0, &peer(3)));
+        assert_eq!(peerset.unassign(0, &peer(3)), Some(Direction::Out));
+        assert_eq!(peerset.slots(0).len(), 1);
+    }
+
+    #[test]
+    fn in_slots_replacement() {
+        let mut peerset = peerset();
+
+        assert_eq!(
+            peerset.in_slot_candidate(0, Role::Full, 0, |_| 0),
+            InSlotCandidate::Free
+        );
+        assert!(peerset.assign_in(0, &peer(1), Role::Full));
+        assert!(!peerset.assign_in(0, &peer(2), Role::Full));
+        assert!(peerset.assign_in(0, &peer(2), Role::Light));
+
+        // Candidate isn't better than the occupant.
+        assert_eq!(
+            peerset.in_slot_candidate(0, Role::Full, 0, |_| 0),
+            InSlotCandidate::Full
+        );
+        assert_eq!(
+            peerset.in_slot_candidate(0, Role::Full, 0, |_| -50),
+            InSlotCandidate::Replace(peer(1))
+        );
+
+        assert_eq!(peerset.unassign(0, &peer(1)), Some(Direction::In));
+        assert!(peerset.assign_in(0, &peer(3), Role::Full));
+
+        // Inbound slots can be turned into outbound slots.
+        assert!(peerset.assign_out(0, &peer(3)));
+        assert_eq!(
+            peerset.slot(0, &peer(3)),
+            Some((Direction::Out, Some(SlotKind::Full)))
+        );
+    }
+}
@@ -30,18 +30,22 @@
 mod block_announces;
 mod block_request;
 mod call_proof;
+mod collation;
 mod grandpa;
 mod grandpa_warp_sync;
 mod identify;
 mod storage_proof;
+mod transactions;
 
 pub use self::block_announces::*;
 pub use self::block_request::*;
 pub use self::call_proof::*;
+pub use self::collation::*;
 pub use self::grandpa::*;
 pub use self::grandpa_warp_sync::*;
 pub use self::identify::*;
 pub use self::storage_proof::*;
+pub use self::transactions::*;
 
 // Protobuf schemas are gathered here.
 mod schema {
@@ -50,6 +54,23 @@ mod schema {
     include!(concat!(env!("OUT_DIR"), "/structs.rs"));
 }
 
+/// Encodes a list of trie node values into the format found in the responses of the light
+/// protocol: a SCALE-encoded `Vec<Vec<u8>>`.
+fn encode_proof(proof: impl Iterator<Item = impl AsRef<[u8]>>) -> alloc::vec::Vec<u8> {
+    let proof = proof.collect::<alloc::vec::Vec<_>>();
+
+    let mut out = crate::util::encode_scale_compact_usize(proof.len())
+        .as_ref()
+        .to_vec();
+    for node_value in proof {
+        out.extend_from_slice(
+            crate::util::encode_scale_compact_usize(node_value.as_ref().len()).as_ref(),
+        );
+        out.extend_from_slice(node_value.as_ref());
+    }
+    out
+}
+
 /// Error while decoding the protobuf encoding.
 #[derive(Debug, Clone, derive_more::Display)]
 #[display(fmt = "{}", _0)]
@@ -17,8 +17,8 @@
 
 use super::{schema, ProtobufDecodeError};
 
-use alloc::vec::Vec;
-use core::iter;
+use alloc::{string::String, vec::Vec};
+use core::{convert::TryFrom as _, iter};
 use prost::Message as _;
 
 /// Description of a call proof request that can be sent to a peer.
@@ -62,6 +62,66 @@ pub fn build_call_proof_request(
     iter::once(request_bytes)
 }
 
+/// Call proof request received from a remote. See [`decode_call_proof_request`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallProofRequest {
+    /// Hash of the block to perform the call against.
+    pub block_hash: [u8; 32],
+    /// Name of the runtime function to call.
+    pub method: String,
+    /// Parameter to pass to the call.
+    pub parameter: Vec<u8>,
+}
+
+/// Decodes a call proof request received from a remote.
+// TODO: should have a more zero-cost API, but we're limited by the protobuf library for that
+pub fn decode_call_proof_request(
+    request_bytes: &[u8],
+) -> Result<CallProofRequest, DecodeCallProofRequestError> {
+    let request = schema::Request::decode(request_bytes)
+        .map_err(ProtobufDecodeError)
+        .map_err(DecodeCallProofRequestError::ProtobufDecode)?;
+
+    let request = match request.request {
+        Some(schema::request::Request::RemoteCallRequest(rq)) => rq,
+        _ => return Err(DecodeCallProofRequestError::BadRequestTy),
+    };
+
+    Ok(CallProofRequest {
+        block_hash: <[u8; 32]>::try_from(&request.block[..])
+            .map_err(|_| DecodeCallProofRequestError::BadBlockHash)?,
+        method: request.method,
+        parameter: request.data,
+    })
+}
+
+/// Builds the bytes corresponding to a response to a call proof request.
+///
+/// The proof consists in a list of node values of the storage trie. It must contain all the
+/// storage entries accessed by the runtime while performing the call.
+pub fn build_call_proof_response(
+    proof: impl Iterator<Item = impl AsRef<[u8]>>,
+) -> impl Iterator<Item = impl AsRef<[u8]>> {
+    // Note: while the API of this function allows for a zero-cost implementation, the protobuf
+    // library doesn't permit to avoid allocations.
+
+    let response = schema::Response {
+        response: Some(schema::response::Response::RemoteCallResponse(
+            schema::RemoteCallResponse {
+                proof: super::encode_proof(proof),
+            },
+        )),
+    };
+
+    let response_bytes = {
+        let mut buf = Vec::with_capacity(response.encoded_len());
+        response.encode(&mut buf).unwrap();
+        buf
+    };
+
+    iter::once(response_bytes)
+}
+
 /// Decodes a response to a call proof request.
 // TODO: should have a more zero-cost API, but we're limited by the protobuf library for that
 pub fn decode_call_proof_response(
@@ -95,6 +155,17 @@ pub fn decode_call_proof_response(
     Ok(decoded)
 }
 
+/// Error potentially returned by [`decode_call_proof_request`].
+#[derive(Debug, Clone, derive_more::Display)]
+pub enum DecodeCallProofRequestError {
+    /// Error while decoding the protobuf encoding.
+    ProtobufDecode(ProtobufDecodeError),
+    /// Request isn't a call proof request.
+    BadRequestTy,
+    /// Block hash in the request doesn't have the correct length.
+    BadBlockHash,
+}
+
 /// Error potentially returned by [`decode_call_proof_response`].
 #[derive(Debug, Clone, derive_more::Display)]
 pub enum DecodeCallProofResponseError {
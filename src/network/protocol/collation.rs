@@ -0,0 +1,202 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Polkadot collation protocol.
+//!
+//! Collators are the nodes that produce the blocks of parachains. They send advertisements of
+//! the blocks they have produced (called "collations") to the validators of the relay chain
+//! through the collation protocol. Validators can then fetch these collations and notify the
+//! collator of the outcome of their verification.
+//!
+//! This module only provides the tools to encode and decode the messages sent over collation
+//! notifications substreams.
+
+use core::{convert::TryFrom as _, iter};
+use nom::Finish as _;
+
+/// Decoded message sent on a collation substream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CollationMessageRef<'a> {
+    /// Sent by a collator as the first message on the substream. Declares the intent of the
+    /// collator to advertise collations for the given parachain.
+    Declare {
+        /// Sr25519 public key of the collator.
+        collator_id: &'a [u8; 32],
+        /// Identifier of the parachain the collator produces blocks for.
+        para_id: u32,
+        /// Sr25519 signature, made with [`CollationMessageRef::Declare::collator_id`], of the
+        /// concatenation of the bytes `b"COLL"` and the [`crate::libp2p::PeerId`] of the
+        /// collator.
+        signature: &'a [u8; 64],
+    },
+
+    /// Sent by a collator in order to advertise that it has a collation available whose relay
+    /// chain parent is the given block.
+    AdvertiseCollation {
+        /// Hash of the relay chain block the collation is built upon.
+        relay_parent: &'a [u8; 32],
+    },
+
+    /// Sent by a validator in order to notify a collator that its collation has been seconded.
+    CollationSeconded {
+        /// Hash of the relay chain block the collation is built upon.
+        relay_parent: &'a [u8; 32],
+        /// SCALE-encoded signed statement of the validator. Not decoded by this module.
+        scale_encoded_statement: &'a [u8],
+    },
+}
+
+/// Turns a collation message into its SCALE-encoding ready to be sent over the wire.
+///
+/// This function returns an iterator of buffers. The encoded message consists in the
+/// concatenation of the buffers.
+pub fn encode_collation_message<'a>(
+    message: CollationMessageRef<'a>,
+) -> impl Iterator<Item = impl AsRef<[u8]> + 'a> + 'a {
+    // The first byte corresponds to the version of the protocol (`0` for the only existing
+    // version), and the second byte to the type of message.
+    match message {
+        CollationMessageRef::Declare {
+            collator_id,
+            para_id,
+            signature,
+        } => either::Left(
+            iter::once(either::Left(either::Left([0u8, 0u8])))
+                .chain(iter::once(either::Right(&collator_id[..])))
+                .chain(iter::once(either::Left(either::Right(
+                    para_id.to_le_bytes(),
+                ))))
+                .chain(iter::once(either::Right(&signature[..]))),
+        ),
+        CollationMessageRef::AdvertiseCollation { relay_parent } => either::Right(either::Left(
+            iter::once(either::Left(either::Left::<_, [u8; 4]>([0u8, 1u8])))
+                .chain(iter::once(either::Right(&relay_parent[..]))),
+        )),
+        CollationMessageRef::CollationSeconded {
+            relay_parent,
+            scale_encoded_statement,
+        } => either::Right(either::Right(
+            iter::once(either::Left(either::Left::<_, [u8; 4]>([0u8, 4u8])))
+                .chain(iter::once(either::Right(&relay_parent[..])))
+                .chain(iter::once(either::Right(scale_encoded_statement))),
+        )),
+    }
+}
+
+/// Decodes a SCALE-encoded collation message.
+pub fn decode_collation_message(
+    bytes: &[u8],
+) -> Result<CollationMessageRef, DecodeCollationMessageError> {
+    let result: Result<_, nom::error::Error<_>> = nom::combinator::all_consuming(
+        nom::sequence::preceded(nom::bytes::complete::tag(&[0]), collation_message),
+    )(bytes)
+    .finish();
+
+    match result {
+        Ok((_, msg)) => Ok(msg),
+        Err(err) => Err(DecodeCollationMessageError(err.code)),
+    }
+}
+
+/// Error potentially returned by [`decode_collation_message`].
+#[derive(Debug, derive_more::Display)]
+#[display(fmt = "Failed to decode a collation message")]
+pub struct DecodeCollationMessageError(nom::error::ErrorKind);
+
+// Nom combinators below.
+
+fn collation_message<'a, E: nom::error::ParseError<&'a [u8]>>(
+    bytes: &'a [u8],
+) -> nom::IResult<&'a [u8], CollationMessageRef<'a>, E> {
+    nom::branch::alt((
+        nom::combinator::map(
+            nom::sequence::preceded(
+                nom::bytes::complete::tag(&[0]),
+                nom::sequence::tuple((
+                    nom::bytes::complete::take(32u32),
+                    nom::number::complete::le_u32,
+                    nom::bytes::complete::take(64u32),
+                )),
+            ),
+            |(collator_id, para_id, signature)| CollationMessageRef::Declare {
+                collator_id: <&[u8; 32]>::try_from(collator_id).unwrap(),
+                para_id,
+                signature: <&[u8; 64]>::try_from(signature).unwrap(),
+            },
+        ),
+        nom::combinator::map(
+            nom::sequence::preceded(
+                nom::bytes::complete::tag(&[1]),
+                nom::bytes::complete::take(32u32),
+            ),
+            |relay_parent| CollationMessageRef::AdvertiseCollation {
+                relay_parent: <&[u8; 32]>::try_from(relay_parent).unwrap(),
+            },
+        ),
+        nom::combinator::map(
+            nom::sequence::preceded(
+                nom::bytes::complete::tag(&[4]),
+                nom::sequence::tuple((nom::bytes::complete::take(32u32), nom::combinator::rest)),
+            ),
+            |(relay_parent, scale_encoded_statement)| CollationMessageRef::CollationSeconded {
+                relay_parent: <&[u8; 32]>::try_from(relay_parent).unwrap(),
+                scale_encoded_statement,
+            },
+        ),
+    ))(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn encode_decode_declare() {
+        let message = super::CollationMessageRef::Declare {
+            collator_id: &[1; 32],
+            para_id: 2000,
+            signature: &[2; 64],
+        };
+
+        let encoded =
+            super::encode_collation_message(message.clone()).fold(Vec::new(), |mut a, b| {
+                a.extend_from_slice(b.as_ref());
+                a
+            });
+
+        assert_eq!(encoded.len(), 2 + 32 + 4 + 64);
+        assert_eq!(super::decode_collation_message(&encoded).unwrap(), message);
+    }
+
+    #[test]
+    fn encode_decode_advertise() {
+        let message = super::CollationMessageRef::AdvertiseCollation {
+            relay_parent: &[5; 32],
+        };
+
+        let encoded =
+            super::encode_collation_message(message.clone()).fold(Vec::new(), |mut a, b| {
+                a.extend_from_slice(b.as_ref());
+                a
+            });
+
+        assert_eq!(super::decode_collation_message(&encoded).unwrap(), message);
+    }
+
+    #[test]
+    fn decode_unknown_version() {
+        assert!(super::decode_collation_message(&[1, 1]).is_err());
+    }
+}
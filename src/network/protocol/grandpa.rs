@@ -39,9 +39,14 @@ impl<'a> GrandpaNotificationRef<'a> {
     /// encoding of that object.
     pub fn scale_encoding(&self) -> impl Iterator<Item = impl AsRef<[u8]> + Clone> + Clone {
         match self {
-            GrandpaNotificationRef::Neighbor(n) => {
-                iter::once(either::Left(&[2u8])).chain(n.scale_encoding().map(either::Right))
-            }
+            GrandpaNotificationRef::Neighbor(n) => either::Left(
+                iter::once(either::Left([2u8]))
+                    .chain(n.scale_encoding().map(|b| either::Right(either::Left(b)))),
+            ),
+            GrandpaNotificationRef::CatchUpRequest(r) => either::Right(
+                iter::once(either::Left([3u8]))
+                    .chain(r.scale_encoding().map(|b| either::Right(either::Right(b)))),
+            ),
             _ => todo!(),
         }
     }
@@ -95,22 +100,75 @@ impl NeighborPacket {
     }
 }
 
+/// Request for the votes of a GrandPa round, sent to a peer whose neighbor packet indicates that
+/// it is ahead of the local node.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CatchUpRequest {
     pub round_number: u64,
     pub set_id: u64,
 }
 
+impl CatchUpRequest {
+    /// Returns an iterator to list of buffers which, when concatenated, produces the SCALE
+    /// encoding of that object.
+    pub fn scale_encoding(&self) -> impl Iterator<Item = impl AsRef<[u8]> + Clone> + Clone {
+        iter::once(self.round_number.to_le_bytes()).chain(iter::once(self.set_id.to_le_bytes()))
+    }
+}
+
+/// Answer to a [`CatchUpRequest`]. Contains the votes of the latest completed round of the peer.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CatchUpRef<'a> {
     pub set_id: u64,
     pub round_number: u64,
     pub prevotes: Vec<PrevoteRef<'a>>,
     pub precommits: Vec<PrecommitRef<'a>>,
+    /// Hash of the block that all the votes of the round are a descendant of.
     pub base_hash: &'a [u8; 32],
+    /// Height of the block that all the votes of the round are a descendant of.
     pub base_number: u32,
 }
 
+impl<'a> CatchUpRef<'a> {
+    /// Builds a SCALE-encoded commit message containing the pre-commits of this catch-up, and
+    /// whose target is [`CatchUpRef::base_hash`].
+    ///
+    /// Since the pre-commits of a commit message are signed in the exact same way as the ones
+    /// of a catch-up, the returned commit message can be verified with
+    /// [`crate::finality::grandpa::commit::verify`] like any commit message received from the
+    /// network. If it successfully verifies, the base of the catch-up can be considered as
+    /// finalized.
+    ///
+    /// > **Note**: The target of the actual commit of this round is likely a descendant of the
+    /// >           base of the catch-up. Determining this descendant, however, requires knowing
+    /// >           the ancestry of the blocks that are voted for.
+    pub fn base_commit_message(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(8 + 8 + 32 + 4 + self.precommits.len() * (36 + 96) + 10);
+        out.extend_from_slice(&self.round_number.to_le_bytes());
+        out.extend_from_slice(&self.set_id.to_le_bytes());
+        out.extend_from_slice(self.base_hash);
+        out.extend_from_slice(&self.base_number.to_le_bytes());
+
+        out.extend_from_slice(
+            crate::util::encode_scale_compact_usize(self.precommits.len()).as_ref(),
+        );
+        for precommit in &self.precommits {
+            out.extend_from_slice(precommit.target_hash);
+            out.extend_from_slice(&precommit.target_number.to_le_bytes());
+        }
+
+        out.extend_from_slice(
+            crate::util::encode_scale_compact_usize(self.precommits.len()).as_ref(),
+        );
+        for precommit in &self.precommits {
+            out.extend_from_slice(precommit.signature);
+            out.extend_from_slice(precommit.authority_public_key);
+        }
+
+        out
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PrevoteRef<'a> {
     /// Hash of the block concerned by the pre-vote.
@@ -378,4 +436,26 @@ mod tests {
 
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn catch_up_request_encode_decode() {
+        let request = super::GrandpaNotificationRef::CatchUpRequest(super::CatchUpRequest {
+            round_number: 3671,
+            set_id: 3490,
+        });
+
+        let encoded = request.scale_encoding().fold(Vec::new(), |mut a, b| {
+            a.extend_from_slice(b.as_ref());
+            a
+        });
+
+        assert_eq!(
+            encoded,
+            &[3, 87, 14, 0, 0, 0, 0, 0, 0, 162, 13, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            super::decode_grandpa_notification(&encoded).unwrap(),
+            request
+        );
+    }
 }
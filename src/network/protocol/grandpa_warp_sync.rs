@@ -38,6 +38,7 @@
 use crate::{finality, header};
 
 use alloc::vec::Vec;
+use core::{convert::TryFrom as _, iter};
 
 // TODO: all the constraints explained here should be checked when decoding the message
 
@@ -68,6 +69,38 @@ pub struct GrandpaWarpSyncResponseFragment {
     pub justification: finality::justification::decode::GrandpaJustification,
 }
 
+/// Error potentially returned by [`decode_grandpa_warp_sync_request`].
+#[derive(Debug, derive_more::Display)]
+pub struct DecodeGrandpaWarpSyncRequestError;
+
+/// Decodes a GrandPa warp sync request received from a remote. Returns the hash of the block
+/// the proof must start from.
+pub fn decode_grandpa_warp_sync_request(
+    request_bytes: &[u8],
+) -> Result<[u8; 32], DecodeGrandpaWarpSyncRequestError> {
+    <[u8; 32]>::try_from(request_bytes).map_err(|_| DecodeGrandpaWarpSyncRequestError)
+}
+
+/// Builds the bytes corresponding to a response to a GrandPa warp sync request.
+///
+/// Must be passed a list of SCALE-encoded headers and their corresponding SCALE-encoded
+/// justification, ordered by ascending block height. See [`GrandpaWarpSyncResponse`].
+pub fn build_grandpa_warp_sync_response(
+    fragments: impl ExactSizeIterator<Item = (impl AsRef<[u8]>, impl AsRef<[u8]>)>,
+    is_finished: bool,
+) -> impl Iterator<Item = impl AsRef<[u8]>> {
+    iter::once(either::Left(either::Left(
+        crate::util::encode_scale_compact_usize(fragments.len()),
+    )))
+    .chain(fragments.flat_map(|(header, justification)| {
+        iter::once(either::Right(either::Left(header)))
+            .chain(iter::once(either::Right(either::Right(justification))))
+    }))
+    .chain(iter::once(either::Left(either::Right([u8::from(
+        is_finished,
+    )]))))
+}
+
 /// Error potentially returned by [`decode_grandpa_warp_sync_response`].
 #[derive(Debug, derive_more::Display)]
 pub struct DecodeGrandpaWarpSyncResponseError;
@@ -122,3 +155,46 @@ fn decode_fragments(bytes: &[u8]) -> nom::IResult<&[u8], Vec<GrandpaWarpSyncResp
         )
     })(bytes)
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{finality, header};
+
+    #[test]
+    fn response_round_trip() {
+        let header = header::Header {
+            parent_hash: [1; 32],
+            number: 5,
+            state_root: [2; 32],
+            extrinsics_root: [3; 32],
+            digest: header::DigestRef::empty().into(),
+        };
+
+        // Justification without any precommit nor ancestry.
+        let mut justification = 7u64.to_le_bytes().to_vec();
+        justification.extend_from_slice(&header.hash());
+        justification.extend_from_slice(&5u32.to_le_bytes());
+        justification.extend_from_slice(&[0, 0]);
+        finality::justification::decode::decode_grandpa(&justification).unwrap();
+
+        let encoded_header = header.scale_encoding_vec();
+        let response = super::build_grandpa_warp_sync_response(
+            [(&encoded_header[..], &justification[..])].iter().copied(),
+            true,
+        )
+        .fold(Vec::new(), |mut a, b| {
+            a.extend_from_slice(b.as_ref());
+            a
+        });
+
+        let decoded = super::decode_grandpa_warp_sync_response(&response).unwrap();
+        assert!(decoded.is_finished);
+        assert_eq!(decoded.fragments.len(), 1);
+        assert_eq!(decoded.fragments[0].header.hash(), header.hash());
+        assert_eq!(decoded.fragments[0].justification.round, 7);
+        assert_eq!(
+            decoded.fragments[0].justification.target_hash,
+            header.hash()
+        );
+    }
+}
@@ -31,10 +31,14 @@
 //! See also [the official specification](https://github.com/libp2p/specs/tree/69e57d59dc5d59d3979d79842b577ec2c483f7fa/identify).
 
 use super::schema;
-use crate::libp2p::{peer_id::PublicKey, Multiaddr};
+use crate::libp2p::{
+    multiaddr,
+    peer_id::{FromProtobufEncodingError, PublicKey},
+    Multiaddr,
+};
 
-use alloc::{borrow::ToOwned as _, vec::Vec};
-use core::iter;
+use alloc::{borrow::ToOwned as _, string::String, vec::Vec};
+use core::{convert::TryFrom as _, iter};
 use prost::Message as _;
 
 /// Description of a response to an identify request.
@@ -81,3 +85,106 @@ pub fn build_identify_response<'a>(
 
     iter::once(request_bytes)
 }
+
+/// Response to an identify request, as decoded by [`decode_identify_response`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedIdentifyResponse {
+    /// Version of the protocol used by the remote. Empty if not provided.
+    pub protocol_version: String,
+    /// Name and version of the implementation of the remote. Empty if not provided.
+    pub agent_version: String,
+    /// Public key of the remote. Can be used to verify that the response indeed comes from the
+    /// expected peer.
+    pub public_key: PublicKey,
+    /// List of addresses the remote is listening on.
+    pub listen_addrs: Vec<Multiaddr>,
+    /// Address of the local node, as seen from the remote.
+    pub observed_addr: Option<Multiaddr>,
+    /// Names of the protocols supported by the remote.
+    pub protocols: Vec<String>,
+}
+
+/// Decodes a response to an identify request.
+pub fn decode_identify_response(
+    response_bytes: &[u8],
+) -> Result<DecodedIdentifyResponse, DecodeIdentifyResponseError> {
+    let response = schema::Identify::decode(response_bytes)
+        .map_err(|_| DecodeIdentifyResponseError::ProtobufDecode)?;
+
+    let public_key = PublicKey::from_protobuf_encoding(
+        response
+            .public_key
+            .as_ref()
+            .ok_or(DecodeIdentifyResponseError::MissingPublicKey)?,
+    )
+    .map_err(DecodeIdentifyResponseError::BadPublicKey)?;
+
+    let listen_addrs = response
+        .listen_addrs
+        .into_iter()
+        .map(Multiaddr::try_from)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(DecodeIdentifyResponseError::BadMultiaddr)?;
+
+    let observed_addr = response
+        .observed_addr
+        .map(Multiaddr::try_from)
+        .transpose()
+        .map_err(DecodeIdentifyResponseError::BadMultiaddr)?;
+
+    Ok(DecodedIdentifyResponse {
+        protocol_version: response.protocol_version.unwrap_or_default(),
+        agent_version: response.agent_version.unwrap_or_default(),
+        public_key,
+        listen_addrs,
+        observed_addr,
+        protocols: response.protocols,
+    })
+}
+
+/// Error potentially returned by [`decode_identify_response`].
+#[derive(Debug, derive_more::Display)]
+pub enum DecodeIdentifyResponseError {
+    /// Error while decoding the protobuf encoding.
+    ProtobufDecode,
+    /// Response doesn't contain the public key of the remote.
+    MissingPublicKey,
+    /// Error while decoding the public key of the remote.
+    #[display(fmt = "Failed to decode public key: {}", _0)]
+    BadPublicKey(FromProtobufEncodingError),
+    /// Error while parsing a [`Multiaddr`] in the response.
+    BadMultiaddr(multiaddr::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::libp2p::{peer_id::PublicKey, Multiaddr};
+    use core::iter;
+
+    #[test]
+    fn encode_decode() {
+        let observed_addr = "/ip4/1.2.3.4/tcp/30333".parse::<Multiaddr>().unwrap();
+        let listen_addr = "/ip4/5.6.7.8/tcp/30333/ws".parse::<Multiaddr>().unwrap();
+
+        let encoded = super::build_identify_response(super::IdentifyResponse {
+            protocol_version: "/substrate/1.0",
+            agent_version: "smoldot",
+            ed25519_public_key: &[5; 32],
+            listen_addrs: iter::once(&listen_addr),
+            observed_addr: &observed_addr,
+            protocols: iter::once("/ipfs/id/1.0.0"),
+        })
+        .fold(Vec::new(), |mut a, b| {
+            a.extend_from_slice(b.as_ref());
+            a
+        });
+
+        let decoded = super::decode_identify_response(&encoded).unwrap();
+        assert_eq!(decoded.protocol_version, "/substrate/1.0");
+        assert_eq!(decoded.agent_version, "smoldot");
+        assert_eq!(decoded.public_key, PublicKey::Ed25519([5; 32]));
+        assert_eq!(decoded.listen_addrs, vec![listen_addr]);
+        assert_eq!(decoded.observed_addr, Some(observed_addr));
+        assert_eq!(decoded.protocols, vec!["/ipfs/id/1.0.0".to_owned()]);
+    }
+}
@@ -17,8 +17,8 @@
 
 use super::{schema, ProtobufDecodeError};
 
-use alloc::vec::Vec;
-use core::iter;
+use alloc::vec::{self, Vec};
+use core::{convert::TryFrom as _, iter};
 use prost::Message as _;
 
 /// Description of a storage proof request that can be sent to a peer.
@@ -55,6 +55,54 @@ pub fn build_storage_proof_request(
     iter::once(request_bytes)
 }
 
+/// Decodes a storage proof request received from a remote.
+// TODO: should have a more zero-cost API, but we're limited by the protobuf library for that
+pub fn decode_storage_proof_request(
+    request_bytes: &[u8],
+) -> Result<StorageProofRequestConfig<vec::IntoIter<Vec<u8>>>, DecodeStorageProofRequestError> {
+    let request = schema::Request::decode(request_bytes)
+        .map_err(ProtobufDecodeError)
+        .map_err(DecodeStorageProofRequestError::ProtobufDecode)?;
+
+    let request = match request.request {
+        Some(schema::request::Request::RemoteReadRequest(rq)) => rq,
+        _ => return Err(DecodeStorageProofRequestError::BadRequestTy),
+    };
+
+    Ok(StorageProofRequestConfig {
+        block_hash: <[u8; 32]>::try_from(&request.block[..])
+            .map_err(|_| DecodeStorageProofRequestError::BadBlockHash)?,
+        keys: request.keys.into_iter(),
+    })
+}
+
+/// Builds the bytes corresponding to a response to a storage proof request.
+///
+/// The proof consists in a list of node values of the storage trie. See the
+/// [`crate::trie::proof_encode`] module.
+pub fn build_storage_proof_response(
+    proof: impl Iterator<Item = impl AsRef<[u8]>>,
+) -> impl Iterator<Item = impl AsRef<[u8]>> {
+    // Note: while the API of this function allows for a zero-cost implementation, the protobuf
+    // library doesn't permit to avoid allocations.
+
+    let response = schema::Response {
+        response: Some(schema::response::Response::RemoteReadResponse(
+            schema::RemoteReadResponse {
+                proof: super::encode_proof(proof),
+            },
+        )),
+    };
+
+    let response_bytes = {
+        let mut buf = Vec::with_capacity(response.encoded_len());
+        response.encode(&mut buf).unwrap();
+        buf
+    };
+
+    iter::once(response_bytes)
+}
+
 /// Decodes a response to a storage proof request.
 // TODO: should have a more zero-cost API, but we're limited by the protobuf library for that
 pub fn decode_storage_proof_response(
@@ -88,6 +136,17 @@ pub fn decode_storage_proof_response(
     Ok(decoded)
 }
 
+/// Error potentially returned by [`decode_storage_proof_request`].
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum DecodeStorageProofRequestError {
+    /// Error while decoding the protobuf encoding.
+    ProtobufDecode(ProtobufDecodeError),
+    /// Request isn't a storage proof request.
+    BadRequestTy,
+    /// Block hash in the request doesn't have the correct length.
+    BadBlockHash,
+}
+
 /// Error potentially returned by [`decode_storage_proof_response`].
 #[derive(Debug, derive_more::Display, Clone)]
 pub enum DecodeStorageProofResponseError {
@@ -98,3 +157,37 @@ pub enum DecodeStorageProofResponseError {
     /// Failed to decode response as a storage proof.
     ProofDecodeError,
 }
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn request_and_response_round_trip() {
+        let request = super::build_storage_proof_request(super::StorageProofRequestConfig {
+            block_hash: [0xaa; 32],
+            keys: [&b"foo"[..], &b"bar"[..]].iter(),
+        })
+        .fold(Vec::new(), |mut a, b| {
+            a.extend_from_slice(b.as_ref());
+            a
+        });
+
+        let decoded = super::decode_storage_proof_request(&request).unwrap();
+        assert_eq!(decoded.block_hash, [0xaa; 32]);
+        assert_eq!(
+            decoded.keys.collect::<Vec<_>>(),
+            vec![b"foo".to_vec(), b"bar".to_vec()]
+        );
+
+        let response = super::build_storage_proof_response([&[1, 2, 3][..], &[4][..]].iter()).fold(
+            Vec::new(),
+            |mut a, b| {
+                a.extend_from_slice(b.as_ref());
+                a
+            },
+        );
+        assert_eq!(
+            super::decode_storage_proof_response(&response).unwrap(),
+            vec![vec![1, 2, 3], vec![4]]
+        );
+    }
+}
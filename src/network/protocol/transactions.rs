@@ -0,0 +1,94 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Transactions notifications protocol.
+//!
+//! Nodes gossip the transactions they are aware of to their peers through notifications
+//! substreams. Each notification contains a list of transactions.
+
+use alloc::vec::Vec;
+use core::iter;
+use nom::Finish as _;
+
+/// Turns a list of SCALE-encoded transactions into a transactions notification ready to be sent
+/// over the wire.
+///
+/// This function returns an iterator of buffers. The encoded message consists in the
+/// concatenation of the buffers.
+pub fn encode_transactions_notification<'a>(
+    transactions: impl ExactSizeIterator<Item = &'a [u8]> + 'a,
+) -> impl Iterator<Item = impl AsRef<[u8]> + 'a> + 'a {
+    let num_transactions = transactions.len();
+
+    iter::once(either::Left(crate::util::encode_scale_compact_usize(
+        num_transactions,
+    )))
+    .chain(transactions.flat_map(|transaction| {
+        iter::once(either::Left(crate::util::encode_scale_compact_usize(
+            transaction.len(),
+        )))
+        .chain(iter::once(either::Right(transaction)))
+    }))
+}
+
+/// Decodes a transactions notification.
+///
+/// On success, returns the list of SCALE-encoded transactions found in the notification.
+pub fn decode_transactions_notification(
+    bytes: &[u8],
+) -> Result<Vec<&[u8]>, DecodeTransactionsNotificationError> {
+    let result: Result<_, nom::error::Error<_>> = nom::combinator::all_consuming(
+        nom::combinator::flat_map(crate::util::nom_scale_compact_usize, |num_elems| {
+            nom::multi::many_m_n(num_elems, num_elems, crate::util::nom_bytes_decode)
+        }),
+    )(bytes)
+    .finish();
+
+    match result {
+        Ok((_, transactions)) => Ok(transactions),
+        Err(err) => Err(DecodeTransactionsNotificationError(err.code)),
+    }
+}
+
+/// Error potentially returned by [`decode_transactions_notification`].
+#[derive(Debug, derive_more::Display)]
+#[display(fmt = "Failed to decode a transactions notification")]
+pub struct DecodeTransactionsNotificationError(nom::error::ErrorKind);
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn encode_decode() {
+        let transactions: [&[u8]; 3] = [&[1, 2, 3], &[], &[0xff; 70]];
+
+        let encoded = super::encode_transactions_notification(transactions.iter().copied()).fold(
+            Vec::new(),
+            |mut a, b| {
+                a.extend_from_slice(b.as_ref());
+                a
+            },
+        );
+
+        let decoded = super::decode_transactions_notification(&encoded).unwrap();
+        assert_eq!(decoded, transactions);
+    }
+
+    #[test]
+    fn decode_trailing_data() {
+        assert!(super::decode_transactions_notification(&[4, 0, 5]).is_err());
+    }
+}
@@ -0,0 +1,450 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Reputation of peers.
+//!
+//! The [`Reputations`] data structure keeps track of a score for each peer. This score starts at
+//! `0` and is lowered every time the peer misbehaves, for example by sending back an invalid
+//! proof or by not answering a request in time. See [`Penalty`].
+//!
+//! Over time, the score of each peer recovers and slowly gets back to `0`. If the score of a peer
+//! goes below [`Config::disconnect_threshold`], the API user is expected to disconnect from it.
+//! If it goes below [`Config::ban_threshold`], the peer is considered as banned for a duration of
+//! [`Config::ban_duration`], during which no connection with this peer should be established.
+//!
+//! At most [`Config::max_peers`] peers are tracked at the same time. When this limit is reached,
+//! the least misbehaving peer is forgotten in order to make space for a newly-reported one. This
+//! prevents peers that keep changing their identity from making the data structure grow without
+//! bound.
+//!
+//! This data structure is purely informative and doesn't perform any networking operation by
+//! itself.
+
+use crate::libp2p::PeerId;
+
+use alloc::vec::Vec;
+use core::{
+    cmp,
+    convert::TryFrom as _,
+    ops::{Add, Sub},
+    time::Duration,
+};
+use rand::{RngCore as _, SeedableRng as _};
+
+/// Configuration for a [`Reputations`].
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Seed for the randomness within the data structure.
+    pub randomness_seed: [u8; 32],
+
+    /// If the score of a peer goes below or equal to this value, it should be disconnected.
+    ///
+    /// Should be negative and superior to [`Config::ban_threshold`].
+    pub disconnect_threshold: i32,
+
+    /// If the score of a peer goes below or equal to this value, it is banned.
+    ///
+    /// Should be negative.
+    pub ban_threshold: i32,
+
+    /// Duration of a ban.
+    pub ban_duration: Duration,
+
+    /// Number of points by which the score of each peer gets closer to `0` every second.
+    pub recovery_per_second: u32,
+
+    /// Maximum number of peers whose score isn't `0` or that are banned to keep track of. A
+    /// value of 0 is treated as 1.
+    ///
+    /// When a peer is reported while this limit is reached, the peer that isn't banned and whose
+    /// score is the closest to `0` is forgotten. If all the peers are banned, the peer whose ban
+    /// expires the soonest is forgotten instead.
+    pub max_peers: usize,
+}
+
+/// Misbehaviour of a peer. See [`Reputations::report`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Penalty {
+    /// Peer has failed to answer a request in time.
+    RequestTimeout,
+    /// Peer has sent back a proof (storage proof, call proof, etc.) that couldn't be verified.
+    InvalidProof,
+    /// Peer has sent a block that failed verification.
+    BadBlock,
+    /// Peer has announced a block that has been considered as invalid by the API user.
+    InvalidBlockAnnounce,
+    /// Peer has gossiped a GrandPa commit or catch-up message whose signatures couldn't be
+    /// verified against the current authorities set.
+    InvalidGrandpaMessage,
+    /// Peer has violated the networking protocol, for example by sending a message that can't be
+    /// decoded.
+    ProtocolViolation,
+    /// Peer has gossiped a transaction that the runtime considers as invalid.
+    InvalidTransaction,
+}
+
+impl Penalty {
+    /// Returns the value to add to the score of a peer when this penalty is reported.
+    pub fn reputation_change(&self) -> i32 {
+        match self {
+            Penalty::RequestTimeout => -10,
+            Penalty::InvalidProof => -100,
+            Penalty::BadBlock => -200,
+            Penalty::InvalidBlockAnnounce => -100,
+            Penalty::InvalidGrandpaMessage => -200,
+            Penalty::ProtocolViolation => -200,
+            Penalty::InvalidTransaction => -50,
+        }
+    }
+}
+
+/// Outcome of [`Reputations::report`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReportOutcome {
+    /// The score of the peer is still above [`Config::disconnect_threshold`].
+    Nothing,
+    /// The score of the peer is below or equal to [`Config::disconnect_threshold`]. The peer
+    /// should be disconnected.
+    Disconnect,
+    /// The score of the peer is below or equal to [`Config::ban_threshold`]. The peer should be
+    /// disconnected, and is now banned until [`Config::ban_duration`] has elapsed.
+    Ban,
+}
+
+/// Collection of the scores of peers. See [the module-level documentation](..).
+pub struct Reputations<TNow> {
+    /// See [`Config::disconnect_threshold`].
+    disconnect_threshold: i32,
+    /// See [`Config::ban_threshold`].
+    ban_threshold: i32,
+    /// See [`Config::ban_duration`].
+    ban_duration: Duration,
+    /// See [`Config::recovery_per_second`].
+    recovery_per_second: u32,
+    /// See [`Config::max_peers`].
+    max_peers: usize,
+
+    /// Score of each peer. Peers whose score is `0` and that aren't banned are absent from this
+    /// container.
+    peers: hashbrown::HashMap<PeerId, PeerReputation<TNow>, ahash::RandomState>,
+}
+
+struct PeerReputation<TNow> {
+    /// Current score, as of [`PeerReputation::last_update`]. Always strictly negative, except
+    /// if the peer is banned.
+    score: i32,
+    /// Moment up to which the recovery of the score has been applied.
+    last_update: TNow,
+    /// If `Some`, the peer is banned until the given moment.
+    banned_until: Option<TNow>,
+}
+
+impl<TNow> Reputations<TNow>
+where
+    TNow: Clone + Add<Duration, Output = TNow> + Sub<TNow, Output = Duration> + Ord,
+{
+    /// Initializes a new empty collection.
+    pub fn new(config: Config) -> Self {
+        let mut randomness = rand_chacha::ChaCha20Rng::from_seed(config.randomness_seed);
+
+        Reputations {
+            disconnect_threshold: config.disconnect_threshold,
+            ban_threshold: config.ban_threshold,
+            ban_duration: config.ban_duration,
+            recovery_per_second: config.recovery_per_second,
+            max_peers: cmp::max(config.max_peers, 1),
+            peers: hashbrown::HashMap::with_hasher(ahash::RandomState::with_seeds(
+                randomness.next_u64(),
+                randomness.next_u64(),
+                randomness.next_u64(),
+                randomness.next_u64(),
+            )),
+        }
+    }
+
+    /// Lowers the score of the given peer according to the given penalty, and returns what should
+    /// be done with this peer.
+    ///
+    /// If [`ReportOutcome::Ban`] is returned, the peer is banned starting from `now`, even if it
+    /// was already banned beforehand.
+    pub fn report(&mut self, now: &TNow, peer_id: &PeerId, penalty: Penalty) -> ReportOutcome {
+        self.apply_recovery(now, peer_id);
+
+        if !self.peers.contains_key(peer_id) && self.peers.len() >= self.max_peers {
+            self.evict_one(now);
+        }
+
+        let entry = self
+            .peers
+            .entry(peer_id.clone())
+            .or_insert_with(|| PeerReputation {
+                score: 0,
+                last_update: now.clone(),
+                banned_until: None,
+            });
+
+        entry.score = entry.score.saturating_add(penalty.reputation_change());
+
+        if entry.score <= self.ban_threshold {
+            entry.banned_until = Some(now.clone() + self.ban_duration);
+            ReportOutcome::Ban
+        } else if entry.score <= self.disconnect_threshold {
+            ReportOutcome::Disconnect
+        } else {
+            ReportOutcome::Nothing
+        }
+    }
+
+    /// Returns the current score of the given peer. Returns `0` for unknown peers.
+    pub fn score(&mut self, now: &TNow, peer_id: &PeerId) -> i32 {
+        self.apply_recovery(now, peer_id);
+        self.peers.get(peer_id).map_or(0, |p| p.score)
+    }
+
+    /// Returns `true` if the given peer is currently banned.
+    pub fn is_banned(&mut self, now: &TNow, peer_id: &PeerId) -> bool {
+        self.apply_recovery(now, peer_id);
+        self.peers
+            .get(peer_id)
+            .and_then(|p| p.banned_until.as_ref())
+            .is_some()
+    }
+
+    /// Returns the list of peers whose score isn't `0` or that are banned, with their score and
+    /// whether they are banned.
+    pub fn iter(&mut self, now: &TNow) -> impl Iterator<Item = (&PeerId, i32, bool)> {
+        let peer_ids = self.peers.keys().cloned().collect::<Vec<_>>();
+        for peer_id in peer_ids {
+            self.apply_recovery(now, &peer_id);
+        }
+
+        self.peers
+            .iter()
+            .map(|(peer_id, p)| (peer_id, p.score, p.banned_until.is_some()))
+    }
+
+    /// Removes one peer from [`Reputations::peers`], as described in [`Config::max_peers`].
+    ///
+    /// The recovery is first applied to all the peers, which might remove some of them already.
+    fn evict_one(&mut self, now: &TNow) {
+        let peer_ids = self.peers.keys().cloned().collect::<Vec<_>>();
+        for peer_id in peer_ids {
+            self.apply_recovery(now, &peer_id);
+        }
+
+        if self.peers.len() < self.max_peers {
+            return;
+        }
+
+        let to_evict = self
+            .peers
+            .iter()
+            .min_by(|(_, a), (_, b)| match (&a.banned_until, &b.banned_until) {
+                (None, None) => b.score.cmp(&a.score),
+                (None, Some(_)) => cmp::Ordering::Less,
+                (Some(_), None) => cmp::Ordering::Greater,
+                (Some(a), Some(b)) => a.cmp(b),
+            })
+            .map(|(peer_id, _)| peer_id.clone())
+            .unwrap();
+        self.peers.remove(&to_evict);
+    }
+
+    /// Updates the score and ban status of the given peer according to the time that has passed
+    /// since the last update. Removes the peer from the list if it is back to its default state.
+    fn apply_recovery(&mut self, now: &TNow, peer_id: &PeerId) {
+        let entry = match self.peers.get_mut(peer_id) {
+            Some(e) => e,
+            None => return,
+        };
+
+        if matches!(entry.banned_until, Some(ref until) if *until <= *now) {
+            entry.banned_until = None;
+        }
+
+        if *now > entry.last_update && self.recovery_per_second != 0 {
+            let elapsed = now.clone() - entry.last_update.clone();
+            let recovery = elapsed
+                .as_millis()
+                .saturating_mul(u128::from(self.recovery_per_second))
+                / 1000;
+            let recovery = i32::try_from(recovery).unwrap_or(i32::MAX);
+
+            if recovery >= entry.score.saturating_abs() {
+                entry.score = 0;
+                entry.last_update = now.clone();
+            } else if recovery != 0 {
+                // Only advance `last_update` by the time that corresponds to the points that
+                // have been recovered, in order to not lose fractions of points.
+                entry.score += recovery;
+                entry.last_update = entry.last_update.clone()
+                    + Duration::from_millis(
+                        u64::try_from(recovery).unwrap() * 1000
+                            / u64::from(self.recovery_per_second),
+                    );
+            }
+        }
+
+        if entry.score == 0 && entry.banned_until.is_none() {
+            self.peers.remove(peer_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, Penalty, ReportOutcome, Reputations};
+    use crate::libp2p::peer_id::{PeerId, PublicKey};
+    use core::time::Duration;
+
+    fn reputations() -> Reputations<Duration> {
+        Reputations::new(Config {
+            randomness_seed: [0; 32],
+            disconnect_threshold: -200,
+            ban_threshold: -500,
+            ban_duration: Duration::from_secs(60),
+            recovery_per_second: 10,
+            max_peers: 3,
+        })
+    }
+
+    fn peer_id(n: u8) -> PeerId {
+        PeerId::from_public_key(&PublicKey::Ed25519([n; 32]))
+    }
+
+    #[test]
+    fn thresholds_and_recovery() {
+        let mut reputations = reputations();
+        let peer_id = PeerId::from_public_key(&PublicKey::Ed25519([1; 32]));
+        let now = Duration::from_secs(0);
+
+        assert_eq!(
+            reputations.report(&now, &peer_id, Penalty::InvalidProof),
+            ReportOutcome::Nothing
+        );
+        assert_eq!(
+            reputations.report(&now, &peer_id, Penalty::InvalidProof),
+            ReportOutcome::Disconnect
+        );
+        assert_eq!(reputations.score(&now, &peer_id), -200);
+
+        // 10 points per second.
+        assert_eq!(
+            reputations.score(&Duration::from_millis(5500), &peer_id),
+            -145
+        );
+        assert_eq!(reputations.score(&Duration::from_secs(6), &peer_id), -140);
+        assert_eq!(reputations.score(&Duration::from_secs(100), &peer_id), 0);
+        assert_eq!(reputations.iter(&Duration::from_secs(100)).count(), 0);
+    }
+
+    #[test]
+    fn ban_expires() {
+        let mut reputations = reputations();
+        let peer_id = PeerId::from_public_key(&PublicKey::Ed25519([1; 32]));
+        let now = Duration::from_secs(0);
+
+        assert_eq!(
+            reputations.report(&now, &peer_id, Penalty::BadBlock),
+            ReportOutcome::Disconnect
+        );
+        assert_eq!(
+            reputations.report(&now, &peer_id, Penalty::BadBlock),
+            ReportOutcome::Disconnect
+        );
+        assert_eq!(
+            reputations.report(&now, &peer_id, Penalty::BadBlock),
+            ReportOutcome::Ban
+        );
+
+        assert!(reputations.is_banned(&Duration::from_secs(59), &peer_id));
+        assert!(!reputations.is_banned(&Duration::from_secs(60), &peer_id));
+    }
+
+    #[test]
+    fn least_misbehaving_peer_evicted() {
+        let mut reputations = reputations();
+        let now = Duration::from_secs(0);
+
+        reputations.report(&now, &peer_id(1), Penalty::BadBlock);
+        reputations.report(&now, &peer_id(2), Penalty::RequestTimeout);
+        reputations.report(&now, &peer_id(3), Penalty::InvalidProof);
+        assert_eq!(reputations.iter(&now).count(), 3);
+
+        // Reporting a new peer evicts the one whose score is the closest to 0.
+        reputations.report(&now, &peer_id(4), Penalty::InvalidProof);
+        assert_eq!(reputations.iter(&now).count(), 3);
+        assert_eq!(reputations.score(&now, &peer_id(2)), 0);
+        assert_eq!(reputations.score(&now, &peer_id(1)), -200);
+        assert_eq!(reputations.score(&now, &peer_id(3)), -100);
+        assert_eq!(reputations.score(&now, &peer_id(4)), -100);
+
+        // Reporting a known peer doesn't evict anything.
+        reputations.report(&now, &peer_id(3), Penalty::InvalidProof);
+        assert_eq!(reputations.iter(&now).count(), 3);
+        assert_eq!(reputations.score(&now, &peer_id(4)), -100);
+    }
+
+    #[test]
+    fn recovered_peers_evicted_first() {
+        let mut reputations = reputations();
+
+        reputations.report(
+            &Duration::from_secs(0),
+            &peer_id(1),
+            Penalty::RequestTimeout,
+        );
+        reputations.report(&Duration::from_secs(0), &peer_id(2), Penalty::BadBlock);
+        reputations.report(&Duration::from_secs(0), &peer_id(3), Penalty::BadBlock);
+
+        // After 5 seconds, peer 1 has fully recovered and peers 2 and 3 are at -150.
+        let now = Duration::from_secs(5);
+        reputations.report(&now, &peer_id(4), Penalty::InvalidProof);
+        assert_eq!(reputations.score(&now, &peer_id(2)), -150);
+        assert_eq!(reputations.score(&now, &peer_id(3)), -150);
+        assert_eq!(reputations.score(&now, &peer_id(4)), -100);
+    }
+
+    #[test]
+    fn banned_peers_evicted_last() {
+        let mut reputations = reputations();
+
+        // Bans peers 1 and 2, peer 1 being banned before peer 2.
+        for (peer, now) in [(1, 0), (2, 1)] {
+            for _ in 0..3 {
+                reputations.report(&Duration::from_secs(now), &peer_id(peer), Penalty::BadBlock);
+            }
+        }
+        reputations.report(&Duration::from_secs(1), &peer_id(3), Penalty::BadBlock);
+
+        // The only peer that isn't banned is evicted.
+        let now = Duration::from_secs(1);
+        reputations.report(&now, &peer_id(4), Penalty::BadBlock);
+        assert_eq!(reputations.score(&now, &peer_id(3)), 0);
+        assert!(reputations.is_banned(&now, &peer_id(1)));
+        assert!(reputations.is_banned(&now, &peer_id(2)));
+
+        // Banning peer 4 makes all the peers banned. The one whose ban expires first is evicted.
+        reputations.report(&now, &peer_id(4), Penalty::BadBlock);
+        reputations.report(&now, &peer_id(4), Penalty::BadBlock);
+        assert!(reputations.is_banned(&now, &peer_id(4)));
+        reputations.report(&now, &peer_id(5), Penalty::BadBlock);
+        assert!(!reputations.is_banned(&now, &peer_id(1)));
+        assert!(reputations.is_banned(&now, &peer_id(2)));
+        assert!(reputations.is_banned(&now, &peer_id(4)));
+    }
+}
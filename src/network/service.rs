@@ -20,18 +20,19 @@ use crate::libp2p::{
     peers::{self, QueueNotificationError},
     PeerId,
 };
-use crate::network::{kademlia, protocol};
+use crate::network::{kademlia, peerset, protocol, reputation};
 use crate::util;
 
 use alloc::{
-    collections::BTreeSet,
+    collections::{BTreeSet, VecDeque},
     format,
     string::{String, ToString as _},
     vec::Vec,
 };
 use core::{
+    cmp,
     convert::TryFrom as _,
-    fmt, iter, mem,
+    fmt, iter,
     num::NonZeroUsize,
     ops::{Add, Sub},
     task::Poll,
@@ -86,6 +87,44 @@ pub struct Config {
     /// and must be aborted.
     pub handshake_timeout: Duration,
 
+    /// Interval between two consecutive outgoing pings on each connection. Pings are used to
+    /// detect dead connections, and also keep connections alive through NATs and firewalls.
+    pub ping_interval: Duration,
+
+    /// Time after which an outgoing ping is considered failed, in which case the connection is
+    /// shut down.
+    pub ping_timeout: Duration,
+
+    /// If `Some`, connections that don't have any substream open other than the ping substreams
+    /// for this amount of time are shut down. If `None`, idle connections are kept alive
+    /// indefinitely.
+    ///
+    /// Note that a connection with a peer that has an open block announces substream is never
+    /// considered as idle.
+    pub idle_connection_timeout: Option<Duration>,
+
+    /// Maximum number of connections, both incoming and outgoing, handshaking or established.
+    /// Once this limit is reached, incoming connections are refused and no new dialing attempt
+    /// is started.
+    pub max_connections: usize,
+
+    /// Maximum number of connections, handshaking or established, whose remote has the same IP
+    /// address. Incoming connections that would exceed this limit are refused.
+    pub max_incoming_connections_per_ip: usize,
+
+    /// Maximum number of dialing attempts that can be in progress at the same time. Prevents
+    /// the local node from dialing a large number of addresses at once, for example when all the
+    /// bootnodes are unreachable.
+    pub max_simultaneous_dials: NonZeroUsize,
+
+    /// Amount of time during which an address isn't dialed again after a failed dialing
+    /// attempt. Doubled after each consecutive failure, up to [`Config::dial_backoff_max`].
+    pub dial_backoff_initial: Duration,
+
+    /// Maximum amount of time during which an address isn't dialed again after a failed dialing
+    /// attempt. See [`Config::dial_backoff_initial`].
+    pub dial_backoff_max: Duration,
+
     /// Number of events that can be buffered internally before connections are back-pressured.
     ///
     /// A good default value is 64.
@@ -100,6 +139,28 @@ pub struct Config {
     /// This value is important if [`ChainNetwork::next_event`] is called at a slower than the
     /// calls to [`ChainNetwork::read_write`] generate events.
     pub pending_api_events_buffer_size: NonZeroUsize,
+
+    /// Configuration of the reputation system. See [`ChainNetwork::report_peer`].
+    pub reputation: reputation::Config,
+
+    /// Number of bytes the remote is allowed to send at once on a substream used to receive the
+    /// response to an outgoing request.
+    ///
+    /// A large value speeds up the download of large responses, at the cost of more data
+    /// potentially buffered in memory. A good default value is 1MiB.
+    pub request_receive_window: u64,
+
+    /// Number of bytes the remote is allowed to send at once on a notifications substream.
+    ///
+    /// A good default value is 256kiB, which is the minimum allowed by the yamux protocol.
+    pub notifications_receive_window: u64,
+
+    /// Maximum value, per connection, of the sum of the maximum response sizes of all the
+    /// outgoing requests in progress. Starting a request that would exceed this limit fails with
+    /// a `Backpressure` error, and the request should be sent to a different peer or later.
+    ///
+    /// A single request is always allowed, even if its maximum response size exceeds this limit.
+    pub max_pending_response_bytes: usize,
 }
 
 /// Configuration for a specific overlay network.
@@ -119,9 +180,48 @@ pub struct ChainConfig {
     /// If `Some`, the chain uses the GrandPa networking protocol.
     pub grandpa_protocol_config: Option<GrandpaState>,
 
-    pub in_slots: u32,
+    /// Number of peers of each category the local node maintains a block announces substream
+    /// with. See [`ChainNetwork::slots`].
+    pub slots: peerset::SlotsConfig,
+
+    /// If `true`, storage proof and call proof requests sent by remotes are accepted and
+    /// reported through [`Event::StorageProofRequestIn`] and [`Event::CallProofRequestIn`].
+    /// Should only be `true` if the API user has access to the storage of blocks.
+    pub serve_light_requests: bool,
+
+    /// If `true`, GrandPa warp sync requests sent by remotes are accepted and reported through
+    /// [`Event::GrandpaWarpSyncRequestIn`]. Should only be `true` if the API user is capable of
+    /// generating warp sync proofs. See [`crate::finality::grandpa::warp_sync_server`].
+    pub serve_grandpa_warp_sync: bool,
+
+    /// If `true`, Kademlia provider records announced by remotes are stored, and Kademlia "get
+    /// providers" requests sent by remotes are answered with them. Other kinds of Kademlia
+    /// requests are refused. See also [`ChainNetwork::kademlia_add_provider`].
+    pub serve_kademlia_providers: bool,
+
+    /// If `true`, the chain is a relay chain and the local node takes part in its collation
+    /// protocol. Collation substreams opened by remotes are accepted, and collation substreams
+    /// towards remotes can be opened with [`ChainNetwork::set_collation_substream_desired`].
+    /// Messages received on collation substreams are reported through
+    /// [`Event::CollationMessage`].
+    ///
+    /// Contrary to the other protocols, the collation protocol is independent of whether the
+    /// chain is connected to a peer (see [`Event::ChainConnected`]).
+    ///
+    /// The name of the collation protocol doesn't depend on the chain. As such, at most one
+    /// chain can have this flag set to `true`.
+    pub collation_protocol: bool,
+
+    /// Maximum sizes of the responses to the requests sent to the peers of this chain.
+    ///
+    /// Responses exceeding these limits are considered as failed requests.
+    pub max_response_sizes: ResponseSizeLimits,
 
-    pub out_slots: u32,
+    /// If `true`, only the nodes of [`ChainConfig::bootstrap_nodes`] (the so-called "reserved
+    /// peers") are considered as peers of this chain. Substreams opened by any other node are
+    /// refused, and the nodes discovered through [`ChainNetwork::kademlia_discovery_round`] are
+    /// ignored.
+    pub reserved_only: bool,
 
     /// Hash of the best block according to the local node.
     pub best_hash: [u8; 32],
@@ -143,10 +243,91 @@ pub struct GrandpaState {
     pub commit_finalized_height: u32,
 }
 
+/// Maximum sizes, in bytes, of the responses to the requests of a chain. See
+/// [`ChainConfig::max_response_sizes`].
+///
+/// The default values are appropriate for most chains. Chains whose storage contains very large
+/// values, such as some parachains, might need larger limits for the light protocol.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ResponseSizeLimits {
+    /// Maximum size of a response to a blocks request.
+    pub blocks: usize,
+    /// Maximum size of a response to a storage proof or call proof request.
+    pub light: usize,
+    /// Maximum size of a response to a Kademlia request.
+    pub kademlia: usize,
+    /// Maximum size of a response to a GrandPa warp sync request.
+    pub grandpa_warp_sync: usize,
+}
+
+impl Default for ResponseSizeLimits {
+    fn default() -> Self {
+        ResponseSizeLimits {
+            blocks: 16 * 1024 * 1024,
+            light: 10 * 1024 * 1024,
+            kademlia: 1024 * 1024,
+            grandpa_warp_sync: 128 * 1024 * 1024, // TODO: this is way too large at the moment ; see https://github.com/paritytech/substrate/pull/8578
+        }
+    }
+}
+
 /// Identifier of a pending connection requested by the network through a [`StartConnect`].
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct PendingId(usize);
 
+/// Health of a bootnode of a chain. See [`ChainNetwork::bootnodes_health`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BootnodeHealth {
+    /// The bootnode hasn't been reached yet.
+    Unknown,
+    /// The bootnode has been reached and has confirmed that it belongs to the chain.
+    Healthy,
+    /// The most recent dialing attempts towards the bootnode have failed.
+    Unreachable {
+        /// Number of consecutive failed dialing attempts towards any address of the bootnode.
+        consecutive_failures: u32,
+    },
+    /// The bootnode has been reached, but uses a different genesis block hash than the local
+    /// node. It is no longer dialed for this chain.
+    WrongGenesis,
+}
+
+/// Entry of the list returned by [`ChainNetwork::metrics`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtocolMetrics {
+    /// Name of the request-response protocol, as negotiated on the wire.
+    pub protocol_name: String,
+    /// Index of the chain the protocol belongs to, or `None` for protocols that aren't specific
+    /// to a chain.
+    pub chain_index: Option<usize>,
+    /// Number of requests that have been sent using this protocol, including failed ones.
+    pub requests_sent: u64,
+    /// Number of requests that have failed, no matter the reason.
+    pub requests_failed: u64,
+    /// Total number of bytes of request payloads sent using this protocol.
+    pub bytes_sent: u64,
+    /// Total number of bytes of response payloads received using this protocol.
+    pub bytes_received: u64,
+    /// Median of the time it took to receive the most recent successful responses, or `None`
+    /// if no request has succeeded yet.
+    pub latency_p50: Option<Duration>,
+    /// 95th percentile of the time it took to receive the most recent successful responses, or
+    /// `None` if no request has succeeded yet.
+    pub latency_p95: Option<Duration>,
+}
+
+/// Entry of the list returned by [`ChainNetwork::address_book`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressBookEntry {
+    /// Identity of the node.
+    pub peer_id: PeerId,
+    /// Address that has been dialed in order to reach the node.
+    pub address: multiaddr::Multiaddr,
+    /// Number of consecutive failed dialing attempts towards this address. `0` if the most
+    /// recent attempt has succeeded.
+    pub consecutive_failures: u32,
+}
+
 /// Data structure containing the list of all connections, pending or not, and their latest known
 /// state. See also [the module-level documentation](..).
 pub struct ChainNetwork<TNow> {
@@ -156,6 +337,21 @@ pub struct ChainNetwork<TNow> {
     /// See [`Config::handshake_timeout`].
     handshake_timeout: Duration,
 
+    /// See [`Config::max_connections`].
+    max_connections: usize,
+
+    /// See [`Config::max_incoming_connections_per_ip`].
+    max_incoming_connections_per_ip: usize,
+
+    /// See [`Config::max_simultaneous_dials`].
+    max_simultaneous_dials: NonZeroUsize,
+
+    /// See [`Config::dial_backoff_initial`].
+    dial_backoff_initial: Duration,
+
+    /// See [`Config::dial_backoff_max`].
+    dial_backoff_max: Duration,
+
     /// Extra fields protected by a `Mutex` and that relate to the logic in
     /// [`ChainNetwork::next_event`]. Must only be locked within that method and is kept locked
     /// throughout that method.
@@ -164,6 +360,11 @@ pub struct ChainNetwork<TNow> {
     /// Extra fields protected by a `Mutex` and that are briefly accessed.
     ephemeral_guarded: Mutex<EphemeralGuarded<TNow>>,
 
+    /// For each request-response protocol, the statistics about the requests that have been
+    /// sent. Indices are the same as the ones of the request-response protocols passed to the
+    /// underlying [`peers::Peers`].
+    metrics: Mutex<Vec<RequestsMetrics>>,
+
     /// Number of chains. Equal to the length of [`EphemeralGuarded::chains`].
     num_chains: usize,
 
@@ -189,6 +390,25 @@ struct NextEventGuarded {
     open_chains: hashbrown::HashSet<(PeerId, usize), ahash::RandomState>,
 }
 
+/// See [`ChainNetwork::metrics`].
+struct RequestsMetrics {
+    /// See [`ProtocolMetrics::protocol_name`].
+    protocol_name: String,
+    /// See [`ProtocolMetrics::chain_index`].
+    chain_index: Option<usize>,
+    /// See [`ProtocolMetrics::requests_sent`].
+    requests_sent: u64,
+    /// See [`ProtocolMetrics::requests_failed`].
+    requests_failed: u64,
+    /// See [`ProtocolMetrics::bytes_sent`].
+    bytes_sent: u64,
+    /// See [`ProtocolMetrics::bytes_received`].
+    bytes_received: u64,
+    /// Time it took to receive the responses of the most recent successful requests, from the
+    /// oldest to the newest. Contains at most [`LATENCY_SAMPLES`] elements.
+    latencies: VecDeque<Duration>,
+}
+
 /// See [`ChainNetwork::ephemeral_guarded`].
 struct EphemeralGuarded<TNow> {
     /// For each peer, the number of pending attempts.
@@ -210,37 +430,233 @@ struct EphemeralGuarded<TNow> {
     // TODO: ideally we'd use a BTreeSet to optimize, but multiaddr has no min or max value
     potential_addresses: hashbrown::HashMap<PeerId, Vec<multiaddr::Multiaddr>, ahash::RandomState>,
 
+    /// Addresses that have been dialed in the past, and the outcome of the dialing attempts
+    /// towards them. See [`ChainNetwork::address_book`].
+    ///
+    /// Entries are removed after [`ADDRESS_BOOK_MAX_CONSECUTIVE_FAILURES`] failures in a row.
+    /// Never contains more than [`ADDRESS_BOOK_MAX_ENTRIES`] entries.
+    address_book: hashbrown::HashMap<
+        (PeerId, multiaddr::Multiaddr),
+        AddressBookEntryState<TNow>,
+        ahash::RandomState,
+    >,
+
     /// For each item in [`Config::chains`], the corresponding chain state.
     ///
     /// The `Vec` always has the same length as [`Config::chains`].
     chains: Vec<EphemeralGuardedChain>,
+
+    /// Slots attributed to peers, for each chain of [`EphemeralGuarded::chains`].
+    ///
+    /// Peers with an outbound slot are always marked as desired in the underlying state machine.
+    /// Peers with an inbound slot are connected to the local node and have opened a block
+    /// announces substream with it.
+    peerset: peerset::Peerset,
+
+    /// Score of each peer. Banned peers are never dialed, and their substreams are refused.
+    reputations: reputation::Reputations<TNow>,
+
+    /// Kademlia provider records announced by remotes, indexed by chain index and provided key.
+    /// Only filled for chains where [`ChainConfig::serve_kademlia_providers`] is `true`.
+    ///
+    /// Never contains more than [`MAX_PROVIDER_RECORD_KEYS`] keys, and never more than
+    /// [`MAX_PROVIDERS_PER_KEY`] records per key.
+    provider_records:
+        hashbrown::HashMap<(usize, Vec<u8>), Vec<ProviderRecord<TNow>>, ahash::RandomState>,
+}
+
+/// See [`EphemeralGuarded::provider_records`].
+struct ProviderRecord<TNow> {
+    /// Identity of the node that provides the key.
+    peer_id: PeerId,
+    /// Addresses of the provider, as announced by it.
+    addresses: Vec<multiaddr::Multiaddr>,
+    /// Moment after which the record must be discarded.
+    expiration: TNow,
 }
 
 struct EphemeralGuardedChain {
     /// See [`ChainConfig`].
     chain_config: ChainConfig,
 
-    /// List of peers with an inbound slot attributed to them. Only includes peers the local node
-    /// is connected to and who have opened a block announces substream with the local node.
-    in_peers: hashbrown::HashSet<PeerId, ahash::RandomState>,
+    /// List of peers found in [`ChainConfig::bootstrap_nodes`]. Always empty if
+    /// [`ChainConfig::reserved_only`] is `false`.
+    reserved_peers: Vec<peer_id::PeerId>,
+
+    /// List of peers found in [`ChainConfig::bootstrap_nodes`], and their health. See
+    /// [`ChainNetwork::bootnodes_health`].
+    bootnodes: Vec<(peer_id::PeerId, BootnodeHealth)>,
+
+    /// Peers whose block announces handshake has indicated that they belong to this chain.
+    /// Used to filter the entries returned by [`ChainNetwork::address_book`].
+    ///
+    /// Only contains peers that have at least one entry in [`EphemeralGuarded::address_book`].
+    members: hashbrown::HashSet<PeerId, ahash::RandomState>,
+}
+
+/// See [`EphemeralGuarded::address_book`].
+struct AddressBookEntryState<TNow> {
+    /// Number of consecutive failed dialing attempts towards this address. `0` if the most
+    /// recent attempt has succeeded.
+    consecutive_failures: u32,
+
+    /// If `Some`, the address must not be dialed again before this moment. Set after a failed
+    /// dialing attempt.
+    dial_not_before: Option<TNow>,
+}
+
+impl<TNow> EphemeralGuarded<TNow> {
+    /// Returns the entry of [`EphemeralGuarded::address_book`] corresponding to the given
+    /// address, inserting a new one if necessary.
+    ///
+    /// If the address book is full, the entry with the highest number of consecutive failures is
+    /// removed in order to make space for the new one.
+    fn address_book_entry(
+        &mut self,
+        peer_id: &PeerId,
+        address: &multiaddr::Multiaddr,
+    ) -> &mut AddressBookEntryState<TNow> {
+        let key = (peer_id.clone(), address.clone());
+
+        if !self.address_book.contains_key(&key)
+            && self.address_book.len() >= ADDRESS_BOOK_MAX_ENTRIES
+        {
+            let to_evict = self
+                .address_book
+                .iter()
+                .max_by_key(|(_, state)| state.consecutive_failures)
+                .map(|(key, _)| key.clone())
+                .unwrap();
+            self.address_book_remove(&to_evict.0, &to_evict.1);
+        }
+
+        self.address_book
+            .entry(key)
+            .or_insert(AddressBookEntryState {
+                consecutive_failures: 0,
+                dial_not_before: None,
+            })
+    }
+
+    /// Inserts a record in [`EphemeralGuarded::provider_records`], replacing the existing record
+    /// of the same provider for the same key, if any.
+    ///
+    /// Expired records of this key are discarded. If the maximum number of records or keys is
+    /// reached, the new record is ignored.
+    fn insert_provider_record(
+        &mut self,
+        chain_index: usize,
+        key: Vec<u8>,
+        record: ProviderRecord<TNow>,
+        now: &TNow,
+    ) where
+        TNow: Ord,
+    {
+        if !self
+            .provider_records
+            .contains_key(&(chain_index, key.clone()))
+        {
+            // Make space by removing the keys whose records have all expired.
+            if self.provider_records.len() >= MAX_PROVIDER_RECORD_KEYS {
+                self.provider_records
+                    .retain(|_, records| records.iter().any(|r| r.expiration > *now));
+            }
+            if self.provider_records.len() >= MAX_PROVIDER_RECORD_KEYS {
+                return;
+            }
+        }
+
+        let records = self
+            .provider_records
+            .entry((chain_index, key))
+            .or_insert_with(Vec::new);
+        records.retain(|r| r.expiration > *now && r.peer_id != record.peer_id);
+        if records.len() < MAX_PROVIDERS_PER_KEY {
+            records.push(record);
+        }
+    }
+
+    /// Removes the given address from [`EphemeralGuarded::address_book`], if present.
+    ///
+    /// If this was the last known address of this peer, it is also removed from the members of
+    /// each chain.
+    fn address_book_remove(&mut self, peer_id: &PeerId, address: &multiaddr::Multiaddr) {
+        self.address_book
+            .remove(&(peer_id.clone(), address.clone()));
+
+        if !self.address_book.keys().any(|(p, _)| p == peer_id) {
+            for chain in &mut self.chains {
+                chain.members.remove(peer_id);
+            }
+        }
+    }
+
+    /// Returns `true` if at least one chain allows the given peer. See
+    /// [`EphemeralGuardedChain::is_peer_allowed`].
+    ///
+    /// Always returns `true` if there isn't any chain.
+    fn is_peer_allowed_any_chain(&self, peer_id: &PeerId) -> bool {
+        self.chains.is_empty() || self.chains.iter().any(|c| c.is_peer_allowed(peer_id))
+    }
+}
 
-    /// List of peers with an outbound slot attributed to them. Can include peers not connected to
-    /// the local node yet. The peers in this list are always marked as desired in the underlying
-    /// state machine.
-    out_peers: hashbrown::HashSet<PeerId, ahash::RandomState>,
+impl EphemeralGuardedChain {
+    /// Returns `true` if the given peer is allowed to be a peer of this chain, in other words
+    /// if the chain isn't in reserved-only mode or if the peer is a reserved peer.
+    fn is_peer_allowed(&self, peer_id: &peer_id::PeerId) -> bool {
+        !self.chain_config.reserved_only || self.reserved_peers.iter().any(|p| p == peer_id)
+    }
 }
 
 // Update this when a new request response protocol is added.
 const REQUEST_RESPONSE_PROTOCOLS_PER_CHAIN: usize = 4;
 // Update this when a new notifications protocol is added.
-const NOTIFICATIONS_PROTOCOLS_PER_CHAIN: usize = 3;
+const NOTIFICATIONS_PROTOCOLS_PER_CHAIN: usize = 4;
+/// Offset of the collation protocol within the notifications protocols of each chain. The
+/// collation protocol is always the last notifications protocol of the chain.
+const COLLATION_PROTOCOL_OFFSET: usize = NOTIFICATIONS_PROTOCOLS_PER_CHAIN - 1;
+/// Name of the collation protocol. See [`ChainConfig::collation_protocol`].
+const COLLATION_PROTOCOL_NAME: &str = "/polkadot/collation/1";
+/// Number of dialing failures in a row after which an address is removed from the address book.
+const ADDRESS_BOOK_MAX_CONSECUTIVE_FAILURES: u32 = 5;
+/// Maximum number of entries in the address book. Entries with the most consecutive dialing
+/// failures are evicted first.
+const ADDRESS_BOOK_MAX_ENTRIES: usize = 2048;
+/// Maximum number of the listen addresses reported by a remote through the identify protocol
+/// that are added to the potential addresses of this remote.
+const IDENTIFY_MAX_LISTEN_ADDRS: usize = 16;
+/// Duration after which a Kademlia provider record is discarded unless it is announced again.
+const PROVIDER_RECORD_TTL: Duration = Duration::from_secs(24 * 3600);
+/// Maximum number of keys for which provider records are stored.
+const MAX_PROVIDER_RECORD_KEYS: usize = 1024;
+/// Maximum number of provider records stored for each key.
+const MAX_PROVIDERS_PER_KEY: usize = 20;
+/// Maximum number of addresses stored for each provider record.
+const MAX_PROVIDER_ADDRESSES: usize = 8;
+/// Number of response times kept per request-response protocol in order to calculate the
+/// latency percentiles reported by [`ChainNetwork::metrics`].
+const LATENCY_SAMPLES: usize = 128;
 
 impl<TNow> ChainNetwork<TNow>
 where
     TNow: Clone + Add<Duration, Output = TNow> + Sub<TNow, Output = Duration> + Ord,
 {
     /// Initializes a new [`ChainNetwork`].
+    ///
+    /// # Panic
+    ///
+    /// Panics if more than one chain has [`ChainConfig::collation_protocol`] set to `true`.
+    ///
     pub fn new(config: Config) -> Self {
+        assert!(
+            config
+                .chains
+                .iter()
+                .filter(|chain| chain.collation_protocol)
+                .count()
+                <= 1
+        );
+
         // The order of protocols here is important, as it defines the values of `protocol_index`
         // to pass to libp2p or that libp2p produces.
         let notification_protocols = config
@@ -271,6 +687,23 @@ where
                         max_notification_size: 1024 * 1024,
                     })
                 })
+                .chain({
+                    // Similarly, a collation protocol is registered for all chains, but
+                    // substreams are only accepted if `collation_protocol` is `true`. Since the
+                    // name of the collation protocol is the same for all chains, the chains that
+                    // don't take part in it use a chain-specific name instead, in order to not
+                    // conflict with the chain that does.
+                    iter::once(peers::NotificationProtocolConfig {
+                        protocol_name: if chain.collation_protocol {
+                            COLLATION_PROTOCOL_NAME.to_string()
+                        } else {
+                            format!("/{}/collation-unused/1", chain.protocol_id)
+                        },
+                        fallback_protocol_names: Vec::new(),
+                        max_handshake_size: 4,
+                        max_notification_size: 64 * 1024, // TODO: arbitrary
+                    })
+                })
             })
             .collect();
 
@@ -288,7 +721,7 @@ where
             iter::once(peers::ConfigRequestResponse {
                 name: format!("/{}/sync/2", chain.protocol_id),
                 inbound_config: peers::ConfigRequestResponseIn::Payload { max_size: 1024 },
-                max_response_size: 16 * 1024 * 1024,
+                max_response_size: chain.max_response_sizes.blocks,
                 // TODO: make this configurable
                 inbound_allowed: false,
                 timeout: Duration::from_secs(6),
@@ -298,29 +731,43 @@ where
                 inbound_config: peers::ConfigRequestResponseIn::Payload {
                     max_size: 1024 * 512,
                 },
-                max_response_size: 10 * 1024 * 1024,
-                // TODO: make this configurable
-                inbound_allowed: false,
+                max_response_size: chain.max_response_sizes.light,
+                inbound_allowed: chain.serve_light_requests,
                 timeout: Duration::from_secs(6),
             }))
             .chain(iter::once(peers::ConfigRequestResponse {
                 name: format!("/{}/kad", chain.protocol_id),
                 inbound_config: peers::ConfigRequestResponseIn::Payload { max_size: 1024 },
-                max_response_size: 1024 * 1024,
-                // TODO: `false` here means we don't insert ourselves in the DHT, which is the polite thing to do for as long as Kad isn't implemented
-                inbound_allowed: false,
+                max_response_size: chain.max_response_sizes.kademlia,
+                // TODO: only provider records are supported at the moment; `false` means we don't insert ourselves in the DHT, which is the polite thing to do for as long as Kad isn't fully implemented
+                inbound_allowed: chain.serve_kademlia_providers,
                 timeout: Duration::from_secs(6),
             }))
             .chain(iter::once(peers::ConfigRequestResponse {
                 name: format!("/{}/sync/warp", chain.protocol_id),
                 inbound_config: peers::ConfigRequestResponseIn::Payload { max_size: 32 },
-                max_response_size: 128 * 1024 * 1024, // TODO: this is way too large at the moment ; see https://github.com/paritytech/substrate/pull/8578
-                // We don't support inbound warp sync requests (yet).
-                inbound_allowed: false,
+                max_response_size: chain.max_response_sizes.grandpa_warp_sync,
+                inbound_allowed: chain.serve_grandpa_warp_sync,
                 timeout: Duration::from_secs(6),
             }))
         }))
-        .collect();
+        .collect::<Vec<_>>();
+
+        let metrics = request_response_protocols
+            .iter()
+            .enumerate()
+            .map(|(protocol_index, protocol)| RequestsMetrics {
+                protocol_name: protocol.name.clone(),
+                chain_index: protocol_index
+                    .checked_sub(1)
+                    .map(|i| i / REQUEST_RESPONSE_PROTOCOLS_PER_CHAIN),
+                requests_sent: 0,
+                requests_failed: 0,
+                bytes_sent: 0,
+                bytes_received: 0,
+                latencies: VecDeque::with_capacity(LATENCY_SAMPLES),
+            })
+            .collect();
 
         let mut randomness = rand_chacha::ChaCha20Rng::from_seed(config.randomness_seed);
         let inner_randomness_seed = randomness.sample(rand::distributions::Standard);
@@ -359,6 +806,12 @@ where
         };
 
         let mut initial_desired_substreams = BTreeSet::new();
+        let mut reserved_peers = (0..config.chains.len())
+            .map(|_| Vec::new())
+            .collect::<Vec<_>>();
+        let mut bootnodes = (0..config.chains.len())
+            .map(|_| Vec::new())
+            .collect::<Vec<_>>();
 
         for (node_index, (peer_id, multiaddr)) in config.known_nodes.into_iter().enumerate() {
             // Register membership of this peer on this chain.
@@ -367,7 +820,17 @@ where
                     continue;
                 }
 
+                if chain.reserved_only && !reserved_peers[chain_index].contains(&peer_id) {
+                    reserved_peers[chain_index].push(peer_id.clone());
+                }
+
+                if !bootnodes[chain_index].iter().any(|(p, _)| *p == peer_id) {
+                    bootnodes[chain_index].push((peer_id.clone(), BootnodeHealth::Unknown));
+                }
+
+                // The collation substream is only ever opened on demand.
                 for notifications_protocol in (0..NOTIFICATIONS_PROTOCOLS_PER_CHAIN)
+                    .filter(|n| *n != COLLATION_PROTOCOL_OFFSET)
                     .map(|n| n + NOTIFICATIONS_PROTOCOLS_PER_CHAIN * chain_index)
                 {
                     initial_desired_substreams.insert((peer_id.clone(), notifications_protocol));
@@ -382,34 +845,46 @@ where
         }
 
         let num_chains = config.chains.len();
+        let peerset = peerset::Peerset::new(peerset::Config {
+            randomness_seed: randomness.sample(rand::distributions::Standard),
+            chains: config.chains.iter().map(|chain| chain.slots).collect(),
+        });
+        let peers_capacity = config.peers_capacity;
         let chains = config
             .chains
             .into_iter()
-            .map(|chain| EphemeralGuardedChain {
-                in_peers: {
-                    let k0 = randomness.next_u64();
-                    let k1 = randomness.next_u64();
-                    let k2 = randomness.next_u64();
-                    let k3 = randomness.next_u64();
-                    hashbrown::HashSet::with_capacity_and_hasher(
-                        usize::try_from(chain.in_slots).unwrap_or(0),
-                        ahash::RandomState::with_seeds(k0, k1, k2, k3),
-                    )
-                },
-                out_peers: {
-                    let k0 = randomness.next_u64();
-                    let k1 = randomness.next_u64();
-                    let k2 = randomness.next_u64();
-                    let k3 = randomness.next_u64();
-                    hashbrown::HashSet::with_capacity_and_hasher(
-                        usize::try_from(chain.out_slots).unwrap_or(0),
-                        ahash::RandomState::with_seeds(k0, k1, k2, k3),
-                    )
+            .zip(reserved_peers)
+            .zip(bootnodes)
+            .map(
+                |((chain, reserved_peers), bootnodes)| EphemeralGuardedChain {
+                    chain_config: chain,
+                    reserved_peers,
+                    bootnodes,
+                    members: {
+                        let k0 = randomness.next_u64();
+                        let k1 = randomness.next_u64();
+                        let k2 = randomness.next_u64();
+                        let k3 = randomness.next_u64();
+                        hashbrown::HashSet::with_capacity_and_hasher(
+                            peers_capacity,
+                            ahash::RandomState::with_seeds(k0, k1, k2, k3),
+                        )
+                    },
                 },
-                chain_config: chain,
-            })
+            )
             .collect();
 
+        let address_book = {
+            let k0 = randomness.next_u64();
+            let k1 = randomness.next_u64();
+            let k2 = randomness.next_u64();
+            let k3 = randomness.next_u64();
+            hashbrown::HashMap::with_capacity_and_hasher(
+                config.peers_capacity,
+                ahash::RandomState::with_seeds(k0, k1, k2, k3),
+            )
+        };
+
         ChainNetwork {
             inner: peers::Peers::new(peers::Config {
                 connections_capacity: config.connections_capacity,
@@ -418,8 +893,14 @@ where
                 noise_key: config.noise_key,
                 randomness_seed: inner_randomness_seed,
                 pending_api_events_buffer_size: config.pending_api_events_buffer_size,
+                request_receive_window: config.request_receive_window,
+                notifications_receive_window: config.notifications_receive_window,
+                max_pending_response_bytes: config.max_pending_response_bytes,
                 notification_protocols,
                 ping_protocol: "/ipfs/ping/1.0.0".into(),
+                ping_interval: config.ping_interval,
+                ping_timeout: config.ping_timeout,
+                idle_connection_timeout: config.idle_connection_timeout,
                 handshake_timeout: config.handshake_timeout,
                 initial_desired_peers: Default::default(), // Empty
                 initial_desired_substreams,
@@ -432,9 +913,28 @@ where
                 num_pending_per_peer: peers,
                 pending_ids: slab::Slab::with_capacity(config.peers_capacity),
                 potential_addresses,
+                address_book,
                 chains,
+                peerset,
+                reputations: reputation::Reputations::new(config.reputation),
+                provider_records: {
+                    let k0 = randomness.next_u64();
+                    let k1 = randomness.next_u64();
+                    let k2 = randomness.next_u64();
+                    let k3 = randomness.next_u64();
+                    hashbrown::HashMap::with_capacity_and_hasher(
+                        0,
+                        ahash::RandomState::with_seeds(k0, k1, k2, k3),
+                    )
+                },
             }),
+            metrics: Mutex::new(metrics),
             handshake_timeout: config.handshake_timeout,
+            max_connections: config.max_connections,
+            max_incoming_connections_per_ip: config.max_incoming_connections_per_ip,
+            max_simultaneous_dials: config.max_simultaneous_dials,
+            dial_backoff_initial: config.dial_backoff_initial,
+            dial_backoff_max: config.dial_backoff_max,
             num_chains,
             randomness: Mutex::new(randomness),
             next_start_connect_waker: AtomicWaker::new(),
@@ -445,6 +945,77 @@ where
         1 + chain_index * REQUEST_RESPONSE_PROTOCOLS_PER_CHAIN + protocol
     }
 
+    /// Sends a request to the given peer using the underlying [`peers::Peers`], and updates
+    /// the metrics of the protocol.
+    async fn request(
+        &self,
+        now: TNow,
+        target: &PeerId,
+        protocol_index: usize,
+        request_data: Vec<u8>,
+    ) -> Result<Vec<u8>, peers::RequestError> {
+        let request_len = u64::try_from(request_data.len()).unwrap_or(u64::max_value());
+        let result = self
+            .inner
+            .request(now, target, protocol_index, request_data)
+            .await;
+
+        let mut metrics = self.metrics.lock().await;
+        let metrics = &mut metrics[protocol_index];
+        metrics.requests_sent = metrics.requests_sent.saturating_add(1);
+        metrics.bytes_sent = metrics.bytes_sent.saturating_add(request_len);
+
+        match result {
+            Ok((response, latency)) => {
+                metrics.bytes_received = metrics
+                    .bytes_received
+                    .saturating_add(u64::try_from(response.len()).unwrap_or(u64::max_value()));
+                if metrics.latencies.len() >= LATENCY_SAMPLES {
+                    metrics.latencies.pop_front();
+                }
+                metrics.latencies.push_back(latency);
+                Ok(response)
+            }
+            Err(err) => {
+                metrics.requests_failed = metrics.requests_failed.saturating_add(1);
+                Err(err)
+            }
+        }
+    }
+
+    /// Returns a snapshot of the statistics about the requests that have been sent, for each
+    /// request-response protocol.
+    ///
+    /// The latency percentiles are calculated over the most recent successful requests.
+    pub async fn metrics(&self) -> Vec<ProtocolMetrics> {
+        let metrics = self.metrics.lock().await;
+        metrics
+            .iter()
+            .map(|m| {
+                let mut latencies = m.latencies.iter().copied().collect::<Vec<_>>();
+                latencies.sort_unstable();
+                let percentile = |p: usize| {
+                    if latencies.is_empty() {
+                        None
+                    } else {
+                        Some(latencies[(latencies.len() - 1) * p / 100])
+                    }
+                };
+
+                ProtocolMetrics {
+                    protocol_name: m.protocol_name.clone(),
+                    chain_index: m.chain_index,
+                    requests_sent: m.requests_sent,
+                    requests_failed: m.requests_failed,
+                    bytes_sent: m.bytes_sent,
+                    bytes_received: m.bytes_received,
+                    latency_p50: percentile(50),
+                    latency_p95: percentile(95),
+                }
+            })
+            .collect()
+    }
+
     /// Returns the number of established TCP connections, both incoming and outgoing.
     // TODO: note about race
     pub async fn num_established_connections(&self) -> usize {
@@ -464,6 +1035,48 @@ where
         self.num_chains
     }
 
+    /// Returns the list of addresses that have been dialed in the past, alongside with the
+    /// outcome of these dialing attempts, of the peers that have been found to belong to the
+    /// given chain. Entries are returned in no specific order.
+    ///
+    /// This list is meant to be persisted by the API user and passed back through
+    /// [`Config::known_nodes`] and [`ChainConfig::bootstrap_nodes`] after a restart, so that
+    /// reconnecting to the network doesn't depend solely on the bootnodes of the chain.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `chain_index` is out of range.
+    ///
+    pub async fn address_book(&self, chain_index: usize) -> Vec<AddressBookEntry> {
+        let lock = self.ephemeral_guarded.lock().await;
+        let members = &lock.chains[chain_index].members;
+
+        lock.address_book
+            .iter()
+            .filter(|((peer_id, _), _)| members.contains(peer_id))
+            .map(|((peer_id, address), state)| AddressBookEntry {
+                peer_id: peer_id.clone(),
+                address: address.clone(),
+                consecutive_failures: state.consecutive_failures,
+            })
+            .collect()
+    }
+
+    /// Returns the list of bootnodes of the given chain, as found in
+    /// [`ChainConfig::bootstrap_nodes`], and their health.
+    ///
+    /// Bootnodes are reached similarly to other nodes. This function exists for diagnostic
+    /// purposes, for example in order to detect chains whose bootnodes are all unusable.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `chain_index` is out of range.
+    ///
+    pub async fn bootnodes_health(&self, chain_index: usize) -> Vec<(PeerId, BootnodeHealth)> {
+        let lock = self.ephemeral_guarded.lock().await;
+        lock.chains[chain_index].bootnodes.clone()
+    }
+
     /// Adds an incoming connection to the state machine.
     ///
     /// This connection hasn't finished handshaking and the [`PeerId`] of the remote isn't known
@@ -478,14 +1091,37 @@ where
     /// The `remote_addr` is the address used to reach back the remote. In the case of TCP, it
     /// contains the TCP dialing port of the remote. The remote can ask, through the `identify`
     /// libp2p protocol, its own address, in which case we send it.
+    ///
+    /// Returns an error if accepting the connection would exceed [`Config::max_connections`] or
+    /// [`Config::max_incoming_connections_per_ip`]. In that case, the connection isn't added
+    /// and the socket should simply be closed.
     pub async fn add_incoming_connection(
         &self,
         when_connected: TNow,
         remote_addr: multiaddr::Multiaddr,
-    ) -> ConnectionId {
-        self.inner
+    ) -> Result<ConnectionId, AddIncomingConnectionError> {
+        let num_pending = self.ephemeral_guarded.lock().await.pending_ids.len();
+        if self.inner.num_connections_matching(|_| true).await + num_pending >= self.max_connections
+        {
+            return Err(AddIncomingConnectionError::TooManyConnections);
+        }
+
+        if let Some(remote_ip) = multiaddr_ip(&remote_addr) {
+            let num_same_ip = self
+                .inner
+                .num_connections_matching(|addr| {
+                    multiaddr_ip(addr).map_or(false, |ip| ip == remote_ip)
+                })
+                .await;
+            if num_same_ip >= self.max_incoming_connections_per_ip {
+                return Err(AddIncomingConnectionError::TooManyConnectionsFromIp);
+            }
+        }
+
+        Ok(self
+            .inner
             .add_incoming_connection(when_connected, remote_addr)
-            .await
+            .await)
     }
 
     /// Modifies the best block of the local node. See [`ChainConfig::best_hash`] and
@@ -556,50 +1192,159 @@ where
             .unwrap() = grandpa_state;
     }
 
-    /// Sends a blocks request to the given peer.
-    // TODO: more docs
-    pub async fn blocks_request(
+    /// Sends a GrandPa catch-up request to the given peer, asking for the votes of the latest
+    /// round it has completed.
+    ///
+    /// The request should only be sent to peers whose neighbor packet (see
+    /// [`Event::GrandpaNeighborPacket`]) indicates that they are ahead of the local node. The
+    /// answer, if any, is later reported as an [`Event::GrandpaCatchUp`].
+    ///
+    /// > **Note**: Peers typically only answer catch-up requests whose set id is equal to their
+    /// >           own and whose round number is inferior to their own.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `chain_index` is out of range.
+    ///
+    pub async fn send_grandpa_catch_up_request(
         &self,
-        now: TNow,
         target: &peer_id::PeerId,
         chain_index: usize,
-        config: protocol::BlocksRequestConfig,
-    ) -> Result<Vec<protocol::BlockData>, BlocksRequestError> {
-        let request_data = protocol::build_block_request(config).fold(Vec::new(), |mut a, b| {
-            a.extend_from_slice(b.as_ref());
-            a
-        });
+        request: protocol::CatchUpRequest,
+    ) -> Result<(), QueueNotificationError> {
+        assert!(chain_index < self.num_chains);
 
-        let response = self
-            .inner
-            .request(
-                now,
+        let notification = protocol::GrandpaNotificationRef::CatchUpRequest(request)
+            .scale_encoding()
+            .fold(Vec::new(), |mut a, b| {
+                a.extend_from_slice(b.as_ref());
+                a
+            });
+
+        self.inner
+            .queue_notification(
                 target,
-                self.protocol_index(chain_index, 0),
-                request_data,
+                chain_index * NOTIFICATIONS_PROTOCOLS_PER_CHAIN + 2,
+                notification,
             )
-            .map_err(BlocksRequestError::Request)
-            .await?;
-
-        protocol::decode_block_response(&response).map_err(BlocksRequestError::Decode)
+            .await
     }
 
-    pub async fn grandpa_warp_sync_request(
+    /// Sets whether a collation substream should be maintained open with the given peer.
+    ///
+    /// If `desired` is `true`, the given peer is dialed if necessary and a collation substream
+    /// is opened with it. Collation messages can then be sent with
+    /// [`ChainNetwork::send_collation_message`]. If `desired` is `false`, the collation
+    /// substream, if any, is closed.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `chain_index` is out of range, or if [`ChainConfig::collation_protocol`] is
+    /// `false` for this chain.
+    ///
+    pub async fn set_collation_substream_desired(
         &self,
-        now: TNow,
-        target: &peer_id::PeerId,
+        peer_id: &peer_id::PeerId,
         chain_index: usize,
-        begin_hash: [u8; 32],
-    ) -> Result<protocol::GrandpaWarpSyncResponse, GrandpaWarpSyncRequestError> {
-        let request_data = begin_hash.to_vec();
+        desired: bool,
+    ) {
+        assert!(
+            self.ephemeral_guarded.lock().await.chains[chain_index]
+                .chain_config
+                .collation_protocol
+        );
 
-        let response = self
-            .inner
-            .request(
-                now,
-                target,
-                self.protocol_index(chain_index, 3),
-                request_data,
+        self.inner
+            .set_peer_notifications_out_desired(
+                peer_id,
+                chain_index * NOTIFICATIONS_PROTOCOLS_PER_CHAIN + COLLATION_PROTOCOL_OFFSET,
+                if desired {
+                    peers::DesiredState::Desired
+                } else {
+                    peers::DesiredState::NotDesired
+                },
+            )
+            .await;
+
+        if desired {
+            self.next_start_connect_waker.wake();
+        }
+    }
+
+    /// Sends a collation message to the given peer.
+    ///
+    /// A collation substream must have been opened with this peer beforehand using
+    /// [`ChainNetwork::set_collation_substream_desired`].
+    ///
+    /// # Panic
+    ///
+    /// Panics if `chain_index` is out of range.
+    ///
+    pub async fn send_collation_message(
+        &self,
+        target: &peer_id::PeerId,
+        chain_index: usize,
+        message: protocol::CollationMessageRef<'_>,
+    ) -> Result<(), QueueNotificationError> {
+        assert!(chain_index < self.num_chains);
+
+        let notification =
+            protocol::encode_collation_message(message).fold(Vec::new(), |mut a, b| {
+                a.extend_from_slice(b.as_ref());
+                a
+            });
+
+        self.inner
+            .queue_notification(
+                target,
+                chain_index * NOTIFICATIONS_PROTOCOLS_PER_CHAIN + COLLATION_PROTOCOL_OFFSET,
+                notification,
+            )
+            .await
+    }
+
+    /// Sends a blocks request to the given peer.
+    // TODO: more docs
+    pub async fn blocks_request(
+        &self,
+        now: TNow,
+        target: &peer_id::PeerId,
+        chain_index: usize,
+        config: protocol::BlocksRequestConfig,
+    ) -> Result<Vec<protocol::BlockData>, BlocksRequestError> {
+        let request_data = protocol::build_block_request(config).fold(Vec::new(), |mut a, b| {
+            a.extend_from_slice(b.as_ref());
+            a
+        });
+
+        let response = self
+            .request(
+                now,
+                target,
+                self.protocol_index(chain_index, 0),
+                request_data,
+            )
+            .map_err(BlocksRequestError::Request)
+            .await?;
+
+        protocol::decode_block_response(&response).map_err(BlocksRequestError::Decode)
+    }
+
+    pub async fn grandpa_warp_sync_request(
+        &self,
+        now: TNow,
+        target: &peer_id::PeerId,
+        chain_index: usize,
+        begin_hash: [u8; 32],
+    ) -> Result<protocol::GrandpaWarpSyncResponse, GrandpaWarpSyncRequestError> {
+        let request_data = begin_hash.to_vec();
+
+        let response = self
+            .request(
+                now,
+                target,
+                self.protocol_index(chain_index, 3),
+                request_data,
             )
             .map_err(GrandpaWarpSyncRequestError::Request)
             .await?;
@@ -624,7 +1369,6 @@ where
             });
 
         let response = self
-            .inner
             .request(
                 now,
                 target,
@@ -661,7 +1405,6 @@ where
             });
 
         let response = self
-            .inner
             .request(
                 now,
                 target,
@@ -674,9 +1417,11 @@ where
         protocol::decode_call_proof_response(&response).map_err(CallProofRequestError::Decode)
     }
 
-    ///
+    /// Sends a transactions notification containing the given transaction to the given peer.
     ///
     /// Must be passed the double-SCALE-encoded transaction.
+    ///
+    /// Transactions received from remotes are reported through [`Event::Transactions`].
     // TODO: -> broadcast_transaction
     pub async fn announce_transaction(
         &self,
@@ -738,8 +1483,18 @@ where
             }
         }
 
+        // Update `lock.address_book`.
+        let (expected_peer_id, multiaddr) = (expected_peer_id.clone(), multiaddr.clone());
+        *lock.address_book_entry(&expected_peer_id, &multiaddr) = AddressBookEntryState {
+            consecutive_failures: 0,
+            dial_not_before: None,
+        };
+
         lock.pending_ids.remove(id.0);
 
+        // A dialing attempt is no longer in progress, which might allow a new one to start.
+        self.next_start_connect_waker.wake();
+
         connection_id
     }
 
@@ -754,7 +1509,60 @@ where
     ///
     pub async fn pending_outcome_err(&self, id: PendingId) {
         let mut lock = self.ephemeral_guarded.lock().await;
-        let (expected_peer_id, _, _) = lock.pending_ids.remove(id.0);
+        let lock = &mut *lock; // Prevents borrow checker issues.
+        let (expected_peer_id, multiaddr, when_started) = lock.pending_ids.remove(id.0);
+
+        // Update `lock.address_book`. The address is dialed again later, after a back-off delay
+        // that grows exponentially with the number of consecutive failures, unless it has failed
+        // too many times in a row, in which case it is forgotten.
+        let consecutive_failures = {
+            let state = lock.address_book_entry(&expected_peer_id, &multiaddr);
+            state.consecutive_failures += 1;
+            let backoff = self
+                .dial_backoff_initial
+                .checked_mul(1 << cmp::min(state.consecutive_failures - 1, 16))
+                .map_or(self.dial_backoff_max, |b| {
+                    cmp::min(b, self.dial_backoff_max)
+                });
+            state.dial_not_before = Some(when_started + backoff);
+            state.consecutive_failures
+        };
+
+        if consecutive_failures >= ADDRESS_BOOK_MAX_CONSECUTIVE_FAILURES {
+            lock.address_book_remove(&expected_peer_id, &multiaddr);
+        } else {
+            let potential_addresses = lock
+                .potential_addresses
+                .entry(expected_peer_id.clone())
+                .or_default();
+            if !potential_addresses.iter().any(|a| *a == multiaddr) {
+                potential_addresses.push(multiaddr);
+            }
+        }
+
+        // Update the health of the bootnodes. A bootnode whose genesis hash has already been
+        // found to be wrong keeps being reported as such.
+        for chain in &mut lock.chains {
+            for (_, health) in chain
+                .bootnodes
+                .iter_mut()
+                .filter(|(peer_id, _)| *peer_id == expected_peer_id)
+            {
+                *health = match *health {
+                    BootnodeHealth::WrongGenesis => BootnodeHealth::WrongGenesis,
+                    BootnodeHealth::Unreachable {
+                        consecutive_failures,
+                    } => BootnodeHealth::Unreachable {
+                        consecutive_failures: consecutive_failures.saturating_add(1),
+                    },
+                    BootnodeHealth::Unknown | BootnodeHealth::Healthy => {
+                        BootnodeHealth::Unreachable {
+                            consecutive_failures: 1,
+                        }
+                    }
+                };
+            }
+        }
 
         // Update `lock.peers`.
         let has_any_attempt_left = {
@@ -811,6 +1619,51 @@ where
             // `inner_event` is a mutable reference to `guarded.to_process_pre_event`. All the
             // branches below must clear `to_process_pre_event` after all potentially-cancellable
             // asynchronous operations are finished.
+
+            // Connections and requests coming from banned peers are refused. Connections are
+            // closed without being reported, as are the requests.
+            // The same goes for peers that aren't reserved peers of chains in reserved-only mode.
+            // Since connections are shared between all the chains, a connection is only refused
+            // if no chain allows the peer.
+            match inner_event {
+                peers::Event::Connected { peer_id, .. } => {
+                    let is_refused = {
+                        let mut ephemeral_guarded = self.ephemeral_guarded.lock().await;
+                        ephemeral_guarded.reputations.is_banned(&now, peer_id)
+                            || !ephemeral_guarded.is_peer_allowed_any_chain(peer_id)
+                    };
+                    if is_refused {
+                        self.inner.disconnect(peer_id).await;
+                        guarded.to_process_pre_event = None;
+                        continue;
+                    }
+                }
+                peers::Event::RequestIn {
+                    peer_id,
+                    protocol_index,
+                    request_id,
+                    ..
+                } => {
+                    let is_refused = {
+                        let mut ephemeral_guarded = self.ephemeral_guarded.lock().await;
+                        // The identify protocol (index 0) isn't specific to any chain.
+                        let is_allowed = match *protocol_index {
+                            0 => ephemeral_guarded.is_peer_allowed_any_chain(peer_id),
+                            protocol_index => ephemeral_guarded.chains
+                                [(protocol_index - 1) / REQUEST_RESPONSE_PROTOCOLS_PER_CHAIN]
+                                .is_peer_allowed(peer_id),
+                        };
+                        ephemeral_guarded.reputations.is_banned(&now, peer_id) || !is_allowed
+                    };
+                    if is_refused {
+                        self.inner.respond(*request_id, Err(())).await;
+                        guarded.to_process_pre_event = None;
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+
             match inner_event {
                 peers::Event::Connected {
                     num_peer_connections,
@@ -828,12 +1681,12 @@ where
                 peers::Event::Disconnected {
                     peer_id,
                     num_peer_connections,
-                    peer_is_desired,
                     ..
                 } if *num_peer_connections == 0 => {
-                    if *peer_is_desired {
-                        self.next_start_connect_waker.wake();
-                    }
+                    // Waking up `next_start_connect` is also necessary if the peer isn't
+                    // desired, as the connection might have been preventing new dials because of
+                    // `Config::max_connections`.
+                    self.next_start_connect_waker.wake();
 
                     // TODO: O(n)
                     let chain_indices = guarded
@@ -857,6 +1710,8 @@ where
                     };
                 }
                 peers::Event::Disconnected { .. } => {
+                    // See above.
+                    self.next_start_connect_waker.wake();
                     guarded.to_process_pre_event = None;
                 }
 
@@ -895,7 +1750,192 @@ where
                         _ => unreachable!(),
                     };
                 }
-                // Only protocol 0 (identify) can receive requests at the moment.
+                // Incoming requests of the light protocol.
+                peers::Event::RequestIn {
+                    protocol_index,
+                    request_id,
+                    request_payload,
+                    ..
+                } if (*protocol_index - 1) % REQUEST_RESPONSE_PROTOCOLS_PER_CHAIN == 1 => {
+                    let chain_index = (*protocol_index - 1) / REQUEST_RESPONSE_PROTOCOLS_PER_CHAIN;
+
+                    // The light protocol is used both for storage proofs and call proofs.
+                    let request = match protocol::decode_storage_proof_request(request_payload) {
+                        Ok(request) => Ok(either::Left(request)),
+                        Err(protocol::DecodeStorageProofRequestError::BadRequestTy) => {
+                            match protocol::decode_call_proof_request(request_payload) {
+                                Ok(request) => Ok(either::Right(request)),
+                                Err(err) => Err(ProtocolError::BadCallProofRequest(err)),
+                            }
+                        }
+                        Err(err) => Err(ProtocolError::BadStorageProofRequest(err)),
+                    };
+
+                    if let Err(error) = request {
+                        self.inner.respond(*request_id, Err(())).await;
+                        return match guarded.to_process_pre_event.take().unwrap() {
+                            peers::Event::RequestIn { peer_id, .. } => {
+                                Event::ProtocolError { peer_id, error }
+                            }
+                            _ => unreachable!(),
+                        };
+                    }
+
+                    let (peer_id, request_id) = match guarded.to_process_pre_event.take().unwrap() {
+                        peers::Event::RequestIn {
+                            peer_id,
+                            request_id,
+                            ..
+                        } => (peer_id, request_id),
+                        _ => unreachable!(),
+                    };
+
+                    return match request {
+                        Ok(either::Left(request)) => Event::StorageProofRequestIn {
+                            peer_id,
+                            chain_index,
+                            request: StorageProofRequestIn {
+                                service: self,
+                                request_id,
+                                block_hash: request.block_hash,
+                                keys: request.keys.collect(),
+                            },
+                        },
+                        Ok(either::Right(request)) => Event::CallProofRequestIn {
+                            peer_id,
+                            chain_index,
+                            request: CallProofRequestIn {
+                                service: self,
+                                request_id,
+                                request,
+                            },
+                        },
+                        Err(_) => unreachable!(),
+                    };
+                }
+                // Incoming requests of the GrandPa warp sync protocol.
+                peers::Event::RequestIn {
+                    protocol_index,
+                    request_id,
+                    request_payload,
+                    ..
+                } if (*protocol_index - 1) % REQUEST_RESPONSE_PROTOCOLS_PER_CHAIN == 3 => {
+                    let chain_index = (*protocol_index - 1) / REQUEST_RESPONSE_PROTOCOLS_PER_CHAIN;
+
+                    let begin_hash =
+                        match protocol::decode_grandpa_warp_sync_request(request_payload) {
+                            Ok(h) => h,
+                            Err(err) => {
+                                self.inner.respond(*request_id, Err(())).await;
+                                return match guarded.to_process_pre_event.take().unwrap() {
+                                    peers::Event::RequestIn { peer_id, .. } => {
+                                        Event::ProtocolError {
+                                            peer_id,
+                                            error: ProtocolError::BadGrandpaWarpSyncRequest(err),
+                                        }
+                                    }
+                                    _ => unreachable!(),
+                                };
+                            }
+                        };
+
+                    return match guarded.to_process_pre_event.take().unwrap() {
+                        peers::Event::RequestIn {
+                            peer_id,
+                            request_id,
+                            ..
+                        } => Event::GrandpaWarpSyncRequestIn {
+                            peer_id,
+                            chain_index,
+                            request: GrandpaWarpSyncRequestIn {
+                                service: self,
+                                request_id,
+                                begin_hash,
+                            },
+                        },
+                        _ => unreachable!(),
+                    };
+                }
+                // Incoming requests of the Kademlia protocol.
+                peers::Event::RequestIn {
+                    peer_id,
+                    protocol_index,
+                    request_id,
+                    request_payload,
+                    ..
+                } if (*protocol_index - 1) % REQUEST_RESPONSE_PROTOCOLS_PER_CHAIN == 2 => {
+                    let chain_index = (*protocol_index - 1) / REQUEST_RESPONSE_PROTOCOLS_PER_CHAIN;
+
+                    let request = match kademlia::decode_request(request_payload) {
+                        Ok(r) => r,
+                        Err(err) => {
+                            self.inner.respond(*request_id, Err(())).await;
+                            return match guarded.to_process_pre_event.take().unwrap() {
+                                peers::Event::RequestIn { peer_id, .. } => Event::ProtocolError {
+                                    peer_id,
+                                    error: ProtocolError::BadKademliaRequest(err),
+                                },
+                                _ => unreachable!(),
+                            };
+                        }
+                    };
+
+                    let response = {
+                        let mut ephemeral_guarded = self.ephemeral_guarded.lock().await;
+                        let ephemeral_guarded = &mut *ephemeral_guarded;
+                        match request {
+                            kademlia::Request::GetProviders { key } => {
+                                let records = ephemeral_guarded
+                                    .provider_records
+                                    .get_mut(&(chain_index, key.clone()));
+                                let providers = match records {
+                                    Some(records) => {
+                                        records.retain(|r| r.expiration > now);
+                                        records
+                                            .iter()
+                                            .map(|r| (&r.peer_id, &r.addresses[..]))
+                                            .collect::<Vec<_>>()
+                                    }
+                                    None => Vec::new(),
+                                };
+                                Ok(kademlia::build_get_providers_response(
+                                    &key,
+                                    providers.into_iter(),
+                                    iter::empty(),
+                                ))
+                            }
+                            kademlia::Request::AddProvider { key, providers } => {
+                                // Remotes can only announce themselves as providers.
+                                for (provider, mut addresses) in providers
+                                    .into_iter()
+                                    .filter(|(provider, _)| provider == peer_id)
+                                {
+                                    addresses.truncate(MAX_PROVIDER_ADDRESSES);
+                                    ephemeral_guarded.insert_provider_record(
+                                        chain_index,
+                                        key.clone(),
+                                        ProviderRecord {
+                                            peer_id: provider,
+                                            addresses,
+                                            expiration: now.clone() + PROVIDER_RECORD_TTL,
+                                        },
+                                        &now,
+                                    );
+                                }
+                                // No response is expected. Refusing the request closes the
+                                // substream.
+                                Err(())
+                            }
+                            // TODO: answer with the closest known nodes once k-buckets are implemented
+                            kademlia::Request::FindNode { .. } => Err(()),
+                        }
+                    };
+
+                    self.inner.respond(*request_id, response).await;
+                    guarded.to_process_pre_event = None;
+                }
+                // Only the identify, light, Kademlia, and GrandPa warp sync protocols can receive
+                // requests at the moment.
                 peers::Event::RequestIn { .. } => unreachable!(),
 
                 // Remote is no longer interested in the response.
@@ -934,6 +1974,102 @@ where
                             }
                         };
 
+                    // Check whether the remote is on the same chain and whether there is a slot
+                    // available for its role.
+                    let slot_check = {
+                        let mut ephemeral_guarded = self.ephemeral_guarded.lock().await;
+                        let local_genesis = ephemeral_guarded.chains[chain_index]
+                            .chain_config
+                            .genesis_hash;
+                        let remote_genesis = *remote_handshake.genesis_hash;
+
+                        if let Some((_, health)) = ephemeral_guarded.chains[chain_index]
+                            .bootnodes
+                            .iter_mut()
+                            .find(|(p, _)| p == peer_id)
+                        {
+                            *health = if remote_genesis == local_genesis {
+                                BootnodeHealth::Healthy
+                            } else {
+                                BootnodeHealth::WrongGenesis
+                            };
+                        }
+
+                        if remote_genesis != local_genesis {
+                            ephemeral_guarded.peerset.unassign(chain_index, peer_id);
+                            Err(NotificationsOutErr::GenesisMismatch {
+                                local_genesis,
+                                remote_genesis,
+                            })
+                        } else {
+                            // The remote belongs to this chain, even if no slot is available.
+                            // Only peers present in the address book are tracked, as the
+                            // members are only used to filter the address book.
+                            if ephemeral_guarded
+                                .address_book
+                                .keys()
+                                .any(|(p, _)| p == peer_id)
+                            {
+                                ephemeral_guarded.chains[chain_index]
+                                    .members
+                                    .insert(peer_id.clone());
+                            }
+
+                            if let peerset::SetRoleOutcome::Released = ephemeral_guarded
+                                .peerset
+                                .set_role(chain_index, peer_id, remote_handshake.role)
+                            {
+                                Err(NotificationsOutErr::NoSlotAvailable)
+                            } else {
+                                Ok(())
+                            }
+                        }
+                    };
+
+                    if let Err(error) = slot_check {
+                        // The slot of the remote, if any, has been unassigned above.
+                        self.inner
+                            .set_peer_notifications_out_desired(
+                                peer_id,
+                                chain_index * NOTIFICATIONS_PROTOCOLS_PER_CHAIN,
+                                peers::DesiredState::NotDesired,
+                            )
+                            .await;
+
+                        // A remote that belongs to a different chain is of no use, and the other
+                        // substreams of this chain are no longer desired either, so that it stops
+                        // being dialed. This is notably the case of bootnodes that are
+                        // initially marked as desired.
+                        // The block announces substream (index 0) has already been marked as not
+                        // desired above.
+                        if let NotificationsOutErr::GenesisMismatch { .. } = error {
+                            for protocol in 1..NOTIFICATIONS_PROTOCOLS_PER_CHAIN {
+                                self.inner
+                                    .set_peer_notifications_out_desired(
+                                        peer_id,
+                                        chain_index * NOTIFICATIONS_PROTOCOLS_PER_CHAIN + protocol,
+                                        peers::DesiredState::NotDesired,
+                                    )
+                                    .await;
+                            }
+                        }
+
+                        // As a slot has potentially been unassigned, wake up the discovery
+                        // process in order for it to be filled.
+                        self.next_start_connect_waker.wake();
+
+                        return match guarded.to_process_pre_event.take().unwrap() {
+                            peers::Event::NotificationsOutResult { peer_id, .. } => {
+                                Event::ChainConnectAttemptFailed {
+                                    peer_id,
+                                    chain_index,
+                                    error,
+                                }
+                            }
+                            _ => unreachable!(),
+                        };
+                    }
+
                     // The desirability of the transactions and grandpa substreams is always equal
                     // to whether the block announces substream is open.
                     self.inner
@@ -951,56 +2087,12 @@ where
                         )
                         .await;
 
-                    {
-                        let ephemeral_guarded = self.ephemeral_guarded.lock().await;
-                        let local_genesis = ephemeral_guarded.chains[chain_index]
-                            .chain_config
-                            .genesis_hash;
-                        let remote_genesis = *remote_handshake.genesis_hash;
+                    let _was_inserted = guarded.open_chains.insert((peer_id.clone(), chain_index));
+                    debug_assert!(_was_inserted);
 
-                        if remote_genesis != local_genesis {
-                            // TODO: DRY
-                            // Unassign the out and in slots of the remote, if any.
-                            self.inner
-                                .set_peer_notifications_out_desired(
-                                    peer_id,
-                                    chain_index * NOTIFICATIONS_PROTOCOLS_PER_CHAIN,
-                                    peers::DesiredState::NotDesired,
-                                )
-                                .await;
-                            {
-                                let mut ephemeral_guarded = self.ephemeral_guarded.lock().await;
-                                let _was_in_out = ephemeral_guarded.chains[chain_index]
-                                    .out_peers
-                                    .remove(peer_id);
-                                let _was_in_in = ephemeral_guarded.chains[chain_index]
-                                    .in_peers
-                                    .remove(peer_id);
-                                debug_assert!(!_was_in_out || !_was_in_in);
-                            }
-
-                            return match guarded.to_process_pre_event.take().unwrap() {
-                                peers::Event::NotificationsOutResult { peer_id, .. } => {
-                                    Event::ChainConnectAttemptFailed {
-                                        peer_id,
-                                        chain_index,
-                                        error: NotificationsOutErr::GenesisMismatch {
-                                            local_genesis,
-                                            remote_genesis,
-                                        },
-                                    }
-                                }
-                                _ => unreachable!(),
-                            };
-                        }
-                    }
-
-                    let _was_inserted = guarded.open_chains.insert((peer_id.clone(), chain_index));
-                    debug_assert!(_was_inserted);
-
-                    let best_hash = *remote_handshake.best_hash;
-                    let best_number = remote_handshake.best_number;
-                    let role = remote_handshake.role;
+                    let best_hash = *remote_handshake.best_hash;
+                    let best_number = remote_handshake.best_number;
+                    let role = remote_handshake.role;
 
                     return match guarded.to_process_pre_event.take().unwrap() {
                         peers::Event::NotificationsOutResult { peer_id, .. } => {
@@ -1026,6 +2118,18 @@ where
                     guarded.to_process_pre_event = None;
                 }
 
+                // Successfully opened collation substream.
+                peers::Event::NotificationsOutResult {
+                    notifications_protocol_index,
+                    result: Ok(_),
+                    ..
+                } if *notifications_protocol_index % NOTIFICATIONS_PROTOCOLS_PER_CHAIN
+                    == COLLATION_PROTOCOL_OFFSET =>
+                {
+                    // Nothing to do.
+                    guarded.to_process_pre_event = None;
+                }
+
                 // Successfully opened Grandpa substream.
                 // Need to send a Grandpa neighbor packet in response.
                 peers::Event::NotificationsOutResult {
@@ -1106,6 +2210,8 @@ where
                     {
                         Vec::new()
                     } else if *notifications_protocol_index % NOTIFICATIONS_PROTOCOLS_PER_CHAIN == 2
+                        || *notifications_protocol_index % NOTIFICATIONS_PROTOCOLS_PER_CHAIN
+                            == COLLATION_PROTOCOL_OFFSET
                     {
                         chain_config.role.scale_encoding().to_vec()
                     } else {
@@ -1136,16 +2242,11 @@ where
                             peers::DesiredState::NotDesired,
                         )
                         .await;
-                    {
-                        let mut ephemeral_guarded = self.ephemeral_guarded.lock().await;
-                        let _was_in_out = ephemeral_guarded.chains[chain_index]
-                            .out_peers
-                            .remove(peer_id);
-                        let _was_in_in = ephemeral_guarded.chains[chain_index]
-                            .in_peers
-                            .remove(peer_id);
-                        debug_assert!(!_was_in_out || !_was_in_in);
-                    }
+                    self.ephemeral_guarded
+                        .lock()
+                        .await
+                        .peerset
+                        .unassign(chain_index, peer_id);
 
                     // As a slot has been unassigned, wake up the discovery process in order for
                     // it to be filled.
@@ -1207,16 +2308,11 @@ where
                             peers::DesiredState::NotDesired,
                         )
                         .await;
-                    {
-                        let mut ephemeral_guarded = self.ephemeral_guarded.lock().await;
-                        let _was_in_out = ephemeral_guarded.chains[chain_index]
-                            .out_peers
-                            .remove(peer_id);
-                        let _was_in_in = ephemeral_guarded.chains[chain_index]
-                            .in_peers
-                            .remove(peer_id);
-                        debug_assert!(!_was_in_out || !_was_in_in);
-                    }
+                    self.ephemeral_guarded
+                        .lock()
+                        .await
+                        .peerset
+                        .unassign(chain_index, peer_id);
 
                     // The chain is now considered as closed.
                     let _was_removed = guarded.open_chains.remove(&(peer_id.clone(), chain_index)); // TODO: cloning :(
@@ -1318,8 +2414,29 @@ where
                         continue;
                     }
 
-                    // TODO: this is unimplemented
-                    guarded.to_process_pre_event = None;
+                    return match guarded.to_process_pre_event.take().unwrap() {
+                        peers::Event::NotificationsIn {
+                            peer_id,
+                            notification,
+                            ..
+                        } => {
+                            if let Err(err) =
+                                protocol::decode_transactions_notification(&notification)
+                            {
+                                Event::ProtocolError {
+                                    error: ProtocolError::BadTransactionsNotification(err),
+                                    peer_id,
+                                }
+                            } else {
+                                Event::Transactions {
+                                    chain_index,
+                                    peer_id,
+                                    transactions: EncodedTransactions(notification),
+                                }
+                            }
+                        }
+                        _ => unreachable!(),
+                    };
                 }
 
                 // Received Grandpa notification.
@@ -1354,20 +2471,132 @@ where
                         }
                     };
 
-                    // Commit messages are the only type of message that is important for
-                    // light clients. Anything else is presently ignored.
-                    if let protocol::GrandpaNotificationRef::Commit(_) = decoded_notif {
-                        let notification = mem::take(notification);
+                    // Commit and catch-up messages that don't concern the current authorities set
+                    // of the local node, or that can't make the finalized block progress, are
+                    // discarded here rather than reported, in order to avoid wasting resources
+                    // verifying their signatures.
+                    let local_grandpa_state = self.ephemeral_guarded.lock().await.chains
+                        [chain_index]
+                        .chain_config
+                        .grandpa_protocol_config;
+                    let is_useless = match (&decoded_notif, local_grandpa_state) {
+                        (
+                            protocol::GrandpaNotificationRef::Commit(commit),
+                            Some(local_grandpa_state),
+                        ) => {
+                            commit.set_id != local_grandpa_state.set_id
+                                || commit.message.target_number
+                                    <= local_grandpa_state.commit_finalized_height
+                        }
+                        (
+                            protocol::GrandpaNotificationRef::CatchUp(catch_up),
+                            Some(local_grandpa_state),
+                        ) => {
+                            catch_up.set_id != local_grandpa_state.set_id
+                                || catch_up.base_number
+                                    <= local_grandpa_state.commit_finalized_height
+                        }
+                        (protocol::GrandpaNotificationRef::Commit(_), None)
+                        | (protocol::GrandpaNotificationRef::CatchUp(_), None) => true,
+                        _ => false,
+                    };
+                    if is_useless {
                         guarded.to_process_pre_event = None;
-                        return Event::GrandpaCommitMessage {
-                            chain_index,
-                            message: EncodedGrandpaCommitMessage(notification),
-                        };
+                        continue;
+                    }
+
+                    // Votes and catch-up requests are ignored, as the local node never
+                    // participates in the voting.
+                    match decoded_notif {
+                        protocol::GrandpaNotificationRef::Commit(_) => {
+                            return match guarded.to_process_pre_event.take().unwrap() {
+                                peers::Event::NotificationsIn {
+                                    peer_id,
+                                    notification,
+                                    ..
+                                } => Event::GrandpaCommitMessage {
+                                    chain_index,
+                                    peer_id,
+                                    message: EncodedGrandpaCommitMessage(notification),
+                                },
+                                _ => unreachable!(),
+                            };
+                        }
+                        protocol::GrandpaNotificationRef::Neighbor(packet) => {
+                            let state = GrandpaState {
+                                round_number: packet.round_number,
+                                set_id: packet.set_id,
+                                commit_finalized_height: packet.commit_finalized_height,
+                            };
+
+                            return match guarded.to_process_pre_event.take().unwrap() {
+                                peers::Event::NotificationsIn { peer_id, .. } => {
+                                    Event::GrandpaNeighborPacket {
+                                        chain_index,
+                                        peer_id,
+                                        state,
+                                    }
+                                }
+                                _ => unreachable!(),
+                            };
+                        }
+                        protocol::GrandpaNotificationRef::CatchUp(_) => {
+                            return match guarded.to_process_pre_event.take().unwrap() {
+                                peers::Event::NotificationsIn {
+                                    peer_id,
+                                    notification,
+                                    ..
+                                } => Event::GrandpaCatchUp {
+                                    chain_index,
+                                    peer_id,
+                                    catch_up: EncodedGrandpaCatchUp(notification),
+                                },
+                                _ => unreachable!(),
+                            };
+                        }
+                        protocol::GrandpaNotificationRef::Vote(_)
+                        | protocol::GrandpaNotificationRef::CatchUpRequest(_) => {}
                     }
 
                     guarded.to_process_pre_event = None;
                 }
 
+                // Received collation message.
+                peers::Event::NotificationsIn {
+                    notifications_protocol_index,
+                    ..
+                } if *notifications_protocol_index % NOTIFICATIONS_PROTOCOLS_PER_CHAIN
+                    == COLLATION_PROTOCOL_OFFSET =>
+                {
+                    let chain_index =
+                        *notifications_protocol_index / NOTIFICATIONS_PROTOCOLS_PER_CHAIN;
+
+                    // Contrary to the other notifications protocols, collation messages are
+                    // reported even if the chain isn't open with this peer. Inbound collation
+                    // substreams are only accepted if the protocol is enabled.
+                    return match guarded.to_process_pre_event.take().unwrap() {
+                        peers::Event::NotificationsIn {
+                            peer_id,
+                            notification,
+                            ..
+                        } => {
+                            if let Err(err) = protocol::decode_collation_message(&notification) {
+                                Event::ProtocolError {
+                                    error: ProtocolError::BadCollationMessage(err),
+                                    peer_id,
+                                }
+                            } else {
+                                Event::CollationMessage {
+                                    chain_index,
+                                    peer_id,
+                                    message: EncodedCollationMessage(notification),
+                                }
+                            }
+                        }
+                        _ => unreachable!(),
+                    };
+                }
+
                 peers::Event::NotificationsIn { .. } => {
                     // Unrecognized notifications protocol.
                     unreachable!()
@@ -1386,36 +2615,31 @@ where
                         *notifications_protocol_index / NOTIFICATIONS_PROTOCOLS_PER_CHAIN;
 
                     // Immediately reject the substream if the handshake fails to parse.
-                    if let Err(err) = protocol::decode_block_announces_handshake(handshake) {
-                        self.inner
-                            .in_notification_refuse(*desired_in_notification_id)
-                            .await;
+                    let remote_role = match protocol::decode_block_announces_handshake(handshake) {
+                        Ok(hs) => hs.role,
+                        Err(err) => {
+                            self.inner
+                                .in_notification_refuse(*desired_in_notification_id)
+                                .await;
 
-                        return Event::ProtocolError {
-                            error: ProtocolError::BadBlockAnnouncesHandshake(err),
-                            peer_id: match guarded.to_process_pre_event.take().unwrap() {
-                                peers::Event::DesiredInNotification { peer_id, .. } => peer_id,
-                                _ => unreachable!(),
-                            },
-                        };
-                    }
+                            return Event::ProtocolError {
+                                error: ProtocolError::BadBlockAnnouncesHandshake(err),
+                                peer_id: match guarded.to_process_pre_event.take().unwrap() {
+                                    peers::Event::DesiredInNotification { peer_id, .. } => peer_id,
+                                    _ => unreachable!(),
+                                },
+                            };
+                        }
+                    };
 
                     let mut ephemeral_guarded = self.ephemeral_guarded.lock().await;
+                    let ephemeral_guarded = &mut *ephemeral_guarded; // Prevents borrow checker issues.
 
-                    // If the peer doesn't already have an outbound slot, check whether we can
-                    // allocate an inbound slot for it.
-                    let has_out_slot = ephemeral_guarded.chains[chain_index]
-                        .out_peers
-                        .contains(peer_id);
-                    if !has_out_slot
-                        && ephemeral_guarded.chains[chain_index].in_peers.len()
-                            >= usize::try_from(
-                                ephemeral_guarded.chains[chain_index].chain_config.in_slots,
-                            )
-                            .unwrap_or(usize::max_value())
+                    // Refuse the substream if the peer is banned or, in reserved-only mode, if the
+                    // peer isn't a reserved peer.
+                    if ephemeral_guarded.reputations.is_banned(&now, peer_id)
+                        || !ephemeral_guarded.chains[chain_index].is_peer_allowed(peer_id)
                     {
-                        // All in slots are occupied. Refuse the substream.
-                        drop(ephemeral_guarded);
                         self.inner
                             .in_notification_refuse(*desired_in_notification_id)
                             .await;
@@ -1423,6 +2647,37 @@ where
                         continue;
                     }
 
+                    // If the peer doesn't already have an outbound slot, check whether we can
+                    // allocate an inbound slot for it, potentially by replacing a peer with a
+                    // lower reputation.
+                    let has_out_slot = matches!(
+                        ephemeral_guarded.peerset.slot(chain_index, peer_id),
+                        Some((peerset::Direction::Out, _))
+                    );
+                    let to_replace = if has_out_slot {
+                        None
+                    } else {
+                        let reputations = &mut ephemeral_guarded.reputations;
+                        let candidate_score = reputations.score(&now, peer_id);
+                        match ephemeral_guarded.peerset.in_slot_candidate(
+                            chain_index,
+                            remote_role,
+                            candidate_score,
+                            |p| reputations.score(&now, p),
+                        ) {
+                            peerset::InSlotCandidate::Free => None,
+                            peerset::InSlotCandidate::Replace(to_replace) => Some(to_replace),
+                            peerset::InSlotCandidate::Full => {
+                                // All in slots are occupied. Refuse the substream.
+                                self.inner
+                                    .in_notification_refuse(*desired_in_notification_id)
+                                    .await;
+                                guarded.to_process_pre_event = None;
+                                continue;
+                            }
+                        }
+                    };
+
                     // At this point, accept the node can no longer fail.
 
                     // Generate the handshake to send back.
@@ -1446,24 +2701,61 @@ where
                         .inner
                         .in_notification_accept(*desired_in_notification_id, handshake)
                         .await
-                        .is_ok()
-                        && !has_out_slot
+                        .is_err()
+                        || has_out_slot
                     {
-                        // TODO: future cancellation issue; if this future is cancelled, then trying to do the `in_notification_accept` again next time will panic
-                        self.inner
-                            .set_peer_notifications_out_desired(
-                                peer_id,
-                                *notifications_protocol_index,
-                                peers::DesiredState::DesiredReset,
-                            )
-                            .await;
+                        guarded.to_process_pre_event = None;
+                        continue;
+                    }
+
+                    // TODO: future cancellation issue; if this future is cancelled, then trying to do the `in_notification_accept` again next time will panic
+                    self.inner
+                        .set_peer_notifications_out_desired(
+                            peer_id,
+                            *notifications_protocol_index,
+                            peers::DesiredState::DesiredReset,
+                        )
+                        .await;
+
+                    // Gracefully close the substreams of the peer being replaced, without
+                    // closing its connection. The collation substream (whose offset is 3) is
+                    // independent of the slots and is left untouched.
+                    if let Some(to_replace) = &to_replace {
+                        for protocol_offset in 0..3 {
+                            self.inner
+                                .set_peer_notifications_out_desired(
+                                    to_replace,
+                                    chain_index * NOTIFICATIONS_PROTOCOLS_PER_CHAIN
+                                        + protocol_offset,
+                                    peers::DesiredState::NotDesired,
+                                )
+                                .await;
+                        }
+                    }
 
-                        // The state modification is done at the very end, to not have any
-                        // future cancellation issue.
-                        let _was_inserted = ephemeral_guarded.chains[chain_index]
-                            .in_peers
-                            .insert(peer_id.clone());
-                        debug_assert!(_was_inserted);
+                    // The state modification is done at the very end, to not have any
+                    // future cancellation issue.
+                    if let Some(to_replace) = &to_replace {
+                        ephemeral_guarded.peerset.unassign(chain_index, to_replace);
+                    }
+                    let _was_inserted =
+                        ephemeral_guarded
+                            .peerset
+                            .assign_in(chain_index, peer_id, remote_role);
+                    debug_assert!(_was_inserted);
+
+                    // If the replaced peer had its chain open, it is now considered as closed.
+                    if let Some(to_replace) = to_replace {
+                        if guarded
+                            .open_chains
+                            .remove(&(to_replace.clone(), chain_index))
+                        {
+                            guarded.to_process_pre_event = None;
+                            return Event::ChainDisconnected {
+                                peer_id: to_replace,
+                                chain_index,
+                            };
+                        }
                     }
 
                     guarded.to_process_pre_event = None;
@@ -1540,6 +2832,49 @@ where
                     guarded.to_process_pre_event = None;
                 }
 
+                // Remote wants to open a collation substream.
+                peers::Event::DesiredInNotification {
+                    peer_id,
+                    id: desired_in_notification_id,
+                    notifications_protocol_index,
+                    ..
+                } if (*notifications_protocol_index % NOTIFICATIONS_PROTOCOLS_PER_CHAIN)
+                    == COLLATION_PROTOCOL_OFFSET =>
+                {
+                    let mut ephemeral_guarded = self.ephemeral_guarded.lock().await;
+                    let chain_index =
+                        *notifications_protocol_index / NOTIFICATIONS_PROTOCOLS_PER_CHAIN;
+
+                    // Reject the substream if the protocol is disabled, if the peer is banned, or
+                    // if the peer isn't a reserved peer in reserved-only mode.
+                    if !ephemeral_guarded.chains[chain_index]
+                        .chain_config
+                        .collation_protocol
+                        || !ephemeral_guarded.chains[chain_index].is_peer_allowed(peer_id)
+                        || ephemeral_guarded.reputations.is_banned(&now, peer_id)
+                    {
+                        self.inner
+                            .in_notification_refuse(*desired_in_notification_id)
+                            .await;
+                        guarded.to_process_pre_event = None;
+                        continue;
+                    }
+
+                    let handshake = ephemeral_guarded.chains[chain_index]
+                        .chain_config
+                        .role
+                        .scale_encoding()
+                        .to_vec();
+
+                    // It doesn't matter if the substream is obsolete.
+                    let _ = self
+                        .inner
+                        .in_notification_accept(*desired_in_notification_id, handshake)
+                        .await;
+
+                    guarded.to_process_pre_event = None;
+                }
+
                 peers::Event::DesiredInNotification { .. } => {
                     // Unrecognized notifications protocol.
                     unreachable!()
@@ -1554,6 +2889,79 @@ where
         }
     }
 
+    /// Inserts nodes that have been discovered through a mechanism external to the
+    /// [`ChainNetwork`], such as mDNS (see [`crate::network::mdns`]), as potential peers of the
+    /// given chain.
+    ///
+    /// Nodes are assigned an outbound slot if one is available, and will then be connected to.
+    /// Nodes are ignored if the chain is in reserved-only mode (see
+    /// [`ChainConfig::reserved_only`]).
+    ///
+    /// > **Note**: Nothing guarantees that the nodes actually belong to the given chain. Nodes
+    /// >           that don't will be disconnected from after the handshake fails.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `chain_index` is out of range.
+    ///
+    pub async fn insert_discovered_nodes(
+        &self,
+        chain_index: usize,
+        nodes: impl IntoIterator<Item = (peer_id::PeerId, Vec<multiaddr::Multiaddr>)>,
+    ) {
+        let mut lock = self.ephemeral_guarded.lock().await;
+        let lock = &mut *lock; // Avoids borrow checker issues.
+
+        for (peer_id, addrs) in nodes {
+            // In reserved-only mode, discovered nodes are never connected to.
+            if !lock.chains[chain_index].is_peer_allowed(&peer_id) {
+                continue;
+            }
+
+            // Only proceed if we have out slots available.
+            if !lock.peerset.has_free_out_slot(chain_index) {
+                break;
+            }
+
+            // Don't assign slots to peers that already have a slot.
+            let previous_slot = lock.peerset.slot(chain_index, &peer_id);
+            if matches!(previous_slot, Some((peerset::Direction::Out, _))) {
+                continue;
+            }
+
+            // It is possible that this peer already has an inbound slot, in which case we try to
+            // turn the inbound slot into an outbound slot.
+            if previous_slot.is_some() {
+                lock.peerset.assign_out(chain_index, &peer_id);
+                continue;
+            }
+
+            // It is now guaranteed that this peer will be assigned an outbound slot.
+            // Add its addresses to the local directory.
+            let existing_addrs = lock.potential_addresses.entry(peer_id.clone()).or_default();
+            for addr in addrs {
+                if !existing_addrs.iter().any(|a| *a == addr) {
+                    existing_addrs.push(addr);
+                }
+            }
+
+            // TODO: hack
+            // TODO: futures cancellation issue
+            self.inner
+                .set_peer_notifications_out_desired(
+                    &peer_id,
+                    chain_index * NOTIFICATIONS_PROTOCOLS_PER_CHAIN,
+                    peers::DesiredState::DesiredReset, // TODO: opinionated
+                )
+                .await;
+
+            let _was_assigned = lock.peerset.assign_out(chain_index, &peer_id);
+            debug_assert!(_was_assigned);
+        }
+
+        self.next_start_connect_waker.wake();
+    }
+
     /// Performs a round of Kademlia discovery.
     ///
     /// This future yields once a list of nodes on the network has been discovered, or a problem
@@ -1599,7 +3007,6 @@ where
     ) -> Result<Vec<(peer_id::PeerId, Vec<multiaddr::Multiaddr>)>, KademliaFindNodeError> {
         let request_data = kademlia::build_find_node_request(close_to_key);
         let response = self
-            .inner
             .request(
                 now,
                 target,
@@ -1613,22 +3020,124 @@ where
         Ok(decoded)
     }
 
-    /// Allocates a [`PendingId`] and returns a [`StartConnect`] indicating a multiaddress that
-    /// the API user must try to dial.
+    /// Sends an identify request to the given peer, and waits for it to answer.
     ///
-    /// Later, the API user must use [`ChainNetwork::pending_outcome_ok`] or
-    /// [`ChainNetwork::pending_outcome_err`] to report how the connection attempt went.
-    ///
-    /// The returned [`StartConnect`] contains the [`StartConnect::timeout`] field. It is the
-    /// responsibility of the API user to ensure that [`ChainNetwork::pending_outcome_err`] is
-    /// called if this timeout is reached.
+    /// On success, the addresses that the remote reports listening on are added to the list of
+    /// addresses known for this peer. Only the first few of these addresses are taken into
+    /// account, in order to prevent a remote from filling the memory of the local node.
     ///
-    /// If no outgoing connection is desired, the method waits until there is one.
-    // TODO: give more control, with number of slots and node choice
-    pub async fn next_start_connect<'a>(&self, now: TNow) -> StartConnect<TNow> {
-        loop {
-            let mut pending_lock = self.ephemeral_guarded.lock().await;
-            let pending = &mut *pending_lock; // Prevents borrow checker issues.
+    /// Returns an error if there is no active connection with that peer, or if the response
+    /// doesn't come from the expected peer.
+    pub async fn identify_request(
+        &self,
+        now: TNow,
+        target: &PeerId,
+    ) -> Result<protocol::DecodedIdentifyResponse, IdentifyRequestError> {
+        let response = self
+            .request(now, target, 0, Vec::new())
+            .await
+            .map_err(IdentifyRequestError::Request)?;
+        let decoded =
+            protocol::decode_identify_response(&response).map_err(IdentifyRequestError::Decode)?;
+
+        if decoded.public_key.clone().into_peer_id() != *target {
+            return Err(IdentifyRequestError::PeerIdMismatch);
+        }
+
+        let mut lock = self.ephemeral_guarded.lock().await;
+        let existing_addrs = lock.potential_addresses.entry(target.clone()).or_default();
+        for addr in decoded.listen_addrs.iter().take(IDENTIFY_MAX_LISTEN_ADDRS) {
+            if !existing_addrs.iter().any(|a| a == addr) {
+                existing_addrs.push(addr.clone());
+            }
+        }
+
+        Ok(decoded)
+    }
+
+    /// Sends a Kademlia "get providers" request to a single peer, and waits for it to answer.
+    ///
+    /// The response contains the peers that the target knows provide the given key, plus the
+    /// nodes closest to this key.
+    ///
+    /// Returns an error if there is no active connection with that peer.
+    pub async fn kademlia_get_providers(
+        &'_ self,
+        target: &PeerId,
+        now: TNow,
+        chain_index: usize,
+        key: &[u8],
+    ) -> Result<kademlia::GetProvidersResponse, KademliaGetProvidersError> {
+        let request_data = kademlia::build_get_providers_request(key);
+        let response = self
+            .request(
+                now,
+                target,
+                self.protocol_index(chain_index, 2),
+                request_data,
+            )
+            .await
+            .map_err(KademliaGetProvidersError::RequestFailed)?;
+        let decoded = kademlia::decode_get_providers_response(&response)
+            .map_err(KademliaGetProvidersError::DecodeError)?;
+        Ok(decoded)
+    }
+
+    /// Sends a Kademlia "add provider" request to a single peer, announcing that the local node
+    /// provides the given key and can be reached at the given addresses.
+    ///
+    /// The target doesn't send back any response. Success only indicates that the request has
+    /// been sent.
+    ///
+    /// Returns an error if there is no active connection with that peer.
+    pub async fn kademlia_add_provider(
+        &'_ self,
+        target: &PeerId,
+        now: TNow,
+        chain_index: usize,
+        key: &[u8],
+        local_addresses: impl Iterator<Item = multiaddr::Multiaddr>,
+    ) -> Result<(), KademliaAddProviderError> {
+        let local_peer_id = peer_id::PeerId::from_public_key(&peer_id::PublicKey::Ed25519(
+            *self.inner.noise_key().libp2p_public_ed25519_key(),
+        ));
+        let request_data =
+            kademlia::build_add_provider_request(key, &local_peer_id, local_addresses);
+        match self
+            .request(
+                now,
+                target,
+                self.protocol_index(chain_index, 2),
+                request_data,
+            )
+            .await
+        {
+            // The remote closes the substream after having read the request, as it doesn't
+            // send back any response.
+            Ok(_)
+            | Err(peers::RequestError::Connection(
+                connection::established::RequestError::SubstreamClosed,
+            )) => Ok(()),
+            Err(err) => Err(KademliaAddProviderError::RequestFailed(err)),
+        }
+    }
+
+    /// Allocates a [`PendingId`] and returns a [`StartConnect`] indicating a multiaddress that
+    /// the API user must try to dial.
+    ///
+    /// Later, the API user must use [`ChainNetwork::pending_outcome_ok`] or
+    /// [`ChainNetwork::pending_outcome_err`] to report how the connection attempt went.
+    ///
+    /// The returned [`StartConnect`] contains the [`StartConnect::timeout`] field. It is the
+    /// responsibility of the API user to ensure that [`ChainNetwork::pending_outcome_err`] is
+    /// called if this timeout is reached.
+    ///
+    /// If no outgoing connection is desired, the method waits until there is one.
+    // TODO: give more control, with number of slots and node choice
+    pub async fn next_start_connect<'a>(&self, now: TNow) -> StartConnect<TNow> {
+        loop {
+            let mut pending_lock = self.ephemeral_guarded.lock().await;
+            let pending = &mut *pending_lock; // Prevents borrow checker issues.
 
             // Ask the underlying state machine which nodes are desired but don't have any
             // associated connection attempt yet.
@@ -1637,7 +3146,25 @@ where
             // `PendingId`.
             let unfulfilled_desired_peers = self.inner.unfulfilled_desired_peers().await;
 
+            // No new dialing attempt is started if too many are already in progress, or if the
+            // limit to the number of connections has been reached.
+            let num_connections = self.inner.num_connections_matching(|_| true).await;
+            let unfulfilled_desired_peers = if pending.pending_ids.len()
+                < self.max_simultaneous_dials.get()
+                && num_connections + pending.pending_ids.len() < self.max_connections
+            {
+                either::Left(unfulfilled_desired_peers)
+            } else {
+                either::Right(iter::empty())
+            };
+
             for peer_id in unfulfilled_desired_peers {
+                // Banned peers are never dialed.
+                // TODO: nothing wakes up this function when a ban expires
+                if pending.reputations.is_banned(&now, &peer_id) {
+                    continue;
+                }
+
                 // TODO: allow more than one simultaneous dial per peer, and distribute the dials so that we don't just return the same peer multiple times in a row while there are other peers waiting
                 let entry = match pending.num_pending_per_peer.entry(peer_id) {
                     hashbrown::hash_map::Entry::Occupied(_) => continue,
@@ -1647,11 +3174,23 @@ where
                 let multiaddr: multiaddr::Multiaddr = if let Some(potential_addresses) =
                     pending.potential_addresses.get_mut(entry.key())
                 {
-                    if potential_addresses.is_empty() {
-                        continue;
-                    }
+                    // Addresses whose back-off delay following a failed dialing attempt hasn't
+                    // expired yet are skipped. This function isn't woken up when the delay
+                    // expires. See [`ChainNetwork::next_dial_backoff_expiration`].
+                    let address_book = &pending.address_book;
+                    let position = potential_addresses.iter().position(|addr| {
+                        address_book
+                            .get(&(entry.key().clone(), addr.clone()))
+                            .and_then(|state| state.dial_not_before.as_ref())
+                            .map_or(true, |not_before| *not_before <= now)
+                    });
+
+                    let position = match position {
+                        Some(p) => p,
+                        None => continue,
+                    };
 
-                    let addr = potential_addresses.remove(0);
+                    let addr = potential_addresses.remove(position);
                     if potential_addresses.is_empty() {
                         pending.potential_addresses.remove(entry.key());
                     }
@@ -1697,6 +3236,24 @@ where
         }
     }
 
+    /// Returns the earliest moment strictly after `now` when an address that
+    /// [`ChainNetwork::next_start_connect`] currently skips because of its back-off delay can be
+    /// dialed again, if any.
+    ///
+    /// [`ChainNetwork::next_start_connect`] isn't woken up when a back-off delay expires. The API
+    /// user is expected to call it again, with an updated `now`, once this moment is reached.
+    pub async fn next_dial_backoff_expiration(&self, now: &TNow) -> Option<TNow> {
+        self.ephemeral_guarded
+            .lock()
+            .await
+            .address_book
+            .values()
+            .filter_map(|state| state.dial_not_before.as_ref())
+            .filter(|not_before| **not_before > *now)
+            .min()
+            .cloned()
+    }
+
     /// Reads data coming from the connection, updates the internal state machine, and writes data
     /// destined to the connection through the [`ReadWrite`].
     ///
@@ -1720,6 +3277,106 @@ where
     pub async fn peers_list(&self) -> impl Iterator<Item = PeerId> {
         self.inner.peers_list().await
     }
+
+    /// Reports a misbehaviour of the given peer, lowering its reputation.
+    ///
+    /// If the score of the peer goes below [`reputation::Config::disconnect_threshold`], all the
+    /// connections with this peer are closed. If it goes below
+    /// [`reputation::Config::ban_threshold`], the peer additionally loses its slots and is no
+    /// longer connected to until the ban expires. Until then, its inbound connections, requests,
+    /// and substreams are refused.
+    pub async fn report_peer(
+        &self,
+        now: TNow,
+        peer_id: &PeerId,
+        penalty: reputation::Penalty,
+    ) -> reputation::ReportOutcome {
+        let mut ephemeral_guarded = self.ephemeral_guarded.lock().await;
+        let outcome = ephemeral_guarded.reputations.report(&now, peer_id, penalty);
+
+        if let reputation::ReportOutcome::Ban = outcome {
+            for chain_index in 0..self.num_chains {
+                if ephemeral_guarded
+                    .peerset
+                    .unassign(chain_index, peer_id)
+                    .is_some()
+                {
+                    // TODO: futures cancellation issue
+                    self.inner
+                        .set_peer_notifications_out_desired(
+                            peer_id,
+                            chain_index * NOTIFICATIONS_PROTOCOLS_PER_CHAIN,
+                            peers::DesiredState::NotDesired,
+                        )
+                        .await;
+                }
+            }
+        }
+
+        drop(ephemeral_guarded);
+
+        match outcome {
+            reputation::ReportOutcome::Nothing => {}
+            reputation::ReportOutcome::Disconnect | reputation::ReportOutcome::Ban => {
+                self.inner.disconnect(peer_id).await;
+            }
+        }
+
+        outcome
+    }
+
+    /// Returns the current reputation score of the given peer. Returns `0` for peers that have
+    /// never been reported or whose score has fully recovered.
+    pub async fn peer_reputation(&self, now: TNow, peer_id: &PeerId) -> i32 {
+        self.ephemeral_guarded
+            .lock()
+            .await
+            .reputations
+            .score(&now, peer_id)
+    }
+
+    /// Returns the list of peers whose reputation score isn't `0` or that are banned, with their
+    /// score and whether they are banned.
+    pub async fn peers_reputations(&self, now: TNow) -> Vec<(PeerId, i32, bool)> {
+        self.ephemeral_guarded
+            .lock()
+            .await
+            .reputations
+            .iter(&now)
+            .map(|(peer_id, score, banned)| (peer_id.clone(), score, banned))
+            .collect()
+    }
+
+    /// Returns the list of peers that have a slot on the given chain, with the direction of the
+    /// slot and its kind. The kind is `None` for outbound slots whose peer hasn't reported its
+    /// role yet.
+    ///
+    /// See also [`ChainConfig::slots`].
+    ///
+    /// # Panic
+    ///
+    /// Panics if `chain_index` is out of range.
+    ///
+    pub async fn slots(
+        &self,
+        chain_index: usize,
+    ) -> Vec<(PeerId, peerset::Direction, Option<peerset::SlotKind>)> {
+        self.ephemeral_guarded
+            .lock()
+            .await
+            .peerset
+            .slots(chain_index)
+            .map(|(peer_id, direction, kind)| (peer_id.clone(), direction, kind))
+            .collect()
+    }
+
+    /// Returns the average round-trip time of the pings sent to the given peer, or `None` if no
+    /// ping to this peer has succeeded yet.
+    ///
+    /// Can be used in order to prefer low-latency peers when sending out requests.
+    pub async fn peer_average_ping_time(&self, peer_id: &PeerId) -> Option<Duration> {
+        self.inner.peer_average_ping_time(peer_id).await
+    }
 }
 
 /// User must start connecting to the given multiaddress.
@@ -1792,12 +3449,46 @@ pub enum Event<'a, TNow> {
     },
 
     /// Received a GrandPa commit message from the network.
+    ///
+    /// Commit messages that don't target the authorities set or that don't finalize a block
+    /// above the ones indicated with [`ChainNetwork::set_local_grandpa_state`] are silently
+    /// discarded. The signatures of the commit, however, haven't been verified. If they turn out
+    /// to be invalid, the sender should be reported with [`ChainNetwork::report_peer`].
     GrandpaCommitMessage {
+        /// Identity of the sender of the commit message.
+        peer_id: peer_id::PeerId,
         /// Index of the chain the commit message relates to.
         chain_index: usize,
         message: EncodedGrandpaCommitMessage,
     },
 
+    /// Received a GrandPa neighbor packet from the network. Indicates the state of the peer
+    /// regarding the GrandPa protocol.
+    ///
+    /// If the peer is ahead of the local node, consider sending it a catch-up request with
+    /// [`ChainNetwork::send_grandpa_catch_up_request`].
+    GrandpaNeighborPacket {
+        /// Identity of the sender of the neighbor packet.
+        peer_id: peer_id::PeerId,
+        /// Index of the chain the neighbor packet relates to.
+        chain_index: usize,
+        /// State of the remote.
+        state: GrandpaState,
+    },
+
+    /// Received a GrandPa catch-up message from the network, normally in response to a call to
+    /// [`ChainNetwork::send_grandpa_catch_up_request`].
+    ///
+    /// > **Note**: Nothing guarantees that a catch-up request has actually been sent to this
+    /// >           peer beforehand.
+    GrandpaCatchUp {
+        /// Identity of the sender of the catch-up.
+        peer_id: peer_id::PeerId,
+        /// Index of the chain the catch-up relates to.
+        chain_index: usize,
+        catch_up: EncodedGrandpaCatchUp,
+    },
+
     /// Error in the protocol in a connection, such as failure to decode a message. This event
     /// doesn't have any consequence on the health of the connection, and is purely for diagnostic
     /// purposes.
@@ -1817,10 +3508,92 @@ pub enum Event<'a, TNow> {
         /// Object allowing sending back the answer.
         request: IdentifyRequestIn<'a, TNow>,
     },
-    /*Transactions {
+    /// A remote has sent a request for a storage proof.
+    ///
+    /// Can only happen if [`ChainConfig::serve_light_requests`] is `true` for the given chain.
+    /// You are strongly encouraged to call [`StorageProofRequestIn::respond`] or
+    /// [`StorageProofRequestIn::refuse`].
+    StorageProofRequestIn {
+        /// Remote that has sent the request.
+        peer_id: PeerId,
+        /// Index of the chain the request relates to.
+        chain_index: usize,
+        /// Object allowing sending back the answer.
+        request: StorageProofRequestIn<'a, TNow>,
+    },
+
+    /// A remote has sent a request for a call proof.
+    ///
+    /// Can only happen if [`ChainConfig::serve_light_requests`] is `true` for the given chain.
+    /// You are strongly encouraged to call [`CallProofRequestIn::respond`] or
+    /// [`CallProofRequestIn::refuse`].
+    CallProofRequestIn {
+        /// Remote that has sent the request.
+        peer_id: PeerId,
+        /// Index of the chain the request relates to.
+        chain_index: usize,
+        /// Object allowing sending back the answer.
+        request: CallProofRequestIn<'a, TNow>,
+    },
+
+    /// A remote has sent a GrandPa warp sync request.
+    ///
+    /// Can only happen if [`ChainConfig::serve_grandpa_warp_sync`] is `true` for the given
+    /// chain. You are strongly encouraged to call [`GrandpaWarpSyncRequestIn::respond`] or
+    /// [`GrandpaWarpSyncRequestIn::refuse`].
+    GrandpaWarpSyncRequestIn {
+        /// Remote that has sent the request.
+        peer_id: PeerId,
+        /// Index of the chain the request relates to.
+        chain_index: usize,
+        /// Object allowing sending back the answer.
+        request: GrandpaWarpSyncRequestIn<'a, TNow>,
+    },
+
+    /// Received a message on a collation substream.
+    ///
+    /// Can only happen if [`ChainConfig::collation_protocol`] is `true` for the given chain.
+    CollationMessage {
+        /// Identity of the sender of the message.
+        peer_id: peer_id::PeerId,
+        /// Index of the chain the message relates to.
+        chain_index: usize,
+        message: EncodedCollationMessage,
+    },
+
+    /// Received a list of transactions gossiped by a peer.
+    ///
+    /// Can only happen after a [`Event::ChainConnected`] with the given `PeerId` and chain index
+    /// combination has happened.
+    ///
+    /// > **Note**: The transactions haven't been validated in any way.
+    Transactions {
+        /// Identity of the sender of the transactions.
         peer_id: peer_id::PeerId,
+        /// Index of the chain the transactions relate to.
+        chain_index: usize,
         transactions: EncodedTransactions,
-    }*/
+    },
+}
+
+/// Error potentially returned by [`ChainNetwork::add_incoming_connection`].
+#[derive(Debug, Clone, derive_more::Display)]
+pub enum AddIncomingConnectionError {
+    /// Accepting the connection would exceed [`Config::max_connections`].
+    #[display(fmt = "Maximum number of connections reached")]
+    TooManyConnections,
+    /// Accepting the connection would exceed [`Config::max_incoming_connections_per_ip`].
+    #[display(fmt = "Maximum number of connections from this IP address reached")]
+    TooManyConnectionsFromIp,
+}
+
+/// Returns the IP address component at the start of the given multiaddress, if any.
+fn multiaddr_ip(addr: &multiaddr::Multiaddr) -> Option<multiaddr::Protocol> {
+    match addr.iter().next()? {
+        ip @ multiaddr::Protocol::Ip4(_) => Some(ip),
+        ip @ multiaddr::Protocol::Ip6(_) => Some(ip),
+        _ => None,
+    }
 }
 
 /// Error that can happen when trying to open an outbound notifications substream.
@@ -1836,6 +3609,9 @@ pub enum NotificationsOutErr {
         /// Hash of the genesis block of the chain according to the remote node.
         remote_genesis: [u8; 32],
     },
+    /// All the slots corresponding to the role of the remote are occupied.
+    #[display(fmt = "All the slots corresponding to the role of the remote are occupied")]
+    NoSlotAvailable,
 }
 
 /// Undecoded but valid block announce handshake.
@@ -1871,6 +3647,40 @@ impl fmt::Debug for EncodedBlockAnnounce {
     }
 }
 
+/// Undecoded but valid collation message.
+#[derive(Clone)]
+pub struct EncodedCollationMessage(Vec<u8>);
+
+impl EncodedCollationMessage {
+    /// Returns the decoded version of the message.
+    pub fn decode(&self) -> protocol::CollationMessageRef {
+        protocol::decode_collation_message(&self.0).unwrap()
+    }
+}
+
+impl fmt::Debug for EncodedCollationMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.decode(), f)
+    }
+}
+
+/// Undecoded but valid transactions notification.
+#[derive(Clone)]
+pub struct EncodedTransactions(Vec<u8>);
+
+impl EncodedTransactions {
+    /// Returns the list of SCALE-encoded transactions contained in the notification.
+    pub fn decode(&self) -> Vec<&[u8]> {
+        protocol::decode_transactions_notification(&self.0).unwrap()
+    }
+}
+
+impl fmt::Debug for EncodedTransactions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.decode(), f)
+    }
+}
+
 /// Undecoded but valid GrandPa commit message.
 #[derive(Clone)]
 pub struct EncodedGrandpaCommitMessage(Vec<u8>);
@@ -1897,6 +3707,32 @@ impl fmt::Debug for EncodedGrandpaCommitMessage {
     }
 }
 
+/// Undecoded but valid GrandPa catch-up message.
+#[derive(Clone)]
+pub struct EncodedGrandpaCatchUp(Vec<u8>);
+
+impl EncodedGrandpaCatchUp {
+    /// Returns the decoded version of the catch-up message.
+    pub fn decode(&self) -> protocol::CatchUpRef {
+        match protocol::decode_grandpa_notification(&self.0) {
+            Ok(protocol::GrandpaNotificationRef::CatchUp(msg)) => msg,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns a SCALE-encoded commit message whose target is the base of the catch-up. See
+    /// [`protocol::CatchUpRef::base_commit_message`].
+    pub fn base_commit_message(&self) -> Vec<u8> {
+        self.decode().base_commit_message()
+    }
+}
+
+impl fmt::Debug for EncodedGrandpaCatchUp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.decode(), f)
+    }
+}
+
 /// Successfull outcome to [`ChainNetwork::kademlia_discovery_round`].
 #[must_use]
 pub struct DiscoveryInsert<'a, TNow> {
@@ -1918,56 +3754,9 @@ where
 
     /// Insert the results in the [`ChainNetwork`].
     pub async fn insert(self) {
-        let mut lock = self.service.ephemeral_guarded.lock().await;
-        let lock = &mut *lock; // Avoids borrow checker issues.
-
-        let chain_index = self.chain_index;
-
-        for (peer_id, addrs) in self.outcome {
-            // Only proceed if we have out slots available.
-            if lock.chains[self.chain_index].out_peers.len()
-                >= usize::try_from(lock.chains[self.chain_index].chain_config.out_slots)
-                    .unwrap_or(usize::max_value())
-            {
-                break;
-            }
-
-            // Don't assign slots to peers that already have a slot.
-            if lock.chains[self.chain_index].out_peers.contains(&peer_id) {
-                continue;
-            }
-
-            // It is now guaranteed that this peer will be assigned an outbound slot.
-            // Add its addresses to the local directory.
-            let existing_addrs = lock.potential_addresses.entry(peer_id.clone()).or_default();
-            for addr in addrs {
-                if !existing_addrs.iter().any(|a| *a == addr) {
-                    existing_addrs.push(addr);
-                }
-            }
-
-            // It is possible that this peer already has an inbound slot, in which case we turn the
-            // inbound slot into an outbound slot.
-            if lock.chains[self.chain_index].in_peers.remove(&peer_id) {
-                lock.chains[self.chain_index].out_peers.insert(peer_id);
-                continue;
-            }
-
-            // TODO: hack
-            // TODO: futures cancellation issue
-            self.service
-                .inner
-                .set_peer_notifications_out_desired(
-                    &peer_id,
-                    chain_index * NOTIFICATIONS_PROTOCOLS_PER_CHAIN,
-                    peers::DesiredState::DesiredReset, // TODO: opinionated
-                )
-                .await;
-
-            lock.chains[self.chain_index].out_peers.insert(peer_id);
-        }
-
-        self.service.next_start_connect_waker.wake();
+        self.service
+            .insert_discovered_nodes(self.chain_index, self.outcome)
+            .await;
     }
 }
 
@@ -2028,6 +3817,293 @@ impl<'a, TNow> fmt::Debug for IdentifyRequestIn<'a, TNow> {
     }
 }
 
+/// See [`Event::StorageProofRequestIn`].
+#[must_use]
+pub struct StorageProofRequestIn<'a, TNow> {
+    service: &'a ChainNetwork<TNow>,
+    request_id: peers::RequestId,
+    block_hash: [u8; 32],
+    keys: Vec<Vec<u8>>,
+}
+
+impl<'a, TNow> StorageProofRequestIn<'a, TNow>
+where
+    TNow: Clone + Add<Duration, Output = TNow> + Sub<TNow, Output = Duration> + Ord,
+{
+    /// Returns the hash of the block whose storage is requested.
+    pub fn block_hash(&self) -> &[u8; 32] {
+        &self.block_hash
+    }
+
+    /// Returns the list of keys whose storage value is requested.
+    pub fn keys(&self) -> impl ExactSizeIterator<Item = &[u8]> {
+        self.keys.iter().map(|k| &k[..])
+    }
+
+    /// Queue the response to send back. The proof must contain the node values of the storage
+    /// trie of the block necessary to prove the storage values of all the requested keys. See
+    /// [`crate::trie::proof_encode`].
+    ///
+    /// Has no effect if the connection that sends the request no longer exists.
+    pub async fn respond(self, proof: impl Iterator<Item = impl AsRef<[u8]>>) {
+        let response =
+            protocol::build_storage_proof_response(proof).fold(Vec::new(), |mut a, b| {
+                a.extend_from_slice(b.as_ref());
+                a
+            });
+
+        let _ = self
+            .service
+            .inner
+            .respond(self.request_id, Ok(response))
+            .await;
+    }
+
+    /// Notifies the remote that the request can't be answered, for example because the storage
+    /// of the block isn't available locally.
+    ///
+    /// Has no effect if the connection that sends the request no longer exists.
+    pub async fn refuse(self) {
+        let _ = self.service.inner.respond(self.request_id, Err(())).await;
+    }
+
+    /// Turns this request into a [`DetachedStorageProofRequestIn`], which doesn't borrow the
+    /// [`ChainNetwork`]. This makes it possible to generate the proof in a different task.
+    pub fn detach(self) -> DetachedStorageProofRequestIn {
+        DetachedStorageProofRequestIn {
+            request_id: self.request_id,
+            block_hash: self.block_hash,
+            keys: self.keys,
+        }
+    }
+}
+
+impl<'a, TNow> fmt::Debug for StorageProofRequestIn<'a, TNow> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StorageProofRequestIn")
+            .field("block_hash", &self.block_hash)
+            .field("num_keys", &self.keys.len())
+            .finish()
+    }
+}
+
+/// See [`StorageProofRequestIn::detach`].
+#[must_use]
+pub struct DetachedStorageProofRequestIn {
+    request_id: peers::RequestId,
+    block_hash: [u8; 32],
+    keys: Vec<Vec<u8>>,
+}
+
+impl DetachedStorageProofRequestIn {
+    /// Returns the hash of the block whose storage is requested.
+    pub fn block_hash(&self) -> &[u8; 32] {
+        &self.block_hash
+    }
+
+    /// Returns the list of keys whose storage value is requested.
+    pub fn keys(&self) -> impl ExactSizeIterator<Item = &[u8]> {
+        self.keys.iter().map(|k| &k[..])
+    }
+
+    /// Turns this request back into a [`StorageProofRequestIn`], in order to answer it.
+    ///
+    /// `service` must be the [`ChainNetwork`] the request has been received from.
+    pub fn attach<TNow>(self, service: &ChainNetwork<TNow>) -> StorageProofRequestIn<TNow> {
+        StorageProofRequestIn {
+            service,
+            request_id: self.request_id,
+            block_hash: self.block_hash,
+            keys: self.keys,
+        }
+    }
+}
+
+impl fmt::Debug for DetachedStorageProofRequestIn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DetachedStorageProofRequestIn")
+            .field("block_hash", &self.block_hash)
+            .field("num_keys", &self.keys.len())
+            .finish()
+    }
+}
+
+/// See [`Event::CallProofRequestIn`].
+#[must_use]
+pub struct CallProofRequestIn<'a, TNow> {
+    service: &'a ChainNetwork<TNow>,
+    request_id: peers::RequestId,
+    request: protocol::CallProofRequest,
+}
+
+impl<'a, TNow> CallProofRequestIn<'a, TNow>
+where
+    TNow: Clone + Add<Duration, Output = TNow> + Sub<TNow, Output = Duration> + Ord,
+{
+    /// Returns the hash of the block against which the call must be performed.
+    pub fn block_hash(&self) -> &[u8; 32] {
+        &self.request.block_hash
+    }
+
+    /// Returns the name of the runtime function to call.
+    pub fn method(&self) -> &str {
+        &self.request.method
+    }
+
+    /// Returns the parameter to pass to the runtime function.
+    pub fn parameter(&self) -> &[u8] {
+        &self.request.parameter
+    }
+
+    /// Queue the response to send back. The proof must contain the node values of the storage
+    /// trie of the block necessary to prove all the storage entries accessed by the runtime
+    /// during the call, including `:code` and `:heappages`. See [`crate::trie::proof_encode`].
+    ///
+    /// Has no effect if the connection that sends the request no longer exists.
+    pub async fn respond(self, proof: impl Iterator<Item = impl AsRef<[u8]>>) {
+        let response = protocol::build_call_proof_response(proof).fold(Vec::new(), |mut a, b| {
+            a.extend_from_slice(b.as_ref());
+            a
+        });
+
+        let _ = self
+            .service
+            .inner
+            .respond(self.request_id, Ok(response))
+            .await;
+    }
+
+    /// Notifies the remote that the request can't be answered, for example because the storage
+    /// of the block isn't available locally.
+    ///
+    /// Has no effect if the connection that sends the request no longer exists.
+    pub async fn refuse(self) {
+        let _ = self.service.inner.respond(self.request_id, Err(())).await;
+    }
+
+    /// Turns this request into a [`DetachedCallProofRequestIn`], which doesn't borrow the
+    /// [`ChainNetwork`]. This makes it possible to generate the proof in a different task.
+    pub fn detach(self) -> DetachedCallProofRequestIn {
+        DetachedCallProofRequestIn {
+            request_id: self.request_id,
+            request: self.request,
+        }
+    }
+}
+
+impl<'a, TNow> fmt::Debug for CallProofRequestIn<'a, TNow> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CallProofRequestIn")
+            .field("block_hash", &self.request.block_hash)
+            .field("method", &self.request.method)
+            .finish()
+    }
+}
+
+/// See [`CallProofRequestIn::detach`].
+#[must_use]
+pub struct DetachedCallProofRequestIn {
+    request_id: peers::RequestId,
+    request: protocol::CallProofRequest,
+}
+
+impl DetachedCallProofRequestIn {
+    /// Returns the hash of the block against which the call must be performed.
+    pub fn block_hash(&self) -> &[u8; 32] {
+        &self.request.block_hash
+    }
+
+    /// Returns the name of the runtime function to call.
+    pub fn method(&self) -> &str {
+        &self.request.method
+    }
+
+    /// Returns the parameter to pass to the runtime function.
+    pub fn parameter(&self) -> &[u8] {
+        &self.request.parameter
+    }
+
+    /// Turns this request back into a [`CallProofRequestIn`], in order to answer it.
+    ///
+    /// `service` must be the [`ChainNetwork`] the request has been received from.
+    pub fn attach<TNow>(self, service: &ChainNetwork<TNow>) -> CallProofRequestIn<TNow> {
+        CallProofRequestIn {
+            service,
+            request_id: self.request_id,
+            request: self.request,
+        }
+    }
+}
+
+impl fmt::Debug for DetachedCallProofRequestIn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DetachedCallProofRequestIn")
+            .field("block_hash", &self.request.block_hash)
+            .field("method", &self.request.method)
+            .finish()
+    }
+}
+
+/// See [`Event::GrandpaWarpSyncRequestIn`].
+#[must_use]
+pub struct GrandpaWarpSyncRequestIn<'a, TNow> {
+    service: &'a ChainNetwork<TNow>,
+    request_id: peers::RequestId,
+    begin_hash: [u8; 32],
+}
+
+impl<'a, TNow> GrandpaWarpSyncRequestIn<'a, TNow>
+where
+    TNow: Clone + Add<Duration, Output = TNow> + Sub<TNow, Output = Duration> + Ord,
+{
+    /// Returns the hash of the block the proof must start from. The proof must only contain
+    /// blocks higher than this one.
+    pub fn begin_hash(&self) -> &[u8; 32] {
+        &self.begin_hash
+    }
+
+    /// Queue the response to send back. Must be passed a list of SCALE-encoded headers and
+    /// their corresponding SCALE-encoded GrandPa justification, ordered by ascending block
+    /// height. See [`protocol::GrandpaWarpSyncResponse`].
+    ///
+    /// Has no effect if the connection that sends the request no longer exists.
+    pub async fn respond(
+        self,
+        fragments: impl ExactSizeIterator<Item = (impl AsRef<[u8]>, impl AsRef<[u8]>)>,
+        is_finished: bool,
+    ) {
+        let response = protocol::build_grandpa_warp_sync_response(fragments, is_finished).fold(
+            Vec::new(),
+            |mut a, b| {
+                a.extend_from_slice(b.as_ref());
+                a
+            },
+        );
+
+        let _ = self
+            .service
+            .inner
+            .respond(self.request_id, Ok(response))
+            .await;
+    }
+
+    /// Notifies the remote that the request can't be answered, for example because the block
+    /// the proof must start from is unknown.
+    ///
+    /// Has no effect if the connection that sends the request no longer exists.
+    pub async fn refuse(self) {
+        let _ = self.service.inner.respond(self.request_id, Err(())).await;
+    }
+}
+
+impl<'a, TNow> fmt::Debug for GrandpaWarpSyncRequestIn<'a, TNow> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GrandpaWarpSyncRequestIn")
+            .field("begin_hash", &self.begin_hash)
+            .finish()
+    }
+}
+
 /// Error during [`ChainNetwork::kademlia_discovery_round`].
 #[derive(Debug, derive_more::Display)]
 pub enum DiscoveryError {
@@ -2042,6 +4118,28 @@ pub enum KademliaFindNodeError {
     DecodeError(kademlia::DecodeFindNodeResponseError),
 }
 
+/// Error during [`ChainNetwork::kademlia_get_providers`].
+#[derive(Debug, derive_more::Display)]
+pub enum KademliaGetProvidersError {
+    RequestFailed(peers::RequestError),
+    DecodeError(kademlia::DecodeGetProvidersResponseError),
+}
+
+/// Error returned by [`ChainNetwork::identify_request`].
+#[derive(Debug, derive_more::Display)]
+pub enum IdentifyRequestError {
+    Request(peers::RequestError),
+    Decode(protocol::DecodeIdentifyResponseError),
+    /// Public key found in the response doesn't match the identity of the peer.
+    PeerIdMismatch,
+}
+
+/// Error during [`ChainNetwork::kademlia_add_provider`].
+#[derive(Debug, derive_more::Display)]
+pub enum KademliaAddProviderError {
+    RequestFailed(peers::RequestError),
+}
+
 /// Error returned by [`ChainNetwork::blocks_request`].
 #[derive(Debug, derive_more::Display)]
 pub enum BlocksRequestError {
@@ -2092,4 +4190,163 @@ pub enum ProtocolError {
     BadBlockAnnounce(protocol::DecodeBlockAnnounceError),
     /// Error while decoding a received Grandpa notification.
     BadGrandpaNotification(protocol::DecodeGrandpaNotificationError),
+    /// Error while decoding a received transactions notification.
+    BadTransactionsNotification(protocol::DecodeTransactionsNotificationError),
+    /// Error while decoding a received collation message.
+    BadCollationMessage(protocol::DecodeCollationMessageError),
+    /// Error while decoding a received storage proof request.
+    BadStorageProofRequest(protocol::DecodeStorageProofRequestError),
+    /// Error while decoding a received call proof request.
+    BadCallProofRequest(protocol::DecodeCallProofRequestError),
+    /// Error while decoding a received GrandPa warp sync request.
+    BadGrandpaWarpSyncRequest(protocol::DecodeGrandpaWarpSyncRequestError),
+    /// Error while decoding a received Kademlia request.
+    BadKademliaRequest(kademlia::DecodeRequestError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ChainConfig, ChainNetwork, Config, ResponseSizeLimits, COLLATION_PROTOCOL_NAME,
+        COLLATION_PROTOCOL_OFFSET, NOTIFICATIONS_PROTOCOLS_PER_CHAIN,
+    };
+    use crate::libp2p::{connection::NoiseKey, multiaddr, peer_id};
+    use crate::network::{peerset, protocol, reputation};
+    use core::{num::NonZeroUsize, time::Duration};
+
+    fn chain(protocol_id: &str, collation_protocol: bool) -> ChainConfig {
+        ChainConfig {
+            protocol_id: protocol_id.to_owned(),
+            bootstrap_nodes: Vec::new(),
+            grandpa_protocol_config: None,
+            slots: peerset::SlotsConfig {
+                in_full: 1,
+                in_light: 1,
+                out_full: 1,
+                out_light: 0,
+            },
+            serve_light_requests: false,
+            serve_grandpa_warp_sync: false,
+            serve_kademlia_providers: false,
+            collation_protocol,
+            max_response_sizes: ResponseSizeLimits::default(),
+            reserved_only: false,
+            best_hash: [0; 32],
+            best_number: 0,
+            genesis_hash: [0; 32],
+            role: protocol::Role::Light,
+        }
+    }
+
+    fn network(chains: Vec<ChainConfig>) -> ChainNetwork<Duration> {
+        network_with_nodes(chains, Vec::new())
+    }
+
+    fn network_with_nodes(
+        chains: Vec<ChainConfig>,
+        known_nodes: Vec<(peer_id::PeerId, multiaddr::Multiaddr)>,
+    ) -> ChainNetwork<Duration> {
+        ChainNetwork::new(Config {
+            connections_capacity: 0,
+            peers_capacity: 0,
+            randomness_seed: [0; 32],
+            chains,
+            known_nodes,
+            noise_key: NoiseKey::new(&[0; 32]),
+            handshake_timeout: Duration::from_secs(8),
+            ping_interval: Duration::from_secs(20),
+            ping_timeout: Duration::from_secs(10),
+            idle_connection_timeout: None,
+            max_connections: 16,
+            max_incoming_connections_per_ip: 4,
+            max_simultaneous_dials: NonZeroUsize::new(4).unwrap(),
+            dial_backoff_initial: Duration::from_secs(2),
+            dial_backoff_max: Duration::from_secs(300),
+            pending_api_events_buffer_size: NonZeroUsize::new(16).unwrap(),
+            reputation: reputation::Config {
+                randomness_seed: [0; 32],
+                disconnect_threshold: -200,
+                ban_threshold: -500,
+                ban_duration: Duration::from_secs(60),
+                recovery_per_second: 2,
+                max_peers: 1024,
+            },
+            request_receive_window: 1024 * 1024,
+            notifications_receive_window: 256 * 1024,
+            max_pending_response_bytes: 1024 * 1024,
+        })
+    }
+
+    #[test]
+    fn notification_protocol_names_unique() {
+        let network = network(vec![
+            chain("dot", true),
+            chain("ksm", false),
+            chain("wnd", false),
+        ]);
+
+        let names = network
+            .inner
+            .notification_protocols()
+            .map(|p| p.protocol_name.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(names.len(), 3 * NOTIFICATIONS_PROTOCOLS_PER_CHAIN);
+
+        // The collation protocol name is only used by the chain that takes part in it.
+        assert_eq!(names[COLLATION_PROTOCOL_OFFSET], COLLATION_PROTOCOL_NAME);
+        assert_eq!(
+            names
+                .iter()
+                .filter(|n| *n == COLLATION_PROTOCOL_NAME)
+                .count(),
+            1
+        );
+
+        // All the protocols of the chains are distinct, with the exception of GrandPa.
+        for (index, name) in names.iter().enumerate() {
+            if name == "/paritytech/grandpa/1" {
+                continue;
+            }
+            assert!(names.iter().skip(index + 1).all(|n| n != name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn reserved_only_peers_allowed() {
+        let reserved_peer = peer_id::PeerId::from_public_key(&peer_id::PublicKey::Ed25519([1; 32]));
+        let other_peer = peer_id::PeerId::from_public_key(&peer_id::PublicKey::Ed25519([2; 32]));
+        let known_nodes = vec![(
+            reserved_peer.clone(),
+            "/ip4/1.2.3.4/tcp/30333".parse().unwrap(),
+        )];
+
+        let reserved_chain = || {
+            let mut config = chain("dot", false);
+            config.reserved_only = true;
+            config.bootstrap_nodes = vec![0];
+            config
+        };
+
+        // Only the reserved peer is allowed if all the chains are in reserved-only mode.
+        let network = network_with_nodes(vec![reserved_chain()], known_nodes.clone());
+        let ephemeral_guarded = network.ephemeral_guarded.try_lock().unwrap();
+        assert!(ephemeral_guarded.chains[0].is_peer_allowed(&reserved_peer));
+        assert!(!ephemeral_guarded.chains[0].is_peer_allowed(&other_peer));
+        assert!(ephemeral_guarded.is_peer_allowed_any_chain(&reserved_peer));
+        assert!(!ephemeral_guarded.is_peer_allowed_any_chain(&other_peer));
+        drop(ephemeral_guarded);
+
+        // Any peer is allowed as long as one chain isn't in reserved-only mode.
+        let network = network_with_nodes(vec![reserved_chain(), chain("ksm", false)], known_nodes);
+        let ephemeral_guarded = network.ephemeral_guarded.try_lock().unwrap();
+        assert!(!ephemeral_guarded.chains[0].is_peer_allowed(&other_peer));
+        assert!(ephemeral_guarded.chains[1].is_peer_allowed(&other_peer));
+        assert!(ephemeral_guarded.is_peer_allowed_any_chain(&other_peer));
+    }
+
+    #[test]
+    #[should_panic]
+    fn multiple_collation_chains_refused() {
+        network(vec![chain("dot", true), chain("ksm", true)]);
+    }
 }
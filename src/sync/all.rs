@@ -48,6 +48,8 @@ use core::{
     time::Duration,
 };
 
+pub use all_forks::FinalityProof;
+
 /// Configuration for the [`AllSync`].
 // TODO: review these fields
 #[derive(Debug)]
@@ -74,6 +76,27 @@ pub struct Config {
     /// See [`all_forks::Config::max_requests_per_block`] for more information.
     pub max_requests_per_block: NonZeroU32,
 
+    /// Minimum number of distinct sources that must know about a block before this block is
+    /// verified.
+    ///
+    /// Only applies after the state machine has reached the head of the chain. Set to `1` in
+    /// order to disable this check.
+    ///
+    /// See [`all_forks::Config::min_sources_per_block`] for more information.
+    pub min_sources_per_block: NonZeroU32,
+
+    /// List of hashes of blocks that are known to be bad.
+    ///
+    /// See [`all_forks::Config::banned_blocks`] and [`optimistic::Config::banned_blocks`] for
+    /// more information.
+    pub banned_blocks: Vec<[u8; 32]>,
+
+    /// List of block heights and hashes that the canonical chain must go through.
+    ///
+    /// See [`all_forks::Config::fork_blocks`] and [`optimistic::Config::fork_blocks`] for more
+    /// information.
+    pub fork_blocks: Vec<(u64, [u8; 32])>,
+
     /// Number of blocks to download ahead of the best verified block.
     ///
     /// Whenever the latest best block is updated, the state machine will start block
@@ -127,6 +150,8 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
                         sources_capacity: config.sources_capacity,
                         blocks_capacity: config.blocks_capacity,
                         download_ahead_blocks: config.download_ahead_blocks,
+                        banned_blocks: config.banned_blocks.clone(),
+                        fork_blocks: config.fork_blocks.clone(),
                         full: Some(optimistic::ConfigFull {
                             finalized_runtime: config_full.finalized_runtime,
                         }),
@@ -148,6 +173,9 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
                 blocks_capacity: config.blocks_capacity,
                 max_disjoint_headers: config.max_disjoint_headers,
                 max_requests_per_block: config.max_requests_per_block,
+                min_sources_per_block: config.min_sources_per_block,
+                banned_blocks: config.banned_blocks,
+                fork_blocks: config.fork_blocks,
             },
         }
     }
@@ -233,15 +261,72 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
         }
     }
 
-    /// Returns true if it is believed that we are near the head of the chain.
+    /// Returns the information about the given non-finalized block, including its body and its
+    /// changes to the storage.
+    ///
+    /// Returns `None` if the block is unknown, or if its body isn't verified by the current
+    /// syncing strategy.
+    // TODO: leaky type
+    pub fn non_finalized_block(&mut self, hash: &[u8; 32]) -> Option<&optimistic::Block<TBl>> {
+        match &mut self.inner {
+            AllSyncInner::Optimistic { inner } => inner.non_finalized_block(hash),
+            AllSyncInner::AllForks(_) | AllSyncInner::GrandpaWarpSync { .. } => None,
+            AllSyncInner::Poisoned => unreachable!(),
+        }
+    }
+
+    /// Returns the proof that the current finalized block is indeed finalized, if known.
     ///
-    /// The way this method is implemented is opaque and cannot be relied on. The return value
-    /// should only ever be shown to the user and not used for any meaningful logic.
-    pub fn is_near_head_of_chain_heuristic(&self) -> bool {
+    /// Always returns `None` if the chain isn't in the "all forks" syncing strategy. See
+    /// [`all_forks::AllForksSync::finality_proof`] for more information.
+    pub fn finality_proof(&self) -> Option<&FinalityProof> {
         match &self.inner {
-            AllSyncInner::AllForks(_) => true,
-            AllSyncInner::Optimistic { .. } => false,
-            AllSyncInner::GrandpaWarpSync { .. } => false,
+            AllSyncInner::AllForks(sync) => sync.finality_proof(),
+            AllSyncInner::Optimistic { .. } | AllSyncInner::GrandpaWarpSync { .. } => None,
+            AllSyncInner::Poisoned => unreachable!(),
+        }
+    }
+
+    /// Removes from the chain the non-finalized blocks that belong to forks branching off from
+    /// the best chain at a height inferior or equal to the height of the best block minus
+    /// `max_depth`.
+    ///
+    /// This is meant to be called in order to bound the memory usage of the state machine when
+    /// the finality of the chain is stalled. Does nothing if the chain isn't in the
+    /// "all forks" syncing strategy, as the other strategies only track the best chain.
+    ///
+    /// Returns the list of blocks that have been removed, in an unspecified order.
+    pub fn prune_old_forks(&mut self, max_depth: u64) -> Vec<(header::Header, TBl)> {
+        match &mut self.inner {
+            AllSyncInner::AllForks(sync) => sync.prune_old_forks(max_depth),
+            AllSyncInner::Optimistic { .. } | AllSyncInner::GrandpaWarpSync { .. } => Vec::new(),
+            AllSyncInner::Poisoned => unreachable!(),
+        }
+    }
+
+    /// Returns the current status of the syncing.
+    ///
+    /// The value returned by this method depends on the syncing strategy currently in use and on
+    /// the best blocks reported by the sources. It is meant to be shown to the user, and isn't
+    /// meant to be used for any meaningful logic.
+    pub fn status(&self) -> Status {
+        // Highest best block reported by any of the sources, if any.
+        let highest_source_block_number = self
+            .sources()
+            .map(|source_id| self.source_best_block(source_id).0)
+            .max();
+
+        match &self.inner {
+            AllSyncInner::AllForks(_) => Status::NearHead,
+            AllSyncInner::Optimistic { .. } => Status::DownloadingBlocks {
+                behind_by: highest_source_block_number
+                    .unwrap_or(0)
+                    .saturating_sub(self.best_block_number()),
+            },
+            AllSyncInner::GrandpaWarpSync { .. } => Status::WarpSyncing {
+                finalized_block_number: self.finalized_block_header().number,
+                highest_source_block_number,
+            },
             AllSyncInner::Poisoned => unreachable!(),
         }
     }
@@ -1409,6 +1494,31 @@ pub struct BlockRequestSuccessBlock<TBl> {
     pub user_data: TBl,
 }
 
+/// Status of the syncing. See [`AllSync::status`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    /// A GrandPa warp sync is in progress. The finalized block jumps forward as the warp sync
+    /// proof is being downloaded and verified.
+    WarpSyncing {
+        /// Height of the latest block whose finality has been verified.
+        finalized_block_number: u64,
+        /// Highest best block reported by the sources, or `None` if there isn't any source.
+        highest_source_block_number: Option<u64>,
+    },
+
+    /// Blocks are being downloaded and verified one after the other in order to catch up with
+    /// the head of the chain.
+    DownloadingBlocks {
+        /// Number of blocks between the local best block and the highest best block reported by
+        /// the sources. Equal to 0 if the highest block isn't known, for example if there isn't
+        /// any source.
+        behind_by: u64,
+    },
+
+    /// The local best block is believed to be near the head of the chain.
+    NearHead,
+}
+
 /// Outcome of calling [`AllSync::block_announce`].
 pub enum BlockAnnounceOutcome {
     /// Header is ready to be verified. Calling [`AllSync::process_one`] might yield that block.
@@ -1708,6 +1818,9 @@ pub enum BlockVerification<TRq, TSrc, TBl> {
     /// Fetching the key of the finalized block storage that follows a given one is required in
     /// order to continue.
     FinalizedStorageNextKey(StorageNextKey<TRq, TSrc, TBl>),
+
+    /// Verifying whether a batch of signatures is valid is required in order to continue.
+    SignatureBatchVerify(SignatureBatchVerify<TRq, TSrc, TBl>),
 }
 
 /// Error that can happen when verifying a block body.
@@ -1808,6 +1921,13 @@ impl<TRq, TSrc, TBl> BlockVerification<TRq, TSrc, TBl> {
                     user_data,
                 })
             }
+            optimistic::BlockVerification::SignatureBatchVerify(inner) => {
+                BlockVerification::SignatureBatchVerify(SignatureBatchVerify {
+                    inner,
+                    shared,
+                    user_data,
+                })
+            }
         }
     }
 }
@@ -1833,11 +1953,24 @@ impl<TRq, TSrc, TBl> StorageGet<TRq, TSrc, TBl> {
         self.inner.key_as_vec()
     }
 
+    /// If `Some`, the key must be read from the child trie whose key is returned rather than from
+    /// the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.inner.child_trie()
+    }
+
     /// Injects the corresponding storage value.
     pub fn inject_value(self, value: Option<&[u8]>) -> BlockVerification<TRq, TSrc, TBl> {
         let inner = self.inner.inject_value(value);
         BlockVerification::from_inner(inner, self.shared, self.user_data)
     }
+
+    /// Stops the verification, as the requested storage can't be provided. The block is then
+    /// considered as failing to verify.
+    pub fn inject_unavailable(self) -> BlockVerification<TRq, TSrc, TBl> {
+        let inner = self.inner.inject_unavailable();
+        BlockVerification::from_inner(inner, self.shared, self.user_data)
+    }
 }
 
 /// Fetching the list of keys with a given prefix is required in order to continue.
@@ -1858,6 +1991,12 @@ impl<TRq, TSrc, TBl> StoragePrefixKeys<TRq, TSrc, TBl> {
         self.inner.prefix()
     }
 
+    /// If `Some`, the keys must be loaded from the child trie whose key is returned rather than
+    /// from the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.inner.child_trie()
+    }
+
     /// Injects the list of keys ordered lexicographically.
     pub fn inject_keys_ordered(
         self,
@@ -1866,6 +2005,13 @@ impl<TRq, TSrc, TBl> StoragePrefixKeys<TRq, TSrc, TBl> {
         let inner = self.inner.inject_keys_ordered(keys);
         BlockVerification::from_inner(inner, self.shared, self.user_data)
     }
+
+    /// Stops the verification, as the requested storage can't be provided. The block is then
+    /// considered as failing to verify.
+    pub fn inject_unavailable(self) -> BlockVerification<TRq, TSrc, TBl> {
+        let inner = self.inner.inject_unavailable();
+        BlockVerification::from_inner(inner, self.shared, self.user_data)
+    }
 }
 
 /// Fetching the key that follows a given one is required in order to continue.
@@ -1882,6 +2028,12 @@ impl<TRq, TSrc, TBl> StorageNextKey<TRq, TSrc, TBl> {
         self.inner.key()
     }
 
+    /// If `Some`, the key must be searched in the child trie whose key is returned rather than
+    /// in the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.inner.child_trie()
+    }
+
     /// Injects the key.
     ///
     /// # Panic
@@ -1892,6 +2044,45 @@ impl<TRq, TSrc, TBl> StorageNextKey<TRq, TSrc, TBl> {
         let inner = self.inner.inject_key(key);
         BlockVerification::from_inner(inner, self.shared, self.user_data)
     }
+
+    /// Stops the verification, as the requested storage can't be provided. The block is then
+    /// considered as failing to verify.
+    pub fn inject_unavailable(self) -> BlockVerification<TRq, TSrc, TBl> {
+        let inner = self.inner.inject_unavailable();
+        BlockVerification::from_inner(inner, self.shared, self.user_data)
+    }
+}
+
+/// Verifying whether a batch of signatures is valid is required in order to continue.
+#[must_use]
+pub struct SignatureBatchVerify<TRq, TSrc, TBl> {
+    inner: optimistic::SignatureBatchVerify<
+        OptimisticRequestExtra<TRq>,
+        OptimisticSourceExtra<TSrc>,
+        TBl,
+    >,
+    shared: Shared<TRq>,
+    user_data: TBl,
+}
+
+impl<TRq, TSrc, TBl> SignatureBatchVerify<TRq, TSrc, TBl> {
+    /// Returns the signatures of the batch.
+    pub fn signatures(&self) -> &[host::SignatureVerification] {
+        self.inner.signatures()
+    }
+
+    /// Resumes the verification. `all_valid` must be `true` if and only if all the signatures
+    /// of the batch are valid.
+    pub fn resume(self, all_valid: bool) -> BlockVerification<TRq, TSrc, TBl> {
+        let inner = self.inner.resume(all_valid);
+        BlockVerification::from_inner(inner, self.shared, self.user_data)
+    }
+
+    /// Verifies the signatures one after the other, then resumes the verification.
+    pub fn verify_and_resume(self) -> BlockVerification<TRq, TSrc, TBl> {
+        let inner = self.inner.verify_and_resume();
+        BlockVerification::from_inner(inner, self.shared, self.user_data)
+    }
 }
 
 enum AllSyncInner<TRq, TSrc, TBl> {
@@ -1952,6 +2143,12 @@ struct Shared<TRq> {
     max_disjoint_headers: usize,
     /// Value passed through [`Config::max_requests_per_block`].
     max_requests_per_block: NonZeroU32,
+    /// Value passed through [`Config::min_sources_per_block`].
+    min_sources_per_block: NonZeroU32,
+    /// Value passed through [`Config::banned_blocks`].
+    banned_blocks: Vec<[u8; 32]>,
+    /// Value passed through [`Config::fork_blocks`].
+    fork_blocks: Vec<(u64, [u8; 32])>,
 }
 
 impl<TRq> Shared<TRq> {
@@ -1967,6 +2164,9 @@ impl<TRq> Shared<TRq> {
             blocks_capacity: self.blocks_capacity,
             max_disjoint_headers: self.max_disjoint_headers,
             max_requests_per_block: self.max_requests_per_block,
+            min_sources_per_block: self.min_sources_per_block,
+            banned_blocks: self.banned_blocks.clone(),
+            fork_blocks: self.fork_blocks.clone(),
             full: false,
         });
 
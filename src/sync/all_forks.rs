@@ -88,7 +88,7 @@ use crate::{
 };
 
 use alloc::vec::Vec;
-use core::{num::NonZeroU32, time::Duration};
+use core::{convert::TryFrom as _, num::NonZeroU32, time::Duration};
 
 mod disjoint;
 mod pending_blocks;
@@ -145,6 +145,39 @@ pub struct Config {
     /// The higher the value, the more bandwidth is potentially wasted.
     pub max_requests_per_block: NonZeroU32,
 
+    /// Minimum number of distinct sources that must know about a block before this block is
+    /// verified and added to the chain. A source is considered as knowing about a block if it
+    /// has announced this block or one of its descendants, or has provided it in response to a
+    /// request.
+    ///
+    /// Setting this value to a number higher than `1` protects against a single malicious source
+    /// feeding blocks that the rest of the network isn't aware of, for example in an eclipse
+    /// attack. A block that isn't known by enough sources stays in the queue of unverified blocks
+    /// until more sources report it.
+    ///
+    /// In order to bound the memory usage, blocks that aren't known by enough sources are
+    /// discarded, highest blocks first, when the number of unverified blocks exceeds
+    /// [`Config::max_disjoint_headers`].
+    ///
+    /// > **Note**: If the number of sources is inferior to this value, no block will ever be
+    /// >           verified.
+    pub min_sources_per_block: NonZeroU32,
+
+    /// List of hashes of blocks that are known to be bad. These blocks, and all their
+    /// descendants, are never verified nor added to the chain.
+    ///
+    /// > **Note**: This list is typically filled with the list of bad blocks found in the chain
+    /// >           specification.
+    pub banned_blocks: Vec<[u8; 32]>,
+
+    /// List of block heights and hashes. Blocks whose height is found in this list but whose
+    /// hash doesn't match, and all their descendants, are never verified nor added to the
+    /// chain.
+    ///
+    /// > **Note**: This list is typically filled with the list of fork blocks found in the chain
+    /// >           specification.
+    pub fork_blocks: Vec<(u64, [u8; 32])>,
+
     /// If true, the block bodies and storage are also synchronized.
     pub full: bool,
 }
@@ -162,6 +195,16 @@ pub struct AllForksSync<TBl, TRq, TSrc> {
 /// Extra fields. In a separate structure in order to be moved around.
 struct Inner<TRq, TSrc> {
     blocks: pending_blocks::PendingBlocks<PendingBlock, TRq, TSrc>,
+
+    /// See [`Config::min_sources_per_block`].
+    min_sources_per_block: NonZeroU32,
+
+    /// See [`Config::max_disjoint_headers`].
+    max_disjoint_headers: usize,
+
+    /// Proof of finality of the current finalized block, if known.
+    /// See [`AllForksSync::finality_proof`].
+    finality_proof: Option<FinalityProof>,
 }
 
 struct PendingBlock {
@@ -198,8 +241,12 @@ impl<TBl, TRq, TSrc> AllForksSync<TBl, TRq, TSrc> {
                     max_requests_per_block: config.max_requests_per_block,
                     sources_capacity: config.sources_capacity,
                     verify_bodies: config.full,
-                    banned_blocks: Vec::new(), // TODO:
+                    banned_blocks: config.banned_blocks,
+                    fork_blocks: config.fork_blocks,
                 }),
+                min_sources_per_block: config.min_sources_per_block,
+                max_disjoint_headers: config.max_disjoint_headers,
+                finality_proof: None,
             },
         }
     }
@@ -252,6 +299,31 @@ impl<TBl, TRq, TSrc> AllForksSync<TBl, TRq, TSrc> {
         self.chain.iter_ancestry_order()
     }
 
+    /// Returns the proof that the current finalized block is indeed finalized.
+    ///
+    /// The proof is the justification or commit message that has been verified and that has led
+    /// to this block being finalized. Returns `None` if the finalized block hasn't been
+    /// finalized by this state machine, for example if it is the block that was passed at
+    /// initialization.
+    pub fn finality_proof(&self) -> Option<&FinalityProof> {
+        self.inner.finality_proof.as_ref()
+    }
+
+    /// Removes from the chain the non-finalized blocks that belong to forks branching off from
+    /// the best chain at a height inferior or equal to the height of the best block minus
+    /// `max_depth`.
+    ///
+    /// See [`blocks_tree::NonFinalizedTree::prune_old_forks`] for more details.
+    ///
+    /// Returns the list of blocks that have been removed, in an unspecified order.
+    pub fn prune_old_forks(&mut self, max_depth: u64) -> Vec<(header::Header, TBl)> {
+        self.chain
+            .prune_old_forks(max_depth)
+            .into_iter()
+            .map(|(header, block)| (header, block.user_data))
+            .collect()
+    }
+
     /// Inform the [`AllForksSync`] of a new potential source of blocks.
     ///
     /// The `user_data` parameter is opaque and decided entirely by the user. It can later be
@@ -676,6 +748,8 @@ impl<TBl, TRq, TSrc> AllForksSync<TBl, TRq, TSrc> {
         {
             Ok(apply) => {
                 apply.apply();
+                self.inner.finality_proof =
+                    Some(FinalityProof::GrandpaCommit(scale_encoded_message.to_vec()));
                 Ok(())
             }
             // In case where the commit message concerns a block older or equal to the finalized
@@ -694,12 +768,22 @@ impl<TBl, TRq, TSrc> AllForksSync<TBl, TRq, TSrc> {
     ///
     /// This method takes ownership of the [`AllForksSync`] and starts a verification
     /// process. The [`AllForksSync`] is yielded back at the end of this process.
+    ///
+    /// Blocks that aren't known by at least [`Config::min_sources_per_block`] sources are
+    /// skipped.
     pub fn process_one(self) -> ProcessOne<TBl, TRq, TSrc> {
         let block = self.inner.blocks.unverified_leaves().find(|block| {
-            block.parent_block_hash == self.chain.finalized_block_hash()
+            let parent_known = block.parent_block_hash == self.chain.finalized_block_hash()
                 || self
                     .chain
-                    .contains_non_finalized_block(&block.parent_block_hash)
+                    .contains_non_finalized_block(&block.parent_block_hash);
+
+            parent_known
+                && self
+                    .inner
+                    .blocks
+                    .num_sources_knowing_block_or_descendant(block.block_number, &block.block_hash)
+                    >= usize::try_from(self.inner.min_sources_per_block.get()).unwrap()
         });
 
         if let Some(block) = block {
@@ -712,6 +796,42 @@ impl<TBl, TRq, TSrc> AllForksSync<TBl, TRq, TSrc> {
         }
     }
 
+    /// Removes from the list of unverified blocks the blocks that aren't known by at least
+    /// [`Config::min_sources_per_block`] sources, highest blocks first, until the number of
+    /// unverified blocks is inferior or equal to [`Config::max_disjoint_headers`].
+    ///
+    /// Without this, a source could make the list of unverified blocks grow without bound by
+    /// sending blocks that the other sources don't know about.
+    fn prune_unconfirmed_blocks(&mut self) {
+        let excess = self
+            .inner
+            .blocks
+            .num_blocks()
+            .saturating_sub(self.inner.max_disjoint_headers);
+        if excess == 0 {
+            return;
+        }
+
+        let min_sources = usize::try_from(self.inner.min_sources_per_block.get()).unwrap();
+        let mut to_remove = self
+            .inner
+            .blocks
+            .blocks_iter()
+            .filter(|(height, hash)| {
+                self.inner
+                    .blocks
+                    .num_sources_knowing_block_or_descendant(*height, hash)
+                    < min_sources
+            })
+            .map(|(height, hash)| (height, *hash))
+            .collect::<Vec<_>>();
+        to_remove.sort_unstable_by(|(h1, _), (h2, _)| h2.cmp(h1));
+
+        for (height, hash) in to_remove.into_iter().take(excess) {
+            self.inner.blocks.remove(height, &hash);
+        }
+    }
+
     /// Called when a source reports a header and an optional body, either through a block
     /// announce, an ancestry search result, or a block request, and so on.
     ///
@@ -831,6 +951,8 @@ impl<TBl, TRq, TSrc> AllForksSync<TBl, TRq, TSrc> {
         //       same as here? since justifications aren't immediately verified, it is possible
         //       for a malicious peer to send us bad justifications
 
+        self.prune_unconfirmed_blocks();
+
         // Block is not part of the finalized chain.
         if header.number == self.chain.finalized_block_header().number + 1
             && *header.parent_hash != self.chain.finalized_block_hash()
@@ -1095,6 +1217,8 @@ impl<TBl, TRq, TSrc> HeaderVerify<TBl, TRq, TSrc> {
                         .inner
                         .blocks
                         .set_finalized_block_height(finalized.last().unwrap().0.number);
+                    self.parent.inner.finality_proof =
+                        Some(FinalityProof::GrandpaJustification(justification));
                     JustificationVerification::NewFinalized(finalized)
                 }
                 Err(err) => JustificationVerification::JustificationVerificationError(err),
@@ -1139,6 +1263,17 @@ pub enum ProcessOne<TBl, TRq, TSrc> {
     HeaderVerify(HeaderVerify<TBl, TRq, TSrc>),
 }
 
+/// Proof that a block has been finalized. See [`AllForksSync::finality_proof`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FinalityProof {
+    /// SCALE-encoded GrandPa justification whose target is the finalized block, as found in
+    /// block responses.
+    GrandpaJustification(Vec<u8>),
+    /// SCALE-encoded GrandPa commit message whose target is the finalized block, as gossiped on
+    /// the network.
+    GrandpaCommit(Vec<u8>),
+}
+
 /// Outcome of calling [`HeaderVerify::perform`].
 pub enum HeaderVerifyOutcome<TBl, TRq, TSrc> {
     /// Header has been successfully verified.
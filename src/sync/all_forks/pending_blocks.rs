@@ -71,6 +71,10 @@
 //! recursive, such that not only direct children but all descendants of a bad block are
 //! automatically marked as bad.
 //!
+//! Blocks listed in [`Config::banned_blocks`], and blocks whose height is found in
+//! [`Config::fork_blocks`] but whose hash doesn't match, are automatically marked as bad when
+//! they are inserted.
+//!
 //! # Requests
 //!
 //! Call [`PendingBlocks::desired_requests`] or [`PendingBlocks::source_desired_requests`] to
@@ -87,7 +91,11 @@
 
 use super::{disjoint, sources};
 
-use alloc::{collections::BTreeSet, vec::Vec};
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    vec,
+    vec::Vec,
+};
 use core::{
     convert::TryFrom as _,
     iter,
@@ -133,8 +141,15 @@ pub struct Config {
     /// > **Note**: This list is typically filled with a list of blocks found in the chain
     /// >           specification. It is part of the "trusted setup" of the node, in other words
     /// >           the information that is passed by the user and blindly assumed to be true.
-    // TODO: unused
-    pub banned_blocks: Vec<[u8; 64]>,
+    pub banned_blocks: Vec<[u8; 32]>,
+
+    /// List of block heights and hashes. Any block whose height is found in this list but whose
+    /// hash doesn't match the one in the list is considered as bad.
+    ///
+    /// > **Note**: This list is typically filled with a list of blocks found in the chain
+    /// >           specification, and is used in order to force the choice of one side of a
+    /// >           contentious fork.
+    pub fork_blocks: Vec<(u64, [u8; 32])>,
 }
 
 /// State of a block in the data structure.
@@ -180,6 +195,12 @@ pub struct PendingBlocks<TBl, TRq, TSrc> {
     /// See [`Config::verify_bodies`].
     verify_bodies: bool,
 
+    /// See [`Config::banned_blocks`].
+    banned_blocks: BTreeSet<[u8; 32]>,
+
+    /// See [`Config::fork_blocks`]. Keys are block heights and values block hashes.
+    fork_blocks: BTreeMap<u64, [u8; 32]>,
+
     /// Set of `(block_height, block_hash, request_id)`.
     /// Contains the list of all requests, associated to their block.
     ///
@@ -238,6 +259,8 @@ impl<TBl, TRq, TSrc> PendingBlocks<TBl, TRq, TSrc> {
             ),
             blocks: disjoint::DisjointBlocks::with_capacity(config.blocks_capacity),
             verify_bodies: config.verify_bodies,
+            banned_blocks: config.banned_blocks.into_iter().collect(),
+            fork_blocks: config.fork_blocks.into_iter().collect(),
             blocks_requests: Default::default(),
             requested_blocks: Default::default(),
             source_occupations: Default::default(),
@@ -420,6 +443,32 @@ impl<TBl, TRq, TSrc> PendingBlocks<TBl, TRq, TSrc> {
         self.sources.knows_non_finalized_block(height, hash)
     }
 
+    /// Returns the number of distinct sources that know about the given block, either directly
+    /// or because they know about one of the descendants of this block that are in the list of
+    /// unverified blocks.
+    ///
+    /// A source that knows about a block necessarily knows about all of its ancestors.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `height` is inferior or equal to the finalized block height.
+    ///
+    pub fn num_sources_knowing_block_or_descendant(&self, height: u64, hash: &[u8; 32]) -> usize {
+        let mut sources = BTreeSet::new();
+        let mut to_visit = vec![(height, *hash)];
+
+        while let Some((height, hash)) = to_visit.pop() {
+            sources.extend(self.sources.knows_non_finalized_block(height, &hash));
+            to_visit.extend(
+                self.blocks
+                    .children(height, &hash)
+                    .map(|(child_height, child_hash, _)| (child_height, *child_hash)),
+            );
+        }
+
+        sources.len()
+    }
+
     /// Returns true if [`PendingBlocks::add_known_block`] or [`PendingBlocks::set_best_block`]
     /// has earlier been called on this source with this height and hash, or if the source was
     /// originally created (using [`PendingBlocks::add_source`]) with this height and hash.
@@ -485,6 +534,9 @@ impl<TBl, TRq, TSrc> PendingBlocks<TBl, TRq, TSrc> {
 
     /// Inserts an unverified block in the collection.
     ///
+    /// The block is immediately marked as bad if it is banned. See [`Config::banned_blocks`] and
+    /// [`Config::fork_blocks`].
+    ///
     /// Returns the previous user data associated to this block, if any.
     pub fn insert_unverified_block(
         &mut self,
@@ -499,14 +551,31 @@ impl<TBl, TRq, TSrc> PendingBlocks<TBl, TRq, TSrc> {
 
         let parent_hash = state.parent_hash().copied();
         // TODO: is it ok to just override the UnverifiedBlockState?
-        self.blocks
+        let previous = self
+            .blocks
             .insert(
                 height,
                 hash,
                 parent_hash,
                 UnverifiedBlock { state, user_data },
             )
-            .map(|b| (b.user_data, b.state))
+            .map(|b| (b.user_data, b.state));
+
+        if self.is_banned(height, &hash) {
+            self.blocks.set_block_bad(height, &hash);
+        }
+
+        previous
+    }
+
+    /// Returns `true` if the block with the given height and hash is banned. See
+    /// [`Config::banned_blocks`] and [`Config::fork_blocks`].
+    pub fn is_banned(&self, height: u64, hash: &[u8; 32]) -> bool {
+        self.banned_blocks.contains(hash)
+            || self
+                .fork_blocks
+                .get(&height)
+                .map_or(false, |expected| expected != hash)
     }
 
     /// Returns `true` if the block with the given height and hash is in the collection.
@@ -649,6 +718,12 @@ impl<TBl, TRq, TSrc> PendingBlocks<TBl, TRq, TSrc> {
         self.blocks.len()
     }
 
+    /// Returns the height and hash of all the blocks stored in the data structure, in an
+    /// unspecified order.
+    pub fn blocks_iter(&'_ self) -> impl Iterator<Item = (u64, &'_ [u8; 32])> + '_ {
+        self.blocks.iter().map(|(height, hash, _)| (height, hash))
+    }
+
     /// Returns the list of blocks whose parent hash is known but absent from the list of disjoint
     /// blocks. These blocks can potentially be verified.
     ///
@@ -985,3 +1060,174 @@ pub struct RequestParams {
     /// than requested.
     pub num_blocks: NonZeroU64,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, PendingBlocks, UnverifiedBlockState};
+    use core::num::NonZeroU32;
+
+    #[test]
+    fn num_sources_knowing_block_or_descendant() {
+        let mut collection = PendingBlocks::<(), (), ()>::new(Config {
+            blocks_capacity: 16,
+            sources_capacity: 16,
+            finalized_block_height: 10,
+            verify_bodies: false,
+            max_requests_per_block: NonZeroU32::new(1).unwrap(),
+            banned_blocks: Vec::new(),
+            fork_blocks: Vec::new(),
+        });
+
+        // Block 11 has two children: 12 and 12'.
+        collection.insert_unverified_block(
+            11,
+            [11; 32],
+            UnverifiedBlockState::HeaderKnown {
+                parent_hash: [10; 32],
+            },
+            (),
+        );
+        collection.insert_unverified_block(
+            12,
+            [12; 32],
+            UnverifiedBlockState::HeaderKnown {
+                parent_hash: [11; 32],
+            },
+            (),
+        );
+        collection.insert_unverified_block(
+            12,
+            [120; 32],
+            UnverifiedBlockState::HeaderKnown {
+                parent_hash: [11; 32],
+            },
+            (),
+        );
+        assert_eq!(collection.blocks_iter().count(), 3);
+
+        assert_eq!(
+            collection.num_sources_knowing_block_or_descendant(11, &[11; 32]),
+            0
+        );
+
+        let source1 = collection.add_source((), 12, [12; 32]);
+        let _source2 = collection.add_source((), 12, [120; 32]);
+        let source3 = collection.add_source((), 10, [10; 32]);
+        collection.add_known_block(source3, 11, [11; 32]);
+
+        assert_eq!(
+            collection.num_sources_knowing_block_or_descendant(11, &[11; 32]),
+            3
+        );
+        assert_eq!(
+            collection.num_sources_knowing_block_or_descendant(12, &[12; 32]),
+            1
+        );
+        assert_eq!(
+            collection.num_sources_knowing_block_or_descendant(12, &[120; 32]),
+            1
+        );
+
+        // A source that knows both a block and one of its descendants is only counted once.
+        collection.add_known_block(source1, 11, [11; 32]);
+        assert_eq!(
+            collection.num_sources_knowing_block_or_descendant(11, &[11; 32]),
+            3
+        );
+
+        let _ = collection.remove_source(source1);
+        assert_eq!(
+            collection.num_sources_knowing_block_or_descendant(11, &[11; 32]),
+            2
+        );
+        assert_eq!(
+            collection.num_sources_knowing_block_or_descendant(12, &[12; 32]),
+            0
+        );
+    }
+
+    #[test]
+    fn banned_and_fork_blocks_marked_bad() {
+        let mut collection = PendingBlocks::<(), (), ()>::new(Config {
+            blocks_capacity: 16,
+            sources_capacity: 16,
+            finalized_block_height: 10,
+            verify_bodies: false,
+            max_requests_per_block: NonZeroU32::new(1).unwrap(),
+            banned_blocks: vec![[20; 32]],
+            fork_blocks: vec![(15, [15; 32])],
+        });
+
+        assert!(collection.is_banned(11, &[20; 32]));
+        assert!(collection.is_banned(15, &[150; 32]));
+        assert!(!collection.is_banned(15, &[15; 32]));
+        assert!(!collection.is_banned(11, &[11; 32]));
+
+        // Block 12' is inserted before its banned parent 11', and block 13' after it. Both are
+        // descendants of a banned block and must never be verified.
+        collection.insert_unverified_block(
+            12,
+            [21; 32],
+            UnverifiedBlockState::HeaderKnown {
+                parent_hash: [20; 32],
+            },
+            (),
+        );
+        assert_eq!(collection.unverified_leaves().count(), 1);
+        collection.insert_unverified_block(
+            11,
+            [20; 32],
+            UnverifiedBlockState::HeaderKnown {
+                parent_hash: [10; 32],
+            },
+            (),
+        );
+        collection.insert_unverified_block(
+            13,
+            [22; 32],
+            UnverifiedBlockState::HeaderKnown {
+                parent_hash: [21; 32],
+            },
+            (),
+        );
+        assert_eq!(collection.unverified_leaves().count(), 0);
+
+        collection.insert_unverified_block(
+            11,
+            [11; 32],
+            UnverifiedBlockState::HeaderKnown {
+                parent_hash: [10; 32],
+            },
+            (),
+        );
+
+        // Blocks at the height of a fork block must match its hash.
+        collection.insert_unverified_block(
+            15,
+            [150; 32],
+            UnverifiedBlockState::HeaderKnown {
+                parent_hash: [14; 32],
+            },
+            (),
+        );
+        collection.insert_unverified_block(
+            15,
+            [15; 32],
+            UnverifiedBlockState::HeaderKnown {
+                parent_hash: [14; 32],
+            },
+            (),
+        );
+
+        let mut leaves = collection
+            .unverified_leaves()
+            .map(|leaf| (leaf.block_number, leaf.block_hash))
+            .collect::<Vec<_>>();
+        leaves.sort();
+        assert_eq!(leaves, vec![(11, [11; 32]), (15, [15; 32])]);
+
+        // Banned blocks are still in the collection, in order to not download them again.
+        assert!(collection.contains_block(11, &[20; 32]));
+        assert!(collection.contains_block(15, &[150; 32]));
+    }
+}
@@ -470,6 +470,25 @@ where
             .map(move |(idx, b)| (&b.header[..], self.best_block_index == Some(idx)))
     }
 
+    /// Returns the distance, in number of blocks, between the "input" best block and the block
+    /// from which the oldest fork that doesn't contain this best block branches off.
+    ///
+    /// Returns `0` if all the non-finalized blocks that have been inserted are either ancestors
+    /// or descendants of the input best block.
+    ///
+    /// See [`fork_tree::ForkTree::max_fork_depth`].
+    pub fn input_max_fork_depth(&self) -> u64 {
+        let input_best = self
+            .non_finalized_blocks
+            .iter_unordered()
+            .max_by_key(|(_, b)| b.input_best_block_weight)
+            .filter(|(_, b)| {
+                b.input_best_block_weight > self.finalized_block.input_best_block_weight
+            })
+            .map(|(idx, _)| idx);
+        self.non_finalized_blocks.max_fork_depth(input_best)
+    }
+
     /// Iterates over all the runtimes stored in this data structure.
     pub fn runtimes_iter(&'_ self) -> impl Iterator<Item = (RuntimeId, &'_ TRt)> + '_ {
         self.runtimes
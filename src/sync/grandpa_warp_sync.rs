@@ -61,7 +61,7 @@ use crate::{
     },
     executor::{
         self,
-        host::{HostVmPrototype, NewErr},
+        host::{self, HostVmPrototype, NewErr},
         vm::ExecHint,
     },
     finality::grandpa::warp_sync,
@@ -497,6 +497,12 @@ impl<TSrc> StorageGet<TSrc> {
         self.inner.key_as_vec()
     }
 
+    /// If `Some`, the key must be read from the child trie whose key is returned rather than from
+    /// the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.inner.child_trie()
+    }
+
     /// Injects the corresponding storage value.
     pub fn inject_value(
         self,
@@ -537,6 +543,12 @@ impl<TSrc> NextKey<TSrc> {
         self.inner.key()
     }
 
+    /// If `Some`, the key must be searched in the child trie whose key is returned rather than
+    /// in the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.inner.child_trie()
+    }
+
     /// Returns the source that we received the warp sync data from.
     pub fn warp_sync_source(&self) -> (SourceId, &TSrc) {
         debug_assert!(self
@@ -922,7 +934,14 @@ impl<TSrc> VirtualMachineParamsGet<TSrc> {
                 }
             };
 
-        match HostVmPrototype::new(code, heap_pages, exec_hint) {
+        match HostVmPrototype::new(host::Config {
+            module: code,
+            heap_pages,
+            exec_hint,
+            allow_unresolved_imports: false,
+            max_memory_size: None,
+            metered: false,
+        }) {
             Ok(runtime) => {
                 let babe_current_epoch_query =
                     babe_fetch_epoch::babe_fetch_epoch(babe_fetch_epoch::Config {
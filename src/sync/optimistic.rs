@@ -52,7 +52,11 @@ use crate::{
     trie::calculate_root,
 };
 
-use alloc::{borrow::ToOwned as _, collections::BTreeMap, vec::Vec};
+use alloc::{
+    borrow::ToOwned as _,
+    collections::{BTreeMap, BTreeSet},
+    vec::Vec,
+};
 use core::{
     cmp,
     convert::TryFrom as _,
@@ -62,6 +66,7 @@ use core::{
 };
 use hashbrown::{HashMap, HashSet};
 
+mod tests;
 mod verification_queue;
 
 /// Configuration for the [`OptimisticSync`].
@@ -90,6 +95,21 @@ pub struct Config {
     /// block requests.
     pub download_ahead_blocks: NonZeroU32,
 
+    /// List of hashes of blocks that are known to be bad. Responses to block requests that
+    /// contain one of these blocks are discarded and the source that has sent them is punished.
+    ///
+    /// > **Note**: This list is typically filled with the list of bad blocks found in the chain
+    /// >           specification.
+    pub banned_blocks: Vec<[u8; 32]>,
+
+    /// List of block heights and hashes. Responses to block requests that contain a block whose
+    /// height is found in this list but whose hash doesn't match are discarded and the source
+    /// that has sent them is punished.
+    ///
+    /// > **Note**: This list is typically filled with the list of fork blocks found in the chain
+    /// >           specification.
+    pub fork_blocks: Vec<(u64, [u8; 32])>,
+
     /// If `Some`, the block bodies and storage are also synchronized. Contains the extra
     /// configuration.
     pub full: Option<ConfigFull>,
@@ -147,6 +167,12 @@ struct OptimisticSyncInner<TRq, TSrc, TBl> {
     /// See [`Config::download_ahead_blocks`].
     download_ahead_blocks: NonZeroU32,
 
+    /// See [`Config::banned_blocks`].
+    banned_blocks: BTreeSet<[u8; 32]>,
+
+    /// See [`Config::fork_blocks`]. Keys are block heights and values block hashes.
+    fork_blocks: BTreeMap<u64, [u8; 32]>,
+
     /// List of sources of blocks.
     sources: HashMap<SourceId, Source<TSrc>, fnv::FnvBuildHasher>,
 
@@ -187,6 +213,24 @@ impl<TRq, TSrc, TBl> OptimisticSyncInner<TRq, TSrc, TBl> {
         self.make_requests_obsolete(chain);
         self
     }
+
+    /// Returns `true` if the block with the given SCALE-encoded header is banned. See
+    /// [`Config::banned_blocks`] and [`Config::fork_blocks`].
+    ///
+    /// Headers that can't be decoded are left for the verification to reject.
+    fn is_banned(&self, scale_encoded_header: &[u8]) -> bool {
+        let number = match header::decode(scale_encoded_header) {
+            Ok(header) => header.number,
+            Err(_) => return false,
+        };
+        let hash = header::hash_from_scale_encoded_header(scale_encoded_header);
+
+        self.banned_blocks.contains(&hash)
+            || self
+                .fork_blocks
+                .get(&number)
+                .map_or(false, |expected| *expected != hash)
+    }
 }
 
 struct Source<TSrc> {
@@ -254,6 +298,8 @@ impl<TRq, TSrc, TBl> OptimisticSync<TRq, TSrc, TBl> {
                     best_block_header_num + 1,
                 ),
                 download_ahead_blocks: config.download_ahead_blocks,
+                banned_blocks: config.banned_blocks.into_iter().collect(),
+                fork_blocks: config.fork_blocks.into_iter().collect(),
                 next_request_id: RequestId(0),
                 obsolete_requests: HashMap::with_capacity_and_hasher(0, Default::default()),
             },
@@ -309,6 +355,17 @@ impl<TRq, TSrc, TBl> OptimisticSync<TRq, TSrc, TBl> {
         self.chain.iter_ancestry_order()
     }
 
+    /// Returns the information about the given non-finalized block, or `None` if the block isn't
+    /// in the chain.
+    pub fn non_finalized_block(&mut self, hash: &[u8; 32]) -> Option<&Block<TBl>> {
+        Some(
+            &*self
+                .chain
+                .non_finalized_block_by_hash(hash)?
+                .into_user_data(),
+        )
+    }
+
     /// Disassembles the state machine into its raw components.
     pub fn disassemble(self) -> Disassemble<TRq, TSrc> {
         Disassemble {
@@ -518,6 +575,9 @@ impl<TRq, TSrc, TBl> OptimisticSync<TRq, TSrc, TBl> {
     /// >           body from the source altogether, and to fill the
     /// >           [`RequestSuccessBlock::scale_encoded_extrinsics`] fields with `Vec::new()`.
     ///
+    /// If one of the blocks is banned, the response is treated the same way as a
+    /// [`RequestFail`]. See [`Config::banned_blocks`] and [`Config::fork_blocks`].
+    ///
     /// # Panic
     ///
     /// Panics if the [`RequestId`] is invalid.
@@ -537,12 +597,29 @@ impl<TRq, TSrc, TBl> OptimisticSync<TRq, TSrc, TBl> {
             return (user_data, FinishRequestOutcome::Obsolete);
         }
 
+        // The blocks that follow a banned block in the response are its descendants, and are
+        // thus banned as well. The entire response is discarded.
+        let outcome = match outcome {
+            Ok(blocks) => {
+                let blocks = blocks.collect::<Vec<_>>();
+                if blocks
+                    .iter()
+                    .any(|block| self.inner.is_banned(&block.scale_encoded_header))
+                {
+                    Err(())
+                } else {
+                    Ok(blocks.into_iter())
+                }
+            }
+            Err(_) => Err(()),
+        };
+
         let outcome_is_err = outcome.is_err();
 
         let (user_data, source_id) = self
             .inner
             .verification_queue
-            .finish_request(request_id, outcome.map_err(|_| ()));
+            .finish_request(request_id, outcome);
 
         self.inner
             .sources
@@ -796,6 +873,9 @@ pub enum BlockVerification<TRq, TSrc, TBl> {
     /// Fetching the key of the finalized block storage that follows a given one is required in
     /// order to continue.
     FinalizedStorageNextKey(StorageNextKey<TRq, TSrc, TBl>),
+
+    /// Verifying whether a batch of signatures is valid is required in order to continue.
+    SignatureBatchVerify(SignatureBatchVerify<TRq, TSrc, TBl>),
 }
 
 enum Inner<TBl> {
@@ -998,6 +1078,22 @@ impl<TRq, TSrc, TBl> BlockVerification<TRq, TSrc, TBl> {
                 Inner::Step2(blocks_tree::BodyVerifyStep2::StorageGet(req)) => {
                     // The underlying verification process is asking for a storage entry in the
                     // parent block.
+                    //
+                    // Only the changes to the main trie are tracked in
+                    // `best_to_finalized_storage_diff`. Child tries can only be queried from the
+                    // user if the parent is the finalized block.
+                    if req.child_trie().is_some() {
+                        if req.num_non_finalized_ancestors() != 0 {
+                            inner = Inner::Step2(req.inject_unavailable());
+                            continue 'verif_steps;
+                        }
+
+                        break BlockVerification::FinalizedStorageGet(StorageGet {
+                            inner: req,
+                            shared,
+                        });
+                    }
+
                     //
                     // The [`OptimisticSync`] stores the difference between the best block's
                     // storage and the finalized block's storage.
@@ -1027,6 +1123,12 @@ impl<TRq, TSrc, TBl> BlockVerification<TRq, TSrc, TBl> {
                 Inner::Step2(blocks_tree::BodyVerifyStep2::StorageNextKey(req)) => {
                     // The underlying verification process is asking for the key that follows
                     // the requested one.
+                    // See the comment about child tries above.
+                    if req.child_trie().is_some() && req.num_non_finalized_ancestors() != 0 {
+                        inner = Inner::Step2(req.inject_unavailable());
+                        continue 'verif_steps;
+                    }
+
                     break BlockVerification::FinalizedStorageNextKey(StorageNextKey {
                         inner: req,
                         shared,
@@ -1035,6 +1137,12 @@ impl<TRq, TSrc, TBl> BlockVerification<TRq, TSrc, TBl> {
                 }
 
                 Inner::Step2(blocks_tree::BodyVerifyStep2::StoragePrefixKeys(req)) => {
+                    // See the comment about child tries above.
+                    if req.child_trie().is_some() && req.num_non_finalized_ancestors() != 0 {
+                        inner = Inner::Step2(req.inject_unavailable());
+                        continue 'verif_steps;
+                    }
+
                     // The underlying verification process is asking for all the keys that start
                     // with a certain prefix.
                     // The first step is to ask the user for that information when it comes to
@@ -1051,6 +1159,15 @@ impl<TRq, TSrc, TBl> BlockVerification<TRq, TSrc, TBl> {
                     continue 'verif_steps;
                 }
 
+                Inner::Step2(blocks_tree::BodyVerifyStep2::SignatureBatchVerify(req)) => {
+                    // The underlying verification process requires verifying a batch of
+                    // signatures. The user decides how to verify them.
+                    break BlockVerification::SignatureBatchVerify(SignatureBatchVerify {
+                        inner: req,
+                        shared,
+                    });
+                }
+
                 // The three variants below correspond to problems during the verification.
                 //
                 // When that happens:
@@ -1176,11 +1293,24 @@ impl<TRq, TSrc, TBl> StorageGet<TRq, TSrc, TBl> {
         self.inner.key_as_vec()
     }
 
+    /// If `Some`, the key must be read from the child trie whose key is returned rather than from
+    /// the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.inner.child_trie()
+    }
+
     /// Injects the corresponding storage value.
     pub fn inject_value(self, value: Option<&[u8]>) -> BlockVerification<TRq, TSrc, TBl> {
         let inner = self.inner.inject_value(value.map(iter::once));
         BlockVerification::from(Inner::Step2(inner), self.shared)
     }
+
+    /// Stops the verification, as the requested storage can't be provided. The block is then
+    /// considered as failing to verify.
+    pub fn inject_unavailable(self) -> BlockVerification<TRq, TSrc, TBl> {
+        let inner = self.inner.inject_unavailable();
+        BlockVerification::from(Inner::Step2(inner), self.shared)
+    }
 }
 
 /// Fetching the list of keys with a given prefix is required in order to continue.
@@ -1196,6 +1326,12 @@ impl<TRq, TSrc, TBl> StoragePrefixKeys<TRq, TSrc, TBl> {
         self.inner.prefix()
     }
 
+    /// If `Some`, the keys must be loaded from the child trie whose key is returned rather than
+    /// from the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.inner.child_trie()
+    }
+
     /// Injects the list of keys ordered lexicographically.
     pub fn inject_keys_ordered(
         self,
@@ -1205,7 +1341,9 @@ impl<TRq, TSrc, TBl> StoragePrefixKeys<TRq, TSrc, TBl> {
             .map(|k| k.as_ref().to_owned())
             .collect::<HashSet<_, fnv::FnvBuildHasher>>();
 
-        {
+        // `best_to_finalized_storage_diff` only concerns the main trie. Child tries are only
+        // requested from the user if the parent is the finalized block.
+        if self.inner.child_trie().is_none() {
             let prefix = self.inner.prefix();
             for (k, v) in self
                 .shared
@@ -1225,6 +1363,13 @@ impl<TRq, TSrc, TBl> StoragePrefixKeys<TRq, TSrc, TBl> {
         let inner = self.inner.inject_keys_ordered(keys.iter());
         BlockVerification::from(Inner::Step2(inner), self.shared)
     }
+
+    /// Stops the verification, as the requested storage can't be provided. The block is then
+    /// considered as failing to verify.
+    pub fn inject_unavailable(self) -> BlockVerification<TRq, TSrc, TBl> {
+        let inner = self.inner.inject_unavailable();
+        BlockVerification::from(Inner::Step2(inner), self.shared)
+    }
 }
 
 /// Fetching the key that follows a given one is required in order to continue.
@@ -1247,6 +1392,12 @@ impl<TRq, TSrc, TBl> StorageNextKey<TRq, TSrc, TBl> {
         }
     }
 
+    /// If `Some`, the key must be searched in the child trie whose key is returned rather than
+    /// in the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.inner.child_trie()
+    }
+
     /// Injects the key.
     ///
     /// # Panic
@@ -1256,6 +1407,13 @@ impl<TRq, TSrc, TBl> StorageNextKey<TRq, TSrc, TBl> {
     pub fn inject_key(self, key: Option<impl AsRef<[u8]>>) -> BlockVerification<TRq, TSrc, TBl> {
         let key = key.as_ref().map(|k| k.as_ref());
 
+        // `best_to_finalized_storage_diff` only concerns the main trie. Child tries are only
+        // requested from the user if the parent is the finalized block.
+        if self.inner.child_trie().is_some() {
+            let inner = self.inner.inject_key(key);
+            return BlockVerification::from(Inner::Step2(inner), self.shared);
+        }
+
         // The key provided by the user as parameter is the next key in the storage of the
         // finalized block.
         // `best_to_finalized_storage_diff` needs to be taken into account in order to provide
@@ -1324,6 +1482,40 @@ impl<TRq, TSrc, TBl> StorageNextKey<TRq, TSrc, TBl> {
         let inner = self.inner.inject_key(outcome);
         BlockVerification::from(Inner::Step2(inner), self.shared)
     }
+
+    /// Stops the verification, as the requested storage can't be provided. The block is then
+    /// considered as failing to verify.
+    pub fn inject_unavailable(self) -> BlockVerification<TRq, TSrc, TBl> {
+        let inner = self.inner.inject_unavailable();
+        BlockVerification::from(Inner::Step2(inner), self.shared)
+    }
+}
+
+/// Verifying whether a batch of signatures is valid is required in order to continue.
+#[must_use]
+pub struct SignatureBatchVerify<TRq, TSrc, TBl> {
+    inner: blocks_tree::SignatureBatchVerify<Block<TBl>>,
+    shared: BlockVerificationShared<TRq, TSrc, TBl>,
+}
+
+impl<TRq, TSrc, TBl> SignatureBatchVerify<TRq, TSrc, TBl> {
+    /// Returns the signatures of the batch.
+    pub fn signatures(&self) -> &[host::SignatureVerification] {
+        self.inner.signatures()
+    }
+
+    /// Resumes the verification. `all_valid` must be `true` if and only if all the signatures
+    /// of the batch are valid.
+    pub fn resume(self, all_valid: bool) -> BlockVerification<TRq, TSrc, TBl> {
+        let inner = self.inner.resume(all_valid);
+        BlockVerification::from(Inner::Step2(inner), self.shared)
+    }
+
+    /// Verifies the signatures one after the other, then resumes the verification.
+    pub fn verify_and_resume(self) -> BlockVerification<TRq, TSrc, TBl> {
+        let inner = self.inner.verify_and_resume();
+        BlockVerification::from(Inner::Step2(inner), self.shared)
+    }
 }
 
 /// Request that should be emitted towards a certain source.
@@ -0,0 +1,132 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+#![cfg(test)]
+
+use super::{Config, FinishRequestOutcome, OptimisticSync, RequestSuccessBlock};
+use crate::{chain::chain_information, header};
+
+use core::{convert::TryFrom as _, num::NonZeroU32};
+
+/// Builds a header with the given parent and height. `discriminant` is written in the state
+/// root in order to be able to build multiple different children of the same parent.
+fn build_header(parent: &header::Header, discriminant: u8) -> header::Header {
+    header::Header {
+        parent_hash: parent.hash(),
+        number: parent.number + 1,
+        state_root: [discriminant; 32],
+        extrinsics_root: [0; 32],
+        digest: header::DigestRef::empty().into(),
+    }
+}
+
+/// Starts a light sync on top of `genesis`, and answers a request of the single source with
+/// the given headers. Returns `true` if the source has been punished.
+fn answer_request(
+    genesis: &header::Header,
+    banned_blocks: Vec<[u8; 32]>,
+    fork_blocks: Vec<(u64, [u8; 32])>,
+    response: &[header::Header],
+) -> bool {
+    let mut sync = OptimisticSync::<(), (), ()>::new(Config {
+        chain_information: chain_information::ValidChainInformation::try_from(
+            chain_information::ChainInformation {
+                finalized_block_header: genesis.clone(),
+                consensus: chain_information::ChainInformationConsensus::AllAuthorized,
+                finality: chain_information::ChainInformationFinality::Outsourced,
+            },
+        )
+        .unwrap(),
+        sources_capacity: 16,
+        blocks_capacity: 16,
+        download_ahead_blocks: NonZeroU32::new(16).unwrap(),
+        banned_blocks,
+        fork_blocks,
+        full: None,
+    });
+
+    let source_id = sync.add_source((), 10);
+    let detail = sync.desired_requests().next().unwrap();
+    assert_eq!(detail.source_id, source_id);
+    assert_eq!(detail.block_height.get(), 1);
+    let request_id = sync.insert_request(detail, ());
+
+    let (_, outcome) = sync.finish_request(
+        request_id,
+        Ok(response.iter().map(|header| RequestSuccessBlock {
+            scale_encoded_header: header.scale_encoding_vec(),
+            scale_encoded_justification: None,
+            scale_encoded_extrinsics: Vec::new(),
+            user_data: (),
+        })),
+    );
+
+    match outcome {
+        FinishRequestOutcome::Queued => false,
+        FinishRequestOutcome::SourcePunished(_) => true,
+        FinishRequestOutcome::Obsolete => panic!(),
+    }
+}
+
+#[test]
+fn banned_blocks_punish_source() {
+    let genesis = header::Header {
+        parent_hash: [0; 32],
+        number: 0,
+        state_root: [0; 32],
+        extrinsics_root: [0; 32],
+        digest: header::DigestRef::empty().into(),
+    };
+    let block1 = build_header(&genesis, 0);
+    let block2 = build_header(&block1, 0);
+    let block2_fork = build_header(&block1, 1);
+
+    assert!(!answer_request(
+        &genesis,
+        Vec::new(),
+        Vec::new(),
+        &[block1.clone(), block2.clone()]
+    ));
+
+    // A banned block anywhere in the response discards the entire response.
+    assert!(answer_request(
+        &genesis,
+        vec![block2.hash()],
+        Vec::new(),
+        &[block1.clone(), block2.clone()]
+    ));
+    assert!(!answer_request(
+        &genesis,
+        vec![block2.hash()],
+        Vec::new(),
+        &[block1.clone(), block2_fork.clone()]
+    ));
+
+    // Blocks at the height of a fork block must match its hash.
+    assert!(answer_request(
+        &genesis,
+        Vec::new(),
+        vec![(2, block2.hash())],
+        &[block1.clone(), block2_fork]
+    ));
+    assert!(!answer_request(
+        &genesis,
+        Vec::new(),
+        vec![(2, block2.hash())],
+        &[block1, block2]
+    ));
+}
@@ -0,0 +1,309 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Telemetry.
+//!
+//! # Overview
+//!
+//! Substrate/Polkadot nodes can optionally report information about themselves, such as their
+//! name or their best block, to so-called *telemetry servers*. This information is then displayed
+//! on web pages, giving an overview of the state of the nodes of a network.
+//!
+//! The list of telemetry servers to connect to is typically found in the chain specification.
+//! See [`crate::chain_spec::ChainSpec::telemetry_endpoints`]. Each endpoint is associated with a
+//! *verbosity* level, and only messages whose verbosity is inferior or equal to this level should
+//! be sent to this endpoint.
+//!
+//! Nodes connect to telemetry servers through the WebSocket protocol and send JSON-formatted
+//! text frames. The first message sent on each connection must always be a
+//! [`SystemConnected`] message. Afterwards, [`SystemInterval`] messages are typically sent at a
+//! regular interval.
+//!
+//! This module contains the tools to parse the addresses of telemetry servers and to build the
+//! messages. Connecting to the servers is left to the user of this module.
+
+use alloc::{
+    format,
+    string::{String, ToString as _},
+    vec::Vec,
+};
+use core::str;
+
+/// Verbosity level of the [`SystemConnected`] and [`SystemInterval`] messages.
+pub const VERBOSITY_INFO: u8 = 0;
+
+/// Address of a telemetry server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoint {
+    /// If `true`, the connection must be encrypted with TLS (`wss://`).
+    pub secure: bool,
+    /// Domain name or IP address of the server.
+    pub host: String,
+    /// TCP port of the server.
+    pub port: u16,
+    /// HTTP path to request during the WebSocket handshake. Always starts with `/`.
+    pub path: String,
+}
+
+/// Parses the address of a telemetry server, as found in the chain specification.
+///
+/// Both URLs (for example `wss://telemetry.polkadot.io/submit/`) and multiaddresses (for example
+/// `/dns/telemetry.polkadot.io/tcp/443/x-parity-wss/%2Fsubmit%2F`) are supported.
+pub fn parse_endpoint(address: &str) -> Result<Endpoint, ParseEndpointError> {
+    if address.starts_with('/') {
+        parse_multiaddr_endpoint(address)
+    } else {
+        parse_url_endpoint(address)
+    }
+}
+
+/// Error potentially returned by [`parse_endpoint`].
+#[derive(Debug, derive_more::Display)]
+#[display(fmt = "Invalid telemetry endpoint")]
+pub struct ParseEndpointError;
+
+fn parse_url_endpoint(address: &str) -> Result<Endpoint, ParseEndpointError> {
+    let (secure, rest) = if let Some(rest) = address.strip_prefix("wss://") {
+        (true, rest)
+    } else if let Some(rest) = address.strip_prefix("ws://") {
+        (false, rest)
+    } else {
+        return Err(ParseEndpointError);
+    };
+
+    let (host_port, path) = match rest.find('/') {
+        Some(pos) => (&rest[..pos], &rest[pos..]),
+        None => (rest, "/"),
+    };
+
+    let (host, port) = match host_port.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().map_err(|_| ParseEndpointError)?),
+        None => (host_port, if secure { 443 } else { 80 }),
+    };
+
+    if host.is_empty() {
+        return Err(ParseEndpointError);
+    }
+
+    Ok(Endpoint {
+        secure,
+        host: host.to_string(),
+        port,
+        path: path.to_string(),
+    })
+}
+
+fn parse_multiaddr_endpoint(address: &str) -> Result<Endpoint, ParseEndpointError> {
+    let mut components = address.split('/').skip(1);
+
+    let host = match (components.next(), components.next()) {
+        (Some("dns" | "dns4" | "dns6" | "ip4" | "ip6"), Some(host)) if !host.is_empty() => host,
+        _ => return Err(ParseEndpointError),
+    };
+
+    let port = match (components.next(), components.next()) {
+        (Some("tcp"), Some(port)) => port.parse().map_err(|_| ParseEndpointError)?,
+        _ => return Err(ParseEndpointError),
+    };
+
+    let secure = match components.next() {
+        Some("ws" | "x-parity-ws") => false,
+        Some("wss" | "x-parity-wss") => true,
+        _ => return Err(ParseEndpointError),
+    };
+
+    // The `x-parity-ws` and `x-parity-wss` protocols are followed with the URL-encoded path.
+    let path = match components.next() {
+        Some(path) => percent_decode(path)?,
+        None => "/".to_string(),
+    };
+
+    if components.next().is_some() || !path.starts_with('/') {
+        return Err(ParseEndpointError);
+    }
+
+    Ok(Endpoint {
+        secure,
+        host: host.to_string(),
+        port,
+        path,
+    })
+}
+
+fn percent_decode(input: &str) -> Result<String, ParseEndpointError> {
+    let mut out = Vec::with_capacity(input.len());
+    let mut bytes = input.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte == b'%' {
+            let digits = [
+                bytes.next().ok_or(ParseEndpointError)?,
+                bytes.next().ok_or(ParseEndpointError)?,
+            ];
+            let digits = str::from_utf8(&digits).map_err(|_| ParseEndpointError)?;
+            out.push(u8::from_str_radix(digits, 16).map_err(|_| ParseEndpointError)?);
+        } else {
+            out.push(byte);
+        }
+    }
+    String::from_utf8(out).map_err(|_| ParseEndpointError)
+}
+
+/// Message that must be sent first on each connection to a telemetry server.
+#[derive(Debug, Clone)]
+pub struct SystemConnected<'a> {
+    /// Name of the chain, as found in the chain specification.
+    pub chain_name: &'a str,
+    /// Name of the node, chosen by the user.
+    pub node_name: &'a str,
+    /// Name of the client implementation.
+    pub implementation: &'a str,
+    /// Version of the client implementation.
+    pub version: &'a str,
+    /// Hash of the genesis block of the chain.
+    pub genesis_hash: &'a [u8; 32],
+    /// Network identity of the node, in other words its base58-encoded `PeerId`.
+    pub network_id: &'a str,
+}
+
+impl<'a> SystemConnected<'a> {
+    /// Returns the JSON-formatted message to send to the telemetry servers.
+    pub fn to_json(&self) -> String {
+        serde_json::json!({
+            "id": 1,
+            "payload": {
+                "msg": "system.connected",
+                "chain": self.chain_name,
+                "name": self.node_name,
+                "implementation": self.implementation,
+                "version": self.version,
+                "genesis_hash": hash_to_string(self.genesis_hash),
+                "network_id": self.network_id,
+                "authority": false,
+            },
+        })
+        .to_string()
+    }
+}
+
+/// Message containing the current state of the node, typically sent at a regular interval.
+#[derive(Debug, Clone)]
+pub struct SystemInterval<'a> {
+    /// Height of the current best block.
+    pub best_block_number: u64,
+    /// Hash of the current best block.
+    pub best_block_hash: &'a [u8; 32],
+    /// Height of the current finalized block.
+    pub finalized_block_number: u64,
+    /// Hash of the current finalized block.
+    pub finalized_block_hash: &'a [u8; 32],
+    /// Number of peers the node is connected to.
+    pub num_peers: u64,
+    /// Number of transactions in the pool of the node.
+    pub num_transactions: u64,
+}
+
+impl<'a> SystemInterval<'a> {
+    /// Returns the JSON-formatted message to send to the telemetry servers.
+    pub fn to_json(&self) -> String {
+        serde_json::json!({
+            "id": 1,
+            "payload": {
+                "msg": "system.interval",
+                "best": hash_to_string(self.best_block_hash),
+                "height": self.best_block_number,
+                "finalized_hash": hash_to_string(self.finalized_block_hash),
+                "finalized_height": self.finalized_block_number,
+                "peers": self.num_peers,
+                "txcount": self.num_transactions,
+            },
+        })
+        .to_string()
+    }
+}
+
+fn hash_to_string(hash: &[u8; 32]) -> String {
+    format!("0x{}", hex::encode(hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_endpoint, Endpoint, SystemInterval};
+
+    #[test]
+    fn parse_multiaddr() {
+        assert_eq!(
+            parse_endpoint("/dns/telemetry.polkadot.io/tcp/443/x-parity-wss/%2Fsubmit%2F").unwrap(),
+            Endpoint {
+                secure: true,
+                host: "telemetry.polkadot.io".into(),
+                port: 443,
+                path: "/submit/".into(),
+            }
+        );
+        assert_eq!(
+            parse_endpoint("/ip4/127.0.0.1/tcp/8000/ws").unwrap(),
+            Endpoint {
+                secure: false,
+                host: "127.0.0.1".into(),
+                port: 8000,
+                path: "/".into(),
+            }
+        );
+        assert!(parse_endpoint("/dns/example.com/udp/443/ws").is_err());
+    }
+
+    #[test]
+    fn parse_url() {
+        assert_eq!(
+            parse_endpoint("wss://telemetry.polkadot.io/submit/").unwrap(),
+            Endpoint {
+                secure: true,
+                host: "telemetry.polkadot.io".into(),
+                port: 443,
+                path: "/submit/".into(),
+            }
+        );
+        assert_eq!(
+            parse_endpoint("ws://localhost:8000").unwrap(),
+            Endpoint {
+                secure: false,
+                host: "localhost".into(),
+                port: 8000,
+                path: "/".into(),
+            }
+        );
+        assert!(parse_endpoint("http://example.com").is_err());
+    }
+
+    #[test]
+    fn system_interval_json() {
+        let message = SystemInterval {
+            best_block_number: 12,
+            best_block_hash: &[0; 32],
+            finalized_block_number: 10,
+            finalized_block_hash: &[1; 32],
+            num_peers: 3,
+            num_transactions: 0,
+        }
+        .to_json();
+
+        let decoded: serde_json::Value = serde_json::from_str(&message).unwrap();
+        assert_eq!(decoded["payload"]["msg"], "system.interval");
+        assert_eq!(decoded["payload"]["height"], 12);
+        assert_eq!(decoded["payload"]["peers"], 3);
+    }
+}
@@ -28,7 +28,7 @@
 //! generated. This can be done for example through a UI, through an offchain worker, or other. A
 //! transaction can be either signed (i.e. have a signature attached to it) or unsigned, depending
 //! on the action to be performed. A balance transfer, for example, generally always requires a
-//! signature.
+//! signature. See the [`construct`] module for more info.
 //!
 //! - The transaction is then processed by a node, generally the node that belongs to the author
 //! of the transaction, where it is *validated* by passing it as parameter to a runtime entry
@@ -102,6 +102,8 @@
 //! double-SCALE-encoded transactions.
 //!
 
+pub mod construct;
+pub mod era;
 pub mod light_pool;
 pub mod pool;
 pub mod validate;
@@ -0,0 +1,314 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Construction of signed transactions.
+//!
+//! # Overview
+//!
+//! A signed transaction, in the format used by most Substrate-based chains (version 4 of the
+//! transactions format), consists of:
+//!
+//! - The address of the signer, as a `MultiAddress`.
+//! - A signature, as a `MultiSignature`.
+//! - The so-called *extra* data of the *signed extensions*, such as the era of the transaction
+//! (see the [`era`](super::era) module), its nonce, or a tip.
+//! - The *call*, in other words the function to call in the runtime followed with its
+//! SCALE-encoded parameters.
+//!
+//! The list of signed extensions is found in the metadata of the runtime. Each signed extension
+//! can add some data to the transaction (the *extra* data), and some data to the payload to sign
+//! without including it in the transaction (the *additional signed* data), such as the genesis
+//! hash of the chain. Both the node that validates the transaction and the signer must agree on
+//! this data for the signature to be valid.
+//!
+//! # Usage
+//!
+//! - Obtain the *metadata* of the runtime of the chain. See the [metadata](crate::metadata)
+//! module for more information.
+//! - Obtain the nonce of the signer, the runtime specification and transaction versions, and,
+//! if the transaction is mortal, the hash of the block corresponding to the birth of the
+//! transaction. This is out of scope of this module.
+//! - SCALE-encode the parameters of the call. The metadata doesn't contain enough information to
+//! do this in a generic way, and this is thus also out of scope of this module.
+//! - Call [`build_signed_extrinsic`], passing a callback that signs the payload.
+//!
+//! > **Note**: Only the signed extensions known to be used by Substrate and Polkadot are
+//! >           supported. [`build_signed_extrinsic`] returns an error if the metadata contains
+//! >           an unknown signed extension, as the resulting transaction would be invalid.
+
+use super::era::Era;
+use crate::{metadata::decode as metadata, util};
+
+use alloc::{borrow::ToOwned as _, string::String, vec::Vec};
+use core::convert::TryFrom as _;
+
+/// Configuration for [`build_signed_extrinsic`].
+pub struct Config<'a, TSign> {
+    /// Metadata of the runtime the transaction is destined to.
+    pub metadata: metadata::MetadataRef<'a>,
+
+    /// Name of the module containing the call, for example `Balances`.
+    pub module_name: &'a str,
+
+    /// Name of the call within the module, for example `transfer`.
+    pub call_name: &'a str,
+
+    /// Concatenation of the SCALE encodings of the parameters of the call.
+    pub call_parameters: &'a [u8],
+
+    /// Public key of the signer of the transaction. Encoded as the `Id` variant of a
+    /// `MultiAddress`.
+    pub signer_public_key: &'a [u8; 32],
+
+    /// Era of the transaction.
+    pub era: Era,
+
+    /// Hash of the block corresponding to [`Era::birth`]. Must be the genesis hash if the
+    /// transaction is immortal.
+    pub birth_block_hash: &'a [u8; 32],
+
+    /// Hash of the genesis block of the chain.
+    pub genesis_block_hash: &'a [u8; 32],
+
+    /// Specification version of the runtime, as found in its runtime specification.
+    pub spec_version: u32,
+
+    /// Transaction version of the runtime, as found in its runtime specification.
+    pub transaction_version: u32,
+
+    /// Nonce of the signer. Must be equal to the number of transactions of this signer that
+    /// have already been included in the chain.
+    pub nonce: u64,
+
+    /// Additional fee paid to the block author in order to increase the priority of the
+    /// transaction.
+    pub tip: u128,
+
+    /// Function that signs the payload passed as parameter with the private key corresponding
+    /// to [`Config::signer_public_key`].
+    pub sign: TSign,
+}
+
+/// Signature of a transaction.
+#[derive(Debug, Clone)]
+pub enum Signature {
+    /// Ed25519 signature.
+    Ed25519([u8; 64]),
+    /// Sr25519 signature.
+    Sr25519([u8; 64]),
+    /// ECDSA signature, including the recovery byte.
+    Ecdsa([u8; 65]),
+}
+
+/// Builds a signed transaction.
+///
+/// On success, returns the double-SCALE-encoded transaction, ready to be submitted to the
+/// chain. See [the `transactions` module](super) for more information about the double-SCALE
+/// encoding.
+pub fn build_signed_extrinsic<TSign>(config: Config<TSign>) -> Result<Vec<u8>, BuildError>
+where
+    TSign: FnOnce(&[u8]) -> Signature,
+{
+    if config.metadata.extrinsic.version != 4 {
+        return Err(BuildError::UnsupportedVersion(
+            config.metadata.extrinsic.version,
+        ));
+    }
+
+    let call = {
+        let index = call_index(config.metadata, config.module_name, config.call_name)?;
+        let mut call = Vec::with_capacity(index.len() + config.call_parameters.len());
+        call.extend_from_slice(&index);
+        call.extend_from_slice(config.call_parameters);
+        call
+    };
+
+    // Data included in the transaction, and data included only in the payload to sign.
+    let mut extra = Vec::with_capacity(32);
+    let mut additional_signed = Vec::with_capacity(128);
+
+    for extension in config.metadata.extrinsic.signed_extensions {
+        match extension {
+            "CheckSpecVersion" => {
+                additional_signed.extend_from_slice(&config.spec_version.to_le_bytes())
+            }
+            "CheckTxVersion" => {
+                additional_signed.extend_from_slice(&config.transaction_version.to_le_bytes())
+            }
+            "CheckGenesis" => additional_signed.extend_from_slice(config.genesis_block_hash),
+            "CheckEra" | "CheckMortality" => {
+                extra.extend_from_slice(config.era.scale_encoding().as_ref());
+                additional_signed.extend_from_slice(config.birth_block_hash);
+            }
+            "CheckNonce" => extra.extend_from_slice(
+                util::encode_scale_compact_u128(u128::from(config.nonce)).as_ref(),
+            ),
+            "ChargeTransactionPayment" => {
+                extra.extend_from_slice(util::encode_scale_compact_u128(config.tip).as_ref())
+            }
+            "ChargeAssetTxPayment" => {
+                extra.extend_from_slice(util::encode_scale_compact_u128(config.tip).as_ref());
+                // Fees are paid in the native token of the chain rather than in an asset.
+                extra.push(0);
+            }
+            // Signed extensions that neither add extra nor additional signed data.
+            "CheckWeight"
+            | "CheckNonZeroSender"
+            | "TransactionCallFilter"
+            | "LimitParathreadCommits"
+            | "ValidateDoubleVoteReports"
+            | "ValidateEquivocationReport"
+            | "PrevalidateAttests" => {}
+            other => return Err(BuildError::UnknownSignedExtension(other.to_owned())),
+        }
+    }
+
+    let signature = {
+        let mut payload = Vec::with_capacity(call.len() + extra.len() + additional_signed.len());
+        payload.extend_from_slice(&call);
+        payload.extend_from_slice(&extra);
+        payload.extend_from_slice(&additional_signed);
+
+        // Payloads longer than 256 bytes are hashed before being signed.
+        if payload.len() > 256 {
+            let hash = blake2_rfc::blake2b::blake2b(32, &[], &payload);
+            (config.sign)(hash.as_bytes())
+        } else {
+            (config.sign)(&payload)
+        }
+    };
+
+    let mut extrinsic = Vec::with_capacity(1 + 33 + 66 + extra.len() + call.len());
+    // Version 4, with the highest bit indicating that the transaction is signed.
+    extrinsic.push(0x84);
+    extrinsic.push(0);
+    extrinsic.extend_from_slice(config.signer_public_key);
+    match signature {
+        Signature::Ed25519(signature) => {
+            extrinsic.push(0);
+            extrinsic.extend_from_slice(&signature);
+        }
+        Signature::Sr25519(signature) => {
+            extrinsic.push(1);
+            extrinsic.extend_from_slice(&signature);
+        }
+        Signature::Ecdsa(signature) => {
+            extrinsic.push(2);
+            extrinsic.extend_from_slice(&signature);
+        }
+    }
+    extrinsic.extend_from_slice(&extra);
+    extrinsic.extend_from_slice(&call);
+
+    let mut out = util::encode_scale_compact_usize(extrinsic.len())
+        .as_ref()
+        .to_vec();
+    out.extend_from_slice(&extrinsic);
+    Ok(out)
+}
+
+/// Returns the two bytes that identify the given call, in other words the index of the module
+/// followed with the index of the call within this module.
+pub fn call_index(
+    metadata: metadata::MetadataRef,
+    module_name: &str,
+    call_name: &str,
+) -> Result<[u8; 2], BuildError> {
+    // Only the modules that have calls are taken into account when determining the index of a
+    // module.
+    let (module_index, calls) = metadata
+        .modules
+        .filter_map(|module| module.calls.map(|calls| (module.name, calls)))
+        .enumerate()
+        .find(|(_, (name, _))| *name == module_name)
+        .map(|(index, (_, calls))| (index, calls))
+        .ok_or(BuildError::UnknownModule)?;
+
+    let call_index = calls
+        .enumerate()
+        .find(|(_, call)| call.name == call_name)
+        .map(|(index, _)| index)
+        .ok_or(BuildError::UnknownCall)?;
+
+    Ok([
+        u8::try_from(module_index).map_err(|_| BuildError::UnknownModule)?,
+        u8::try_from(call_index).map_err(|_| BuildError::UnknownCall)?,
+    ])
+}
+
+/// Error potentially returned by [`build_signed_extrinsic`] and [`call_index`].
+#[derive(Debug, derive_more::Display)]
+pub enum BuildError {
+    /// Version of the transactions format indicated in the metadata isn't supported.
+    #[display(fmt = "Unsupported transactions format version: {}", _0)]
+    UnsupportedVersion(u8),
+    /// No module with the requested name and containing calls has been found.
+    UnknownModule,
+    /// No call with the requested name has been found in the module.
+    UnknownCall,
+    /// The metadata contains a signed extension whose data is unknown.
+    #[display(fmt = "Unknown signed extension: {}", _0)]
+    UnknownSignedExtension(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_signed_extrinsic, call_index, Config, Signature};
+    use crate::transactions::era::{extrinsic_era, Era};
+
+    #[test]
+    fn system_remark() {
+        let metadata =
+            crate::metadata::decode(&include_bytes!("../metadata/decode/example-metadata")[..])
+                .unwrap();
+        assert_eq!(call_index(metadata, "System", "remark").unwrap(), [0, 1]);
+        assert!(call_index(metadata, "System", "foo").is_err());
+
+        let era = Era::mortal(64, 106);
+        let mut signed_payload = None;
+        let extrinsic = build_signed_extrinsic(Config {
+            metadata,
+            module_name: "System",
+            call_name: "remark",
+            call_parameters: &[0x0c, 1, 2, 3],
+            signer_public_key: &[0xaa; 32],
+            era,
+            birth_block_hash: &[0xbb; 32],
+            genesis_block_hash: &[0xcc; 32],
+            spec_version: 9000,
+            transaction_version: 5,
+            nonce: 3,
+            tip: 0,
+            sign: |payload: &[u8]| {
+                signed_payload = Some(payload.to_vec());
+                Signature::Sr25519([0xdd; 64])
+            },
+        })
+        .unwrap();
+
+        assert_eq!(extrinsic_era(&extrinsic), Some(era));
+        assert!(extrinsic.ends_with(&[0, 1, 0x0c, 1, 2, 3]));
+
+        // Call, era, nonce, tip, spec version, transaction version, genesis hash, birth hash.
+        let mut expected_payload = vec![0, 1, 0x0c, 1, 2, 3, 0xa5, 0x02, 0x0c, 0x00];
+        expected_payload.extend_from_slice(&9000u32.to_le_bytes());
+        expected_payload.extend_from_slice(&5u32.to_le_bytes());
+        expected_payload.extend_from_slice(&[0xcc; 32]);
+        expected_payload.extend_from_slice(&[0xbb; 32]);
+        assert_eq!(signed_payload.unwrap(), expected_payload);
+    }
+}
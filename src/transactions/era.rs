@@ -0,0 +1,291 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Mortality of transactions.
+//!
+//! Signed transactions typically contain a so-called *era*, indicating the range of blocks in
+//! which they can be included. A transaction whose era is *immortal* can be included in any
+//! block. A transaction whose era is *mortal* can only be included in a window of `period`
+//! blocks, starting at a block whose number modulo `period` is equal to `phase`. The first
+//! block of this window is called the *birth* of the transaction, and the first block after
+//! this window its *death*.
+//!
+//! Knowing the era of a transaction makes it possible to determine when a transaction can no
+//! longer be included in the chain without having to validate it again.
+//!
+//! > **Note**: Strictly speaking, the format of transactions, and thus the way the era is
+//! >           encoded, depends on the runtime. [`extrinsic_era`] assumes the format used by
+//! >           most Substrate-based chains and returns `None` if the transaction doesn't match
+//! >           this format.
+
+use crate::util;
+
+use core::{cmp, convert::TryFrom as _};
+
+/// Range of blocks in which a transaction can be included.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Era {
+    /// The transaction can be included in any block.
+    Immortal,
+
+    /// The transaction can only be included in `period` blocks starting from its birth.
+    Mortal {
+        /// Number of blocks during which the transaction is valid. Always a power of two
+        /// superior or equal to 4.
+        period: u64,
+        /// Number of the birth block modulo `period`. Always inferior to `period`.
+        phase: u64,
+    },
+}
+
+impl Era {
+    /// Builds a mortal era that is valid for approximately `period` blocks starting from the
+    /// block whose number is `current`.
+    ///
+    /// The period is rounded up to the next power of two and clamped between `4` and `65536`.
+    pub fn mortal(period: u64, current: u64) -> Era {
+        let period = cmp::min(cmp::max(period.next_power_of_two(), 4), 1 << 16);
+        let quantize_factor = cmp::max(period >> 12, 1);
+        let phase = current % period / quantize_factor * quantize_factor;
+        Era::Mortal { period, phase }
+    }
+
+    /// Returns the SCALE encoding of this era.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the era is mortal and its period or phase doesn't respect the constraints
+    /// documented in [`Era::Mortal`].
+    ///
+    pub fn scale_encoding(&self) -> impl AsRef<[u8]> + Clone {
+        let mut array = arrayvec::ArrayVec::<u8, 2>::new();
+        match *self {
+            Era::Immortal => array.push(0),
+            Era::Mortal { period, phase } => {
+                assert!(period.is_power_of_two() && (4..=(1 << 16)).contains(&period));
+                assert!(phase < period);
+                let quantize_factor = cmp::max(period >> 12, 1);
+                let encoded = u16::try_from(
+                    u64::from(period.trailing_zeros() - 1) | ((phase / quantize_factor) << 4),
+                )
+                .unwrap();
+                array.try_extend_from_slice(&encoded.to_le_bytes()).unwrap();
+            }
+        }
+        array
+    }
+
+    /// Decodes a SCALE-encoded era.
+    ///
+    /// Returns `None` if the encoding is invalid.
+    pub fn decode(scale_encoded: &[u8]) -> Option<Era> {
+        match nom::combinator::all_consuming(era)(scale_encoded) {
+            Ok((_, era)) => Some(era),
+            Err(_) => None,
+        }
+    }
+
+    /// Returns the number of the first block in which the transaction can be included, assuming
+    /// that `current` is a block number within the validity window of the transaction.
+    pub fn birth(&self, current: u64) -> u64 {
+        match *self {
+            Era::Immortal => 0,
+            Era::Mortal { period, phase } => {
+                (cmp::max(current, phase) - phase) / period * period + phase
+            }
+        }
+    }
+
+    /// Returns the number of the first block in which the transaction can no longer be
+    /// included, assuming that `current` is a block number within the validity window of the
+    /// transaction.
+    ///
+    /// Returns `u64::max_value()` if the era is immortal.
+    pub fn death(&self, current: u64) -> u64 {
+        match *self {
+            Era::Immortal => u64::max_value(),
+            Era::Mortal { period, .. } => self.birth(current).saturating_add(period),
+        }
+    }
+}
+
+/// Extracts the era of the given double-SCALE-encoded transaction.
+///
+/// Returns `None` if the transaction is unsigned or doesn't follow the format of signed
+/// transactions used by most Substrate-based chains, in other words version 4 of the
+/// transactions format, with a `MultiAddress` as the signer and a `MultiSignature` as the
+/// signature.
+pub fn extrinsic_era(double_scale_encoded: &[u8]) -> Option<Era> {
+    let result: Result<_, nom::Err<nom::error::Error<&[u8]>>> = nom::sequence::preceded(
+        nom::sequence::tuple((
+            util::nom_scale_compact_usize,
+            nom::bytes::complete::tag(&[0x84]),
+            multi_address,
+            multi_signature,
+        )),
+        era,
+    )(double_scale_encoded);
+
+    result.ok().map(|(_, era)| era)
+}
+
+// `nom` parser functions can be found below.
+
+fn era(bytes: &[u8]) -> nom::IResult<&[u8], Era> {
+    nom::error::context(
+        "era",
+        nom::branch::alt((
+            nom::combinator::map(nom::bytes::complete::tag(&[0]), |_| Era::Immortal),
+            nom::combinator::map_opt(nom::number::complete::le_u16, |encoded| {
+                let encoded = u64::from(encoded);
+                let period = 2u64 << (encoded % (1 << 4));
+                let quantize_factor = cmp::max(period >> 12, 1);
+                let phase = (encoded >> 4) * quantize_factor;
+                if period >= 4 && phase < period {
+                    Some(Era::Mortal { period, phase })
+                } else {
+                    None
+                }
+            }),
+        )),
+    )(bytes)
+}
+
+fn multi_address(bytes: &[u8]) -> nom::IResult<&[u8], ()> {
+    nom::error::context(
+        "multi address",
+        nom::combinator::map(
+            nom::branch::alt((
+                nom::sequence::preceded(
+                    nom::bytes::complete::tag(&[0]),
+                    nom::bytes::complete::take(32u32),
+                ),
+                nom::sequence::preceded(
+                    nom::bytes::complete::tag(&[1]),
+                    nom::combinator::recognize(util::nom_scale_compact_usize),
+                ),
+                nom::sequence::preceded(nom::bytes::complete::tag(&[2]), util::nom_bytes_decode),
+                nom::sequence::preceded(
+                    nom::bytes::complete::tag(&[3]),
+                    nom::bytes::complete::take(32u32),
+                ),
+                nom::sequence::preceded(
+                    nom::bytes::complete::tag(&[4]),
+                    nom::bytes::complete::take(20u32),
+                ),
+            )),
+            |_| (),
+        ),
+    )(bytes)
+}
+
+fn multi_signature(bytes: &[u8]) -> nom::IResult<&[u8], ()> {
+    nom::error::context(
+        "multi signature",
+        nom::combinator::map(
+            nom::branch::alt((
+                nom::sequence::preceded(
+                    nom::bytes::complete::tag(&[0]),
+                    nom::bytes::complete::take(64u32),
+                ),
+                nom::sequence::preceded(
+                    nom::bytes::complete::tag(&[1]),
+                    nom::bytes::complete::take(64u32),
+                ),
+                nom::sequence::preceded(
+                    nom::bytes::complete::tag(&[2]),
+                    nom::bytes::complete::take(65u32),
+                ),
+            )),
+            |_| (),
+        ),
+    )(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{extrinsic_era, Era};
+
+    #[test]
+    fn decode_mortal() {
+        let era = Era::decode(&[0xa5, 0x02]).unwrap();
+        assert_eq!(
+            era,
+            Era::Mortal {
+                period: 64,
+                phase: 42
+            }
+        );
+        assert_eq!(era.birth(100), 42);
+        assert_eq!(era.death(100), 106);
+    }
+
+    #[test]
+    fn decode_immortal() {
+        assert_eq!(Era::decode(&[0]), Some(Era::Immortal));
+        assert_eq!(Era::Immortal.death(100), u64::max_value());
+    }
+
+    #[test]
+    fn encode_roundtrip() {
+        let era = Era::mortal(64, 106);
+        assert_eq!(
+            era,
+            Era::Mortal {
+                period: 64,
+                phase: 42
+            }
+        );
+        assert_eq!(era.scale_encoding().as_ref(), &[0xa5, 0x02]);
+        assert_eq!(Era::decode(era.scale_encoding().as_ref()), Some(era));
+
+        let era = Era::mortal(100_000, 123_456);
+        assert_eq!(Era::decode(era.scale_encoding().as_ref()), Some(era));
+        assert_eq!(Era::Immortal.scale_encoding().as_ref(), &[0]);
+    }
+
+    #[test]
+    fn invalid_era() {
+        // Period of 2.
+        assert_eq!(Era::decode(&[0x10, 0x00]), None);
+    }
+
+    #[test]
+    fn signed_extrinsic() {
+        let mut extrinsic = vec![0x84, 0x00];
+        extrinsic.extend_from_slice(&[0xaa; 32]);
+        extrinsic.push(0x01);
+        extrinsic.extend_from_slice(&[0xbb; 64]);
+        extrinsic.extend_from_slice(&[0xa5, 0x02]);
+        extrinsic.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+        let mut double_encoded = crate::util::encode_scale_compact_usize(extrinsic.len())
+            .as_ref()
+            .to_vec();
+        double_encoded.extend_from_slice(&extrinsic);
+
+        assert_eq!(
+            extrinsic_era(&double_encoded),
+            Some(Era::Mortal {
+                period: 64,
+                phase: 42
+            })
+        );
+
+        // Unsigned extrinsic.
+        assert_eq!(extrinsic_era(&[0x0c, 0x04, 0x00, 0x00]), None);
+    }
+}
@@ -58,6 +58,19 @@
 //! validated. Validation should be performed using the [`validate`](../validate) module, and
 //! the result reported with [`LightPool::set_validation_result`].
 //!
+//! # Transactions replacement
+//!
+//! Two transactions that [provide](ValidTransaction::provides) the same tag are mutually
+//! exclusive. In practice, this typically happens when the same sender submits two transactions
+//! with the same nonce, as runtimes generate a tag derived from the sender and the nonce.
+//!
+//! When a transaction is successfully validated and provides a tag that is already provided by
+//! another transaction in the pool, only the transaction with the highest priority is kept. If
+//! the newly-validated transaction has a strictly higher priority than all the conflicting
+//! transactions, these conflicting transactions are removed from the pool and returned by
+//! [`LightPool::set_validation_result`]. Otherwise, the validation result is discarded and the
+//! newly-validated transaction should be removed by the API user.
+//!
 
 use super::validate::{TransactionValidityError, ValidTransaction};
 use crate::chain::fork_tree;
@@ -66,7 +79,7 @@ use alloc::{
     collections::{BTreeMap, BTreeSet},
     vec::Vec,
 };
-use core::{convert::TryFrom as _, fmt, iter};
+use core::{convert::TryFrom as _, fmt, iter, mem};
 
 mod tests;
 
@@ -96,6 +109,10 @@ pub struct LightPool<TTx, TBl> {
     /// Actual list of transactions.
     transactions: slab::Slab<Transaction<TTx>>,
 
+    /// Sum of the sizes of [`Transaction::double_scale_encoded`] of all the transactions in
+    /// [`LightPool::transactions`].
+    transactions_bytes: usize,
+
     /// Holds tuples of `(block_hash, transaction_id)`. When an entry is present in this set, it
     /// means that this transaction has been found in the body of this block.
     ///
@@ -129,6 +146,10 @@ pub struct LightPool<TTx, TBl> {
     /// hash of the bytes of the transaction.
     by_hash: BTreeSet<([u8; 32], TransactionId)>,
 
+    /// Transaction ids (i.e. indices within [`LightPool::transactions`]) indexed by the tags
+    /// they provide, as found in [`Transaction::provided_tags`].
+    by_provided_tag: BTreeSet<(Vec<u8>, TransactionId)>,
+
     /// Tree of all the non-finalized and finalized blocks. This is necessary in case of a re-org
     /// (i.e. the new best block is a nephew of the previous best block) in order to know which
     /// transactions that were present in the previous best chain are still present in the new
@@ -156,6 +177,7 @@ impl<TTx, TBl> LightPool<TTx, TBl> {
     pub fn new(config: Config) -> Self {
         LightPool {
             transactions: slab::Slab::with_capacity(config.transactions_capacity),
+            transactions_bytes: 0,
             transactions_by_inclusion: BTreeSet::new(),
             included_transactions: BTreeSet::new(),
             transaction_validations: BTreeMap::new(),
@@ -165,6 +187,7 @@ impl<TTx, TBl> LightPool<TTx, TBl> {
                 Default::default(),
             ),
             by_hash: BTreeSet::new(),
+            by_provided_tag: BTreeSet::new(),
             blocks_tree: fork_tree::ForkTree::with_capacity(config.blocks_capacity),
             blocks_by_id: hashbrown::HashMap::with_capacity_and_hasher(
                 config.blocks_capacity,
@@ -181,6 +204,11 @@ impl<TTx, TBl> LightPool<TTx, TBl> {
         self.transactions.len()
     }
 
+    /// Returns the total size in bytes of the double-SCALE-encoded transactions in the pool.
+    pub fn transactions_bytes(&self) -> usize {
+        self.transactions_bytes
+    }
+
     /// Inserts a new unvalidated transaction in the pool.
     ///
     /// Must be passed as parameter the double-SCALE-encoded transaction.
@@ -190,9 +218,12 @@ impl<TTx, TBl> LightPool<TTx, TBl> {
         user_data: TTx,
     ) -> TransactionId {
         let hash = blake2_hash(double_scale_encoded.as_ref());
+        self.transactions_bytes += double_scale_encoded.len();
 
         let tx_id = TransactionId(self.transactions.insert(Transaction {
             double_scale_encoded,
+            priority: 0,
+            provided_tags: Vec::new(),
             user_data,
         }));
 
@@ -214,6 +245,7 @@ impl<TTx, TBl> LightPool<TTx, TBl> {
     #[track_caller]
     pub fn remove_transaction(&mut self, id: TransactionId) -> TTx {
         let tx = self.transactions.remove(id.0); // Panics if `id` is invalid.
+        self.transactions_bytes -= tx.double_scale_encoded.len();
 
         let blocks_included = self
             .included_transactions
@@ -251,6 +283,11 @@ impl<TTx, TBl> LightPool<TTx, TBl> {
             .remove(&(blake2_hash(&tx.double_scale_encoded), id));
         debug_assert!(_removed);
 
+        for tag in tx.provided_tags {
+            let _removed = self.by_provided_tag.remove(&(tag, id));
+            debug_assert!(_removed);
+        }
+
         tx.user_data
     }
 
@@ -303,6 +340,19 @@ impl<TTx, TBl> LightPool<TTx, TBl> {
         Some(&self.transactions.get(id.0)?.double_scale_encoded)
     }
 
+    /// Returns the priority of the given transaction according to its latest successful
+    /// validation.
+    ///
+    /// Returns `None` if the identifier is invalid or if the transaction has never been
+    /// successfully validated.
+    pub fn transaction_priority(&self, id: TransactionId) -> Option<u64> {
+        let tx = self.transactions.get(id.0)?;
+        if tx.provided_tags.is_empty() {
+            return None;
+        }
+        Some(tx.priority)
+    }
+
     /// Tries to find the transactions in the pool whose bytes are `double_scale_encoded`.
     pub fn find_transaction(
         &'_ self,
@@ -368,21 +418,74 @@ impl<TTx, TBl> LightPool<TTx, TBl> {
     /// The block hash must be the block hash against which the transaction has been
     /// validated.
     ///
+    /// If the validation is successful and the transaction provides a tag that is also provided
+    /// by other transactions of the pool, the transaction with the highest priority is kept. See
+    /// the documentation of [`SetValidationResult`].
+    ///
     /// # Panic
     ///
     /// Panics if the transaction with the given id is invalid.
     /// Panics if no block with that hash has been inserted before.
     ///
     // TODO: is it correct to pass a `Result`, instead of just a `ValidTransaction`?
+    #[must_use]
     pub fn set_validation_result(
         &mut self,
         id: TransactionId,
         block_hash_validated_against: &[u8; 32],
         result: Result<ValidTransaction, TransactionValidityError>,
-    ) {
-        // Make sure that the block exists.
+    ) -> SetValidationResult<TTx> {
+        // Make sure that the transaction and the block exist.
+        assert!(self.transactions.contains(id.0));
         let _block_index = *self.blocks_by_id.get(block_hash_validated_against).unwrap();
 
+        // List of transactions that are removed from the pool because they provide the same
+        // tags as this transaction with a lower priority.
+        let mut usurped = Vec::new();
+
+        if let Ok(valid) = &result {
+            // Find the other transactions that provide at least one of the tags that this
+            // transaction provides.
+            let mut conflicting = Vec::new();
+            for tag in &valid.provides {
+                for (_, other_id) in self.by_provided_tag.range(
+                    (tag.clone(), TransactionId(usize::min_value()))
+                        ..=(tag.clone(), TransactionId(usize::max_value())),
+                ) {
+                    if *other_id != id && !conflicting.contains(other_id) {
+                        conflicting.push(*other_id);
+                    }
+                }
+            }
+
+            // A transaction can only replace other transactions if its priority is strictly
+            // higher than theirs.
+            if let Some(existing) = conflicting
+                .iter()
+                .find(|other_id| self.transactions[other_id.0].priority >= valid.priority)
+            {
+                return SetValidationResult::TooLowPriority {
+                    existing: *existing,
+                };
+            }
+
+            // Update the tags provided by this transaction.
+            let tx = &mut self.transactions[id.0];
+            for tag in mem::take(&mut tx.provided_tags) {
+                let _removed = self.by_provided_tag.remove(&(tag, id));
+                debug_assert!(_removed);
+            }
+            tx.priority = valid.priority;
+            tx.provided_tags = valid.provides.clone();
+            for tag in &valid.provides {
+                self.by_provided_tag.insert((tag.clone(), id));
+            }
+
+            for other_id in conflicting {
+                usurped.push((other_id, self.remove_transaction(other_id)));
+            }
+        }
+
         // This will replace an existing entry.
         self.transaction_validations
             .insert((id, *block_hash_validated_against), result);
@@ -400,6 +503,47 @@ impl<TTx, TBl> LightPool<TTx, TBl> {
                     == 1
             );
         }
+
+        SetValidationResult::Stored { usurped }
+    }
+
+    /// Removes all the validation results of the transaction with the given identifier. Its
+    /// status is then "not validated", and it will be returned again by
+    /// [`LightPool::unvalidated_transactions`].
+    ///
+    /// This is typically used after a transaction has been retracted from the best chain, as
+    /// the previous validation results might no longer be accurate.
+    ///
+    /// The tags provided by the transaction according to its latest successful validation are
+    /// still taken into account until a new validation result is set.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the transaction with the given id is invalid.
+    ///
+    pub fn clear_validation_results(&mut self, id: TransactionId) {
+        assert!(self.transactions.contains(id.0));
+
+        let blocks_validated = self
+            .transaction_validations
+            .range((id, [0; 32])..=(id, [0xff; 32]))
+            .map(|((_, block), _)| *block)
+            .collect::<Vec<_>>();
+
+        if blocks_validated.is_empty() {
+            debug_assert!(self.not_validated.contains(&id));
+            return;
+        }
+
+        for block_hash in blocks_validated {
+            let _removed = self.transaction_validations.remove(&(id, block_hash));
+            debug_assert!(_removed.is_some());
+            let _removed = self.transactions_by_validation.remove(&(block_hash, id));
+            debug_assert!(_removed);
+        }
+
+        let _was_inserted = self.not_validated.insert(id);
+        debug_assert!(_was_inserted);
     }
 
     /// Adds a block to the collection of blocks.
@@ -819,7 +963,7 @@ impl<TTx, TBl> LightPool<TTx, TBl> {
                 // Completely remove this transaction from the pool, similar to what
                 // `remove_transaction` does.
                 let tx = self.transactions.remove(tx_id.0);
-                included_transactions.push((*tx_id, tx.user_data));
+                self.transactions_bytes -= tx.double_scale_encoded.len();
 
                 let blocks_included = self
                     .included_transactions
@@ -858,6 +1002,13 @@ impl<TTx, TBl> LightPool<TTx, TBl> {
                     .by_hash
                     .remove(&(blake2_hash(&tx.double_scale_encoded), *tx_id));
                 debug_assert!(_removed);
+
+                for tag in tx.provided_tags {
+                    let _removed = self.by_provided_tag.remove(&(tag, *tx_id));
+                    debug_assert!(_removed);
+                }
+
+                included_transactions.push((*tx_id, tx.user_data));
             }
 
             // Purge the state from any validation information about that block.
@@ -956,11 +1107,40 @@ pub struct SetBestBlock {
     pub included_transactions: Vec<(TransactionId, [u8; 32])>,
 }
 
+/// See [`LightPool::set_validation_result`].
+#[derive(Debug)]
+pub enum SetValidationResult<TTx> {
+    /// The validation result has been stored.
+    Stored {
+        /// List of transactions that provided at least one of the tags provided by the
+        /// newly-validated transaction, and whose priority was lower. These transactions have
+        /// been removed from the pool.
+        usurped: Vec<(TransactionId, TTx)>,
+    },
+
+    /// The transaction provides at least one tag that is also provided by another transaction
+    /// of the pool whose priority is higher or equal. The validation result has been discarded,
+    /// and the transaction should be removed from the pool.
+    TooLowPriority {
+        /// One of the transactions of the pool that conflicts with the newly-validated
+        /// transaction.
+        existing: TransactionId,
+    },
+}
+
 /// Entry in [`LightPool::transactions`].
 struct Transaction<TTx> {
     /// Bytes corresponding to the double-SCALE-encoded transaction.
     double_scale_encoded: Vec<u8>,
 
+    /// Priority of the transaction according to its latest successful validation. Irrelevant if
+    /// [`Transaction::provided_tags`] is empty.
+    priority: u64,
+
+    /// Tags provided by the transaction according to its latest successful validation. Empty if
+    /// the transaction has never been successfully validated.
+    provided_tags: Vec<Vec<u8>>,
+
     /// User data chosen by the user.
     user_data: TTx,
 }
@@ -17,7 +17,10 @@
 
 #![cfg(test)]
 
-use super::{Config, LightPool};
+use super::{Config, LightPool, SetValidationResult};
+use crate::transactions::validate::ValidTransaction;
+
+use core::num::NonZeroU64;
 
 #[test]
 fn regular_path() {
@@ -112,4 +115,89 @@ fn transaction_retracted_after_reorg() {
     assert!(set_best_block.retracted_transactions.is_empty());
 }
 
+#[test]
+fn same_tag_replacement() {
+    let mut pool = LightPool::new(Config {
+        blocks_capacity: 16,
+        finalized_block_hash: [0; 32],
+        transactions_capacity: 16,
+    });
+
+    pool.add_block([1; 32], &[0; 32], ());
+    let _ = pool.set_best_block(&[1; 32]);
+
+    let validity = |priority| ValidTransaction {
+        priority,
+        requires: Vec::new(),
+        provides: vec![b"sender-nonce".to_vec()],
+        longevity: NonZeroU64::new(64).unwrap(),
+        propagate: true,
+    };
+
+    let tx1 = pool.add_unvalidated(vec![4, 1], 1);
+    let tx2 = pool.add_unvalidated(vec![4, 2], 2);
+    let tx3 = pool.add_unvalidated(vec![4, 3], 3);
+
+    match pool.set_validation_result(tx1, &[1; 32], Ok(validity(10))) {
+        SetValidationResult::Stored { usurped } => assert!(usurped.is_empty()),
+        _ => panic!(),
+    }
+
+    // Same priority as the existing transaction. Doesn't replace it.
+    match pool.set_validation_result(tx2, &[1; 32], Ok(validity(10))) {
+        SetValidationResult::TooLowPriority { existing } => assert_eq!(existing, tx1),
+        _ => panic!(),
+    }
+    assert_eq!(pool.remove_transaction(tx2), 2);
+
+    // Higher priority than the existing transaction. Replaces it.
+    match pool.set_validation_result(tx3, &[1; 32], Ok(validity(20))) {
+        SetValidationResult::Stored { usurped } => assert_eq!(usurped, vec![(tx1, 1)]),
+        _ => panic!(),
+    }
+    assert_eq!(pool.num_transactions(), 1);
+    assert!(pool.transaction_user_data(tx1).is_none());
+    assert_eq!(pool.transaction_user_data(tx3), Some(&3));
+    assert_eq!(pool.transaction_priority(tx3), Some(20));
+    assert_eq!(pool.transactions_bytes(), 2);
+}
+
+#[test]
+fn clear_validation_results() {
+    let mut pool = LightPool::new(Config {
+        blocks_capacity: 16,
+        finalized_block_hash: [0; 32],
+        transactions_capacity: 16,
+    });
+
+    pool.add_block([1; 32], &[0; 32], ());
+    let _ = pool.set_best_block(&[1; 32]);
+
+    let tx_id = pool.add_unvalidated(vec![4, 0], ());
+    assert_eq!(pool.unvalidated_transactions().count(), 1);
+    assert_eq!(pool.transaction_priority(tx_id), None);
+
+    let _ = pool.set_validation_result(
+        tx_id,
+        &[1; 32],
+        Ok(ValidTransaction {
+            priority: 0,
+            requires: Vec::new(),
+            provides: vec![vec![0]],
+            longevity: NonZeroU64::new(64).unwrap(),
+            propagate: true,
+        }),
+    );
+    assert_eq!(pool.unvalidated_transactions().count(), 0);
+    assert!(pool.is_valid_against_best_block(tx_id));
+
+    pool.clear_validation_results(tx_id);
+    assert_eq!(pool.unvalidated_transactions().count(), 1);
+    assert!(!pool.is_valid_against_best_block(tx_id));
+
+    // Transaction can be removed afterwards.
+    pool.remove_transaction(tx_id);
+    assert_eq!(pool.unvalidated_transactions().count(), 0);
+}
+
 // TODO: more tests
@@ -16,6 +16,28 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 //! Runtime call to obtain the transactions validity status.
+//!
+//! The validity of a transaction is obtained by calling the
+//! `TaggedTransactionQueue_validate_transaction` entry point of the runtime, and decoding its
+//! output into a `TransactionValidity`.
+//!
+//! # Usage
+//!
+//! Calling [`validate_transaction`] returns a [`Query`] enum containing the state of the
+//! validation. If the [`Query`] is a [`Query::Finished`], then the validation is over and the
+//! result can be retrieved. Otherwise, access to the storage of the block the transaction is
+//! validated against is required in order to continue.
+//!
+//! The parameters of the runtime call depend on the version of the `TaggedTransactionQueue` API
+//! that the runtime supports. [`validate_transaction`] automatically determines this version.
+//! Callers that perform the runtime call themselves can instead use
+//! [`validate_transaction_runtime_parameters_v2`] or
+//! [`validate_transaction_runtime_parameters_v3`] in order to build the parameters, and
+//! [`decode_validate_transaction_return_value`] in order to decode the output.
+//!
+//! > **Note**: In version 2 of the API, the runtime expects `Core_initialize_block` to have been
+//! >           called beforehand. [`validate_transaction`] takes care of this, but callers
+//! >           performing the runtime call themselves must do it manually.
 
 use crate::{
     executor::{self, host, runtime_host},
@@ -24,6 +46,7 @@ use crate::{
 
 use alloc::{borrow::ToOwned as _, collections::BTreeMap, vec::Vec};
 use core::{iter, num::NonZeroU64};
+use hashbrown::HashMap;
 
 /// Configuration for a transaction validation process.
 pub struct Config<'a, TTx> {
@@ -195,7 +218,8 @@ pub enum Error {
     WasmVmReadOnly(runtime_host::ErrorDetail),
     /// Error while decoding the output of the runtime.
     OutputDecodeError(DecodeError),
-    /// The list of provided tags ([`ValidTransaction::provides`]). This is a bug in the runtime.
+    /// The list of provided tags ([`ValidTransaction::provides`]) is empty. This is a bug in the
+    /// runtime.
     EmptyProvidedTags,
 }
 
@@ -288,6 +312,7 @@ pub fn validate_transaction(
             return Query::Finished {
                 result: Err(Error::RuntimeVersion(err)),
                 virtual_machine,
+                offchain_storage_changes: Default::default(),
             }
         }
     };
@@ -305,6 +330,7 @@ pub fn validate_transaction(
                     return Query::Finished {
                         result: Err(Error::InvalidHeader(err)),
                         virtual_machine,
+                        offchain_storage_changes: Default::default(),
                     }
                 }
             };
@@ -323,6 +349,7 @@ pub fn validate_transaction(
                 .scale_encoding(),
                 top_trie_root_calculation_cache: None,
                 storage_top_trie_changes: BTreeMap::new(),
+                storage_child_tries_changes: BTreeMap::new(),
                 offchain_storage_changes: hashbrown::HashMap::default(),
             });
 
@@ -343,6 +370,7 @@ pub fn validate_transaction(
                 Err((err, virtual_machine)) => Query::Finished {
                     result: Err(Error::WasmStart(err)),
                     virtual_machine,
+                    offchain_storage_changes: Default::default(),
                 },
             }
         }
@@ -359,6 +387,7 @@ pub fn validate_transaction(
                 ),
                 top_trie_root_calculation_cache: None,
                 storage_top_trie_changes: BTreeMap::default(),
+                storage_child_tries_changes: BTreeMap::default(),
                 offchain_storage_changes: hashbrown::HashMap::default(),
             });
 
@@ -367,12 +396,14 @@ pub fn validate_transaction(
                 Err((err, virtual_machine)) => Query::Finished {
                     result: Err(Error::WasmStart(err)),
                     virtual_machine,
+                    offchain_storage_changes: Default::default(),
                 },
             }
         }
         _ => Query::Finished {
             result: Err(Error::UnknownApiVersion),
             virtual_machine,
+            offchain_storage_changes: Default::default(),
         },
     }
 }
@@ -389,6 +420,9 @@ pub enum Query {
         result: Result<Result<ValidTransaction, TransactionValidityError>, Error>,
         /// Virtual machine initially passed through the configuration.
         virtual_machine: host::HostVmPrototype,
+        /// Changes to the offchain storage made by the runtime through the offchain indexing
+        /// host functions. Always empty if the runtime call has failed.
+        offchain_storage_changes: HashMap<Vec<u8>, Option<Vec<u8>>, fnv::FnvBuildHasher>,
     },
     /// Loading a storage value is required in order to continue.
     StorageGet(StorageGet),
@@ -435,6 +469,7 @@ impl Query {
                     return Query::Finished {
                         result: Err(Error::OutputDecodeError(DecodeError())),
                         virtual_machine: success.virtual_machine.into_prototype(),
+                        offchain_storage_changes: Default::default(),
                     };
                 }
 
@@ -446,6 +481,7 @@ impl Query {
                         info.transaction_source,
                     ),
                     storage_top_trie_changes: success.storage_top_trie_changes,
+                    storage_child_tries_changes: success.storage_child_tries_changes,
                     offchain_storage_changes: success.offchain_storage_changes,
                     top_trie_root_calculation_cache: Some(success.top_trie_root_calculation_cache),
                 });
@@ -455,12 +491,14 @@ impl Query {
                     Err((err, virtual_machine)) => Query::Finished {
                         result: Err(Error::WasmStart(err)),
                         virtual_machine,
+                        offchain_storage_changes: Default::default(),
                     },
                 }
             }
             runtime_host::RuntimeHostVm::Finished(Err(err)) => Query::Finished {
                 result: Err(Error::WasmVmReadWrite(err.detail)),
                 virtual_machine: err.prototype,
+                offchain_storage_changes: Default::default(),
             },
             runtime_host::RuntimeHostVm::StorageGet(i) => {
                 Query::StorageGet(StorageGet(StorageGetInner::Stage1(i, info)))
@@ -471,6 +509,11 @@ impl Query {
             runtime_host::RuntimeHostVm::NextKey(inner) => {
                 Query::NextKey(NextKey(NextKeyInner::Stage1(inner, info)))
             }
+            runtime_host::RuntimeHostVm::SignatureBatchVerify(inner) => {
+                // The validation of a transaction only verifies a small number of signatures.
+                // The batch is thus verified on the current thread.
+                Query::from_step1(inner.verify_and_resume(), info)
+            }
         }
     }
 
@@ -492,6 +535,7 @@ impl Query {
                                 return Query::Finished {
                                     result: Err(Error::EmptyProvidedTags),
                                     virtual_machine: success.virtual_machine.into_prototype(),
+                                    offchain_storage_changes: Default::default(),
                                 };
                             }
                         }
@@ -501,6 +545,7 @@ impl Query {
                         return Query::Finished {
                             result: Err(err),
                             virtual_machine: success.virtual_machine.into_prototype(),
+                            offchain_storage_changes: Default::default(),
                         }
                     }
                 };
@@ -508,11 +553,13 @@ impl Query {
                 Query::Finished {
                     result: Ok(result),
                     virtual_machine: success.virtual_machine.into_prototype(),
+                    offchain_storage_changes: success.offchain_storage_changes,
                 }
             }
             runtime_host::RuntimeHostVm::Finished(Err(err)) => Query::Finished {
                 result: Err(Error::WasmVmReadOnly(err.detail)),
                 virtual_machine: err.prototype,
+                offchain_storage_changes: Default::default(),
             },
             runtime_host::RuntimeHostVm::StorageGet(i) => {
                 Query::StorageGet(StorageGet(StorageGetInner::Stage2(i, info)))
@@ -523,6 +570,11 @@ impl Query {
             runtime_host::RuntimeHostVm::NextKey(inner) => {
                 Query::NextKey(NextKey(NextKeyInner::Stage2(inner, info)))
             }
+            runtime_host::RuntimeHostVm::SignatureBatchVerify(inner) => {
+                // The validation of a transaction only verifies a small number of signatures.
+                // The batch is thus verified on the current thread.
+                Query::from_step2(inner.verify_and_resume(), info)
+            }
         }
     }
 }
@@ -564,6 +616,15 @@ impl StorageGet {
         }
     }
 
+    /// If `Some`, the key must be read from the child trie whose key is returned rather than from
+    /// the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        match &self.0 {
+            StorageGetInner::Stage1(inner, _) => inner.child_trie().map(either::Left),
+            StorageGetInner::Stage2(inner, _) => inner.child_trie().map(either::Right),
+        }
+    }
+
     /// Injects the corresponding storage value.
     pub fn inject_value(self, value: Option<impl Iterator<Item = impl AsRef<[u8]>>>) -> Query {
         match self.0 {
@@ -595,6 +656,15 @@ impl NextKey {
         }
     }
 
+    /// If `Some`, the key must be searched in the child trie whose key is returned rather than
+    /// in the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        match &self.0 {
+            NextKeyInner::Stage1(inner, _) => inner.child_trie().map(either::Left),
+            NextKeyInner::Stage2(inner, _) => inner.child_trie().map(either::Right),
+        }
+    }
+
     /// Injects the key.
     ///
     /// # Panic
@@ -628,6 +698,15 @@ impl PrefixKeys {
         }
     }
 
+    /// If `Some`, the keys must be loaded from the child trie whose key is returned rather than
+    /// from the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        match &self.0 {
+            PrefixKeysInner::Stage1(inner, _) => inner.child_trie().map(either::Left),
+            PrefixKeysInner::Stage2(inner, _) => inner.child_trie().map(either::Right),
+        }
+    }
+
     /// Injects the list of keys ordered lexicographically.
     pub fn inject_keys_ordered(self, keys: impl Iterator<Item = impl AsRef<[u8]>>) -> Query {
         match self.0 {
@@ -83,6 +83,28 @@
 //! its ancestors. As such, the time spent calculating the Merkle value of the root node of a trie
 //! mostly depends on the number of modifications that are performed on it, and only a bit on the
 //! size of the trie.
+//!
+//! ## Trie entry versions
+//!
+//! Storage values can be encoded in the trie in two different ways. In version 0, the storage
+//! value is always directly included in the node value of its node. In version 1, storage values
+//! that are at least 33 bytes long are instead hashed, and only their hash is included in the
+//! node value.
+//!
+//! Which version to use is indicated by the runtime when it requests the calculation of a trie
+//! root. See [`TrieEntryVersion`].
+//!
+//! ## Node values
+//!
+//! The *node value* of a node is its encoding. It consists of a header indicating the type of
+//! node and the length of its partial key, followed with the partial key, a bitmap of the
+//! children that are present, the storage value or its hash, and finally the Merkle values of
+//! the children. The Merkle value of a node is its node value, or the hash of its node value if
+//! the node value is at least 32 bytes long or if the node is the root node.
+//!
+//! The [`node_value`] module contains the functions that build node values, and the
+//! [`proof_node_decode`] module contains the function that decodes a node value into its
+//! components. Both support all types of nodes, and both versions of storage values.
 
 use alloc::{collections::BTreeMap, vec::Vec};
 use core::{iter, mem};
@@ -92,8 +114,11 @@ mod nibble;
 pub mod calculate_root;
 pub mod node_value;
 pub mod prefix_proof;
+pub mod proof_decode;
+pub mod proof_encode;
 pub mod proof_node_decode;
 pub mod proof_verify;
+pub mod streaming_root;
 pub mod trie_structure;
 
 pub use nibble::{
@@ -101,6 +126,27 @@ pub use nibble::{
     NibbleFromU8Error,
 };
 
+/// Way a storage value is encoded in the trie. See [the module-level documentation](..).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum TrieEntryVersion {
+    /// Storage values are always directly included in node values.
+    V0,
+    /// Storage values that are at least 33 bytes long are hashed.
+    V1,
+}
+
+impl TrieEntryVersion {
+    /// Turns a state version, as passed by the runtime to the host functions, into a
+    /// [`TrieEntryVersion`]. Returns `None` if the value is unknown.
+    pub fn from_state_version(state_version: u32) -> Option<Self> {
+        match state_version {
+            0 => Some(TrieEntryVersion::V0),
+            1 => Some(TrieEntryVersion::V1),
+            _ => None,
+        }
+    }
+}
+
 /// Radix-16 Merkle-Patricia trie.
 // TODO: probably useless, remove
 pub struct Trie {
@@ -144,11 +190,13 @@ impl Trie {
         self.entries.clear();
     }
 
-    /// Calculates the Merkle value of the root node.
+    /// Calculates the Merkle value of the root node, encoding all the storage values with the
+    /// given version.
     ///
     /// Passes an optional cache.
     pub fn root_merkle_value(
         &self,
+        version: TrieEntryVersion,
         mut cache: Option<&mut calculate_root::CalculationCache>,
     ) -> [u8; 32] {
         let mut calculation = calculate_root::root_merkle_value({
@@ -178,7 +226,8 @@ impl Trie {
                 }
                 calculate_root::RootMerkleValueCalculation::StorageValue(value) => {
                     let key = value.key().collect::<Vec<u8>>();
-                    calculation = value.inject(self.entries.get(&key));
+                    calculation =
+                        value.inject(self.entries.get(&key).map(|value| (value, version)));
                 }
             }
         }
@@ -202,18 +251,76 @@ pub fn empty_trie_merkle_value() -> [u8; 32] {
                 calculation = keys.inject(iter::empty::<iter::Empty<u8>>());
             }
             calculate_root::RootMerkleValueCalculation::StorageValue(val) => {
-                calculation = val.inject(None::<&[u8]>);
+                calculation = val.inject(None::<(&[u8], _)>);
             }
         }
     }
 }
 
+/// Returns the Merkle value of the root of the trie whose keys are the SCALE-compact-encoded
+/// indices of the given entries, and whose storage values are the entries themselves.
+///
+/// This is notably how the extrinsics root found in the header of a block is calculated from the
+/// list of extrinsics of the block.
+pub fn ordered_root(
+    version: TrieEntryVersion,
+    entries: impl Iterator<Item = impl AsRef<[u8]>>,
+) -> [u8; 32] {
+    // The SCALE-compact encoding of the indices isn't ordered lexicographically, and the
+    // entries must thus be sorted before being passed to the calculation.
+    let mut entries = entries
+        .enumerate()
+        .map(|(index, entry)| {
+            let key = crate::util::encode_scale_compact_usize(index)
+                .as_ref()
+                .to_vec();
+            (key, entry)
+        })
+        .collect::<Vec<_>>();
+    entries.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+    let mut calculation = streaming_root::StreamingRootCalculation::new(version);
+    for (key, entry) in entries {
+        calculation.push(&key, entry.as_ref());
+    }
+    calculation.finish()
+}
+
 #[cfg(test)]
 mod tests {
+    use super::{Trie, TrieEntryVersion};
+
     #[test]
     fn empty_trie() {
         let obtained = super::empty_trie_merkle_value();
         let expected = blake2_rfc::blake2b::blake2b(32, &[], &[0x0]);
         assert_eq!(obtained, expected.as_bytes());
     }
+
+    #[test]
+    fn ordered_root_matches_trie() {
+        let entries = (0..100u8)
+            .map(|n| vec![n; usize::from(n)])
+            .collect::<Vec<_>>();
+
+        for version in [TrieEntryVersion::V0, TrieEntryVersion::V1] {
+            let mut trie = Trie::new();
+            for (index, entry) in entries.iter().enumerate() {
+                trie.insert(
+                    crate::util::encode_scale_compact_usize(index).as_ref(),
+                    entry.clone(),
+                );
+            }
+
+            assert_eq!(
+                super::ordered_root(version, entries.iter()),
+                trie.root_merkle_value(version, None)
+            );
+        }
+
+        assert_eq!(
+            super::ordered_root(TrieEntryVersion::V0, core::iter::empty::<Vec<u8>>()),
+            super::empty_trie_merkle_value()
+        );
+    }
 }
@@ -28,7 +28,7 @@
 //!
 //! ```
 //! use std::collections::BTreeMap;
-//! use smoldot::trie::calculate_root;
+//! use smoldot::trie::{calculate_root, TrieEntryVersion};
 //!
 //! // In this example, the storage consists in a binary tree map.
 //! let mut storage = BTreeMap::<Vec<u8>, Vec<u8>>::new();
@@ -44,7 +44,8 @@
 //!             }
 //!             calculate_root::RootMerkleValueCalculation::StorageValue(value_request) => {
 //!                 let key = value_request.key().collect::<Vec<u8>>();
-//!                 calculation = value_request.inject(storage.get(&key));
+//!                 calculation = value_request
+//!                     .inject(storage.get(&key).map(|value| (value, TrieEntryVersion::V0)));
 //!             }
 //!         }
 //!     }
@@ -66,7 +67,7 @@
 
 use super::{
     nibble::{bytes_to_nibbles, Nibble},
-    node_value, trie_structure,
+    node_value, trie_structure, TrieEntryVersion,
 };
 
 use alloc::vec::Vec;
@@ -309,6 +310,7 @@ impl CalcInner {
                             ty: node_value::NodeTy::Root { key: iter::empty() },
                             children: (0..16).map(|_| None),
                             stored_value: None::<Vec<u8>>,
+                            version: TrieEntryVersion::V0,
                         });
 
                         return RootMerkleValueCalculation::Finished {
@@ -393,6 +395,7 @@ impl CalcInner {
                         }
                     }),
                     stored_value: None::<Vec<u8>>,
+                    version: TrieEntryVersion::V0,
                 });
 
                 current.user_data().merkle_value = Some(merkle_value);
@@ -456,9 +459,13 @@ impl StorageValue {
         })
     }
 
-    /// Indicates the storage value and advances the calculation.
-    pub fn inject(mut self, stored_value: Option<impl AsRef<[u8]>>) -> RootMerkleValueCalculation {
-        assert!(stored_value.is_some());
+    /// Indicates the storage value and the version of the trie entry, and advances the
+    /// calculation.
+    pub fn inject(
+        mut self,
+        stored_value: Option<(impl AsRef<[u8]>, TrieEntryVersion)>,
+    ) -> RootMerkleValueCalculation {
+        let (stored_value, version) = stored_value.unwrap();
 
         let trie_structure = self.calculation.cache.structure.as_mut().unwrap();
         let mut current: trie_structure::NodeAccess<_> = trie_structure
@@ -481,7 +488,8 @@ impl StorageValue {
                     .child_user_data(Nibble::try_from(child_idx).unwrap())
                     .map(|child| child.merkle_value.as_ref().unwrap())
             }),
-            stored_value,
+            stored_value: Some(stored_value),
+            version,
         });
 
         current.user_data().merkle_value = Some(merkle_value);
@@ -498,6 +506,13 @@ mod tests {
     use alloc::collections::BTreeMap;
 
     fn calculate_root(trie: BTreeMap<Vec<u8>, Vec<u8>>) -> [u8; 32] {
+        calculate_root_with_version(trie, super::TrieEntryVersion::V0)
+    }
+
+    fn calculate_root_with_version(
+        trie: BTreeMap<Vec<u8>, Vec<u8>>,
+        version: super::TrieEntryVersion,
+    ) -> [u8; 32] {
         let mut calculation = super::root_merkle_value(None);
 
         loop {
@@ -510,7 +525,7 @@ mod tests {
                 }
                 super::RootMerkleValueCalculation::StorageValue(value) => {
                     let key = value.key().collect::<Vec<u8>>();
-                    calculation = value.inject(trie.get(&key));
+                    calculation = value.inject(trie.get(&key).map(|value| (value, version)));
                 }
             }
         }
@@ -581,4 +596,23 @@ mod tests {
         let expected = blake2_rfc::blake2b::blake2b(32, &[], &ex);
         assert_eq!(calculate_root(trie), expected.as_bytes());
     }
+
+    #[test]
+    fn trie_root_hashed_value() {
+        let mut trie = BTreeMap::new();
+        trie.insert([0xaa].to_vec(), [0xbb; 40].to_vec());
+
+        let mut node_value = vec![
+            0x22, // leaf with hashed value 0x20 (2^5) with (+) key of 2 nibbles (0x02)
+            0xaa, // key data
+        ];
+        node_value.extend_from_slice(blake2_rfc::blake2b::blake2b(32, &[], &[0xbb; 40]).as_bytes());
+        let expected = blake2_rfc::blake2b::blake2b(32, &[], &node_value);
+
+        assert_eq!(
+            calculate_root_with_version(trie.clone(), super::TrieEntryVersion::V1),
+            expected.as_bytes()
+        );
+        assert_ne!(calculate_root(trie), expected.as_bytes());
+    }
 }
@@ -24,7 +24,7 @@
 //!
 //! ```
 //! use std::convert::TryFrom as _;
-//! use smoldot::trie::{Nibble, node_value};
+//! use smoldot::trie::{Nibble, TrieEntryVersion, node_value};
 //!
 //! let merkle_value = {
 //!     // The example node whose value we calculate has three children.
@@ -56,6 +56,7 @@
 //!         },
 //!         children: children.iter().map(|opt| opt.as_ref()),
 //!         stored_value: Some(b"hello world"),
+//!         version: TrieEntryVersion::V0,
 //!     })
 //! };
 //!
@@ -68,9 +69,10 @@
 //! );
 //! ```
 
-use super::nibble::Nibble;
+use super::{nibble::Nibble, proof_node_decode, TrieEntryVersion};
 use crate::util;
 
+use alloc::vec::Vec;
 use arrayvec::ArrayVec;
 use core::{convert::TryFrom as _, fmt};
 
@@ -87,6 +89,10 @@ pub struct Config<TChIter, TPKey, TVal> {
 
     /// Value of the node in the storage.
     pub stored_value: Option<TVal>,
+
+    /// Version of the trie entry of the storage value. Determines whether the storage value is
+    /// hashed. Irrelevant if [`Config::stored_value`] is `None`.
+    pub version: TrieEntryVersion,
 }
 
 /// Type of node whose node value is to be calculated.
@@ -127,10 +133,6 @@ where
     TPKey: ExactSizeIterator<Item = Nibble>,
     TVal: AsRef<[u8]>,
 {
-    assert_eq!(config.children.len(), 16);
-
-    let has_children = config.children.clone().any(|c| c.is_some());
-
     // This value will be used as the sink for all the components of the merkle value.
     let mut merkle_value_sink = if matches!(config.ty, NodeTy::Root { .. }) {
         HashOrInline::Hasher(blake2_rfc::blake2b::Blake2b::new(32))
@@ -138,55 +140,149 @@ where
         HashOrInline::Inline(ArrayVec::new())
     };
 
+    encode_node_value(config, |data| merkle_value_sink.update(data));
+    merkle_value_sink.finalize()
+}
+
+/// Calculates the node value of a node given the information about this node.
+///
+/// Contrary to [`calculate_merkle_root`], the output is never hashed. This is the value that is
+/// found in trie proofs.
+///
+/// # Panic
+///
+/// Panics if `config.children.len() != 16`.
+///
+pub fn calculate_node_value<'a, TChIter, TPKey, TVal>(
+    config: Config<TChIter, TPKey, TVal>,
+) -> Vec<u8>
+where
+    TChIter: ExactSizeIterator<Item = Option<&'a Output>> + Clone,
+    TPKey: ExactSizeIterator<Item = Nibble>,
+    TVal: AsRef<[u8]>,
+{
+    let mut node_value = Vec::new();
+    encode_node_value(config, |data| node_value.extend_from_slice(data));
+    node_value
+}
+
+/// Encodes a node value from its components.
+///
+/// Contrary to [`calculate_node_value`], the storage value is passed as it is found in the node
+/// value, and the children are passed as raw Merkle values. This is the inverse operation of
+/// [`proof_node_decode::decode`], and makes it possible to build a node whose storage value is
+/// hashed without knowing the storage value itself.
+pub fn encode_node<'a>(
+    partial_key: impl ExactSizeIterator<Item = Nibble>,
+    children: &[Option<&'a [u8]>; 16],
+    storage_value: proof_node_decode::StorageValue<'a>,
+) -> Vec<u8> {
+    let mut node_value = Vec::new();
+    encode_components(
+        partial_key,
+        children.iter().copied(),
+        storage_value,
+        |data| node_value.extend_from_slice(data),
+    );
+    node_value
+}
+
+/// Pushes to `merkle_value_sink` all the components of the node value of the given node.
+fn encode_node_value<'a, TChIter, TPKey, TVal>(
+    config: Config<TChIter, TPKey, TVal>,
+    merkle_value_sink: impl FnMut(&[u8]),
+) where
+    TChIter: ExactSizeIterator<Item = Option<&'a Output>> + Clone,
+    TPKey: ExactSizeIterator<Item = Nibble>,
+    TVal: AsRef<[u8]>,
+{
     // For node value calculation purposes, the root key is treated the same as the partial key.
-    let mut partial_key = match config.ty {
+    let partial_key = match config.ty {
         NodeTy::Root { key } => key,
         NodeTy::NonRoot { partial_key } => partial_key,
     };
 
+    // In version 1 of the trie, storage values that are at least 33 bytes long are replaced
+    // with their hash.
+    let stored_value_hash = match (&config.stored_value, config.version) {
+        (Some(value), TrieEntryVersion::V1) if value.as_ref().len() >= 33 => {
+            Some(blake2_rfc::blake2b::blake2b(32, &[], value.as_ref()))
+        }
+        _ => None,
+    };
+
+    let storage_value = match (&stored_value_hash, &config.stored_value) {
+        (Some(hash), _) => {
+            proof_node_decode::StorageValue::Hashed(<&[u8; 32]>::try_from(hash.as_bytes()).unwrap())
+        }
+        (None, Some(value)) => proof_node_decode::StorageValue::Unhashed(value.as_ref()),
+        (None, None) => proof_node_decode::StorageValue::None,
+    };
+
+    encode_components(
+        partial_key,
+        config.children,
+        storage_value,
+        merkle_value_sink,
+    );
+}
+
+/// Pushes to `merkle_value_sink` all the components of the node value of the given node.
+fn encode_components<TCh: AsRef<[u8]>>(
+    mut partial_key: impl ExactSizeIterator<Item = Nibble>,
+    children: impl ExactSizeIterator<Item = Option<TCh>> + Clone,
+    storage_value: proof_node_decode::StorageValue,
+    mut merkle_value_sink: impl FnMut(&[u8]),
+) {
+    assert_eq!(children.len(), 16);
+
+    let has_children = children.clone().any(|c| c.is_some());
+
     // Push the header of the node to `merkle_value_sink`.
     {
-        // The first two most significant bits of the header contain the type of node.
-        let two_msb: u8 = {
-            let has_stored_value = config.stored_value.is_some();
-            match (has_stored_value, has_children) {
-                (false, false) => {
-                    // This should only ever be reached if we compute the root node of an
-                    // empty trie.
-                    0b00
-                }
-                (true, false) => 0b01,
-                (false, true) => 0b10,
-                (true, true) => 0b11,
+        // The most significant bits of the header contain the type of node. The number of bits
+        // used for the type of node depends on the type of node.
+        let (header_prefix, header_prefix_bits): (u8, u32) = match (has_children, storage_value) {
+            (false, proof_node_decode::StorageValue::None) => {
+                // This should only ever be reached if we compute the root node of an
+                // empty trie.
+                (0b00, 2)
             }
+            (false, proof_node_decode::StorageValue::Unhashed(_)) => (0b01, 2),
+            (true, proof_node_decode::StorageValue::None) => (0b10, 2),
+            (true, proof_node_decode::StorageValue::Unhashed(_)) => (0b11, 2),
+            (false, proof_node_decode::StorageValue::Hashed(_)) => (0b001, 3),
+            (true, proof_node_decode::StorageValue::Hashed(_)) => (0b0001, 4),
         };
 
         // Another weird algorithm to encode the partial key length into the header.
+        let max_in_header = 0xff >> header_prefix_bits;
+        let first_byte = header_prefix << (8 - header_prefix_bits);
         let mut pk_len = partial_key.len();
-        if pk_len >= 63 {
-            pk_len -= 63;
-            merkle_value_sink.update(&[(two_msb << 6) + 63]);
-            while pk_len > 255 {
+        if pk_len >= usize::from(max_in_header) {
+            pk_len -= usize::from(max_in_header);
+            merkle_value_sink(&[first_byte | max_in_header]);
+            while pk_len >= 255 {
                 pk_len -= 255;
-                merkle_value_sink.update(&[255]);
+                merkle_value_sink(&[255]);
             }
-            merkle_value_sink.update(&[u8::try_from(pk_len).unwrap()]);
+            merkle_value_sink(&[u8::try_from(pk_len).unwrap()]);
         } else {
-            merkle_value_sink.update(&[(two_msb << 6) + u8::try_from(pk_len).unwrap()]);
+            merkle_value_sink(&[first_byte | u8::try_from(pk_len).unwrap()]);
         }
     }
 
     // Turn the partial key into bytes with a weird encoding and push it to `merkle_value_sink`.
     if partial_key.len() % 2 != 0 {
         // next().unwrap() can't panic, otherwise `len() % 2` would have returned 0.
-        merkle_value_sink.update(&[u8::from(partial_key.next().unwrap())]);
+        merkle_value_sink(&[u8::from(partial_key.next().unwrap())]);
     }
     {
         let mut previous = None;
         for nibble in partial_key {
             if let Some(prev) = previous.take() {
                 let val = (u8::from(prev) << 4) | u8::from(nibble);
-                merkle_value_sink.update(&[val]);
+                merkle_value_sink(&[val]);
             } else {
                 previous = Some(nibble);
             }
@@ -196,58 +292,47 @@ where
 
     // Compute the node subvalue and push it to `merkle_value_sink`.
 
-    // If there isn't any children, the node subvalue only consists in the storage value.
-    // We take a shortcut and end the calculation now.
-    if !has_children {
-        if let Some(stored_value) = config.stored_value {
-            // Doing something like `merkle_value_sink.update(stored_value.encode());` would be
-            // quite expensive because we would duplicate the storage value. Instead, we do the
-            // encoding manually by pushing the length then the value.
-            merkle_value_sink
-                .update(util::encode_scale_compact_usize(stored_value.as_ref().len()).as_ref());
-            merkle_value_sink.update(stored_value.as_ref());
-        }
-
-        return merkle_value_sink.finalize();
-    }
-
     // If there is any child, we a `u16` where each bit is `1` if there exists a child there.
-    merkle_value_sink.update({
-        let mut children_bitmap = 0u16;
-        for (child_index, child) in config.children.clone().enumerate() {
-            if child.is_some() {
-                children_bitmap |= 1 << u32::try_from(child_index).unwrap();
+    if has_children {
+        merkle_value_sink({
+            let mut children_bitmap = 0u16;
+            for (child_index, child) in children.clone().enumerate() {
+                if child.is_some() {
+                    children_bitmap |= 1 << u32::try_from(child_index).unwrap();
+                }
             }
-        }
-        &children_bitmap.to_le_bytes()[..]
-    });
+            &children_bitmap.to_le_bytes()[..]
+        });
+    }
 
     // Add our own stored value.
-    if let Some(stored_value) = config.stored_value {
-        // Doing something like `merkle_value_sink.update(stored_value.encode());` would be
-        // quite expensive because we would duplicate the storage value. Instead, we do the
-        // encoding manually by pushing the length then the value.
-        merkle_value_sink
-            .update(util::encode_scale_compact_usize(stored_value.as_ref().len()).as_ref());
-        merkle_value_sink.update(stored_value.as_ref());
+    match storage_value {
+        proof_node_decode::StorageValue::Hashed(hash) => merkle_value_sink(&hash[..]),
+        proof_node_decode::StorageValue::Unhashed(stored_value) => {
+            // Doing something like `merkle_value_sink(stored_value.encode());` would be
+            // quite expensive because we would duplicate the storage value. Instead, we do the
+            // encoding manually by pushing the length then the value.
+            merkle_value_sink(util::encode_scale_compact_usize(stored_value.len()).as_ref());
+            merkle_value_sink(stored_value);
+        }
+        proof_node_decode::StorageValue::None => {}
     }
 
     // Finally, push the merkle values of all the children.
-    for child in config.children.clone() {
+    for child in children {
         let child_merkle_value = match child {
             Some(v) => v,
             None => continue,
         };
 
-        // Doing something like `merkle_value_sink.update(child_merkle_value.encode());` would be
+        // Doing something like `merkle_value_sink(child_merkle_value.encode());` would be
         // expensive because we would duplicate the merkle value. Instead, we do the encoding
         // manually by pushing the length then the value.
-        merkle_value_sink
-            .update(util::encode_scale_compact_usize(child_merkle_value.as_ref().len()).as_ref());
-        merkle_value_sink.update(child_merkle_value.as_ref());
+        merkle_value_sink(
+            util::encode_scale_compact_usize(child_merkle_value.as_ref().len()).as_ref(),
+        );
+        merkle_value_sink(child_merkle_value.as_ref());
     }
-
-    merkle_value_sink.finalize()
 }
 
 /// Output of the calculation.
@@ -357,6 +442,7 @@ mod tests {
             ty: super::NodeTy::Root { key: iter::empty() },
             children: (0..16).map(|_| None),
             stored_value: None::<Vec<u8>>,
+            version: super::TrieEntryVersion::V0,
         });
 
         assert_eq!(
@@ -376,6 +462,7 @@ mod tests {
             },
             children: (0..16).map(|_| None),
             stored_value: None::<Vec<u8>>,
+            version: super::TrieEntryVersion::V0,
         });
 
         assert_eq!(obtained.as_ref(), &[0u8]);
@@ -411,6 +498,7 @@ mod tests {
             },
             children: children.iter().map(|opt| opt.as_ref()),
             stored_value: Some(b"hello world"),
+            version: super::TrieEntryVersion::V0,
         });
 
         assert_eq!(
@@ -422,6 +510,36 @@ mod tests {
         );
     }
 
+    #[test]
+    fn hashed_value() {
+        let value = [0xaa; 33];
+        let partial_key = [Nibble::try_from(1).unwrap(), Nibble::try_from(2).unwrap()];
+
+        let obtained = super::calculate_node_value(super::Config {
+            ty: super::NodeTy::NonRoot {
+                partial_key: partial_key.iter().cloned(),
+            },
+            children: (0..16).map(|_| None),
+            stored_value: Some(&value[..]),
+            version: super::TrieEntryVersion::V1,
+        });
+
+        let mut expected = vec![0b0010_0010, 0x12];
+        expected.extend_from_slice(blake2_rfc::blake2b::blake2b(32, &[], &value).as_bytes());
+        assert_eq!(obtained, expected);
+
+        // Values shorter than 33 bytes aren't hashed, even in version 1.
+        let obtained = super::calculate_node_value(super::Config {
+            ty: super::NodeTy::NonRoot {
+                partial_key: partial_key.iter().cloned(),
+            },
+            children: (0..16).map(|_| None),
+            stored_value: Some(&value[..32]),
+            version: super::TrieEntryVersion::V1,
+        });
+        assert_eq!(obtained[0], 0b0100_0010);
+    }
+
     #[test]
     #[should_panic]
     fn bad_children_len() {
@@ -431,6 +549,7 @@ mod tests {
             },
             children: iter::empty(),
             stored_value: None::<Vec<u8>>,
+            version: super::TrieEntryVersion::V0,
         });
     }
 }
@@ -0,0 +1,608 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Decoding of a trie proof into a structure that can be queried multiple times.
+//!
+//! The functions of [the `proof_verify` module](super::proof_verify) iterate over the entire
+//! proof and decode node values every time they are called. When a proof needs to be queried
+//! many times, for example when it is used in order to execute a runtime call, it is preferable
+//! to use [`decode_and_verify_proof`], which decodes the proof once and builds a
+//! [`DecodedProof`].
+//!
+//! A [`DecodedProof`] contains the nodes of all the tries whose root node is found in the proof.
+//! The queries thus require passing the hash of the root of the trie to query. This makes it
+//! possible to query child tries, whose nodes are typically found in the same proof as the main
+//! trie.
+//!
+//! > **Note**: Each query is performed in `O(d * log(n))` time, where `d` is the depth of the
+//! >           node in the trie and `n` the number of nodes in the proof.
+//!
+//! # Example
+//!
+//! ```
+//! use smoldot::trie::{proof_decode, proof_encode, TrieEntryVersion};
+//!
+//! let mut builder = proof_encode::ProofBuilder::new(
+//!     TrieEntryVersion::V0,
+//!     [(&b"foo"[..], &b"bar"[..]), (&b"foobaz"[..], &b"hello"[..])]
+//!         .iter()
+//!         .cloned(),
+//! );
+//!
+//! let trie_root_hash = builder.root_merkle_value();
+//! let proof = builder.build_prefix_proof(b"foo");
+//!
+//! let decoded = proof_decode::decode_and_verify_proof(proof_decode::Config {
+//!     proof: proof.iter().map(|v| &v[..]),
+//!     cache: None,
+//! })
+//! .unwrap();
+//!
+//! assert_eq!(
+//!     decoded.storage_value(&trie_root_hash, b"foobaz").unwrap(),
+//!     Some(&b"hello"[..])
+//! );
+//! assert_eq!(
+//!     decoded.next_key(&trie_root_hash, b"foo").unwrap(),
+//!     Some(b"foobaz".to_vec())
+//! );
+//! ```
+
+use super::{
+    nibble::{self, Nibble},
+    proof_node_decode,
+    proof_verify::{Children, Error, StorageValue},
+};
+
+use alloc::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+    vec::Vec,
+};
+use arrayvec::ArrayVec;
+use core::{convert::TryFrom as _, fmt};
+
+/// Configuration to pass to [`decode_and_verify_proof`].
+pub struct Config<'a, I> {
+    /// List of node values of nodes found in the trie. No specific order is required.
+    pub proof: I,
+
+    /// Optional cache of node values decoded during previous calls. Entries of the proof that
+    /// are found in the cache don't need to be decoded again, and the newly-decoded entries are
+    /// added to the cache.
+    ///
+    /// Passing a cache is useful when decoding many proofs that share nodes, such as multiple
+    /// proofs built against the same block.
+    pub cache: Option<&'a mut NodesCache>,
+}
+
+/// Cache of decoded node values, indexed by hash. See [`Config::cache`].
+///
+/// Since node values are identified by their hash, a cache can safely be shared between proofs
+/// of different tries. It is, however, preferable to use one cache per trie root hash, so that
+/// the cache can be discarded when the trie is no longer queried.
+///
+/// > **Note**: The cache never removes entries by itself. It is the responsibility of the user
+/// >           to discard it or to call [`NodesCache::clear`].
+#[derive(Default)]
+pub struct NodesCache {
+    nodes: BTreeMap<[u8; 32], Result<CachedNode, proof_node_decode::Error>>,
+}
+
+impl NodesCache {
+    /// Builds a new empty cache.
+    pub fn new() -> Self {
+        NodesCache {
+            nodes: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the number of node values in the cache.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Returns `true` if the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Removes all the entries of the cache.
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+    }
+}
+
+impl fmt::Debug for NodesCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("NodesCache")
+            .field(&self.nodes.len())
+            .finish()
+    }
+}
+
+/// Owned equivalent of [`proof_node_decode::Decoded`].
+#[derive(Clone)]
+struct CachedNode {
+    partial_key: Vec<Nibble>,
+    children: [Option<ArrayVec<u8, 32>>; 16],
+    storage_value: CachedStorageValue,
+}
+
+#[derive(Clone)]
+enum CachedStorageValue {
+    Unhashed(Vec<u8>),
+    Hashed([u8; 32]),
+    None,
+}
+
+impl CachedNode {
+    fn decode(node_value: &[u8]) -> Result<Self, proof_node_decode::Error> {
+        let decoded = proof_node_decode::decode(node_value)?;
+
+        let mut children: [Option<ArrayVec<u8, 32>>; 16] = Default::default();
+        for (child_index, child) in decoded.children.iter().enumerate() {
+            children[child_index] = child.map(|c| c.iter().copied().collect());
+        }
+
+        Ok(CachedNode {
+            partial_key: decoded.partial_key.collect(),
+            children,
+            storage_value: match decoded.storage_value {
+                proof_node_decode::StorageValue::Unhashed(value) => {
+                    CachedStorageValue::Unhashed(value.to_vec())
+                }
+                proof_node_decode::StorageValue::Hashed(hash) => CachedStorageValue::Hashed(*hash),
+                proof_node_decode::StorageValue::None => CachedStorageValue::None,
+            },
+        })
+    }
+
+    fn children_bitmap(&self) -> u16 {
+        let mut out = 0u16;
+        for (child_index, child) in self.children.iter().enumerate() {
+            if child.is_some() {
+                out |= 1 << child_index;
+            }
+        }
+        out
+    }
+}
+
+/// Decodes the given proof.
+///
+/// Entries of the proof that aren't referenced by any other entry are considered as the root
+/// nodes of tries. All the nodes reachable from these root nodes are decoded, and an error is
+/// returned if one of them has an invalid format.
+pub fn decode_and_verify_proof<'a>(
+    config: Config<'_, impl Iterator<Item = &'a [u8]>>,
+) -> Result<DecodedProof, Error> {
+    let entries = config.proof.collect::<Vec<_>>();
+
+    // Hashes of all the entries of the proof, indexed by hash.
+    let entries_by_hash = entries
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            let hash = blake2_rfc::blake2b::blake2b(32, &[], entry);
+            (<[u8; 32]>::try_from(hash.as_bytes()).unwrap(), index)
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    // Decode all the entries that aren't in the cache yet. Entries that fail to decode are
+    // kept, as they might be storage values.
+    let mut local_cache = NodesCache::new();
+    let cache = match config.cache {
+        Some(cache) => cache,
+        None => &mut local_cache,
+    };
+    for (hash, index) in &entries_by_hash {
+        cache
+            .nodes
+            .entry(*hash)
+            .or_insert_with(|| CachedNode::decode(entries[*index]));
+    }
+    let cache = &*cache;
+
+    // Hashes of all the entries that are referenced by another entry, either as a child or as a
+    // hashed storage value.
+    let referenced = entries_by_hash
+        .keys()
+        .filter_map(|hash| cache.nodes.get(hash).unwrap().as_ref().ok())
+        .flat_map(|node| {
+            let storage_value_hash = match &node.storage_value {
+                CachedStorageValue::Hashed(hash) => Some(*hash),
+                _ => None,
+            };
+
+            node.children
+                .iter()
+                .filter_map(|child| {
+                    child
+                        .as_ref()
+                        .and_then(|c| <[u8; 32]>::try_from(&c[..]).ok())
+                })
+                .chain(storage_value_hash)
+        })
+        .collect::<BTreeSet<_>>();
+
+    let mut nodes = BTreeMap::new();
+
+    for root_hash in entries_by_hash.keys() {
+        if referenced.contains(root_hash) {
+            continue;
+        }
+
+        // Entries that aren't referenced and that fail to decode are unrelated to the tries
+        // found in the proof, and are ignored.
+        let root_node = match cache.nodes.get(root_hash).unwrap() {
+            Ok(n) => n,
+            Err(_) => continue,
+        };
+
+        // Nodes yet to be visited, with the key of their parent followed with their child index.
+        let mut to_visit = Vec::new();
+        to_visit.push((Vec::new(), Cow::Borrowed(root_node)));
+
+        while let Some((mut key, node)) = to_visit.pop() {
+            key.extend(node.partial_key.iter().copied());
+
+            for (nibble, child) in nibble::all_nibbles().zip(node.children.iter()) {
+                let child = match child {
+                    Some(c) => c,
+                    None => continue,
+                };
+
+                let child_node = if child.len() < 32 {
+                    // The node value of the child is directly inlined.
+                    Cow::Owned(CachedNode::decode(child).map_err(Error::InvalidNodeValue)?)
+                } else {
+                    let hash = <[u8; 32]>::try_from(&child[..]).unwrap();
+                    if !entries_by_hash.contains_key(&hash) {
+                        // Child is missing from the proof.
+                        continue;
+                    }
+                    match cache.nodes.get(&hash).unwrap() {
+                        Ok(n) => Cow::Borrowed(n),
+                        Err(err) => return Err(Error::InvalidNodeValue(err.clone())),
+                    }
+                };
+
+                let mut child_key = Vec::with_capacity(key.len() + 1);
+                child_key.extend_from_slice(&key);
+                child_key.push(nibble);
+                to_visit.push((child_key, child_node));
+            }
+
+            let storage_value = match &node.storage_value {
+                CachedStorageValue::Unhashed(value) => NodeStorageValue::Known(value.clone()),
+                CachedStorageValue::Hashed(hash) => match entries_by_hash.get(hash) {
+                    Some(index) => NodeStorageValue::Known(entries[*index].to_vec()),
+                    None => NodeStorageValue::HashKnownValueMissing(*hash),
+                },
+                CachedStorageValue::None => NodeStorageValue::None,
+            };
+
+            nodes.insert(
+                (*root_hash, key),
+                Node {
+                    storage_value,
+                    children_bitmap: node.children_bitmap(),
+                },
+            );
+        }
+    }
+
+    Ok(DecodedProof { nodes })
+}
+
+/// Decoded proof. See [the module-level documentation](..).
+pub struct DecodedProof {
+    /// All the nodes found in the proof, indexed by the hash of the root of the trie they belong
+    /// to and by their full key.
+    ///
+    /// Since the key of a node is always a prefix of the keys of all of its descendants, the
+    /// first node after a certain key in this map that starts with this key is the node closest
+    /// to the root amongst the nodes that start with this key.
+    nodes: BTreeMap<([u8; 32], Vec<Nibble>), Node>,
+}
+
+struct Node {
+    storage_value: NodeStorageValue,
+    children_bitmap: u16,
+}
+
+enum NodeStorageValue {
+    Known(Vec<u8>),
+    HashKnownValueMissing([u8; 32]),
+    None,
+}
+
+impl DecodedProof {
+    /// Returns the storage value associated to the given key in the given trie.
+    ///
+    /// Returns `Ok(None)` if the proof shows that the key doesn't have any storage value, and an
+    /// error if the proof doesn't contain enough information.
+    pub fn storage_value(
+        &self,
+        trie_root_hash: &[u8; 32],
+        key: &[u8],
+    ) -> Result<Option<&[u8]>, Error> {
+        let key = nibble::bytes_to_nibbles(key.iter().copied()).collect::<Vec<_>>();
+        match self.node_info(trie_root_hash, &key)?.0 {
+            StorageValue::Known(value) => Ok(Some(value)),
+            StorageValue::HashKnownValueMissing(_) => Err(Error::MissingStorageValue),
+            StorageValue::None => Ok(None),
+        }
+    }
+
+    /// Returns the smallest key of the given trie that is strictly superior to the given key.
+    ///
+    /// Returns an error if the proof doesn't contain enough information.
+    pub fn next_key(
+        &self,
+        trie_root_hash: &[u8; 32],
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let key = nibble::bytes_to_nibbles(key.iter().copied()).collect::<Vec<_>>();
+        self.next_key_inner(trie_root_hash, &key, &mut Vec::new())
+    }
+
+    /// Returns the list of keys of the given trie that start with the given prefix, ordered
+    /// lexicographically.
+    ///
+    /// Returns an error if the proof doesn't contain enough information.
+    pub fn prefix_keys_ordered(
+        &self,
+        trie_root_hash: &[u8; 32],
+        prefix: &[u8],
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        let mut to_find = Vec::new();
+        to_find.push(nibble::bytes_to_nibbles(prefix.iter().copied()).collect::<Vec<_>>());
+        let mut output = Vec::new();
+
+        // Nodes are visited in depth-first order, parents before their children and children in
+        // increasing order, which guarantees that the keys are found in lexicographic order.
+        while let Some(key) = to_find.pop() {
+            let (storage_value, children) = self.node_info(trie_root_hash, &key)?;
+
+            if storage_value.is_some() {
+                // Trie nodes with a value are always aligned to "bytes-keys".
+                debug_assert_eq!(key.len() % 2, 0);
+                output.push(nibble::nibbles_to_bytes_extend(key.iter().copied()).collect());
+            }
+
+            let first_child_pos = to_find.len();
+            for nibble in children.next_nibbles() {
+                let mut child = key.clone();
+                child.push(nibble);
+                to_find.push(child);
+            }
+            to_find[first_child_pos..].reverse();
+        }
+
+        Ok(output)
+    }
+
+    /// Returns the storage value and children of the node whose key is `key`, in the same way as
+    /// [`super::proof_verify::trie_node_info`].
+    fn node_info(
+        &self,
+        trie_root_hash: &[u8; 32],
+        key: &[Nibble],
+    ) -> Result<(StorageValue, Children), Error> {
+        // The root node is always the first node of its trie in the map.
+        let (mut node_key, mut node) = self
+            .nodes
+            .range((*trie_root_hash, Vec::new())..)
+            .next()
+            .filter(|((hash, _), _)| hash == trie_root_hash)
+            .map(|((_, key), node)| (key, node))
+            .ok_or(Error::TrieRootNotFound)?;
+
+        loop {
+            if !key.starts_with(node_key) {
+                // The requested key diverges from the key of the node, or is a strict prefix of
+                // it.
+                return Ok(if node_key.starts_with(key) {
+                    (StorageValue::None, Children::One(node_key[key.len()]))
+                } else {
+                    (StorageValue::None, Children::None)
+                });
+            }
+
+            if key.len() == node_key.len() {
+                let storage_value = match &node.storage_value {
+                    NodeStorageValue::Known(value) => StorageValue::Known(value),
+                    NodeStorageValue::HashKnownValueMissing(hash) => {
+                        StorageValue::HashKnownValueMissing(hash)
+                    }
+                    NodeStorageValue::None => StorageValue::None,
+                };
+
+                return Ok((
+                    storage_value,
+                    Children::Multiple {
+                        children_bitmap: node.children_bitmap,
+                    },
+                ));
+            }
+
+            let child_index = key[node_key.len()];
+            if node.children_bitmap & (1 << u8::from(child_index)) == 0 {
+                return Ok((StorageValue::None, Children::None));
+            }
+
+            let child_prefix = &key[..node_key.len() + 1];
+            let (child_key, child) = self
+                .nodes
+                .range((*trie_root_hash, child_prefix.to_vec())..)
+                .next()
+                .filter(|((hash, k), _)| hash == trie_root_hash && k.starts_with(child_prefix))
+                .map(|((_, key), node)| (key, node))
+                .ok_or(Error::MissingProofEntry {
+                    closest_ancestor_nibbles: node_key.len(),
+                })?;
+
+            node_key = child_key;
+            node = child;
+        }
+    }
+
+    /// Returns the smallest key of the trie that is strictly superior to `requested_key` and
+    /// that starts with `prefix`.
+    ///
+    /// `prefix` is used as a buffer while iterating down the trie, and is always restored to its
+    /// original value before this function returns successfully.
+    fn next_key_inner(
+        &self,
+        trie_root_hash: &[u8; 32],
+        requested_key: &[Nibble],
+        prefix: &mut Vec<Nibble>,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let (storage_value, children) = self.node_info(trie_root_hash, prefix)?;
+
+        // If `prefix` isn't a prefix of `requested_key`, then all the keys that start with
+        // `prefix` are strictly superior to `requested_key`, as this function never iterates
+        // towards keys that are inferior.
+        let is_prefix_of_requested = requested_key.starts_with(&prefix[..]);
+
+        if !is_prefix_of_requested && storage_value.is_some() {
+            // Trie nodes with a value are always aligned to "bytes-keys".
+            debug_assert_eq!(prefix.len() % 2, 0);
+            return Ok(Some(
+                nibble::nibbles_to_bytes_extend(prefix.iter().copied()).collect(),
+            ));
+        }
+
+        for nibble in children.next_nibbles() {
+            if is_prefix_of_requested
+                && prefix.len() < requested_key.len()
+                && nibble < requested_key[prefix.len()]
+            {
+                continue;
+            }
+
+            prefix.push(nibble);
+            let outcome = self.next_key_inner(trie_root_hash, requested_key, prefix)?;
+            prefix.pop();
+
+            if outcome.is_some() {
+                return Ok(outcome);
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl fmt::Debug for DecodedProof {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("DecodedProof").finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_and_verify_proof, Config};
+    use crate::trie::{proof_encode::ProofBuilder, TrieEntryVersion};
+
+    #[test]
+    fn matches_storage() {
+        let entries = [
+            (&b"foo"[..], &b"bar"[..]),
+            (&b"foobaz"[..], &[0xaa; 64][..]),
+            (&b"fooqux"[..], &b"hello"[..]),
+            (&b"abcdef"[..], &[0x55; 40][..]),
+            (&b"a"[..], &b""[..]),
+        ];
+
+        let mut builder = ProofBuilder::new(TrieEntryVersion::V0, entries.iter().cloned());
+        let trie_root_hash = builder.root_merkle_value();
+        let proof = builder.build_prefix_proof(b"");
+
+        let decoded = decode_and_verify_proof(Config {
+            proof: proof.iter().map(|v| &v[..]),
+            cache: None,
+        })
+        .unwrap();
+
+        for (key, value) in entries.iter() {
+            assert_eq!(
+                decoded.storage_value(&trie_root_hash, key).unwrap(),
+                Some(*value)
+            );
+        }
+        assert_eq!(decoded.storage_value(&trie_root_hash, b"fo").unwrap(), None);
+
+        assert_eq!(
+            decoded
+                .prefix_keys_ordered(&trie_root_hash, b"foo")
+                .unwrap(),
+            vec![b"foo".to_vec(), b"foobaz".to_vec(), b"fooqux".to_vec()]
+        );
+        assert_eq!(
+            decoded.prefix_keys_ordered(&trie_root_hash, b"").unwrap(),
+            vec![
+                b"a".to_vec(),
+                b"abcdef".to_vec(),
+                b"foo".to_vec(),
+                b"foobaz".to_vec(),
+                b"fooqux".to_vec()
+            ]
+        );
+
+        assert_eq!(
+            decoded.next_key(&trie_root_hash, b"").unwrap(),
+            Some(b"a".to_vec())
+        );
+        assert_eq!(
+            decoded.next_key(&trie_root_hash, b"abcdef").unwrap(),
+            Some(b"foo".to_vec())
+        );
+        assert_eq!(
+            decoded.next_key(&trie_root_hash, b"foob").unwrap(),
+            Some(b"foobaz".to_vec())
+        );
+        assert_eq!(decoded.next_key(&trie_root_hash, b"fooqux").unwrap(), None);
+    }
+
+    #[test]
+    fn incomplete_proof() {
+        let entries = [
+            (&b"foo"[..], &[0x11; 64][..]),
+            (&b"bar"[..], &[0x22; 64][..]),
+        ];
+
+        let mut builder = ProofBuilder::new(TrieEntryVersion::V0, entries.iter().cloned());
+        let trie_root_hash = builder.root_merkle_value();
+        let proof = builder.build_proof([&b"foo"[..]].iter());
+
+        let decoded = decode_and_verify_proof(Config {
+            proof: proof.iter().map(|v| &v[..]),
+            cache: None,
+        })
+        .unwrap();
+
+        assert_eq!(
+            decoded.storage_value(&trie_root_hash, b"foo").unwrap(),
+            Some(&[0x11; 64][..])
+        );
+        assert!(decoded.storage_value(&trie_root_hash, b"bar").is_err());
+        assert!(decoded.storage_value(&[0; 32], b"foo").is_err());
+    }
+}
@@ -0,0 +1,781 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Generation of trie proofs.
+//!
+//! This module is the counterpart of [the `proof_verify` module](super::proof_verify). See the
+//! documentation there for an explanation of what a trie proof is.
+//!
+//! The [`ProofBuilder`] is built from the list of all the entries of the storage. Building it
+//! requires calculating the Merkle values of all the nodes of the trie, and is therefore an
+//! expensive operation. Once built, however, generating a proof is relatively cheap, and the
+//! same [`ProofBuilder`] should be used to generate multiple proofs.
+//!
+//! # Example
+//!
+//! ```
+//! use smoldot::trie::{proof_encode, proof_verify, TrieEntryVersion};
+//!
+//! let mut builder = proof_encode::ProofBuilder::new(
+//!     TrieEntryVersion::V0,
+//!     [
+//!         (&b"foo"[..], &b"bar"[..]),
+//!         (&b"foobaz"[..], &b"a value long enough for its node to be hashed"[..]),
+//!     ]
+//!         .iter()
+//!         .cloned(),
+//! );
+//!
+//! let trie_root_hash = builder.root_merkle_value();
+//! let proof = builder.build_proof([&b"foobaz"[..]].iter());
+//!
+//! let value = proof_verify::verify_proof(proof_verify::VerifyProofConfig {
+//!     requested_key: b"foobaz",
+//!     trie_root_hash: &trie_root_hash,
+//!     proof: proof.iter().map(|v| &v[..]),
+//! })
+//! .unwrap();
+//! assert_eq!(value, Some(&b"a value long enough for its node to be hashed"[..]));
+//! ```
+
+use super::{
+    bytes_to_nibbles, nibble::all_nibbles, node_value, trie_structure, Nibble, TrieEntryVersion,
+};
+
+use alloc::{collections::BTreeSet, vec::Vec};
+
+/// Prototype for the generation of proofs. See [the module-level documentation](..).
+pub struct ProofBuilder {
+    /// Structure of the trie. Each node contains its storage value, if any, and its Merkle value.
+    trie: trie_structure::TrieStructure<Node>,
+
+    /// Version of the trie entries. Determines whether large storage values are hashed within
+    /// the node values.
+    version: TrieEntryVersion,
+}
+
+struct Node {
+    /// Storage value of the node, if any.
+    storage_value: Option<Vec<u8>>,
+    /// Merkle value of the node. Always `Some` once [`ProofBuilder::new`] or
+    /// [`ProofBuilder::apply_changes`] has returned.
+    merkle_value: Option<node_value::Output>,
+}
+
+impl ProofBuilder {
+    /// Builds a new [`ProofBuilder`] from the list of all the entries of the storage.
+    ///
+    /// The trie entry version must be the one indicated by the runtime of the block whose
+    /// storage is passed, otherwise the root and the proofs don't match the block.
+    pub fn new(
+        version: TrieEntryVersion,
+        entries: impl Iterator<Item = (impl AsRef<[u8]>, impl Into<Vec<u8>>)>,
+    ) -> ProofBuilder {
+        let mut builder = ProofBuilder {
+            trie: trie_structure::TrieStructure::new(),
+            version,
+        };
+
+        for (key, value) in entries {
+            builder.insert_storage_value(key.as_ref(), value.into());
+        }
+
+        builder.calculate_missing_merkle_values();
+        builder
+    }
+
+    /// Modifies the entries of the storage. Entries whose value is `None` are removed.
+    ///
+    /// Only the Merkle values of the nodes affected by the changes are calculated again, which
+    /// makes this operation considerably cheaper than building a new [`ProofBuilder`] when the
+    /// number of changes is small compared to the size of the storage.
+    pub fn apply_changes(
+        &mut self,
+        changes: impl Iterator<Item = (impl AsRef<[u8]>, Option<impl Into<Vec<u8>>>)>,
+    ) {
+        for (key, value) in changes {
+            match value {
+                Some(value) => self.insert_storage_value(key.as_ref(), value.into()),
+                None => self.remove_storage_value(key.as_ref()),
+            }
+        }
+
+        self.calculate_missing_merkle_values();
+    }
+
+    /// Modifies the trie entry version passed to [`ProofBuilder::new`].
+    ///
+    /// This must be called when a runtime upgrade modifies the state version. The Merkle values
+    /// of all the nodes are calculated again if the version is different from the current one.
+    pub fn set_version(&mut self, version: TrieEntryVersion) {
+        if self.version == version {
+            return;
+        }
+
+        self.version = version;
+        if let Some(root_index) = self.trie.root_node().map(|n| n.node_index()) {
+            self.clear_descendants_merkle_values(root_index);
+        }
+        self.calculate_missing_merkle_values();
+    }
+
+    /// Returns the Merkle value of the root node of the trie, in other words the hash of the
+    /// trie.
+    pub fn root_merkle_value(&mut self) -> [u8; 32] {
+        match self.trie.root_node() {
+            Some(mut root) => root.user_data().merkle_value.clone().unwrap().into(),
+            None => super::empty_trie_merkle_value(),
+        }
+    }
+
+    /// Builds a proof containing all the node values necessary to find the storage value of the
+    /// given keys, or to prove their absence.
+    ///
+    /// The node values of all the keys are merged into a single list, and duplicate node values
+    /// are removed.
+    pub fn build_proof(&mut self, keys: impl Iterator<Item = impl AsRef<[u8]>>) -> Vec<Vec<u8>> {
+        let mut proof = BTreeSet::new();
+
+        let root_index = match self.trie.root_node() {
+            Some(root) => root.node_index(),
+            None => {
+                // The proof of absence of a key in an empty trie consists in the node value of
+                // the root node of an empty trie.
+                return Vec::from([Vec::from([0u8])]);
+            }
+        };
+
+        for key in keys {
+            let key = bytes_to_nibbles(key.as_ref().iter().copied()).collect::<Vec<_>>();
+            let mut key = &key[..];
+            let mut node_index = root_index;
+
+            loop {
+                let node_value = self.node_value(node_index);
+                // Node values that are smaller than 32 bytes are directly included within the
+                // node value of their parent, and don't need to be part of the proof.
+                if node_index == root_index || node_value.len() >= 32 {
+                    proof.insert(node_value);
+                }
+
+                let mut node = self.trie.node_by_index(node_index).unwrap();
+                let partial_key = node.partial_key().collect::<Vec<_>>();
+                if !key.starts_with(&partial_key) {
+                    break;
+                }
+                key = &key[partial_key.len()..];
+
+                let child_index = match key.first() {
+                    Some(nibble) => *nibble,
+                    None => {
+                        // The storage value of the requested key, if hashed within the node
+                        // value, must be provided alongside the node value.
+                        if let Some(value) = self.hashed_storage_value(node_index) {
+                            proof.insert(value);
+                        }
+                        break;
+                    }
+                };
+                key = &key[1..];
+
+                match node.child(child_index) {
+                    Some(child) => node_index = child.node_index(),
+                    None => break,
+                }
+            }
+        }
+
+        proof.into_iter().collect()
+    }
+
+    /// Builds a proof containing all the node values necessary to find the list of keys that
+    /// start with the given prefix, and their storage values.
+    ///
+    /// Such a proof can for example be verified with [the `prefix_proof`
+    /// module](super::prefix_proof).
+    pub fn build_prefix_proof(&mut self, prefix: &[u8]) -> Vec<Vec<u8>> {
+        let mut proof = BTreeSet::new();
+
+        let root_index = match self.trie.root_node() {
+            Some(root) => root.node_index(),
+            None => return Vec::from([Vec::from([0u8])]),
+        };
+
+        let prefix = bytes_to_nibbles(prefix.iter().copied()).collect::<Vec<_>>();
+        let mut prefix = &prefix[..];
+        let mut node_index = root_index;
+
+        loop {
+            let node_value = self.node_value(node_index);
+            if node_index == root_index || node_value.len() >= 32 {
+                proof.insert(node_value);
+            }
+
+            let mut node = self.trie.node_by_index(node_index).unwrap();
+            let partial_key = node.partial_key().collect::<Vec<_>>();
+
+            // If the rest of the prefix is within the partial key of this node, then this node
+            // and all of its descendants are the ones whose key starts with the prefix.
+            if partial_key.starts_with(prefix) {
+                if let Some(value) = self.hashed_storage_value(node_index) {
+                    proof.insert(value);
+                }
+                self.insert_descendants(node_index, &mut proof);
+                break;
+            }
+
+            if !prefix.starts_with(&partial_key) {
+                break;
+            }
+            prefix = &prefix[partial_key.len()..];
+
+            let child_index = prefix[0];
+            prefix = &prefix[1..];
+
+            match node.child(child_index) {
+                Some(child) => node_index = child.node_index(),
+                None => break,
+            }
+        }
+
+        proof.into_iter().collect()
+    }
+
+    /// Inserts in `proof` the node values of all the descendants of the given node.
+    fn insert_descendants(
+        &mut self,
+        node_index: trie_structure::NodeIndex,
+        proof: &mut BTreeSet<Vec<u8>>,
+    ) {
+        let children = {
+            let mut node = self.trie.node_by_index(node_index).unwrap();
+            all_nibbles()
+                .filter_map(|nibble| node.child(nibble).map(|c| c.node_index()))
+                .collect::<Vec<_>>()
+        };
+
+        for child in children {
+            // Node values that are smaller than 32 bytes are directly included within the node
+            // value of their parent.
+            let node_value = self.node_value(child);
+            if node_value.len() >= 32 {
+                proof.insert(node_value);
+            }
+            if let Some(value) = self.hashed_storage_value(child) {
+                proof.insert(value);
+            }
+
+            self.insert_descendants(child, proof);
+        }
+    }
+
+    /// Sets the storage value of the given key, and clears the Merkle values of the nodes that
+    /// are affected by this change.
+    fn insert_storage_value(&mut self, key: &[u8], value: Vec<u8>) {
+        let (node_index, is_new_node) = match self.trie.node(bytes_to_nibbles(key.iter().copied()))
+        {
+            trie_structure::Entry::Vacant(entry) => {
+                let node = entry.insert_storage_value().insert(
+                    Node {
+                        storage_value: None,
+                        merkle_value: None,
+                    },
+                    Node {
+                        storage_value: None,
+                        merkle_value: None,
+                    },
+                );
+                (node.node_index(), true)
+            }
+            trie_structure::Entry::Occupied(trie_structure::NodeAccess::Branch(entry)) => {
+                (entry.insert_storage_value().node_index(), false)
+            }
+            trie_structure::Entry::Occupied(trie_structure::NodeAccess::Storage(entry)) => {
+                (entry.node_index(), false)
+            }
+        };
+
+        self.trie
+            .node_by_index(node_index)
+            .unwrap()
+            .user_data()
+            .storage_value = Some(value);
+
+        // Inserting a new node might have modified the partial key of the children of the new
+        // node and, if a branch node has been inserted as well, of the sibling of the new node.
+        if is_new_node {
+            self.clear_children_merkle_values(node_index);
+            let parent_index = self
+                .trie
+                .node_by_index(node_index)
+                .unwrap()
+                .into_parent()
+                .map(|p| p.node_index());
+            if let Some(parent_index) = parent_index {
+                self.clear_children_merkle_values(parent_index);
+            }
+        }
+
+        self.clear_merkle_values(node_index);
+    }
+
+    /// Removes the storage value of the given key, if any, and clears the Merkle values of the
+    /// nodes that are affected by this change.
+    fn remove_storage_value(&mut self, key: &[u8]) {
+        let entry = match self
+            .trie
+            .existing_node(bytes_to_nibbles(key.iter().copied()))
+        {
+            Some(trie_structure::NodeAccess::Storage(entry)) => entry,
+            Some(trie_structure::NodeAccess::Branch(_)) | None => return,
+        };
+
+        // The node whose Merkle value must be calculated again, alongside with all its
+        // ancestors. Removing a node modifies the partial key of its child, if any, or of the
+        // sibling of the node if the parent branch node is removed as well.
+        let modified_node = match entry.remove() {
+            trie_structure::Remove::StorageToBranch(mut branch) => {
+                branch.user_data().storage_value = None;
+                branch.node_index()
+            }
+            trie_structure::Remove::SingleRemoveChild { child, .. } => child.node_index(),
+            trie_structure::Remove::SingleRemoveNoChild { parent, .. } => parent.node_index(),
+            trie_structure::Remove::BranchAlsoRemoved { sibling, .. } => sibling.node_index(),
+            trie_structure::Remove::TrieNowEmpty { .. } => return,
+        };
+
+        self.clear_merkle_values(modified_node);
+    }
+
+    /// Clears the Merkle value of the given node and of all its ancestors.
+    fn clear_merkle_values(&mut self, node_index: trie_structure::NodeIndex) {
+        let mut node = self.trie.node_by_index(node_index).unwrap();
+        loop {
+            node.user_data().merkle_value = None;
+            node = match node.into_parent() {
+                Some(parent) => parent,
+                None => break,
+            };
+        }
+    }
+
+    /// Clears the Merkle value of the direct children of the given node.
+    fn clear_children_merkle_values(&mut self, node_index: trie_structure::NodeIndex) {
+        let mut node = self.trie.node_by_index(node_index).unwrap();
+        for nibble in all_nibbles() {
+            if let Some(mut child) = node.child(nibble) {
+                child.user_data().merkle_value = None;
+            }
+        }
+    }
+
+    /// Clears the Merkle value of the given node and of all its descendants.
+    fn clear_descendants_merkle_values(&mut self, node_index: trie_structure::NodeIndex) {
+        let children = {
+            let mut node = self.trie.node_by_index(node_index).unwrap();
+            node.user_data().merkle_value = None;
+            all_nibbles()
+                .filter_map(|nibble| node.child(nibble).map(|c| c.node_index()))
+                .collect::<Vec<_>>()
+        };
+
+        for child in children {
+            self.clear_descendants_merkle_values(child);
+        }
+    }
+
+    /// Returns the storage value of the given node if it is hashed within the node value, in
+    /// which case it must be included in proofs separately from the node value.
+    fn hashed_storage_value(&mut self, node_index: trie_structure::NodeIndex) -> Option<Vec<u8>> {
+        if self.version == TrieEntryVersion::V0 {
+            return None;
+        }
+
+        // Must match the threshold used by `node_value::calculate_node_value`.
+        let mut node = self.trie.node_by_index(node_index).unwrap();
+        node.user_data()
+            .storage_value
+            .as_ref()
+            .filter(|value| value.len() >= 33)
+            .cloned()
+    }
+
+    /// Calculates and stores the Merkle value of all the nodes whose Merkle value is missing.
+    fn calculate_missing_merkle_values(&mut self) {
+        if let Some(root_index) = self.trie.root_node().map(|n| n.node_index()) {
+            self.calculate_merkle_values(root_index);
+        }
+    }
+
+    /// Calculates and stores the Merkle value of the given node and of all its descendants whose
+    /// Merkle value is missing.
+    ///
+    /// The Merkle value of a node is missing if the Merkle value of any of its descendants is
+    /// missing.
+    fn calculate_merkle_values(&mut self, node_index: trie_structure::NodeIndex) {
+        let children = {
+            let mut node = self.trie.node_by_index(node_index).unwrap();
+            if node.user_data().merkle_value.is_some() {
+                return;
+            }
+            all_nibbles()
+                .filter_map(|nibble| node.child(nibble).map(|c| c.node_index()))
+                .collect::<Vec<_>>()
+        };
+
+        for child in children {
+            self.calculate_merkle_values(child);
+        }
+
+        let node_value = self.node_value(node_index);
+        let mut node = self.trie.node_by_index(node_index).unwrap();
+        let merkle_value = if node.is_root_node() || node_value.len() >= 32 {
+            node_value::Output::from_bytes(
+                blake2_rfc::blake2b::blake2b(32, &[], &node_value).as_bytes(),
+            )
+        } else {
+            node_value::Output::from_bytes(&node_value)
+        };
+        node.user_data().merkle_value = Some(merkle_value);
+    }
+
+    /// Calculates the node value of the given node.
+    ///
+    /// The Merkle values of the children of the node must have been calculated.
+    fn node_value(&mut self, node_index: trie_structure::NodeIndex) -> Vec<u8> {
+        let mut node = self.trie.node_by_index(node_index).unwrap();
+
+        let children = all_nibbles()
+            .map(|nibble| {
+                node.child_user_data(nibble)
+                    .map(|child| child.merkle_value.clone().unwrap())
+            })
+            .collect::<Vec<_>>();
+        let partial_key = node.partial_key().collect::<Vec<Nibble>>();
+        let is_root = node.is_root_node();
+
+        node_value::calculate_node_value(node_value::Config {
+            ty: if is_root {
+                node_value::NodeTy::Root {
+                    key: partial_key.iter().copied(),
+                }
+            } else {
+                node_value::NodeTy::NonRoot {
+                    partial_key: partial_key.iter().copied(),
+                }
+            },
+            children: children.iter().map(|c| c.as_ref()),
+            stored_value: node.user_data().storage_value.as_ref(),
+            version: self.version,
+        })
+    }
+}
+
+/// Merges multiple proofs into one.
+///
+/// The entries of all the proofs are put together and duplicate entries are removed. The
+/// resulting proof can be used in order to verify everything that any of the input proofs could
+/// verify, as [the `proof_verify` module](super::proof_verify) ignores unused entries.
+///
+/// > **Note**: This function doesn't verify the proofs. Merging proofs built against different
+/// >           trie roots is possible, but leads to a proof that contains entries unnecessary
+/// >           to the verification against any given root.
+pub fn merge_proofs<'a>(
+    proofs: impl IntoIterator<Item = impl IntoIterator<Item = &'a [u8]>>,
+) -> Vec<Vec<u8>> {
+    proofs
+        .into_iter()
+        .flat_map(|proof| proof.into_iter())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(|entry| entry.to_vec())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProofBuilder;
+    use crate::trie::{prefix_proof, proof_verify, Trie, TrieEntryVersion};
+
+    #[test]
+    fn matches_trie_root_and_verifies() {
+        let entries = [
+            (&b"foo"[..], &b"bar"[..]),
+            (&b"foobaz"[..], &[0xaa; 64][..]),
+            (&b"fooqux"[..], &b"hello"[..]),
+            (&b"abcdef"[..], &[0x55; 40][..]),
+            (&b"a"[..], &b""[..]),
+        ];
+
+        let mut trie = Trie::new();
+        for (key, value) in entries.iter() {
+            trie.insert(key, *value);
+        }
+
+        let mut builder = ProofBuilder::new(TrieEntryVersion::V0, entries.iter().cloned());
+        let trie_root_hash = builder.root_merkle_value();
+        assert_eq!(
+            trie_root_hash,
+            trie.root_merkle_value(TrieEntryVersion::V0, None)
+        );
+
+        let proof = builder.build_proof(
+            entries
+                .iter()
+                .map(|(k, _)| *k)
+                .chain([&b"food"[..], &b"b"[..]].iter().copied()),
+        );
+
+        for (key, value) in entries.iter() {
+            let obtained = proof_verify::verify_proof(proof_verify::VerifyProofConfig {
+                requested_key: key,
+                trie_root_hash: &trie_root_hash,
+                proof: proof.iter().map(|v| &v[..]),
+            })
+            .unwrap();
+            assert_eq!(obtained, Some(*value));
+        }
+
+        for absent_key in [&b"food"[..], &b"b"[..]].iter() {
+            let obtained = proof_verify::verify_proof(proof_verify::VerifyProofConfig {
+                requested_key: absent_key,
+                trie_root_hash: &trie_root_hash,
+                proof: proof.iter().map(|v| &v[..]),
+            })
+            .unwrap();
+            assert_eq!(obtained, None);
+        }
+    }
+
+    #[test]
+    fn version_1_matches_trie_root_and_verifies() {
+        let entries = [
+            (&b"foo"[..], &b"bar"[..]),
+            (&b"foobaz"[..], &[0xaa; 64][..]),
+            (&b"fooqux"[..], &[0xbb; 33][..]),
+            (&b"abcdef"[..], &[0x55; 32][..]),
+        ];
+
+        let mut trie = Trie::new();
+        for (key, value) in entries.iter() {
+            trie.insert(key, *value);
+        }
+
+        let mut builder = ProofBuilder::new(TrieEntryVersion::V1, entries.iter().cloned());
+        let trie_root_hash = builder.root_merkle_value();
+        assert_eq!(
+            trie_root_hash,
+            trie.root_merkle_value(TrieEntryVersion::V1, None)
+        );
+        assert_ne!(
+            trie_root_hash,
+            trie.root_merkle_value(TrieEntryVersion::V0, None)
+        );
+
+        for (key, value) in entries.iter() {
+            let proof = builder.build_proof(core::iter::once(key));
+            let obtained = proof_verify::verify_proof(proof_verify::VerifyProofConfig {
+                requested_key: key,
+                trie_root_hash: &trie_root_hash,
+                proof: proof.iter().map(|v| &v[..]),
+            })
+            .unwrap();
+            assert_eq!(obtained, Some(*value));
+        }
+    }
+
+    #[test]
+    fn set_version_recalculates_root() {
+        let entries = [
+            (&b"foo"[..], &b"bar"[..]),
+            (&b"foobaz"[..], &[0xaa; 64][..]),
+        ];
+
+        let mut trie = Trie::new();
+        for (key, value) in entries.iter() {
+            trie.insert(key, *value);
+        }
+
+        let mut builder = ProofBuilder::new(TrieEntryVersion::V0, entries.iter().cloned());
+        builder.set_version(TrieEntryVersion::V1);
+        assert_eq!(
+            builder.root_merkle_value(),
+            trie.root_merkle_value(TrieEntryVersion::V1, None)
+        );
+
+        builder.set_version(TrieEntryVersion::V0);
+        assert_eq!(
+            builder.root_merkle_value(),
+            trie.root_merkle_value(TrieEntryVersion::V0, None)
+        );
+    }
+
+    #[test]
+    fn prefix_proof_verifies() {
+        let entries = [
+            (&b"foo"[..], &b"bar"[..]),
+            (&b"foobaz"[..], &[0xaa; 64][..]),
+            (&b"fooqux"[..], &b"hello"[..]),
+            (&b"abcdef"[..], &[0x55; 40][..]),
+            (&b"a"[..], &b""[..]),
+        ];
+
+        let mut builder = ProofBuilder::new(TrieEntryVersion::V0, entries.iter().cloned());
+        let trie_root_hash = builder.root_merkle_value();
+
+        for (prefix, expected) in [
+            (
+                &b"foo"[..],
+                &[&b"foo"[..], &b"foobaz"[..], &b"fooqux"[..]][..],
+            ),
+            (&b"fooq"[..], &[&b"fooqux"[..]][..]),
+            (&b"a"[..], &[&b"a"[..], &b"abcdef"[..]][..]),
+            (&b"b"[..], &[][..]),
+        ]
+        .iter()
+        {
+            let proof = builder.build_prefix_proof(prefix);
+
+            let scan = prefix_proof::prefix_scan(prefix_proof::Config {
+                prefix,
+                trie_root_hash,
+            });
+
+            let mut keys = match scan.resume(proof.iter().map(|v| &v[..])) {
+                Ok(prefix_proof::ResumeOutcome::Success { keys }) => keys,
+                _ => panic!(),
+            };
+            keys.sort();
+
+            assert_eq!(
+                keys,
+                expected.iter().map(|k| k.to_vec()).collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn merged_proofs_verify() {
+        let entries = [
+            (&b"foo"[..], &[0x11; 40][..]),
+            (&b"foobar"[..], &[0x22; 40][..]),
+            (&b"baz"[..], &[0x33; 40][..]),
+        ];
+
+        let mut builder = ProofBuilder::new(TrieEntryVersion::V0, entries.iter().cloned());
+        let trie_root_hash = builder.root_merkle_value();
+
+        let proofs = entries
+            .iter()
+            .map(|(key, _)| builder.build_proof(core::iter::once(key)))
+            .collect::<Vec<_>>();
+        let merged = super::merge_proofs(proofs.iter().map(|p| p.iter().map(|e| &e[..])));
+
+        // The root node is found in each proof but must only appear once.
+        assert!(merged.len() < proofs.iter().map(|p| p.len()).sum::<usize>());
+
+        for (key, value) in &entries {
+            let obtained = proof_verify::verify_proof(proof_verify::VerifyProofConfig {
+                requested_key: key,
+                trie_root_hash: &trie_root_hash,
+                proof: merged.iter().map(|v| &v[..]),
+            })
+            .unwrap();
+            assert_eq!(obtained, Some(*value));
+        }
+    }
+
+    #[test]
+    fn empty_trie() {
+        let mut builder =
+            ProofBuilder::new(TrieEntryVersion::V0, core::iter::empty::<(&[u8], &[u8])>());
+        let trie_root_hash = builder.root_merkle_value();
+        assert_eq!(trie_root_hash, crate::trie::empty_trie_merkle_value());
+
+        // The proof consists in the node value of the root node.
+        let proof = builder.build_proof([&b"foo"[..]].iter());
+        assert_eq!(proof, vec![vec![0]]);
+        assert_eq!(
+            blake2_rfc::blake2b::blake2b(32, &[], &proof[0]).as_bytes(),
+            &trie_root_hash[..]
+        );
+    }
+
+    #[test]
+    fn apply_changes_matches_new() {
+        let mut storage = alloc::collections::BTreeMap::<Vec<u8>, Vec<u8>>::new();
+        storage.insert(b"foo".to_vec(), b"bar".to_vec());
+        storage.insert(b"foobaz".to_vec(), vec![0xaa; 64]);
+        storage.insert(b"abcdef".to_vec(), vec![0x55; 40]);
+
+        let mut builder = ProofBuilder::new(
+            TrieEntryVersion::V0,
+            storage.iter().map(|(k, v)| (k, v.clone())),
+        );
+
+        let changes: Vec<Vec<(&[u8], Option<&[u8]>)>> = vec![
+            // Updating a value.
+            vec![(&b"foo"[..], Some(&b"baz"[..]))],
+            // Inserting below an existing node, and next to an existing node.
+            vec![
+                (&b"foobar"[..], Some(&[0x11; 40][..])),
+                (&b"abcxyz"[..], Some(&b"hello"[..])),
+            ],
+            // Inserting above the root node.
+            vec![(&b""[..], Some(&b"root"[..]))],
+            // Removing a node with children, a node without children, and a missing key.
+            vec![
+                (&b"foo"[..], None),
+                (&b"abcxyz"[..], None),
+                (&b"missing"[..], None),
+            ],
+            // Removing the root node.
+            vec![(&b""[..], None)],
+            // Removing everything.
+            vec![
+                (&b"foobaz"[..], None),
+                (&b"foobar"[..], None),
+                (&b"abcdef"[..], None),
+            ],
+            // Inserting in an empty trie.
+            vec![(&b"a"[..], Some(&b"b"[..]))],
+        ];
+
+        for changes in changes {
+            for (key, value) in &changes {
+                match value {
+                    Some(value) => storage.insert(key.to_vec(), value.to_vec()),
+                    None => storage.remove(*key),
+                };
+            }
+
+            builder.apply_changes(changes.iter().map(|(k, v)| (*k, *v)));
+
+            let mut expected = ProofBuilder::new(
+                TrieEntryVersion::V0,
+                storage.iter().map(|(k, v)| (k, v.clone())),
+            );
+            assert_eq!(builder.root_merkle_value(), expected.root_merkle_value());
+            for key in storage.keys() {
+                assert_eq!(
+                    builder.build_proof(core::iter::once(key)),
+                    expected.build_proof(core::iter::once(key))
+                );
+            }
+        }
+    }
+}
@@ -15,7 +15,9 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use super::nibble;
+use super::{nibble, node_value};
+
+use alloc::vec::Vec;
 use core::{convert::TryFrom as _, fmt, iter, slice};
 
 /// Decodes a node value found in a proof into its components.
@@ -24,14 +26,26 @@ pub fn decode(mut node_value: &[u8]) -> Result<Decoded, Error> {
         return Err(Error::Empty);
     }
 
-    let has_children = (node_value[0] & 0x80) != 0;
-    let has_storage_value = (node_value[0] & 0x40) != 0;
+    // The most significant bits of the header contain the type of node. The number of bits
+    // used for the type of node depends on the type of node.
+    let (has_children, storage_value_ty, pk_len_mask) = match node_value[0] >> 6 {
+        0b01 => (false, StorageValueTy::Unhashed, 0b111111),
+        0b10 => (true, StorageValueTy::None, 0b111111),
+        0b11 => (true, StorageValueTy::Unhashed, 0b111111),
+        _ => match node_value[0] >> 4 {
+            0b0010 | 0b0011 => (false, StorageValueTy::Hashed, 0b11111),
+            0b0001 => (true, StorageValueTy::Hashed, 0b1111),
+            // Only the node value of the root node of an empty trie can start with `0`.
+            0b0000 if node_value[0] == 0 => (false, StorageValueTy::None, 0),
+            _ => return Err(Error::InvalidHeader),
+        },
+    };
 
     // Length of the partial key, in nibbles.
     let pk_len = {
-        let mut accumulator = usize::from(node_value[0] & 0b111111);
+        let mut accumulator = usize::from(node_value[0] & pk_len_mask);
         node_value = &node_value[1..];
-        let mut continue_iter = accumulator == 63;
+        let mut continue_iter = pk_len_mask != 0 && accumulator == usize::from(pk_len_mask);
         while continue_iter {
             if node_value.is_empty() {
                 return Err(Error::PartialKeyLenTooShort);
@@ -79,19 +93,28 @@ pub fn decode(mut node_value: &[u8]) -> Result<Decoded, Error> {
         0
     };
 
-    let storage_value = if has_storage_value {
-        // Now at the value that interests us.
-        let (node_value_update, len) = crate::util::nom_scale_compact_usize(node_value)
-            .map_err(|_: nom::Err<nom::error::Error<&[u8]>>| Error::StorageValueLenDecode)?;
-        node_value = node_value_update;
-        if node_value.len() < len {
-            return Err(Error::StorageValueTooShort);
+    let storage_value = match storage_value_ty {
+        StorageValueTy::None => StorageValue::None,
+        StorageValueTy::Unhashed => {
+            // Now at the value that interests us.
+            let (node_value_update, len) = crate::util::nom_scale_compact_usize(node_value)
+                .map_err(|_: nom::Err<nom::error::Error<&[u8]>>| Error::StorageValueLenDecode)?;
+            node_value = node_value_update;
+            if node_value.len() < len {
+                return Err(Error::StorageValueTooShort);
+            }
+            let storage_value = &node_value[..len];
+            node_value = &node_value[len..];
+            StorageValue::Unhashed(storage_value)
+        }
+        StorageValueTy::Hashed => {
+            if node_value.len() < 32 {
+                return Err(Error::StorageValueTooShort);
+            }
+            let storage_value_hash = <&[u8; 32]>::try_from(&node_value[..32]).unwrap();
+            node_value = &node_value[32..];
+            StorageValue::Hashed(storage_value_hash)
         }
-        let storage_value = &node_value[..len];
-        node_value = &node_value[len..];
-        Some(storage_value)
-    } else {
-        None
     };
 
     let mut children = [None; 16];
@@ -142,8 +165,8 @@ pub struct Decoded<'a> {
     ///
     pub children: [Option<&'a [u8]>; 16],
 
-    /// Storage value of this node, or `None` if there is no storage value.
-    pub storage_value: Option<&'a [u8]>,
+    /// Storage value of this node.
+    pub storage_value: StorageValue<'a>,
 }
 
 impl<'a> Decoded<'a> {
@@ -158,6 +181,32 @@ impl<'a> Decoded<'a> {
         }
         out
     }
+
+    /// Encodes back the node value. This is the inverse operation of [`decode`].
+    ///
+    /// See also [`node_value::encode_node`].
+    pub fn encode(&self) -> Vec<u8> {
+        node_value::encode_node(self.partial_key.clone(), &self.children, self.storage_value)
+    }
+}
+
+/// See [`Decoded::storage_value`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StorageValue<'a> {
+    /// Storage value of the node is present in the node value.
+    Unhashed(&'a [u8]),
+    /// Only the hash of the storage value of the node is present in the node value. This is
+    /// the case for storage values of at least 33 bytes in version 1 of the trie.
+    Hashed(&'a [u8; 32]),
+    /// Node doesn't have any storage value.
+    None,
+}
+
+/// Type of storage value found in a node value, as indicated by the header.
+enum StorageValueTy {
+    None,
+    Unhashed,
+    Hashed,
 }
 
 /// Iterator to the nibbles of the partial key. See [`Decoded::partial_key`].
@@ -210,6 +259,8 @@ impl<'a> fmt::Debug for PartialKey<'a> {
 pub enum Error {
     /// Node value is empty.
     Empty,
+    /// The header of the node value contains an unknown node type.
+    InvalidHeader,
     /// Node value ends while parsing partial key length.
     PartialKeyLenTooShort,
     /// Length of partial key is too large to be reasonable.
@@ -254,7 +305,10 @@ mod tests {
                 nibble::Nibble::try_from(0x3).unwrap()
             ]
         );
-        assert_eq!(decoded.storage_value, Some(&[][..]));
+        assert_eq!(
+            decoded.storage_value,
+            super::StorageValue::Unhashed(&[][..])
+        );
 
         assert_eq!(decoded.children.iter().filter(|c| c.is_some()).count(), 2);
         assert_eq!(
@@ -276,4 +330,45 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn hashed_value() {
+        let mut node_value = vec![0b0010_0010, 0x12];
+        node_value.extend_from_slice(&[0xaa; 32]);
+
+        let decoded = super::decode(&node_value).unwrap();
+        assert_eq!(
+            decoded.partial_key.clone().collect::<Vec<_>>(),
+            vec![
+                nibble::Nibble::try_from(0x1).unwrap(),
+                nibble::Nibble::try_from(0x2).unwrap()
+            ]
+        );
+        assert_eq!(
+            decoded.storage_value,
+            super::StorageValue::Hashed(&[0xaa; 32])
+        );
+        assert!(decoded.children.iter().all(|c| c.is_none()));
+        assert_eq!(decoded.encode(), node_value);
+    }
+
+    #[test]
+    fn encode_decode_roundtrip() {
+        let node_values: &[&[u8]] = &[
+            &[
+                194, 99, 192, 0, 0, 128, 129, 254, 111, 21, 39, 188, 215, 18, 139, 76, 128, 157,
+                108, 33, 139, 232, 34, 73, 0, 21, 202, 54, 18, 71, 145, 117, 47, 222, 189, 93, 119,
+                68, 128, 108, 211, 105, 98, 122, 206, 246, 73, 77, 237, 51, 77, 26, 166, 1, 52,
+                179, 173, 43, 89, 219, 104, 196, 190, 208, 128, 135, 177, 13, 185, 111, 175,
+            ],
+            &[0],
+            &[0x43, 0x01, 0x23, 0x0c, 0x66, 0x6f, 0x6f],
+            &[0x80, 0x01, 0x00, 0x0c, 0x66, 0x6f, 0x6f],
+        ];
+
+        for node_value in node_values {
+            let decoded = super::decode(node_value).unwrap();
+            assert_eq!(&decoded.encode()[..], *node_value);
+        }
+    }
 }
@@ -80,12 +80,17 @@ pub struct VerifyProofConfig<'a, I> {
 pub fn verify_proof<'a, 'b>(
     config: VerifyProofConfig<'a, impl Iterator<Item = &'b [u8]> + Clone>,
 ) -> Result<Option<&'b [u8]>, Error> {
-    Ok(trie_node_info(TrieNodeInfoConfig {
+    match trie_node_info(TrieNodeInfoConfig {
         requested_key: nibble::bytes_to_nibbles(config.requested_key.iter().cloned()),
         trie_root_hash: config.trie_root_hash,
         proof: config.proof,
     })?
-    .storage_value)
+    .storage_value
+    {
+        StorageValue::Known(value) => Ok(Some(value)),
+        StorageValue::HashKnownValueMissing(_) => Err(Error::MissingStorageValue),
+        StorageValue::None => Ok(None),
+    }
 }
 
 /// Configuration to pass to [`trie_node_info`].
@@ -124,32 +129,12 @@ pub fn trie_node_info<'a, 'b>(
 ) -> Result<TrieNodeInfo<'b>, Error> {
     // The proof contains node values, while Merkle values will be needed. Create a list of
     // Merkle values, one per entry in `config.proof`.
-    let merkle_values = config
-        .proof
-        .clone()
-        .map(|proof_entry| -> arrayvec::ArrayVec<u8, 32> {
-            if proof_entry.len() >= 32 {
-                blake2_rfc::blake2b::blake2b(32, &[], proof_entry)
-                    .as_bytes()
-                    .iter()
-                    .cloned()
-                    .collect()
-            } else {
-                proof_entry.iter().cloned().collect()
-            }
-        })
-        .collect::<Vec<_>>();
+    let merkle_values = merkle_values(config.proof.clone());
 
     // Find the expected trie root in the proof and put it in `node_value`. This is the start
     // point of the verification.
     // `node_value` is updated as the decoding progresses.
-    let mut node_value = {
-        let proof_iter = merkle_values
-            .iter()
-            .position(|v| v[..] == config.trie_root_hash[..])
-            .ok_or(Error::TrieRootNotFound)?;
-        config.proof.clone().nth(proof_iter).unwrap()
-    };
+    let mut node_value = root_node_value(config.trie_root_hash, config.proof.clone())?;
 
     // Number of nibbles that have been found during the iteration below.
     // Used only for debugging purposes.
@@ -167,13 +152,13 @@ pub fn trie_node_info<'a, 'b>(
             match expected_nibbles_iter.next() {
                 None => {
                     return Ok(TrieNodeInfo {
-                        storage_value: None,
+                        storage_value: StorageValue::None,
                         children: Children::One(nibble),
                     });
                 }
                 Some(n) if n != nibble => {
                     return Ok(TrieNodeInfo {
-                        storage_value: None,
+                        storage_value: StorageValue::None,
                         children: Children::None,
                     });
                 }
@@ -193,7 +178,7 @@ pub fn trie_node_info<'a, 'b>(
                 None => {
                     // No child with the requested index exists.
                     return Ok(TrieNodeInfo {
-                        storage_value: None,
+                        storage_value: StorageValue::None,
                         children: Children::None,
                     });
                 }
@@ -218,8 +203,21 @@ pub fn trie_node_info<'a, 'b>(
             iter_nibbles += 1;
         } else {
             // The current node (as per `proof_iter`) exactly matches the requested key.
+            let storage_value = match decoded_node_value.storage_value {
+                proof_node_decode::StorageValue::Unhashed(value) => StorageValue::Known(value),
+                proof_node_decode::StorageValue::Hashed(hash) => {
+                    // In version 1 of the trie, the storage value is a separate entry of the
+                    // proof, whose hash is found in the node value.
+                    match merkle_values.iter().position(|v| v[..] == hash[..]) {
+                        Some(pos) => StorageValue::Known(config.proof.clone().nth(pos).unwrap()),
+                        None => StorageValue::HashKnownValueMissing(hash),
+                    }
+                }
+                proof_node_decode::StorageValue::None => StorageValue::None,
+            };
+
             return Ok(TrieNodeInfo {
-                storage_value: decoded_node_value.storage_value,
+                storage_value,
                 children: Children::Multiple {
                     children_bitmap: decoded_node_value.children_bitmap(),
                 },
@@ -231,11 +229,30 @@ pub fn trie_node_info<'a, 'b>(
 /// Information about a node of the trie.
 pub struct TrieNodeInfo<'a> {
     /// Storage value of the node, if any.
-    pub storage_value: Option<&'a [u8]>,
+    pub storage_value: StorageValue<'a>,
     /// Which children the node has.
     pub children: Children,
 }
 
+/// See [`TrieNodeInfo::storage_value`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StorageValue<'a> {
+    /// The storage value was found in the proof.
+    Known(&'a [u8]),
+    /// The node has a storage value, but only its hash was found in the proof. This can only
+    /// happen in version 1 of the trie.
+    HashKnownValueMissing(&'a [u8; 32]),
+    /// The node doesn't have a storage value.
+    None,
+}
+
+impl<'a> StorageValue<'a> {
+    /// Returns `true` if the node has a storage value, even if this storage value isn't known.
+    pub fn is_some(&self) -> bool {
+        !matches!(self, StorageValue::None)
+    }
+}
+
 /// See [`TrieNodeInfo::children`].
 #[derive(Debug, Copy, Clone)]
 pub enum Children {
@@ -267,6 +284,346 @@ impl Children {
     }
 }
 
+/// Configuration to pass to [`closest_ancestor`].
+pub struct ClosestAncestorConfig<'a, I> {
+    /// Key whose closest ancestor needs to be found.
+    pub key: &'a [u8],
+
+    /// Merkle value (or node value) of the root node of the trie.
+    ///
+    /// > **Note**: The Merkle value and node value are always the same for the root node.
+    pub trie_root_hash: &'a [u8; 32],
+
+    /// List of node values of nodes found in the trie. No specific order is required. All the
+    /// values between the root node and the node closest to the requested key have to be included
+    /// in the list in order for the verification to be able to succeed.
+    pub proof: I,
+}
+
+/// Find the node of the trie that is the closest ancestor of [`ClosestAncestorConfig::key`],
+/// excluding the node whose key is [`ClosestAncestorConfig::key`] itself.
+///
+/// Returns an error if the proof couldn't be verified.
+/// If the proof could be verified, `Ok(Some(_))` is returned containing the key and Merkle value
+/// of the closest ancestor, or `Ok(None)` if the key doesn't have any ancestor in the trie.
+///
+/// > **Note**: This does not fully verify the correctness of the node values provided by `proof`.
+/// >           Only the minimum amount of information required is fetched from `proof`, and an
+/// >           error is returned if a problem happens during this process.
+pub fn closest_ancestor<'a, 'b>(
+    config: ClosestAncestorConfig<'a, impl Iterator<Item = &'b [u8]> + Clone>,
+) -> Result<Option<ClosestAncestor>, Error> {
+    let merkle_values = merkle_values(config.proof.clone());
+    let requested_key = nibble::bytes_to_nibbles(config.key.iter().copied()).collect::<Vec<_>>();
+
+    let mut node_value = root_node_value(config.trie_root_hash, config.proof.clone())?;
+    let mut node_merkle_value = config.trie_root_hash.iter().copied().collect();
+
+    // Key of the node designated by `node_value`, not including its partial key yet.
+    let mut node_key = Vec::with_capacity(requested_key.len());
+
+    // Closest ancestor found so far.
+    let mut closest = None;
+
+    loop {
+        let decoded_node_value =
+            proof_node_decode::decode(node_value).map_err(Error::InvalidNodeValue)?;
+        node_key.extend(decoded_node_value.partial_key.clone());
+
+        // Stop iterating if the current node isn't a strict ancestor of the requested key.
+        if node_key.len() >= requested_key.len() || !requested_key.starts_with(&node_key) {
+            return Ok(closest);
+        }
+
+        let child_index = requested_key[node_key.len()];
+        let child = decoded_node_value.children[usize::from(u8::from(child_index))];
+
+        closest = Some(ClosestAncestor {
+            key: node_key.clone(),
+            merkle_value: node_merkle_value,
+        });
+
+        let child = match child {
+            Some(c) => c,
+            None => return Ok(closest),
+        };
+
+        node_value = if child.len() < 32 {
+            // If the node value is less than 32 bytes, it means it's unhashed. In that case, the
+            // child isn't part of `proof` but directly in the node.
+            child
+        } else {
+            let proof_iter = merkle_values.iter().position(|v| v[..] == *child).ok_or(
+                Error::MissingProofEntry {
+                    closest_ancestor_nibbles: node_key.len(),
+                },
+            )?;
+            config.proof.clone().nth(proof_iter).unwrap()
+        };
+
+        node_merkle_value = child.iter().copied().collect();
+        node_key.push(child_index);
+    }
+}
+
+/// See [`closest_ancestor`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClosestAncestor {
+    /// Key of the ancestor, in nibbles. Always a strict prefix of the requested key.
+    pub key: Vec<nibble::Nibble>,
+    /// Merkle value of the ancestor.
+    pub merkle_value: arrayvec::ArrayVec<u8, 32>,
+}
+
+/// Configuration to pass to [`range_entries`].
+pub struct RangeEntriesConfig<'a, I> {
+    /// Lower bound, inclusive, of the keys to return.
+    pub start_key: &'a [u8],
+
+    /// Upper bound, exclusive, of the keys to return. If `None`, there is no upper bound.
+    pub end_key: Option<&'a [u8]>,
+
+    /// Merkle value (or node value) of the root node of the trie.
+    ///
+    /// > **Note**: The Merkle value and node value are always the same for the root node.
+    pub trie_root_hash: &'a [u8; 32],
+
+    /// List of node values of nodes found in the trie. No specific order is required. All the
+    /// nodes of the trie whose key is within the range, plus their ancestors, have to be included
+    /// in the list in order for the verification to be able to succeed.
+    pub proof: I,
+}
+
+/// Find all the keys with a storage value that are within the range designated by
+/// [`RangeEntriesConfig::start_key`] and [`RangeEntriesConfig::end_key`], alongside with their
+/// storage values.
+///
+/// Returns an error if the proof couldn't be verified. On success, the list of keys and values
+/// is returned, ordered lexicographically by key.
+///
+/// > **Note**: This does not fully verify the correctness of the node values provided by `proof`.
+/// >           Only the minimum amount of information required is fetched from `proof`, and an
+/// >           error is returned if a problem happens during this process.
+pub fn range_entries<'a, 'b>(
+    config: RangeEntriesConfig<'a, impl Iterator<Item = &'b [u8]> + Clone>,
+) -> Result<Vec<(Vec<u8>, &'b [u8])>, Error> {
+    let merkle_values = merkle_values(config.proof.clone());
+    let start_key = nibble::bytes_to_nibbles(config.start_key.iter().copied()).collect::<Vec<_>>();
+    let end_key = config
+        .end_key
+        .map(|k| nibble::bytes_to_nibbles(k.iter().copied()).collect::<Vec<_>>());
+
+    // Returns `true` if some keys starting with `prefix` might be within the range.
+    let overlaps_range = |prefix: &[nibble::Nibble]| {
+        if prefix < &start_key[..] && !start_key.starts_with(prefix) {
+            return false;
+        }
+        match &end_key {
+            Some(end_key) => prefix < &end_key[..],
+            None => true,
+        }
+    };
+
+    let root_node_value = root_node_value(config.trie_root_hash, config.proof.clone())?;
+
+    let mut output = Vec::new();
+
+    // Node values yet to visit, with the key of their parent followed with their child index.
+    // Nodes are visited in depth-first order, parents before their children and children in
+    // increasing order, which guarantees that the keys are found in lexicographic order.
+    let mut to_visit = Vec::new();
+    to_visit.push((root_node_value, Vec::new()));
+
+    while let Some((node_value, mut node_key)) = to_visit.pop() {
+        let decoded_node_value =
+            proof_node_decode::decode(node_value).map_err(Error::InvalidNodeValue)?;
+        node_key.extend(decoded_node_value.partial_key.clone());
+
+        if !overlaps_range(&node_key) {
+            continue;
+        }
+
+        if node_key >= start_key && end_key.as_ref().map_or(true, |end| node_key < *end) {
+            let storage_value = match decoded_node_value.storage_value {
+                proof_node_decode::StorageValue::Unhashed(value) => Some(value),
+                proof_node_decode::StorageValue::Hashed(hash) => {
+                    let pos = merkle_values
+                        .iter()
+                        .position(|v| v[..] == hash[..])
+                        .ok_or(Error::MissingStorageValue)?;
+                    Some(config.proof.clone().nth(pos).unwrap())
+                }
+                proof_node_decode::StorageValue::None => None,
+            };
+
+            if let Some(storage_value) = storage_value {
+                // Trie nodes with a value are always aligned to "bytes-keys".
+                debug_assert_eq!(node_key.len() % 2, 0);
+                let key = nibble::nibbles_to_bytes_extend(node_key.iter().copied()).collect();
+                output.push((key, storage_value));
+            }
+        }
+
+        let first_child_pos = to_visit.len();
+        for nibble in nibble::all_nibbles() {
+            let child = match decoded_node_value.children[usize::from(u8::from(nibble))] {
+                Some(c) => c,
+                None => continue,
+            };
+
+            let mut child_key = Vec::with_capacity(node_key.len() + 1);
+            child_key.extend_from_slice(&node_key);
+            child_key.push(nibble);
+
+            // Children that are known to be out of the range don't need to be in the proof.
+            if !overlaps_range(&child_key) {
+                continue;
+            }
+
+            let child_node_value = if child.len() < 32 {
+                // If the node value is less than 32 bytes, it means it's unhashed. In that case,
+                // the child isn't part of `proof` but directly in the node.
+                child
+            } else {
+                let proof_iter = merkle_values.iter().position(|v| v[..] == *child).ok_or(
+                    Error::MissingProofEntry {
+                        closest_ancestor_nibbles: node_key.len(),
+                    },
+                )?;
+                config.proof.clone().nth(proof_iter).unwrap()
+            };
+
+            to_visit.push((child_node_value, child_key));
+        }
+        to_visit[first_child_pos..].reverse();
+    }
+
+    Ok(output)
+}
+
+/// Configuration to pass to [`next_key`].
+pub struct NextKeyConfig<'a, I> {
+    /// Key whose follow-up needs to be found. The key returned by [`next_key`] is always strictly
+    /// superior to this key.
+    pub key_before: &'a [u8],
+
+    /// Merkle value (or node value) of the root node of the trie.
+    ///
+    /// > **Note**: The Merkle value and node value are always the same for the root node.
+    pub trie_root_hash: &'a [u8; 32],
+
+    /// List of node values of nodes found in the trie. No specific order is required. All the
+    /// values between the root node and the node of the next key, plus the nodes necessary to
+    /// prove that no key exists in between, have to be included in the list in order for the
+    /// verification to be able to succeed.
+    pub proof: I,
+}
+
+/// Find the key with a storage value that immediately follows [`NextKeyConfig::key_before`] in
+/// lexicographic order.
+///
+/// Returns an error if the proof couldn't be verified.
+/// If the proof could be verified, `Ok(Some(_))` is returned containing the next key, or
+/// `Ok(None)` if there isn't any key after [`NextKeyConfig::key_before`] in the trie.
+///
+/// > **Note**: This does not fully verify the correctness of the node values provided by `proof`.
+/// >           Only the minimum amount of information required is fetched from `proof`, and an
+/// >           error is returned if a problem happens during this process.
+pub fn next_key<'a, 'b>(
+    config: NextKeyConfig<'a, impl Iterator<Item = &'b [u8]> + Clone>,
+) -> Result<Option<Vec<u8>>, Error> {
+    let key_before =
+        nibble::bytes_to_nibbles(config.key_before.iter().copied()).collect::<Vec<_>>();
+    next_key_inner(
+        config.trie_root_hash,
+        config.proof,
+        &key_before,
+        &mut Vec::new(),
+    )
+}
+
+/// Returns the smallest key of the trie that is strictly superior to `key_before` and that
+/// starts with `prefix`.
+///
+/// `prefix` is used as a buffer while iterating down the trie, and is always restored to its
+/// original value before this function returns successfully.
+fn next_key_inner<'b>(
+    trie_root_hash: &[u8; 32],
+    proof: impl Iterator<Item = &'b [u8]> + Clone,
+    key_before: &[nibble::Nibble],
+    prefix: &mut Vec<nibble::Nibble>,
+) -> Result<Option<Vec<u8>>, Error> {
+    let node_info = trie_node_info(TrieNodeInfoConfig {
+        requested_key: prefix.iter().cloned(),
+        trie_root_hash,
+        proof: proof.clone(),
+    })?;
+
+    // If `prefix` isn't a prefix of `key_before`, then all the keys that start with `prefix`
+    // are strictly superior to `key_before`, as this function never iterates towards keys
+    // that are inferior.
+    let is_prefix_of_key_before = key_before.starts_with(&prefix[..]);
+
+    if !is_prefix_of_key_before && node_info.storage_value.is_some() {
+        // Trie nodes with a value are always aligned to "bytes-keys".
+        debug_assert_eq!(prefix.len() % 2, 0);
+        return Ok(Some(
+            nibble::nibbles_to_bytes_extend(prefix.iter().copied()).collect(),
+        ));
+    }
+
+    for nibble in node_info.children.next_nibbles() {
+        if is_prefix_of_key_before
+            && prefix.len() < key_before.len()
+            && nibble < key_before[prefix.len()]
+        {
+            continue;
+        }
+
+        prefix.push(nibble);
+        let outcome = next_key_inner(trie_root_hash, proof.clone(), key_before, prefix)?;
+        prefix.pop();
+
+        if outcome.is_some() {
+            return Ok(outcome);
+        }
+    }
+
+    Ok(None)
+}
+
+/// Returns the node value of the root node found in the given proof.
+///
+/// The Merkle value of the root node is always a hash, even when its node value is shorter than
+/// 32 bytes. [`merkle_values`] can't be used to find it.
+fn root_node_value<'b>(
+    trie_root_hash: &[u8; 32],
+    mut proof: impl Iterator<Item = &'b [u8]>,
+) -> Result<&'b [u8], Error> {
+    proof
+        .find(|proof_entry| {
+            blake2_rfc::blake2b::blake2b(32, &[], proof_entry).as_bytes() == &trie_root_hash[..]
+        })
+        .ok_or(Error::TrieRootNotFound)
+}
+
+/// Returns the Merkle values of the given node values, in the same order.
+fn merkle_values<'b>(proof: impl Iterator<Item = &'b [u8]>) -> Vec<arrayvec::ArrayVec<u8, 32>> {
+    proof
+        .map(|proof_entry| -> arrayvec::ArrayVec<u8, 32> {
+            if proof_entry.len() >= 32 {
+                blake2_rfc::blake2b::blake2b(32, &[], proof_entry)
+                    .as_bytes()
+                    .iter()
+                    .cloned()
+                    .collect()
+            } else {
+                proof_entry.iter().cloned().collect()
+            }
+        })
+        .collect()
+}
+
 /// Possible error returned by [`verify_proof`]
 #[derive(Debug, Clone, derive_more::Display)]
 pub enum Error {
@@ -285,6 +642,9 @@ pub enum Error {
         /// Number of nibbles in the key of the closest ancestor that was found in the proof.
         closest_ancestor_nibbles: usize,
     },
+    /// The storage value of the requested key is hashed, and the value itself is missing from
+    /// the proof.
+    MissingStorageValue,
 }
 
 #[cfg(test)]
@@ -384,4 +744,114 @@ mod tests {
 
         assert_eq!(obtained, Some(&[80, 82, 127, 41, 119, 1, 0, 0][..]));
     }
+
+    #[test]
+    fn closest_ancestor_and_range_work() {
+        let mut builder = crate::trie::proof_encode::ProofBuilder::new(
+            crate::trie::TrieEntryVersion::V0,
+            [
+                (&b"a"[..], &b"1"[..]),
+                (&b"abcd"[..], &b"2"[..]),
+                (&b"abce"[..], &b"3"[..]),
+                (&b"b"[..], &b"4"[..]),
+            ]
+            .iter()
+            .cloned(),
+        );
+
+        let trie_root = builder.root_merkle_value();
+        let proof = builder.build_prefix_proof(b"");
+
+        let closest_ancestor = |key: &[u8]| {
+            super::closest_ancestor(super::ClosestAncestorConfig {
+                key,
+                trie_root_hash: &trie_root,
+                proof: proof.iter().map(|p| &p[..]),
+            })
+            .unwrap()
+            .map(|ancestor| {
+                crate::trie::nibbles_to_bytes_extend(ancestor.key.into_iter()).collect::<Vec<_>>()
+            })
+        };
+
+        // Keys with an uneven number of nibbles are padded with a `0` nibble, hence the "`".
+        assert_eq!(closest_ancestor(b""), None);
+        assert_eq!(closest_ancestor(b"a"), Some(b"`".to_vec()));
+        assert_eq!(closest_ancestor(b"ab"), Some(b"a".to_vec()));
+        assert_eq!(closest_ancestor(b"abcd"), Some(b"abc`".to_vec()));
+        assert_eq!(closest_ancestor(b"c"), Some(b"`".to_vec()));
+
+        let root_ancestor = super::closest_ancestor(super::ClosestAncestorConfig {
+            key: b"b",
+            trie_root_hash: &trie_root,
+            proof: proof.iter().map(|p| &p[..]),
+        })
+        .unwrap()
+        .unwrap();
+        assert_eq!(&root_ancestor.merkle_value[..], &trie_root[..]);
+
+        let range = |start_key: &[u8], end_key: Option<&[u8]>| {
+            super::range_entries(super::RangeEntriesConfig {
+                start_key,
+                end_key,
+                trie_root_hash: &trie_root,
+                proof: proof.iter().map(|p| &p[..]),
+            })
+            .unwrap()
+            .into_iter()
+            .map(|(key, value)| (key, value.to_vec()))
+            .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            range(b"ab", Some(&b"abce"[..])),
+            vec![(b"abcd".to_vec(), b"2".to_vec())]
+        );
+        assert_eq!(
+            range(b"abcd", None),
+            vec![
+                (b"abcd".to_vec(), b"2".to_vec()),
+                (b"abce".to_vec(), b"3".to_vec()),
+                (b"b".to_vec(), b"4".to_vec())
+            ]
+        );
+        assert!(range(b"", Some(&b"a"[..])).is_empty());
+        assert_eq!(range(b"", None).len(), 4);
+    }
+
+    #[test]
+    fn next_key_works() {
+        let mut builder = crate::trie::proof_encode::ProofBuilder::new(
+            crate::trie::TrieEntryVersion::V0,
+            [
+                (&b"a"[..], &b"1"[..]),
+                (&b"abcd"[..], &b"2"[..]),
+                (&b"abce"[..], &b"3"[..]),
+                (&b"b"[..], &b"4"[..]),
+            ]
+            .iter()
+            .cloned(),
+        );
+
+        let trie_root = builder.root_merkle_value();
+        let proof = builder.build_prefix_proof(b"");
+
+        let next_key = |key_before: &[u8]| {
+            super::next_key(super::NextKeyConfig {
+                key_before,
+                trie_root_hash: &trie_root,
+                proof: proof.iter().map(|p| &p[..]),
+            })
+            .unwrap()
+        };
+
+        assert_eq!(next_key(b""), Some(b"a".to_vec()));
+        assert_eq!(next_key(b"a"), Some(b"abcd".to_vec()));
+        assert_eq!(next_key(b"ab"), Some(b"abcd".to_vec()));
+        assert_eq!(next_key(b"abcd"), Some(b"abce".to_vec()));
+        assert_eq!(next_key(b"abcdzz"), Some(b"abce".to_vec()));
+        assert_eq!(next_key(b"abce"), Some(b"b".to_vec()));
+        assert_eq!(next_key(b"b"), None);
+        assert_eq!(next_key(b"c"), None);
+    }
 }
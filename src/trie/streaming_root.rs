@@ -0,0 +1,231 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Calculation of the root of a trie from a stream of entries ordered by key.
+//!
+//! Contrary to [the `calculate_root` module](super::calculate_root), which requires the list of
+//! all the keys of the trie to be available at once, this module only keeps in memory the nodes
+//! between the root of the trie and the latest entry that has been pushed. The Merkle value of
+//! each node is calculated as soon as it is known that no other entry will be pushed in its
+//! sub-tree, after which the node is discarded. The memory usage is thus proportional to the
+//! depth of the trie rather than to its number of entries.
+//!
+//! In exchange, the entries must be pushed in increasing lexicographic order of keys.
+//!
+//! # Example
+//!
+//! ```
+//! use smoldot::trie::{streaming_root, TrieEntryVersion};
+//!
+//! let mut calculation = streaming_root::StreamingRootCalculation::new(TrieEntryVersion::V0);
+//! calculation.push(b"foo", b"bar".to_vec());
+//! let trie_root = calculation.finish();
+//!
+//! assert_eq!(
+//!     trie_root,
+//!     [204, 86, 28, 213, 155, 206, 247, 145, 28, 169, 212, 146, 182, 159, 224, 82,
+//!      116, 162, 143, 156, 19, 43, 183, 8, 41, 178, 204, 69, 41, 37, 224, 91]
+//! );
+//! ```
+
+use super::{
+    nibble::{bytes_to_nibbles, Nibble},
+    node_value, TrieEntryVersion,
+};
+
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Calculates the root of a trie whose entries are pushed in increasing lexicographic order.
+pub struct StreamingRootCalculation {
+    /// Nodes whose Merkle value hasn't been calculated yet, ordered from the root to the node of
+    /// the latest entry that has been pushed. The key of each node is a strict prefix of the key
+    /// of the next node.
+    stack: Vec<PendingNode>,
+
+    /// Version to use when encoding the storage values.
+    version: TrieEntryVersion,
+}
+
+struct PendingNode {
+    /// Full key of the node.
+    key: Vec<Nibble>,
+    /// Storage value of the node, if any.
+    storage_value: Option<Vec<u8>>,
+    /// Merkle values of the children of the node that have already been calculated.
+    children: [Option<node_value::Output>; 16],
+}
+
+impl StreamingRootCalculation {
+    /// Initializes a new calculation for an empty trie. Storage values are encoded using the
+    /// given version.
+    pub fn new(version: TrieEntryVersion) -> Self {
+        StreamingRootCalculation {
+            stack: Vec::with_capacity(16),
+            version,
+        }
+    }
+
+    /// Adds an entry to the trie.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `key` isn't strictly superior to the key of the previously-pushed entry.
+    ///
+    pub fn push(&mut self, key: &[u8], storage_value: impl Into<Vec<u8>>) {
+        let key = bytes_to_nibbles(key.iter().copied()).collect::<Vec<_>>();
+
+        if let Some(last) = self.stack.last() {
+            assert!(key > last.key, "entries must be pushed in increasing order");
+
+            // Number of nibbles shared between the new key and the previous one.
+            let common_prefix_len = key
+                .iter()
+                .zip(last.key.iter())
+                .take_while(|(a, b)| a == b)
+                .count();
+
+            // Since the keys are pushed in order, no other entry will be pushed in the sub-trees
+            // of the nodes that aren't ancestors of the new key. Their Merkle values can be
+            // calculated.
+            while self.stack.last().unwrap().key.len() > common_prefix_len {
+                let node = self.stack.pop().unwrap();
+
+                // The new key and the key of `node` diverge at `common_prefix_len`. If the
+                // parent of `node` is above this divergence point, a branch node must be
+                // inserted.
+                let needs_branch = self
+                    .stack
+                    .last()
+                    .map_or(true, |parent| parent.key.len() < common_prefix_len);
+                if needs_branch {
+                    self.stack.push(PendingNode {
+                        key: node.key[..common_prefix_len].to_vec(),
+                        storage_value: None,
+                        children: Default::default(),
+                    });
+                }
+
+                self.finalize_child(node);
+            }
+        }
+
+        self.stack.push(PendingNode {
+            key,
+            storage_value: Some(storage_value.into()),
+            children: Default::default(),
+        });
+    }
+
+    /// Returns the Merkle value of the root of the trie containing all the entries that have
+    /// been pushed.
+    pub fn finish(mut self) -> [u8; 32] {
+        while self.stack.len() >= 2 {
+            let node = self.stack.pop().unwrap();
+            self.finalize_child(node);
+        }
+
+        let root = match self.stack.pop() {
+            Some(r) => r,
+            None => return super::empty_trie_merkle_value(),
+        };
+
+        node_value::calculate_merkle_root(node_value::Config {
+            ty: node_value::NodeTy::Root {
+                key: root.key.iter().copied(),
+            },
+            children: root.children.iter().map(|c| c.as_ref()),
+            stored_value: root.storage_value.as_ref(),
+            version: self.version,
+        })
+        .into()
+    }
+
+    /// Calculates the Merkle value of the given node and stores it in its parent, which must be
+    /// the last element of [`StreamingRootCalculation::stack`].
+    fn finalize_child(&mut self, node: PendingNode) {
+        let parent = self.stack.last_mut().unwrap();
+        debug_assert!(node.key.starts_with(&parent.key));
+        debug_assert!(node.key.len() > parent.key.len());
+
+        let merkle_value = node_value::calculate_merkle_root(node_value::Config {
+            ty: node_value::NodeTy::NonRoot {
+                partial_key: node.key[parent.key.len() + 1..].iter().copied(),
+            },
+            children: node.children.iter().map(|c| c.as_ref()),
+            stored_value: node.storage_value.as_ref(),
+            version: self.version,
+        });
+
+        let child_index = usize::from(u8::from(node.key[parent.key.len()]));
+        debug_assert!(parent.children[child_index].is_none());
+        parent.children[child_index] = Some(merkle_value);
+    }
+}
+
+impl fmt::Debug for StreamingRootCalculation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("StreamingRootCalculation").finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StreamingRootCalculation;
+    use crate::trie::{Trie, TrieEntryVersion};
+
+    #[test]
+    fn empty_trie() {
+        let calculation = StreamingRootCalculation::new(TrieEntryVersion::V0);
+        assert_eq!(calculation.finish(), crate::trie::empty_trie_merkle_value());
+    }
+
+    #[test]
+    fn matches_trie() {
+        let entries: &[(&[u8], &[u8])] = &[
+            (b"", b"empty"),
+            (b"a", b"1"),
+            (b"abcd", b"2"),
+            (b"abce", &[0x55; 40]),
+            (b"abcef", b"4"),
+            (b"b", b"5"),
+            (b"ba", b""),
+            (b"zzz", &[0xaa; 64]),
+        ];
+
+        for version in [TrieEntryVersion::V0, TrieEntryVersion::V1] {
+            for start in 0..entries.len() {
+                let mut trie = Trie::new();
+                let mut calculation = StreamingRootCalculation::new(version);
+                for (key, value) in &entries[start..] {
+                    trie.insert(key, *value);
+                    calculation.push(key, *value);
+                }
+
+                assert_eq!(calculation.finish(), trie.root_merkle_value(version, None));
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn unordered_panics() {
+        let mut calculation = StreamingRootCalculation::new(TrieEntryVersion::V0);
+        calculation.push(b"b", b"1");
+        calculation.push(b"a", b"2");
+    }
+}
@@ -201,3 +201,26 @@ pub(crate) fn encode_scale_compact_usize(mut value: usize) -> impl AsRef<[u8]> +
 
     array
 }
+
+/// Returns a buffer containing the SCALE-compact encoding of the parameter.
+pub(crate) fn encode_scale_compact_u128(mut value: u128) -> impl AsRef<[u8]> + Clone {
+    let mut array = arrayvec::ArrayVec::<u8, 17>::new();
+
+    if value < (1 << 30) {
+        // Values below this threshold are encoded identically regardless of the integer type.
+        array
+            .try_extend_from_slice(
+                encode_scale_compact_usize(usize::try_from(value).unwrap()).as_ref(),
+            )
+            .unwrap();
+    } else {
+        array.push(0);
+        while value != 0 {
+            array.push(u8::try_from(value & 0xff).unwrap());
+            value >>= 8;
+        }
+        array[0] = (u8::try_from(array.len() - 1 - 4).unwrap() << 2) | 0b11;
+    }
+
+    array
+}
@@ -107,6 +107,11 @@ impl FramedInProgress {
         }
     }
 
+    /// Returns the maximum allowed length of the frame, as passed to [`FramedInProgress::new`].
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+
     pub fn update(mut self, mut data: &[u8]) -> Result<(usize, Framed), FramedError> {
         fn decode_leb128(buffer: &[u8]) -> Option<Result<usize, FramedError>> {
             let mut out = 0usize;
@@ -70,5 +70,6 @@ mod execute_block;
 
 pub mod aura;
 pub mod babe;
+pub mod execution_proof;
 pub mod header_body;
 pub mod header_only;
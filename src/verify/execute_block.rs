@@ -78,6 +78,9 @@ pub struct Success {
     pub parent_runtime: host::HostVmPrototype,
     /// List of changes to the storage top trie that the block performs.
     pub storage_top_trie_changes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    /// List of changes to the storage child tries that the block performs, indexed by child
+    /// trie.
+    pub storage_child_tries_changes: BTreeMap<Vec<u8>, BTreeMap<Vec<u8>, Option<Vec<u8>>>>,
     /// List of changes to the offchain storage that this block performs.
     pub offchain_storage_changes: HashMap<Vec<u8>, Option<Vec<u8>>, fnv::FnvBuildHasher>,
     /// Cache used for calculating the top trie root.
@@ -125,6 +128,7 @@ pub fn execute_block(
         },
         top_trie_root_calculation_cache: config.top_trie_root_calculation_cache,
         storage_top_trie_changes: Default::default(),
+        storage_child_tries_changes: Default::default(),
         offchain_storage_changes: Default::default(),
     });
 
@@ -145,9 +149,31 @@ pub enum Verify {
     PrefixKeys(PrefixKeys),
     /// Fetching the key that follows a given one is required in order to continue.
     NextKey(NextKey),
+    /// Verifying whether a batch of signatures is valid is required in order to continue.
+    SignatureBatchVerify(SignatureBatchVerify),
 }
 
 impl Verify {
+    /// Cancels execution of the virtual machine and returns back the prototype.
+    pub fn into_prototype(self) -> host::HostVmPrototype {
+        match self {
+            Verify::Finished(Ok(success)) => success.parent_runtime,
+            Verify::Finished(Err((_, prototype))) => prototype,
+            Verify::StorageGet(inner) => {
+                runtime_host::RuntimeHostVm::StorageGet(inner.0).into_prototype()
+            }
+            Verify::PrefixKeys(inner) => {
+                runtime_host::RuntimeHostVm::PrefixKeys(inner.0).into_prototype()
+            }
+            Verify::NextKey(inner) => {
+                runtime_host::RuntimeHostVm::NextKey(inner.0).into_prototype()
+            }
+            Verify::SignatureBatchVerify(inner) => {
+                runtime_host::RuntimeHostVm::SignatureBatchVerify(inner.0).into_prototype()
+            }
+        }
+    }
+
     fn from_inner(inner: runtime_host::RuntimeHostVm) -> Self {
         match inner {
             runtime_host::RuntimeHostVm::Finished(Ok(success)) => {
@@ -161,6 +187,7 @@ impl Verify {
                 Verify::Finished(Ok(Success {
                     parent_runtime: success.virtual_machine.into_prototype(),
                     storage_top_trie_changes: success.storage_top_trie_changes,
+                    storage_child_tries_changes: success.storage_child_tries_changes,
                     offchain_storage_changes: success.offchain_storage_changes,
                     top_trie_root_calculation_cache: success.top_trie_root_calculation_cache,
                     logs: success.logs,
@@ -172,6 +199,9 @@ impl Verify {
             runtime_host::RuntimeHostVm::StorageGet(inner) => Verify::StorageGet(StorageGet(inner)),
             runtime_host::RuntimeHostVm::PrefixKeys(inner) => Verify::PrefixKeys(PrefixKeys(inner)),
             runtime_host::RuntimeHostVm::NextKey(inner) => Verify::NextKey(NextKey(inner)),
+            runtime_host::RuntimeHostVm::SignatureBatchVerify(inner) => {
+                Verify::SignatureBatchVerify(SignatureBatchVerify(inner))
+            }
         }
     }
 }
@@ -193,10 +223,22 @@ impl StorageGet {
         self.0.key_as_vec()
     }
 
+    /// If `Some`, the key must be read from the child trie whose key is returned rather than from
+    /// the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.0.child_trie()
+    }
+
     /// Injects the corresponding storage value.
     pub fn inject_value(self, value: Option<impl Iterator<Item = impl AsRef<[u8]>>>) -> Verify {
         Verify::from_inner(self.0.inject_value(value))
     }
+
+    /// Stops the verification, as the requested storage can't be provided. The block is then
+    /// considered as failing to verify.
+    pub fn inject_unavailable(self) -> Verify {
+        Verify::from_inner(self.0.inject_unavailable())
+    }
 }
 
 /// Fetching the list of keys with a given prefix is required in order to continue.
@@ -209,10 +251,22 @@ impl PrefixKeys {
         self.0.prefix()
     }
 
+    /// If `Some`, the keys must be loaded from the child trie whose key is returned rather than
+    /// from the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.0.child_trie()
+    }
+
     /// Injects the list of keys ordered lexicographically.
     pub fn inject_keys_ordered(self, keys: impl Iterator<Item = impl AsRef<[u8]>>) -> Verify {
         Verify::from_inner(self.0.inject_keys_ordered(keys))
     }
+
+    /// Stops the verification, as the requested storage can't be provided. The block is then
+    /// considered as failing to verify.
+    pub fn inject_unavailable(self) -> Verify {
+        Verify::from_inner(self.0.inject_unavailable())
+    }
 }
 
 /// Fetching the key that follows a given one is required in order to continue.
@@ -225,6 +279,12 @@ impl NextKey {
         self.0.key()
     }
 
+    /// If `Some`, the key must be searched in the child trie whose key is returned rather than
+    /// in the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.0.child_trie()
+    }
+
     /// Injects the key.
     ///
     /// # Panic
@@ -234,4 +294,32 @@ impl NextKey {
     pub fn inject_key(self, key: Option<impl AsRef<[u8]>>) -> Verify {
         Verify::from_inner(self.0.inject_key(key))
     }
+
+    /// Stops the verification, as the requested storage can't be provided. The block is then
+    /// considered as failing to verify.
+    pub fn inject_unavailable(self) -> Verify {
+        Verify::from_inner(self.0.inject_unavailable())
+    }
+}
+
+/// Verifying whether a batch of signatures is valid is required in order to continue.
+#[must_use]
+pub struct SignatureBatchVerify(runtime_host::SignatureBatchVerify);
+
+impl SignatureBatchVerify {
+    /// Returns the signatures of the batch.
+    pub fn signatures(&self) -> &[host::SignatureVerification] {
+        self.0.signatures()
+    }
+
+    /// Resumes the verification. `all_valid` must be `true` if and only if all the signatures
+    /// of the batch are valid.
+    pub fn resume(self, all_valid: bool) -> Verify {
+        Verify::from_inner(self.0.resume(all_valid))
+    }
+
+    /// Verifies the signatures one after the other, then resumes the verification.
+    pub fn verify_and_resume(self) -> Verify {
+        Verify::from_inner(self.0.verify_and_resume())
+    }
 }
@@ -0,0 +1,293 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Block execution against a storage proof.
+//!
+//! Verifying the validity of a block requires executing it, which in turn requires access to the
+//! storage of its parent. Light clients normally don't have access to this storage, but can
+//! instead download from the network a *storage proof* containing all the trie nodes that the
+//! execution of the block reads.
+//!
+//! The [`verify_execution`] function calls the `Core_execute_block` runtime function with the
+//! given header and body, and answers all the storage requests of the runtime using the proof.
+//! The proof is verified against the state trie root of the parent block.
+//!
+//! Executing a block is expensive, and is consequently meant to be done only for specific blocks
+//! of interest rather than for every single block.
+//!
+//! > **Note**: Just like when executing a block normally, the consensus-related aspects of the
+//! >           block header aren't verified. The header passed to [`verify_execution`] must not
+//! >           contain any seal.
+//!
+//! # Child tries
+//!
+//! The root of a child trie is found in the main trie under the key `:child_storage:default:`
+//! followed with the key of the child trie. Consequently, the proof must contain the trie nodes
+//! leading to this key in addition to the nodes of the child trie that are read.
+//!
+
+use super::execute_block;
+use crate::{
+    executor::{host, runtime_host},
+    header,
+    trie::{self, proof_verify},
+};
+
+use alloc::{collections::BTreeMap, string::String, vec, vec::Vec};
+use core::{convert::TryFrom, iter};
+use hashbrown::HashMap;
+
+/// Configuration for a block execution against a storage proof.
+pub struct Config<'a, TBody, TProof> {
+    /// Runtime used to execute the block. Must be built using the Wasm code found at the `:code`
+    /// key of the parent block storage.
+    pub parent_runtime: host::HostVmPrototype,
+
+    /// State trie root hash found in the header of the parent of the block to execute.
+    pub parent_storage_root: &'a [u8; 32],
+
+    /// Header of the block to execute, in SCALE encoding. Must **not** contain any `Seal` item.
+    pub block_header: header::HeaderRef<'a>,
+
+    /// Body of the block to execute.
+    pub block_body: TBody,
+
+    /// List of node values of nodes found in the storage of the parent block. No specific order
+    /// is required. Must contain all the nodes that the execution of the block reads.
+    pub storage_proof: TProof,
+}
+
+/// Block successfully executed.
+pub struct Success {
+    /// Runtime that was passed by [`Config`].
+    pub parent_runtime: host::HostVmPrototype,
+    /// List of changes to the storage top trie that the block performs.
+    pub storage_top_trie_changes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    /// List of changes to the storage child tries that the block performs, indexed by child
+    /// trie.
+    pub storage_child_tries_changes: BTreeMap<Vec<u8>, BTreeMap<Vec<u8>, Option<Vec<u8>>>>,
+    /// List of changes to the offchain storage that this block performs.
+    pub offchain_storage_changes: HashMap<Vec<u8>, Option<Vec<u8>>, fnv::FnvBuildHasher>,
+    /// Concatenation of all the log messages printed by the runtime.
+    pub logs: String,
+}
+
+/// Error that can happen during the execution.
+#[derive(Debug, derive_more::Display)]
+pub enum Error {
+    /// Error while starting the Wasm virtual machine.
+    #[display(fmt = "{}", _0)]
+    WasmStart(host::StartErr),
+    /// Error while running the Wasm virtual machine.
+    #[display(fmt = "{}", _0)]
+    WasmVm(runtime_host::ErrorDetail),
+    /// Output of `Core_execute_block` wasn't empty.
+    NonEmptyOutput,
+    /// Error while reading the storage from the proof.
+    #[display(fmt = "Invalid storage proof: {}", _0)]
+    StorageProof(proof_verify::Error),
+    /// The value found in the main trie as the root of a child trie isn't 32 bytes long.
+    InvalidChildTrieRoot,
+}
+
+/// Executes the given block, answering all the storage requests using the given proof.
+pub fn verify_execution<'a>(
+    config: Config<
+        'a,
+        impl ExactSizeIterator<Item = impl AsRef<[u8]> + Clone> + Clone,
+        impl Iterator<Item = &'a [u8]> + Clone,
+    >,
+) -> Result<Success, (Error, host::HostVmPrototype)> {
+    let proof = config.storage_proof;
+    let parent_storage_root = config.parent_storage_root;
+
+    let mut verify = execute_block::execute_block(execute_block::Config {
+        parent_runtime: config.parent_runtime,
+        block_header: config.block_header,
+        block_body: config.block_body,
+        top_trie_root_calculation_cache: None,
+    });
+
+    loop {
+        match verify {
+            execute_block::Verify::Finished(Ok(success)) => {
+                break Ok(Success {
+                    parent_runtime: success.parent_runtime,
+                    storage_top_trie_changes: success.storage_top_trie_changes,
+                    storage_child_tries_changes: success.storage_child_tries_changes,
+                    offchain_storage_changes: success.offchain_storage_changes,
+                    logs: success.logs,
+                })
+            }
+            execute_block::Verify::Finished(Err((err, prototype))) => {
+                let err = match err {
+                    execute_block::Error::WasmStart(err) => Error::WasmStart(err),
+                    execute_block::Error::WasmVm(err) => Error::WasmVm(err),
+                    execute_block::Error::NonEmptyOutput => Error::NonEmptyOutput,
+                };
+                break Err((err, prototype));
+            }
+            execute_block::Verify::StorageGet(get) => {
+                let child_trie = get.child_trie().map(|c| c.as_ref().to_vec());
+                let value = trie_root_hash(parent_storage_root, child_trie.as_deref(), &proof)
+                    .and_then(|trie_root_hash| {
+                        let trie_root_hash = match trie_root_hash {
+                            Some(h) => h,
+                            None => return Ok(None),
+                        };
+
+                        proof_verify::verify_proof(proof_verify::VerifyProofConfig {
+                            requested_key: &get.key_as_vec(),
+                            trie_root_hash: &trie_root_hash,
+                            proof: proof.clone(),
+                        })
+                        .map_err(Error::StorageProof)
+                    });
+
+                verify = match value {
+                    Ok(value) => get.inject_value(value.map(iter::once)),
+                    Err(err) => {
+                        let prototype = execute_block::Verify::StorageGet(get).into_prototype();
+                        break Err((err, prototype));
+                    }
+                };
+            }
+            execute_block::Verify::PrefixKeys(prefix_keys) => {
+                let child_trie = prefix_keys.child_trie().map(|c| c.as_ref().to_vec());
+                let keys = trie_root_hash(parent_storage_root, child_trie.as_deref(), &proof)
+                    .and_then(|trie_root_hash| {
+                        let trie_root_hash = match trie_root_hash {
+                            Some(h) => h,
+                            None => return Ok(Vec::new()),
+                        };
+
+                        keys_with_prefix(
+                            &trie_root_hash,
+                            proof.clone(),
+                            prefix_keys.prefix().as_ref(),
+                        )
+                        .map_err(Error::StorageProof)
+                    });
+
+                verify = match keys {
+                    Ok(keys) => prefix_keys.inject_keys_ordered(keys.into_iter()),
+                    Err(err) => {
+                        let prototype =
+                            execute_block::Verify::PrefixKeys(prefix_keys).into_prototype();
+                        break Err((err, prototype));
+                    }
+                };
+            }
+            execute_block::Verify::NextKey(next_key) => {
+                let child_trie = next_key.child_trie().map(|c| c.as_ref().to_vec());
+                let key = trie_root_hash(parent_storage_root, child_trie.as_deref(), &proof)
+                    .and_then(|trie_root_hash| {
+                        let trie_root_hash = match trie_root_hash {
+                            Some(h) => h,
+                            None => return Ok(None),
+                        };
+
+                        proof_verify::next_key(proof_verify::NextKeyConfig {
+                            key_before: next_key.key().as_ref(),
+                            trie_root_hash: &trie_root_hash,
+                            proof: proof.clone(),
+                        })
+                        .map_err(Error::StorageProof)
+                    });
+
+                verify = match key {
+                    Ok(key) => next_key.inject_key(key),
+                    Err(err) => {
+                        let prototype = execute_block::Verify::NextKey(next_key).into_prototype();
+                        break Err((err, prototype));
+                    }
+                };
+            }
+            execute_block::Verify::SignatureBatchVerify(batch) => {
+                verify = batch.verify_and_resume();
+            }
+        }
+    }
+}
+
+/// Returns the root hash of the trie designated by `child_trie`, or of the main trie if `None`.
+///
+/// Returns `Ok(None)` if the child trie doesn't exist.
+fn trie_root_hash<'a>(
+    parent_storage_root: &[u8; 32],
+    child_trie: Option<&[u8]>,
+    proof: &(impl Iterator<Item = &'a [u8]> + Clone),
+) -> Result<Option<[u8; 32]>, Error> {
+    let child_trie = match child_trie {
+        Some(c) => c,
+        None => return Ok(Some(*parent_storage_root)),
+    };
+
+    let key = b":child_storage:default:"
+        .iter()
+        .chain(child_trie.iter())
+        .copied()
+        .collect::<Vec<_>>();
+
+    let value = proof_verify::verify_proof(proof_verify::VerifyProofConfig {
+        requested_key: &key,
+        trie_root_hash: parent_storage_root,
+        proof: proof.clone(),
+    })
+    .map_err(Error::StorageProof)?;
+
+    match value {
+        Some(value) => <[u8; 32]>::try_from(value)
+            .map(Some)
+            .map_err(|_| Error::InvalidChildTrieRoot),
+        None => Ok(None),
+    }
+}
+
+/// Returns the list of all the keys of the trie that start with the given prefix, ordered
+/// lexicographically.
+fn keys_with_prefix<'a>(
+    trie_root_hash: &[u8; 32],
+    proof: impl Iterator<Item = &'a [u8]> + Clone,
+    prefix: &[u8],
+) -> Result<Vec<Vec<u8>>, proof_verify::Error> {
+    let mut to_find = vec![trie::bytes_to_nibbles(prefix.iter().copied()).collect::<Vec<_>>()];
+    let mut output = Vec::new();
+
+    while let Some(key) = to_find.pop() {
+        let node_info = proof_verify::trie_node_info(proof_verify::TrieNodeInfoConfig {
+            requested_key: key.iter().cloned(),
+            trie_root_hash,
+            proof: proof.clone(),
+        })?;
+
+        if node_info.storage_value.is_some() {
+            // Trie nodes with a value are always aligned to "bytes-keys".
+            debug_assert_eq!(key.len() % 2, 0);
+            output.push(trie::nibbles_to_bytes_extend(key.iter().copied()).collect::<Vec<_>>());
+        }
+
+        for nibble in node_info.children.next_nibbles() {
+            let mut child = key.clone();
+            child.push(nibble);
+            to_find.push(child);
+        }
+    }
+
+    output.sort();
+    Ok(output)
+}
@@ -309,6 +309,8 @@ pub enum Verify {
     StoragePrefixKeys(StoragePrefixKeys),
     /// Fetching the key that follows a given one is required in order to continue.
     StorageNextKey(StorageNextKey),
+    /// Verifying whether a batch of signatures is valid is required in order to continue.
+    SignatureBatchVerify(SignatureBatchVerify),
 }
 
 struct VerifyInner {
@@ -388,6 +390,12 @@ impl VerifyInner {
                 inner,
                 consensus_success: self.consensus_success,
             }),
+            execute_block::Verify::SignatureBatchVerify(inner) => {
+                Verify::SignatureBatchVerify(SignatureBatchVerify {
+                    inner,
+                    consensus_success: self.consensus_success,
+                })
+            }
         }
     }
 }
@@ -412,6 +420,12 @@ impl StorageGet {
         self.inner.key_as_vec()
     }
 
+    /// If `Some`, the key must be read from the child trie whose key is returned rather than from
+    /// the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.inner.child_trie()
+    }
+
     /// Injects the corresponding storage value.
     pub fn inject_value(self, value: Option<impl Iterator<Item = impl AsRef<[u8]>>>) -> Verify {
         VerifyInner {
@@ -420,6 +434,16 @@ impl StorageGet {
         }
         .run()
     }
+
+    /// Stops the verification, as the requested storage can't be provided. The block is then
+    /// considered as failing to verify.
+    pub fn inject_unavailable(self) -> Verify {
+        VerifyInner {
+            inner: self.inner.inject_unavailable(),
+            consensus_success: self.consensus_success,
+        }
+        .run()
+    }
 }
 
 /// Fetching the list of keys with a given prefix is required in order to continue.
@@ -435,6 +459,12 @@ impl StoragePrefixKeys {
         self.inner.prefix()
     }
 
+    /// If `Some`, the keys must be loaded from the child trie whose key is returned rather than
+    /// from the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.inner.child_trie()
+    }
+
     /// Injects the list of keys ordered lexicographically.
     pub fn inject_keys_ordered(self, keys: impl Iterator<Item = impl AsRef<[u8]>>) -> Verify {
         VerifyInner {
@@ -443,6 +473,16 @@ impl StoragePrefixKeys {
         }
         .run()
     }
+
+    /// Stops the verification, as the requested storage can't be provided. The block is then
+    /// considered as failing to verify.
+    pub fn inject_unavailable(self) -> Verify {
+        VerifyInner {
+            inner: self.inner.inject_unavailable(),
+            consensus_success: self.consensus_success,
+        }
+        .run()
+    }
 }
 
 /// Fetching the key that follows a given one is required in order to continue.
@@ -458,6 +498,12 @@ impl StorageNextKey {
         self.inner.key()
     }
 
+    /// If `Some`, the key must be searched in the child trie whose key is returned rather than
+    /// in the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.inner.child_trie()
+    }
+
     /// Injects the key.
     ///
     /// # Panic
@@ -471,6 +517,49 @@ impl StorageNextKey {
         }
         .run()
     }
+
+    /// Stops the verification, as the requested storage can't be provided. The block is then
+    /// considered as failing to verify.
+    pub fn inject_unavailable(self) -> Verify {
+        VerifyInner {
+            inner: self.inner.inject_unavailable(),
+            consensus_success: self.consensus_success,
+        }
+        .run()
+    }
+}
+
+/// Verifying whether a batch of signatures is valid is required in order to continue.
+#[must_use]
+pub struct SignatureBatchVerify {
+    inner: execute_block::SignatureBatchVerify,
+    consensus_success: SuccessConsensus,
+}
+
+impl SignatureBatchVerify {
+    /// Returns the signatures of the batch.
+    pub fn signatures(&self) -> &[host::SignatureVerification] {
+        self.inner.signatures()
+    }
+
+    /// Resumes the verification. `all_valid` must be `true` if and only if all the signatures
+    /// of the batch are valid.
+    pub fn resume(self, all_valid: bool) -> Verify {
+        VerifyInner {
+            inner: self.inner.resume(all_valid),
+            consensus_success: self.consensus_success,
+        }
+        .run()
+    }
+
+    /// Verifies the signatures one after the other, then resumes the verification.
+    pub fn verify_and_resume(self) -> Verify {
+        VerifyInner {
+            inner: self.inner.verify_and_resume(),
+            consensus_success: self.consensus_success,
+        }
+        .run()
+    }
 }
 
 /// A new runtime must be compiled.
@@ -497,11 +586,14 @@ impl RuntimeCompilation {
             .as_ref()
             .unwrap();
 
-        let new_runtime = match host::HostVmPrototype::new(
-            code,
-            self.heap_pages,
-            vm::ExecHint::CompileAheadOfTime,
-        ) {
+        let mut new_runtime = match host::HostVmPrototype::new(host::Config {
+            module: code,
+            heap_pages: self.heap_pages,
+            exec_hint: vm::ExecHint::CompileAheadOfTime,
+            allow_unresolved_imports: false,
+            max_memory_size: self.success.parent_runtime.max_memory_size(),
+            metered: self.success.parent_runtime.metered(),
+        }) {
             Ok(vm) => vm,
             Err(err) => {
                 return Verify::Finished(Err((
@@ -511,6 +603,9 @@ impl RuntimeCompilation {
             }
         };
 
+        // The new runtime traces the calls to host functions the same way as the parent runtime.
+        new_runtime.set_trace_host_calls(self.success.parent_runtime.trace_host_calls());
+
         Verify::Finished(Ok(Success {
             parent_runtime: self.success.parent_runtime,
             new_runtime: Some(new_runtime),